@@ -0,0 +1,62 @@
+#![cfg(windows)]
+//! A library installing a detour from `DllMain`, whilst the loader lock is
+//! held, in loader-safe mode.
+//!
+//! Loaded by the `hooks_from_dllmain` test, which checks the exported status
+//! and target.
+use detour::{pool, RawDetour, Result};
+use std::sync::atomic::{AtomicI32, Ordering};
+use winapi::shared::minwindef::{BOOL, DWORD, HINSTANCE, LPVOID, TRUE};
+use winapi::um::winnt::DLL_PROCESS_ATTACH;
+
+/// Memory registered with the pool, since it cannot grow whilst the loader
+/// lock is held.
+#[repr(C, align(4096))]
+struct Region([u8; 0x1000]);
+
+static mut REGION: Region = Region([0; 0x1000]);
+
+/// The result of installing the detour, or -1 if it has not been attempted.
+static STATUS: AtomicI32 = AtomicI32::new(-1);
+
+/// The target, which is detoured once the library is loaded.
+#[no_mangle]
+#[inline(never)]
+pub extern "C" fn loader_safe_target(x: i32, y: i32) -> i32 {
+  unsafe { std::ptr::read_volatile(&x as *const i32) + y }
+}
+
+extern "C" fn detour(x: i32, y: i32) -> i32 {
+  x - y
+}
+
+/// Returns 0 if the detour was installed, and 1 otherwise.
+#[no_mangle]
+pub extern "C" fn loader_safe_status() -> i32 {
+  STATUS.load(Ordering::SeqCst)
+}
+
+/// Installs the detour, without growing the pool.
+unsafe fn install() -> Result<()> {
+  pool::set_loader_safe(true);
+  pool::add_region(
+    std::ptr::addr_of_mut!(REGION) as *mut u8,
+    0x1000,
+    pool::RegionKind::NeedsProtectFlip,
+  )?;
+
+  let hook = RawDetour::new(loader_safe_target as *const (), detour as *const ())?;
+  hook.enable()?;
+  std::mem::forget(hook);
+  Ok(())
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+unsafe extern "system" fn DllMain(_module: HINSTANCE, reason: DWORD, _reserved: LPVOID) -> BOOL {
+  if reason == DLL_PROCESS_ATTACH {
+    let status = if install().is_ok() { 0 } else { 1 };
+    STATUS.store(status, Ordering::SeqCst);
+  }
+  TRUE
+}
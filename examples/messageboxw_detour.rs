@@ -59,6 +59,11 @@ fn get_module_symbol_address(module: &str, symbol: &str) -> Option<usize> {
   }
 }
 
+/// The DLL entry point.
+///
+/// # Safety
+///
+/// Only to be invoked by the Windows loader.
 #[no_mangle]
 #[allow(non_snake_case)]
 pub unsafe extern "system" fn DllMain(
@@ -0,0 +1,214 @@
+#ifndef DETOUR_H
+#define DETOUR_H
+
+/* This file is generated by cbindgen; do not edit it manually. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * The error codes reported by the C interface.
+ *
+ * The values are part of the interface; codes are only ever appended.
+ */
+typedef enum detour_error {
+  /**
+   * The operation succeeded.
+   */
+  DETOUR_ERROR_OK = 0,
+  /**
+   * The address for the target and detour are identical.
+   */
+  DETOUR_ERROR_SAME_ADDRESS = 1,
+  /**
+   * The address does not contain valid instructions.
+   */
+  DETOUR_ERROR_INVALID_CODE = 2,
+  /**
+   * The address has no available area for patching.
+   */
+  DETOUR_ERROR_NO_PATCH_AREA = 3,
+  /**
+   * The address is not executable memory.
+   */
+  DETOUR_ERROR_NOT_EXECUTABLE = 4,
+  /**
+   * The detour is not initialized.
+   */
+  DETOUR_ERROR_NOT_INITIALIZED = 5,
+  /**
+   * The detour is already initialized.
+   */
+  DETOUR_ERROR_ALREADY_INITIALIZED = 6,
+  /**
+   * The system is out of executable memory.
+   */
+  DETOUR_ERROR_OUT_OF_MEMORY = 7,
+  /**
+   * The address contains an instruction that prevents detouring.
+   */
+  DETOUR_ERROR_UNSUPPORTED_INSTRUCTION = 8,
+  /**
+   * No operating system backend has been installed.
+   */
+  DETOUR_ERROR_MISSING_BACKEND = 9,
+  /**
+   * A memory operation failed.
+   */
+  DETOUR_ERROR_REGION_FAILURE = 10,
+  /**
+   * A required pointer argument was null.
+   */
+  DETOUR_ERROR_NULL_POINTER = 11,
+  /**
+   * The operation panicked.
+   */
+  DETOUR_ERROR_PANIC = 12,
+  /**
+   * A library symbol could not be found.
+   */
+  DETOUR_ERROR_SYMBOL_NOT_FOUND = 13,
+  /**
+   * The operation is not permitted in loader-safe mode.
+   */
+  DETOUR_ERROR_LOADER_UNSAFE = 14,
+  /**
+   * No registered region within range has sufficient space.
+   */
+  DETOUR_ERROR_REGION_EXHAUSTED = 15,
+  /**
+   * The operating system denied an operation.
+   */
+  DETOUR_ERROR_PERMISSION_DENIED = 16,
+  /**
+   * No free memory could be found close to the target.
+   */
+  DETOUR_ERROR_NO_MEMORY_IN_RANGE = 17,
+  /**
+   * The operating system failed to map memory.
+   */
+  DETOUR_ERROR_ALLOCATION_FAILED = 18,
+  /**
+   * The address of the detour is not executable memory.
+   */
+  DETOUR_ERROR_DETOUR_NOT_EXECUTABLE = 19,
+  /**
+   * The target is part of a trampoline allocated by the library, or of the
+   * library's own patching code.
+   */
+  DETOUR_ERROR_SELF_HOOK = 20,
+  /**
+   * The detour cannot be reached by a relative jump from the target.
+   */
+  DETOUR_ERROR_OUT_OF_RANGE = 21,
+  /**
+   * All debug registers are occupied by hardware breakpoints.
+   */
+  DETOUR_ERROR_NO_DEBUG_REGISTER = 22,
+  /**
+   * A function pointer slot no longer contains the detour.
+   */
+  DETOUR_ERROR_SLOT_CHANGED = 23,
+  /**
+   * A patch was rejected by a callback.
+   */
+  DETOUR_ERROR_PATCH_REJECTED = 24,
+  /**
+   * A configured option has an invalid value.
+   */
+  DETOUR_ERROR_INVALID_OPTION = 25,
+  /**
+   * A static detour has no closure set.
+   */
+  DETOUR_ERROR_NO_DETOUR_SET = 26,
+  /**
+   * Executable memory was denied, and so was each fallback.
+   */
+  DETOUR_ERROR_EXECUTABLE_MEMORY_DENIED = 27,
+  /**
+   * Another patch operation is in progress.
+   */
+  DETOUR_ERROR_WOULD_BLOCK = 28,
+  /**
+   * An exported symbol is forwarded to another module.
+   */
+  DETOUR_ERROR_FORWARDED_EXPORT = 29,
+  /**
+   * The address is ARM64EC code within an emulated process.
+   */
+  DETOUR_ERROR_ARM_CODE = 30,
+  /**
+   * The address is an ARM64EC fast-forward sequence; its real entry is
+   * returned by `detour_fast_forward_entry`.
+   */
+  DETOUR_ERROR_FAST_FORWARD_THUNK = 31,
+  /**
+   * Handed off state is incompatible, or no longer matches its target.
+   */
+  DETOUR_ERROR_INCOMPATIBLE_STATE = 32,
+  /**
+   * No static detour is registered under a handoff key.
+   */
+  DETOUR_ERROR_UNKNOWN_KEY = 33,
+} detour_error;
+
+/**
+ * An opaque handle to a detour.
+ */
+typedef struct detour_handle detour_handle;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Creates a new (disabled) detour from `target` to `detour`.
+ *
+ * Returns null on failure, and stores the error code in `error` (if it's not
+ * null). The handle must be released using `detour_destroy`.
+ */
+struct detour_handle *detour_create(const void *target,
+                                    const void *detour,
+                                    enum detour_error *error);
+
+/**
+ * Enables the detour.
+ */
+enum detour_error detour_enable(const struct detour_handle *handle);
+
+/**
+ * Disables the detour.
+ */
+enum detour_error detour_disable(const struct detour_handle *handle);
+
+/**
+ * Returns whether the detour is enabled or not.
+ */
+bool detour_is_enabled(const struct detour_handle *handle);
+
+/**
+ * Returns a callable address to the original function, or null if the
+ * handle is null.
+ */
+const void *detour_trampoline(const struct detour_handle *handle);
+
+/**
+ * Returns the real ARM64EC entry of a target that is a fast-forward sequence
+ * (i.e creating a detour of it fails with `DETOUR_ERROR_FAST_FORWARD_THUNK`),
+ * or null otherwise.
+ */
+const void *detour_fast_forward_entry(const void *target);
+
+/**
+ * Disables and releases the detour. A null handle is ignored.
+ */
+void detour_destroy(struct detour_handle *handle);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* DETOUR_H */
@@ -0,0 +1,583 @@
+//! Procedural macros for the [detour](https://docs.rs/detour) library.
+//!
+//! The macros are re-exported by `detour` when its `macros` feature is
+//! enabled, and should be used through it.
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::parse::{ParseStream, Parser};
+use syn::spanned::Spanned;
+use syn::{
+  Data, DeriveInput, Error, Expr, ExprLit, Fields, FnArg, ForeignItem, ItemFn, ItemForeignMod, Lit,
+  LitStr, Meta, Result, Type,
+};
+
+/// Defines a static detour, with the annotated function as its detour.
+///
+/// The function keeps its name, and a type of the same name is defined
+/// alongside it, with the following associated functions:
+///
+/// - `install()`: Resolves the target and initializes the detour (upon the
+///   first call), and enables it.
+/// - `remove()`: Disables the detour.
+/// - `is_installed()`: Returns whether the detour is enabled or not.
+///
+/// Within the function's body, `original(...)` calls the original target
+/// regardless of whether it's hooked or not.
+///
+/// # Arguments
+///
+/// - `target`: Either a path to a function (e.g `target = my_crate::open`), or
+///   a string describing a symbol exported by a library (e.g `target =
+///   "kernel32.dll!Sleep"`). The latter requires the `libloading` feature of
+///   `detour`, and the library is loaded upon installation.
+/// - `abi`: The calling convention of the target (e.g `abi = "system"`). The
+///   Rust calling convention is used if it is omitted.
+///
+/// The detour's function type is derived from the function's signature, and
+/// it is `unsafe` if the function is. Due to being a static detour, the
+/// signature may not be generic, nor have reference arguments.
+///
+/// # Example
+///
+/// ```ignore
+/// use detour::detour;
+///
+/// #[detour(target = "kernel32.dll!Sleep", abi = "system")]
+/// fn my_sleep(ms: u32) {
+///   original(ms * 2)
+/// }
+///
+/// unsafe { my_sleep::install()? };
+/// ```
+#[proc_macro_attribute]
+pub fn detour(arguments: TokenStream, item: TokenStream) -> TokenStream {
+  let function = syn::parse_macro_input!(item as ItemFn);
+  Arguments::parse(arguments.into())
+    .and_then(|arguments| expand_detour(arguments, function))
+    .unwrap_or_else(Error::into_compile_error)
+    .into()
+}
+
+/// Defines a set of static detours, from a struct of detour functions.
+///
+/// Each field of the struct must be a function pointer, describing the
+/// prototype of a hook. A static detour is defined for each field, accessed
+/// by an associated function of the same name (as defined by
+/// `static_detour!`), along with the following functions:
+///
+/// - `initialize_all(self, resolver)`: Initializes every hook, with the
+///   struct's fields as their detours. The resolver maps each field's name to
+///   its target's address.
+/// - `enable_all()`: Enables every hook.
+/// - `disable_all()`: Disables every hook.
+///
+/// Every hook is operated upon in declaration order, regardless of whether a
+/// prior hook failed, and the failures are aggregated in a `HookSetError`.
+///
+/// # Example
+///
+/// ```ignore
+/// use detour::HookSet;
+///
+/// #[derive(HookSet)]
+/// struct FileHooks {
+///   open: unsafe extern "C" fn(*const c_char, i32) -> i32,
+///   close: unsafe extern "C" fn(i32) -> i32,
+/// }
+///
+/// unsafe {
+///   FileHooks { open: my_open, close: my_close }.initialize_all(|name| resolve(name))?;
+///   FileHooks::enable_all()?;
+///   FileHooks::open().call(path, 0);
+/// }
+/// ```
+#[proc_macro_derive(HookSet)]
+pub fn hook_set(item: TokenStream) -> TokenStream {
+  let input = syn::parse_macro_input!(item as DeriveInput);
+  expand_hook_set(input)
+    .unwrap_or_else(Error::into_compile_error)
+    .into()
+}
+
+/// Defines a static detour for each function declared within `extern` blocks.
+///
+/// For each declared function, a type of the same name is defined, with a
+/// `detour()` function returning its static detour (as defined by
+/// `static_detour!`). The declaration itself is replaced by a function with the
+/// same prototype (and attributes), which calls the original function through
+/// the detour once it's initialized, and the declared function otherwise.
+///
+/// A `pub(crate) initialize_all()` function is also defined, which resolves
+/// each function's symbol (respecting `link_name`) within the modules loaded by
+/// the process, and initializes its detour with a closure calling the original
+/// function. The detours can then be changed using `set_detour`, and enabled
+/// individually. Every detour is initialized in declaration order, and the
+/// failures are aggregated in a `HookSetError`.
+///
+/// Since `initialize_all` is defined alongside the functions, the macro may
+/// only be invoked once per module. Variadic functions are not supported.
+///
+/// # Example
+///
+/// ```ignore
+/// use detour::hook_extern;
+///
+/// hook_extern! {
+///   extern "C" {
+///     fn malloc(size: usize) -> *mut c_void;
+///     fn free(ptr: *mut c_void);
+///   }
+/// }
+///
+/// unsafe {
+///   initialize_all()?;
+///   malloc::detour().set_detour(|size| malloc::detour().call(size * 2));
+///   malloc::detour().enable()?;
+/// }
+/// ```
+#[proc_macro]
+pub fn hook_extern(input: TokenStream) -> TokenStream {
+  let blocks = syn::parse_macro_input!(input with parse_extern_blocks);
+  expand_hook_extern(blocks)
+    .unwrap_or_else(Error::into_compile_error)
+    .into()
+}
+
+/// Parses a sequence of `extern` blocks.
+fn parse_extern_blocks(input: ParseStream) -> Result<Vec<ItemForeignMod>> {
+  let mut blocks = Vec::new();
+  while !input.is_empty() {
+    blocks.push(input.parse()?);
+  }
+  Ok(blocks)
+}
+
+/// The target of a detour.
+enum Target {
+  /// An expression evaluating to the target function.
+  Function(Expr),
+  /// A symbol exported by a library.
+  Symbol { library: LitStr, symbol: LitStr },
+}
+
+/// The arguments of the `detour` attribute.
+struct Arguments {
+  target: Target,
+  abi: Option<LitStr>,
+}
+
+impl Arguments {
+  /// Parses the arguments of the attribute.
+  fn parse(tokens: TokenStream2) -> Result<Self> {
+    let mut target = None;
+    let mut abi = None;
+
+    let parser = syn::meta::parser(|meta| {
+      if meta.path.is_ident("target") {
+        target = Some(match meta.value()?.parse()? {
+          Expr::Lit(ExprLit {
+            lit: Lit::Str(spec),
+            ..
+          }) => Self::parse_symbol(&spec)?,
+          expression => Target::Function(expression),
+        });
+        Ok(())
+      } else if meta.path.is_ident("abi") {
+        abi = Some(meta.value()?.parse()?);
+        Ok(())
+      } else {
+        Err(meta.error("unsupported detour argument, expected `target` or `abi`"))
+      }
+    });
+    parser.parse2(tokens)?;
+
+    let target = target.ok_or_else(|| Error::new(Span::call_site(), "missing detour `target`"))?;
+    Ok(Arguments { target, abi })
+  }
+
+  /// Parses a `library!symbol` specification.
+  fn parse_symbol(spec: &LitStr) -> Result<Target> {
+    match spec.value().split_once('!') {
+      Some((library, symbol)) if !library.is_empty() && !symbol.is_empty() => Ok(Target::Symbol {
+        library: LitStr::new(library, spec.span()),
+        symbol: LitStr::new(symbol, spec.span()),
+      }),
+      _ => Err(Error::new(
+        spec.span(),
+        "expected a symbol in the form \"library!symbol\"",
+      )),
+    }
+  }
+}
+
+/// Expands a detour function into the function itself, and its detour type.
+fn expand_detour(arguments: Arguments, mut function: ItemFn) -> Result<TokenStream2> {
+  let signature = &function.sig;
+  if let Some(token) = &signature.constness {
+    return Err(Error::new(token.span(), "a detour cannot be `const`"));
+  }
+  if let Some(token) = &signature.asyncness {
+    return Err(Error::new(token.span(), "a detour cannot be `async`"));
+  }
+  if let Some(abi) = &signature.abi {
+    return Err(Error::new(
+      abi.span(),
+      "a detour's calling convention is specified by the `abi` argument",
+    ));
+  }
+  if !signature.generics.params.is_empty() || signature.generics.where_clause.is_some() {
+    return Err(Error::new(
+      signature.generics.span(),
+      "a detour cannot be generic",
+    ));
+  }
+  if let Some(variadic) = &signature.variadic {
+    return Err(Error::new(variadic.span(), "a detour cannot be variadic"));
+  }
+
+  let mut argument_types = Vec::new();
+  for argument in &signature.inputs {
+    match argument {
+      FnArg::Typed(argument) => argument_types.push(argument.ty.clone()),
+      FnArg::Receiver(receiver) => {
+        return Err(Error::new(
+          receiver.span(),
+          "a detour cannot have a receiver",
+        ))
+      },
+    }
+  }
+
+  let argument_names = (0..argument_types.len())
+    .map(|index| format_ident!("__arg_{}", index))
+    .collect::<Vec<_>>();
+
+  let name = &signature.ident;
+  let visibility = &function.vis;
+  let unsafety = &signature.unsafety;
+  let output = &signature.output;
+  let abi = arguments.abi.as_ref().map(|abi| quote!(extern #abi));
+  let fn_type = quote!(#unsafety #abi fn(#(#argument_types),*) #output);
+
+  let target = match &arguments.target {
+    Target::Function(expression) => quote!(#expression),
+    Target::Symbol { library, symbol } => quote!(::detour::__resolve_symbol(#library, #symbol)?),
+  };
+
+  // The original function is available within the detour's body
+  let block = &function.block;
+  function.block = syn::parse_quote!({
+    #[allow(dead_code)]
+    #unsafety fn original(#(#argument_names: #argument_types),*) #output {
+      #[allow(unused_unsafe)]
+      unsafe { #name::__detour().call(#(#argument_names),*) }
+    }
+
+    #block
+  });
+
+  let documentation = format!("The static detour of [`{}`].", name);
+  Ok(quote! {
+    #function
+
+    #[doc = #documentation]
+    #[allow(non_camel_case_types)]
+    #visibility struct #name {}
+
+    impl #name {
+      ::detour::static_detour! {
+        #[doc(hidden)]
+        pub fn __detour: #fn_type;
+      }
+
+      /// Initializes the detour if required, and enables it.
+      ///
+      /// The target is resolved upon initialization.
+      pub unsafe fn install() -> ::detour::Result<()> {
+        let detour = Self::__detour();
+        match detour.enable() {
+          Err(::detour::Error::NotInitialized) => detour
+            .initialize(#target, |#(#argument_names: #argument_types),*| {
+              #[allow(unused_unsafe)]
+              unsafe { #name(#(#argument_names),*) }
+            })?
+            .enable(),
+          result => result,
+        }
+      }
+
+      /// Disables the detour.
+      pub unsafe fn remove() -> ::detour::Result<()> {
+        Self::__detour().disable()
+      }
+
+      /// Returns whether the detour is enabled or not.
+      pub fn is_installed() -> bool {
+        Self::__detour().is_enabled()
+      }
+    }
+  })
+}
+
+/// Expands a struct of detour functions into a set of static detours.
+fn expand_hook_set(input: DeriveInput) -> Result<TokenStream2> {
+  if !input.generics.params.is_empty() || input.generics.where_clause.is_some() {
+    return Err(Error::new(
+      input.generics.span(),
+      "a hook set cannot be generic",
+    ));
+  }
+
+  let fields = match &input.data {
+    Data::Struct(data) => match &data.fields {
+      Fields::Named(fields) => &fields.named,
+      _ => {
+        return Err(Error::new(
+          input.ident.span(),
+          "a hook set must have named fields",
+        ))
+      },
+    },
+    _ => {
+      return Err(Error::new(
+        input.ident.span(),
+        "a hook set must be a struct",
+      ))
+    },
+  };
+
+  let mut accessors = Vec::new();
+  let mut initializers = Vec::new();
+  let mut enablers = Vec::new();
+  let mut disablers = Vec::new();
+
+  for field in fields {
+    let function = match &field.ty {
+      Type::BareFn(function) if function.variadic.is_none() => function,
+      ty => {
+        return Err(Error::new(
+          ty.span(),
+          "a hook must be a non-variadic function pointer",
+        ))
+      },
+    };
+
+    let name = field.ident.as_ref().expect("named field");
+    let label = name.to_string();
+    let visibility = &field.vis;
+    let ty = &field.ty;
+
+    // Documentation is forwarded to the accessor, and conditions to every use
+    let docs = field
+      .attrs
+      .iter()
+      .filter(|attr| attr.path().is_ident("doc"));
+    let cfgs = field
+      .attrs
+      .iter()
+      .filter(|attr| attr.path().is_ident("cfg"))
+      .collect::<Vec<_>>();
+
+    let argument_types = function.inputs.iter().map(|argument| &argument.ty);
+    let argument_names = (0..function.inputs.len())
+      .map(|index| format_ident!("__arg_{}", index))
+      .collect::<Vec<_>>();
+
+    accessors.push(quote! {
+      #(#docs)*
+      #(#cfgs)*
+      #visibility fn #name: #ty;
+    });
+    initializers.push(quote! {
+      #(#cfgs)*
+      errors.__record(#label, {
+        let detour = self.#name;
+        ::detour::__resolve_address(resolver(#label)).and_then(|target| {
+          Self::#name().initialize(target, move |#(#argument_names: #argument_types),*| {
+            #[allow(unused_unsafe)]
+            unsafe { detour(#(#argument_names),*) }
+          })
+        })
+      });
+    });
+    enablers.push(quote! {
+      #(#cfgs)*
+      errors.__record(#label, Self::#name().enable());
+    });
+    disablers.push(quote! {
+      #(#cfgs)*
+      errors.__record(#label, Self::#name().disable());
+    });
+  }
+
+  let name = &input.ident;
+  Ok(quote! {
+    impl #name {
+      ::detour::static_detour! {
+        #(#accessors)*
+      }
+
+      /// Initializes every hook in declaration order, with the fields as their
+      /// detours, and their targets resolved by name.
+      pub unsafe fn initialize_all<R>(
+        self,
+        mut resolver: R,
+      ) -> ::core::result::Result<(), ::detour::HookSetError>
+      where
+        R: FnMut(&str) -> *const (),
+      {
+        let mut errors = ::detour::HookSetError::__new();
+        #(#initializers)*
+        errors.__finish()
+      }
+
+      /// Enables every hook in declaration order.
+      pub unsafe fn enable_all() -> ::core::result::Result<(), ::detour::HookSetError> {
+        let mut errors = ::detour::HookSetError::__new();
+        #(#enablers)*
+        errors.__finish()
+      }
+
+      /// Disables every hook in declaration order.
+      pub unsafe fn disable_all() -> ::core::result::Result<(), ::detour::HookSetError> {
+        let mut errors = ::detour::HookSetError::__new();
+        #(#disablers)*
+        errors.__finish()
+      }
+    }
+  })
+}
+
+/// Expands `extern` blocks into a static detour for each declared function.
+fn expand_hook_extern(blocks: Vec<ItemForeignMod>) -> Result<TokenStream2> {
+  let mut functions = Vec::new();
+  let mut initializers = Vec::new();
+
+  for block in &blocks {
+    let abi = &block.abi;
+    let block_attributes = &block.attrs;
+
+    for item in &block.items {
+      let item = match item {
+        ForeignItem::Fn(item) => item,
+        item => return Err(Error::new(item.span(), "only functions can be detoured")),
+      };
+
+      let signature = &item.sig;
+      if let Some(variadic) = &signature.variadic {
+        return Err(Error::new(
+          variadic.span(),
+          "a variadic function cannot be detoured",
+        ));
+      }
+
+      // The symbol's name may differ from the function's
+      let mut symbol = LitStr::new(&signature.ident.to_string(), signature.ident.span());
+      let mut link_attributes = Vec::new();
+      let mut attributes = Vec::new();
+      for attribute in &item.attrs {
+        match &attribute.meta {
+          Meta::NameValue(meta) if meta.path.is_ident("link_name") => {
+            if let Expr::Lit(ExprLit {
+              lit: Lit::Str(name),
+              ..
+            }) = &meta.value
+            {
+              symbol = name.clone();
+            }
+            link_attributes.push(attribute);
+          },
+          _ => attributes.push(attribute),
+        }
+      }
+
+      let cfgs = attributes
+        .iter()
+        .filter(|attribute| attribute.path().is_ident("cfg"))
+        .collect::<Vec<_>>();
+
+      let argument_types = signature
+        .inputs
+        .iter()
+        .map(|argument| match argument {
+          FnArg::Typed(argument) => Ok(&argument.ty),
+          FnArg::Receiver(receiver) => Err(Error::new(
+            receiver.span(),
+            "a detour cannot have a receiver",
+          )),
+        })
+        .collect::<Result<Vec<_>>>()?;
+      let argument_names = (0..argument_types.len())
+        .map(|index| format_ident!("__arg_{}", index))
+        .collect::<Vec<_>>();
+
+      let name = &signature.ident;
+      let label = name.to_string();
+      let visibility = &item.vis;
+      let output = &signature.output;
+      let fn_type = quote!(unsafe #abi fn(#(#argument_types),*) #output);
+      let documentation = format!("The static detour of [`{}`].", name);
+
+      functions.push(quote! {
+        #(#attributes)*
+        #visibility unsafe #abi fn #name(#(#argument_names: #argument_types),*) #output {
+          #(#block_attributes)*
+          #abi {
+            #(#link_attributes)*
+            fn #name(#(#argument_names: #argument_types),*) #output;
+          }
+
+          let detour = #name::detour();
+          #[allow(unused_unsafe)]
+          unsafe {
+            if detour.is_initialized() {
+              detour.call(#(#argument_names),*)
+            } else {
+              #name(#(#argument_names),*)
+            }
+          }
+        }
+
+        #(#cfgs)*
+        #[doc = #documentation]
+        #[allow(non_camel_case_types)]
+        #visibility struct #name {}
+
+        #(#cfgs)*
+        impl #name {
+          ::detour::static_detour! {
+            /// Returns the static detour of the function.
+            pub fn detour: #fn_type;
+          }
+        }
+      });
+
+      initializers.push(quote! {
+        #(#cfgs)*
+        errors.__record(
+          #label,
+          ::detour::__resolve_loaded(#symbol).and_then(|target| {
+            #name::detour().initialize(target, |#(#argument_names: #argument_types),*| {
+              #[allow(unused_unsafe)]
+              unsafe { #name::detour().call(#(#argument_names),*) }
+            })
+          }),
+        );
+      });
+    }
+  }
+
+  Ok(quote! {
+    #(#functions)*
+
+    /// Initializes the static detour of every declared function, with a
+    /// detour calling the original function.
+    #[allow(dead_code)]
+    pub(crate) unsafe fn initialize_all() -> ::core::result::Result<(), ::detour::HookSetError> {
+      let mut errors = ::detour::HookSetError::__new();
+      #(#initializers)*
+      errors.__finish()
+    }
+  })
+}
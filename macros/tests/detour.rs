@@ -0,0 +1,116 @@
+//! Expansion tests for the `detour` attribute.
+use detour::Result;
+use detour_macros::detour;
+
+mod targets {
+  #[inline(never)]
+  pub fn add(x: i32, y: i32) -> i32 {
+    unsafe { std::ptr::read_volatile(&x as *const i32) + y }
+  }
+
+  #[inline(never)]
+  pub unsafe extern "C" fn mul(x: i32, y: i32) -> i32 {
+    std::ptr::read_volatile(&x as *const i32) * y
+  }
+}
+
+#[detour(target = targets::add)]
+fn add_detour(x: i32, y: i32) -> i32 {
+  original(x, y) + 100
+}
+
+mod hooks {
+  use super::*;
+
+  #[detour(target = crate::targets::mul, abi = "C")]
+  pub(crate) unsafe fn mul_detour(x: i32, y: i32) -> i32 {
+    original(x + 1, y)
+  }
+}
+
+#[test]
+fn function_target() -> Result<()> {
+  assert_eq!(targets::add(1, 2), 3);
+  assert!(!add_detour::is_installed());
+
+  unsafe { add_detour::install()? };
+  assert!(add_detour::is_installed());
+  assert_eq!(targets::add(1, 2), 103);
+
+  unsafe { add_detour::remove()? };
+  assert_eq!(targets::add(1, 2), 3);
+
+  // A reinstallation reuses the initialized detour
+  unsafe { add_detour::install()? };
+  assert_eq!(targets::add(2, 2), 104);
+  unsafe { add_detour::remove()? };
+  Ok(())
+}
+
+#[test]
+fn unsafe_abi() -> Result<()> {
+  unsafe {
+    hooks::mul_detour::install()?;
+    assert_eq!(targets::mul(2, 3), 9);
+    assert_eq!(hooks::mul_detour(2, 3), 9);
+    hooks::mul_detour::remove()?;
+    assert_eq!(targets::mul(2, 3), 6);
+  }
+  Ok(())
+}
+
+#[test]
+fn local_detour() -> Result<()> {
+  #[inline(never)]
+  fn negate(x: i32) -> i32 {
+    unsafe { -std::ptr::read_volatile(&x as *const i32) }
+  }
+
+  #[detour(target = negate)]
+  fn negate_twice(x: i32) -> i32 {
+    original(original(x))
+  }
+
+  unsafe { negate_twice::install()? };
+  assert_eq!(negate(5), 5);
+  unsafe { negate_twice::remove()? };
+  assert_eq!(negate(5), -5);
+  Ok(())
+}
+
+#[cfg(target_os = "linux")]
+mod library_symbol {
+  use super::*;
+  use detour::Error;
+  use libloading::Library;
+  use matches::assert_matches;
+
+  #[detour(target = "libm.so.6!cbrt", abi = "C")]
+  fn cbrt_detour(x: f64) -> f64 {
+    original(x) * 2.0
+  }
+
+  #[detour(target = "libm.so.6!not_a_symbol", abi = "C")]
+  fn missing_detour() {}
+
+  #[test]
+  fn resolves_symbol() -> Result<()> {
+    let library = unsafe { Library::new("libm.so.6") }.unwrap();
+    let cbrt = unsafe { *library.get::<extern "C" fn(f64) -> f64>(b"cbrt\0").unwrap() };
+
+    let original = cbrt(27.0);
+
+    unsafe { cbrt_detour::install()? };
+    assert_eq!(cbrt(27.0), original * 2.0);
+    unsafe { cbrt_detour::remove()? };
+    assert_eq!(cbrt(27.0), original);
+    Ok(())
+  }
+
+  #[test]
+  fn missing_symbol() {
+    let error = unsafe { missing_detour::install() }.unwrap_err();
+    assert_matches!(error, Error::SymbolNotFound { ref name, .. } if name == "libm.so.6!not_a_symbol");
+    assert!(!missing_detour::is_installed());
+  }
+}
@@ -0,0 +1,61 @@
+//! Expansion tests for the `hook_extern` macro.
+#![cfg(target_os = "linux")]
+use detour::Result;
+use libloading::Library;
+
+mod libm {
+  detour_macros::hook_extern! {
+    #[link(name = "m")]
+    extern "C" {
+      /// Computes the cube root.
+      pub fn cbrt(x: f64) -> f64;
+
+      #[link_name = "floor"]
+      pub fn round_down(x: f64) -> f64;
+
+      #[cfg(any())]
+      pub fn removed(x: NotAType);
+    }
+  }
+}
+
+fn symbol(name: &[u8]) -> extern "C" fn(f64) -> f64 {
+  let library = unsafe { Library::new("libm.so.6") }.unwrap();
+  let function = unsafe { *library.get::<extern "C" fn(f64) -> f64>(name).unwrap() };
+  std::mem::forget(library);
+  function
+}
+
+#[test]
+fn hooks_declarations() -> Result<()> {
+  let (cbrt, floor) = (symbol(b"cbrt\0"), symbol(b"floor\0"));
+  let original = cbrt(27.0);
+
+  // The declarations are callable before initialization
+  assert_eq!(unsafe { libm::cbrt(27.0) }, original);
+  assert!(!libm::cbrt::detour().is_initialized());
+
+  unsafe { libm::initialize_all().unwrap() };
+  assert!(libm::round_down::detour().is_initialized());
+
+  // The detours forward to the original functions by default
+  unsafe { libm::cbrt::detour().enable()? };
+  assert_eq!(cbrt(27.0), original);
+
+  libm::cbrt::detour().set_detour(|x| unsafe { libm::cbrt::detour().call(x) } * 2.0);
+  libm::round_down::detour().set_detour(|_| 0.0);
+  unsafe { libm::round_down::detour().enable()? };
+  assert_eq!(cbrt(27.0), original * 2.0);
+  assert_eq!(floor(1.5), 0.0);
+
+  // The declarations call the original functions
+  assert_eq!(unsafe { libm::cbrt(27.0) }, original);
+  assert_eq!(unsafe { libm::round_down(1.5) }, 1.0);
+
+  unsafe { libm::cbrt::detour().disable()? };
+  unsafe { libm::round_down::detour().disable()? };
+  assert_eq!(cbrt(27.0), original);
+  assert_eq!(floor(1.5), 1.0);
+  assert!(detour::os::Native::resolve_symbol("not_a_symbol").is_none());
+  Ok(())
+}
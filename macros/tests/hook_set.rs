@@ -0,0 +1,95 @@
+//! Expansion tests for the `HookSet` derive.
+use detour::{Error, HookSetError};
+use detour_macros::HookSet;
+use matches::assert_matches;
+
+#[inline(never)]
+extern "C" fn add(x: i32, y: i32) -> i32 {
+  unsafe { std::ptr::read_volatile(&x as *const i32) + y }
+}
+
+#[inline(never)]
+fn negate(x: i32) -> i32 {
+  unsafe { -std::ptr::read_volatile(&x as *const i32) }
+}
+
+#[derive(HookSet)]
+struct MathHooks {
+  /// Detours `add`.
+  add: extern "C" fn(i32, i32) -> i32,
+  pub(crate) negate: fn(x: i32) -> i32,
+  #[cfg(any())]
+  removed: fn(NotAType),
+}
+
+#[derive(HookSet)]
+struct MissingHooks {
+  first: unsafe extern "C" fn() -> i32,
+  second: unsafe extern "C" fn() -> i32,
+}
+
+fn resolve(name: &str) -> *const () {
+  match name {
+    "add" => add as *const (),
+    "negate" => negate as *const (),
+    _ => std::ptr::null(),
+  }
+}
+
+#[test]
+fn operates_on_all() -> Result<(), HookSetError> {
+  extern "C" fn add_detour(x: i32, y: i32) -> i32 {
+    MathHooks::add().call(x, y) * 10
+  }
+
+  let mut resolved = Vec::new();
+  unsafe {
+    MathHooks {
+      add: add_detour,
+      negate: |x| MathHooks::negate().call(x) - 1,
+    }
+    .initialize_all(|name| {
+      resolved.push(name.to_string());
+      resolve(name)
+    })?
+  };
+  assert_eq!(resolved, ["add", "negate"]);
+
+  assert_eq!(add(1, 2), 3);
+  unsafe { MathHooks::enable_all()? };
+  assert_eq!(add(1, 2), 30);
+  assert_eq!(negate(1), -2);
+
+  unsafe { MathHooks::disable_all()? };
+  assert_eq!(add(1, 2), 3);
+  assert_eq!(negate(1), -1);
+  Ok(())
+}
+
+#[test]
+fn aggregates_errors() {
+  unsafe extern "C" fn detour() -> i32 {
+    0
+  }
+
+  let hooks = MissingHooks {
+    first: detour,
+    second: detour,
+  };
+
+  let error = unsafe { hooks.initialize_all(|_| std::ptr::null()) }.unwrap_err();
+  assert_eq!(
+    error
+      .errors()
+      .iter()
+      .map(|(name, _)| *name)
+      .collect::<Vec<_>>(),
+    ["first", "second"]
+  );
+  assert!(error.to_string().starts_with("2 hook(s) failed"));
+
+  let error = unsafe { MissingHooks::enable_all() }.unwrap_err();
+  let errors = error.into_errors();
+  assert_eq!(errors.len(), 2);
+  assert_matches!(errors[0], ("first", Error::NotInitialized));
+}
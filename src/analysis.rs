@@ -0,0 +1,332 @@
+//! Analysis of the code targeted by detours.
+//!
+//! The bounds of a function are looked up in the unwind information of its
+//! module, if any: the `RUNTIME_FUNCTION` table (`.pdata`) on Windows x64,
+//! and the FDEs of `.eh_frame` on Linux. These are exact, and consulted
+//! before relocating a target's prolog, or patching into the padding after a
+//! function.
+//!
+//! Otherwise, the bounds are estimated from the padding after a function
+//! (i.e `int3` or NOPs up to the next 16 byte boundary), which is flagged by
+//! [BoundsSource::Padding](./enum.BoundsSource.html). Such an estimate starts
+//! at the address itself, and may end prematurely (e.g at padding between a
+//! function's hot and cold paths), or not at all.
+//!
+//! # Example
+//!
+//! ```rust
+//! # #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+//! # fn main() {
+//! use detour::analysis::{self, BoundsSource};
+//!
+//! #[inline(never)]
+//! extern "C" fn add(x: i32, y: i32) -> i32 {
+//!   x + y
+//! }
+//!
+//! if let Some(bounds) = analysis::bounds(add as *const ()) {
+//!   assert!(bounds.range.contains(&(add as *const () as usize)));
+//!
+//!   if bounds.source == BoundsSource::UnwindInfo {
+//!     assert_eq!(analysis::function_bounds(add as *const ()), Some(bounds.range));
+//!   }
+//! }
+//! # }
+//! # #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+//! # fn main() {}
+//! ```
+
+use crate::arch;
+use core::ops::Range;
+
+/// The alignment of functions assumed by the padding heuristic.
+const ALIGNMENT: usize = 16;
+
+/// The most bytes disassembled by the padding heuristic.
+const MAX_SCAN_SIZE: usize = 0x1_0000;
+
+/// The bounds of a function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionBounds {
+  /// The addresses of the function's code, excluding trailing padding.
+  pub range: Range<usize>,
+  /// How the bounds were determined.
+  pub source: BoundsSource,
+}
+
+/// The source of a function's bounds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BoundsSource {
+  /// The bounds are exact, as recorded by the unwind information of the
+  /// function's module.
+  UnwindInfo,
+  /// The bounds are estimated, from the address itself to the first return or
+  /// unconditional jump followed by padding.
+  Padding,
+}
+
+/// Returns the addresses of the function containing an address.
+///
+/// See [bounds](./fn.bounds.html) for the source of the range.
+pub fn function_bounds(address: *const ()) -> Option<Range<usize>> {
+  bounds(address).map(|bounds| bounds.range)
+}
+
+/// Returns the bounds of the function containing an address, and how they
+/// were determined.
+///
+/// Without unwind information, the address is presumed to be the start of a
+/// function, and `None` is returned unless padding is found within 64 KiB of
+/// it.
+pub fn bounds(address: *const ()) -> Option<FunctionBounds> {
+  if let Some(range) = unwind_bounds(address) {
+    return Some(FunctionBounds {
+      range,
+      source: BoundsSource::UnwindInfo,
+    });
+  }
+
+  padded_bounds(address).map(|range| FunctionBounds {
+    range,
+    source: BoundsSource::Padding,
+  })
+}
+
+/// Returns the exact bounds of the function containing an address, if its
+/// module has unwind information.
+pub(crate) fn unwind_bounds(address: *const ()) -> Option<Range<usize>> {
+  if address.is_null() {
+    return None;
+  }
+
+  unsafe { platform::unwind_bounds(address as usize) }
+    .filter(|range| range.contains(&(address as usize)))
+}
+
+/// Estimates the bounds of the function at an address, from its padding.
+fn padded_bounds(address: *const ()) -> Option<Range<usize>> {
+  let region = crate::os::backend()
+    .and_then(|backend| backend.query(address))
+    .ok()??;
+
+  if !region.protection.contains(crate::os::Protection::READ) {
+    return None;
+  }
+
+  let limit = (region.upper() - address as usize).min(MAX_SCAN_SIZE);
+  let end = unsafe { arch::meta::padded_end(address, limit, ALIGNMENT)? };
+  Some(address as usize..end)
+}
+
+#[cfg(all(feature = "std", target_os = "linux"))]
+mod platform {
+  //! The FDEs of `.eh_frame`, found through the unwinder.
+  use core::ffi::c_void;
+  use core::ops::Range;
+  use core::{mem, ptr, slice};
+
+  /// The bases of an FDE's module (i.e `struct dwarf_eh_bases`).
+  #[repr(C)]
+  struct EhBases {
+    text: *mut c_void,
+    data: *mut c_void,
+    function: *mut c_void,
+  }
+
+  extern "C" {
+    fn _Unwind_Find_FDE(pc: *mut c_void, bases: *mut EhBases) -> *const u8;
+  }
+
+  /// The pointer encodings' formats (`DW_EH_PE_*`).
+  const ABSPTR: u8 = 0x00;
+  const ULEB128: u8 = 0x01;
+  const UDATA2: u8 = 0x02;
+  const UDATA4: u8 = 0x03;
+  const UDATA8: u8 = 0x04;
+  const SLEB128: u8 = 0x09;
+  const SDATA2: u8 = 0x0A;
+  const SDATA4: u8 = 0x0B;
+  const SDATA8: u8 = 0x0C;
+  /// A pointer that's not present.
+  const OMIT: u8 = 0xFF;
+
+  /// A cursor over DWARF data in memory.
+  struct Reader(*const u8);
+
+  impl Reader {
+    unsafe fn u8(&mut self) -> u8 {
+      let value = *self.0;
+      self.0 = self.0.add(1);
+      value
+    }
+
+    unsafe fn read<T: Copy>(&mut self) -> T {
+      let value = ptr::read_unaligned(self.0 as *const T);
+      self.0 = self.0.add(mem::size_of::<T>());
+      value
+    }
+
+    unsafe fn uleb128(&mut self) -> u64 {
+      let (mut value, mut shift) = (0u64, 0);
+      loop {
+        let byte = self.u8();
+        if shift < 64 {
+          value |= u64::from(byte & 0x7F) << shift;
+        }
+        shift += 7;
+        if byte & 0x80 == 0 {
+          return value;
+        }
+      }
+    }
+
+    unsafe fn sleb128(&mut self) -> i64 {
+      let (mut value, mut shift) = (0i64, 0);
+      loop {
+        let byte = self.u8();
+        if shift < 64 {
+          value |= i64::from(byte & 0x7F) << shift;
+        }
+        shift += 7;
+        if byte & 0x80 == 0 {
+          if shift < 64 && byte & 0x40 != 0 {
+            value |= -1 << shift;
+          }
+          return value;
+        }
+      }
+    }
+
+    /// Reads a value of an encoding's format, excluding its application
+    /// (e.g relative to the data).
+    unsafe fn encoded(&mut self, encoding: u8) -> Option<u64> {
+      Some(match encoding & 0x0F {
+        ABSPTR => self.read::<usize>() as u64,
+        ULEB128 => self.uleb128(),
+        UDATA2 => u64::from(self.read::<u16>()),
+        UDATA4 => u64::from(self.read::<u32>()),
+        UDATA8 => self.read::<u64>(),
+        SLEB128 => self.sleb128() as u64,
+        SDATA2 => self.read::<i16>() as u64,
+        SDATA4 => self.read::<i32>() as u64,
+        SDATA8 => self.read::<i64>() as u64,
+        _ => None?,
+      })
+    }
+
+    /// Reads the length of a CIE or FDE, skipping an extended length.
+    unsafe fn length(&mut self) -> u64 {
+      match self.read::<u32>() {
+        0xFFFF_FFFF => self.read::<u64>(),
+        length => u64::from(length),
+      }
+    }
+  }
+
+  /// Returns the range of the function containing an address.
+  pub unsafe fn unwind_bounds(address: usize) -> Option<Range<usize>> {
+    let mut bases = EhBases {
+      text: ptr::null_mut(),
+      data: ptr::null_mut(),
+      function: ptr::null_mut(),
+    };
+
+    let fde = _Unwind_Find_FDE(address as *mut c_void, &mut bases);
+    if fde.is_null() {
+      return None;
+    }
+
+    let mut reader = Reader(fde);
+    reader.length();
+
+    // The CIE pointer is relative to itself
+    let position = reader.0 as usize;
+    let cie = position.wrapping_sub(reader.read::<u32>() as usize);
+    let encoding = pointer_encoding(cie as *const u8)?;
+
+    // The initial location is resolved by the unwinder, only its size matters
+    reader.encoded(encoding)?;
+    let size = reader.encoded(encoding & 0x0F)? as usize;
+
+    let start = bases.function as usize;
+    Some(start..start.checked_add(size)?)
+  }
+
+  /// Returns the encoding of the pointers in the FDEs of a CIE.
+  unsafe fn pointer_encoding(cie: *const u8) -> Option<u8> {
+    let mut reader = Reader(cie);
+    reader.length();
+    reader.read::<u32>();
+    let version = reader.u8();
+
+    let start = reader.0;
+    while reader.u8() != 0 {}
+    let augmentation = slice::from_raw_parts(start, reader.0 as usize - start as usize - 1);
+
+    if version >= 4 {
+      // The address and segment selector sizes
+      reader.read::<u16>();
+    }
+
+    // The code and data alignment factors, and the return address register
+    reader.uleb128();
+    reader.sleb128();
+    if version == 1 {
+      reader.u8();
+    } else {
+      reader.uleb128();
+    }
+
+    if augmentation.first() != Some(&b'z') {
+      return augmentation.is_empty().then_some(ABSPTR);
+    }
+
+    reader.uleb128();
+    for character in &augmentation[1..] {
+      match character {
+        b'R' => return Some(reader.u8()).filter(|&encoding| encoding != OMIT),
+        b'P' => {
+          let encoding = reader.u8();
+          reader.encoded(encoding)?;
+        },
+        b'L' => {
+          reader.u8();
+        },
+        b'S' | b'B' | b'G' => (),
+        _ => return None,
+      }
+    }
+
+    Some(ABSPTR)
+  }
+}
+
+#[cfg(all(feature = "std", windows, target_arch = "x86_64"))]
+mod platform {
+  //! The `RUNTIME_FUNCTION` table, found through the loader.
+  use core::ops::Range;
+  use core::ptr;
+  use winapi::um::winnt::RtlLookupFunctionEntry;
+
+  /// Returns the range of the function containing an address.
+  pub unsafe fn unwind_bounds(address: usize) -> Option<Range<usize>> {
+    let mut base = 0;
+    let entry = RtlLookupFunctionEntry(address as u64, &mut base, ptr::null_mut()).as_ref()?;
+
+    let base = base as usize;
+    Some(base + entry.BeginAddress as usize..base + entry.EndAddress as usize)
+  }
+}
+
+#[cfg(not(any(
+  all(feature = "std", target_os = "linux"),
+  all(feature = "std", windows, target_arch = "x86_64")
+)))]
+mod platform {
+  use core::ops::Range;
+
+  /// Returns `None`, since no unwind information is available.
+  pub unsafe fn unwind_bounds(_address: usize) -> Option<Range<usize>> {
+    None
+  }
+}
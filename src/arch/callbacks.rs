@@ -0,0 +1,169 @@
+use crate::error::{Error, Result};
+use crate::sync::Mutex;
+#[cfg(feature = "std")]
+use std::panic::{self, AssertUnwindSafe};
+
+/// A callback invoked before a patch is written, with the address and size of
+/// the patch area, and the code about to be written to it.
+pub type BeforePatch = fn(*const (), usize, &[u8]) -> bool;
+
+/// A callback invoked after a patch has been written, with the address and
+/// size of the patch area.
+pub type AfterPatch = fn(*const (), usize);
+
+/// Callbacks invoked around each modification of a target's code.
+///
+/// The callbacks are invoked by every [Patcher](./struct.Patcher.html) whose
+/// patch is enabled or disabled, including those of all detours, but not by
+/// [Patcher::write](./struct.Patcher.html#method.write), which leaves the
+/// sequence to the caller. They're useful for integrity checks, which must
+/// permit or account for each write.
+///
+/// Both callbacks are invoked outside of the window in which the code is
+/// writable; the before-callback prior to changing the protection, and the
+/// after-callback once it has been restored and the instruction cache has
+/// been flushed. They're invoked whilst holding the lock serializing all
+/// detours, and must therefore not create, enable or disable any detour.
+///
+/// # Example
+///
+/// ```rust
+/// # use detour::Result;
+/// use detour::{set_patch_callbacks, Error, PatchCallbacks, RawDetour};
+///
+/// #[inline(never)]
+/// extern "C" fn add5(val: i32) -> i32 {
+///   unsafe { std::ptr::read_volatile(&val) + 5 }
+/// }
+///
+/// extern "C" fn add10(val: i32) -> i32 {
+///   val + 10
+/// }
+///
+/// # fn main() -> Result<()> {
+/// let hook = unsafe { RawDetour::new(add5 as *const (), add10 as *const ())? };
+///
+/// // Reject all patches
+/// set_patch_callbacks(PatchCallbacks {
+///   on_before_patch: Some(|_address, _size, _code| false),
+///   ..PatchCallbacks::default()
+/// });
+///
+/// assert!(matches!(unsafe { hook.enable() }, Err(Error::PatchRejected)));
+/// assert_eq!(add5(5), 10);
+///
+/// set_patch_callbacks(PatchCallbacks::default());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PatchCallbacks {
+  /// Invoked before code is written.
+  ///
+  /// Returning false aborts the operation with `Error::PatchRejected`,
+  /// leaving the code unmodified. Rejecting the restoration of a detour's
+  /// original code whilst it's dropped leaves the target redirected to
+  /// released memory.
+  pub on_before_patch: Option<BeforePatch>,
+  /// Invoked after code has been written.
+  pub on_after_patch: Option<AfterPatch>,
+}
+
+impl PatchCallbacks {
+  /// Invokes the before-callback, if any.
+  pub(crate) fn before(&self, address: *const (), code: &[u8]) -> Result<()> {
+    match self.on_before_patch {
+      Some(callback) if !callback(address, code.len(), code) => Err(Error::PatchRejected),
+      _ => Ok(()),
+    }
+  }
+
+  /// Invokes the after-callback, if any.
+  pub(crate) fn after(&self, address: *const (), size: usize) {
+    if let Some(callback) = self.on_after_patch {
+      callback(address, size);
+    }
+  }
+}
+
+static CALLBACKS: Mutex<PatchCallbacks> = Mutex::new(PatchCallbacks {
+  on_before_patch: None,
+  on_after_patch: None,
+});
+
+/// Sets the callbacks invoked around each modification of a target's code.
+pub fn set_patch_callbacks(callbacks: PatchCallbacks) {
+  *CALLBACKS.lock() = callbacks;
+}
+
+/// Returns the callbacks invoked around each modification of a target's
+/// code.
+pub fn patch_callbacks() -> PatchCallbacks {
+  *CALLBACKS.lock()
+}
+
+/// A handler invoked when a detour fails to restore its target whilst it's
+/// dropped.
+pub type DropErrorHandler = fn(&Error, DropContext);
+
+/// The code (or function pointer slot) a dropped detour failed to restore.
+///
+/// A failure typically occurs during process teardown, or after the target's
+/// module has been unloaded, when the protection of the target can no longer
+/// be changed. A target left patched is redirected to released memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DropContext {
+  /// The address of the patch area (or slot).
+  pub target: *const (),
+  /// The size of the patch area (or slot).
+  pub size: usize,
+}
+
+/// Writes the failure to the standard error stream, if available.
+fn log_drop_error(error: &Error, context: DropContext) {
+  #[cfg(feature = "std")]
+  std::eprintln!(
+    "detour: failed to restore {:p} ({} bytes) whilst dropping: {}",
+    context.target,
+    context.size,
+    error
+  );
+
+  #[cfg(not(feature = "std"))]
+  let _ = (error, context);
+}
+
+static DROP_ERROR_HANDLER: Mutex<DropErrorHandler> = Mutex::new(log_drop_error);
+
+/// Sets the handler invoked when a detour fails to restore its target whilst
+/// it's dropped.
+///
+/// The default handler writes the failure to the standard error stream (with
+/// the `std` feature). Dropping a detour never panics; a panic within the
+/// handler is caught (with the `std` feature).
+///
+/// # Example
+///
+/// ```rust
+/// use detour::{set_drop_error_handler, DropContext, Error};
+///
+/// fn on_drop_error(error: &Error, context: DropContext) {
+///   eprintln!("{:p} remains patched: {}", context.target, error);
+/// }
+///
+/// set_drop_error_handler(on_drop_error);
+/// ```
+pub fn set_drop_error_handler(handler: DropErrorHandler) {
+  *DROP_ERROR_HANDLER.lock() = handler;
+}
+
+/// Invokes the drop error handler, without holding any lock.
+pub(crate) fn report_drop_error(error: &Error, context: DropContext) {
+  let handler = *DROP_ERROR_HANDLER.lock();
+
+  #[cfg(feature = "std")]
+  let _ = panic::catch_unwind(AssertUnwindSafe(|| handler(error, context)));
+
+  #[cfg(not(feature = "std"))]
+  handler(error, context);
+}
@@ -1,61 +1,556 @@
 use super::memory;
 use crate::error::{Error, Result};
-use crate::{alloc, arch, util};
-use std::cell::UnsafeCell;
-use std::fmt;
-use std::sync::atomic::{AtomicBool, Ordering};
+use crate::profiling::CodeKind;
+use crate::{arch, os, pic, pool};
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::ops::Range;
+use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
 
 /// An architecture-independent implementation of a base detour.
 ///
 /// This class is never instantiated by itself, it merely exposes an API
 /// available through it's descendants.
+///
+/// The patcher is only accessed whilst holding `memory::LOCK`, which
+/// serializes enabling and disabling across all detours. The state is updated
+/// once the target has been written, so observing it implies the write.
+/// Everything else (i.e the analysis of the target, the patch and the code
+/// generated for it) is performed upon binding, and reused by every toggle.
+///
+/// The patch is declared first, so it's dropped (i.e restores the target)
+/// before the relays and trampolines are released.
 pub struct Detour {
-  #[allow(dead_code)]
-  relay: Option<alloc::ExecutableMemory>,
-  trampoline: alloc::ExecutableMemory,
+  patch: Box<Patch>,
+  /// The current binding, owned by `bindings`.
+  binding: AtomicPtr<Binding>,
+  /// Every binding of the detour, only modified whilst holding the lock.
+  ///
+  /// Previous bindings are retained until the detour is dropped, so that
+  /// references to their trampolines remain valid. They're boxed so their
+  /// addresses are stable.
+  #[allow(clippy::vec_box)]
+  bindings: UnsafeCell<Vec<Box<Binding>>>,
+  /// The code required to bind the detour to another target, if supported.
+  rebind: Option<Rebind>,
+  /// The destination of the detour, if any (i.e not an entry thunk).
+  destination: Option<*const ()>,
+  bound: AtomicBool,
+}
+
+/// The patcher of a detour, and whether it's enabled.
+///
+/// It's boxed so its address remains stable whilst the detour is moved, since
+/// it's registered (see `arch::registry`).
+pub(crate) struct Patch {
   patcher: UnsafeCell<arch::Patcher>,
   enabled: AtomicBool,
 }
 
+impl Patch {
+  /// Returns whether the patch is enabled or not.
+  pub(crate) fn is_enabled(&self) -> bool {
+    self.enabled.load(Ordering::SeqCst)
+  }
+
+  /// Returns the address of the patch area, whilst holding the lock.
+  pub(crate) unsafe fn address_locked(&self) -> *const () {
+    (*self.patcher.get()).address()
+  }
+
+  /// Enables or disables the patch, whilst holding the lock.
+  pub(crate) unsafe fn set_enabled_locked(&self, enabled: bool) -> Result<()> {
+    (*self.patcher.get()).set_enabled_locked(enabled)?;
+    self.enabled.store(enabled, Ordering::SeqCst);
+    Ok(())
+  }
+
+  /// Returns the address and original code of the patch area, if enabled.
+  ///
+  /// This neither locks nor allocates (e.g for a signal handler), but the
+  /// patch may be modified concurrently, unless the lock is held.
+  #[cfg_attr(not(feature = "std"), allow(dead_code))]
+  pub(crate) unsafe fn original_raw(&self) -> Option<(*const (), &[u8])> {
+    let patcher = &*self.patcher.get();
+    self
+      .is_enabled()
+      .then(|| (patcher.address(), patcher.original()))
+  }
+}
+
+/// How a detour's target is redirected.
+///
+/// Detours prefer allocating their trampoline and relay within range of a
+/// relative jump from the target (see
+/// [meta::detour_range](./meta/fn.detour_range.html)). If no memory is
+/// available within range, they fall back to strategies that require less (or
+/// no) memory close to the target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatchStrategy {
+  /// A relative jump to the detour, or a relay close to the target. The
+  /// trampoline is allocated close to the target, unless it resides within a
+  /// hot-patchable target.
+  Relative,
+  /// A relative jump to a small relay (a hop) close to the target, which jumps
+  /// to the detour. The trampoline is allocated anywhere.
+  Hop,
+  /// An absolute jump to the detour, spanning 14 bytes of the target's prolog
+  /// (x64 only). Nothing is allocated close to the target.
+  Absolute,
+}
+
+/// What the remainder of a target's relocated prolog is filled with, after
+/// the jump written by a detour.
+///
+/// The original bytes are always restored when the detour is disabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrologueFill {
+  /// The remainder is left as is, minimizing the modified bytes (e.g for
+  /// targets whose code is checksummed).
+  Original,
+  /// The remainder is filled with NOPs, so stray jumps into it fall through
+  /// to the following instructions.
+  Nop,
+  /// The remainder is filled with breakpoints (`int3`), so stray jumps into
+  /// it fault immediately.
+  Int3,
+}
+
+/// Options of a detour, beyond its detour and shims.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Options {
+  /// The minimum amount of bytes relocated from the target's prolog.
+  pub min_prolog_size: usize,
+  /// The fill of the relocated prolog's remainder, if other than the default.
+  pub fill: Option<PrologueFill>,
+  /// The relocated prolog, and the amount of bytes it replaces, if provided
+  /// by the caller instead of relocated automatically.
+  pub manual_prologue: Option<(Vec<u8>, usize)>,
+  /// Whether the trampoline is emitted upon first use, instead of upon
+  /// construction.
+  pub lazy_trampoline: bool,
+}
+
+impl Options {
+  /// Returns the fill of the relocated prolog's remainder; NOPs if a minimum
+  /// prolog size is set, otherwise the original bytes.
+  fn fill(&self) -> PrologueFill {
+    self.fill.unwrap_or(if self.min_prolog_size > 0 {
+      PrologueFill::Nop
+    } else {
+      PrologueFill::Original
+    })
+  }
+}
+
+/// The code generated for a target.
+struct Binding {
+  /// The target of the detour.
+  target: *const (),
+  /// The size of the code written to the patch area.
+  patch_size: usize,
+  #[allow(dead_code)]
+  relay: Option<pool::ExecutableMemory>,
+  #[allow(dead_code)]
+  hop: Option<pool::ExecutableMemory>,
+  trampoline: LazyTrampoline,
+  strategy: PatchStrategy,
+  /// The destination of the indirect hop leading to the relay, once the
+  /// binding has been handed off.
+  #[cfg_attr(not(feature = "std"), allow(dead_code))]
+  cell: Option<usize>,
+}
+
+impl Binding {
+  /// Constructs a binding without any relay.
+  fn new(target: *const (), patcher: &arch::Patcher, trampoline: arch::Trampoline) -> Self {
+    Binding {
+      target,
+      patch_size: patcher.code().len(),
+      relay: None,
+      hop: None,
+      trampoline: LazyTrampoline::new(trampoline),
+      strategy: PatchStrategy::Relative,
+      cell: None,
+    }
+  }
+}
+
+/// The state of an enabled detour, handed off to another instance of the
+/// library (e.g within a reloaded module).
+///
+/// The relay of the detour jumps to an indirect hop, whose destination is
+/// either the trampoline, or the relay of the instance adopting the detour.
+/// The patch, trampoline and indirect hop are never released.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Handoff {
+  /// The target of the detour.
+  pub target: usize,
+  /// The address of the patch area.
+  pub address: usize,
+  /// The original contents of the patch area.
+  pub original: Vec<u8>,
+  /// The code written to the patch area.
+  pub code: Vec<u8>,
+  /// The address of the trampoline.
+  pub trampoline: usize,
+  /// The size of the relocated prolog.
+  pub prolog_size: usize,
+  /// How the target is redirected.
+  pub strategy: PatchStrategy,
+  /// The address of the indirect hop's destination.
+  pub cell: usize,
+}
+
+#[cfg(feature = "std")]
+impl Handoff {
+  /// Restores the target's original code, e.g if the detour is not adopted.
+  pub(crate) unsafe fn restore(&self) -> Result<()> {
+    let mut patcher = arch::Patcher::adopt(self.address as *const (), &self.original, &self.code)?;
+    patcher.set_enabled(false)
+  }
+
+  /// Replaces the destination of the indirect hop, whilst holding the lock.
+  unsafe fn redirect_locked(cell: usize, destination: *const ()) -> Result<()> {
+    memory::write_pool(cell as *const (), &(destination as usize).to_ne_bytes())
+  }
+}
+
+/// A trampoline, which may be emitted upon first use.
+///
+/// The relocations and prolog size are known upon construction, regardless
+/// of whether the trampoline has been emitted. It's only emitted whilst
+/// holding the lock, so concurrent first uses emit it exactly once.
+struct LazyTrampoline {
+  /// The code of the trampoline, if its emission is deferred.
+  pending: Option<arch::PendingTrampoline>,
+  /// The trampoline, once emitted; only written whilst holding the lock.
+  emitted: UnsafeCell<Option<arch::Trampoline>>,
+  /// The address of the trampoline, or null until it's emitted.
+  address: AtomicPtr<()>,
+}
+
+impl LazyTrampoline {
+  /// Constructs an emitted trampoline.
+  fn new(trampoline: arch::Trampoline) -> Self {
+    LazyTrampoline {
+      pending: None,
+      address: AtomicPtr::new(trampoline.address() as *mut ()),
+      emitted: UnsafeCell::new(Some(trampoline)),
+    }
+  }
+
+  /// Constructs a trampoline emitted upon first use.
+  fn deferred(pending: arch::PendingTrampoline) -> Self {
+    LazyTrampoline {
+      pending: Some(pending),
+      emitted: UnsafeCell::new(None),
+      address: AtomicPtr::default(),
+    }
+  }
+
+  /// Returns the address of the trampoline, emitting it if required.
+  ///
+  /// The trampoline is preferably emitted within its planned range, otherwise
+  /// anywhere (like the code of a far binding).
+  fn address(&self) -> Result<*const ()> {
+    let address = self.address.load(Ordering::SeqCst);
+    if !address.is_null() {
+      return Ok(address);
+    }
+
+    let _guard = memory::LOCK.lock();
+    self.address_locked()
+  }
+
+  /// Returns the address of the trampoline, emitting it if required, whilst
+  /// holding the lock.
+  fn address_locked(&self) -> Result<*const ()> {
+    let address = self.address.load(Ordering::SeqCst);
+    if !address.is_null() {
+      return Ok(address);
+    }
+
+    let pending = self.pending.as_ref().expect("pending trampoline");
+    let trampoline = match unsafe { pending.emit_locked(pending.range()) } {
+      Err(error) if Rebind::is_out_of_range(&error) => unsafe { pending.emit_locked(usize::MAX) },
+      result => result,
+    }?;
+
+    let address = trampoline.address() as *mut ();
+    unsafe { *self.emitted.get() = Some(trampoline) };
+    self.address.store(address, Ordering::SeqCst);
+    Ok(address)
+  }
+
+  /// Returns the address of the trampoline, if it has been emitted.
+  fn emitted_address(&self) -> Option<*const ()> {
+    let address = self.address.load(Ordering::SeqCst);
+    (!address.is_null()).then_some(address as *const ())
+  }
+
+  /// Returns the size of the prolog (i.e the amount of relocated bytes).
+  fn prolog_size(&self) -> usize {
+    match self.pending {
+      Some(ref pending) => pending.prolog_size(),
+      None => self.trampoline().prolog_size(),
+    }
+  }
+
+  /// Returns a record for each relocated instruction.
+  fn relocations(&self) -> &[arch::RelocationRecord] {
+    match self.pending {
+      Some(ref pending) => pending.relocations(),
+      None => self.trampoline().relocations(),
+    }
+  }
+
+  /// Returns the system call number of the target, if any.
+  fn syscall_number(&self) -> Option<u32> {
+    match self.pending {
+      Some(ref pending) => pending.syscall_number(),
+      None => self.trampoline().syscall_number(),
+    }
+  }
+
+  /// Returns the trampoline emitted upon construction.
+  fn trampoline(&self) -> &arch::Trampoline {
+    unsafe { (*self.emitted.get()).as_ref() }.expect("emitted trampoline")
+  }
+}
+
+/// The detour and shims of a detour, required for binding it to a target.
+struct Rebind {
+  detour: *const (),
+  before_detour: pic::CodeEmitter,
+  before_original: pic::CodeEmitter,
+  options: Options,
+}
+
 impl Detour {
   pub unsafe fn new(target: *const (), detour: *const ()) -> Result<Self> {
+    Self::with_shims(
+      target,
+      detour,
+      pic::CodeEmitter::new(),
+      pic::CodeEmitter::new(),
+    )
+  }
+
+  /// Constructs a detour, executing custom code before the detour and before
+  /// the original function respectively.
+  pub unsafe fn with_shims(
+    target: *const (),
+    detour: *const (),
+    before_detour: pic::CodeEmitter,
+    before_original: pic::CodeEmitter,
+  ) -> Result<Self> {
+    Self::with_options(
+      target,
+      detour,
+      before_detour,
+      before_original,
+      Options::default(),
+    )
+  }
+
+  /// Constructs a detour with shims and options.
+  pub(crate) unsafe fn with_options(
+    target: *const (),
+    detour: *const (),
+    before_detour: pic::CodeEmitter,
+    before_original: pic::CodeEmitter,
+    options: Options,
+  ) -> Result<Self> {
+    if target.is_null() || detour.is_null() {
+      Err(Error::NullPointer)?;
+    }
+
     if target == detour {
       Err(Error::SameAddress)?;
     }
 
     // Lock this so OS operations are not performed in parallell
-    let mut pool = memory::POOL.lock().unwrap();
+    let _guard = memory::LOCK.lock();
+    Self::validate(target, detour)?;
 
-    if !util::is_executable_address(target)? || !util::is_executable_address(detour)? {
-      Err(Error::NotExecutable)?;
+    let rebind = Rebind {
+      detour,
+      before_detour,
+      before_original,
+      options,
+    };
+
+    let (patcher, binding) = rebind.bind(target)?;
+    Ok(Self::from_parts(
+      patcher,
+      binding,
+      Some(rebind),
+      Some(detour),
+    ))
+  }
+
+  /// Constructs a detour, executing an entry thunk before the original
+  /// function, whenever the target is called.
+  ///
+  /// The target is redirected to its own trampoline, which starts with the
+  /// thunk.
+  pub unsafe fn with_entry(target: *const (), entry: pic::CodeEmitter) -> Result<Self> {
+    if target.is_null() {
+      Err(Error::NullPointer)?;
     }
 
-    // Create a trampoline generator for the target function
+    let _guard = memory::LOCK.lock();
+    Self::validate_target(target)?;
+
+    // The trampoline is always within range of the target
     let margin = arch::meta::prolog_margin(target);
-    let trampoline = arch::Trampoline::new(target, margin)?;
+    let trampoline = arch::Trampoline::new_locked(target, margin, entry)?;
+    let patcher = arch::Patcher::new(target, trampoline.address(), trampoline.prolog_size())?;
 
-    // A relay is used in case a normal branch cannot reach the destination
-    let relay = if let Some(emitter) = arch::meta::relay_builder(target, detour)? {
-      Some(memory::allocate_pic(&mut pool, &emitter, target)?)
-    } else {
-      None
-    };
+    let binding = Binding::new(target, &patcher, trampoline);
+    Ok(Self::from_parts(patcher, binding, None, None))
+  }
 
-    // If a relay is supplied, use it instead of the detour address
-    let detour = relay
-      .as_ref()
-      .map(|code| code.as_ptr() as *const ())
-      .unwrap_or(detour);
+  /// Constructs a detour, writing a breakpoint instruction at the target when
+  /// enabled.
+  ///
+  /// Redirecting the breakpoint is left to the caller; the trampoline
+  /// relocates the instruction displaced by it.
+  #[cfg_attr(not(feature = "std"), allow(dead_code))]
+  pub unsafe fn with_breakpoint(target: *const (), detour: *const ()) -> Result<Self> {
+    if target.is_null() || detour.is_null() {
+      Err(Error::NullPointer)?;
+    }
 
-    Ok(Detour {
-      patcher: UnsafeCell::new(arch::Patcher::new(
-        target,
-        detour,
-        trampoline.prolog_size(),
-      )?),
-      trampoline: memory::allocate_pic(&mut pool, trampoline.emitter(), target)?,
+    if target == detour {
+      Err(Error::SameAddress)?;
+    }
+
+    let _guard = memory::LOCK.lock();
+    Self::validate(target, detour)?;
+
+    let patcher = arch::Patcher::with_code(target, &arch::meta::breakpoint());
+    let trampoline = arch::Trampoline::new_locked(target, 1, pic::CodeEmitter::new())?;
+    let binding = Binding::new(target, &patcher, trampoline);
+    Ok(Self::from_parts(patcher, binding, None, Some(detour)))
+  }
+
+  /// Constructs a (disabled) detour bound to a target.
+  ///
+  /// The patch is registered (along with its destination, if any), until the
+  /// detour is dropped.
+  fn from_parts(
+    patcher: arch::Patcher,
+    binding: Binding,
+    rebind: Option<Rebind>,
+    detour: Option<*const ()>,
+  ) -> Self {
+    let mut binding = Box::new(binding);
+    let patch = Box::new(Patch {
+      patcher: UnsafeCell::new(patcher),
       enabled: AtomicBool::default(),
-      relay,
+    });
+
+    arch::registry::register(&patch, detour);
+
+    // A detoured primitive is henceforth called through its trampoline
+    #[cfg(feature = "std")]
+    if detour.is_some() {
+      os::primitives::bind(Self::area(&patch), || {
+        binding.trampoline.address_locked().ok()
+      });
+    }
+
+    Detour {
+      patch,
+      binding: AtomicPtr::new(&mut *binding),
+      bindings: UnsafeCell::new(vec![binding]),
+      rebind,
+      destination: detour,
+      bound: AtomicBool::new(true),
+    }
+  }
+
+  /// Returns the range of the patch area, whilst holding the lock.
+  #[cfg(feature = "std")]
+  fn area(patch: &Patch) -> core::ops::Range<usize> {
+    let patcher = unsafe { &*patch.patcher.get() };
+    let address = patcher.address() as usize;
+    address..address + patcher.code().len()
+  }
+
+  /// Verifies that both addresses are eligible for detouring.
+  pub(crate) fn validate(target: *const (), detour: *const ()) -> Result<()> {
+    Self::validate_target(target)?;
+
+    if !crate::memory::is_executable(detour)? {
+      Err(Error::DetourNotExecutable)?;
+    }
+
+    Ok(())
+  }
+
+  /// Verifies that the target is eligible for detouring.
+  pub(crate) fn validate_target(target: *const ()) -> Result<()> {
+    let is_code = os::backend()?
+      .query(target)?
+      .is_some_and(|region| region.protection.contains(os::Protection::READ_EXECUTE));
+
+    if !is_code {
+      Err(Error::NotExecutable)?;
+    }
+
+    // Patching a trampoline would corrupt another detour, and patching the
+    // library's own code would recurse into the detour whilst patching
+    if pool::region_of(target).is_some() || Self::is_machinery(target) {
+      Err(Error::SelfHook)?;
+    }
+
+    // Only x64 code can be patched within an ARM64EC process
+    #[cfg(all(feature = "std", windows, target_arch = "x86_64"))]
+    unsafe {
+      os::arm64ec::validate_target(target)?;
+    }
+
+    Ok(())
+  }
+
+  /// Returns whether an address is within the library's own functions
+  /// invoked whilst patching.
+  ///
+  /// Each function's bounds are looked up in the unwind information of the
+  /// library; without it, only the function's entry is matched.
+  fn is_machinery(target: *const ()) -> bool {
+    #[cfg_attr(not(feature = "std"), allow(unused_mut))]
+    let mut machinery: Vec<*const ()> = vec![
+      Self::enable as *const (),
+      Self::disable as *const (),
+      Self::toggle as *const (),
+      toggle_all as *const (),
+      arch::Patcher::set_enabled as *const (),
+      arch::Patcher::write as *const (),
+      memory::write_pool as *const (),
+      os::protect_with_guard as *const (),
+    ];
+
+    #[cfg(feature = "std")]
+    machinery.push(<os::Native as os::Backend>::protect as *const ());
+    #[cfg(all(
+      feature = "std",
+      any(unix, windows),
+      not(any(target_os = "netbsd", target_os = "openbsd"))
+    ))]
+    machinery.push(os::primitives::protect as *const ());
+
+    machinery.into_iter().any(|function| {
+      function == target
+        || crate::analysis::unwind_bounds(function)
+          .is_some_and(|range| range.contains(&(target as usize)))
     })
   }
 
@@ -71,61 +566,711 @@ impl Detour {
 
   /// Returns whether the detour is enabled or not.
   pub fn is_enabled(&self) -> bool {
-    self.enabled.load(Ordering::SeqCst)
+    self.patch.is_enabled()
+  }
+
+  /// Prepares enabling or disabling the detour, so it can be committed
+  /// without locking or allocating (e.g from a signal handler).
+  pub unsafe fn prepare(&self, enabled: bool) -> Result<PreparedPatch<'_>> {
+    let _guard = memory::LOCK.lock();
+
+    if !self.is_bound() {
+      Err(Error::NotInitialized)?;
+    }
+
+    let patcher = &*self.patch.patcher.get();
+    let address = patcher.address();
+    let backend = os::backend()?;
+    let pages = os::page_range(address as usize, patcher.code().len(), backend.page_size());
+
+    // The patch area spans at most two pages, thus two regions
+    let mut regions = [None; 2];
+    let mut current = pages.start;
+
+    for slot in &mut regions {
+      if current >= pages.end {
+        break;
+      }
+
+      let mut region = backend
+        .query(current as *const ())?
+        .ok_or(Error::NotExecutable)?;
+      let upper = region.upper().min(pages.end);
+      region.base = current as *const ();
+      region.size = upper - current;
+
+      current = upper;
+      *slot = Some(region);
+    }
+
+    if current < pages.end {
+      Err(Error::NotExecutable)?;
+    }
+
+    Ok(PreparedPatch {
+      detour: self,
+      enabled,
+      address,
+      pages,
+      regions,
+      callbacks: arch::patch_callbacks(),
+    })
+  }
+
+  /// Returns whether the detour is bound to a target or not.
+  pub fn is_bound(&self) -> bool {
+    self.bound.load(Ordering::SeqCst)
+  }
+
+  /// Binds the detour to another target, e.g after the target's module has
+  /// been reloaded.
+  ///
+  /// The detour is disabled at the current target, unless it's no longer
+  /// mapped, and enabled at the new target if it was enabled. If the new
+  /// target cannot be detoured, the detour is left unbound, and cannot be
+  /// enabled until it's bound to another target. The trampolines of previous
+  /// targets are retained until the detour is dropped.
+  pub unsafe fn swap_target(&self, target: *const ()) -> Result<()> {
+    if target.is_null() {
+      Err(Error::NullPointer)?;
+    }
+
+    let rebind = self.rebind.as_ref().ok_or(Error::NotInitialized)?;
+    if target == rebind.detour {
+      Err(Error::SameAddress)?;
+    }
+
+    let _guard = memory::LOCK.lock();
+    let patcher = &mut *self.patch.patcher.get();
+    let was_enabled = self.patch.is_enabled();
+
+    // The previous target may have been unmapped (e.g an unloaded module)
+    if was_enabled {
+      if crate::memory::is_executable(patcher.address()).unwrap_or(false) {
+        patcher.set_enabled_locked(false)?;
+      } else {
+        patcher.abandon();
+      }
+      self.patch.enabled.store(false, Ordering::SeqCst);
+    }
+
+    let result = Self::validate_target(target).and_then(|_| rebind.bind(target));
+    let (new_patcher, binding) = match result {
+      Ok(parts) => parts,
+      Err(error) => {
+        self.bound.store(false, Ordering::SeqCst);
+        return Err(error);
+      },
+    };
+
+    // The previous patcher has already been disabled (or abandoned)
+    *patcher = new_patcher;
+
+    #[cfg(feature = "std")]
+    {
+      if let Some(previous) = self.binding().trampoline.emitted_address() {
+        os::primitives::unbind(previous);
+      }
+      os::primitives::bind(Self::area(&self.patch), || {
+        binding.trampoline.address_locked().ok()
+      });
+    }
+
+    let bindings = &mut *self.bindings.get();
+    let mut binding = Box::new(binding);
+    self.binding.store(&mut *binding, Ordering::SeqCst);
+    bindings.push(binding);
+    self.bound.store(true, Ordering::SeqCst);
+
+    if was_enabled {
+      patcher.set_enabled_locked(true)?;
+      self.patch.enabled.store(true, Ordering::SeqCst);
+    }
+    Ok(())
   }
 
   /// Returns a reference to the generated trampoline.
+  ///
+  /// Panics if a deferred trampoline cannot be emitted.
   pub fn trampoline(&self) -> &() {
-    unsafe {
-      (self.trampoline.as_ptr() as *const ())
-        .as_ref()
-        .expect("trampoline should not be null")
+    self.try_trampoline().expect("emitting deferred trampoline")
+  }
+
+  /// Returns a reference to the generated trampoline, emitting it if it's
+  /// deferred.
+  pub fn try_trampoline(&self) -> Result<&()> {
+    let address = self.binding().trampoline.address()?;
+    Ok(unsafe { address.as_ref().expect("trampoline should not be null") })
+  }
+
+  /// Returns statistics for the pool region containing the trampoline, if
+  /// it has been emitted.
+  pub fn region(&self) -> Option<pool::RegionStats> {
+    pool::region_of(self.binding().trampoline.emitted_address()?)
+  }
+
+  /// Returns a record for each instruction relocated to the trampoline.
+  pub fn trampoline_map(&self) -> &[arch::RelocationRecord] {
+    self.binding().trampoline.relocations()
+  }
+
+  /// Returns how the target is redirected.
+  pub fn strategy(&self) -> PatchStrategy {
+    self.binding().strategy
+  }
+
+  /// Returns the system call number of the target, if it's a system call stub.
+  pub fn syscall_number(&self) -> Option<u32> {
+    self.binding().trampoline.syscall_number()
+  }
+
+  /// Returns the address of the patch area.
+  pub fn patch_address(&self) -> *const () {
+    let _guard = memory::LOCK.lock();
+    unsafe { self.patch.address_locked() }
+  }
+
+  /// Returns the original contents of the patch area.
+  pub fn original_code(&self) -> Vec<u8> {
+    let _guard = memory::LOCK.lock();
+    unsafe { (*self.patch.patcher.get()).original().to_vec() }
+  }
+
+  /// Returns the current target.
+  pub fn target(&self) -> *const () {
+    self.binding().target
+  }
+
+  /// Returns the destination of the detour, unless it's an entry thunk.
+  pub fn destination(&self) -> Option<*const ()> {
+    self.destination
+  }
+
+  /// Returns the address of the current trampoline, if it has been emitted.
+  pub fn trampoline_address(&self) -> Option<*const ()> {
+    self.binding().trampoline.emitted_address()
+  }
+
+  /// Returns the size of the code written to the current patch area.
+  pub fn patch_size(&self) -> usize {
+    self.binding().patch_size
+  }
+
+  /// Formats the detour as a struct with a name, without taking the lock, so
+  /// it may be formatted whilst it's being toggled (e.g within the detour).
+  pub(crate) fn debug_struct<'a, 'b: 'a>(
+    &self,
+    f: &'a mut fmt::Formatter<'b>,
+    name: &str,
+  ) -> fmt::DebugStruct<'a, 'b> {
+    let mut debug = f.debug_struct(name);
+    debug
+      .field("target", &self.target())
+      .field("detour", &self.destination())
+      .field("trampoline", &self.trampoline_address())
+      .field("enabled", &self.is_enabled())
+      .field("patch_size", &self.patch_size());
+    debug
+  }
+
+  /// Hands off the enabled detour to another instance of the library,
+  /// without modifying its target.
+  ///
+  /// The relay is redirected to the trampoline, through an indirect hop, and
+  /// the detour is unregistered. The detour must be leaked afterwards, since
+  /// dropping it would restore its target.
+  #[cfg(feature = "std")]
+  pub(crate) unsafe fn hand_off(&self) -> Result<Handoff> {
+    let _guard = memory::LOCK.lock();
+    if !self.patch.is_enabled() || !self.is_bound() {
+      Err(Error::NotInitialized)?;
+    }
+
+    let binding = self.binding();
+    let trampoline = binding.trampoline.address_locked()?;
+    let cell = match binding.cell {
+      Some(cell) => {
+        Handoff::redirect_locked(cell, trampoline)?;
+        cell
+      },
+      None => {
+        let relay = binding
+          .relay
+          .as_ref()
+          .ok_or(Error::NotInitialized)?
+          .as_ptr() as *const ();
+        let (emitter, offset) = arch::meta::indirect_hop(trampoline);
+        let hop = memory::allocate_pic(&emitter, relay, CodeKind::Relay)?;
+        let address = hop.as_ptr() as *const ();
+
+        memory::write_pool(relay, &arch::meta::hop(address).emit(relay))?;
+        core::mem::forget(hop);
+        address as usize + offset
+      },
+    };
+
+    arch::registry::unregister(&self.patch);
+    for binding in &*self.bindings.get() {
+      if let Some(trampoline) = binding.trampoline.emitted_address() {
+        os::primitives::unbind(trampoline);
+      }
     }
+
+    let patcher = &*self.patch.patcher.get();
+    Ok(Handoff {
+      target: binding.target as usize,
+      address: patcher.address() as usize,
+      original: patcher.original().to_vec(),
+      code: patcher.code().to_vec(),
+      trampoline: trampoline as usize,
+      prolog_size: binding.trampoline.prolog_size(),
+      strategy: binding.strategy,
+      cell,
+    })
+  }
+
+  /// Constructs an enabled detour, adopting one handed off by another
+  /// instance of the library, without modifying its target.
+  ///
+  /// A relay to the detour is created, and the indirect hop is redirected to
+  /// it once the detour is complete.
+  #[cfg(feature = "std")]
+  pub(crate) unsafe fn adopt(
+    handoff: &Handoff,
+    detour: *const (),
+    before_detour: pic::CodeEmitter,
+  ) -> Result<Self> {
+    let _guard = memory::LOCK.lock();
+    let target = handoff.address as *const ();
+
+    let relay = match arch::meta::relay_builder(target, detour, before_detour.clone())? {
+      Some(emitter) => Some(memory::allocate_pic_within(
+        &emitter,
+        target,
+        usize::MAX,
+        CodeKind::Relay,
+      )?),
+      None => None,
+    };
+    let destination = relay
+      .as_ref()
+      .map(|code| code.as_ptr() as *const ())
+      .unwrap_or(detour);
+
+    let mut patcher = arch::Patcher::adopt(target, &handoff.original, &handoff.code)?;
+    if let Err(error) = Handoff::redirect_locked(handoff.cell, destination) {
+      // The target remains redirected to the trampoline
+      patcher.abandon();
+      return Err(error);
+    }
+
+    let trampoline =
+      arch::Trampoline::foreign(handoff.trampoline as *const (), handoff.prolog_size);
+    let binding = Binding {
+      target: handoff.target as *const (),
+      patch_size: handoff.code.len(),
+      relay,
+      hop: None,
+      trampoline: LazyTrampoline::new(trampoline),
+      strategy: handoff.strategy,
+      cell: Some(handoff.cell),
+    };
+    let rebind = Rebind {
+      detour,
+      before_detour,
+      before_original: pic::CodeEmitter::new(),
+      options: Options::default(),
+    };
+
+    let detour = Self::from_parts(patcher, binding, Some(rebind), Some(detour));
+    detour.patch.enabled.store(true, Ordering::SeqCst);
+    Ok(detour)
+  }
+
+  /// Returns the current binding, which lives as long as the detour.
+  fn binding(&self) -> &Binding {
+    unsafe { &*self.binding.load(Ordering::SeqCst) }
   }
 
   /// Enables or disables the detour.
   unsafe fn toggle(&self, enabled: bool) -> Result<()> {
-    let _guard = memory::POOL.lock().unwrap();
+    let _guard = memory::LOCK.lock();
 
-    if self.enabled.load(Ordering::SeqCst) == enabled {
+    if self.patch.is_enabled() == enabled {
       return Ok(());
     }
 
-    // Runtime code is by default only read-execute
-    let _handle = {
-      let area = (*self.patcher.get()).area();
-      region::protect_with_handle(
-        area.as_ptr(),
-        area.len(),
-        region::Protection::READ_WRITE_EXECUTE,
-      )
-    }?;
+    if !self.is_bound() {
+      Err(Error::NotInitialized)?;
+    }
 
     // Copy either the detour or the original bytes of the function
-    (*self.patcher.get()).toggle(enabled);
-    self.enabled.store(enabled, Ordering::SeqCst);
+    self.patch.set_enabled_locked(enabled)
+  }
+}
+
+/// Enables or disables detours at once, coalescing the protection changes
+/// and instruction cache flushes of patch areas sharing pages.
+///
+/// Detours already in the requested state are skipped. Each group of pages is
+/// written in full, or not at all; if any group fails, those already written
+/// are restored.
+pub unsafe fn toggle_all(detours: &[&Detour], enabled: bool) -> Result<()> {
+  let _guard = memory::LOCK.lock();
+
+  let mut pending = Vec::<&Detour>::new();
+  for &detour in detours {
+    if detour.patch.is_enabled() != enabled
+      && !pending.iter().any(|&other| core::ptr::eq(other, detour))
+    {
+      pending.push(detour);
+    }
+  }
+
+  if pending.iter().any(|detour| !detour.is_bound()) {
+    Err(Error::NotInitialized)?;
+  }
+
+  let areas = pending
+    .iter()
+    .map(|detour| {
+      let patcher = &*detour.patch.patcher.get();
+      let address = patcher.address() as usize;
+      address..address + patcher.code().len()
+    })
+    .collect::<Vec<_>>();
+  let groups = memory::PageGroup::partition(&areas, os::backend()?.page_size());
+
+  memory::PageGroup::write_all(&groups, |index| pending[index].patch.patcher.get(), enabled)?;
+  for detour in pending {
+    detour.patch.enabled.store(enabled, Ordering::SeqCst);
+  }
+  Ok(())
+}
+
+/// Enabling or disabling a detour, prepared ahead of time.
+///
+/// Everything that requires locking, allocating or querying the operating
+/// system is performed upon preparation (i.e the protection of the patch
+/// area, and the patch callbacks), so that committing only changes the
+/// protection of the patch area whilst it's written.
+pub struct PreparedPatch<'a> {
+  detour: &'a Detour,
+  enabled: bool,
+  /// The address of the patch area, as of the preparation.
+  address: *const (),
+  /// The pages spanning the patch area.
+  pages: Range<usize>,
+  /// The protection of the pages, restored once the patch area is written.
+  regions: [Option<os::Region>; 2],
+  callbacks: arch::PatchCallbacks,
+}
+
+unsafe impl Send for PreparedPatch<'_> {}
+unsafe impl Sync for PreparedPatch<'_> {}
+
+impl PreparedPatch<'_> {
+  /// Enables or disables the detour, as prepared.
+  ///
+  /// This is async-signal-safe (i.e it never blocks, nor allocates); if
+  /// another patch operation is in progress, `Error::WouldBlock` is returned
+  /// instead. If the detour has since been bound to another target,
+  /// `Error::NotInitialized` is returned. The patch may be committed
+  /// repeatedly; once the detour is in the prepared state, it returns
+  /// immediately.
+  ///
+  /// The patch callbacks installed upon preparation are invoked, rather than
+  /// those currently installed.
+  pub unsafe fn commit(&self) -> Result<()> {
+    let _guard = memory::LOCK.try_lock().ok_or(Error::WouldBlock)?;
+    let patch = &self.detour.patch;
+
+    if patch.is_enabled() == self.enabled {
+      return Ok(());
+    }
+
+    let patcher = &mut *patch.patcher.get();
+    if !self.detour.is_bound() || patcher.address() != self.address {
+      Err(Error::NotInitialized)?;
+    }
+
+    let size = patcher.code().len();
+    self
+      .callbacks
+      .before(self.address, patcher.prolog(self.enabled))?;
+
+    let backend = os::backend()?;
+    let pages = self.pages.start as *const ();
+    backend.protect(pages, self.pages.len(), os::Protection::READ_WRITE_EXECUTE)?;
+
+    patcher.write(self.enabled);
+    backend.flush_instruction_cache(self.address, size);
+
+    for region in self.regions.iter().flatten() {
+      let _ = backend.protect(region.base, region.size, region.protection);
+    }
+
+    patch.enabled.store(self.enabled, Ordering::SeqCst);
+    self.callbacks.after(self.address, size);
     Ok(())
   }
+
+  /// Returns whether the patch enables or disables the detour.
+  pub fn enables(&self) -> bool {
+    self.enabled
+  }
+}
+
+impl fmt::Debug for PreparedPatch<'_> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct("PreparedPatch")
+      .field("address", &self.address)
+      .field("enabled", &self.enabled)
+      .finish()
+  }
+}
+
+impl Rebind {
+  /// Creates the patcher, trampoline and relays for a target, whilst holding
+  /// the lock.
+  ///
+  /// Unless memory is unavailable within range of the target, a relative jump
+  /// is used; otherwise a hop, followed by an absolute jump.
+  unsafe fn bind(&self, target: *const ()) -> Result<(arch::Patcher, Binding)> {
+    const FALLBACKS: &[PatchStrategy] = &[
+      PatchStrategy::Hop,
+      #[cfg(target_arch = "x86_64")]
+      PatchStrategy::Absolute,
+    ];
+
+    let error = match self.bind_relative(target) {
+      Err(error) if Self::is_out_of_range(&error) => error,
+      result => return result,
+    };
+
+    // If no fallback is applicable, the lack of memory is reported
+    FALLBACKS
+      .iter()
+      .find_map(|&strategy| self.bind_far(target, strategy).ok())
+      .ok_or(error)
+  }
+
+  /// Creates a trampoline within `range` bytes of the target, relocating at
+  /// least `margin` bytes (or the minimum prolog size), unless the relocated
+  /// prolog has been provided.
+  ///
+  /// If the trampoline is lazy, it's only generated, not emitted.
+  unsafe fn trampoline(
+    &self,
+    target: *const (),
+    margin: usize,
+    range: usize,
+  ) -> Result<LazyTrampoline> {
+    let prologue = self.before_original.clone();
+    let pending = match self.options.manual_prologue {
+      Some((ref code, size)) => {
+        arch::PendingTrampoline::manual(target, code, size, prologue, range)
+      },
+      None => {
+        let margin = margin.max(self.options.min_prolog_size);
+        arch::PendingTrampoline::new(target, margin, prologue, range)?
+      },
+    };
+
+    if self.options.lazy_trampoline {
+      Ok(LazyTrampoline::deferred(pending))
+    } else {
+      pending.emit_locked(range).map(LazyTrampoline::new)
+    }
+  }
+
+  /// Extends the patch area over the whole relocated prolog, unless its
+  /// remainder is left as is (or the patch does not start at the target).
+  ///
+  /// Returns `Error::NoPatchArea` if the target ends before the minimum prolog
+  /// size.
+  unsafe fn extend(
+    &self,
+    target: *const (),
+    mut patcher: arch::Patcher,
+    trampoline: &LazyTrampoline,
+  ) -> Result<arch::Patcher> {
+    if trampoline.prolog_size() < self.options.min_prolog_size {
+      Err(Error::NoPatchArea)?;
+    }
+
+    let fill = self.options.fill();
+    if fill != PrologueFill::Original && patcher.address() == target {
+      patcher.extend(trampoline.prolog_size(), fill);
+    }
+    Ok(patcher)
+  }
+
+  /// Returns whether an error is caused by the lack of memory within range.
+  fn is_out_of_range(error: &Error) -> bool {
+    matches!(
+      error,
+      Error::NoMemoryInRange { .. } | Error::RegionExhausted | Error::OutOfRange
+    )
+  }
+
+  /// Binds the detour using a relative jump.
+  unsafe fn bind_relative(&self, target: *const ()) -> Result<(arch::Patcher, Binding)> {
+    // Hot-patchable targets (and those starting with NOPs) are redirected
+    // without relocating their prolog
+    let (hotpatch, pad) =
+      if self.before_original.is_empty() && self.options.manual_prologue.is_none() {
+        let options = arch::hotpatch_options();
+        let is_sufficient = |&size: &usize| size >= self.options.min_prolog_size;
+        match arch::meta::hotpatch_entry(target, &options)?.filter(is_sufficient) {
+          Some(entry_size) => (Some(entry_size), None),
+          None => (
+            None,
+            arch::meta::entry_pad(target, &options).filter(is_sufficient),
+          ),
+        }
+      } else {
+        (None, None)
+      };
+
+    // Create a trampoline for the target function
+    let trampoline = match hotpatch.or(pad) {
+      Some(entry_size) => LazyTrampoline::new(arch::Trampoline::in_place(target, entry_size)),
+      None => self.trampoline(
+        target,
+        arch::meta::prolog_margin(target),
+        crate::meta::detour_range(),
+      )?,
+    };
+
+    // A relay is used in case a normal branch cannot reach the destination, or
+    // if custom code should be executed before it
+    let relay = if let Some(emitter) =
+      arch::meta::relay_builder(target, self.detour, self.before_detour.clone())?
+    {
+      Some(memory::allocate_pic(&emitter, target, CodeKind::Relay)?)
+    } else {
+      None
+    };
+
+    // If a relay is supplied, use it instead of the detour address
+    let detour = relay
+      .as_ref()
+      .map(|code| code.as_ptr() as *const ())
+      .unwrap_or(self.detour);
+
+    let patcher = match hotpatch {
+      Some(_) => arch::Patcher::hotpatch(target, detour)?,
+      None => self.extend(
+        target,
+        arch::Patcher::new(target, detour, trampoline.prolog_size())?,
+        &trampoline,
+      )?,
+    };
+
+    let binding = Binding {
+      target,
+      patch_size: patcher.code().len(),
+      relay,
+      hop: None,
+      trampoline,
+      strategy: PatchStrategy::Relative,
+      cell: None,
+    };
+    Ok((patcher, binding))
+  }
+
+  /// Binds the detour using a hop or an absolute jump, allocating the
+  /// trampoline and relay anywhere.
+  unsafe fn bind_far(
+    &self,
+    target: *const (),
+    strategy: PatchStrategy,
+  ) -> Result<(arch::Patcher, Binding)> {
+    let margin = match strategy {
+      #[cfg(target_arch = "x86_64")]
+      PatchStrategy::Absolute => arch::meta::absolute_margin(),
+      _ => arch::meta::prolog_margin(target),
+    };
+
+    let trampoline = self.trampoline(target, margin, usize::MAX)?;
+
+    // The relay is only required for executing custom code
+    let relay = if self.before_detour.is_empty() {
+      None
+    } else {
+      let emitter = arch::meta::relay_builder(target, self.detour, self.before_detour.clone())?
+        .expect("relay with a prologue");
+      Some(memory::allocate_pic_within(
+        &emitter,
+        target,
+        usize::MAX,
+        CodeKind::Relay,
+      )?)
+    };
+
+    let detour = relay
+      .as_ref()
+      .map(|code| code.as_ptr() as *const ())
+      .unwrap_or(self.detour);
+
+    let (mut patcher, hop) = match strategy {
+      #[cfg(target_arch = "x86_64")]
+      PatchStrategy::Absolute => (
+        arch::Patcher::absolute(target, detour, trampoline.prolog_size())?,
+        None,
+      ),
+      _ => {
+        let hop = memory::allocate_pic(&arch::meta::hop(detour), target, CodeKind::Relay)?;
+        let patcher =
+          arch::Patcher::new(target, hop.as_ptr() as *const (), trampoline.prolog_size())?;
+        (patcher, Some(hop))
+      },
+    };
+    patcher = self.extend(target, patcher, &trampoline)?;
+
+    let binding = Binding {
+      target,
+      patch_size: patcher.code().len(),
+      relay,
+      hop,
+      trampoline,
+      strategy,
+      cell: None,
+    };
+    Ok((patcher, binding))
+  }
 }
 
 impl Drop for Detour {
   /// Disables the detour, if enabled.
+  ///
+  /// The trampolines and relays are released back to the pool afterwards. On
+  /// failure, the patcher retries once more when it's dropped, and reports
+  /// the error if it persists.
   fn drop(&mut self) {
-    debug_assert!(unsafe { self.disable().is_ok() });
+    let _ = unsafe { self.disable() };
+
+    let _guard = memory::LOCK.lock();
+    arch::registry::unregister(&self.patch);
+
+    #[cfg(feature = "std")]
+    for binding in unsafe { &*self.bindings.get() } {
+      if let Some(trampoline) = binding.trampoline.emitted_address() {
+        os::primitives::unbind(trampoline);
+      }
+    }
   }
 }
 
 impl fmt::Debug for Detour {
-  /// Output whether the detour is enabled or not.
+  /// Output the addresses of the detour, and whether it's enabled or not.
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    write!(
-      f,
-      "Detour {{ enabled: {}, trampoline: {:?} }}",
-      self.is_enabled(),
-      self.trampoline()
-    )
+    self.debug_struct(f, "Detour").finish()
   }
 }
 
+// The patcher and bindings are synchronized by the lock, and the remaining
+// state is immutable or atomic.
 unsafe impl Send for Detour {}
 unsafe impl Sync for Detour {}
@@ -0,0 +1,83 @@
+use crate::sync::Mutex;
+
+/// Options for detecting hot-patchable targets.
+///
+/// Functions compiled for hot patching (e.g using `patchable-function-entry`,
+/// or MSVC's `/hotpatch` and `/FUNCTIONPADMIN`) are preceded by padding, and
+/// start with a NOP. When a detour is created for such a target, a long jump
+/// is written within the padding, and a short jump to it replaces the NOP.
+/// The target's prolog is never relocated; the instruction after the NOP
+/// serves as the trampoline, which makes these targets immune to instructions
+/// that cannot be relocated.
+///
+/// Likewise, if the target starts with NOPs spanning a long jump (e.g
+/// alignment padding, or `patchable-function-entry` without a prefix), the
+/// jump is written over them, and the first instruction after them serves as
+/// the trampoline.
+///
+/// The fast path is not used for detours executing custom code before the
+/// original function (see [Shims](./struct.Shims.html)).
+///
+/// # Example
+///
+/// ```rust
+/// use detour::{configure_hotpatch, hotpatch_options, HotpatchOptions};
+///
+/// // Expect the padding emitted by `/FUNCTIONPADMIN:6`
+/// configure_hotpatch(HotpatchOptions {
+///   prefix_size: 6,
+///   ..HotpatchOptions::default()
+/// });
+/// assert_eq!(hotpatch_options().prefix_size, 6);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HotpatchOptions {
+  /// Whether hot-patchable targets are detected.
+  pub enabled: bool,
+  /// The minimum amount of padding preceding the target.
+  ///
+  /// Values smaller than the size of a long jump (five bytes) are treated as
+  /// such.
+  pub prefix_size: usize,
+  /// The size of the NOP the target starts with.
+  ///
+  /// Values smaller than the size of a short jump (two bytes) are treated as
+  /// such.
+  pub entry_size: usize,
+  /// Whether NOPs at the target's entry, spanning a long jump, are used as
+  /// the patch area (if hot-patchable targets are detected). Otherwise, they
+  /// are relocated like any other prolog, except that they're omitted from
+  /// the trampoline.
+  pub entry_pad: bool,
+}
+
+impl HotpatchOptions {
+  /// The options used unless they have been configured, matching functions
+  /// with `prefix_nops = 5, entry_nops = 2`.
+  pub const DEFAULT: HotpatchOptions = HotpatchOptions {
+    enabled: true,
+    prefix_size: 5,
+    entry_size: 2,
+    entry_pad: true,
+  };
+}
+
+impl Default for HotpatchOptions {
+  fn default() -> Self {
+    Self::DEFAULT
+  }
+}
+
+static OPTIONS: Mutex<HotpatchOptions> = Mutex::new(HotpatchOptions::DEFAULT);
+
+/// Configures the detection of hot-patchable targets.
+///
+/// The options apply to detours created afterwards.
+pub fn configure_hotpatch(options: HotpatchOptions) {
+  *OPTIONS.lock() = options;
+}
+
+/// Returns the options for detecting hot-patchable targets.
+pub fn hotpatch_options() -> HotpatchOptions {
+  *OPTIONS.lock()
+}
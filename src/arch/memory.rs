@@ -1,26 +1,174 @@
-use crate::{alloc, arch, error::Result, pic};
-use lazy_static::lazy_static;
-use std::sync::Mutex;
-
-lazy_static! {
-  /// Shared allocator for all detours.
-  pub static ref POOL: Mutex<alloc::ThreadAllocator> = {
-    // Use a range of +/- 2 GB for seeking a memory block
-    Mutex::new(alloc::ThreadAllocator::new(arch::meta::DETOUR_RANGE))
-  };
-}
+use crate::error::Result;
+use crate::profiling::{self, CodeKind};
+use crate::sync::Mutex;
+use crate::{arch, os, pic, pool};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::Range;
+use core::slice;
 
-/// Allocates PIC code at the specified address.
+/// Serializes OS operations performed by detours.
+pub static LOCK: Mutex<()> = Mutex::new(());
+
+/// Allocates PIC code at the specified address, recording its symbol.
 pub fn allocate_pic(
-  pool: &mut alloc::ThreadAllocator,
   emitter: &pic::CodeEmitter,
   origin: *const (),
-) -> Result<alloc::ExecutableMemory> {
+  kind: CodeKind,
+) -> Result<pool::ExecutableMemory> {
+  allocate_pic_within(emitter, origin, crate::meta::detour_range(), kind)
+}
+
+/// Allocates PIC code within `range` bytes of the specified address,
+/// recording its symbol.
+pub fn allocate_pic_within(
+  emitter: &pic::CodeEmitter,
+  origin: *const (),
+  range: usize,
+  kind: CodeKind,
+) -> Result<pool::ExecutableMemory> {
   // Allocate memory close to the origin
-  pool.allocate(origin, emitter.len()).map(|mut memory| {
-    // Generate code for the obtained address
-    let code = emitter.emit(memory.as_ptr() as *const _);
-    memory.copy_from_slice(code.as_slice());
-    memory
-  })
+  let mut memory = pool::ExecutableMemory::allocate_within(origin, emitter.len(), range)?;
+
+  // Generate code for the obtained address, padded to the allocation's size
+  let address = memory.as_ptr() as *const ();
+  let mut code = emitter.emit(address as *const _);
+  let size = code.len();
+  code.resize(memory.len(), 0);
+  arch::meta::fill_nops(&mut code[size..]);
+
+  unsafe { memory.write_unlocked(0, &code)? };
+  profiling::register(address, memory.len(), kind, origin);
+  Ok(memory)
+}
+
+/// Writes code to memory allocated from the pool, and flushes the
+/// instruction cache.
+///
+/// Dual mapped memory is written through its alias. Code within an aligned
+/// 64-bit word is written atomically.
+pub unsafe fn write_pool(address: *const (), code: &[u8]) -> Result<()> {
+  let backend = os::backend()?;
+  let alias = backend.write_alias(address);
+
+  // Registered regions may not be writable
+  let is_writable = alias.is_some()
+    || backend
+      .query(address)?
+      .is_some_and(|region| region.protection.contains(os::Protection::WRITE));
+
+  let _guard = if is_writable {
+    None
+  } else {
+    Some(os::protect_with_guard(
+      address,
+      code.len(),
+      os::Protection::READ_WRITE_EXECUTE,
+    )?)
+  };
+
+  let area = slice::from_raw_parts_mut(alias.unwrap_or(address as *mut u8), code.len());
+  if !arch::Patcher::write_atomic(area, code) {
+    area.copy_from_slice(code);
+  }
+
+  backend.flush_instruction_cache(address, code.len());
+  Ok(())
+}
+
+/// Patch areas sharing pages, which are made writable at once.
+pub struct PageGroup {
+  /// The range spanning the patch areas.
+  pub area: Range<usize>,
+  /// The indices of the patch areas, in address order.
+  pub members: Vec<usize>,
+}
+
+impl PageGroup {
+  /// Partitions patch areas into groups sharing pages.
+  pub fn partition(areas: &[Range<usize>], page_size: usize) -> Vec<PageGroup> {
+    let mut order = (0..areas.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&index| areas[index].start);
+
+    let mut groups = Vec::<PageGroup>::new();
+    for index in order {
+      let area = areas[index].clone();
+
+      match groups.last_mut() {
+        Some(group) if area.start / page_size <= (group.area.end - 1) / page_size => {
+          group.area.end = group.area.end.max(area.end);
+          group.members.push(index);
+        },
+        _ => groups.push(PageGroup {
+          area,
+          members: vec![index],
+        }),
+      }
+    }
+    groups
+  }
+
+  /// Patches or unpatches the areas of all groups, one group at a time,
+  /// whilst holding the lock.
+  ///
+  /// If any group fails, those already written are restored.
+  pub unsafe fn write_all(
+    groups: &[PageGroup],
+    patcher: impl Fn(usize) -> *mut arch::Patcher,
+    enabled: bool,
+  ) -> Result<()> {
+    for (index, group) in groups.iter().enumerate() {
+      if let Err(error) = group.write(&patcher, enabled) {
+        for group in &groups[..index] {
+          let _ = group.write(&patcher, !enabled);
+        }
+        return Err(error);
+      }
+    }
+    Ok(())
+  }
+
+  /// Writes the patch areas of the group, whilst holding the lock.
+  ///
+  /// The protection of the group is changed, and the instruction cache is
+  /// flushed, once. The patch callbacks are invoked for each area, outside of
+  /// the window in which the group is writable. Areas sharing an address
+  /// (i.e stacked patches) are restored in the reverse order of patching.
+  unsafe fn write(
+    &self,
+    patcher: impl Fn(usize) -> *mut arch::Patcher,
+    enabled: bool,
+  ) -> Result<()> {
+    let callbacks = arch::patch_callbacks();
+    for &index in &self.members {
+      let patcher = &*patcher(index);
+      callbacks.before(patcher.address(), patcher.prolog(enabled))?;
+    }
+
+    let (address, size) = (self.area.start as *const (), self.area.len());
+    {
+      let _handle = os::protect_with_guard(address, size, os::Protection::READ_WRITE_EXECUTE)?;
+
+      if enabled {
+        self
+          .members
+          .iter()
+          .for_each(|&index| (*patcher(index)).write(true));
+      } else {
+        self
+          .members
+          .iter()
+          .rev()
+          .for_each(|&index| (*patcher(index)).write(false));
+      }
+
+      os::backend()?.flush_instruction_cache(address, size);
+    }
+
+    for &index in &self.members {
+      let patcher = &*patcher(index);
+      callbacks.after(patcher.address(), patcher.code().len());
+    }
+    Ok(())
+  }
 }
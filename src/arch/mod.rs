@@ -2,35 +2,163 @@
 ///
 /// The current implementation requires a module to expose some functionality:
 ///
-/// - A standalone `relay_builder` function.
-/// This function creates a relay for targets with large displacement, that
-/// requires special attention. An example would be detours further away than
-/// 2GB on x64. A relative jump is not enough, so the `relay_builder`
-/// generates an absolute jump that the relative jump can reach. If it's
-/// needless, `None` can   be returned.
+/// - A standalone `relay_builder` function. This function creates a relay for
+///   targets with large displacement, that requires special attention. An
+///   example would be detours further away than 2GB on x64. A relative jump is
+///   not enough, so the `relay_builder` generates an absolute jump that the
+///   relative jump can reach. If it's needless, `None` can be returned.
 ///
 /// - A `Patcher`, modifies a target in-memory.
 /// - A `Trampoline`, generates a callable address to the target.
-pub use self::detour::Detour;
+#[cfg(feature = "std")]
+pub(crate) use self::detour::Handoff;
+pub(crate) use self::detour::{toggle_all, Options};
+pub use self::detour::{Detour, PatchStrategy, PreparedPatch, PrologueFill};
 
 use cfg_if::cfg_if;
+use core::convert::TryFrom;
 
 // TODO: flush instruction cache? __clear_cache
 // See: https://github.com/llvm-mirror/compiler-rt/blob/master/lib/builtins/clear_cache.c
 cfg_if! {
     if #[cfg(any(target_arch = "x86", target_arch = "x86_64"))] {
         mod x86;
-        use self::x86::{Patcher, Trampoline, meta};
+        pub(crate) use self::x86::meta;
+        pub use self::x86::{Patcher, RegisterState, RelocationRecord, Trampoline};
+        pub(crate) use self::x86::PendingTrampoline;
     } else {
         // TODO: Implement ARM/AARCH64/MIPS support!
     }
 }
 
+mod callbacks;
 mod detour;
-mod memory;
+mod hotpatch;
+pub(crate) mod memory;
+pub(crate) mod registry;
 
-/// Returns true if the displacement is within a certain range.
+pub(crate) use self::callbacks::report_drop_error;
+pub use self::callbacks::{patch_callbacks, set_drop_error_handler, set_patch_callbacks};
+pub use self::callbacks::{AfterPatch, BeforePatch, DropContext, DropErrorHandler, PatchCallbacks};
+pub use self::hotpatch::{configure_hotpatch, hotpatch_options, HotpatchOptions};
+
+/// Returns true if the displacement is within the configured detour range.
 pub fn is_within_range(displacement: isize) -> bool {
-  let range = meta::DETOUR_RANGE as i64;
+  is_within(displacement, crate::meta::detour_range())
+}
+
+/// Returns true if the displacement can be encoded by a relative jump.
+pub fn is_encodable(displacement: isize) -> bool {
+  is_within(displacement, meta::DETOUR_RANGE)
+}
+
+/// Returns true if a relative jump at `source`, spanning `size` bytes, can
+/// reach `destination` within the configured detour range.
+pub fn is_reachable(source: usize, size: usize, destination: usize) -> bool {
+  offset(source, size as isize)
+    .and_then(|source| displacement(source, destination))
+    .is_some_and(is_within_range)
+}
+
+/// Returns the operand of a relative instruction (rel32) at `source`,
+/// spanning `size` bytes, that refers to `destination`.
+///
+/// Returns `None` if the displacement cannot be encoded.
+pub fn relative_operand(source: usize, size: usize, destination: usize) -> Option<i32> {
+  let displacement = displacement(offset(source, size as isize)?, destination)?;
+  is_encodable(displacement).then_some(displacement as i32)
+}
+
+/// Returns the displacement from `source` to `destination`.
+///
+/// On x64, the displacement is exact, and `None` is returned if it overflows.
+/// On x86, the instruction pointer wraps around the address space, and so do
+/// displacements (i.e the shortest displacement is returned).
+pub fn displacement(source: usize, destination: usize) -> Option<isize> {
+  if cfg!(target_pointer_width = "64") {
+    let displacement = destination as i128 - source as i128;
+    isize::try_from(displacement).ok()
+  } else {
+    Some(destination.wrapping_sub(source) as isize)
+  }
+}
+
+/// Returns the address at a displacement from `address`.
+///
+/// On x64, `None` is returned if the address overflows. On x86, the address
+/// wraps around the address space.
+pub fn offset(address: usize, displacement: isize) -> Option<usize> {
+  if cfg!(target_pointer_width = "64") {
+    address.checked_add_signed(displacement)
+  } else {
+    Some(address.wrapping_add_signed(displacement))
+  }
+}
+
+fn is_within(displacement: isize, range: usize) -> bool {
+  let range = range as i64;
   (-range..range).contains(&(displacement as i64))
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[cfg(target_pointer_width = "64")]
+  const GIB: usize = 0x4000_0000;
+
+  #[test]
+  #[cfg(target_pointer_width = "64")]
+  fn displacement_at_boundaries() {
+    // The top of the lower canonical half
+    let top = 0x7FFF_FFFF_FFFF;
+    assert_eq!(displacement(top - 0xF, top), Some(0xF));
+    assert_eq!(displacement(0, usize::MAX), None);
+    assert_eq!(displacement(usize::MAX, 0), None);
+    assert_eq!(displacement(usize::MAX, 1 << 63), Some(isize::MIN + 1));
+
+    assert_eq!(offset(top, 1), Some(top + 1));
+    assert_eq!(offset(usize::MAX - 1, 2), None);
+    assert_eq!(offset(1, -2), None);
+  }
+
+  #[test]
+  #[cfg(target_pointer_width = "64")]
+  fn relative_operand_at_boundaries() {
+    let source = 0x7FFF_FFFF_F000 - 2 * GIB;
+    let end = source + 5;
+
+    // The furthest encodable destinations in either direction
+    assert_eq!(
+      relative_operand(source, 5, end + 2 * GIB - 1),
+      Some(i32::MAX)
+    );
+    assert_eq!(relative_operand(source, 5, end + 2 * GIB), None);
+    assert_eq!(relative_operand(source, 5, end - 2 * GIB), Some(i32::MIN));
+    assert_eq!(relative_operand(source, 5, end - 2 * GIB - 1), None);
+
+    // Displacements never wrap around the address space
+    assert_eq!(relative_operand(0x10, 5, usize::MAX - 0x10), None);
+    assert_eq!(relative_operand(usize::MAX - 0x10, 5, 0x10), None);
+    assert_eq!(relative_operand(usize::MAX - 2, 5, usize::MAX), None);
+
+    assert!(is_reachable(source, 5, end + 2 * GIB - 1));
+    assert!(!is_reachable(source, 5, end + 2 * GIB));
+    assert!(!is_reachable(usize::MAX - 2, 5, usize::MAX));
+  }
+
+  #[test]
+  #[cfg(target_pointer_width = "32")]
+  fn displacement_wraps_around() {
+    // A target just under 2 GiB, in a large address aware process
+    assert_eq!(displacement(0x7FFF_FFF5, 0x8000_0100), Some(0x10B));
+    assert_eq!(relative_operand(0x7FFF_FFF0, 5, 0x8000_0100), Some(0x10B));
+    assert_eq!(relative_operand(0x8000_0100, 5, 0x7FFF_FFF0), Some(-0x115));
+
+    // The instruction pointer wraps around the address space
+    assert_eq!(offset(0xFFFF_FFFE, 5), Some(3));
+    assert_eq!(relative_operand(0xFFFF_FFFE, 5, 0x10), Some(0xD));
+    assert_eq!(relative_operand(0x10, 5, 0xFFFF_FFF0), Some(-0x25));
+    assert!(is_reachable(0xFFFF_FFFE, 5, 0x10));
+  }
+}
@@ -0,0 +1,104 @@
+//! A registry of every live patch, readable without locking or allocating
+//! (e.g from a signal handler).
+//!
+//! Slots are never released, since the registry may be read concurrently;
+//! instead, vacant slots are reused. Slots are only modified whilst holding
+//! `memory::LOCK`. The registry is only read with `std` (see `unload` and
+//! `teardown`).
+#![cfg_attr(not(feature = "std"), allow(dead_code))]
+
+use super::detour::Patch;
+use alloc::boxed::Box;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+/// A patch registered along with its destination.
+struct Slot {
+  patch: AtomicPtr<Patch>,
+  detour: AtomicUsize,
+  sequence: AtomicUsize,
+  next: *const Slot,
+}
+
+unsafe impl Send for Slot {}
+unsafe impl Sync for Slot {}
+
+/// A patch, as read from the registry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry {
+  pub patch: *const Patch,
+  /// The destination of the patch, or zero if it has none.
+  pub detour: usize,
+  /// The order in which the patch was registered.
+  pub sequence: usize,
+}
+
+unsafe impl Send for Entry {}
+
+/// The most recently allocated slot, linking to all others.
+static SLOTS: AtomicPtr<Slot> = AtomicPtr::new(ptr::null_mut());
+
+/// The sequence of the next registered patch.
+static SEQUENCE: AtomicUsize = AtomicUsize::new(1);
+
+/// Registers a patch, whilst holding the lock.
+pub fn register(patch: &Patch, detour: Option<*const ()>) {
+  let slot = match slots().find(|slot| slot.patch.load(Ordering::SeqCst).is_null()) {
+    Some(slot) => slot,
+    None => {
+      let slot = Box::leak(Box::new(Slot {
+        patch: AtomicPtr::default(),
+        detour: AtomicUsize::new(0),
+        sequence: AtomicUsize::new(0),
+        next: SLOTS.load(Ordering::SeqCst),
+      }));
+      SLOTS.store(slot, Ordering::SeqCst);
+      slot
+    },
+  };
+
+  // The slot must be complete before the patch is visible
+  let detour = detour.map_or(0, |detour| detour as usize);
+  slot.detour.store(detour, Ordering::SeqCst);
+  slot
+    .sequence
+    .store(SEQUENCE.fetch_add(1, Ordering::SeqCst), Ordering::SeqCst);
+  slot
+    .patch
+    .store(patch as *const _ as *mut _, Ordering::SeqCst);
+}
+
+/// Unregisters a patch, whilst holding the lock.
+pub fn unregister(patch: &Patch) {
+  if let Some(slot) = slots().find(|slot| ptr::eq(slot.patch.load(Ordering::SeqCst), patch)) {
+    slot.patch.store(ptr::null_mut(), Ordering::SeqCst);
+  }
+}
+
+/// Returns whether an entry is still registered, whilst holding the lock.
+pub fn contains(entry: &Entry) -> bool {
+  entries().any(|other| other == *entry)
+}
+
+/// Returns an iterator over all registered patches, in no particular order.
+pub fn entries() -> impl Iterator<Item = Entry> {
+  slots().filter_map(|slot| {
+    let patch = slot.patch.load(Ordering::SeqCst);
+    (!patch.is_null()).then(|| Entry {
+      patch,
+      detour: slot.detour.load(Ordering::SeqCst),
+      sequence: slot.sequence.load(Ordering::SeqCst),
+    })
+  })
+}
+
+/// Returns an iterator over all allocated slots.
+fn slots() -> impl Iterator<Item = &'static Slot> {
+  let mut current = SLOTS.load(Ordering::SeqCst) as *const Slot;
+
+  core::iter::from_fn(move || unsafe {
+    let slot = current.as_ref()?;
+    current = slot.next;
+    Some(slot)
+  })
+}
@@ -0,0 +1,331 @@
+//! Entries of closure thunks, passing a context as an additional, last
+//! argument (x86/x64).
+//!
+//! The location of the context is determined by the calling convention and
+//! the arguments preceding it. If it's passed in a register, the entry loads
+//! it and jumps to the shim. Otherwise, the entry copies the caller's stack
+//! arguments to a frame of its own, stores the context after them, and calls
+//! the shim.
+
+use super::thunk;
+use crate::error::{Error, Result};
+use crate::pic;
+use crate::thunk::Parameter;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+
+/// A calling convention supported by closure thunks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Convention {
+  SysV,
+  Win64,
+  Cdecl,
+  Stdcall,
+  Fastcall,
+  Thiscall,
+}
+
+impl Convention {
+  /// Returns the convention of an ABI on the target, if supported.
+  fn of(abi: &str) -> Option<Convention> {
+    let abi = abi.strip_suffix("-unwind").unwrap_or(abi);
+    let windows = cfg!(windows);
+
+    Some(match abi {
+      "C" | "system" if cfg!(target_arch = "x86_64") && windows => Convention::Win64,
+      "C" | "system" if cfg!(target_arch = "x86_64") => Convention::SysV,
+      "system" if windows => Convention::Stdcall,
+      "C" | "system" | "cdecl" => Convention::Cdecl,
+      "win64" => Convention::Win64,
+      "sysv64" => Convention::SysV,
+      "stdcall" => Convention::Stdcall,
+      "fastcall" => Convention::Fastcall,
+      "thiscall" => Convention::Thiscall,
+      _ => None?,
+    })
+  }
+}
+
+/// The location of the context.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Location {
+  /// A register, given the opcode loading an immediate into it.
+  Register(&'static [u8]),
+  /// The stack, after the given amount of bytes of the caller's arguments.
+  Stack {
+    arguments: usize,
+    /// The amount of bytes popped by the callee.
+    popped: usize,
+  },
+}
+
+/// The registers of integer arguments, as `mov reg, imm` opcodes.
+#[cfg(target_arch = "x86_64")]
+const SYSV_REGISTERS: [&[u8]; 6] = [
+  &[0x48, 0xBF],
+  &[0x48, 0xBE],
+  &[0x48, 0xBA],
+  &[0x48, 0xB9],
+  &[0x49, 0xB8],
+  &[0x49, 0xB9],
+];
+#[cfg(target_arch = "x86_64")]
+const WIN64_REGISTERS: [&[u8]; 4] = [&[0x48, 0xB9], &[0x48, 0xBA], &[0x49, 0xB8], &[0x49, 0xB9]];
+#[cfg(target_arch = "x86")]
+const FASTCALL_REGISTERS: [&[u8]; 2] = [&[0xB9], &[0xBA]];
+
+/// Returns the location of a context following the arguments, or `None` if
+/// any of them (or the result) is not a scalar.
+fn locate(convention: Convention, arguments: &[Parameter], output: Parameter) -> Option<Location> {
+  const SLOT: usize = core::mem::size_of::<usize>();
+
+  let is_scalar = |parameter: &Parameter| parameter.size.is_power_of_two() && parameter.size <= 8;
+  if !arguments.iter().all(is_scalar) || !(output.size == 0 || is_scalar(&output)) {
+    return None;
+  }
+
+  // Each argument occupies whole slots on the stack
+  let slots = |arguments: &[Parameter]| -> usize {
+    arguments
+      .iter()
+      .map(|argument| argument.size.div_ceil(SLOT) * SLOT)
+      .sum()
+  };
+
+  match convention {
+    #[cfg(target_arch = "x86_64")]
+    Convention::SysV => {
+      let (mut integers, mut floats, mut stack) = (0, 0, 0);
+      for argument in arguments {
+        match argument.float {
+          true if floats < 8 => floats += 1,
+          false if integers < 6 => integers += 1,
+          _ => stack += SLOT,
+        }
+      }
+
+      Some(match SYSV_REGISTERS.get(integers) {
+        Some(register) => Location::Register(register),
+        None => Location::Stack {
+          arguments: stack,
+          popped: 0,
+        },
+      })
+    },
+    #[cfg(target_arch = "x86_64")]
+    Convention::Win64 => Some(match WIN64_REGISTERS.get(arguments.len()) {
+      Some(register) => Location::Register(register),
+      // The first four slots are the home space of the register arguments
+      None => Location::Stack {
+        arguments: arguments.len() * SLOT,
+        popped: 0,
+      },
+    }),
+    #[cfg(target_arch = "x86")]
+    Convention::Cdecl | Convention::Stdcall => Some(Location::Stack {
+      arguments: slots(arguments),
+      popped: if convention == Convention::Stdcall {
+        slots(arguments)
+      } else {
+        0
+      },
+    }),
+    #[cfg(target_arch = "x86")]
+    Convention::Fastcall | Convention::Thiscall => {
+      let capacity = if convention == Convention::Fastcall {
+        2
+      } else {
+        1
+      };
+
+      // Integers up to 32 bits are passed in registers, which is ambiguous
+      // once any other argument precedes them
+      let registers = arguments
+        .iter()
+        .take_while(|argument| !argument.float && argument.size <= SLOT)
+        .take(capacity)
+        .count();
+      if registers < capacity && registers < arguments.len() {
+        return None;
+      }
+
+      let stack = slots(&arguments[registers..]);
+      Some(
+        match FASTCALL_REGISTERS
+          .get(registers)
+          .filter(|_| registers < capacity)
+        {
+          Some(register) => Location::Register(register),
+          None => Location::Stack {
+            arguments: stack,
+            popped: stack,
+          },
+        },
+      )
+    },
+    #[allow(unreachable_patterns)]
+    _ => {
+      let _ = slots;
+      None
+    },
+  }
+}
+
+/// Creates the entry of a closure thunk, calling `shim` with `context` as an
+/// additional, last argument.
+///
+/// The parameters consist of the arguments followed by the result. Returns
+/// `Error::UnsupportedSignature` if the convention, or any parameter, is not
+/// supported.
+pub fn entry(
+  shim: *const (),
+  context: usize,
+  abi: &str,
+  parameters: &[Parameter],
+  signature: impl FnOnce() -> String,
+) -> Result<pic::CodeEmitter> {
+  let (output, arguments) = parameters.split_last().expect("result parameter");
+  let location = Convention::of(abi)
+    .and_then(|convention| locate(convention, arguments, *output))
+    .ok_or_else(|| Error::UnsupportedSignature {
+      signature: signature(),
+    })?;
+
+  let mut emitter = super::meta::entry();
+  match location {
+    Location::Register(opcode) => {
+      let mut code = opcode.to_vec();
+      code.extend(context.to_le_bytes());
+      emitter.add_thunk(Box::new(code));
+      emitter.add_thunk(thunk::jmp(shim as usize));
+    },
+    Location::Stack { arguments, popped } => {
+      emitter.add_thunk(Box::new(enter(arguments, context)));
+      emitter.add_thunk(thunk::call(shim as usize));
+
+      // leave; ret | ret imm16
+      let mut code = vec![0xC9];
+      match popped {
+        0 => code.push(0xC3),
+        popped => {
+          code.push(0xC2);
+          code.extend(u16::try_from(popped).expect("popped bytes").to_le_bytes());
+        },
+      }
+      emitter.add_thunk(Box::new(code));
+    },
+  }
+  Ok(emitter)
+}
+
+/// Creates the code setting up a frame with a copy of the caller's stack
+/// arguments, followed by the context.
+///
+/// The stack remains aligned to 16 bytes, as the callee expects.
+fn enter(arguments: usize, context: usize) -> Vec<u8> {
+  const SLOT: usize = core::mem::size_of::<usize>();
+
+  // The return address and the saved frame pointer precede the frame
+  let size = (arguments + SLOT + 2 * SLOT).div_ceil(16) * 16 - 2 * SLOT;
+  let disp = |value: usize| {
+    u32::try_from(value)
+      .expect("stack displacement")
+      .to_le_bytes()
+  };
+
+  // push rbp; mov rbp, rsp; sub rsp, imm32 (x86 without REX.W)
+  let rex: &[u8] = if cfg!(target_arch = "x86_64") {
+    &[0x48]
+  } else {
+    &[]
+  };
+  let mut code = vec![0x55];
+  code.extend(rex);
+  code.extend([0x89, 0xE5]);
+  code.extend(rex);
+  code.extend([0x81, 0xEC]);
+  code.extend(disp(size));
+
+  // mov rax, [rbp+16+N]; mov [rsp+N], rax
+  for offset in (0..arguments).step_by(SLOT) {
+    code.extend(rex);
+    code.extend([0x8B, 0x85]);
+    code.extend(disp(2 * SLOT + offset));
+    code.extend(rex);
+    code.extend([0x89, 0x84, 0x24]);
+    code.extend(disp(offset));
+  }
+
+  // mov rax, imm; mov [rsp+N], rax
+  code.extend(rex);
+  code.push(0xB8);
+  code.extend(context.to_le_bytes());
+  code.extend(rex);
+  code.extend([0x89, 0x84, 0x24]);
+  code.extend(disp(arguments));
+  code
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const INTEGER: Parameter = Parameter {
+    size: 4,
+    float: false,
+  };
+  const FLOAT: Parameter = Parameter {
+    size: 8,
+    float: true,
+  };
+  const UNIT: Parameter = Parameter {
+    size: 0,
+    float: false,
+  };
+
+  #[test]
+  fn rejects_aggregates() {
+    let aggregate = Parameter {
+      size: 24,
+      float: false,
+    };
+    let convention = Convention::of("C").unwrap();
+    assert_eq!(locate(convention, &[aggregate], UNIT), None);
+    assert_eq!(locate(convention, &[INTEGER], aggregate), None);
+    assert_eq!(Convention::of("Rust"), None);
+  }
+
+  #[test]
+  #[cfg(target_arch = "x86_64")]
+  fn locates_context() {
+    use Location::*;
+
+    let sysv = |arguments: &[Parameter]| locate(Convention::SysV, arguments, UNIT).unwrap();
+    assert_eq!(sysv(&[]), Register(SYSV_REGISTERS[0]));
+    assert_eq!(sysv(&[FLOAT; 8]), Register(SYSV_REGISTERS[0]));
+    assert_eq!(
+      sysv(&[INTEGER, FLOAT, INTEGER]),
+      Register(SYSV_REGISTERS[2])
+    );
+    assert_eq!(
+      sysv(&[[INTEGER; 6].as_slice(), &[FLOAT; 9]].concat()),
+      Stack {
+        arguments: 8,
+        popped: 0
+      }
+    );
+
+    let win64 = |arguments: &[Parameter]| locate(Convention::Win64, arguments, FLOAT).unwrap();
+    assert_eq!(win64(&[FLOAT, FLOAT]), Register(WIN64_REGISTERS[2]));
+    assert_eq!(
+      win64(&[INTEGER; 5]),
+      Stack {
+        arguments: 40,
+        popped: 0
+      }
+    );
+  }
+}
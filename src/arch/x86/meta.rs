@@ -1,21 +1,250 @@
-use super::thunk;
-use crate::{error::Result, pic};
-use std::mem;
+use super::{thunk, Patcher};
+use crate::{arch, error::Result, pic, HotpatchOptions};
+use alloc::boxed::Box;
+use alloc::vec;
+use core::convert::TryFrom;
+use core::{mem, slice};
 
 /// The furthest distance between a target and its detour (2 GiB).
 pub const DETOUR_RANGE: usize = 0x8000_0000;
 
+/// The default maximum amount of bytes relocated from a target's prolog; a
+/// long jump, followed by the longest possible instruction.
+pub const MAX_PROLOG_SIZE: usize = mem::size_of::<thunk::x86::JumpRel>() + 15;
+
 /// Returns the preferred prolog size for the target.
 pub fn prolog_margin(_target: *const ()) -> usize {
   mem::size_of::<thunk::x86::JumpRel>()
 }
 
-/// Creates a relay; required for destinations further away than 2GB (on x64).
-pub fn relay_builder(target: *const (), detour: *const ()) -> Result<Option<pic::CodeEmitter>> {
-  let displacement = (target as isize).wrapping_sub(detour as isize);
+/// Returns the prolog size required for an absolute jump.
+#[cfg(target_arch = "x86_64")]
+pub fn absolute_margin() -> usize {
+  mem::size_of::<thunk::x64::JumpAbs>()
+}
+
+/// Returns the size of the NOP at a hot-patchable target's entry, if it starts
+/// with one, and is preceded by enough padding for a long jump.
+pub unsafe fn hotpatch_entry(
+  target: *const (),
+  options: &HotpatchOptions,
+) -> Result<Option<usize>> {
+  if !options.enabled {
+    return Ok(None);
+  }
+
+  let prefix_size = options
+    .prefix_size
+    .max(mem::size_of::<thunk::x86::JumpRel>());
+  let entry_size = options
+    .entry_size
+    .max(mem::size_of::<thunk::x86::JumpShort>());
+
+  // The padding may reside within another region (or none at all)
+  let prefix = (target as usize).wrapping_sub(prefix_size);
+  if !crate::memory::is_executable(prefix as *const ())? {
+    return Ok(None);
+  }
+
+  let mut nop = vec![0; entry_size];
+  fill_nops(&mut nop);
+
+  // On x86, MSVC uses `mov edi, edi` instead
+  let entry = slice::from_raw_parts(target as *const u8, entry_size);
+  let is_nop = entry == &nop[..] || (cfg!(target_arch = "x86") && entry == &[0x8B, 0xFF][..]);
+  let is_padded = Patcher::is_code_padding(slice::from_raw_parts(prefix as *const u8, prefix_size));
+  Ok((is_nop && is_padded).then_some(entry_size))
+}
+
+/// Returns the size of the NOPs at a target's entry (e.g emitted by
+/// `patchable-function-entry` without a prefix), if they span a long jump.
+pub unsafe fn entry_pad(target: *const (), options: &HotpatchOptions) -> Option<usize> {
+  if !options.enabled || !options.entry_pad {
+    return None;
+  }
+
+  let size = super::trampoline::leading_nops(target, crate::meta::MAX_PROLOG_LIMIT);
+  (size >= mem::size_of::<thunk::x86::JumpRel>()).then_some(size)
+}
+
+/// Returns the end of the code at a target, if it's followed by padding up to
+/// the next `alignment` boundary, disassembling at most `limit` bytes.
+pub unsafe fn padded_end(target: *const (), limit: usize, alignment: usize) -> Option<usize> {
+  super::trampoline::padded_end(target, limit, alignment)
+}
+
+/// Fills a buffer with multi-byte NOPs, used as padding between code.
+pub fn fill_nops(buffer: &mut [u8]) {
+  // The recommended NOP sequences, one for each length (1-9 bytes)
+  const NOPS: [&[u8]; 9] = [
+    &[0x90],
+    &[0x66, 0x90],
+    &[0x0F, 0x1F, 0x00],
+    &[0x0F, 0x1F, 0x40, 0x00],
+    &[0x0F, 0x1F, 0x44, 0x00, 0x00],
+    &[0x66, 0x0F, 0x1F, 0x44, 0x00, 0x00],
+    &[0x0F, 0x1F, 0x80, 0x00, 0x00, 0x00, 0x00],
+    &[0x0F, 0x1F, 0x84, 0x00, 0x00, 0x00, 0x00, 0x00],
+    &[0x66, 0x0F, 0x1F, 0x84, 0x00, 0x00, 0x00, 0x00, 0x00],
+  ];
+
+  for chunk in buffer.chunks_mut(NOPS.len()) {
+    chunk.copy_from_slice(NOPS[chunk.len() - 1]);
+  }
+}
+
+/// Returns the code placed at the entry of all trampolines and relays.
+///
+/// Generated code may be reached by indirect branches (e.g a trampoline stored
+/// in a function pointer), which fault when Indirect Branch Tracking is
+/// enforced, unless they land on an `endbr` instruction.
+pub fn entry() -> pic::CodeEmitter {
+  let mut emitter = pic::CodeEmitter::new();
+  emitter.add_thunk(thunk::endbr());
+  emitter
+}
+
+/// Returns whether the current thread has a shadow stack (CET).
+///
+/// `rdssp` is a NOP unless a shadow stack is enabled, in which case it reads
+/// the shadow stack pointer (which is never null).
+pub fn shadow_stack_enabled() -> bool {
+  let pointer: usize;
+  unsafe {
+    #[cfg(target_arch = "x86_64")]
+    core::arch::asm!(
+      "rdsspq {pointer}",
+      pointer = inout(reg) 0usize => pointer,
+      options(nomem, nostack, preserves_flags),
+    );
+    #[cfg(target_arch = "x86")]
+    core::arch::asm!(
+      "rdsspd {pointer}",
+      pointer = inout(reg) 0usize => pointer,
+      options(nomem, nostack, preserves_flags),
+    );
+  }
+  pointer != 0
+}
+
+/// Returns the breakpoint instruction (`int3`).
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
+pub fn breakpoint() -> [u8; 1] {
+  [0xCC]
+}
+
+/// Creates the code calling `callback` with the registers of the thread,
+/// restoring them afterwards.
+pub fn entry_thunk(callback: extern "C" fn(&arch::RegisterState)) -> pic::CodeEmitter {
+  thunk::call_with_registers(callback as usize)
+}
+
+/// Creates a stub loading an index into a scratch register, before jumping to
+/// a relay at a displacement from the start of the stub.
+///
+/// The register (`r11` on x64, `eax` on x86) is not used for arguments by any
+/// supported calling convention.
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
+pub fn index_stub(index: u32, relay: isize) -> pic::CodeEmitter {
+  // mov r11d, imm32 | mov eax, imm32
+  let mut code = if cfg!(target_arch = "x86_64") {
+    vec![0x41, 0xBB]
+  } else {
+    vec![0xB8]
+  };
+  code.extend(index.to_le_bytes());
+
+  // jmp rel32, relative to the end of the stub
+  let size = code.len() + mem::size_of::<thunk::x86::JumpRel>();
+  let displacement = relay
+    .checked_sub(size as isize)
+    .and_then(|displacement| i32::try_from(displacement).ok())
+    .expect("relay within range of the stub");
+  code.push(0xE9);
+  code.extend(displacement.to_le_bytes());
+
+  let mut emitter = pic::CodeEmitter::new();
+  emitter.add_thunk(Box::new(code));
+  emitter
+}
+
+/// Returns the index loaded by an index stub.
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
+pub fn stub_index(registers: &arch::RegisterState) -> usize {
+  #[cfg(target_arch = "x86_64")]
+  let index = registers.r11;
+  #[cfg(target_arch = "x86")]
+  let index = registers.eax;
+  index as u32 as usize
+}
+
+/// Creates the entry of a closure thunk, calling `shim` with `context` as an
+/// additional, last argument.
+#[cfg(feature = "std")]
+pub fn closure_entry(
+  shim: *const (),
+  context: usize,
+  abi: &str,
+  parameters: &[crate::thunk::Parameter],
+  signature: impl FnOnce() -> alloc::string::String,
+) -> Result<pic::CodeEmitter> {
+  super::closure::entry(shim, context, abi, parameters, signature)
+}
+
+/// Creates a hop, i.e a minimal relay jumping to a destination at any
+/// distance.
+///
+/// Hops are only reached by the jump at their target, therefore they do not
+/// start with an `endbr` instruction.
+pub fn hop(destination: *const ()) -> pic::CodeEmitter {
+  let mut emitter = pic::CodeEmitter::new();
+  emitter.add_thunk(thunk::jmp(destination as usize));
+  emitter
+}
+
+/// Creates an indirect hop, jumping to the destination stored within it, and
+/// returns the offset of the destination.
+///
+/// The destination is aligned to the size of an address (if the hop is), so
+/// it can be replaced atomically, unlike a hop's.
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
+pub fn indirect_hop(destination: *const ()) -> (pic::CodeEmitter, usize) {
+  const CELL: usize = 8;
+  let destination = destination as usize;
+
+  let thunk = pic::FixedThunk::<{ CELL + mem::size_of::<usize>() }>::new(move |source| {
+    let mut code = [0xCC; CELL + mem::size_of::<usize>()];
+
+    // jmp [rip+2] | jmp [cell]
+    let operand = if cfg!(target_arch = "x86_64") {
+      (CELL - 6) as u32
+    } else {
+      (source + CELL) as u32
+    };
+    code[..2].copy_from_slice(&[0xFF, 0x25]);
+    code[2..6].copy_from_slice(&operand.to_le_bytes());
+    code[CELL..].copy_from_slice(&destination.to_ne_bytes());
+    code
+  });
+
+  let mut emitter = pic::CodeEmitter::new();
+  emitter.add_thunk(Box::new(thunk));
+  (emitter, CELL)
+}
+
+/// Creates a relay; required for destinations further away than 2GB (on x64),
+/// or if any code should be executed before the detour.
+pub fn relay_builder(
+  target: *const (),
+  detour: *const (),
+  prologue: pic::CodeEmitter,
+) -> Result<Option<pic::CodeEmitter>> {
+  let jump_rel32_size = mem::size_of::<thunk::x86::JumpRel>();
+  let is_reachable = crate::arch::is_reachable(target as usize, jump_rel32_size, detour as usize);
 
-  if cfg!(target_arch = "x86_64") && !crate::arch::is_within_range(displacement) {
-    let mut emitter = pic::CodeEmitter::new();
+  if !prologue.is_empty() || (cfg!(target_arch = "x86_64") && !is_reachable) {
+    let mut emitter = entry();
+    emitter.append(prologue);
     emitter.add_thunk(thunk::jmp(detour as usize));
     Ok(Some(emitter))
   } else {
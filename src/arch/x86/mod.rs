@@ -1,108 +1,897 @@
 pub use self::patcher::Patcher;
-pub use self::trampoline::Trampoline;
+pub use self::registers::RegisterState;
+pub(crate) use self::trampoline::PendingTrampoline;
+pub use self::trampoline::{RelocationRecord, Trampoline};
 
+#[cfg(feature = "std")]
+mod closure;
 pub mod meta;
 mod patcher;
+mod registers;
 mod thunk;
 mod trampoline;
 
 // TODO: Add test for targets further away than DETOUR_RANGE
-// TODO: Add test for unsupported branches
 // TODO: Add test for negative branch displacements
 #[cfg(all(feature = "nightly", test))]
 mod tests {
-  use std::arch::asm;
   use crate::error::{Error, Result};
+  use crate::testing::{self, ret10, Fixture};
   use crate::RawDetour;
-  use matches::assert_matches;
+  use std::arch::naked_asm;
   use std::mem;
+  use std::string::ToString;
+  use std::vec::Vec;
 
-  /// Default test case function definition.
-  type CRet = unsafe extern "C" fn() -> i32;
+  #[test]
+  fn detour_hotpatch() -> Result<()> {
+    unsafe { testing::assert_hook_roundtrip(testing::hotpatch_ret0(), 0) }
+  }
 
-  /// Detours a C function returning an integer, and asserts its return value.
-  #[inline(never)]
-  unsafe fn detour_test(target: CRet, result: i32) -> Result<()> {
-    let hook = RawDetour::new(target as *const (), ret10 as *const ())?;
+  #[test]
+  fn detour_nop_pad() -> Result<()> {
+    unsafe { testing::assert_hook_roundtrip(testing::nop_pad_ret3, 3)? };
 
-    assert_eq!(target(), result);
-    hook.enable()?;
-    {
-      assert_eq!(target(), 10);
-      let original: CRet = mem::transmute(hook.trampoline());
-      assert_eq!(original(), result);
-    }
-    hook.disable()?;
-    assert_eq!(target(), result);
+    // The jump is written over the pad, and nothing is relocated
+    let target = testing::nop_pad_ret3 as *const ();
+    let hook = unsafe { RawDetour::new(target, ret10 as *const ())? };
+    assert_eq!(hook.trampoline() as *const () as usize, target as usize + 5);
+    assert!(hook.trampoline_map().is_empty());
+    assert!(hook.region().is_none());
     Ok(())
   }
 
+  #[test]
+  fn detour_nop_prefix() -> Result<()> {
+    unsafe { testing::assert_hook_roundtrip(testing::nop_prefix_ret4, 4)? };
 
+    // The NOP is overwritten, but omitted from the trampoline
+    let target = testing::nop_prefix_ret4 as *const ();
+    let hook = unsafe { RawDetour::new(target, ret10 as *const ())? };
+    let map = hook.trampoline_map();
+    assert_eq!(map.len(), 2);
+    assert_eq!(
+      (map[0].original_size, map[0].size, map[0].rewritten),
+      (3, 0, true)
+    );
+    assert_eq!((map[1].original_size, map[1].size), (5, 5));
+    assert_eq!(map[0].offset, map[1].offset);
+    Ok(())
+  }
 
   #[test]
-  fn detour_hotpatch() -> Result<()> {
-    #[naked]
-    unsafe extern "C" fn hotpatch_ret0() -> i32 {
-      asm!(
+  fn detour_split_function() -> Result<()> {
+    unsafe { testing::assert_hook_roundtrip(testing::split_ret11, 11)? };
+
+    // Only the jump is relocated, reaching the same destination
+    let target = testing::split_ret11 as *const ();
+    let hook = unsafe { RawDetour::new(target, ret10 as *const ())? };
+    let map = hook.trampoline_map();
+    assert_eq!(map.len(), 1);
+    assert_eq!((map[0].original_size, map[0].rewritten), (5, true));
+    assert_eq!(hook.patch_size(), 5);
+    Ok(())
+  }
+
+  #[test]
+  #[cfg(target_arch = "x86_64")]
+  fn detour_rip_relative_pos() -> Result<()> {
+    unsafe { testing::assert_hook_roundtrip(testing::rip_relative_ret195, 195) }
+  }
+
+  #[test]
+  #[cfg(target_arch = "x86_64")]
+  fn detour_rip_relative_neg() -> Result<()> {
+    unsafe { testing::assert_hook_roundtrip(testing::rip_relative_prolog_ret49, 49) }
+  }
+
+  #[test]
+  #[cfg(target_arch = "x86_64")]
+  fn detour_rip_relative_immediate() -> Result<()> {
+    unsafe { testing::assert_hook_roundtrip(testing::rip_relative_immediate_ret7, 7) }
+  }
+
+  #[test]
+  fn detour_external_loop() {
+    #[unsafe(naked)]
+    unsafe extern "C" fn external_loop_ret0() -> i32 {
+      naked_asm!(
         "
+            xor ecx, ecx
+            loop 2f
+            nop
+            nop
             nop
             nop
             nop
             nop
             nop
+            nop
+        2:
             xor eax, eax
-            ret
-            mov eax, 5",
-        options(noreturn)
+            ret"
       )
     }
 
-    unsafe { detour_test(mem::transmute(hotpatch_ret0 as usize + 5), 0) }
+    let target = external_loop_ret0 as *const ();
+    let error = unsafe { RawDetour::new(target, ret10 as *const ()) }.unwrap_err();
+
+    // The error describes the loop, which jumps beyond the prolog
+    let expected = (target as usize + 2, 2, &[0xE2, 0x08][..]);
+    match error {
+      Error::UnsupportedInstruction {
+        address,
+        offset,
+        ref bytes,
+        ..
+      } => assert_eq!((address, offset, &bytes[..]), expected),
+      _ => panic!("unexpected error: {:?}", error),
+    }
+
+    let message = error.to_string();
+    assert!(message
+      .lines()
+      .next()
+      .unwrap()
+      .ends_with("(offset 0x2): e2 08"));
+
+    // The listing marks the loop, and is part of the message
+    #[cfg(feature = "disassembly")]
+    {
+      let details = error.details().unwrap();
+      let marked = details.lines().find(|line| line.starts_with("=>")).unwrap();
+      assert!(marked.contains("e2 08") && marked.contains("loop"));
+      assert!(details.lines().next().unwrap().contains("xor ecx, ecx"));
+      assert!(message.ends_with(details));
+    }
   }
 
+  #[test]
+  fn patcher_custom_code() -> Result<()> {
+    use testing::ret5;
+
+    let target = ret5 as *const ();
+    let mut patcher = unsafe { super::Patcher::new(target, ret10 as *const (), 5)? };
+    assert_eq!(patcher.address(), target);
+    assert_eq!(patcher.area(), patcher.original());
+    assert_eq!(patcher.code()[0], 0xE9);
+
+    // Code larger than the patch area is rejected
+    assert!(matches!(
+      patcher.set_code(&[0x90; 6]),
+      Err(Error::NoPatchArea)
+    ));
 
+    // Shorter code is padded, i.e `mov al, 7` is followed by NOPs
+    patcher.set_code(&[0xB0, 0x07])?;
+    assert_eq!(patcher.code(), &[0xB0, 0x07, 0x90, 0x90, 0x90]);
 
+    unsafe {
+      patcher.set_enabled(true)?;
+      assert!(patcher.is_enabled());
+      assert_eq!(patcher.area(), patcher.code());
+      assert_eq!(ret5() & 0xFF, 7);
 
+      patcher.set_enabled(false)?;
+      assert_eq!(ret5(), 5);
+
+      // The original code is restored when an enabled patcher is dropped
+      patcher.set_enabled(true)?;
+      mem::drop(patcher);
+      assert_eq!(ret5(), 5);
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn patcher_invalid_addresses() {
+    let target = ret10 as *const ();
+    let null = unsafe { super::Patcher::new(target, std::ptr::null(), 5) };
+    assert!(matches!(null, Err(Error::NullPointer)));
+
+    #[cfg(target_arch = "x86_64")]
+    {
+      let distant = (target as usize).wrapping_add(1 << 40) as *const ();
+      let result = unsafe { super::Patcher::new(target, distant, 5) };
+      assert!(matches!(result, Err(Error::OutOfRange)));
+    }
+  }
+
+  #[test]
+  fn trampoline_standalone() -> Result<()> {
+    #[unsafe(naked)]
+    unsafe extern "C" fn loop_ret0() -> i32 {
+      naked_asm!(
+        "
+            mov eax, 3
+        2:
+            dec eax
+            jnz 2b
+            ret"
+      )
+    }
+
+    let target = loop_ret0 as *const ();
+    let trampoline = unsafe { super::Trampoline::new(target, 5)? };
+    assert_eq!(trampoline.prolog_size(), 5);
+    assert!(crate::pool::region_of(trampoline.address()).is_some());
+
+    // The target is never patched, and both are callable
+    unsafe {
+      let original: Fixture = mem::transmute(trampoline.address());
+      assert_eq!(original(), 0);
+      assert_eq!(loop_ret0(), 0);
+    }
+
+    let null = unsafe { super::Trampoline::new(std::ptr::null(), 5) };
+    assert!(matches!(null, Err(Error::NullPointer)));
+    Ok(())
+  }
+
+  #[test]
+  fn detour_shims_preserve_flags() -> Result<()> {
+    /// Returns the carry flag, with a relocatable six byte prolog.
+    #[unsafe(naked)]
+    unsafe extern "C" fn carry() -> i32 {
+      naked_asm!(
+        "
+            setc al
+            movzx eax, al
+            ret"
+      )
+    }
+
+    /// Returns the carry flag, offset by ten.
+    #[unsafe(naked)]
+    unsafe extern "C" fn carry_plus10() -> i32 {
+      naked_asm!(
+        "
+            setc al
+            movzx eax, al
+            add eax, 10
+            ret"
+      )
+    }
+
+    /// Calls a function with the carry flag set.
+    unsafe fn call_with_carry(function: *const ()) -> i32 {
+      let result: i32;
+      std::arch::asm!(
+        "stc",
+        "call {function}",
+        function = in(reg) function,
+        out("eax") result,
+        clobber_abi("C"),
+      );
+      result
+    }
+
+    // Each shim clears the carry flag, but restores it afterwards
+    let mut shims = crate::Shims::default();
+    shims.before_detour.add_code(&[0x9C, 0xF8, 0x9D]);
+    shims.before_original.add_code(&[0x9C, 0xF8, 0x9D]);
+
+    unsafe {
+      let hook = RawDetour::with_shims(carry as *const (), carry_plus10 as *const (), shims)?;
+      hook.enable()?;
+      assert_eq!(call_with_carry(carry as *const ()), 11);
+      assert_eq!(call_with_carry(hook.trampoline() as *const ()), 1);
+    }
+
+    // Without restoring the flags, the carry flag is observably cleared
+    let mut shims = crate::Shims::default();
+    shims.before_detour.add_code(&[0xF8]);
+    shims.before_original.add_code(&[0xF8]);
+
+    unsafe {
+      let hook = RawDetour::with_shims(carry as *const (), carry_plus10 as *const (), shims)?;
+      hook.enable()?;
+      assert_eq!(call_with_carry(carry as *const ()), 10);
+      assert_eq!(call_with_carry(hook.trampoline() as *const ()), 0);
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn trampoline_map_records() -> Result<()> {
+    let target = testing::short_branch_ret7 as *const ();
+    let hook = unsafe { RawDetour::new(target, ret10 as *const ())? };
+    let map = hook.trampoline_map();
+
+    // The conditional jump is widened, whilst the others are copied as is
+    let original = map
+      .iter()
+      .map(|record| {
+        (
+          record.original_address as usize - target as usize,
+          record.original_size,
+        )
+      })
+      .collect::<Vec<_>>();
+    assert_eq!(original, [(0, 2), (2, 2), (4, 1)]);
+    assert_eq!(
+      map
+        .iter()
+        .map(|record| record.rewritten)
+        .collect::<Vec<_>>(),
+      [false, true, false]
+    );
+    assert!(map[1].size > map[1].original_size);
+
+    // The emitted code is contiguous, following the landing pad
+    for (record, next) in map.iter().zip(&map[1..]) {
+      assert_eq!(record.offset + record.size, next.offset);
+    }
+    assert_eq!(map[0].offset, ENDBR.len());
+
+    unsafe {
+      let trampoline: Fixture = mem::transmute(hook.trampoline());
+      assert_eq!(trampoline(), 7);
+    }
+    Ok(())
+  }
 
   #[test]
   #[cfg(target_arch = "x86_64")]
-  fn detour_rip_relative_pos() -> Result<()> {
-    #[naked]
-    unsafe extern "C" fn rip_relative_ret195() -> i32 {
-      asm!(
+  fn trampoline_map_rip_relative() -> Result<()> {
+    let target = testing::rip_relative_ret195 as *const ();
+    let hook = unsafe { RawDetour::new(target, ret10 as *const ())? };
+    let map = hook.trampoline_map();
+
+    // The operand is adjusted in place, without changing its size
+    assert_eq!(map.len(), 2);
+    assert!(!map[0].rewritten && map[1].rewritten);
+    assert_eq!(
+      (map[1].offset, map[1].size, map[1].original_size),
+      (ENDBR.len() + 2, 6, 6)
+    );
+    Ok(())
+  }
+
+  #[test]
+  #[cfg(target_arch = "x86_64")]
+  fn trampoline_syscall_stub() -> Result<()> {
+    /// `ntdll!NtClose` of Windows 10 (never called), starting with the
+    /// encoding of `mov r10, rcx` used by `ntdll`.
+    #[unsafe(naked)]
+    unsafe extern "C" fn nt_close() {
+      naked_asm!(
+        "
+            .byte 0x4c, 0x8b, 0xd1
+            mov eax, 0xF
+            test byte ptr [0x7FFE0308], 1
+            jne 2f
+            syscall
+            ret
+        2:
+            int 0x2e
+            ret"
+      )
+    }
+
+    /// `ntdll!NtClose` of Windows 7 (never called).
+    #[unsafe(naked)]
+    unsafe extern "C" fn nt_close_legacy() {
+      naked_asm!(
+        "
+            .byte 0x4c, 0x8b, 0xd1
+            mov eax, 0xC
+            syscall
+            ret
+            nop dword ptr [rax + rax]"
+      )
+    }
+
+    let target = nt_close as *const ();
+    let hook = unsafe { RawDetour::new(target, ret10 as *const ())? };
+    assert_eq!(hook.syscall_number(), Some(0xF));
+
+    // The whole stub is relocated, and only the `jne` is rewritten
+    let map = hook.trampoline_map();
+    let original = map
+      .iter()
+      .map(|record| {
+        (
+          record.original_address as usize - target as usize,
+          record.original_size,
+          record.rewritten,
+        )
+      })
+      .collect::<Vec<_>>();
+    assert_eq!(
+      original,
+      [
+        (0, 3, false),
+        (3, 5, false),
+        (8, 8, false),
+        (16, 2, true),
+        (18, 2, false),
+        (20, 1, false)
+      ]
+    );
+
+    // The widened `jne` branches to the original `int 2e` path, whilst the
+    // trampoline ends with the stub's `syscall; ret`
+    let trampoline = hook.trampoline() as *const () as *const u8;
+    let size = map
+      .last()
+      .map(|record| record.offset + record.size)
+      .unwrap();
+    let code = unsafe { std::slice::from_raw_parts(trampoline, size) };
+    let stub = unsafe { std::slice::from_raw_parts(target as *const u8, 16) };
+    let jne = &code[map[3].offset..map[3].offset + map[3].size];
+    assert_eq!(&code[ENDBR.len()..map[3].offset], stub);
+    assert_eq!(&jne[..8], &[0x74, 0x0E, 0xFF, 0x25, 0x00, 0x00, 0x00, 0x00]);
+    assert_eq!(jne[8..], (target as usize + 21).to_ne_bytes());
+    assert_eq!(&code[map[4].offset..], &[0x0F, 0x05, 0xC3]);
+
+    let legacy = unsafe { RawDetour::new(nt_close_legacy as *const (), ret10 as *const ())? };
+    assert_eq!(legacy.syscall_number(), Some(0xC));
+    assert_eq!(legacy.trampoline_map().len(), 4);
+
+    // Other functions have no system call number
+    let other = unsafe { RawDetour::new(ret10 as *const (), nt_close as *const ())? };
+    assert_eq!(other.syscall_number(), None);
+    Ok(())
+  }
+
+  #[test]
+  fn detour_min_prologue_bytes() -> Result<()> {
+    #[unsafe(naked)]
+    unsafe extern "C" fn sum_ret15() -> i32 {
+      naked_asm!(
         "
             xor eax, eax
-            mov al, [rip+0x3]
-            nop
-            nop
+            add eax, 1
+            add eax, 2
+            add eax, 3
+            add eax, 4
+            add eax, 5
+            ret"
+      )
+    }
+
+    let target = sum_ret15 as *const ();
+    let code = |size| unsafe { std::slice::from_raw_parts(target as *const u8, size).to_vec() };
+    let original = code(17);
+
+    unsafe {
+      let hook = crate::DetourBuilder::new(target, ret10 as *const ())
+        .min_prologue_bytes(16)
+        .build()?;
+
+      // Whole instructions covering 16 bytes are relocated and backed up
+      assert_eq!(hook.patch_address(), target);
+      assert_eq!(hook.original_code(), original);
+      assert_eq!(hook.trampoline_map().len(), 6);
+
+      // The remainder of the patch area consists of NOPs
+      hook.enable()?;
+      assert_eq!(code(17)[5..], [0x90; 12]);
+      assert_eq!(sum_ret15(), 10);
+      let trampoline: Fixture = mem::transmute(hook.trampoline());
+      assert_eq!(trampoline(), 15);
+
+      hook.disable()?;
+      assert_eq!(code(17), original);
+    }
+
+    // By default, only the jump is patched
+    let hook = unsafe { RawDetour::new(target, ret10 as *const ())? };
+    assert_eq!(hook.original_code(), &original[..5]);
+
+    // The minimum is bound by the target, and the maximum prolog size
+    for bytes in [original.len() + 2, crate::meta::max_prolog_size() + 1] {
+      let result = unsafe {
+        RawDetour::builder(target, ret10 as *const ())
+          .min_prologue_bytes(bytes)
+          .build()
+      };
+      assert!(matches!(result, Err(Error::NoPatchArea)));
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn detour_prologue_fill() -> Result<()> {
+    use crate::{DetourBuilder, PrologueFill};
+
+    /// A target with a seven byte prolog, displaced by a five byte jump.
+    #[unsafe(naked)]
+    unsafe extern "C" fn ret15() -> i32 {
+      naked_asm!(
+        "
+            xor eax, eax
+            mov ecx, 15
+            mov eax, ecx
+            ret"
+      )
+    }
+
+    let target = ret15 as *const ();
+    let code = || unsafe { std::slice::from_raw_parts(target as *const u8, 7).to_vec() };
+    let original = code();
+
+    for (fill, remainder) in [
+      (PrologueFill::Original, original[5..].to_vec()),
+      (PrologueFill::Nop, [0x90; 2].to_vec()),
+      (PrologueFill::Int3, [0xCC; 2].to_vec()),
+    ] {
+      unsafe {
+        let hook = DetourBuilder::new(target, ret10 as *const ())
+          .prologue_fill(fill)
+          .build()?;
+
+        hook.enable()?;
+        let enabled = code();
+        assert_eq!(enabled[0], 0xE9);
+        assert_eq!(enabled[5..], remainder[..], "{:?}", fill);
+        assert_eq!(ret15(), 10);
+
+        hook.disable()?;
+        assert_eq!(code(), original);
+        assert_eq!(ret15(), 15);
+      }
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn detour_manual_prologue() -> Result<()> {
+    /// A target with a loop to the instruction after its prolog.
+    #[unsafe(naked)]
+    unsafe extern "C" fn loop_ret7() -> i32 {
+      naked_asm!(
+        "
+            xor ecx, ecx
+            loop 2f
             nop
-            ret",
-        options(noreturn)
+        2:
+            mov eax, 7
+            ret"
       )
     }
 
-    unsafe { detour_test(rip_relative_ret195, 195) }
+    let target = loop_ret7 as *const ();
+    let original = unsafe { std::slice::from_raw_parts(target as *const u8, 5).to_vec() };
+
+    // The loop cannot be relocated automatically
+    let result = unsafe { RawDetour::new(target, ret10 as *const ()) };
+    assert!(matches!(result, Err(Error::UnsupportedInstruction { .. })));
+
+    unsafe {
+      // xor ecx, ecx; dec ecx (the loop always falls through)
+      let hook = RawDetour::builder(target, ret10 as *const ())
+        .with_manual_prologue(&[0x31, 0xC9, 0xFF, 0xC9], 5)
+        .build()?;
+
+      let map = hook.trampoline_map();
+      assert_eq!((map.len(), map[0].original_size, map[0].size), (1, 5, 4));
+      assert_eq!(hook.original_code(), original);
+
+      hook.enable()?;
+      assert_eq!(loop_ret7(), 10);
+      let trampoline: Fixture = mem::transmute(hook.trampoline());
+      assert_eq!(trampoline(), 7);
+
+      hook.disable()?;
+      assert_eq!(loop_ret7(), 7);
+
+      let result = RawDetour::builder(target, ret10 as *const ())
+        .with_manual_prologue(&[], 0)
+        .build();
+      assert!(matches!(result, Err(Error::InvalidOption { .. })));
+    }
+    Ok(())
   }
 
   #[test]
   #[cfg(target_arch = "x86_64")]
-  fn detour_rip_relative_neg() -> Result<()> {
-    #[naked]
-    unsafe extern "C" fn rip_relative_prolog_ret49() -> i32 {
-      asm!(
+  fn detour_lazy_trampoline() -> Result<()> {
+    use testing::rip_relative_ret195;
+
+    let target = rip_relative_ret195 as *const ();
+    let hook = unsafe {
+      RawDetour::builder(target, ret10 as *const ())
+        .lazy_trampoline(true)
+        .build()?
+    };
+
+    // Everything but the trampoline's address is known beforehand
+    assert!(hook.region().is_none());
+    assert_eq!(hook.trampoline_map().len(), 2);
+    assert_eq!(hook.original_code(), unsafe {
+      std::slice::from_raw_parts(target as *const u8, 5)
+    });
+
+    // The prolog is relocated from its backup, after the target is patched
+    unsafe { hook.enable()? };
+    assert_eq!(unsafe { rip_relative_ret195() }, 10);
+    assert!(hook.region().is_none());
+
+    let addresses = std::thread::scope(|scope| {
+      let threads = (0..4)
+        .map(|_| scope.spawn(|| hook.trampoline() as *const () as usize))
+        .collect::<Vec<_>>();
+      threads
+        .into_iter()
+        .map(|thread| thread.join().unwrap())
+        .collect::<Vec<_>>()
+    });
+    assert!(addresses.iter().all(|&address| address == addresses[0]));
+    assert_eq!(hook.try_trampoline()? as *const () as usize, addresses[0]);
+    assert!(hook.region().is_some());
+
+    let original: Fixture = unsafe { mem::transmute(hook.trampoline()) };
+    assert_eq!(unsafe { original() }, 195);
+    unsafe { hook.disable()? };
+    assert_eq!(unsafe { rip_relative_ret195() }, 195);
+    Ok(())
+  }
+
+  /// Verifies the paths through generated code that a shadow stack observes;
+  /// a relay with an entry thunk, and a trampoline with a relocated call.
+  ///
+  /// Whether shadow stacks are enforced depends on the processor, kernel and
+  /// C library, therefore the test passes either way. To validate the paths
+  /// manually on Linux (6.6+, with glibc 2.39+), build every object with
+  /// shadow stack support and enable it for the process:
+  ///
+  /// ```sh
+  /// RUSTFLAGS="-Z cf-protection=full" GLIBC_TUNABLES=glibc.cpu.x86_shstk=on \
+  ///   cargo +nightly test -Z build-std --target x86_64-unknown-linux-gnu --lib shadow_stack
+  /// ```
+  ///
+  /// An unmatched `ret` raises a control protection exception (`SIGSEGV`).
+  #[test]
+  fn shadow_stack_paths() -> Result<()> {
+    #[unsafe(naked)]
+    unsafe extern "C" fn call_ret5() -> i32 {
+      naked_asm!(
         "
-            xor eax, eax
-            mov al, [rip-0x8]
+            call {ret5}
             ret",
-        options(noreturn)
+        ret5 = sym ret5,
       )
     }
 
-    unsafe { detour_test(rip_relative_prolog_ret49, 49) }
+    extern "C" fn ret5() -> i32 {
+      5
+    }
+
+    extern "C" fn callback(_: &crate::RegisterState) {}
+
+    let shims = crate::Shims {
+      before_detour: super::meta::entry_thunk(callback),
+      ..crate::Shims::default()
+    };
+
+    unsafe {
+      let hook = RawDetour::with_shims(call_ret5 as *const (), ret10 as *const (), shims)?;
+      assert!(hook.trampoline_map()[0].rewritten);
+      hook.enable()?;
+      let original: Fixture = mem::transmute(hook.trampoline());
+      assert_eq!((call_ret5(), original()), (10, 5));
+    }
+
+    // The detection agrees with the kernel (ARCH_SHSTK_STATUS)
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    {
+      let mut status = 0u64;
+      let result = unsafe { libc::syscall(libc::SYS_arch_prctl, 0x5005, &mut status) };
+      let enabled = result == 0 && status & 1 != 0;
+      assert_eq!(crate::meta::shadow_stack_enabled(), enabled);
+    }
+    Ok(())
+  }
+
+  /// Verifies that generated code can be the target of indirect branches.
+  ///
+  /// Whether Indirect Branch Tracking is enforced depends on the processor,
+  /// kernel and toolchain, therefore the landing pads are verified by their
+  /// encoding. To verify the behavior manually, build a binary with
+  /// `-C cf-protection=branch` and run it with IBT enforced; an indirect call
+  /// into a trampoline without a landing pad raises a control protection
+  /// exception.
+  #[test]
+  fn endbr_landing_pads() -> Result<()> {
+    use testing::ret5;
+
+    let mut shims = crate::Shims::default();
+    shims.before_detour.add_code(&[0x90]);
+    shims.before_original.add_code(&[0x90]);
+
+    unsafe {
+      for hook in [
+        RawDetour::new(ret5 as *const (), ret10 as *const ())?,
+        RawDetour::with_shims(ret5 as *const (), ret10 as *const (), shims)?,
+      ] {
+        let trampoline = hook.trampoline() as *const () as *const u8;
+        assert_eq!(std::slice::from_raw_parts(trampoline, ENDBR.len()), ENDBR);
+
+        // Indirect calls land on the pad of both the trampoline and relay
+        hook.enable()?;
+        let target: Fixture = std::hint::black_box(ret5);
+        let original: Fixture =
+          std::hint::black_box(mem::transmute::<*const u8, Fixture>(trampoline));
+        assert_eq!((target(), original()), (10, 5));
+        hook.disable()?;
+      }
+    }
+    Ok(())
   }
 
-  /// Default detour target.
-  unsafe extern "C" fn ret10() -> i32 {
-    10
+  #[test]
+  #[cfg(target_arch = "x86_64")]
+  fn entry_detour_preserves_registers() -> Result<()> {
+    use crate::{EntryDetour, RegisterState};
+    use std::arch::asm;
+
+    /// The GPRs (except `rsp`), the flags, the stack pointer and the low
+    /// quadword of `xmm0-7` observed by the target.
+    static mut OBSERVED: [u64; 25] = [0; 25];
+    static mut STACK: u64 = 0;
+    static mut STATE: Option<(RegisterState, u64)> = None;
+
+    /// Calls the target with known register contents and the carry flag set.
+    #[unsafe(naked)]
+    unsafe extern "sysv64" fn drive(target: *const ()) {
+      naked_asm!(
+        "
+            push rbx
+            push rbp
+            push r12
+            push r13
+            push r14
+            push r15
+            push rsi
+            push rdi
+            sub rsp, 8
+            mov [rsp], rdi
+            mov [rip + {stack}], rsp
+            mov eax, 101
+            movq xmm0, rax
+            mov eax, 102
+            movq xmm1, rax
+            mov eax, 103
+            movq xmm2, rax
+            mov eax, 104
+            movq xmm3, rax
+            mov eax, 105
+            movq xmm4, rax
+            mov eax, 106
+            movq xmm5, rax
+            mov eax, 107
+            movq xmm6, rax
+            mov eax, 108
+            movq xmm7, rax
+            mov eax, 1
+            mov ecx, 2
+            mov edx, 3
+            mov ebx, 4
+            mov ebp, 5
+            mov esi, 6
+            mov edi, 7
+            mov r8d, 8
+            mov r9d, 9
+            mov r10d, 10
+            mov r11d, 11
+            mov r12d, 12
+            mov r13d, 13
+            mov r14d, 14
+            mov r15d, 15
+            stc
+            call qword ptr [rsp]
+            add rsp, 8
+            pop rdi
+            pop rsi
+            pop r15
+            pop r14
+            pop r13
+            pop r12
+            pop rbp
+            pop rbx
+            ret",
+        stack = sym STACK,
+      )
+    }
+
+    /// Records the registers it's called with.
+    #[unsafe(naked)]
+    unsafe extern "sysv64" fn record() {
+      naked_asm!(
+        "
+            .byte 0x0f, 0x1f, 0x44, 0x00, 0x00
+            mov [rip + {observed} + 0], rax
+            mov [rip + {observed} + 8], rcx
+            mov [rip + {observed} + 16], rdx
+            mov [rip + {observed} + 24], rbx
+            mov [rip + {observed} + 32], rbp
+            mov [rip + {observed} + 40], rsi
+            mov [rip + {observed} + 48], rdi
+            mov [rip + {observed} + 56], r8
+            mov [rip + {observed} + 64], r9
+            mov [rip + {observed} + 72], r10
+            mov [rip + {observed} + 80], r11
+            mov [rip + {observed} + 88], r12
+            mov [rip + {observed} + 96], r13
+            mov [rip + {observed} + 104], r14
+            mov [rip + {observed} + 112], r15
+            pushfq
+            pop rax
+            mov [rip + {observed} + 120], rax
+            mov [rip + {observed} + 128], rsp
+            movq [rip + {observed} + 136], xmm0
+            movq [rip + {observed} + 144], xmm1
+            movq [rip + {observed} + 152], xmm2
+            movq [rip + {observed} + 160], xmm3
+            movq [rip + {observed} + 168], xmm4
+            movq [rip + {observed} + 176], xmm5
+            movq [rip + {observed} + 184], xmm6
+            movq [rip + {observed} + 192], xmm7
+            ret",
+        observed = sym OBSERVED,
+      )
+    }
+
+    /// Records the state, and clobbers all volatile registers and flags.
+    extern "C" fn callback(registers: &RegisterState) {
+      unsafe {
+        STATE = Some((*registers, &registers.return_address as *const usize as u64));
+        asm!(
+          "xor eax, eax",
+          "mov rcx, -1",
+          "mov rdx, -1",
+          "mov rsi, -1",
+          "mov rdi, -1",
+          "mov r8, -1",
+          "mov r9, -1",
+          "mov r10, -1",
+          "mov r11, -1",
+          "pcmpeqd xmm0, xmm0",
+          "pcmpeqd xmm1, xmm1",
+          "pcmpeqd xmm5, xmm5",
+          "pcmpeqd xmm7, xmm7",
+          out("rax") _, out("rcx") _, out("rdx") _, out("rsi") _, out("rdi") _,
+          out("r8") _, out("r9") _, out("r10") _, out("r11") _,
+          out("xmm0") _, out("xmm1") _, out("xmm5") _, out("xmm7") _,
+        );
+      }
+    }
+
+    unsafe fn observe() -> ([u64; 25], u64) {
+      drive(record as *const ());
+      let observed = *std::ptr::addr_of!(OBSERVED);
+      (observed, *std::ptr::addr_of!(STACK))
+    }
+
+    unsafe {
+      let (expected, stack) = observe();
+      assert_eq!(&expected[..15], (1..=15).collect::<Vec<_>>());
+      assert_eq!(expected[120 / 8] & 1, 1);
+      assert_eq!(expected[128 / 8], stack - 8);
+
+      let hook = EntryDetour::new(record as *const (), callback)?;
+      hook.enable()?;
+      let (observed, _) = observe();
+      hook.disable()?;
+
+      // Neither any register, the flags nor the stack pointer differ
+      assert_eq!(observed, expected);
+
+      // The callback observed the registers of the call
+      let (state, stack_pointer) = (*std::ptr::addr_of!(STATE)).expect("callback invoked");
+      assert_eq!((state.rax, state.rcx, state.r15), (1, 2, 15));
+      assert_eq!(state.rflags & 1, 1);
+      assert_eq!(state.xmm[7][..8], 108u64.to_ne_bytes());
+      assert_eq!(stack_pointer, stack - 8);
+    }
+    Ok(())
   }
+
+  /// The encoding of `endbr64` or `endbr32`.
+  #[cfg(target_arch = "x86_64")]
+  const ENDBR: [u8; 4] = [0xF3, 0x0F, 0x1E, 0xFA];
+  #[cfg(target_arch = "x86")]
+  const ENDBR: [u8; 4] = [0xF3, 0x0F, 0x1E, 0xFB];
 }
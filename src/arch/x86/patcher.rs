@@ -1,27 +1,111 @@
 use super::thunk;
+use crate::arch::{self, memory, DropContext};
 use crate::error::{Error, Result};
-use crate::{pic, util};
-use std::{mem, slice};
+use crate::{os, pic};
+use alloc::vec::Vec;
+use core::{mem, ptr, slice};
 
+/// An inline patch, redirecting a target function to a detour.
+///
+/// This is the building block used by [RawDetour](./struct.RawDetour.html) to
+/// modify a target, and is exposed for custom patch strategies (e.g writing
+/// the patch within a narrow window of time). It does not generate a
+/// trampoline; the original function cannot be called whilst patched.
+///
+/// The patch area consists of a relative jump at the target. If the target's
+/// prolog is too small for one, a short jump is used instead, to a relative
+/// jump within the padding preceding the target (i.e a hot patch). Detours
+/// without any executable memory in range of their target use an absolute
+/// jump instead (x64 only).
+///
+/// # Invariants
+///
+/// A patcher assumes exclusive ownership of its patch area. The area must not
+/// be modified by other means (including another patcher or detour) whilst the
+/// patcher exists, and the target must remain mapped. The patch is restored
+/// when the patcher is dropped, if it's enabled.
+///
+/// # Example
+///
+/// A custom sequence, patching and restoring a target whilst its memory is
+/// made writable only once:
+///
+/// ```rust
+/// # #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+/// # fn main() -> detour::Result<()> {
+/// use detour::{os, Patcher};
+///
+/// /// A target with a five byte prolog (`mov eax, 5`).
+/// #[unsafe(naked)]
+/// extern "C" fn target() -> i32 {
+///   core::arch::naked_asm!("mov eax, 5", "ret")
+/// }
+///
+/// extern "C" fn detour() -> i32 {
+///   10
+/// }
+///
+/// let mut patcher = unsafe { Patcher::new(target as *const (), detour as *const (), 5)? };
+/// assert_eq!(patcher.area(), patcher.original());
+///
+/// // Make the patch area writable for the duration of the sequence
+/// let backend = os::backend()?;
+/// let (address, size) = (patcher.address(), patcher.code().len());
+/// unsafe { backend.protect(address, size, os::Protection::READ_WRITE_EXECUTE)? };
+///
+/// unsafe {
+///   patcher.write(true);
+///   backend.flush_instruction_cache(address, size);
+/// }
+/// assert_eq!(target(), 10);
+///
+/// unsafe {
+///   patcher.write(false);
+///   backend.flush_instruction_cache(address, size);
+///   backend.protect(address, size, os::Protection::READ_EXECUTE)?;
+/// }
+/// assert_eq!(target(), 5);
+/// # Ok(())
+/// # }
+/// # #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+/// # fn main() {}
+/// ```
 pub struct Patcher {
   patch_area: &'static mut [u8],
   original_prolog: Vec<u8>,
   detour_prolog: Vec<u8>,
+  enabled: bool,
 }
 
 impl Patcher {
-  /// Creates a new detour patcher for an address.
+  /// Creates a new (disabled) patcher, redirecting a target to a detour.
   ///
   /// # Arguments
   ///
   /// * `target` - An address that should be hooked.
-  /// * `detour` - An address that the target should be redirected to.
-  /// * `prolog_size` - The available inline space for the hook.
+  /// * `detour` - An address that the target should be redirected to. It must
+  ///   be within range of a relative jump (±2GB) from the target.
+  /// * `prolog_size` - The available inline space for the hook, i.e the size of
+  ///   the whole instructions at the target that may be overwritten.
   pub unsafe fn new(target: *const (), detour: *const (), prolog_size: usize) -> Result<Patcher> {
+    if target.is_null() || detour.is_null() {
+      Err(Error::NullPointer)?;
+    }
+
+    if !crate::memory::is_executable(target)? {
+      Err(Error::NotExecutable)?;
+    }
+
     // Calculate the patch area (i.e if a short or long jump should be used)
     let patch_area = Self::patch_area(target, prolog_size)?;
-    let emitter = Self::hook_template(detour, patch_area);
+    let jump_rel32_size = mem::size_of::<thunk::x86::JumpRel>();
+    let jump_source = patch_area.as_ptr() as usize;
+
+    if !arch::is_reachable(jump_source, jump_rel32_size, detour as usize) {
+      Err(Error::OutOfRange)?;
+    }
 
+    let emitter = Self::hook_template(detour, patch_area);
     let patch_address = patch_area.as_ptr() as *const ();
     let original_prolog = patch_area.to_vec();
 
@@ -29,22 +113,340 @@ impl Patcher {
       detour_prolog: emitter.emit(patch_address),
       original_prolog,
       patch_area,
+      enabled: false,
+    })
+  }
+
+  /// Creates a new (disabled) patcher, writing arbitrary code at the target.
+  ///
+  /// The code must not exceed the whole instructions at the target.
+  #[cfg_attr(not(feature = "std"), allow(dead_code))]
+  pub(crate) unsafe fn with_code(target: *const (), code: &[u8]) -> Patcher {
+    let patch_area = slice::from_raw_parts_mut(target as *mut u8, code.len());
+
+    Patcher {
+      original_prolog: patch_area.to_vec(),
+      detour_prolog: code.to_vec(),
+      patch_area,
+      enabled: false,
+    }
+  }
+
+  /// Creates an enabled patcher for a patch written by another instance of
+  /// the library, given its original and current code.
+  ///
+  /// Returns `Error::IncompatibleState` unless the area contains the code.
+  #[cfg_attr(not(feature = "std"), allow(dead_code))]
+  pub(crate) unsafe fn adopt(address: *const (), original: &[u8], code: &[u8]) -> Result<Patcher> {
+    if original.len() != code.len() || !crate::memory::is_executable(address)? {
+      Err(Error::IncompatibleState)?;
+    }
+
+    let patch_area = slice::from_raw_parts_mut(address as *mut u8, code.len());
+    if *patch_area != *code {
+      Err(Error::IncompatibleState)?;
+    }
+
+    Ok(Patcher {
+      original_prolog: original.to_vec(),
+      detour_prolog: code.to_vec(),
+      patch_area,
+      enabled: true,
+    })
+  }
+
+  /// Creates a new (disabled) patcher for a hot-patchable target, writing a
+  /// long jump within the padding preceding it, and a short jump to it at the
+  /// target.
+  pub(crate) unsafe fn hotpatch(target: *const (), detour: *const ()) -> Result<Patcher> {
+    let jump_rel08_size = mem::size_of::<thunk::x86::JumpShort>();
+    let jump_rel32_size = mem::size_of::<thunk::x86::JumpRel>();
+
+    // The long jump precedes the target
+    let jump_source = (target as usize).wrapping_sub(jump_rel32_size);
+    if !arch::is_reachable(jump_source, jump_rel32_size, detour as usize) {
+      Err(Error::OutOfRange)?;
+    }
+
+    let patch_area =
+      slice::from_raw_parts_mut(jump_source as *mut u8, jump_rel32_size + jump_rel08_size);
+    let emitter = Self::hook_template(detour, patch_area);
+
+    Ok(Patcher {
+      detour_prolog: emitter.emit(patch_area.as_ptr() as *const ()),
+      original_prolog: patch_area.to_vec(),
+      patch_area,
+      enabled: false,
     })
   }
 
-  /// Returns the target's patch area.
+  /// Creates a new (disabled) patcher, redirecting a target to a detour at
+  /// any distance, using an absolute jump (`jmp [rip+0]` and the address).
+  ///
+  /// The jump spans 14 bytes, which must be covered by the whole instructions
+  /// at the target (i.e `prolog_size`) and any padding after them.
+  #[cfg(target_arch = "x86_64")]
+  pub(crate) unsafe fn absolute(
+    target: *const (),
+    detour: *const (),
+    prolog_size: usize,
+  ) -> Result<Patcher> {
+    let jump_abs_size = mem::size_of::<thunk::x64::JumpAbs>();
+    if !Self::is_patchable(target, prolog_size, jump_abs_size) {
+      Err(Error::NoPatchArea)?;
+    }
+
+    let patch_area = slice::from_raw_parts_mut(target as *mut u8, jump_abs_size);
+    let mut emitter = pic::CodeEmitter::new();
+    emitter.add_thunk(thunk::x64::jmp_abs(detour as usize));
+
+    Ok(Patcher {
+      detour_prolog: emitter.emit(target),
+      original_prolog: patch_area.to_vec(),
+      patch_area,
+      enabled: false,
+    })
+  }
+
+  /// Extends the patch area to span `size` bytes, filling the remainder of
+  /// the code with either NOPs or breakpoints.
+  ///
+  /// The area must start at the target, and `size` must not exceed the whole
+  /// instructions at it. Patchers are only extended before being enabled.
+  pub(crate) unsafe fn extend(&mut self, size: usize, fill: arch::PrologueFill) {
+    debug_assert!(!self.enabled);
+    if size <= self.patch_area.len() {
+      return;
+    }
+
+    self.patch_area = slice::from_raw_parts_mut(self.patch_area.as_mut_ptr(), size);
+    self.original_prolog = self.patch_area.to_vec();
+
+    match fill {
+      arch::PrologueFill::Original => {
+        let length = self.detour_prolog.len();
+        self
+          .detour_prolog
+          .extend_from_slice(&self.original_prolog[length..]);
+      },
+      arch::PrologueFill::Nop => self.detour_prolog.resize(size, 0x90),
+      arch::PrologueFill::Int3 => self.detour_prolog.resize(size, 0xCC),
+    }
+  }
+
+  /// Returns the address of the patch area.
+  ///
+  /// This precedes the target if a hot patch is used.
+  pub fn address(&self) -> *const () {
+    self.patch_area.as_ptr() as *const ()
+  }
+
+  /// Returns the current contents of the patch area.
   pub fn area(&self) -> &[u8] {
     self.patch_area
   }
 
-  /// Either patches or unpatches the function.
-  pub unsafe fn toggle(&mut self, enable: bool) {
+  /// Returns the code written to the patch area when enabled.
+  pub fn code(&self) -> &[u8] {
+    &self.detour_prolog
+  }
+
+  /// Returns the original contents of the patch area, restored when
+  /// disabled.
+  pub fn original(&self) -> &[u8] {
+    &self.original_prolog
+  }
+
+  /// Replaces the code written to the patch area when enabled.
+  ///
+  /// The code may be shorter than the patch area, in which case it's padded
+  /// with NOPs, but not larger. An enabled patch is not rewritten; the code is
+  /// written the next time it's enabled.
+  pub fn set_code(&mut self, code: &[u8]) -> Result<()> {
+    if code.len() > self.patch_area.len() {
+      Err(Error::NoPatchArea)?;
+    }
+
+    self.detour_prolog.clear();
+    self.detour_prolog.extend_from_slice(code);
+    self.detour_prolog.resize(self.patch_area.len(), 0x90);
+    Ok(())
+  }
+
+  /// Returns whether the patch is enabled or not.
+  pub fn is_enabled(&self) -> bool {
+    self.enabled
+  }
+
+  /// Either patches or unpatches the target.
+  ///
+  /// The patch area's protection is changed for the duration of the write,
+  /// and the instruction cache is flushed afterwards. Operating system calls
+  /// are serialized with those of all detours, and the
+  /// [patch callbacks](./struct.PatchCallbacks.html) are invoked around the
+  /// write.
+  pub unsafe fn set_enabled(&mut self, enabled: bool) -> Result<()> {
+    let _guard = memory::LOCK.lock();
+    self.set_enabled_locked(enabled)
+  }
+
+  /// Either patches or unpatches the target, whilst holding the lock.
+  pub(crate) unsafe fn set_enabled_locked(&mut self, enabled: bool) -> Result<()> {
+    if self.enabled == enabled {
+      return Ok(());
+    }
+
+    let callbacks = arch::patch_callbacks();
+    let (address, size) = (self.address(), self.patch_area.len());
+    callbacks.before(address, self.prolog(enabled))?;
+
+    {
+      // Runtime code is by default only read-execute
+      let _handle = os::protect_with_guard(address, size, os::Protection::READ_WRITE_EXECUTE)?;
+
+      self.write(enabled);
+      os::backend()?.flush_instruction_cache(address, size);
+    }
+
+    callbacks.after(address, size);
+    Ok(())
+  }
+
+  /// Treats the patch as disabled, without restoring the patch area (e.g
+  /// because it has been unmapped).
+  pub(crate) fn abandon(&mut self) {
+    self.enabled = false;
+  }
+
+  /// Returns the code written to the patch area when either enabled or
+  /// disabled.
+  pub(crate) fn prolog(&self, enabled: bool) -> &[u8] {
+    if enabled {
+      &self.detour_prolog
+    } else {
+      &self.original_prolog
+    }
+  }
+
+  /// Writes either the patch or the original code to the patch area.
+  ///
+  /// Unlike `set_enabled`, the patch area is written as is; it must be
+  /// writable, and the caller is responsible for flushing the instruction
+  /// cache.
+  pub unsafe fn write(&mut self, enabled: bool) {
     // Copy either the detour or the original bytes of the function
-    self.patch_area.copy_from_slice(if enable {
+    let code = if enabled {
       &self.detour_prolog
     } else {
       &self.original_prolog
-    });
+    };
+
+    // Prevent other threads from executing a partially written patch
+    if Self::write_atomic(self.patch_area, code) {
+      self.enabled = enabled;
+      return;
+    }
+
+    // The short jump of a hot patch must never lead to a partial long jump;
+    // it's written after it, and restored before it
+    let jump_rel08_size = mem::size_of::<thunk::x86::JumpShort>();
+    let jump_rel32_size = mem::size_of::<thunk::x86::JumpRel>();
+    if self.patch_area.len() > jump_rel32_size + jump_rel08_size {
+      // Threads reaching an absolute jump whilst it's written spin at its
+      // entry, until its first instruction is complete
+      let (entry, rest) = self.patch_area.split_at_mut(jump_rel08_size);
+      // A jump to itself (i.e `jmp $`), written without allocating
+      let spin = [0xEB, 0xFE];
+
+      if Self::write_atomic(entry, &spin) {
+        rest.copy_from_slice(&code[jump_rel08_size..]);
+        if !Self::write_atomic(entry, &code[..jump_rel08_size]) {
+          entry.copy_from_slice(&code[..jump_rel08_size]);
+        }
+      } else {
+        self.patch_area.copy_from_slice(code);
+      }
+    } else if self.patch_area.len() > jump_rel32_size {
+      let (long, short) = self.patch_area.split_at_mut(jump_rel32_size);
+      if enabled {
+        long.copy_from_slice(&code[..jump_rel32_size]);
+      }
+
+      if !Self::write_atomic(short, &code[jump_rel32_size..]) {
+        short.copy_from_slice(&code[jump_rel32_size..]);
+      }
+
+      if !enabled {
+        long.copy_from_slice(&code[..jump_rel32_size]);
+      }
+    } else {
+      self.patch_area.copy_from_slice(code);
+    }
+    self.enabled = enabled;
+  }
+
+  /// Writes code atomically, if it resides within an aligned 64-bit word.
+  #[cfg(target_has_atomic = "64")]
+  pub(crate) unsafe fn write_atomic(area: &mut [u8], code: &[u8]) -> bool {
+    let offset = area.as_ptr() as usize % mem::size_of::<u64>();
+    if offset + code.len() > mem::size_of::<u64>() {
+      return false;
+    }
+
+    let word = (area.as_ptr() as usize - offset) as *mut u64;
+    let mut current = ptr::read_volatile(word);
+
+    loop {
+      let mut bytes = current.to_ne_bytes();
+      bytes[offset..offset + code.len()].copy_from_slice(code);
+
+      match Self::compare_exchange(word, current, u64::from_ne_bytes(bytes)) {
+        Ok(()) => return true,
+        Err(value) => current = value,
+      }
+    }
+  }
+
+  /// Exchanges a word of code, if it contains `current`.
+  ///
+  /// On x64, the instruction is issued directly, since code is not memory
+  /// tracked by sanitizers (e.g ThreadSanitizer has no shadow memory for it).
+  #[cfg(target_arch = "x86_64")]
+  unsafe fn compare_exchange(
+    word: *mut u64,
+    current: u64,
+    new: u64,
+  ) -> core::result::Result<(), u64> {
+    let previous: u64;
+    core::arch::asm!(
+      "lock cmpxchg qword ptr [{word}], {new}",
+      word = in(reg) word,
+      new = in(reg) new,
+      inout("rax") current => previous,
+      options(nostack),
+    );
+    if previous == current {
+      Ok(())
+    } else {
+      Err(previous)
+    }
+  }
+
+  #[cfg(all(target_arch = "x86", target_has_atomic = "64"))]
+  unsafe fn compare_exchange(
+    word: *mut u64,
+    current: u64,
+    new: u64,
+  ) -> core::result::Result<(), u64> {
+    use core::sync::atomic::{AtomicU64, Ordering};
+    (*(word as *const AtomicU64))
+      .compare_exchange(current, new, Ordering::SeqCst, Ordering::SeqCst)
+      .map(|_| ())
+  }
+
+  #[cfg(not(target_has_atomic = "64"))]
+  pub(crate) unsafe fn write_atomic(_area: &mut [u8], _code: &[u8]) -> bool {
+    false
   }
 
   /// Returns the patch area for a function, consisting of a long jump and
@@ -59,12 +461,12 @@ impl Patcher {
       if Self::is_patchable(target, prolog_size, jump_rel08_size) {
         // A small jump relies on there being a hot patch area above the
         // function, that consists of at least 5 bytes (a rel32 jump).
-        let hot_patch = target as usize - jump_rel32_size;
+        let hot_patch = (target as usize).wrapping_sub(jump_rel32_size);
         let hot_patch_area = slice::from_raw_parts(hot_patch as *const u8, jump_rel32_size);
 
         // Ensure that the hot patch area only contains padding and is executable
         if !Self::is_code_padding(hot_patch_area)
-          || !util::is_executable_address(hot_patch_area.as_ptr() as *const _)?
+          || !crate::memory::is_executable(hot_patch_area.as_ptr() as *const _)?
         {
           Err(Error::NoPatchArea)?;
         }
@@ -115,18 +517,41 @@ impl Patcher {
       return true;
     }
 
-    // Otherwise the inline patch relies on padding after the prolog
-    let slice = slice::from_raw_parts(
-      (target as usize + prolog_size) as *const u8,
-      patch_size - prolog_size,
-    );
+    // Otherwise the inline patch relies on padding after the prolog, which
+    // must also be after the function, if its bounds are known
+    let prolog_end = target as usize + prolog_size;
+    if crate::analysis::unwind_bounds(target).is_some_and(|bounds| bounds.end != prolog_end) {
+      return false;
+    }
+
+    let slice = slice::from_raw_parts(prolog_end as *const u8, patch_size - prolog_size);
 
     Self::is_code_padding(slice)
   }
 
   /// Returns true if the slice only contains code padding.
-  fn is_code_padding(buffer: &[u8]) -> bool {
+  pub(crate) fn is_code_padding(buffer: &[u8]) -> bool {
     const PADDING: [u8; 3] = [0x00, 0x90, 0xCC];
     buffer.iter().all(|code| PADDING.contains(code))
   }
 }
+
+impl Drop for Patcher {
+  /// Restores the original code, if enabled.
+  ///
+  /// A failure is reported to the
+  /// [drop error handler](./fn.set_drop_error_handler.html).
+  fn drop(&mut self) {
+    if self.enabled {
+      if let Err(error) = unsafe { self.set_enabled(false) } {
+        arch::report_drop_error(
+          &error,
+          DropContext {
+            target: self.address(),
+            size: self.patch_area.len(),
+          },
+        );
+      }
+    }
+  }
+}
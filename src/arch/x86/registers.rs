@@ -0,0 +1,59 @@
+/// The registers of a thread when a function is entered (x86).
+///
+/// The layout mirrors the stack of an [EntryDetour](./struct.EntryDetour.html)
+/// thunk, from the last saved register to the first.
+#[cfg(target_arch = "x86")]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegisterState {
+  /// The vector registers `xmm0` to `xmm5`.
+  pub xmm: [[u8; 16]; 6],
+  pub edi: usize,
+  pub esi: usize,
+  pub ebp: usize,
+  /// The stack pointer once the flags have been saved.
+  pub esp: usize,
+  pub ebx: usize,
+  pub edx: usize,
+  pub ecx: usize,
+  pub eax: usize,
+  pub eflags: usize,
+  /// The address the function returns to.
+  ///
+  /// The field resides at the top of the thread's stack, i.e its address is
+  /// the stack pointer when the function was entered.
+  pub return_address: usize,
+}
+
+/// The registers of a thread when a function is entered (x64).
+///
+/// The layout mirrors the stack of an [EntryDetour](./struct.EntryDetour.html)
+/// thunk, from the last saved register to the first.
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegisterState {
+  /// The vector registers `xmm0` to `xmm7`.
+  pub xmm: [[u8; 16]; 8],
+  pub r15: usize,
+  pub r14: usize,
+  pub r13: usize,
+  pub r12: usize,
+  pub r11: usize,
+  pub r10: usize,
+  pub r9: usize,
+  pub r8: usize,
+  pub rdi: usize,
+  pub rsi: usize,
+  pub rbp: usize,
+  pub rbx: usize,
+  pub rdx: usize,
+  pub rcx: usize,
+  pub rax: usize,
+  pub rflags: usize,
+  /// The address the function returns to.
+  ///
+  /// The field resides at the top of the thread's stack, i.e its address is
+  /// the stack pointer when the function was entered.
+  pub return_address: usize,
+}
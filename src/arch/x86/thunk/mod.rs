@@ -10,6 +10,8 @@ pub mod x64;
 #[cfg(target_arch = "x86")]
 mod arch {
   pub use super::x86::call_rel32 as call;
+  pub use super::x86::call_with_registers_x86 as call_with_registers;
+  pub use super::x86::endbr32 as endbr;
   pub use super::x86::jcc_rel32 as jcc;
   pub use super::x86::jmp_rel32 as jmp;
 }
@@ -17,6 +19,8 @@ mod arch {
 #[cfg(target_arch = "x86_64")]
 mod arch {
   pub use super::x64::call_abs as call;
+  pub use super::x64::call_with_registers_x64 as call_with_registers;
+  pub use super::x64::endbr64 as endbr;
   pub use super::x64::jcc_abs as jcc;
   pub use super::x64::jmp_abs as jmp;
 }
@@ -1,7 +1,9 @@
-use crate::pic::Thunkable;
-use std::mem;
+use crate::pic::{self, Thunkable};
+use alloc::boxed::Box;
+use alloc::vec;
+use core::mem;
 
-#[repr(packed)]
+#[repr(C, packed)]
 struct CallAbs {
   // call [rip+8]
   opcode0: u8,
@@ -28,8 +30,8 @@ pub fn call_abs(destination: usize) -> Box<dyn Thunkable> {
   Box::new(slice.to_vec())
 }
 
-#[repr(packed)]
-struct JumpAbs {
+#[repr(C, packed)]
+pub struct JumpAbs {
   // jmp +6
   opcode0: u8,
   opcode1: u8,
@@ -50,7 +52,7 @@ pub fn jmp_abs(destination: usize) -> Box<dyn Thunkable> {
   Box::new(slice.to_vec())
 }
 
-#[repr(packed)]
+#[repr(C, packed)]
 struct JccAbs {
   // jxx + 16
   opcode: u8,
@@ -76,3 +78,44 @@ pub fn jcc_abs(destination: usize, condition: u8) -> Box<dyn Thunkable> {
   let slice: [u8; 16] = unsafe { mem::transmute(code) };
   Box::new(slice.to_vec())
 }
+
+/// Returns an indirect branch landing pad (`endbr64`).
+///
+/// It's required by Indirect Branch Tracking, and is a no-op otherwise.
+pub fn endbr64() -> Box<dyn Thunkable> {
+  Box::new([0xF3, 0x0F, 0x1E, 0xFA].to_vec())
+}
+
+/// Constructs a thunk calling `callback` with a pointer to all saved
+/// registers (i.e a `RegisterState`), and restoring them afterwards.
+///
+/// The pointer is passed in both `rdi` and `rcx`, satisfying the System V and
+/// Windows calling conventions alike.
+pub fn call_with_registers_x64(callback: usize) -> pic::CodeEmitter {
+  let mut emitter = pic::CodeEmitter::new();
+
+  // pushfq; push rax, rcx, rdx, rbx, rbp, rsi, rdi, r8-r15
+  let mut save = vec![0x9C, 0x50, 0x51, 0x52, 0x53, 0x55, 0x56, 0x57];
+  save.extend((0x50..0x58).flat_map(|opcode| [0x41, opcode]));
+
+  // sub rsp, 0x80; movdqu [rsp+N*16], xmmN
+  save.extend([0x48, 0x81, 0xEC, 0x80, 0x00, 0x00, 0x00]);
+  save.extend(super::x86::movdqu_stack(true, 8));
+
+  // mov rbx, rsp; mov rdi, rsp; mov rcx, rsp; and rsp, -16; sub rsp, 0x20
+  save.extend([0x48, 0x89, 0xE3, 0x48, 0x89, 0xE7, 0x48, 0x89, 0xE1]);
+  save.extend([0x48, 0x83, 0xE4, 0xF0, 0x48, 0x83, 0xEC, 0x20]);
+  emitter.add_thunk(Box::new(save));
+  emitter.add_thunk(call_abs(callback));
+
+  // mov rsp, rbx; movdqu xmmN, [rsp+N*16]; add rsp, 0x80
+  let mut restore = vec![0x48, 0x89, 0xDC];
+  restore.extend(super::x86::movdqu_stack(false, 8));
+  restore.extend([0x48, 0x81, 0xC4, 0x80, 0x00, 0x00, 0x00]);
+
+  // pop r15-r8, rdi, rsi, rbp, rbx, rdx, rcx, rax; popfq
+  restore.extend((0x58..0x60).rev().flat_map(|opcode| [0x41, opcode]));
+  restore.extend([0x5F, 0x5E, 0x5D, 0x5B, 0x5A, 0x59, 0x58, 0x9D]);
+  emitter.add_thunk(Box::new(restore));
+  emitter
+}
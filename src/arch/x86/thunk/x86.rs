@@ -1,8 +1,10 @@
-use crate::pic::{FixedThunk, Thunkable};
-use generic_array::{typenum, GenericArray};
-use std::mem;
+use crate::pic::{self, FixedThunk, Thunkable};
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem;
 
-#[repr(packed)]
+#[repr(C, packed)]
 pub struct JumpRel {
   opcode: u8,
   operand: u32,
@@ -13,14 +15,13 @@ fn relative32(destination: usize, is_jump: bool) -> Box<dyn Thunkable> {
   const CALL: u8 = 0xE8;
   const JMP: u8 = 0xE9;
 
-  Box::new(FixedThunk::<typenum::U5>::new(move |source| {
+  Box::new(FixedThunk::<5>::new(move |source| {
     let code = JumpRel {
       opcode: if is_jump { JMP } else { CALL },
       operand: calculate_displacement(source, destination, mem::size_of::<JumpRel>()),
     };
 
-    let slice: [u8; 5] = unsafe { mem::transmute(code) };
-    GenericArray::clone_from_slice(&slice)
+    unsafe { mem::transmute::<_, [u8; 5]>(code) }
   }))
 }
 
@@ -29,6 +30,13 @@ pub fn nop() -> Box<dyn Thunkable> {
   Box::new([0x90].to_vec())
 }
 
+/// Returns an indirect branch landing pad (`endbr32`).
+///
+/// It's required by Indirect Branch Tracking, and is a no-op otherwise.
+pub fn endbr32() -> Box<dyn Thunkable> {
+  Box::new([0xF3, 0x0F, 0x1E, 0xFB].to_vec())
+}
+
 /// Constructs a relative call operation.
 pub fn call_rel32(destination: usize) -> Box<dyn Thunkable> {
   relative32(destination, false)
@@ -39,7 +47,7 @@ pub fn jmp_rel32(destination: usize) -> Box<dyn Thunkable> {
   relative32(destination, true)
 }
 
-#[repr(packed)]
+#[repr(C, packed)]
 struct JccRel {
   opcode0: u8,
   opcode1: u8,
@@ -48,19 +56,18 @@ struct JccRel {
 
 /// Constructs a conditional relative jump operation.
 pub fn jcc_rel32(destination: usize, condition: u8) -> Box<dyn Thunkable> {
-  Box::new(FixedThunk::<typenum::U6>::new(move |source| {
+  Box::new(FixedThunk::<6>::new(move |source| {
     let code = JccRel {
       opcode0: 0x0F,
       opcode1: 0x80 | condition,
       operand: calculate_displacement(source, destination, mem::size_of::<JccRel>()),
     };
 
-    let slice: [u8; 6] = unsafe { mem::transmute(code) };
-    GenericArray::clone_from_slice(&slice)
+    unsafe { mem::transmute::<_, [u8; 6]>(code) }
   }))
 }
 
-#[repr(packed)]
+#[repr(C, packed)]
 pub struct JumpShort {
   opcode: u8,
   operand: i8,
@@ -68,26 +75,57 @@ pub struct JumpShort {
 
 /// Constructs a relative short jump.
 pub fn jmp_rel8(displacement: i8) -> Box<dyn Thunkable> {
-  Box::new(FixedThunk::<typenum::U2>::new(move |_| {
+  Box::new(FixedThunk::<2>::new(move |_| {
     let code = JumpShort {
       opcode: 0xEB,
       operand: displacement - mem::size_of::<JumpShort>() as i8,
     };
 
-    let slice: [u8; 2] = unsafe { mem::transmute(code) };
-    GenericArray::clone_from_slice(&slice)
+    unsafe { mem::transmute::<_, [u8; 2]>(code) }
   }))
 }
 
 /// Calculates the relative displacement for an instruction.
+///
+/// The destination must be within range of a relative jump (+/- 2GB), which
+/// is verified before any code is emitted; a wrapped displacement is never
+/// emitted.
 fn calculate_displacement(source: usize, destination: usize, instruction_size: usize) -> u32 {
-  let displacement =
-    (destination as isize).wrapping_sub(source as isize + instruction_size as isize);
+  crate::arch::relative_operand(source, instruction_size, destination)
+    .expect("destination within range of a relative operand") as u32
+}
 
-  // Ensure that the detour can be reached with a relative jump (+/- 2GB).
-  // This only needs to be asserted on x64, since it wraps around on x86.
-  #[cfg(target_arch = "x86_64")]
-  assert!(crate::arch::is_within_range(displacement));
+/// Constructs `movdqu` operations between XMM registers and the stack.
+///
+/// Each register, starting from `xmm0`, is stored at (or loaded from) a
+/// multiple of 16 bytes from the stack pointer.
+pub fn movdqu_stack(store: bool, registers: u8) -> Vec<u8> {
+  (0..registers)
+    .flat_map(|index| {
+      let opcode = if store { 0x7F } else { 0x6F };
+      [0xF3, 0x0F, opcode, 0x44 | (index << 3), 0x24, index * 16]
+    })
+    .collect()
+}
 
-  displacement as u32
+/// Constructs a thunk calling `callback` with a pointer to all saved
+/// registers (i.e a `RegisterState`), and restoring them afterwards.
+pub fn call_with_registers_x86(callback: usize) -> pic::CodeEmitter {
+  let mut emitter = pic::CodeEmitter::new();
+
+  // pushfd; pushad; sub esp, 0x60; movdqu [esp+N*16], xmmN
+  let mut save = vec![0x9C, 0x60, 0x83, 0xEC, 0x60];
+  save.extend(movdqu_stack(true, 6));
+
+  // mov ebx, esp; and esp, -16; sub esp, 12; push ebx
+  save.extend([0x89, 0xE3, 0x83, 0xE4, 0xF0, 0x83, 0xEC, 0x0C, 0x53]);
+  emitter.add_thunk(Box::new(save));
+  emitter.add_thunk(call_rel32(callback));
+
+  // mov esp, ebx; movdqu xmmN, [esp+N*16]; add esp, 0x60; popad; popfd
+  let mut restore = vec![0x89, 0xDC];
+  restore.extend(movdqu_stack(false, 6));
+  restore.extend([0x83, 0xC4, 0x60, 0x61, 0x9D]);
+  emitter.add_thunk(Box::new(restore));
+  emitter
 }
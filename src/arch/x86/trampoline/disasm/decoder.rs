@@ -0,0 +1,616 @@
+//! A pure Rust x86/x64 instruction decoder.
+//!
+//! Only the layout of an instruction is decoded; its prefixes, opcode, ModR/M
+//! and SIB bytes, displacement and immediate. This determines its length, its
+//! relative branch displacement and RIP relative operand (the operands that
+//! must be adjusted once relocated), and the few classes of instructions the
+//! trampoline treats specially. The bytes are read one at a time, so none are
+//! read past the end of an instruction.
+use super::{Class, Instruction};
+use core::slice;
+
+/// The maximum length of an instruction.
+const MAX_LENGTH: usize = 15;
+
+/// A x86/x64 disassembler.
+///
+/// Each instruction is decoded on its own, therefore it has no state.
+#[cfg_attr(feature = "udis86", allow(dead_code))]
+pub struct Disassembler;
+
+#[cfg_attr(feature = "udis86", allow(dead_code))]
+impl Disassembler {
+  /// Creates a disassembler for the host's architecture.
+  pub fn new(_target: *const ()) -> Disassembler {
+    Disassembler
+  }
+
+  /// Disassembles the instruction at `address`.
+  pub unsafe fn decode(&mut self, address: *const ()) -> Option<Instruction> {
+    let decoded = decode(address as *const u8, cfg!(target_arch = "x86_64"))?;
+    Some(Instruction {
+      address: address as usize,
+      bytes: slice::from_raw_parts(address as *const u8, decoded.length),
+      class: decoded.class,
+      branch: decoded.branch,
+      rip_operand: decoded.rip_operand,
+    })
+  }
+}
+
+/// The properties of a decoded instruction.
+#[derive(Debug, PartialEq, Eq)]
+struct Decoded {
+  length: usize,
+  class: Class,
+  branch: Option<isize>,
+  rip_operand: Option<(isize, usize)>,
+}
+
+/// The layout of an instruction following its opcode.
+#[derive(Default)]
+struct Layout {
+  /// Whether it has a ModR/M byte.
+  modrm: bool,
+  /// The size of its immediate operand(s).
+  immediate: usize,
+  /// The size of its relative branch displacement.
+  branch: usize,
+}
+
+impl Layout {
+  fn modrm(immediate: usize) -> Self {
+    Layout {
+      modrm: true,
+      immediate,
+      branch: 0,
+    }
+  }
+
+  fn immediate(immediate: usize) -> Self {
+    Layout {
+      immediate,
+      ..Layout::default()
+    }
+  }
+
+  fn branch(branch: usize) -> Self {
+    Layout {
+      branch,
+      ..Layout::default()
+    }
+  }
+}
+
+/// The opcode maps.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Map {
+  /// The one-byte opcodes.
+  Primary,
+  /// The two-byte opcodes (`0F xx`).
+  Secondary,
+  /// The three-byte opcodes (`0F 38 xx`).
+  Escape38,
+  /// The three-byte opcodes (`0F 3A xx`).
+  Escape3A,
+  /// The opcodes of VEX, EVEX and XOP encoded instructions, by their map.
+  Extended(u8),
+}
+
+/// The prefixes affecting an instruction's layout.
+struct Prefixes {
+  long_mode: bool,
+  operand_size: bool,
+  address_size: bool,
+  repeat: bool,
+  rex: u8,
+}
+
+impl Prefixes {
+  /// Returns the size of a word or doubleword immediate (`imm16/32`).
+  fn z(&self) -> usize {
+    if self.operand_size && self.rex & 0x08 == 0 {
+      2
+    } else {
+      4
+    }
+  }
+
+  /// Returns the size of a word, doubleword or quadword immediate.
+  fn v(&self) -> usize {
+    if self.rex & 0x08 != 0 {
+      8
+    } else {
+      self.z()
+    }
+  }
+
+  /// Returns the size of a near branch displacement (`rel16/32`).
+  fn rel(&self) -> usize {
+    if self.operand_size && !self.long_mode {
+      2
+    } else {
+      4
+    }
+  }
+
+  /// Returns the size of a memory offset (`moffs`).
+  fn moffs(&self) -> usize {
+    match (self.long_mode, self.address_size) {
+      (true, false) => 8,
+      (true, true) | (false, false) => 4,
+      (false, true) => 2,
+    }
+  }
+}
+
+/// Reads the bytes of an instruction.
+struct Reader {
+  code: *const u8,
+  length: usize,
+}
+
+impl Reader {
+  /// Returns the next byte, without consuming it.
+  unsafe fn peek(&self) -> Option<u8> {
+    (self.length < MAX_LENGTH).then(|| *self.code.add(self.length))
+  }
+
+  /// Consumes the next byte.
+  unsafe fn byte(&mut self) -> Option<u8> {
+    let byte = self.peek()?;
+    self.length += 1;
+    Some(byte)
+  }
+
+  /// Consumes a sign-extended value of `size` bytes.
+  unsafe fn signed(&mut self, size: usize) -> Option<isize> {
+    let mut value = 0u32;
+    for index in 0..size {
+      value |= u32::from(self.byte()?) << (index * 8);
+    }
+
+    let unused = 32 - size as u32 * 8;
+    Some(((value << unused) as i32 >> unused) as isize)
+  }
+
+  /// Skips `size` bytes.
+  fn skip(&mut self, size: usize) -> Option<()> {
+    self.length += size;
+    (self.length <= MAX_LENGTH).then_some(())
+  }
+}
+
+/// Decodes the instruction at `code`, returning `None` if it's invalid.
+unsafe fn decode(code: *const u8, long_mode: bool) -> Option<Decoded> {
+  let mut reader = Reader { code, length: 0 };
+  let mut prefixes = Prefixes {
+    long_mode,
+    operand_size: false,
+    address_size: false,
+    repeat: false,
+    rex: 0,
+  };
+
+  // A REX prefix only applies if it immediately precedes the opcode
+  let opcode = loop {
+    let byte = reader.byte()?;
+    match byte {
+      0x66 => prefixes.operand_size = true,
+      0x67 => prefixes.address_size = true,
+      0xF3 => prefixes.repeat = true,
+      0x26 | 0x2E | 0x36 | 0x3E | 0x64 | 0x65 | 0xF0 | 0xF2 => (),
+      0x40..=0x4F if long_mode => {
+        prefixes.rex = byte;
+        continue;
+      },
+      _ => break byte,
+    }
+    prefixes.rex = 0;
+  };
+
+  // Outside of long mode, VEX, EVEX and XOP prefixes reuse the opcodes of
+  // instructions with a memory operand, and are followed by `11` instead
+  let is_extended = |prefix: u8| match prefix {
+    0xC4 | 0xC5 | 0x62 => long_mode || reader.peek().is_some_and(|next| next >= 0xC0),
+    0x8F => reader.peek().is_some_and(|next| next & 0x1F >= 8),
+    _ => false,
+  };
+
+  let (map, opcode) = match opcode {
+    0x0F => match reader.byte()? {
+      0x38 => (Map::Escape38, reader.byte()?),
+      0x3A => (Map::Escape3A, reader.byte()?),
+      opcode => (Map::Secondary, opcode),
+    },
+    0xC5 if is_extended(0xC5) => {
+      reader.skip(1)?;
+      (Map::Extended(1), reader.byte()?)
+    },
+    0xC4 | 0x8F if is_extended(opcode) => {
+      let map = reader.byte()? & 0x1F;
+      reader.skip(1)?;
+      (Map::Extended(map), reader.byte()?)
+    },
+    0x62 if is_extended(0x62) => {
+      let map = reader.byte()? & 0x07;
+      reader.skip(2)?;
+      (Map::Extended(map), reader.byte()?)
+    },
+    opcode => (Map::Primary, opcode),
+  };
+
+  let mut layout = match map {
+    Map::Primary => primary(opcode, &prefixes)?,
+    Map::Secondary => secondary(opcode, &prefixes)?,
+    Map::Escape38 => Layout::modrm(0),
+    Map::Escape3A => Layout::modrm(1),
+    Map::Extended(map) => extended(map, opcode)?,
+  };
+
+  let mut class = match (map, opcode) {
+    (Map::Primary, 0x90) if !prefixes.repeat && prefixes.rex & 0x01 == 0 => Class::Nop,
+    (Map::Primary, 0xC2 | 0xC3) => Class::Return,
+    (Map::Primary, 0xE0..=0xE3) => Class::Loop,
+    (Map::Primary, 0x9A | 0xE8) => Class::Call,
+    (Map::Primary, 0xE9..=0xEB) => Class::Jump,
+    (Map::Secondary, 0x1F) => Class::Nop,
+    _ => Class::Other,
+  };
+
+  let mut rip_operand = None;
+  if layout.modrm {
+    let modrm = reader.byte()?;
+    let (mode, reg, rm) = (modrm >> 6, (modrm >> 3) & 0x07, modrm & 0x07);
+
+    // Control and debug register moves ignore the mode (always registers)
+    let mode = match (map, opcode) {
+      (Map::Secondary, 0x20..=0x23) => 0b11,
+      _ => mode,
+    };
+
+    // Groups with instructions distinguished by the `reg` field
+    match (map, opcode, reg) {
+      (Map::Primary, 0xF6, 0 | 1) => layout.immediate = 1,
+      (Map::Primary, 0xF7, 0 | 1) => layout.immediate = prefixes.z(),
+      (Map::Primary, 0xFF, 2 | 3) => class = Class::Call,
+      (Map::Primary, 0xFF, 4 | 5) => class = Class::Jump,
+      _ => (),
+    }
+
+    if mode != 0b11 {
+      if !long_mode && prefixes.address_size {
+        // 16-bit addressing has no SIB byte
+        reader.skip(match (mode, rm) {
+          (0b00, 0b110) | (0b10, _) => 2,
+          (0b00, _) => 0,
+          _ => 1,
+        })?;
+      } else {
+        let base = if rm == 0b100 {
+          reader.byte()? & 0x07
+        } else {
+          rm
+        };
+        match (mode, rm, base) {
+          // With an address size prefix, the operand is relative to EIP
+          (0b00, 0b101, _) if long_mode => {
+            let offset = reader.length;
+            rip_operand = Some((reader.signed(4)?, offset));
+          },
+          (0b00, _, 0b101) | (0b10, ..) => reader.skip(4)?,
+          (0b00, ..) => (),
+          _ => reader.skip(1)?,
+        }
+      }
+    }
+  }
+
+  reader.skip(layout.immediate)?;
+  let branch = match layout.branch {
+    0 => None,
+    size => Some(reader.signed(size)?),
+  };
+
+  Some(Decoded {
+    length: reader.length,
+    class,
+    branch,
+    rip_operand,
+  })
+}
+
+/// Returns the layout of a one-byte opcode.
+fn primary(opcode: u8, prefixes: &Prefixes) -> Option<Layout> {
+  let long_mode = prefixes.long_mode;
+  let layout = match opcode {
+    // Arithmetic (e.g `add`, `cmp`)
+    0x00..=0x3F => match opcode & 0x07 {
+      0..=3 => Layout::modrm(0),
+      4 => Layout::immediate(1),
+      5 => Layout::immediate(prefixes.z()),
+      // Segment registers and decimal adjustments
+      _ if long_mode => return None,
+      _ => Layout::default(),
+    },
+    0x40..=0x5F | 0x6C..=0x6F | 0x90..=0x99 | 0x9B..=0x9F => Layout::default(),
+    0xA4..=0xA7 | 0xAA..=0xAF | 0xC3 | 0xC9 | 0xCB | 0xCC | 0xCF | 0xD7 => Layout::default(),
+    0xEC..=0xEF | 0xF1 | 0xF4 | 0xF5 | 0xF8..=0xFD => Layout::default(),
+    0x60 | 0x61 | 0xCE | 0xD6 if !long_mode => Layout::default(),
+    0x62 | 0xC4 | 0xC5 if !long_mode => Layout::modrm(0),
+    0x63 | 0x84..=0x8F | 0xD0..=0xD3 | 0xD8..=0xDF | 0xFE | 0xFF => Layout::modrm(0),
+    0xF6 | 0xF7 => Layout::modrm(0),
+    0x68 | 0xA9 => Layout::immediate(prefixes.z()),
+    0x69 | 0x81 | 0xC7 => Layout::modrm(prefixes.z()),
+    0x6A | 0xA8 | 0xB0..=0xB7 | 0xCD | 0xE4..=0xE7 => Layout::immediate(1),
+    0x6B | 0x80 | 0x83 | 0xC0 | 0xC1 | 0xC6 => Layout::modrm(1),
+    0x82 if !long_mode => Layout::modrm(1),
+    0xD4 | 0xD5 if !long_mode => Layout::immediate(1),
+    0xA0..=0xA3 => Layout::immediate(prefixes.moffs()),
+    0xB8..=0xBF => Layout::immediate(prefixes.v()),
+    0xC2 | 0xCA => Layout::immediate(2),
+    0xC8 => Layout::immediate(3),
+    0x9A | 0xEA if !long_mode => Layout::immediate(prefixes.z() + 2),
+    0x70..=0x7F | 0xE0..=0xE3 | 0xEB => Layout::branch(1),
+    0xE8 | 0xE9 => Layout::branch(prefixes.rel()),
+    _ => return None,
+  };
+  Some(layout)
+}
+
+/// Returns the layout of a two-byte opcode (`0F xx`).
+fn secondary(opcode: u8, prefixes: &Prefixes) -> Option<Layout> {
+  let layout = match opcode {
+    0x00..=0x03 | 0x0D | 0x10..=0x23 | 0x28..=0x2F | 0x40..=0x6F => Layout::modrm(0),
+    0x74..=0x76 | 0x78 | 0x79 | 0x7C..=0x7F | 0x90..=0x9F | 0xA3 | 0xA5 => Layout::modrm(0),
+    0xAB | 0xAD..=0xB9 | 0xBB..=0xC1 | 0xC3 | 0xC7 | 0xD0..=0xFF => Layout::modrm(0),
+    // The 3DNow! opcode is encoded as an immediate
+    0x0F | 0x70..=0x73 | 0xA4 | 0xAC | 0xBA | 0xC2 | 0xC4..=0xC6 => Layout::modrm(1),
+    0x05..=0x09 | 0x0B | 0x0E | 0x30..=0x35 | 0x37 | 0x77 | 0xA0..=0xA2 => Layout::default(),
+    0xA8..=0xAA | 0xC8..=0xCF => Layout::default(),
+    0x80..=0x8F => Layout::branch(prefixes.rel()),
+    _ => return None,
+  };
+  Some(layout)
+}
+
+/// Returns the layout of a VEX, EVEX or XOP encoded opcode, within its map.
+fn extended(map: u8, opcode: u8) -> Option<Layout> {
+  let layout = match (map, opcode) {
+    // `vzeroupper` and `vzeroall`
+    (1, 0x77) => Layout::default(),
+    (1, 0x70..=0x73 | 0xC2 | 0xC4..=0xC6) | (3, _) | (8, _) => Layout::modrm(1),
+    (1 | 2 | 5 | 6 | 9, _) => Layout::modrm(0),
+    (10, _) => Layout::modrm(4),
+    _ => return None,
+  };
+  Some(layout)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Decodes an instruction, padded so it's never read beyond its bytes.
+  fn decode_bytes(bytes: &[u8], long_mode: bool) -> Option<Decoded> {
+    let mut code = [0xCC; 32];
+    code[..bytes.len()].copy_from_slice(bytes);
+    unsafe { decode(code.as_ptr(), long_mode) }
+  }
+
+  fn assert_decodes(bytes: &[u8], long_mode: bool, class: Class, branch: Option<isize>) {
+    let decoded = decode_bytes(bytes, long_mode).expect("decoding instruction");
+    assert_eq!(decoded.length, bytes.len(), "{:02x?}", bytes);
+    assert_eq!(
+      (decoded.class, decoded.branch),
+      (class, branch),
+      "{:02x?}",
+      bytes
+    );
+  }
+
+  #[test]
+  fn decodes_lengths() {
+    let common: &[&[u8]] = &[
+      &[0x55],                                           // push rbp
+      &[0x89, 0xE5],                                     // mov ebp, esp
+      &[0x8B, 0x44, 0x24, 0x08],                         // mov eax, [esp+8]
+      &[0x8B, 0x84, 0x24, 0x00, 0x01, 0x00, 0x00],       // mov eax, [esp+0x100]
+      &[0x8B, 0x04, 0x25, 0x00, 0x10, 0x00, 0x00],       // mov eax, [0x1000]
+      &[0x8B, 0x45, 0x00],                               // mov eax, [ebp]
+      &[0x83, 0xEC, 0x20],                               // sub esp, 0x20
+      &[0x81, 0xEC, 0x00, 0x01, 0x00, 0x00],             // sub esp, 0x100
+      &[0x66, 0x81, 0xC1, 0x00, 0x01],                   // add cx, 0x100
+      &[0x66, 0xB8, 0x34, 0x12],                         // mov ax, 0x1234
+      &[0xC7, 0x44, 0x24, 0x04, 0x01, 0x00, 0x00, 0x00], // mov dword [esp+4], 1
+      &[0xF6, 0xC1, 0x01],                               // test cl, 1
+      &[0xF7, 0xC1, 0x01, 0x00, 0x00, 0x00],             // test ecx, 1
+      &[0xF7, 0xD0],                                     // not eax
+      &[0xC8, 0x10, 0x00, 0x00],                         // enter 0x10, 0
+      &[0x0F, 0xB6, 0xC0],                               // movzx eax, al
+      &[0x0F, 0xBA, 0xE0, 0x03],                         // bt eax, 3
+      &[0x0F, 0x20, 0xC0],                               // mov eax, cr0
+      &[0x0F, 0x01, 0xD0],                               // xgetbv
+      &[0x0F, 0xA2],                                     // cpuid
+      &[0x66, 0x0F, 0x3A, 0x0F, 0xC1, 0x08],             // palignr xmm0, xmm1, 8
+      &[0x66, 0x0F, 0x38, 0x00, 0xC1],                   // pshufb xmm0, xmm1
+      &[0xF3, 0x0F, 0x1E, 0xFB],                         // endbr32
+      &[0xF3, 0xA4],                                     // rep movsb
+      &[0xC5, 0xF8, 0x77],                               // vzeroupper
+      &[0xC5, 0xF9, 0x70, 0xC1, 0x1B],                   // vpshufd xmm0, xmm1, 0x1b
+      &[0xC4, 0xE3, 0x79, 0x0F, 0xC1, 0x08],             // vpalignr xmm0, xmm0, xmm1, 8
+      &[0x62, 0xF1, 0x7C, 0x48, 0x28, 0xC1],             // vmovaps zmm0, zmm1
+      &[0xD9, 0xEE],                                     // fldz
+      &[0xDD, 0x44, 0x24, 0x08],                         // fld qword [esp+8]
+      &[0x8F, 0xC0],                                     // pop eax
+      &[0xCC],                                           // int3
+    ];
+
+    for long_mode in [false, true] {
+      for bytes in common {
+        assert_decodes(bytes, long_mode, Class::Other, None);
+      }
+    }
+
+    let long: &[&[u8]] = &[
+      &[0x48, 0x89, 0xE5],                                           // mov rbp, rsp
+      &[0x48, 0xB8, 0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11], // mov rax, imm64
+      &[0x66, 0x48, 0xB8, 0x01, 0, 0, 0, 0, 0, 0, 0],                // mov rax, imm64
+      &[0x48, 0xA1, 0, 0, 0, 0, 0, 0, 0, 0],                         // mov rax, [moffs64]
+      &[0x48, 0x81, 0xEC, 0x00, 0x01, 0x00, 0x00],                   // sub rsp, 0x100
+      &[0x41, 0x8B, 0x45, 0x08],                                     // mov eax, [r13+8]
+      &[0x41, 0x90],                                                 // xchg r8d, eax
+      &[0xF3, 0x90],                                                 // pause
+      &[0xF3, 0x0F, 0x1E, 0xFA],                                     // endbr64
+      &[0x0F, 0x05],                                                 // syscall
+      &[0x8F, 0xE8, 0x78, 0xC2, 0xC1, 0x00],                         // vprotb xmm0, xmm1, 0
+    ];
+
+    for bytes in long {
+      assert_decodes(bytes, true, Class::Other, None);
+    }
+
+    let legacy: &[&[u8]] = &[
+      &[0x40],                                     // inc eax
+      &[0x60],                                     // pushad
+      &[0xA1, 0x00, 0x10, 0x00, 0x00],             // mov eax, [moffs32]
+      &[0x67, 0x8B, 0x46, 0x08],                   // mov eax, [bp+8]
+      &[0x67, 0x8B, 0x06, 0x00, 0x10],             // mov eax, [0x1000]
+      &[0x8B, 0x05, 0x00, 0x10, 0x00, 0x00],       // mov eax, [0x1000]
+      &[0xC4, 0x06],                               // les eax, [esi]
+      &[0x62, 0x06],                               // bound eax, [esi]
+      &[0x9A, 0x00, 0x00, 0x00, 0x00, 0x08, 0x00], // call far 0x8:0
+    ];
+
+    for bytes in legacy.iter().filter(|bytes| bytes[0] != 0x9A) {
+      assert_decodes(bytes, false, Class::Other, None);
+    }
+    assert_decodes(legacy[8], false, Class::Call, None);
+  }
+
+  #[test]
+  fn decodes_classes() {
+    for long_mode in [false, true] {
+      assert_decodes(&[0x90], long_mode, Class::Nop, None);
+      assert_decodes(&[0x66, 0x90], long_mode, Class::Nop, None);
+      assert_decodes(&[0x0F, 0x1F, 0x00], long_mode, Class::Nop, None);
+      assert_decodes(&[0x0F, 0x1F, 0x44, 0x00, 0x00], long_mode, Class::Nop, None);
+      assert_decodes(
+        &[0x66, 0x0F, 0x1F, 0x84, 0, 0, 0, 0, 0],
+        long_mode,
+        Class::Nop,
+        None,
+      );
+      assert_decodes(&[0xC3], long_mode, Class::Return, None);
+      assert_decodes(&[0xC2, 0x08, 0x00], long_mode, Class::Return, None);
+      assert_decodes(&[0xFF, 0xD0], long_mode, Class::Call, None);
+      assert_decodes(&[0xFF, 0xE0], long_mode, Class::Jump, None);
+      assert_decodes(&[0xFF, 0x64, 0x24, 0x08], long_mode, Class::Jump, None);
+    }
+  }
+
+  #[test]
+  fn decodes_branches() {
+    for long_mode in [false, true] {
+      assert_decodes(&[0xEB, 0xFE], long_mode, Class::Jump, Some(-2));
+      assert_decodes(&[0x74, 0x10], long_mode, Class::Other, Some(0x10));
+      assert_decodes(&[0xE2, 0x08], long_mode, Class::Loop, Some(8));
+      assert_decodes(&[0xE3, 0xF0], long_mode, Class::Loop, Some(-0x10));
+      assert_decodes(
+        &[0xE8, 0xF0, 0xFF, 0xFF, 0xFF],
+        long_mode,
+        Class::Call,
+        Some(-0x10),
+      );
+      assert_decodes(
+        &[0xE9, 0x00, 0x01, 0x00, 0x00],
+        long_mode,
+        Class::Jump,
+        Some(0x100),
+      );
+      assert_decodes(
+        &[0x0F, 0x84, 0x00, 0x01, 0x00, 0x00],
+        long_mode,
+        Class::Other,
+        Some(0x100),
+      );
+      assert_decodes(
+        &[0xF2, 0xE9, 0x10, 0x00, 0x00, 0x00],
+        long_mode,
+        Class::Jump,
+        Some(0x10),
+      );
+    }
+
+    // The operand size prefix only shortens branches outside of long mode
+    assert_decodes(&[0x66, 0xE8, 0x00, 0x80], false, Class::Call, Some(-0x8000));
+    assert_decodes(&[0x66, 0xE8, 0, 0, 0, 0], true, Class::Call, Some(0));
+    assert_decodes(
+      &[0x66, 0x0F, 0x84, 0x00, 0x80],
+      false,
+      Class::Other,
+      Some(-0x8000),
+    );
+    assert_decodes(&[0x66, 0x0F, 0x84, 0, 0, 0, 0], true, Class::Other, Some(0));
+  }
+
+  #[test]
+  fn decodes_rip_operands() {
+    let cases: &[(&[u8], isize, usize)] = &[
+      (&[0x48, 0x8B, 0x05, 0x10, 0x00, 0x00, 0x00], 0x10, 3), // mov rax, [rip+0x10]
+      (&[0x8D, 0x0D, 0xF0, 0xFF, 0xFF, 0xFF], -0x10, 2),      // lea ecx, [rip-0x10]
+      (&[0x83, 0x3D, 0x10, 0, 0, 0, 0x05], 0x10, 2),          // cmp dword [rip+0x10], 5
+      (&[0xF7, 0x05, 0x10, 0, 0, 0, 1, 0, 0, 0], 0x10, 2),    // test dword [rip+0x10], 1
+      (&[0x41, 0x8B, 0x05, 0x10, 0x00, 0x00, 0x00], 0x10, 3), // mov eax, [rip+0x10]
+      (&[0xFF, 0x25, 0x00, 0x00, 0x00, 0x00], 0, 2),          // jmp [rip]
+      (&[0x62, 0xF1, 0x7C, 0x48, 0x10, 0x05, 0, 0, 0, 0], 0, 6), // vmovups zmm0, [rip]
+    ];
+
+    for &(bytes, displacement, offset) in cases {
+      let decoded = decode_bytes(bytes, true).unwrap();
+      assert_eq!(decoded.length, bytes.len(), "{:02x?}", bytes);
+      assert_eq!(
+        decoded.rip_operand,
+        Some((displacement, offset)),
+        "{:02x?}",
+        bytes
+      );
+
+      // Outside of long mode, the operand is absolute
+      let decoded = decode_bytes(bytes, false);
+      if bytes[0] & 0xF0 != 0x40 && bytes[0] != 0x62 {
+        assert_eq!(decoded.unwrap().rip_operand, None, "{:02x?}", bytes);
+      }
+    }
+
+    // An address size prefix makes the operand relative to EIP
+    let decoded = decode_bytes(&[0x67, 0x8B, 0x05, 0x10, 0, 0, 0], true).unwrap();
+    assert_eq!(decoded.rip_operand, Some((0x10, 3)));
+
+    let decoded = decode_bytes(&[0xFF, 0x25, 0, 0, 0, 0], true).unwrap();
+    assert_eq!(decoded.class, Class::Jump);
+  }
+
+  #[test]
+  fn rejects_invalid_instructions() {
+    // Opcodes removed from long mode
+    assert_eq!(decode_bytes(&[0x06], true), None);
+    assert_eq!(decode_bytes(&[0x60], true), None);
+    assert_eq!(decode_bytes(&[0xEA, 0, 0, 0, 0, 0, 0], true), None);
+    assert_eq!(decode_bytes(&[0x0F, 0x04], true), None);
+
+    // Instructions exceeding the maximum length
+    let mut prefixed = [0x66; MAX_LENGTH];
+    prefixed[MAX_LENGTH - 1] = 0x90;
+    assert!(decode_bytes(&prefixed, true).is_some());
+    assert_eq!(
+      decode_bytes(&[&[0x66][..], &prefixed[..]].concat(), true),
+      None
+    );
+
+    let mut immediate = [0x2E; MAX_LENGTH + 1];
+    immediate[MAX_LENGTH - 4..].copy_from_slice(&[0xB8, 0, 0, 0, 0]);
+    assert!(decode_bytes(&immediate[1..], false).is_some());
+    assert_eq!(decode_bytes(&immediate, false), None);
+  }
+}
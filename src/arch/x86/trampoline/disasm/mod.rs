@@ -0,0 +1,152 @@
+//! The underlying disassembler should be opaque to the outside.
+//!
+//! Instructions are decoded by a pure Rust [decoder](./decoder/index.html),
+//! unless the `udis86` feature selects the C library instead.
+use cfg_if::cfg_if;
+
+mod decoder;
+#[cfg(any(feature = "udis86", feature = "disassembly"))]
+mod udis;
+
+cfg_if! {
+  if #[cfg(feature = "udis86")] {
+    pub use self::udis::Disassembler;
+  } else {
+    pub use self::decoder::Disassembler;
+  }
+}
+
+#[cfg(feature = "disassembly")]
+pub use self::udis::listing;
+
+/// The classes of instructions that are relocated specially.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Class {
+  Call,
+  Jump,
+  Loop,
+  Nop,
+  Return,
+  Other,
+}
+
+/// Safe wrapper around an instruction.
+pub struct Instruction {
+  address: usize,
+  bytes: &'static [u8],
+  class: Class,
+  /// The relative branch displacement.
+  branch: Option<isize>,
+  /// The RIP relative operand's displacement, and its offset.
+  rip_operand: Option<(isize, usize)>,
+}
+
+impl Instruction {
+  /// Disassembles a new instruction at the specified address.
+  pub unsafe fn new(disasm: &mut Disassembler, address: *const ()) -> Option<Self> {
+    disasm.decode(address)
+  }
+
+  /// Returns the instruction's address.
+  pub fn address(&self) -> usize {
+    self.address
+  }
+
+  /// Returns the next instruction's address.
+  pub fn next_instruction_address(&self) -> usize {
+    self.address().wrapping_add(self.len())
+  }
+
+  /// Returns the instructions relative branch offset, if applicable.
+  pub fn relative_branch_displacement(&self) -> Option<isize> {
+    self.branch
+  }
+
+  /// Returns the instructions RIP operand displacement if applicable.
+  pub fn rip_operand_displacement(&self) -> Option<isize> {
+    // The operands displacement (e.g `mov eax, [rip+0x10]` ⟶ 0x10)
+    self.rip_operand.map(|(displacement, _)| displacement)
+  }
+
+  /// Returns the offset of the RIP operand's displacement within the
+  /// instruction, if applicable (it's followed by any immediate operand).
+  pub fn rip_operand_offset(&self) -> Option<usize> {
+    self.rip_operand.map(|(_, offset)| offset)
+  }
+
+  /// Returns true if this instruction any type of a loop.
+  pub fn is_loop(&self) -> bool {
+    self.class == Class::Loop
+  }
+
+  /// Returns true if this instruction is an unconditional jump.
+  pub fn is_unconditional_jump(&self) -> bool {
+    self.class == Class::Jump
+  }
+
+  /// Returns true if this instruction is a function call.
+  pub fn is_call(&self) -> bool {
+    self.class == Class::Call
+  }
+
+  /// Returns true if this instruction is a NOP (e.g `nop` or `xchg ax, ax`,
+  /// including multi-byte forms).
+  pub fn is_nop(&self) -> bool {
+    self.class == Class::Nop
+  }
+
+  /// Returns true if this instruction is a return.
+  pub fn is_return(&self) -> bool {
+    self.class == Class::Return
+  }
+
+  /// Returns the instruction's bytes.
+  pub unsafe fn as_slice(&self) -> &[u8] {
+    self.bytes
+  }
+
+  /// Returns the size of the instruction in bytes.
+  pub fn len(&self) -> usize {
+    self.bytes.len()
+  }
+}
+
+#[cfg(all(test, feature = "udis86"))]
+mod tests {
+  use super::*;
+
+  /// Verifies that the decoder agrees with `udis86`, over compiled code.
+  #[test]
+  fn decoder_matches_udis() {
+    let functions = [
+      core::str::from_utf8 as *const (),
+      core::fmt::write as *const (),
+      <f64 as core::str::FromStr>::from_str as *const (),
+      <[u32]>::sort_unstable as *const (),
+      std::collections::HashMap::<u64, u64>::insert as *const (),
+      crate::RawDetour::new as *const (),
+    ];
+
+    for function in functions {
+      let mut decoder = decoder::Disassembler::new(function);
+      let mut udis = udis::Disassembler::new(function);
+      let mut address = function as usize;
+
+      for _ in 0..512 {
+        let expected = unsafe { udis.decode(address as *const ()) }.unwrap();
+        let decoded = unsafe { decoder.decode(address as *const ()) }.unwrap();
+        let describe = |instruction: &Instruction| {
+          (
+            unsafe { instruction.as_slice() }.to_vec(),
+            instruction.class,
+            instruction.branch,
+            instruction.rip_operand,
+          )
+        };
+
+        assert_eq!(describe(&decoded), describe(&expected), "{:#x}", address);
+        address += decoded.len();
+      }
+    }
+  }
+}
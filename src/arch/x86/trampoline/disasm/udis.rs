@@ -0,0 +1,127 @@
+//! Decodes instructions using the `udis86` C library.
+use super::{Class, Instruction};
+use core::slice;
+
+/// A x86/x64 disassembler.
+pub struct Disassembler(udis::ud);
+
+#[cfg_attr(not(feature = "udis86"), allow(dead_code))]
+impl Disassembler {
+  /// Creates a default x86 disassembler.
+  pub fn new(target: *const ()) -> Disassembler {
+    unsafe {
+      let mut ud = ::core::mem::zeroed();
+      udis::ud_init(&mut ud);
+      udis::ud_set_user_opaque_data(&mut ud, target as *mut _);
+      udis::ud_set_input_hook(&mut ud, Some(Self::udis_read_address));
+      udis::ud_set_mode(&mut ud, (::core::mem::size_of::<usize>() * 8) as u8);
+      Disassembler(ud)
+    }
+  }
+
+  /// Reads one byte from a pointer and advances it.
+  unsafe extern "C" fn udis_read_address(ud: *mut udis::ud) -> libc::c_int {
+    let pointer = udis::ud_get_user_opaque_data(ud) as *mut u8;
+    let result = *pointer;
+    udis::ud_set_user_opaque_data(ud, pointer.offset(1) as *mut _);
+    libc::c_int::from(result)
+  }
+
+  /// Disassembles the next instruction, located at `address`.
+  pub unsafe fn decode(&mut self, address: *const ()) -> Option<Instruction> {
+    let instruction_bytes = udis::ud_disassemble(&mut self.0) as usize;
+    if instruction_bytes == 0 {
+      return None;
+    }
+
+    let operands = &self.0.operand;
+    let branch = operands
+      .iter()
+      .find(|op| op.otype == udis::ud_type::UD_OP_JIMM)
+      .map(|op| match op.size {
+        8 => op.lval.sbyte as isize,
+        16 => op.lval.sword as isize,
+        32 => op.lval.sdword as isize,
+        _ => unreachable!("Operand size: {}", op.size),
+      });
+
+    // RIP relative operands have no SIB byte, so the displacement follows
+    let rip_operand = operands
+      .iter()
+      .find(|op| op.otype == udis::ud_type::UD_OP_MEM && op.base == udis::ud_type::UD_R_RIP)
+      .map(|op| (op.lval.sdword as isize, self.0.modrm_offset as usize + 1));
+
+    Some(Instruction {
+      address: address as usize,
+      bytes: slice::from_raw_parts(address as *const _, instruction_bytes),
+      class: match udis::ud_insn_mnemonic(&self.0) {
+        udis::ud_mnemonic_code::UD_Icall => Class::Call,
+        udis::ud_mnemonic_code::UD_Ijmp => Class::Jump,
+        udis::ud_mnemonic_code::UD_Iloop
+        | udis::ud_mnemonic_code::UD_Iloope
+        | udis::ud_mnemonic_code::UD_Iloopne
+        | udis::ud_mnemonic_code::UD_Ijecxz
+        | udis::ud_mnemonic_code::UD_Ijcxz => Class::Loop,
+        udis::ud_mnemonic_code::UD_Inop => Class::Nop,
+        udis::ud_mnemonic_code::UD_Iret => Class::Return,
+        _ => Class::Other,
+      },
+      branch,
+      rip_operand,
+    })
+  }
+}
+
+/// Returns a listing of the first instructions at `target`, with the
+/// instruction at `marked` highlighted.
+#[cfg(feature = "disassembly")]
+pub unsafe fn listing(target: *const (), marked: usize) -> alloc::string::String {
+  use alloc::format;
+  use alloc::vec::Vec;
+  use core::ffi::CStr;
+
+  /// The amount of bytes listed, excluding the last instruction's remainder.
+  const LENGTH: usize = 32;
+  /// The maximum length of an x86 instruction.
+  const MAX_INSTRUCTION_LENGTH: usize = 15;
+
+  // Avoid reading beyond the target's region
+  let size = crate::os::backend()
+    .and_then(|backend| backend.query(target))
+    .ok()
+    .flatten()
+    .map_or(LENGTH, |region| region.upper() - target as usize)
+    .min(LENGTH + MAX_INSTRUCTION_LENGTH);
+
+  let mut ud = ::core::mem::zeroed();
+  udis::ud_init(&mut ud);
+  udis::ud_set_mode(&mut ud, (::core::mem::size_of::<usize>() * 8) as u8);
+  udis::ud_set_pc(&mut ud, target as u64);
+  udis::ud_set_syntax(&mut ud, Some(udis::ud_translate_intel));
+  udis::ud_set_input_buffer(&mut ud, target as *const u8, size);
+
+  let mut lines = Vec::new();
+  let mut offset = 0;
+
+  while offset < LENGTH && udis::ud_disassemble(&mut ud) > 0 {
+    let address = target as usize + offset;
+    let length = udis::ud_insn_len(&ud) as usize;
+    let bytes = slice::from_raw_parts(address as *const u8, length)
+      .iter()
+      .map(|byte| format!("{:02x}", byte))
+      .collect::<Vec<_>>()
+      .join(" ");
+    let assembly = CStr::from_ptr(udis::ud_insn_asm(&ud))
+      .to_str()
+      .unwrap_or("?");
+    let marker = if address == marked { "=>" } else { "  " };
+
+    lines.push(format!(
+      "{} {:#x}  {:<30} {}",
+      marker, address, bytes, assembly
+    ));
+    offset += length;
+  }
+
+  lines.join("\n")
+}
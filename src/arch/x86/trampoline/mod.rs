@@ -1,32 +1,406 @@
 use self::disasm::*;
 use crate::arch::x86::thunk;
+use crate::arch::{self, memory};
 use crate::error::{Error, Result};
-use crate::pic;
-use std::mem;
+use crate::profiling::CodeKind;
+use crate::{pic, pool};
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::slice;
 
 mod disasm;
+#[cfg(target_arch = "x86_64")]
+mod syscall;
 
-/// A trampoline generator (x86/x64).
+/// A relocated copy of a function's prolog (x86/x64).
+///
+/// A trampoline consists of the whole instructions covering at least `margin`
+/// bytes of a target, relocated to executable memory close to it, followed by
+/// a jump to the first instruction after them. Calling the trampoline is
+/// equivalent to calling the target, even whilst the target's prolog has been
+/// overwritten. It starts with an `endbr` instruction, so it can be called
+/// indirectly when Indirect Branch Tracking is enforced.
+///
+/// This is the building block used by [RawDetour](./struct.RawDetour.html) to
+/// call the original function, and is exposed for setups where the target is
+/// patched by other means. The trampoline's memory is released back to the
+/// [pool](./pool/index.html) when it's dropped.
+///
+/// On x64, system call stubs (e.g `ntdll!NtClose`) are relocated as a whole;
+/// the `jne` to the `int 2e` path is widened, and the trampoline executes the
+/// `syscall` itself. See [syscall_number](#method.syscall_number).
+///
+/// A function starting with an unconditional jump (e.g the entry of a function
+/// split by PGO or BOLT, jumping to its hot part) is relocated as that jump
+/// alone, adjusted to reach the same destination, since nothing after it is
+/// executed. The jump is relocated, not followed: the entry remains the
+/// target, hence code jumping to the destination directly is not detoured.
+///
+/// # Example
+///
+/// ```rust
+/// # #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+/// # fn main() -> detour::Result<()> {
+/// use detour::Trampoline;
+/// use std::mem;
+///
+/// /// A target with a five byte prolog (`mov eax, 5`).
+/// #[unsafe(naked)]
+/// extern "C" fn target() -> i32 {
+///   core::arch::naked_asm!("mov eax, 5", "ret")
+/// }
+///
+/// let trampoline = unsafe { Trampoline::new(target as *const (), 5)? };
+/// assert_eq!(trampoline.prolog_size(), 5);
+/// assert!(trampoline.size() > trampoline.prolog_size());
+///
+/// let original: extern "C" fn() -> i32 = unsafe { mem::transmute(trampoline.address()) };
+/// assert_eq!(original(), 5);
+/// # Ok(())
+/// # }
+/// # #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+/// # fn main() {}
+/// ```
 pub struct Trampoline {
-  emitter: pic::CodeEmitter,
+  #[allow(dead_code)]
+  memory: Option<pool::ExecutableMemory>,
+  address: *const (),
+  relocations: Vec<RelocationRecord>,
+  size: usize,
   prolog_size: usize,
+  syscall_number: Option<u32>,
+}
+
+/// A record of an instruction relocated to a trampoline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RelocationRecord {
+  /// Address of the original instruction.
+  pub original_address: *const (),
+  /// Size of the original instruction.
+  pub original_size: usize,
+  /// Offset of the emitted code within the trampoline.
+  pub offset: usize,
+  /// Size of the emitted code.
+  pub size: usize,
+  /// Whether the instruction was rewritten (e.g a RIP relative operand was
+  /// adjusted, or a branch was widened), instead of copied as is. NOPs at the
+  /// start of the target are omitted, i.e rewritten as no code at all.
+  pub rewritten: bool,
 }
 
 impl Trampoline {
   /// Constructs a new trampoline for an address.
+  ///
+  /// # Arguments
+  ///
+  /// * `target` - An address of the function to relocate.
+  /// * `margin` - The minimum amount of bytes to relocate (e.g the size of a
+  ///   patch). Margins larger than five bytes may lead to undefined behavior.
+  ///
+  /// Returns `Error::NoPatchArea` if the relocated instructions would exceed
+  /// the configured [max_prolog_size](./meta/fn.max_prolog_size.html).
+  ///
+  /// The trampoline is only valid whilst the instructions after its prolog
+  /// remain intact.
   pub unsafe fn new(target: *const (), margin: usize) -> Result<Trampoline> {
-    Builder::new(target, margin).build()
+    if target.is_null() {
+      Err(Error::NullPointer)?;
+    }
+
+    if !crate::memory::is_executable(target)? {
+      Err(Error::NotExecutable)?;
+    }
+
+    let _guard = memory::LOCK.lock();
+    Self::new_locked(target, margin, pic::CodeEmitter::new())
+  }
+
+  /// Constructs a new trampoline for an address, whilst holding the lock.
+  ///
+  /// The prologue is executed before the relocated instructions.
+  pub(crate) unsafe fn new_locked(
+    target: *const (),
+    margin: usize,
+    prologue: pic::CodeEmitter,
+  ) -> Result<Trampoline> {
+    Self::new_within_locked(target, margin, prologue, crate::meta::detour_range())
   }
 
-  /// Returns a reference to the trampoline's code emitter.
-  pub fn emitter(&self) -> &pic::CodeEmitter {
-    &self.emitter
+  /// Constructs a new trampoline within `range` bytes of an address, whilst
+  /// holding the lock.
+  ///
+  /// Trampolines with RIP relative operands are always allocated within range
+  /// of the operands' destinations.
+  pub(crate) unsafe fn new_within_locked(
+    target: *const (),
+    margin: usize,
+    prologue: pic::CodeEmitter,
+    range: usize,
+  ) -> Result<Trampoline> {
+    PendingTrampoline::new(target, margin, prologue, range)?.emit_locked(range)
   }
 
-  /// Returns the size of the prolog (i.e the amount of disassembled bytes).
+  /// Constructs a trampoline residing within a hot-patchable target, i.e
+  /// after the NOPs at its entry.
+  ///
+  /// Nothing is relocated, nor allocated.
+  pub(crate) fn in_place(target: *const (), entry_size: usize) -> Trampoline {
+    Trampoline {
+      memory: None,
+      address: (target as usize + entry_size) as *const (),
+      relocations: Vec::new(),
+      size: 0,
+      prolog_size: entry_size,
+      syscall_number: None,
+    }
+  }
+
+  /// Creates a trampoline emitted by another instance of the library, whose
+  /// memory is not owned by the pool.
+  #[cfg_attr(not(feature = "std"), allow(dead_code))]
+  pub(crate) fn foreign(address: *const (), prolog_size: usize) -> Trampoline {
+    Trampoline {
+      memory: None,
+      address,
+      relocations: Vec::new(),
+      size: 0,
+      prolog_size,
+      syscall_number: None,
+    }
+  }
+
+  /// Returns the address of the trampoline.
+  pub fn address(&self) -> *const () {
+    self.address
+  }
+
+  /// Returns the size of the trampoline's code.
+  pub fn size(&self) -> usize {
+    self.size
+  }
+
+  /// Returns the size of the prolog (i.e the amount of relocated bytes).
   pub fn prolog_size(&self) -> usize {
     self.prolog_size
   }
+
+  /// Returns a record for each relocated instruction, in order.
+  ///
+  /// The records map addresses within the trampoline to the original
+  /// instructions. The jump back to the target is not included.
+  pub fn relocations(&self) -> &[RelocationRecord] {
+    &self.relocations
+  }
+
+  /// Returns the system call number (SSN) of the target, if it's a Windows
+  /// system call stub (x64 only).
+  pub fn syscall_number(&self) -> Option<u32> {
+    self.syscall_number
+  }
+}
+
+/// The code of a trampoline, generated but not yet emitted to memory.
+///
+/// Everything but the trampoline's address (i.e its relocations and prolog
+/// size) is known beforehand, so a target may be patched before its
+/// trampoline is emitted. The relocated instructions are captured, therefore
+/// the target may be overwritten in the meantime.
+pub struct PendingTrampoline {
+  target: *const (),
+  emitter: pic::CodeEmitter,
+  relocations: Vec<RelocationRecord>,
+  prolog_size: usize,
+  syscall_number: Option<u32>,
+  /// The range required by RIP relative operands, if any.
+  operand_range: usize,
+  /// The preferred range of the trampoline.
+  range: usize,
+}
+
+impl PendingTrampoline {
+  /// Generates a trampoline relocating at least `margin` bytes of an address,
+  /// preferably emitted within `range` bytes of it.
+  ///
+  /// The prologue is executed before the relocated instructions.
+  pub unsafe fn new(
+    target: *const (),
+    margin: usize,
+    prologue: pic::CodeEmitter,
+    range: usize,
+  ) -> Result<PendingTrampoline> {
+    let mut emitter = arch::meta::entry();
+    emitter.append(prologue);
+
+    let mut builder = Builder::new(target, margin);
+    #[cfg(target_arch = "x86_64")]
+    let stub = syscall::SyscallStub::at(target);
+    #[cfg(target_arch = "x86_64")]
+    if let Some(stub) = stub {
+      // The stub ends with `ret`, so no jump back to the target is required
+      builder.margin = builder.margin.max(stub.size);
+      builder.max_size = builder.max_size.max(stub.size);
+    }
+
+    let (emitter, relocations) = builder.build(emitter)?;
+    let prolog_size = relocations.iter().map(|record| record.original_size).sum();
+
+    // Every operand must be encodable from anywhere within the trampoline
+    let operand_range = match builder.operand_distance {
+      Some(distance) => distance
+        .checked_add(emitter.len())
+        .and_then(|reach| arch::meta::DETOUR_RANGE.checked_sub(reach))
+        .ok_or(Error::OutOfRange)?,
+      None => usize::MAX,
+    };
+
+    Ok(PendingTrampoline {
+      target,
+      emitter,
+      relocations,
+      prolog_size,
+      #[cfg(target_arch = "x86_64")]
+      syscall_number: stub.map(|stub| stub.number),
+      #[cfg(target_arch = "x86")]
+      syscall_number: None,
+      operand_range,
+      range,
+    })
+  }
+
+  /// Generates a trampoline from a prolog relocated by the caller, replacing
+  /// the first `size` bytes of an address.
+  pub fn manual(
+    target: *const (),
+    code: &[u8],
+    size: usize,
+    prologue: pic::CodeEmitter,
+    range: usize,
+  ) -> PendingTrampoline {
+    let mut emitter = arch::meta::entry();
+    emitter.append(prologue);
+
+    let record = RelocationRecord {
+      original_address: target,
+      original_size: size,
+      offset: emitter.len(),
+      size: code.len(),
+      rewritten: true,
+    };
+    emitter.add_code(code);
+    emitter.add_thunk(thunk::jmp(target as usize + size));
+
+    PendingTrampoline {
+      target,
+      emitter,
+      relocations: alloc::vec![record],
+      prolog_size: size,
+      syscall_number: None,
+      operand_range: usize::MAX,
+      range,
+    }
+  }
+
+  /// Emits the trampoline within `range` bytes of the target (and any RIP
+  /// relative operands' destinations), whilst holding the lock.
+  pub unsafe fn emit_locked(&self, range: usize) -> Result<Trampoline> {
+    let range = range.min(self.operand_range);
+    let memory =
+      memory::allocate_pic_within(&self.emitter, self.target, range, CodeKind::Trampoline)?;
+
+    Ok(Trampoline {
+      address: memory.as_ptr() as *const (),
+      memory: Some(memory),
+      relocations: self.relocations.clone(),
+      size: self.emitter.len(),
+      prolog_size: self.prolog_size,
+      syscall_number: self.syscall_number,
+    })
+  }
+
+  /// Returns the preferred range of the trampoline.
+  pub fn range(&self) -> usize {
+    self.range
+  }
+
+  /// Returns the size of the prolog (i.e the amount of relocated bytes).
+  pub fn prolog_size(&self) -> usize {
+    self.prolog_size
+  }
+
+  /// Returns a record for each relocated instruction, in order.
+  pub fn relocations(&self) -> &[RelocationRecord] {
+    &self.relocations
+  }
+
+  /// Returns the system call number (SSN) of the target, if any.
+  pub fn syscall_number(&self) -> Option<u32> {
+    self.syscall_number
+  }
+}
+
+/// Returns the size of the NOPs at the start of an address (e.g alignment
+/// padding, or a pad for hot patching), disassembling at most `limit` bytes.
+pub(crate) unsafe fn leading_nops(target: *const (), limit: usize) -> usize {
+  let mut disassembler = Disassembler::new(target);
+  let mut size = 0;
+
+  while size < limit {
+    match Instruction::new(&mut disassembler, (target as usize + size) as *const ()) {
+      Some(instruction) if instruction.is_nop() => size += instruction.len(),
+      _ => break,
+    }
+  }
+  size
+}
+
+/// Returns the end of the code at an address, estimated as the first return
+/// or unconditional jump beyond any forward branch, that is followed by
+/// padding (i.e `int3` or NOPs) up to the next `alignment` boundary.
+///
+/// At most `limit` bytes are disassembled, and no byte at or beyond it is
+/// read.
+pub(crate) unsafe fn padded_end(
+  target: *const (),
+  limit: usize,
+  alignment: usize,
+) -> Option<usize> {
+  const MAX_INSTRUCTION_SIZE: usize = 15;
+
+  let mut disassembler = Disassembler::new(target);
+  let limit = target as usize + limit;
+  let mut address = target as usize;
+  let mut furthest_branch = address;
+
+  while address + MAX_INSTRUCTION_SIZE <= limit {
+    let instruction = Instruction::new(&mut disassembler, address as *const ())?;
+    address = instruction.next_instruction_address();
+
+    if let Some(displacement) = instruction.relative_branch_displacement() {
+      if !instruction.is_call() {
+        furthest_branch = furthest_branch.max(address.wrapping_add(displacement as usize));
+      }
+    }
+
+    let is_terminal = instruction.is_return() || instruction.is_unconditional_jump();
+    if !is_terminal || address <= furthest_branch {
+      continue;
+    }
+
+    // Aligned code without padding may just as well belong to the function
+    let boundary = (address + alignment - 1) & !(alignment - 1);
+    if boundary == address || boundary + MAX_INSTRUCTION_SIZE > limit {
+      continue;
+    }
+
+    let padding = slice::from_raw_parts(address as *const u8, boundary - address);
+    if padding.iter().all(|&code| code == 0xCC)
+      || leading_nops(address as *const (), padding.len()) == padding.len()
+    {
+      return Some(address);
+    }
+  }
+  None
 }
 
 /// A trampoline builder.
@@ -37,10 +411,22 @@ struct Builder {
   branch_address: Option<usize>,
   /// Total amount of bytes disassembled.
   total_bytes_disassembled: usize,
+  /// The amount of bytes of NOPs at the start of the target, which are not
+  /// relocated.
+  leading_nops: usize,
   /// The preferred minimum amount of bytes disassembled.
   margin: usize,
+  /// The maximum amount of bytes relocated.
+  max_size: usize,
+  /// The end of the target's function, if known exactly.
+  function_end: Option<usize>,
   /// Whether disassembling has finished or not.
   finished: bool,
+  /// Whether the current instruction has been rewritten or not.
+  rewritten: bool,
+  /// The furthest distance between the target and the destination of an
+  /// adjusted RIP relative operand.
+  operand_distance: Option<usize>,
   /// The target the trampoline is adapted for.
   target: *const (),
 }
@@ -52,28 +438,56 @@ impl Builder {
       disassembler: Disassembler::new(target),
       branch_address: None,
       total_bytes_disassembled: 0,
+      leading_nops: 0,
       finished: false,
+      rewritten: false,
+      operand_distance: None,
       target,
       margin,
+      max_size: crate::meta::max_prolog_size(),
+      function_end: crate::analysis::unwind_bounds(target).map(|bounds| bounds.end),
     }
   }
 
-  /// Creates a trampoline with the supplied settings.
+  /// Generates the trampoline's code after `emitter`'s, and a record for each
+  /// relocated instruction.
   ///
   /// Margins larger than five bytes may lead to undefined behavior.
-  pub unsafe fn build(mut self) -> Result<Trampoline> {
-    let mut emitter = pic::CodeEmitter::new();
+  pub unsafe fn build(
+    &mut self,
+    mut emitter: pic::CodeEmitter,
+  ) -> Result<(pic::CodeEmitter, Vec<RelocationRecord>)> {
+    let mut relocations = Vec::new();
 
     while !self.finished {
       let instruction = self.next_instruction()?;
       let thunk = self.process_instruction(&instruction)?;
 
+      // NOPs at the start (e.g padding) are overwritten, but not relocated
+      let is_leading_nop = self.is_leading_nop(&instruction);
+
       // If the trampoline displacement is larger than the target
       // function, all instructions will be displaced, and if there is
       // internal branching, it will end up at the wrong instructions.
       if self.is_instruction_in_branch(&instruction) && instruction.len() != thunk.len() {
-        Err(Error::UnsupportedInstruction)?;
+        Err(self.unsupported(&instruction))?;
+      } else if is_leading_nop {
+        self.leading_nops += instruction.len();
+        relocations.push(RelocationRecord {
+          original_address: instruction.address() as *const (),
+          original_size: instruction.len(),
+          offset: emitter.len(),
+          size: 0,
+          rewritten: true,
+        });
       } else {
+        relocations.push(RelocationRecord {
+          original_address: instruction.address() as *const (),
+          original_size: instruction.len(),
+          offset: emitter.len(),
+          size: thunk.len(),
+          rewritten: self.rewritten,
+        });
         emitter.add_thunk(thunk);
       }
 
@@ -85,10 +499,7 @@ impl Builder {
       }
     }
 
-    Ok(Trampoline {
-      prolog_size: self.total_bytes_disassembled,
-      emitter,
-    })
+    Ok((emitter, relocations))
   }
 
   /// Disassembles the next instruction and returns its properties.
@@ -97,10 +508,25 @@ impl Builder {
 
     // Disassemble the next instruction
     match Instruction::new(&mut self.disassembler, instruction_address as *const _) {
-      None => Err(Error::InvalidCode)?,
+      None => Err(Error::InvalidCode {
+        details: self.details(instruction_address),
+      })?,
       Some(instruction) => {
-        // Keep track of the total amount of bytes
+        // Keep track of the total amount of bytes, excluding leading NOPs
         self.total_bytes_disassembled += instruction.len();
+        if self.total_bytes_disassembled - self.leading_nops > self.max_size
+          && !self.is_leading_nop(&instruction)
+        {
+          Err(Error::NoPatchArea)?;
+        }
+
+        // Code beyond the function's end may belong to another one
+        if self
+          .function_end
+          .is_some_and(|end| instruction.next_instruction_address() > end)
+        {
+          Err(Error::NoPatchArea)?;
+        }
         Ok(instruction)
       },
     }
@@ -111,6 +537,8 @@ impl Builder {
     &mut self,
     instruction: &Instruction,
   ) -> Result<Box<dyn pic::Thunkable>> {
+    self.rewritten = false;
+
     if let Some(displacement) = instruction.rip_operand_displacement() {
       return self.handle_rip_relative_instruction(instruction, displacement);
     } else if let Some(displacement) = instruction.relative_branch_displacement() {
@@ -147,9 +575,18 @@ impl Builder {
       return Ok(Box::new(instruction.as_slice().to_vec()));
     }
 
+    // The operand's destination must be addressable
+    let destination = arch::offset(instruction.next_instruction_address(), displacement)
+      .ok_or_else(|| self.unsupported(instruction))?;
+    let distance = destination.abs_diff(self.target as usize);
+    self.operand_distance = self.operand_distance.max(Some(distance));
+
     // These need to be captured by the closure
-    let instruction_address = instruction.address() as isize;
+    self.rewritten = true;
     let instruction_bytes = instruction.as_slice().to_vec();
+    let index = instruction
+      .rip_operand_offset()
+      .expect("RIP relative operand offset");
 
     Ok(Box::new(pic::UnsafeThunk::new(
       move |offset| {
@@ -157,18 +594,16 @@ impl Builder {
 
         // Calculate the new relative displacement for the operand. The
         // instruction is relative so the offset (i.e where the trampoline is
-        // allocated), must be within a range of +/- 2GB.
-        let adjusted_displacement = instruction_address
-          .wrapping_sub(offset as isize)
-          .wrapping_add(displacement);
-        assert!(crate::arch::is_within_range(adjusted_displacement));
-
-        // The displacement value is placed at (instruction - disp32)
-        let index = instruction_bytes.len() - mem::size_of::<u32>();
-
-        // Write the adjusted displacement offset to the operand
-        let as_bytes: [u8; 4] = mem::transmute(adjusted_displacement as u32);
-        bytes[index..instruction_bytes.len()].copy_from_slice(&as_bytes);
+        // allocated), must be within a range of +/- 2GB, which is ensured
+        // when it's allocated.
+        let adjusted_displacement =
+          arch::relative_operand(offset, instruction_bytes.len(), destination)
+            .expect("trampoline within range of a RIP relative operand");
+
+        // Write the adjusted displacement offset to the operand, which may be
+        // followed by an immediate (e.g `cmp dword [rip+0x10], 5`)
+        let as_bytes = (adjusted_displacement as u32).to_ne_bytes();
+        bytes[index..index + as_bytes.len()].copy_from_slice(&as_bytes);
         bytes
       },
       instruction.len(),
@@ -182,12 +617,13 @@ impl Builder {
     displacement: isize,
   ) -> Result<Box<dyn pic::Thunkable>> {
     // Calculate the absolute address of the target destination
-    let destination_address_abs = instruction
-      .next_instruction_address()
-      .wrapping_add(displacement as usize);
+    let destination_address_abs =
+      arch::offset(instruction.next_instruction_address(), displacement)
+        .ok_or_else(|| self.unsupported(instruction))?;
 
     if instruction.is_call() {
       // Calls are not an issue since they return to the original address
+      self.rewritten = true;
       return Ok(thunk::call(destination_address_abs));
     }
 
@@ -195,18 +631,24 @@ impl Builder {
 
     // If the relative jump is internal, and short enough to
     // fit within the copied function prolog (i.e `margin`),
-    // the jump instruction can be copied indiscriminately.
+    // the jump instruction can be copied indiscriminately, unless
+    // it jumps to leading NOPs that are not relocated.
     if prolog_range.contains(&destination_address_abs) {
+      if destination_address_abs < self.target as usize + self.leading_nops {
+        Err(self.unsupported(instruction))?;
+      }
+
       // Keep track of the jump's destination address
       self.branch_address = Some(destination_address_abs);
       Ok(Box::new(instruction.as_slice().to_vec()))
     } else if instruction.is_loop() {
       // Loops (e.g 'loopnz', 'jecxz') to the outside are not supported
-      Err(Error::UnsupportedInstruction)
+      Err(self.unsupported(instruction))
     } else if instruction.is_unconditional_jump() {
       // If the function is not in a branch, and it unconditionally jumps
       // a distance larger than the prolog, it's the same as if it terminates.
       self.finished = !self.is_instruction_in_branch(instruction);
+      self.rewritten = true;
       Ok(thunk::jmp(destination_address_abs))
     } else {
       // Conditional jumps (Jcc)
@@ -220,14 +662,99 @@ impl Builder {
 
       // Extract the condition (i.e 0x74 is [jz rel8] ⟶ 0x74 & 0x0F == 4)
       let condition = primary_opcode & 0x0F;
+      self.rewritten = true;
       Ok(thunk::jcc(destination_address_abs, condition))
     }
   }
 
+  /// Returns an error describing an instruction that cannot be relocated.
+  unsafe fn unsupported(&self, instruction: &Instruction) -> Error {
+    let bytes = instruction.as_slice();
+    Error::UnsupportedInstruction {
+      address: instruction.address(),
+      offset: instruction.address() - self.target as usize,
+      bytes: bytes[..bytes.len().min(16)].to_vec(),
+      details: self.details(instruction.address()),
+    }
+  }
+
+  /// Returns a listing of the target, marking the instruction at `address`.
+  unsafe fn details(&self, address: usize) -> Option<String> {
+    #[cfg(feature = "disassembly")]
+    return Some(listing(self.target, address));
+
+    #[cfg(not(feature = "disassembly"))]
+    {
+      let _ = address;
+      None
+    }
+  }
+
+  /// Returns whether an instruction is a NOP preceded by NOPs only.
+  fn is_leading_nop(&self, instruction: &Instruction) -> bool {
+    instruction.is_nop() && instruction.address() == self.target as usize + self.leading_nops
+  }
+
   /// Returns whether the current instruction is inside a branch or not.
   fn is_instruction_in_branch(&self, instruction: &Instruction) -> bool {
     self
       .branch_address
-      .map_or(false, |offset| instruction.address() < offset)
+      .is_some_and(|offset| instruction.address() < offset)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::arch::x86::meta;
+  use core::mem;
+
+  /// Verifies that generated code never executes `ret`, which faults when a
+  /// shadow stack is enforced, unless the return address was pushed by a
+  /// matching `call`.
+  ///
+  /// Absolute destinations are embedded as data, therefore they consist of
+  /// bytes that never decode as a return.
+  #[test]
+  fn generated_code_never_returns() {
+    let address = usize::from_ne_bytes([0x11; mem::size_of::<usize>()]) as *const ();
+
+    let single = |thunk| {
+      let mut emitter = pic::CodeEmitter::new();
+      emitter.add_thunk(thunk);
+      emitter
+    };
+
+    let emitters = [
+      // The entry thunk, calling the same address instead of a callback
+      meta::relay_builder(
+        address,
+        address,
+        thunk::call_with_registers(address as usize),
+      )
+      .unwrap()
+      .unwrap(),
+      meta::hop(address),
+      meta::index_stub(1, 0x1000),
+      meta::entry(),
+      single(thunk::call(address as usize)),
+      single(thunk::jcc(address as usize, 5)),
+    ];
+
+    for emitter in &emitters {
+      // Padded, so the disassembler never reads beyond the code
+      let mut code = emitter.emit(0x2000 as *const ());
+      let size = code.len();
+      code.extend([0x90; 16]);
+
+      let mut offset = 0;
+      let mut disassembler = Disassembler::new(code.as_ptr() as *const ());
+      while offset < size {
+        let address = code[offset..].as_ptr() as *const ();
+        let instruction = unsafe { Instruction::new(&mut disassembler, address) }.unwrap();
+        assert!(!instruction.is_return(), "{:02x?}", &code[..size]);
+        offset += instruction.len();
+      }
+    }
   }
 }
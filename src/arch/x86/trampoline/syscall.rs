@@ -0,0 +1,155 @@
+//! Recognition of Windows system call stubs (e.g `ntdll!NtClose`).
+use core::convert::TryFrom;
+use core::slice;
+
+/// `mov r10, rcx`, the first instruction of every stub.
+const MOV_R10_RCX: [u8; 3] = [0x4C, 0x8B, 0xD1];
+
+/// `mov eax, imm32`, loading the system call number.
+const MOV_EAX: u8 = 0xB8;
+
+/// `test byte ptr [disp32], imm8`, checking whether `int 2e` is preferred.
+const TEST_BYTE_ABS: [u8; 3] = [0xF6, 0x04, 0x25];
+
+/// `jne rel8`, branching to the `int 2e` path.
+const JNE_REL8: u8 = 0x75;
+
+/// `syscall` followed by `ret`.
+const SYSCALL_RET: [u8; 3] = [0x0F, 0x05, 0xC3];
+
+/// A system call stub of an x64 Windows process.
+///
+/// The stubs exported by `ntdll` (i.e `Nt*` and `Zw*`) have a rigid shape:
+///
+/// ```asm
+/// mov r10, rcx
+/// mov eax, SSN
+/// test byte ptr [0x7FFE0308], 1 ; optional, since Windows 10
+/// jne 1f                        ; optional, since Windows 10
+/// syscall
+/// ret
+/// 1:
+/// int 0x2e
+/// ret
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyscallStub {
+  /// The system call number (SSN) loaded into `eax`.
+  pub number: u32,
+  /// The size of the stub, up to and including the `ret` after `syscall`.
+  pub size: usize,
+}
+
+impl SyscallStub {
+  /// The size of the longest stub.
+  pub const MAX_SIZE: usize = 21;
+
+  /// Recognizes a stub at the start of `code`.
+  ///
+  /// The `jne`, if any, must branch beyond the `ret` after `syscall`.
+  pub fn parse(code: &[u8]) -> Option<SyscallStub> {
+    if !code.starts_with(&MOV_R10_RCX) || code.get(3) != Some(&MOV_EAX) {
+      return None;
+    }
+
+    let number = u32::from_le_bytes(<[u8; 4]>::try_from(code.get(4..8)?).ok()?);
+    let mut offset = 8;
+
+    if code[offset..].starts_with(&TEST_BYTE_ABS) {
+      // test (8 bytes) followed by jne rel8 (2 bytes)
+      let branch = offset + 8;
+      let displacement = *code.get(branch + 1)? as i8;
+      if code.get(branch) != Some(&JNE_REL8) || displacement < SYSCALL_RET.len() as i8 {
+        return None;
+      }
+      offset = branch + 2;
+    }
+
+    code[offset..]
+      .starts_with(&SYSCALL_RET)
+      .then_some(SyscallStub {
+        number,
+        size: offset + SYSCALL_RET.len(),
+      })
+  }
+
+  /// Recognizes a stub at `target`.
+  ///
+  /// Nothing beyond the first instruction is read, unless it's the stub's,
+  /// and nothing beyond the target's region.
+  pub unsafe fn at(target: *const ()) -> Option<SyscallStub> {
+    let code = target as *const u8;
+    if slice::from_raw_parts(code, MOV_R10_RCX.len()) != MOV_R10_RCX {
+      return None;
+    }
+
+    let readable = crate::memory::region_info(target)
+      .ok()??
+      .end()
+      .saturating_sub(target as usize);
+    Self::parse(slice::from_raw_parts(code, readable.min(Self::MAX_SIZE)))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// `NtClose` of Windows 10, followed by the `int 2e` path.
+  const STUB: [u8; 24] = [
+    0x4C, 0x8B, 0xD1, 0xB8, 0x0F, 0x00, 0x00, 0x00, 0xF6, 0x04, 0x25, 0x08, 0x03, 0xFE, 0x7F, 0x01,
+    0x75, 0x03, 0x0F, 0x05, 0xC3, 0xCD, 0x2E, 0xC3,
+  ];
+
+  /// `NtClose` of Windows 7, followed by padding.
+  const LEGACY_STUB: [u8; 16] = [
+    0x4C, 0x8B, 0xD1, 0xB8, 0x0C, 0x00, 0x00, 0x00, 0x0F, 0x05, 0xC3, 0x0F, 0x1F, 0x44, 0x00, 0x00,
+  ];
+
+  #[test]
+  fn parse_stubs() {
+    let stub = SyscallStub::parse(&STUB).unwrap();
+    assert_eq!(
+      stub,
+      SyscallStub {
+        number: 0xF,
+        size: 21
+      }
+    );
+    assert_eq!(stub.size, SyscallStub::MAX_SIZE);
+
+    let legacy = SyscallStub::parse(&LEGACY_STUB).unwrap();
+    assert_eq!(
+      legacy,
+      SyscallStub {
+        number: 0xC,
+        size: 11
+      }
+    );
+
+    // The number is a full 32-bit immediate
+    let mut stub = STUB;
+    stub[4..8].copy_from_slice(&0x1234_5678u32.to_le_bytes());
+    assert_eq!(SyscallStub::parse(&stub).unwrap().number, 0x1234_5678);
+  }
+
+  #[test]
+  fn parse_rejects_other_code() {
+    // Truncated stubs are not recognized
+    for size in 0..SyscallStub::MAX_SIZE {
+      assert_eq!(SyscallStub::parse(&STUB[..size]), None);
+    }
+
+    // A `jne` branching into the stub
+    let mut stub = STUB;
+    stub[17] = 0x01;
+    assert_eq!(SyscallStub::parse(&stub), None);
+
+    // Any other instruction instead of `syscall`, `jne` or `mov eax`
+    for index in [3, 16, 18, 20] {
+      let mut stub = STUB;
+      stub[index] = 0x90;
+      assert_eq!(SyscallStub::parse(&stub), None, "index {}", index);
+    }
+  }
+}
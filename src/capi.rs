@@ -0,0 +1,250 @@
+//! A C interface for creating and managing detours.
+//!
+//! The interface is exported when the `capi` feature is enabled, and is
+//! described by the `include/detour.h` header. Detours are represented by an
+//! opaque `detour_handle`, which may be used and destroyed from any thread.
+//! Panics never cross the boundary; they are reported as
+//! `DETOUR_ERROR_PANIC`.
+//!
+//! To build a shared or static library, specify the crate type explicitly:
+//!
+//! ```sh
+//! cargo rustc --release --features capi --crate-type cdylib
+//! ```
+//!
+//! The header is generated by `cbindgen` from this module alone, and verified
+//! by the test suite. To regenerate it after changing the interface:
+//!
+//! ```sh
+//! DETOUR_BLESS=1 cargo test --features capi --test capi
+//! ```
+
+use crate::error::Error;
+use crate::RawDetour;
+use std::boxed::Box;
+use std::ffi::c_void;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+/// An opaque handle to a detour.
+pub struct DetourHandle(RawDetour);
+
+/// The error codes reported by the C interface.
+///
+/// The values are part of the interface; codes are only ever appended.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DetourError {
+  /// The operation succeeded.
+  Ok = 0,
+  /// The address for the target and detour are identical.
+  SameAddress = 1,
+  /// The address does not contain valid instructions.
+  InvalidCode = 2,
+  /// The address has no available area for patching.
+  NoPatchArea = 3,
+  /// The address is not executable memory.
+  NotExecutable = 4,
+  /// The detour is not initialized.
+  NotInitialized = 5,
+  /// The detour is already initialized.
+  AlreadyInitialized = 6,
+  /// The system is out of executable memory.
+  OutOfMemory = 7,
+  /// The address contains an instruction that prevents detouring.
+  UnsupportedInstruction = 8,
+  /// No operating system backend has been installed.
+  MissingBackend = 9,
+  /// A memory operation failed.
+  RegionFailure = 10,
+  /// A required pointer argument was null.
+  NullPointer = 11,
+  /// The operation panicked.
+  Panic = 12,
+  /// A library symbol could not be found.
+  SymbolNotFound = 13,
+  /// The operation is not permitted in loader-safe mode.
+  LoaderUnsafe = 14,
+  /// No registered region within range has sufficient space.
+  RegionExhausted = 15,
+  /// The operating system denied an operation.
+  PermissionDenied = 16,
+  /// No free memory could be found close to the target.
+  NoMemoryInRange = 17,
+  /// The operating system failed to map memory.
+  AllocationFailed = 18,
+  /// The address of the detour is not executable memory.
+  DetourNotExecutable = 19,
+  /// The target is part of a trampoline allocated by the library, or of the
+  /// library's own patching code.
+  SelfHook = 20,
+  /// The detour cannot be reached by a relative jump from the target.
+  OutOfRange = 21,
+  /// All debug registers are occupied by hardware breakpoints.
+  NoDebugRegister = 22,
+  /// A function pointer slot no longer contains the detour.
+  SlotChanged = 23,
+  /// A patch was rejected by a callback.
+  PatchRejected = 24,
+  /// A configured option has an invalid value.
+  InvalidOption = 25,
+  /// A static detour has no closure set.
+  NoDetourSet = 26,
+  /// Executable memory was denied, and so was each fallback.
+  ExecutableMemoryDenied = 27,
+  /// Another patch operation is in progress.
+  WouldBlock = 28,
+  /// An exported symbol is forwarded to another module.
+  ForwardedExport = 29,
+  /// The address is ARM64EC code within an emulated process.
+  ArmCode = 30,
+  /// The address is an ARM64EC fast-forward sequence; its real entry is
+  /// returned by `detour_fast_forward_entry`.
+  FastForwardThunk = 31,
+  /// Handed off state is incompatible, or no longer matches its target.
+  IncompatibleState = 32,
+  /// No static detour is registered under a handoff key.
+  UnknownKey = 33,
+}
+
+impl From<&Error> for DetourError {
+  fn from(error: &Error) -> Self {
+    match error {
+      Error::SameAddress => DetourError::SameAddress,
+      Error::InvalidCode { .. } => DetourError::InvalidCode,
+      Error::NoPatchArea => DetourError::NoPatchArea,
+      Error::NotExecutable => DetourError::NotExecutable,
+      Error::DetourNotExecutable => DetourError::DetourNotExecutable,
+      Error::NullPointer => DetourError::NullPointer,
+      Error::SelfHook => DetourError::SelfHook,
+      Error::OutOfRange => DetourError::OutOfRange,
+      Error::NotInitialized => DetourError::NotInitialized,
+      Error::AlreadyInitialized => DetourError::AlreadyInitialized,
+      Error::OutOfMemory => DetourError::OutOfMemory,
+      Error::UnsupportedInstruction { .. } => DetourError::UnsupportedInstruction,
+      Error::MissingBackend => DetourError::MissingBackend,
+      Error::LoaderUnsafe => DetourError::LoaderUnsafe,
+      Error::RegionExhausted => DetourError::RegionExhausted,
+      Error::PermissionDenied { .. } => DetourError::PermissionDenied,
+      Error::NoMemoryInRange { .. } => DetourError::NoMemoryInRange,
+      Error::AllocationFailed { .. } => DetourError::AllocationFailed,
+      #[cfg(not(any(target_os = "netbsd", target_os = "openbsd")))]
+      Error::RegionFailure(_) => DetourError::RegionFailure,
+      Error::NoDebugRegister => DetourError::NoDebugRegister,
+      Error::SlotChanged => DetourError::SlotChanged,
+      Error::PatchRejected => DetourError::PatchRejected,
+      Error::WouldBlock => DetourError::WouldBlock,
+      Error::InvalidOption { .. } => DetourError::InvalidOption,
+      Error::NoDetourSet => DetourError::NoDetourSet,
+      Error::ExecutableMemoryDenied { .. } => DetourError::ExecutableMemoryDenied,
+      Error::UnknownSymbol { .. } | Error::UnknownModule { .. } => DetourError::SymbolNotFound,
+      Error::ForwardedExport { .. } => DetourError::ForwardedExport,
+      Error::ArmCode => DetourError::ArmCode,
+      Error::FastForwardThunk { .. } => DetourError::FastForwardThunk,
+      Error::UnsupportedSignature { .. } => DetourError::InvalidCode,
+      Error::IncompatibleState => DetourError::IncompatibleState,
+      Error::UnknownKey { .. } => DetourError::UnknownKey,
+      #[cfg(feature = "libloading")]
+      Error::SymbolNotFound { .. } => DetourError::SymbolNotFound,
+    }
+  }
+}
+
+/// Invokes a fallible operation, converting any errors or panics.
+fn guard<T, F: FnOnce() -> crate::Result<T>>(operation: F) -> Result<T, DetourError> {
+  match panic::catch_unwind(AssertUnwindSafe(operation)) {
+    Ok(result) => result.map_err(|error| DetourError::from(&error)),
+    Err(_) => Err(DetourError::Panic),
+  }
+}
+
+/// Creates a new (disabled) detour from `target` to `detour`.
+///
+/// Returns null on failure, and stores the error code in `error` (if it's not
+/// null). The handle must be released using `detour_destroy`.
+#[no_mangle]
+pub unsafe extern "C" fn detour_create(
+  target: *const c_void,
+  detour: *const c_void,
+  error: *mut DetourError,
+) -> *mut DetourHandle {
+  let result = if target.is_null() || detour.is_null() {
+    Err(DetourError::NullPointer)
+  } else {
+    guard(|| RawDetour::new(target as *const (), detour as *const ()))
+  };
+
+  let (handle, code) = match result {
+    Ok(detour) => (
+      Box::into_raw(Box::new(DetourHandle(detour))),
+      DetourError::Ok,
+    ),
+    Err(code) => (ptr::null_mut(), code),
+  };
+
+  if !error.is_null() {
+    *error = code;
+  }
+  handle
+}
+
+/// Enables the detour.
+#[no_mangle]
+pub unsafe extern "C" fn detour_enable(handle: *const DetourHandle) -> DetourError {
+  match handle.as_ref() {
+    Some(handle) => guard(|| handle.0.enable()).err().unwrap_or(DetourError::Ok),
+    None => DetourError::NullPointer,
+  }
+}
+
+/// Disables the detour.
+#[no_mangle]
+pub unsafe extern "C" fn detour_disable(handle: *const DetourHandle) -> DetourError {
+  match handle.as_ref() {
+    Some(handle) => guard(|| handle.0.disable())
+      .err()
+      .unwrap_or(DetourError::Ok),
+    None => DetourError::NullPointer,
+  }
+}
+
+/// Returns whether the detour is enabled or not.
+#[no_mangle]
+pub unsafe extern "C" fn detour_is_enabled(handle: *const DetourHandle) -> bool {
+  handle.as_ref().is_some_and(|handle| handle.0.is_enabled())
+}
+
+/// Returns a callable address to the original function, or null if the
+/// handle is null.
+#[no_mangle]
+pub unsafe extern "C" fn detour_trampoline(handle: *const DetourHandle) -> *const c_void {
+  handle.as_ref().map_or(ptr::null(), |handle| {
+    handle.0.trampoline() as *const () as *const c_void
+  })
+}
+
+/// Returns the real ARM64EC entry of a target that is a fast-forward sequence
+/// (i.e creating a detour of it fails with `DETOUR_ERROR_FAST_FORWARD_THUNK`),
+/// or null otherwise.
+#[no_mangle]
+pub unsafe extern "C" fn detour_fast_forward_entry(target: *const c_void) -> *const c_void {
+  #[cfg(all(windows, target_arch = "x86_64"))]
+  if !target.is_null() {
+    if let Err(Error::FastForwardThunk { entry }) =
+      crate::os::arm64ec::validate_target(target as *const ())
+    {
+      return entry as *const c_void;
+    }
+  }
+
+  let _ = target;
+  ptr::null()
+}
+
+/// Disables and releases the detour. A null handle is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn detour_destroy(handle: *mut DetourHandle) {
+  if !handle.is_null() {
+    let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(handle))));
+  }
+}
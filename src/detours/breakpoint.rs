@@ -0,0 +1,112 @@
+use super::exception::{self, Kind, Slot};
+use crate::arch::Detour;
+use crate::error::Result;
+
+/// A detour redirecting a target using a breakpoint instruction.
+///
+/// When enabled, only the first byte of the target is overwritten (with an
+/// `int3`), which makes this mode applicable to targets that cannot tolerate a
+/// jump being written (e.g a checksummed or undecodable prolog). Whenever the
+/// breakpoint is hit, a shared exception handler (a vectored exception
+/// handler on Windows, a `SIGTRAP` handler elsewhere) redirects the thread to
+/// the detour. The handler is installed along with the first breakpoint
+/// detour (including [hardware](./struct.HwBreakpointDetour.html) ones), and
+/// removed once the last one is dropped.
+///
+/// Each call raises an exception, which makes this mode considerably slower
+/// than an inline detour.
+///
+/// # Debuggers
+///
+/// Debuggers receive breakpoint exceptions before the process does. Whilst a
+/// debugger is attached, it must be instructed to pass them on (e.g `handle
+/// SIGTRAP nostop noprint pass` in GDB), and its own software breakpoints must
+/// not be placed at a hooked target. Breakpoints that do not belong to a
+/// detour are forwarded to the previously installed signal handler (or the
+/// next exception handler on Windows).
+///
+/// # Example
+///
+/// ```rust
+/// # use detour::Result;
+/// use detour::BreakpointDetour;
+/// use std::mem;
+///
+/// #[inline(never)]
+/// extern "C" fn add5(val: i32) -> i32 {
+///   unsafe { std::ptr::read_volatile(&val) + 5 }
+/// }
+///
+/// extern "C" fn add10(val: i32) -> i32 {
+///   val + 10
+/// }
+///
+/// # fn main() -> Result<()> {
+/// let hook = unsafe { BreakpointDetour::new(add5 as *const (), add10 as *const ())? };
+///
+/// unsafe { hook.enable()? };
+/// assert_eq!(add5(5), 15);
+///
+/// let original: extern "C" fn(i32) -> i32 = unsafe { mem::transmute(hook.trampoline()) };
+/// assert_eq!(original(5), 10);
+///
+/// unsafe { hook.disable()? };
+/// assert_eq!(add5(5), 10);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct BreakpointDetour {
+  detour: Detour,
+  slot: &'static Slot,
+}
+
+impl BreakpointDetour {
+  /// Constructs a new breakpoint detour.
+  ///
+  /// The hook is disabled by default. The detour is entered with the state of
+  /// the target's caller, i.e it must have a prototype identical to the
+  /// target's.
+  pub unsafe fn new(target: *const (), detour: *const ()) -> Result<Self> {
+    let inner = Detour::with_breakpoint(target, detour)?;
+    let slot = exception::register(Kind::Software, target as usize, detour as usize)?;
+
+    Ok(BreakpointDetour {
+      detour: inner,
+      slot,
+    })
+  }
+
+  /// Enables the detour.
+  pub unsafe fn enable(&self) -> Result<()> {
+    self.detour.enable()
+  }
+
+  /// Disables the detour.
+  pub unsafe fn disable(&self) -> Result<()> {
+    self.detour.disable()
+  }
+
+  /// Returns whether the detour is enabled or not.
+  pub fn is_enabled(&self) -> bool {
+    self.detour.is_enabled()
+  }
+
+  /// Returns a reference to the generated trampoline.
+  ///
+  /// The trampoline executes the instruction displaced by the breakpoint, and
+  /// continues with the remainder of the original function.
+  pub fn trampoline(&self) -> &() {
+    self.detour.trampoline()
+  }
+}
+
+impl Drop for BreakpointDetour {
+  /// Disables the detour, and removes the exception handler if this is the
+  /// last breakpoint detour.
+  fn drop(&mut self) {
+    let result = unsafe { self.detour.disable() };
+    debug_assert!(result.is_ok());
+    unsafe { exception::unregister(self.slot) };
+  }
+}
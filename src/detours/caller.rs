@@ -0,0 +1,143 @@
+//! Tracking of the addresses static detours are called from.
+//!
+//! The relay of each static detour records the return address at its entry,
+//! before any frames have been pushed, and the dispatch function moves it to a
+//! per-thread stack for the duration of the closure.
+use crate::Shims;
+
+#[cfg(feature = "std")]
+mod imp {
+  use crate::{arch, RegisterState, Shims};
+  use core::cell::{Cell, RefCell};
+  use std::vec::Vec;
+
+  std::thread_local! {
+    /// The caller recorded by a relay, awaiting its dispatch function.
+    static PENDING: Cell<Option<usize>> = const { Cell::new(None) };
+    /// The callers of all detours currently executing on the thread.
+    static CALLERS: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+  }
+
+  pub fn caller_address() -> Option<*const ()> {
+    CALLERS
+      .try_with(|callers| callers.borrow().last().copied())
+      .ok()
+      .flatten()
+      .map(|address| address as *const ())
+  }
+
+  /// Records the caller of a detoured function.
+  extern "C" fn record(registers: &RegisterState) {
+    let _ = PENDING.try_with(|pending| pending.set(Some(registers.return_address)));
+  }
+
+  pub fn shims() -> Shims {
+    Shims {
+      before_detour: arch::meta::entry_thunk(record),
+      ..Shims::default()
+    }
+  }
+
+  pub fn enter() -> bool {
+    let caller = PENDING.try_with(|pending| pending.take()).ok().flatten();
+    caller.is_some_and(|caller| {
+      CALLERS
+        .try_with(|callers| callers.borrow_mut().push(caller))
+        .is_ok()
+    })
+  }
+
+  pub fn exit() {
+    let _ = CALLERS.try_with(|callers| callers.borrow_mut().pop());
+  }
+}
+
+#[cfg(not(feature = "std"))]
+mod imp {
+  use crate::Shims;
+
+  pub fn caller_address() -> Option<*const ()> {
+    None
+  }
+
+  pub fn shims() -> Shims {
+    Shims::default()
+  }
+
+  pub fn enter() -> bool {
+    false
+  }
+
+  pub fn exit() {}
+}
+
+/// Returns the address the currently executing static detour was called
+/// from.
+///
+/// The address is the return address of the call to the target, captured
+/// before the detour is invoked, and is therefore unaffected by inlining
+/// within the closure. Nested detours are tracked separately; once a nested
+/// detour returns, the address of the outer one is returned again.
+///
+/// Returns `None` outside of a static detour's closure. Without the `std`
+/// feature, the address is never captured. With it, each call to a static
+/// detour saves and restores the thread's registers whilst the address is
+/// recorded.
+///
+/// # Example
+///
+/// ```rust
+/// # use detour::Result;
+/// use detour::{caller_address, static_detour};
+///
+/// static_detour! {
+///   static Hook: extern "C" fn(i32) -> i32;
+/// }
+///
+/// #[inline(never)]
+/// extern "C" fn add5(val: i32) -> i32 {
+///   unsafe { std::ptr::read_volatile(&val) + 5 }
+/// }
+///
+/// # fn main() -> Result<()> {
+/// unsafe {
+///   Hook.initialize(add5, |val| {
+///     // e.g only intercept calls from a specific module
+///     assert!(caller_address().is_some());
+///     Hook.call(val)
+///   })?
+///   .enable()?;
+/// }
+///
+/// assert_eq!(add5(5), 10);
+/// assert_eq!(caller_address(), None);
+/// # Ok(())
+/// # }
+/// ```
+pub fn caller_address() -> Option<*const ()> {
+  imp::caller_address()
+}
+
+/// Returns the shims capturing the caller of a static detour.
+pub(crate) fn shims() -> Shims {
+  imp::shims()
+}
+
+/// The caller of a static detour, for the duration of its closure.
+#[doc(hidden)]
+pub struct __CallerFrame(bool);
+
+impl __CallerFrame {
+  /// Moves the caller recorded by the relay to the thread's stack.
+  pub fn enter() -> Self {
+    __CallerFrame(imp::enter())
+  }
+}
+
+impl Drop for __CallerFrame {
+  fn drop(&mut self) {
+    if self.0 {
+      imp::exit();
+    }
+  }
+}
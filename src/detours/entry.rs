@@ -0,0 +1,80 @@
+use crate::arch::{self, Detour};
+use crate::error::Result;
+use crate::{pool, RegisterState};
+
+/// A detour calling a function with the registers of each call.
+///
+/// Whenever the target is called, all general purpose registers, the flags
+/// and the vector registers used for arguments are saved, and the callback is
+/// invoked with a reference to them. Afterwards, the registers are restored,
+/// and the original function is executed unchanged.
+///
+/// Since the arguments are never interpreted, the target's prototype does not
+/// need to be known (e.g for logging calls to functions that cannot be
+/// expressed using the `Function` trait).
+///
+/// # Example
+///
+/// ```rust
+/// # use detour::Result;
+/// use detour::{EntryDetour, RegisterState};
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+///
+/// static CALLS: AtomicUsize = AtomicUsize::new(0);
+///
+/// #[inline(never)]
+/// extern "C" fn add5(val: i32) -> i32 {
+///   unsafe { std::ptr::read_volatile(&val) + 5 }
+/// }
+///
+/// extern "C" fn log(_registers: &RegisterState) {
+///   CALLS.fetch_add(1, Ordering::SeqCst);
+/// }
+///
+/// # fn main() -> Result<()> {
+/// let hook = unsafe { EntryDetour::new(add5 as *const (), log)? };
+///
+/// unsafe { hook.enable()? };
+/// assert_eq!(add5(5), 10);
+/// assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+///
+/// unsafe { hook.disable()? };
+/// assert_eq!(add5(5), 10);
+/// assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct EntryDetour(Detour);
+
+impl EntryDetour {
+  /// Constructs a new entry detour.
+  ///
+  /// The hook is disabled by default. The callback may be invoked from any
+  /// thread calling the target, and must not unwind.
+  pub unsafe fn new(target: *const (), callback: extern "C" fn(&RegisterState)) -> Result<Self> {
+    Detour::with_entry(target, arch::meta::entry_thunk(callback)).map(EntryDetour)
+  }
+
+  /// Enables the detour.
+  pub unsafe fn enable(&self) -> Result<()> {
+    self.0.enable()
+  }
+
+  /// Disables the detour.
+  pub unsafe fn disable(&self) -> Result<()> {
+    self.0.disable()
+  }
+
+  /// Returns whether the detour is enabled or not.
+  pub fn is_enabled(&self) -> bool {
+    self.0.is_enabled()
+  }
+
+  /// Returns statistics for the pool region containing the thunk.
+  ///
+  /// Returns `None` if the thunk was allocated by a custom allocator.
+  pub fn region(&self) -> Option<pool::RegionStats> {
+    self.0.region()
+  }
+}
@@ -0,0 +1,351 @@
+//! A shared exception handler, redirecting breakpoints to their detours.
+//!
+//! The handler is installed along with the first registered breakpoint, and
+//! removed once the last one is unregistered. Exceptions are dispatched in the
+//! following order:
+//!
+//! - A single step of a thread executing code within a guarded page re-arms the
+//!   page.
+//! - A hardware breakpoint, breakpoint instruction or access to a guarded page
+//!   at a registered target redirects the thread to its detour.
+//! - Any other access to a guarded page is single-stepped.
+//!
+//! Exceptions that do not belong to a detour are forwarded to the previously
+//! installed signal handler (or the next exception handler on Windows).
+
+use crate::error::Result;
+use crate::sync::Mutex;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use std::boxed::Box;
+
+/// The mechanism used to raise an exception at a target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+  /// A breakpoint instruction (`int3`) written at the target.
+  Software = 1,
+  /// A debug register programmed with the target.
+  Hardware = 2,
+  /// A guarded page containing the target.
+  Guard = 3,
+}
+
+/// A breakpoint registered with the exception handler.
+///
+/// Slots are never released, since the handler may be executing concurrently;
+/// instead, vacant slots are reused.
+#[derive(Debug)]
+pub struct Slot {
+  target: AtomicUsize,
+  detour: AtomicUsize,
+  kind: AtomicUsize,
+  next: *const Slot,
+}
+
+unsafe impl Send for Slot {}
+unsafe impl Sync for Slot {}
+
+/// The most recently allocated slot, linking to all others.
+static SLOTS: AtomicPtr<Slot> = AtomicPtr::new(ptr::null_mut());
+
+/// The number of registered breakpoints, serializing registrations.
+static HOOKS: Mutex<usize> = Mutex::new(0);
+
+/// Registers a breakpoint, installing the exception handler if required.
+pub unsafe fn register(kind: Kind, target: usize, detour: usize) -> Result<&'static Slot> {
+  let mut hooks = HOOKS.lock();
+
+  if *hooks == 0 {
+    handler::install()?;
+  }
+
+  let slot = match slots().find(|slot| slot.target.load(Ordering::SeqCst) == 0) {
+    Some(slot) => slot,
+    None => {
+      let slot = Box::leak(Box::new(Slot {
+        target: AtomicUsize::new(0),
+        detour: AtomicUsize::new(0),
+        kind: AtomicUsize::new(0),
+        next: SLOTS.load(Ordering::SeqCst),
+      }));
+      SLOTS.store(slot, Ordering::SeqCst);
+      slot
+    },
+  };
+
+  // The detour must be visible before the slot is matched
+  slot.detour.store(detour, Ordering::SeqCst);
+  slot.kind.store(kind as usize, Ordering::SeqCst);
+  slot.target.store(target, Ordering::SeqCst);
+  *hooks += 1;
+  Ok(slot)
+}
+
+/// Unregisters a breakpoint, removing the exception handler if it's the last
+/// one.
+pub unsafe fn unregister(slot: &Slot) {
+  let mut hooks = HOOKS.lock();
+  slot.target.store(0, Ordering::SeqCst);
+  *hooks -= 1;
+
+  if *hooks == 0 {
+    handler::uninstall();
+  }
+}
+
+/// Returns the detour registered for a breakpoint at an address.
+///
+/// This is invoked from the exception handler, and must not allocate or lock.
+pub(super) fn lookup(kind: Kind, address: usize) -> Option<usize> {
+  slots()
+    .find(|slot| {
+      slot.target.load(Ordering::SeqCst) == address
+        && slot.kind.load(Ordering::SeqCst) == kind as usize
+    })
+    .map(|slot| slot.detour.load(Ordering::SeqCst))
+}
+
+/// Returns an iterator over all allocated slots.
+fn slots() -> impl Iterator<Item = &'static Slot> {
+  let mut current = SLOTS.load(Ordering::SeqCst) as *const Slot;
+
+  core::iter::from_fn(move || unsafe {
+    let slot = current.as_ref()?;
+    current = slot.next;
+    Some(slot)
+  })
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(super) use self::handler::program_counter;
+
+#[cfg(unix)]
+mod handler {
+  use super::{lookup, Kind};
+  use crate::arch;
+  use crate::error::Result;
+  use core::cell::UnsafeCell;
+  use core::mem::{self, MaybeUninit};
+  use core::ptr;
+  use libc::{c_int, c_void, siginfo_t, ucontext_t};
+
+  #[cfg(any(target_os = "linux", target_os = "android"))]
+  use crate::detours::guard::{self, Fault, TRAP_FLAG};
+
+  /// A signal action replaced by the handler.
+  struct Previous(UnsafeCell<MaybeUninit<libc::sigaction>>);
+
+  unsafe impl Sync for Previous {}
+
+  impl Previous {
+    const fn new() -> Self {
+      Previous(UnsafeCell::new(MaybeUninit::uninit()))
+    }
+  }
+
+  /// The handled signals, along with the actions replaced by the handler (only
+  /// written whilst it's not installed).
+  #[cfg(any(target_os = "linux", target_os = "android"))]
+  static SIGNALS: [(c_int, Previous); 2] = [
+    (libc::SIGTRAP, Previous::new()),
+    (libc::SIGSEGV, Previous::new()),
+  ];
+
+  #[cfg(not(any(target_os = "linux", target_os = "android")))]
+  static SIGNALS: [(c_int, Previous); 1] = [(libc::SIGTRAP, Previous::new())];
+
+  /// Installs the signal handler, saving the current actions.
+  pub unsafe fn install() -> Result<()> {
+    let mut action: libc::sigaction = mem::zeroed();
+    action.sa_sigaction = handler as *const () as usize;
+    action.sa_flags = libc::SA_SIGINFO | libc::SA_ONSTACK;
+    libc::sigemptyset(&mut action.sa_mask);
+
+    for (signal, previous) in SIGNALS.iter() {
+      // The arguments are valid, so this cannot fail
+      let result = libc::sigaction(*signal, &action, (*previous.0.get()).as_mut_ptr());
+      debug_assert_eq!(result, 0);
+    }
+    Ok(())
+  }
+
+  /// Restores the actions replaced by the handler.
+  pub unsafe fn uninstall() {
+    for (signal, previous) in SIGNALS.iter() {
+      libc::sigaction(*signal, (*previous.0.get()).as_ptr(), ptr::null_mut());
+    }
+  }
+
+  /// Redirects exceptions belonging to a detour, and forwards others to the
+  /// previous action.
+  unsafe extern "C" fn handler(signal: c_int, info: *mut siginfo_t, context: *mut c_void) {
+    let pc = program_counter(context as *mut ucontext_t);
+
+    match signal {
+      libc::SIGTRAP => {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if guard::step_completed() {
+          *flags(context as *mut ucontext_t) &= !TRAP_FLAG;
+          return;
+        }
+
+        // A debug register traps before the target is executed, whereas the
+        // program counter succeeds a breakpoint instruction
+        let breakpoint = (*pc).wrapping_sub(arch::meta::breakpoint().len());
+        let detour = lookup(Kind::Hardware, *pc).or_else(|| lookup(Kind::Software, breakpoint));
+
+        if let Some(detour) = detour {
+          *pc = detour;
+          return;
+        }
+      },
+      #[cfg(any(target_os = "linux", target_os = "android"))]
+      libc::SIGSEGV => match guard::fault(*pc, (*info).si_addr() as usize) {
+        Some(Fault::Redirect(detour)) => {
+          *pc = detour;
+          return;
+        },
+        Some(Fault::Step) => {
+          *flags(context as *mut ucontext_t) |= TRAP_FLAG;
+          return;
+        },
+        None => (),
+      },
+      _ => (),
+    }
+
+    forward(signal, info, context);
+  }
+
+  /// Forwards a signal to the action replaced by the handler.
+  unsafe fn forward(signal: c_int, info: *mut siginfo_t, context: *mut c_void) {
+    let previous = match SIGNALS.iter().find(|(handled, _)| *handled == signal) {
+      Some((_, previous)) => &*(*previous.0.get()).as_ptr(),
+      None => return,
+    };
+
+    match previous.sa_sigaction {
+      libc::SIG_IGN => (),
+      libc::SIG_DFL => {
+        // Terminate the process as if no handler was installed; the signal is
+        // delivered once the handler returns
+        libc::sigaction(signal, previous, ptr::null_mut());
+        libc::raise(signal);
+      },
+      action if previous.sa_flags & libc::SA_SIGINFO != 0 => {
+        let action: extern "C" fn(c_int, *mut siginfo_t, *mut c_void) = mem::transmute(action);
+        action(signal, info, context);
+      },
+      action => {
+        let action: extern "C" fn(c_int) = mem::transmute(action);
+        action(signal);
+      },
+    }
+  }
+
+  /// Returns the location of the program counter within a signal context.
+  #[cfg(all(
+    any(target_os = "linux", target_os = "android"),
+    target_arch = "x86_64"
+  ))]
+  pub(in crate::detours) unsafe fn program_counter(context: *mut ucontext_t) -> *mut usize {
+    &mut (*context).uc_mcontext.gregs[libc::REG_RIP as usize] as *mut _ as *mut usize
+  }
+
+  #[cfg(all(any(target_os = "linux", target_os = "android"), target_arch = "x86"))]
+  pub(in crate::detours) unsafe fn program_counter(context: *mut ucontext_t) -> *mut usize {
+    &mut (*context).uc_mcontext.gregs[libc::REG_EIP as usize] as *mut _ as *mut usize
+  }
+
+  #[cfg(target_os = "macos")]
+  pub(in crate::detours) unsafe fn program_counter(context: *mut ucontext_t) -> *mut usize {
+    &mut (*(*context).uc_mcontext).__ss.__rip as *mut _ as *mut usize
+  }
+
+  /// Returns the location of the flags register within a signal context.
+  #[cfg(any(target_os = "linux", target_os = "android"))]
+  unsafe fn flags(context: *mut ucontext_t) -> *mut usize {
+    &mut (*context).uc_mcontext.gregs[libc::REG_EFL as usize] as *mut _ as *mut usize
+  }
+}
+
+#[cfg(windows)]
+mod handler {
+  use super::{lookup, Kind};
+  use crate::detours::guard::{self, Fault, TRAP_FLAG};
+  use crate::detours::hardware;
+  use crate::error::{Error, Result};
+  use core::ptr;
+  use core::sync::atomic::{AtomicPtr, Ordering};
+  use winapi::ctypes::c_void;
+  use winapi::um::errhandlingapi::{AddVectoredExceptionHandler, RemoveVectoredExceptionHandler};
+  use winapi::um::minwinbase::{EXCEPTION_BREAKPOINT, EXCEPTION_GUARD_PAGE, EXCEPTION_SINGLE_STEP};
+  use winapi::um::winnt::{EXCEPTION_POINTERS, LONG};
+  use winapi::vc::excpt::{EXCEPTION_CONTINUE_EXECUTION, EXCEPTION_CONTINUE_SEARCH};
+
+  /// The handle of the installed exception handler.
+  static HANDLE: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
+
+  /// Installs the vectored exception handler, before any others.
+  pub unsafe fn install() -> Result<()> {
+    let handle = AddVectoredExceptionHandler(1, Some(handler));
+    if handle.is_null() {
+      Err(Error::OutOfMemory)?;
+    }
+
+    HANDLE.store(handle, Ordering::SeqCst);
+    Ok(())
+  }
+
+  /// Removes the vectored exception handler.
+  pub unsafe fn uninstall() {
+    RemoveVectoredExceptionHandler(HANDLE.swap(ptr::null_mut(), Ordering::SeqCst));
+  }
+
+  /// Redirects exceptions belonging to a detour, and lets others continue the
+  /// search for a handler.
+  unsafe extern "system" fn handler(info: *mut EXCEPTION_POINTERS) -> LONG {
+    let record = &*(*info).ExceptionRecord;
+    let context = &mut *(*info).ContextRecord;
+
+    // The exception address is that of the target, for either kind of
+    // breakpoint
+    let address = record.ExceptionAddress as usize;
+    let detour = match record.ExceptionCode {
+      EXCEPTION_BREAKPOINT => lookup(Kind::Software, address),
+      EXCEPTION_SINGLE_STEP if guard::step_completed() => {
+        context.EFlags &= !(TRAP_FLAG as u32);
+        return EXCEPTION_CONTINUE_EXECUTION;
+      },
+      EXCEPTION_SINGLE_STEP => lookup(Kind::Hardware, address),
+      EXCEPTION_GUARD_PAGE => match guard::fault(address, record.ExceptionInformation[1]) {
+        Some(Fault::Redirect(detour)) => Some(detour),
+        Some(Fault::Step) => {
+          context.EFlags |= TRAP_FLAG as u32;
+          return EXCEPTION_CONTINUE_EXECUTION;
+        },
+        None => None,
+      },
+      hardware::SYNCHRONIZE => {
+        hardware::synchronize(context);
+        return EXCEPTION_CONTINUE_EXECUTION;
+      },
+      _ => None,
+    };
+
+    match detour {
+      Some(detour) => {
+        #[cfg(target_arch = "x86_64")]
+        {
+          context.Rip = detour as u64;
+        }
+        #[cfg(target_arch = "x86")]
+        {
+          context.Eip = detour as u32;
+        }
+        EXCEPTION_CONTINUE_EXECUTION
+      },
+      None => EXCEPTION_CONTINUE_SEARCH,
+    }
+  }
+}
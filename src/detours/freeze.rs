@@ -0,0 +1,787 @@
+use crate::error::Result;
+use crate::sync::{Mutex, MutexGuard};
+use core::fmt;
+use std::vec::Vec;
+
+/// Serializes freezes, since a frozen thread cannot freeze the others.
+static LOCK: Mutex<()> = Mutex::new(());
+
+/// The number of threads accommodated by a freeze, before it's restarted with
+/// a larger capacity.
+const INITIAL_CAPACITY: usize = 64;
+
+/// A guard suspending all other threads of the process, which are resumed once
+/// it's dropped (including whilst unwinding).
+///
+/// Threads are enumerated repeatedly until no unfrozen thread remains, which
+/// includes those created by threads before they were frozen. The
+/// instruction pointer of each thread is captured as it's frozen, allowing
+/// callers to implement their own policies when modifying code (e.g moving
+/// threads out of a patch area, or retrying later).
+///
+/// - On Windows, each thread of a converged toolhelp snapshot is suspended
+///   using `SuspendThread`. Its context is available through the guard.
+/// - On Linux, each thread is sent a signal (`SIGPWR` by default, see
+///   [set_signal](#method.set_signal)), whose handler parks the thread until
+///   it's thawed. This is best-effort; threads blocking the signal, or not
+///   stopping within a second, are skipped. Subsequent freezes skip such a
+///   thread immediately, until it has handled the signal or exited.
+///
+/// Memory is reserved before any thread is frozen, since a frozen thread may
+/// hold the allocator's lock. For the same reason, the current thread should
+/// neither allocate nor acquire locks shared with other threads whilst the
+/// guard exists. Freezes are serialized; creating a freeze whilst holding one
+/// deadlocks.
+///
+/// # Example
+///
+/// ```rust
+/// # use detour::Result;
+/// use detour::ThreadFreeze;
+///
+/// # fn main() -> Result<()> {
+/// let freeze = ThreadFreeze::new()?;
+/// for thread in freeze.threads() {
+///   assert_ne!(thread.instruction_pointer(), 0);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct ThreadFreeze {
+  threads: Vec<FrozenThread>,
+  _guard: MutexGuard<'static, ()>,
+}
+
+/// A thread suspended by a [ThreadFreeze](./struct.ThreadFreeze.html).
+pub struct FrozenThread {
+  id: usize,
+  instruction_pointer: usize,
+  native: native::Thread,
+}
+
+impl ThreadFreeze {
+  /// Freezes all threads of the process, except the current one.
+  pub fn new() -> Result<Self> {
+    let mut freeze = ThreadFreeze {
+      threads: Vec::new(),
+      _guard: LOCK.lock(),
+    };
+
+    native::prepare()?;
+    let current = native::current_thread();
+    let mut capacity = INITIAL_CAPACITY;
+    let mut ids = Vec::new();
+    // Threads that exited, or did not stop in time
+    let mut skipped = Vec::new();
+
+    'restart: loop {
+      ids.reserve(capacity);
+      skipped.reserve(capacity);
+      freeze.threads.reserve(capacity);
+      native::reserve(capacity);
+
+      loop {
+        ids.clear();
+        if !native::thread_ids(&mut ids)? {
+          // The capacity cannot be increased whilst threads are frozen
+          freeze.thaw();
+          capacity = ids.capacity() * 2;
+          continue 'restart;
+        }
+
+        let mut converged = true;
+        for &id in &ids {
+          let is_frozen = freeze.threads.iter().any(|thread| thread.id == id);
+          if id == current || is_frozen || skipped.contains(&id) {
+            continue;
+          }
+
+          converged = false;
+          match unsafe { native::suspend(id)? } {
+            Some(thread) => freeze.threads.push(thread),
+            // Skipped threads are never signaled again, hence listed once
+            None if skipped.len() < skipped.capacity() => skipped.push(id),
+            None => {
+              freeze.thaw();
+              capacity = skipped.capacity() * 2;
+              continue 'restart;
+            },
+          }
+        }
+
+        if converged {
+          return Ok(freeze);
+        }
+      }
+    }
+  }
+
+  /// Changes the signal stopping each thread on Linux (`SIGPWR` by default),
+  /// e.g if it's used by a garbage collector.
+  ///
+  /// The signal can only be changed before the first freeze; returns
+  /// `Error::AlreadyInitialized` otherwise.
+  #[cfg(any(target_os = "linux", target_os = "android"))]
+  pub fn set_signal(signal: i32) -> Result<()> {
+    let _guard = LOCK.lock();
+    native::set_signal(signal)
+  }
+
+  /// Returns the frozen threads.
+  pub fn threads(&self) -> &[FrozenThread] {
+    &self.threads
+  }
+
+  /// Returns the frozen threads, allowing their state to be modified.
+  pub fn threads_mut(&mut self) -> &mut [FrozenThread] {
+    &mut self.threads
+  }
+
+  /// Resumes all frozen threads.
+  fn thaw(&mut self) {
+    for thread in self.threads.drain(..) {
+      unsafe { native::resume(thread.native) };
+    }
+  }
+}
+
+impl Drop for ThreadFreeze {
+  /// Resumes all frozen threads.
+  fn drop(&mut self) {
+    self.thaw();
+  }
+}
+
+impl fmt::Debug for ThreadFreeze {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct("ThreadFreeze")
+      .field("threads", &self.threads)
+      .finish()
+  }
+}
+
+impl FrozenThread {
+  /// Returns the identifier assigned to the thread by the operating system.
+  pub fn id(&self) -> usize {
+    self.id
+  }
+
+  /// Returns the address of the instruction the thread resumes at.
+  pub fn instruction_pointer(&self) -> usize {
+    self.instruction_pointer
+  }
+
+  /// Changes the address of the instruction the thread resumes at.
+  ///
+  /// On Linux, the address is applied once the thread is thawed.
+  pub unsafe fn set_instruction_pointer(&mut self, address: usize) -> Result<()> {
+    self.native.set_instruction_pointer(address)?;
+    self.instruction_pointer = address;
+    Ok(())
+  }
+}
+
+#[cfg(windows)]
+impl FrozenThread {
+  /// Returns the thread's handle, which is valid whilst it's frozen.
+  pub fn handle(&self) -> winapi::um::winnt::HANDLE {
+    self.native.handle()
+  }
+
+  /// Retrieves the thread's context, given the flags of the parts to
+  /// retrieve (e.g `CONTEXT_DEBUG_REGISTERS`).
+  pub fn context(&self, flags: u32) -> Result<winapi::um::winnt::CONTEXT> {
+    self.native.context(flags)
+  }
+
+  /// Replaces the parts of the thread's context denoted by its flags.
+  pub unsafe fn set_context(&mut self, context: &winapi::um::winnt::CONTEXT) -> Result<()> {
+    self.native.set_context(context)?;
+    self.instruction_pointer = native::instruction_pointer(context);
+    Ok(())
+  }
+}
+
+impl fmt::Debug for FrozenThread {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "FrozenThread {{ id: {}, instruction_pointer: {:#x} }}",
+      self.id, self.instruction_pointer
+    )
+  }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod native {
+  use super::FrozenThread;
+  use crate::detours::exception::program_counter;
+  use crate::error::{Error, OsError, Result};
+  use core::cell::UnsafeCell;
+  use core::mem::{self, MaybeUninit};
+  use core::ptr;
+  use core::sync::atomic::{AtomicBool, AtomicI32, AtomicPtr, AtomicU32, AtomicUsize, Ordering};
+  use libc::{c_int, c_void, siginfo_t, ucontext_t};
+  use std::boxed::Box;
+  use std::time::{Duration, Instant};
+  use std::vec::Vec;
+
+  /// The signal stopping each thread.
+  static SIGNAL: AtomicI32 = AtomicI32::new(libc::SIGPWR);
+
+  /// The time a thread is given to stop, before it's skipped.
+  const TIMEOUT: Duration = Duration::from_secs(1);
+
+  // The states of a slot
+  const FREE: u32 = 0;
+  const SIGNALED: u32 = 1;
+  const STOPPED: u32 = 2;
+  const THAWED: u32 = 3;
+  const ABANDONED: u32 = 4;
+
+  /// The state of a thread being frozen, shared with its signal handler.
+  ///
+  /// Slots are never deallocated, since a handler may access one at any time.
+  /// Those of threads that did not stop in time are abandoned, since their
+  /// signal may still be delivered, and reused once it has been handled or
+  /// the thread has exited.
+  struct Slot {
+    thread: AtomicI32,
+    state: AtomicU32,
+    instruction_pointer: AtomicUsize,
+    resume_at: AtomicUsize,
+    next: *mut Slot,
+  }
+
+  /// The list of all slots (only pushed to whilst holding the lock).
+  static SLOTS: AtomicPtr<Slot> = AtomicPtr::new(ptr::null_mut());
+
+  /// Whether the signal handler has been installed.
+  static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+  /// The signal action replaced by the handler.
+  struct Previous(UnsafeCell<MaybeUninit<libc::sigaction>>);
+
+  unsafe impl Sync for Previous {}
+
+  static PREVIOUS: Previous = Previous(UnsafeCell::new(MaybeUninit::uninit()));
+
+  /// A thread parked within the signal handler.
+  pub struct Thread(&'static Slot);
+
+  impl Thread {
+    pub fn set_instruction_pointer(&mut self, address: usize) -> Result<()> {
+      self.0.resume_at.store(address, Ordering::SeqCst);
+      Ok(())
+    }
+  }
+
+  /// Changes the signal, unless the handler has been installed.
+  pub fn set_signal(signal: c_int) -> Result<()> {
+    if INSTALLED.load(Ordering::SeqCst) {
+      return Err(Error::AlreadyInitialized);
+    }
+
+    SIGNAL.store(signal, Ordering::SeqCst);
+    Ok(())
+  }
+
+  /// Installs the signal handler, unless already installed.
+  ///
+  /// The handler remains installed, since signals sent to threads that did
+  /// not stop in time may be delivered at any time.
+  pub fn prepare() -> Result<()> {
+    if INSTALLED.load(Ordering::SeqCst) {
+      return Ok(());
+    }
+
+    unsafe {
+      let mut action: libc::sigaction = mem::zeroed();
+      action.sa_sigaction = handler as *const () as usize;
+      action.sa_flags = libc::SA_SIGINFO | libc::SA_ONSTACK | libc::SA_RESTART;
+      libc::sigfillset(&mut action.sa_mask);
+
+      let signal = SIGNAL.load(Ordering::SeqCst);
+      if libc::sigaction(signal, &action, (*PREVIOUS.0.get()).as_mut_ptr()) != 0 {
+        Err(Error::PermissionDenied {
+          operation: "sigaction",
+          error: last_error(),
+        })?;
+      }
+    }
+
+    INSTALLED.store(true, Ordering::SeqCst);
+    Ok(())
+  }
+
+  /// Returns the identifier of the current thread.
+  pub fn current_thread() -> usize {
+    unsafe { libc::syscall(libc::SYS_gettid) as usize }
+  }
+
+  /// Ensures that at least `count` slots are free.
+  pub fn reserve(count: usize) {
+    // An exited thread never handles its signal
+    for slot in slots() {
+      let id = slot.thread.load(Ordering::SeqCst) as usize;
+      if slot.state.load(Ordering::SeqCst) == ABANDONED && unsafe { signal(id, 0) } != 0 {
+        let _ = slot
+          .state
+          .compare_exchange(ABANDONED, FREE, Ordering::SeqCst, Ordering::SeqCst);
+      }
+    }
+
+    let free = slots()
+      .filter(|slot| slot.state.load(Ordering::SeqCst) == FREE)
+      .count();
+
+    for _ in free..count {
+      let slot = Box::new(Slot {
+        thread: AtomicI32::new(0),
+        state: AtomicU32::new(FREE),
+        instruction_pointer: AtomicUsize::new(0),
+        resume_at: AtomicUsize::new(0),
+        next: SLOTS.load(Ordering::SeqCst),
+      });
+      SLOTS.store(Box::into_raw(slot), Ordering::SeqCst);
+    }
+  }
+
+  /// Lists the identifiers of the process's threads, without allocating.
+  ///
+  /// Returns false if they exceed the list's capacity.
+  pub fn thread_ids(ids: &mut Vec<usize>) -> Result<bool> {
+    #[repr(C, align(8))]
+    struct Buffer([u8; 4096]);
+
+    unsafe {
+      let path = b"/proc/self/task\0";
+      let directory = libc::open(
+        path.as_ptr() as *const _,
+        libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC,
+      );
+
+      if directory < 0 {
+        Err(Error::PermissionDenied {
+          operation: "open",
+          error: last_error(),
+        })?;
+      }
+
+      let mut buffer = Buffer([0; 4096]);
+      let mut fits = true;
+
+      loop {
+        let size = libc::syscall(
+          libc::SYS_getdents64,
+          directory,
+          buffer.0.as_mut_ptr(),
+          buffer.0.len(),
+        );
+
+        if size <= 0 {
+          libc::close(directory);
+          return if size < 0 {
+            Err(Error::PermissionDenied {
+              operation: "getdents64",
+              error: last_error(),
+            })
+          } else {
+            Ok(fits)
+          };
+        }
+
+        // Each entry (`linux_dirent64`) consists of the inode, offset, record
+        // length and type, followed by the name
+        let mut offset = 0;
+        while offset < size as usize {
+          let entry = &buffer.0[offset..];
+          let length = u16::from_ne_bytes([entry[16], entry[17]]) as usize;
+          let name = &entry[19..length];
+          let name = &name[..name.iter().position(|&c| c == 0).unwrap_or(name.len())];
+
+          if let Some(id) = parse(name) {
+            if ids.len() < ids.capacity() {
+              ids.push(id);
+            } else {
+              fits = false;
+            }
+          }
+          offset += length;
+        }
+      }
+    }
+  }
+
+  /// Stops a thread, returning `None` if it has exited or did not stop in
+  /// time.
+  pub unsafe fn suspend(id: usize) -> Result<Option<FrozenThread>> {
+    // A thread that has yet to handle a previous signal is skipped immediately
+    if slots().any(|slot| {
+      slot.state.load(Ordering::SeqCst) == ABANDONED
+        && slot.thread.load(Ordering::SeqCst) == id as i32
+    }) {
+      return Ok(None);
+    }
+
+    let slot = match slots().find(|slot| slot.state.load(Ordering::SeqCst) == FREE) {
+      Some(slot) => slot,
+      // Only slots of abandoned threads are missing
+      None => {
+        reserve(1);
+        return suspend(id);
+      },
+    };
+
+    slot.thread.store(id as i32, Ordering::SeqCst);
+    slot.resume_at.store(0, Ordering::SeqCst);
+    slot.state.store(SIGNALED, Ordering::SeqCst);
+
+    if signal(id, SIGNAL.load(Ordering::SeqCst)) != 0 {
+      slot.state.store(FREE, Ordering::SeqCst);
+      return Ok(None);
+    }
+
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+      if slot.state.load(Ordering::SeqCst) == STOPPED {
+        return Ok(Some(FrozenThread {
+          id,
+          instruction_pointer: slot.instruction_pointer.load(Ordering::SeqCst),
+          native: Thread(slot),
+        }));
+      }
+
+      // An exited thread never handles the signal, therefore its slot can be
+      // reused immediately
+      let (exited, expired) = (signal(id, 0) != 0, Instant::now() >= deadline);
+      if exited || expired {
+        let state = if exited { FREE } else { ABANDONED };
+        if slot
+          .state
+          .compare_exchange(SIGNALED, state, Ordering::SeqCst, Ordering::SeqCst)
+          .is_ok()
+        {
+          return Ok(None);
+        }
+        continue;
+      }
+
+      libc::sched_yield();
+    }
+  }
+
+  /// Resumes a stopped thread.
+  pub unsafe fn resume(thread: Thread) {
+    thread.0.state.store(THAWED, Ordering::SeqCst);
+    futex(
+      &thread.0.state,
+      libc::FUTEX_WAKE | libc::FUTEX_PRIVATE_FLAG,
+      i32::MAX as u32,
+    );
+  }
+
+  /// Parks the current thread if it's being frozen, and forwards the signal to
+  /// the previous action otherwise.
+  ///
+  /// The slot of a thread that did not stop in time is released instead.
+  unsafe extern "C" fn handler(signal: c_int, info: *mut siginfo_t, context: *mut c_void) {
+    let saved = *errno();
+    let id = current_thread() as i32;
+
+    let slot = slots().find(|slot| {
+      matches!(slot.state.load(Ordering::SeqCst), SIGNALED | ABANDONED)
+        && slot.thread.load(Ordering::SeqCst) == id
+    });
+
+    match slot {
+      Some(slot) => park(slot, context as *mut ucontext_t),
+      None => forward(signal, info, context),
+    }
+    *errno() = saved;
+  }
+
+  /// Parks the current thread until it's thawed.
+  unsafe fn park(slot: &Slot, context: *mut ucontext_t) {
+    let pc = program_counter(context);
+    slot.instruction_pointer.store(*pc, Ordering::SeqCst);
+
+    if slot
+      .state
+      .compare_exchange(SIGNALED, STOPPED, Ordering::SeqCst, Ordering::SeqCst)
+      .is_err()
+    {
+      // The thread was abandoned, and is no longer being waited for
+      let _ = slot
+        .state
+        .compare_exchange(ABANDONED, FREE, Ordering::SeqCst, Ordering::SeqCst);
+      return;
+    }
+
+    while slot.state.load(Ordering::SeqCst) == STOPPED {
+      futex(
+        &slot.state,
+        libc::FUTEX_WAIT | libc::FUTEX_PRIVATE_FLAG,
+        STOPPED,
+      );
+    }
+
+    let address = slot.resume_at.load(Ordering::SeqCst);
+    if address != 0 {
+      *pc = address;
+    }
+    slot.state.store(FREE, Ordering::SeqCst);
+  }
+
+  /// Forwards a signal to the action replaced by the handler.
+  ///
+  /// Unless a handler was installed, the signal is ignored.
+  unsafe fn forward(signal: c_int, info: *mut siginfo_t, context: *mut c_void) {
+    let previous = &*(*PREVIOUS.0.get()).as_ptr();
+
+    match previous.sa_sigaction {
+      libc::SIG_IGN | libc::SIG_DFL => (),
+      action if previous.sa_flags & libc::SA_SIGINFO != 0 => {
+        let action: extern "C" fn(c_int, *mut siginfo_t, *mut c_void) = mem::transmute(action);
+        action(signal, info, context);
+      },
+      action => {
+        let action: extern "C" fn(c_int) = mem::transmute(action);
+        action(signal);
+      },
+    }
+  }
+
+  /// Returns an iterator over all slots.
+  fn slots() -> impl Iterator<Item = &'static Slot> {
+    let mut slot = SLOTS.load(Ordering::SeqCst);
+    core::iter::from_fn(move || {
+      let current = unsafe { slot.as_ref()? };
+      slot = current.next;
+      Some(current)
+    })
+  }
+
+  /// Parses the decimal identifier of a thread.
+  fn parse(name: &[u8]) -> Option<usize> {
+    if name.is_empty() {
+      return None;
+    }
+
+    name.iter().try_fold(0usize, |id, &c| match c {
+      b'0'..=b'9' => Some(id * 10 + (c - b'0') as usize),
+      _ => None,
+    })
+  }
+
+  /// Sends a signal to a thread of the process.
+  unsafe fn signal(id: usize, signal: c_int) -> libc::c_long {
+    libc::syscall(libc::SYS_tgkill, libc::getpid(), id as c_int, signal)
+  }
+
+  /// Waits on, or wakes threads waiting on, a state.
+  unsafe fn futex(state: &AtomicU32, operation: c_int, value: u32) {
+    libc::syscall(
+      libc::SYS_futex,
+      state as *const AtomicU32,
+      operation,
+      value,
+      ptr::null::<libc::timespec>(),
+    );
+  }
+
+  /// Returns the location of the current thread's `errno`.
+  unsafe fn errno() -> *mut c_int {
+    #[cfg(target_os = "linux")]
+    return libc::__errno_location();
+
+    #[cfg(target_os = "android")]
+    return libc::__errno();
+  }
+
+  /// Returns the error of the last failed operation.
+  fn last_error() -> OsError {
+    OsError(std::io::Error::last_os_error().raw_os_error().unwrap_or(0))
+  }
+}
+
+#[cfg(windows)]
+mod native {
+  use super::FrozenThread;
+  use crate::error::{Error, OsError, Result};
+  use core::mem;
+  use std::vec::Vec;
+  use winapi::shared::minwindef::FALSE;
+  use winapi::shared::winerror::ERROR_INVALID_PARAMETER;
+  use winapi::um::errhandlingapi::GetLastError;
+  use winapi::um::handleapi::{CloseHandle, INVALID_HANDLE_VALUE};
+  use winapi::um::processthreadsapi::{
+    GetCurrentProcessId, GetCurrentThreadId, GetThreadContext, OpenThread, ResumeThread,
+    SetThreadContext, SuspendThread,
+  };
+  use winapi::um::tlhelp32::{
+    CreateToolhelp32Snapshot, Thread32First, Thread32Next, TH32CS_SNAPTHREAD, THREADENTRY32,
+  };
+  use winapi::um::winnt::{
+    CONTEXT, CONTEXT_CONTROL, HANDLE, THREAD_GET_CONTEXT, THREAD_QUERY_INFORMATION,
+    THREAD_SET_CONTEXT, THREAD_SUSPEND_RESUME,
+  };
+
+  /// A handle to a suspended thread.
+  pub struct Thread(HANDLE);
+
+  impl Thread {
+    pub fn handle(&self) -> HANDLE {
+      self.0
+    }
+
+    pub fn context(&self, flags: u32) -> Result<CONTEXT> {
+      unsafe {
+        let mut context: CONTEXT = mem::zeroed();
+        context.ContextFlags = flags;
+
+        if GetThreadContext(self.0, &mut context) == FALSE {
+          Err(Error::PermissionDenied {
+            operation: "GetThreadContext",
+            error: last_error(),
+          })?;
+        }
+        Ok(context)
+      }
+    }
+
+    pub unsafe fn set_context(&mut self, context: &CONTEXT) -> Result<()> {
+      if SetThreadContext(self.0, context) == FALSE {
+        Err(Error::PermissionDenied {
+          operation: "SetThreadContext",
+          error: last_error(),
+        })?;
+      }
+      Ok(())
+    }
+
+    pub fn set_instruction_pointer(&mut self, address: usize) -> Result<()> {
+      let mut context = self.context(CONTEXT_CONTROL)?;
+      #[cfg(target_arch = "x86_64")]
+      {
+        context.Rip = address as _;
+      }
+      #[cfg(target_arch = "x86")]
+      {
+        context.Eip = address as _;
+      }
+      unsafe { self.set_context(&context) }
+    }
+  }
+
+  /// Returns the instruction pointer of a context.
+  pub fn instruction_pointer(context: &CONTEXT) -> usize {
+    #[cfg(target_arch = "x86_64")]
+    return context.Rip as usize;
+
+    #[cfg(target_arch = "x86")]
+    return context.Eip as usize;
+  }
+
+  /// Nothing needs to be prepared.
+  pub fn prepare() -> Result<()> {
+    Ok(())
+  }
+
+  /// Returns the identifier of the current thread.
+  pub fn current_thread() -> usize {
+    unsafe { GetCurrentThreadId() as usize }
+  }
+
+  /// Nothing needs to be reserved.
+  pub fn reserve(_count: usize) {}
+
+  /// Lists the identifiers of the process's threads, using a toolhelp
+  /// snapshot.
+  ///
+  /// Returns false if they exceed the list's capacity.
+  pub fn thread_ids(ids: &mut Vec<usize>) -> Result<bool> {
+    unsafe {
+      let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPTHREAD, 0);
+      if snapshot == INVALID_HANDLE_VALUE {
+        Err(Error::PermissionDenied {
+          operation: "CreateToolhelp32Snapshot",
+          error: last_error(),
+        })?;
+      }
+
+      let process = GetCurrentProcessId();
+      let mut entry: THREADENTRY32 = mem::zeroed();
+      entry.dwSize = mem::size_of::<THREADENTRY32>() as u32;
+
+      let mut fits = true;
+      let mut is_valid = Thread32First(snapshot, &mut entry) != FALSE;
+
+      while is_valid {
+        if entry.th32OwnerProcessID == process {
+          if ids.len() < ids.capacity() {
+            ids.push(entry.th32ThreadID as usize);
+          } else {
+            fits = false;
+          }
+        }
+        is_valid = Thread32Next(snapshot, &mut entry) != FALSE;
+      }
+
+      CloseHandle(snapshot);
+      Ok(fits)
+    }
+  }
+
+  /// Suspends a thread, returning `None` if it has exited.
+  pub unsafe fn suspend(id: usize) -> Result<Option<FrozenThread>> {
+    let access =
+      THREAD_SUSPEND_RESUME | THREAD_GET_CONTEXT | THREAD_SET_CONTEXT | THREAD_QUERY_INFORMATION;
+    let handle = OpenThread(access, FALSE, id as u32);
+
+    if handle.is_null() {
+      return match GetLastError() {
+        ERROR_INVALID_PARAMETER => Ok(None),
+        _ => Err(Error::PermissionDenied {
+          operation: "OpenThread",
+          error: last_error(),
+        }),
+      };
+    }
+
+    if SuspendThread(handle) == u32::MAX {
+      let error = last_error();
+      CloseHandle(handle);
+      Err(Error::PermissionDenied {
+        operation: "SuspendThread",
+        error,
+      })?;
+    }
+
+    // The suspension is asynchronous; retrieving the context ensures that the
+    // thread has been suspended
+    let thread = Thread(handle);
+    match thread.context(CONTEXT_CONTROL) {
+      Ok(context) => Ok(Some(FrozenThread {
+        id,
+        instruction_pointer: instruction_pointer(&context),
+        native: thread,
+      })),
+      Err(error) => {
+        resume(thread);
+        Err(error)
+      },
+    }
+  }
+
+  /// Resumes a suspended thread, and closes its handle.
+  pub unsafe fn resume(thread: Thread) {
+    ResumeThread(thread.0);
+    CloseHandle(thread.0);
+  }
+
+  /// Returns the error of the last failed operation.
+  fn last_error() -> OsError {
+    OsError(unsafe { GetLastError() } as i32)
+  }
+}
@@ -1,7 +1,17 @@
 use crate::arch::Detour;
-use crate::error::Result;
-use crate::{Function, HookableWith};
-use std::marker::PhantomData;
+#[cfg(feature = "std")]
+use crate::arch::Handoff;
+use crate::error::{Error, Result};
+#[cfg(feature = "latency")]
+use crate::latency::{Latency, Metric};
+use crate::{
+  pool, Function, HookCompatible, HookableWith, PatchStrategy, PreparedPatch, RelocationRecord,
+  Shims, Signature,
+};
+use core::fmt;
+use core::marker::PhantomData;
+#[cfg(feature = "libloading")]
+use std::sync::Arc;
 
 /// A type-safe detour.
 ///
@@ -11,6 +21,8 @@ use std::marker::PhantomData;
 ///
 /// ```c
 /// /// Calls the original function regardless of whether it's hooked or not.
+/// ///
+/// /// Panics if a lazy trampoline cannot be emitted (unlike `try_trampoline`).
 /// fn call(&self, T::Arguments) -> T::Output
 /// ```
 ///
@@ -45,10 +57,17 @@ use std::marker::PhantomData;
 /// # Ok(())
 /// # }
 /// ```
-#[derive(Debug)]
+///
+/// # Thread safety
+///
+/// The same as [RawDetour](./struct.RawDetour.html#thread-safety).
 pub struct GenericDetour<T: Function> {
   phantom: PhantomData<T>,
   detour: Detour,
+  #[cfg(feature = "libloading")]
+  library: Option<Arc<libloading::Library>>,
+  #[cfg(feature = "latency")]
+  latency: Latency,
 }
 
 impl<T: Function> GenericDetour<T> {
@@ -62,9 +81,130 @@ impl<T: Function> GenericDetour<T> {
     Detour::new(target.to_ptr(), detour.to_ptr()).map(|detour| GenericDetour {
       phantom: PhantomData,
       detour,
+      #[cfg(feature = "libloading")]
+      library: None,
+      #[cfg(feature = "latency")]
+      latency: Latency::new(),
+    })
+  }
+
+  /// Create a new hook given a target function and a detour function asserted
+  /// to be ABI-compatible with it (see
+  /// [HookCompatible](./trait.HookCompatible.html)), despite a different
+  /// signature.
+  pub unsafe fn new_compatible<D>(target: T, detour: D) -> Result<Self>
+  where
+    D: HookCompatible<T>,
+  {
+    Detour::new(target.to_ptr(), detour.to_ptr()).map(|detour| GenericDetour {
+      phantom: PhantomData,
+      detour,
+      #[cfg(feature = "libloading")]
+      library: None,
+      #[cfg(feature = "latency")]
+      latency: Latency::new(),
+    })
+  }
+
+  /// Create a new hook, with custom code executed on either side of the
+  /// detour.
+  pub(crate) unsafe fn with_shims<D>(target: T, detour: D, shims: Shims) -> Result<Self>
+  where
+    T: HookableWith<D>,
+    D: Function,
+  {
+    let (before_detour, before_original) = (shims.before_detour, shims.before_original);
+    Detour::with_shims(
+      target.to_ptr(),
+      detour.to_ptr(),
+      before_detour,
+      before_original,
+    )
+    .map(|detour| GenericDetour {
+      phantom: PhantomData,
+      detour,
+      #[cfg(feature = "libloading")]
+      library: None,
+      #[cfg(feature = "latency")]
+      latency: Latency::new(),
     })
   }
 
+  /// Adopts a detour handed off by another instance of the library (see
+  /// [handoff](./handoff/index.html)), whose target is of the same type.
+  #[cfg(feature = "std")]
+  pub(crate) unsafe fn adopt(handoff: &Handoff, detour: T, shims: Shims) -> Result<Self> {
+    Detour::adopt(handoff, detour.to_ptr(), shims.before_detour).map(|detour| GenericDetour {
+      phantom: PhantomData,
+      detour,
+      #[cfg(feature = "libloading")]
+      library: None,
+      #[cfg(feature = "latency")]
+      latency: Latency::new(),
+    })
+  }
+
+  /// Hands off the enabled detour to another instance of the library; it must
+  /// be leaked afterwards.
+  #[cfg(feature = "std")]
+  pub(crate) unsafe fn hand_off(&self) -> Result<Handoff> {
+    self.detour.hand_off()
+  }
+
+  /// Create a new hook for a symbol exported by a library.
+  ///
+  /// The hook retains a reference to the library, which ensures it stays
+  /// loaded for as long as the target may be patched.
+  ///
+  /// # Example
+  ///
+  /// ```no_run
+  /// # use detour::Result;
+  /// use detour::GenericDetour;
+  /// use libloading::Library;
+  /// use std::sync::Arc;
+  ///
+  /// extern "C" fn cos_detour(_: f64) -> f64 {
+  ///   0.5
+  /// }
+  ///
+  /// # fn main() -> Result<()> {
+  /// let library = Arc::new(unsafe { Library::new("libm.so.6") }.unwrap());
+  /// let hook = unsafe {
+  ///   GenericDetour::<extern "C" fn(f64) -> f64>::for_library_symbol(&library, b"cos", cos_detour)?
+  /// };
+  ///
+  /// // The library is kept loaded until the hook is dropped
+  /// drop(library);
+  /// unsafe { hook.enable()? };
+  /// # Ok(())
+  /// # }
+  /// ```
+  #[cfg(feature = "libloading")]
+  pub unsafe fn for_library_symbol<D>(
+    library: &Arc<libloading::Library>,
+    name: &[u8],
+    detour: D,
+  ) -> Result<Self>
+  where
+    T: HookableWith<D>,
+    D: Function,
+  {
+    let target = library
+      .get::<T>(name)
+      .map(|symbol| *symbol)
+      .map_err(|error| crate::Error::SymbolNotFound {
+        name: std::string::String::from_utf8_lossy(name)
+          .trim_end_matches('\0')
+          .into(),
+        error,
+      })?;
+
+    let mut hook = Self::new(target, detour)?;
+    hook.library = Some(library.clone());
+    Ok(hook)
+  }
+
   /// Enables the detour.
   pub unsafe fn enable(&self) -> Result<()> {
     self.detour.enable()
@@ -75,16 +215,152 @@ impl<T: Function> GenericDetour<T> {
     self.detour.disable()
   }
 
+  /// Disables the detour if enabled, and drops it, or returns it along with
+  /// the error.
+  ///
+  /// See [RawDetour::close](./struct.RawDetour.html#method.close).
+  #[allow(clippy::result_large_err)]
+  pub unsafe fn close(self) -> core::result::Result<(), (Self, Error)> {
+    match self.detour.disable() {
+      Ok(()) => Ok(()),
+      Err(error) => Err((self, error)),
+    }
+  }
+
   /// Returns whether the detour is enabled or not.
   pub fn is_enabled(&self) -> bool {
     self.detour.is_enabled()
   }
 
-  /// Returns a reference to the generated trampoline.
-  pub(crate) fn trampoline(&self) -> &() {
-    self.detour.trampoline()
+  /// Prepares enabling or disabling the detour, so it can be committed from a
+  /// signal handler (see [PreparedPatch](./struct.PreparedPatch.html)).
+  pub unsafe fn prepare(&self, enabled: bool) -> Result<PreparedPatch<'_>> {
+    self.detour.prepare(enabled)
+  }
+
+  /// Binds the detour to another target, e.g after reloading a module.
+  ///
+  /// See [RawDetour::swap_target](./struct.RawDetour.html#method.swap_target)
+  /// for the state of the detour on failure.
+  pub unsafe fn swap_target(&self, new_target: T) -> Result<()> {
+    self.detour.swap_target(new_target.to_ptr())
+  }
+
+  /// Returns whether the detour is bound to a target or not.
+  pub fn is_bound(&self) -> bool {
+    self.detour.is_bound()
+  }
+
+  /// Returns the generated trampoline, typed as the target function.
+  ///
+  /// Invoking the trampoline is equivalent to calling the original function,
+  /// regardless of whether the detour is enabled or not.
+  ///
+  /// Panics if a [lazy](./struct.DetourBuilder.html#method.lazy_trampoline)
+  /// trampoline cannot be emitted.
+  pub fn trampoline(&self) -> T {
+    unsafe { T::from_ptr(self.detour.trampoline() as *const ()) }
+  }
+
+  /// Returns the generated trampoline, or the error emitting a
+  /// [lazy](./struct.DetourBuilder.html#method.lazy_trampoline) trampoline.
+  pub fn try_trampoline(&self) -> Result<T> {
+    let trampoline = self.detour.try_trampoline()?;
+    Ok(unsafe { T::from_ptr(trampoline as *const ()) })
+  }
+
+  /// Calls the original function through the trampoline, with its arguments
+  /// as a tuple.
+  ///
+  /// Unlike `call`, it's available to code that's generic over the function
+  /// type, e.g to forward the arguments received by a closure.
+  ///
+  /// # Safety
+  ///
+  /// The function's contract must be upheld, if it's an unsafe function.
+  pub unsafe fn call_tuple(&self, arguments: T::Arguments) -> T::Output {
+    let original = self.trampoline();
+    self.__call_original(|| original.call_tuple(arguments))
+  }
+
+  /// Returns statistics for the pool region containing the trampoline.
+  ///
+  /// Returns `None` if the trampoline was allocated by a custom allocator, or
+  /// resides within a hot-patchable target.
+  pub fn region(&self) -> Option<pool::RegionStats> {
+    self.detour.region()
+  }
+
+  /// Returns a record for each instruction relocated to the trampoline.
+  pub fn trampoline_map(&self) -> &[RelocationRecord] {
+    self.detour.trampoline_map()
+  }
+
+  /// Returns how the target is redirected.
+  pub fn strategy(&self) -> PatchStrategy {
+    self.detour.strategy()
+  }
+
+  /// Returns the system call number (SSN) loaded by the target, if it's a
+  /// Windows system call stub (x64 only).
+  pub fn syscall_number(&self) -> Option<u32> {
+    self.detour.syscall_number()
+  }
+
+  /// Returns the address of the target.
+  pub fn target_address(&self) -> *const () {
+    self.detour.target()
+  }
+
+  /// Returns the address of the detour.
+  pub fn detour_address(&self) -> *const () {
+    self
+      .detour
+      .destination()
+      .expect("destination of a generic detour")
+  }
+
+  /// Returns the address of the trampoline.
+  pub fn trampoline_address(&self) -> Option<*const ()> {
+    self.detour.trampoline_address()
+  }
+
+  /// Returns the size of the code written to the patch area when enabled.
+  pub fn patch_size(&self) -> usize {
+    self.detour.patch_size()
+  }
+
+  /// Returns the signature of the target, for diagnostics.
+  pub fn signature(&self) -> Signature {
+    Signature::of::<T>()
+  }
+
+  /// Calls the original function through the trampoline.
+  #[inline]
+  pub(crate) fn __call_original<R>(&self, call: impl FnOnce() -> R) -> R {
+    #[cfg(feature = "latency")]
+    return self.latency.measure(Metric::Original, call);
+
+    #[cfg(not(feature = "latency"))]
+    call()
+  }
+
+  /// Returns the latency recorder of the detour.
+  ///
+  /// Only calls of the original function (through `call`) are measured.
+  #[cfg(feature = "latency")]
+  pub fn latency(&self) -> &Latency {
+    &self.latency
+  }
+}
+
+impl<T: Function> fmt::Debug for GenericDetour<T> {
+  /// Output the addresses of the detour, and whether it's enabled or not.
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    self.detour.debug_struct(f, "GenericDetour").finish()
   }
 }
 
+// The underlying detour is synchronized, and `T` is a function pointer.
 unsafe impl<T: Function> Send for GenericDetour<T> {}
 unsafe impl<T: Function> Sync for GenericDetour<T> {}
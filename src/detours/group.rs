@@ -0,0 +1,190 @@
+use super::raw::{disable_all, enable_all, RawDetour};
+use crate::error::Result;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// A collection of named raw detours, enabled and disabled at once.
+///
+/// The detours are toggled using [enable_all](./fn.enable_all.html) and
+/// [disable_all](./fn.disable_all.html), in the order they were added, and
+/// are dropped along with the group.
+///
+/// # Example
+///
+/// ```rust
+/// # use detour::Result;
+/// use detour::{DetourGroup, RawDetour};
+///
+/// #[inline(never)]
+/// extern "C" fn add5(val: i32) -> i32 {
+///   unsafe { std::ptr::read_volatile(&val) + 5 }
+/// }
+///
+/// #[inline(never)]
+/// extern "C" fn sub5(val: i32) -> i32 {
+///   unsafe { std::ptr::read_volatile(&val) - 5 }
+/// }
+///
+/// extern "C" fn identity(val: i32) -> i32 {
+///   val
+/// }
+///
+/// # fn main() -> Result<()> {
+/// let mut group = DetourGroup::new();
+/// group.insert("add5", unsafe { RawDetour::new(add5 as *const (), identity as *const ())? });
+/// group.insert("sub5", unsafe { RawDetour::new(sub5 as *const (), identity as *const ())? });
+///
+/// unsafe { group.enable()? };
+/// assert_eq!((add5(10), sub5(10)), (10, 10));
+/// assert!(group.get("add5").unwrap().is_enabled());
+///
+/// unsafe { group.disable()? };
+/// assert_eq!((add5(10), sub5(10)), (15, 5));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct DetourGroup {
+  detours: Vec<(String, RawDetour)>,
+}
+
+impl DetourGroup {
+  /// Constructs an empty group.
+  pub fn new() -> Self {
+    DetourGroup::default()
+  }
+
+  /// Detours the functions exported by a loaded module, whose names match a
+  /// predicate.
+  ///
+  /// For each matching function, `factory` is invoked with its name and
+  /// address, returning the detour to use, or `None` to skip it. Each
+  /// matching export that cannot be detoured is returned along with its error,
+  /// in the order of the module's symbol table; this includes data exports
+  /// (`Error::NotExecutable`) and forwarded exports
+  /// (`Error::ForwardedExport`), for which `factory` is not invoked. The
+  /// detours are disabled; see [exports](./os/fn.exports.html) for how the
+  /// module is identified.
+  ///
+  /// # Example
+  ///
+  /// ```rust
+  /// # #[cfg(target_os = "linux")]
+  /// # fn main() -> detour::Result<()> {
+  /// use detour::DetourGroup;
+  ///
+  /// extern "C" fn distance(value: i64) -> i64 {
+  ///   value.abs()
+  /// }
+  ///
+  /// let (group, errors) = unsafe {
+  ///   DetourGroup::hook_exports(
+  ///     "libc.so.6",
+  ///     |name| name == "labs" || name == "llabs",
+  ///     |_name, _address| Some(distance as *const ()),
+  ///   )?
+  /// };
+  ///
+  /// assert!(errors.is_empty());
+  /// assert_eq!(group.len(), 2);
+  /// # Ok(())
+  /// # }
+  /// # #[cfg(not(target_os = "linux"))]
+  /// # fn main() {}
+  /// ```
+  #[cfg(all(
+    feature = "std",
+    any(windows, target_os = "linux", target_os = "android")
+  ))]
+  pub unsafe fn hook_exports<P, F>(
+    module: &str,
+    mut predicate: P,
+    mut factory: F,
+  ) -> Result<(Self, Vec<(String, crate::Error)>)>
+  where
+    P: FnMut(&str) -> bool,
+    F: FnMut(&str, *const ()) -> Option<*const ()>,
+  {
+    use crate::os::{self, ExportKind};
+    use crate::Error;
+
+    let mut group = DetourGroup::new();
+    let mut errors = Vec::new();
+
+    for export in os::exports(module)? {
+      if !predicate(&export.name) {
+        continue;
+      }
+
+      let result = match export.kind {
+        ExportKind::Function => match factory(&export.name, export.address) {
+          Some(detour) => RawDetour::new(export.address, detour).map(Some),
+          None => Ok(None),
+        },
+        ExportKind::Data => Err(Error::NotExecutable),
+        ExportKind::Forwarded(forwarder) => Err(Error::ForwardedExport { forwarder }),
+      };
+
+      match result {
+        Ok(Some(detour)) => group.insert(export.name, detour),
+        Ok(None) => (),
+        Err(error) => errors.push((export.name, error)),
+      }
+    }
+
+    Ok((group, errors))
+  }
+
+  /// Adds a detour to the group.
+  pub fn insert<S: Into<String>>(&mut self, name: S, detour: RawDetour) {
+    self.detours.push((name.into(), detour));
+  }
+
+  /// Enables every detour of the group at once.
+  pub unsafe fn enable(&self) -> Result<()> {
+    enable_all(&self.detours())
+  }
+
+  /// Disables every detour of the group at once.
+  pub unsafe fn disable(&self) -> Result<()> {
+    disable_all(&self.detours())
+  }
+
+  /// Returns whether every detour of the group is enabled, i.e `false` for
+  /// an empty group.
+  pub fn is_enabled(&self) -> bool {
+    !self.is_empty() && self.detours.iter().all(|(_, detour)| detour.is_enabled())
+  }
+
+  /// Returns the first detour with a name, if any.
+  pub fn get(&self, name: &str) -> Option<&RawDetour> {
+    self
+      .detours
+      .iter()
+      .find(|(other, _)| other == name)
+      .map(|(_, detour)| detour)
+  }
+
+  /// Returns an iterator over the names and detours, in the order added.
+  pub fn iter(&self) -> impl Iterator<Item = (&str, &RawDetour)> {
+    self
+      .detours
+      .iter()
+      .map(|(name, detour)| (name.as_str(), detour))
+  }
+
+  /// Returns the number of detours within the group.
+  pub fn len(&self) -> usize {
+    self.detours.len()
+  }
+
+  /// Returns whether the group is empty.
+  pub fn is_empty(&self) -> bool {
+    self.detours.is_empty()
+  }
+
+  /// Returns references to the detours, in the order added.
+  fn detours(&self) -> Vec<&RawDetour> {
+    self.detours.iter().map(|(_, detour)| detour).collect()
+  }
+}
@@ -0,0 +1,389 @@
+use super::exception::{self, Kind, Slot};
+use crate::arch::{memory, Detour, Trampoline};
+use crate::error::{Error, Result};
+use crate::sync::Mutex;
+use crate::{os, pic};
+use core::cell::Cell;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+use core::{fmt, ptr};
+use std::boxed::Box;
+
+/// The trap flag, single-stepping a thread when set.
+pub(crate) const TRAP_FLAG: usize = 1 << 8;
+
+/// A detour redirecting a target by guarding the page containing it.
+///
+/// When enabled, the page containing the target is guarded (on Windows using
+/// `PAGE_GUARD`, elsewhere by revoking its execute permission), and the shared
+/// exception handler of [breakpoint detours](./struct.BreakpointDetour.html)
+/// redirects threads executing the target to the detour. The target's code is
+/// never modified, nor is any debug register used.
+///
+/// # Performance
+///
+/// All code on the guarded page is affected, not only the target. Any other
+/// instruction executed on the page (including the remainder of the original
+/// function, when invoked through the trampoline) raises an exception, and is
+/// single-stepped with the page temporarily unguarded. On Windows, reading
+/// the page does the same. Code executed frequently should therefore not
+/// share a page with the target.
+///
+/// Whilst a thread is single-stepped, the page is unguarded for all threads;
+/// calls to the target made concurrently may not be intercepted. The page is
+/// guarded again once no thread is single-stepped.
+///
+/// # Interaction
+///
+/// A guarded page must not contain the target of a breakpoint or hardware
+/// breakpoint detour, and the same debugger limitations apply. Multiple page
+/// guard detours may share a page.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use detour::Result;
+/// use detour::PageGuardDetour;
+/// use std::mem;
+///
+/// #[inline(never)]
+/// extern "C" fn add5(val: i32) -> i32 {
+///   unsafe { std::ptr::read_volatile(&val) + 5 }
+/// }
+///
+/// extern "C" fn add10(val: i32) -> i32 {
+///   val + 10
+/// }
+///
+/// # fn main() -> Result<()> {
+/// let hook = unsafe { PageGuardDetour::new(add5 as *const (), add10 as *const ())? };
+///
+/// unsafe { hook.enable()? };
+/// assert_eq!(add5(5), 15);
+///
+/// let original: extern "C" fn(i32) -> i32 = unsafe { mem::transmute(hook.trampoline()) };
+/// assert_eq!(original(5), 10);
+///
+/// unsafe { hook.disable()? };
+/// assert_eq!(add5(5), 10);
+/// # Ok(())
+/// # }
+/// ```
+pub struct PageGuardDetour {
+  target: *const (),
+  detour: *const (),
+  trampoline: Trampoline,
+  page: &'static Page,
+  slot: Mutex<Option<&'static Slot>>,
+}
+
+impl PageGuardDetour {
+  /// Constructs a new page guard detour.
+  ///
+  /// The hook is disabled by default.
+  pub unsafe fn new(target: *const (), detour: *const ()) -> Result<Self> {
+    if target.is_null() || detour.is_null() {
+      Err(Error::NullPointer)?;
+    }
+
+    if target == detour {
+      Err(Error::SameAddress)?;
+    }
+
+    let _guard = memory::LOCK.lock();
+    Detour::validate(target, detour)?;
+
+    let backend = os::backend()?;
+    let protection = backend
+      .query(target)?
+      .map(|region| region.protection)
+      .ok_or(Error::NotExecutable)?;
+
+    let size = backend.page_size();
+    let base = target as usize & !(size - 1);
+
+    Ok(PageGuardDetour {
+      trampoline: Trampoline::new_locked(target, 1, pic::CodeEmitter::new())?,
+      page: Page::acquire(base, size, native::protection(protection)),
+      slot: Mutex::new(None),
+      target,
+      detour,
+    })
+  }
+
+  /// Enables the detour.
+  pub unsafe fn enable(&self) -> Result<()> {
+    let mut slot = self.slot.lock();
+
+    if slot.is_none() {
+      *slot = Some(exception::register(
+        Kind::Guard,
+        self.target as usize,
+        self.detour as usize,
+      )?);
+      self
+        .page
+        .with_lock(|page| page.hooks.fetch_add(1, Ordering::SeqCst));
+    }
+    Ok(())
+  }
+
+  /// Disables the detour.
+  pub unsafe fn disable(&self) -> Result<()> {
+    if let Some(slot) = self.slot.lock().take() {
+      self
+        .page
+        .with_lock(|page| page.hooks.fetch_sub(1, Ordering::SeqCst));
+      exception::unregister(slot);
+    }
+    Ok(())
+  }
+
+  /// Returns whether the detour is enabled or not.
+  pub fn is_enabled(&self) -> bool {
+    self.slot.lock().is_some()
+  }
+
+  /// Returns a reference to the generated trampoline.
+  ///
+  /// The trampoline executes the target's first instruction, and continues
+  /// with the remainder of the original function (within the guarded page).
+  pub fn trampoline(&self) -> &() {
+    unsafe {
+      self
+        .trampoline
+        .address()
+        .as_ref()
+        .expect("trampoline should not be null")
+    }
+  }
+}
+
+impl Drop for PageGuardDetour {
+  /// Disables the detour, unguarding the page if no other detour guards it.
+  fn drop(&mut self) {
+    let result = unsafe { self.disable() };
+    debug_assert!(result.is_ok());
+  }
+}
+
+impl fmt::Debug for PageGuardDetour {
+  /// Output whether the detour is enabled or not.
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "PageGuardDetour {{ enabled: {}, page: {:#x}, trampoline: {:?} }}",
+      self.is_enabled(),
+      self.page.base.load(Ordering::SeqCst),
+      self.trampoline.address()
+    )
+  }
+}
+
+unsafe impl Send for PageGuardDetour {}
+unsafe impl Sync for PageGuardDetour {}
+
+/// An exception caused by accessing a guarded page.
+pub(crate) enum Fault {
+  /// The target was executed; the thread should be redirected to the detour.
+  Redirect(usize),
+  /// Other code was accessed; the thread should be single-stepped.
+  Step,
+}
+
+/// Handles an access to a guarded page, returning how the thread should
+/// proceed.
+///
+/// Returns `None` if the address is not within a guarded page. This is
+/// invoked from the exception handler, and must not allocate.
+pub(crate) fn fault(pc: usize, address: usize) -> Option<Fault> {
+  let page = pages().find(|page| page.contains(address))?;
+
+  Some(page.with_lock(|page| {
+    match exception::lookup(Kind::Guard, pc).filter(|_| pc == address) {
+      Some(detour) => Fault::Redirect(detour),
+      None => {
+        // The page is unguarded until all threads have been stepped
+        page.stepping.fetch_add(1, Ordering::SeqCst);
+        let _ = STEPPING.try_with(|stepping| stepping.set(page as *const Page));
+        Fault::Step
+      },
+    }
+  }))
+}
+
+/// Completes a single step of the current thread, guarding the page again if
+/// no other thread is stepped.
+///
+/// Returns false if the thread was not single-stepped from a guarded page.
+pub(crate) fn step_completed() -> bool {
+  let page = STEPPING
+    .try_with(|stepping| stepping.replace(ptr::null()))
+    .unwrap_or(ptr::null());
+
+  match unsafe { page.as_ref() } {
+    Some(page) => {
+      page.with_lock(|page| page.stepping.fetch_sub(1, Ordering::SeqCst));
+      true
+    },
+    None => false,
+  }
+}
+
+std::thread_local! {
+  /// The page which the current thread is single-stepped from, if any.
+  static STEPPING: Cell<*const Page> = const { Cell::new(ptr::null()) };
+}
+
+/// A page containing targets of page guard detours.
+///
+/// Pages are never released, since the handler may be executing concurrently;
+/// instead, they are reused for targets within the same page.
+struct Page {
+  base: AtomicUsize,
+  size: usize,
+  protection: usize,
+  /// The number of enabled detours guarding the page.
+  hooks: AtomicUsize,
+  /// The number of threads being single-stepped.
+  stepping: AtomicUsize,
+  lock: AtomicBool,
+  next: *const Page,
+}
+
+unsafe impl Send for Page {}
+unsafe impl Sync for Page {}
+
+/// The most recently allocated page, linking to all others.
+static PAGES: AtomicPtr<Page> = AtomicPtr::new(ptr::null_mut());
+
+/// Serializes allocations of pages.
+static ALLOCATION: Mutex<()> = Mutex::new(());
+
+impl Page {
+  /// Returns the page at an address, allocating it if required.
+  fn acquire(base: usize, size: usize, protection: usize) -> &'static Page {
+    let _guard = ALLOCATION.lock();
+
+    if let Some(page) = pages().find(|page| page.base.load(Ordering::SeqCst) == base) {
+      return page;
+    }
+
+    let page = Box::leak(Box::new(Page {
+      base: AtomicUsize::new(base),
+      size,
+      protection,
+      hooks: AtomicUsize::new(0),
+      stepping: AtomicUsize::new(0),
+      lock: AtomicBool::new(false),
+      next: PAGES.load(Ordering::SeqCst),
+    }));
+    PAGES.store(page, Ordering::SeqCst);
+    page
+  }
+
+  /// Returns whether the page contains an address.
+  fn contains(&self, address: usize) -> bool {
+    let base = self.base.load(Ordering::SeqCst);
+    (base..base + self.size).contains(&address)
+  }
+
+  /// Updates the page whilst holding its lock, guarding it afterwards if any
+  /// detour is enabled, and no thread is being single-stepped.
+  ///
+  /// The lock is a spin lock, since it's acquired within the exception
+  /// handler; it's never held whilst executing code within the page.
+  fn with_lock<T>(&self, operation: impl FnOnce(&Self) -> T) -> T {
+    while self
+      .lock
+      .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+      .is_err()
+    {
+      core::hint::spin_loop();
+    }
+
+    let result = operation(self);
+    let guarded =
+      self.hooks.load(Ordering::SeqCst) > 0 && self.stepping.load(Ordering::SeqCst) == 0;
+
+    // A guard may be consumed by an exception, so it's always reapplied
+    unsafe {
+      native::protect(
+        self.base.load(Ordering::SeqCst),
+        self.size,
+        self.protection,
+        guarded,
+      )
+    };
+
+    self.lock.store(false, Ordering::Release);
+    result
+  }
+}
+
+/// Returns an iterator over all allocated pages.
+fn pages() -> impl Iterator<Item = &'static Page> {
+  let mut current = PAGES.load(Ordering::SeqCst) as *const Page;
+
+  core::iter::from_fn(move || unsafe {
+    let page = current.as_ref()?;
+    current = page.next;
+    Some(page)
+  })
+}
+
+#[cfg(unix)]
+mod native {
+  use crate::os::Protection;
+
+  /// Returns the native representation of a protection.
+  pub fn protection(protection: Protection) -> usize {
+    [
+      (Protection::READ, libc::PROT_READ),
+      (Protection::WRITE, libc::PROT_WRITE),
+      (Protection::EXECUTE, libc::PROT_EXEC),
+    ]
+    .iter()
+    .filter(|(flag, _)| protection.contains(*flag))
+    .fold(libc::PROT_NONE, |result, (_, flag)| result | flag) as usize
+  }
+
+  /// Either guards or unguards a page, by revoking its execute permission.
+  pub unsafe fn protect(base: usize, size: usize, protection: usize, guarded: bool) {
+    let protection = if guarded {
+      protection & !(libc::PROT_EXEC as usize)
+    } else {
+      protection
+    };
+
+    libc::mprotect(base as *mut libc::c_void, size, protection as libc::c_int);
+  }
+}
+
+#[cfg(windows)]
+mod native {
+  use crate::os::Protection;
+  use winapi::shared::minwindef::DWORD;
+  use winapi::um::memoryapi::VirtualProtect;
+  use winapi::um::winnt::{PAGE_EXECUTE_READ, PAGE_EXECUTE_READWRITE, PAGE_GUARD};
+
+  /// Returns the native representation of a protection.
+  pub fn protection(protection: Protection) -> usize {
+    if protection.contains(Protection::WRITE) {
+      PAGE_EXECUTE_READWRITE as usize
+    } else {
+      PAGE_EXECUTE_READ as usize
+    }
+  }
+
+  /// Either guards or unguards a page, using `PAGE_GUARD`.
+  pub unsafe fn protect(base: usize, size: usize, protection: usize, guarded: bool) {
+    let protection = if guarded {
+      protection as DWORD | PAGE_GUARD
+    } else {
+      protection as DWORD
+    };
+
+    let mut previous = 0;
+    VirtualProtect(base as *mut _, size, protection, &mut previous);
+  }
+}
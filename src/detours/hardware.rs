@@ -0,0 +1,394 @@
+use super::exception::{self, Kind, Slot};
+use crate::arch::{memory, Detour, Trampoline};
+use crate::error::{Error, Result};
+use crate::pic;
+use crate::sync::Mutex;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+#[cfg(windows)]
+pub(crate) use self::debug::{synchronize, SYNCHRONIZE};
+
+/// The number of debug registers available for breakpoints (`DR0`-`DR3`).
+const REGISTERS: usize = 4;
+
+/// A detour redirecting a target using a hardware breakpoint.
+///
+/// When enabled, a debug register is programmed with the target's address,
+/// and the shared exception handler of [breakpoint
+/// detours](./struct.BreakpointDetour.html) redirects the thread to the
+/// detour. The target's code is never modified, which makes this mode
+/// applicable to code that is verified byte-for-byte.
+///
+/// At most four hardware breakpoint detours may exist simultaneously, one for
+/// each debug register. Like breakpoint detours, each call raises an
+/// exception, and debuggers must be instructed to pass them on.
+///
+/// # Threads
+///
+/// - On Windows, the debug registers of all threads are updated when enabled or
+///   disabled, and those of threads created afterwards are initialized when
+///   they start (using a TLS callback).
+///
+/// - On Linux and Android, the breakpoint is a performance event (requiring
+///   Linux 5.13 or later). It only applies to the thread enabling the detour,
+///   and any threads that this thread creates afterwards.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use detour::Result;
+/// use detour::HwBreakpointDetour;
+/// use std::mem;
+///
+/// #[inline(never)]
+/// extern "C" fn add5(val: i32) -> i32 {
+///   unsafe { std::ptr::read_volatile(&val) + 5 }
+/// }
+///
+/// extern "C" fn add10(val: i32) -> i32 {
+///   val + 10
+/// }
+///
+/// # fn main() -> Result<()> {
+/// let hook = unsafe { HwBreakpointDetour::new(add5 as *const (), add10 as *const ())? };
+///
+/// unsafe { hook.enable()? };
+/// assert_eq!(add5(5), 15);
+///
+/// let original: extern "C" fn(i32) -> i32 = unsafe { mem::transmute(hook.trampoline()) };
+/// assert_eq!(original(5), 10);
+///
+/// unsafe { hook.disable()? };
+/// assert_eq!(add5(5), 10);
+/// # Ok(())
+/// # }
+/// ```
+pub struct HwBreakpointDetour {
+  target: *const (),
+  trampoline: Trampoline,
+  breakpoint: Mutex<Option<debug::Breakpoint>>,
+  slot: &'static Slot,
+  register: Register,
+}
+
+impl HwBreakpointDetour {
+  /// Constructs a new hardware breakpoint detour.
+  ///
+  /// The hook is disabled by default. A debug register is reserved for the
+  /// detour's lifetime, or `NoDebugRegister` is returned if all are occupied.
+  pub unsafe fn new(target: *const (), detour: *const ()) -> Result<Self> {
+    if target.is_null() || detour.is_null() {
+      Err(Error::NullPointer)?;
+    }
+
+    if target == detour {
+      Err(Error::SameAddress)?;
+    }
+
+    let register = Register::reserve()?;
+    let trampoline = {
+      let _guard = memory::LOCK.lock();
+      Detour::validate(target, detour)?;
+      Trampoline::new_locked(target, 1, pic::CodeEmitter::new())?
+    };
+
+    Ok(HwBreakpointDetour {
+      slot: exception::register(Kind::Hardware, target as usize, detour as usize)?,
+      breakpoint: Mutex::new(None),
+      trampoline,
+      register,
+      target,
+    })
+  }
+
+  /// Enables the detour.
+  pub unsafe fn enable(&self) -> Result<()> {
+    let mut breakpoint = self.breakpoint.lock();
+
+    if breakpoint.is_none() {
+      *breakpoint = Some(debug::Breakpoint::new(self.register.0, self.target)?);
+    }
+    Ok(())
+  }
+
+  /// Disables the detour.
+  pub unsafe fn disable(&self) -> Result<()> {
+    self.breakpoint.lock().take();
+    Ok(())
+  }
+
+  /// Returns whether the detour is enabled or not.
+  pub fn is_enabled(&self) -> bool {
+    self.breakpoint.lock().is_some()
+  }
+
+  /// Returns a reference to the generated trampoline.
+  ///
+  /// The trampoline executes the target's first instruction, and continues
+  /// with the remainder of the original function.
+  pub fn trampoline(&self) -> &() {
+    unsafe {
+      self
+        .trampoline
+        .address()
+        .as_ref()
+        .expect("trampoline should not be null")
+    }
+  }
+}
+
+impl Drop for HwBreakpointDetour {
+  /// Disables the detour, and releases its debug register.
+  fn drop(&mut self) {
+    let result = unsafe { self.disable() };
+    debug_assert!(result.is_ok());
+    unsafe { exception::unregister(self.slot) };
+  }
+}
+
+impl fmt::Debug for HwBreakpointDetour {
+  /// Output whether the detour is enabled or not.
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "HwBreakpointDetour {{ enabled: {}, register: {}, trampoline: {:?} }}",
+      self.is_enabled(),
+      self.register.0,
+      self.trampoline.address()
+    )
+  }
+}
+
+unsafe impl Send for HwBreakpointDetour {}
+unsafe impl Sync for HwBreakpointDetour {}
+
+/// The debug registers reserved by detours.
+static RESERVED: [AtomicBool; REGISTERS] = [
+  AtomicBool::new(false),
+  AtomicBool::new(false),
+  AtomicBool::new(false),
+  AtomicBool::new(false),
+];
+
+/// A reserved debug register, released once dropped.
+struct Register(usize);
+
+impl Register {
+  /// Reserves a vacant debug register.
+  fn reserve() -> Result<Self> {
+    (0..REGISTERS)
+      .find(|&index| {
+        RESERVED[index]
+          .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+          .is_ok()
+      })
+      .map(Register)
+      .ok_or(Error::NoDebugRegister)
+  }
+}
+
+impl Drop for Register {
+  fn drop(&mut self) {
+    RESERVED[self.0].store(false, Ordering::SeqCst);
+  }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod debug {
+  use crate::error::{Error, OsError, Result};
+  use core::mem;
+  use libc::{c_int, c_long, c_ulong};
+
+  /// The attributes of a performance event (`perf_event_attr`), up to and
+  /// including `sig_data`.
+  #[repr(C)]
+  #[derive(Default)]
+  struct Attributes {
+    kind: u32,
+    size: u32,
+    config: u64,
+    sample_period: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    wakeup_events: u32,
+    bp_type: u32,
+    bp_addr: u64,
+    bp_len: u64,
+    branch_sample_type: u64,
+    sample_regs_user: u64,
+    sample_stack_user: u32,
+    clockid: i32,
+    sample_regs_intr: u64,
+    aux_watermark: u32,
+    sample_max_stack: u16,
+    reserved_2: u16,
+    aux_sample_size: u32,
+    reserved_3: u32,
+    sig_data: u64,
+  }
+
+  const PERF_TYPE_BREAKPOINT: u32 = 5;
+  const PERF_FLAG_FD_CLOEXEC: c_ulong = 8;
+  const HW_BREAKPOINT_X: u32 = 4;
+
+  // Bits of the attributes' flags
+  const INHERIT: u64 = 1 << 1;
+  const EXCLUDE_KERNEL: u64 = 1 << 5;
+  const EXCLUDE_HV: u64 = 1 << 6;
+  const INHERIT_THREAD: u64 = 1 << 35;
+  const REMOVE_ON_EXEC: u64 = 1 << 36;
+  const SIGTRAP: u64 = 1 << 37;
+
+  /// An enabled breakpoint, implemented as a performance event raising
+  /// `SIGTRAP` when the target is executed.
+  pub struct Breakpoint(c_int);
+
+  impl Breakpoint {
+    /// Enables a breakpoint for the current thread, and threads created by it.
+    ///
+    /// The kernel assigns the debug register itself.
+    pub unsafe fn new(_register: usize, target: *const ()) -> Result<Self> {
+      let attributes = Attributes {
+        kind: PERF_TYPE_BREAKPOINT,
+        size: mem::size_of::<Attributes>() as u32,
+        sample_period: 1,
+        flags: INHERIT | EXCLUDE_KERNEL | EXCLUDE_HV | INHERIT_THREAD | REMOVE_ON_EXEC | SIGTRAP,
+        bp_type: HW_BREAKPOINT_X,
+        bp_addr: target as u64,
+        bp_len: mem::size_of::<c_long>() as u64,
+        sig_data: target as u64,
+        ..Default::default()
+      };
+
+      let descriptor = libc::syscall(
+        libc::SYS_perf_event_open,
+        &attributes as *const Attributes,
+        0,
+        -1,
+        -1,
+        PERF_FLAG_FD_CLOEXEC,
+      );
+
+      if descriptor < 0 {
+        let error = OsError(std::io::Error::last_os_error().raw_os_error().unwrap_or(0));
+        Err(match error.code() {
+          libc::ENOSPC => Error::NoDebugRegister,
+          _ => Error::PermissionDenied {
+            operation: "perf_event_open",
+            error,
+          },
+        })?;
+      }
+
+      Ok(Breakpoint(descriptor as c_int))
+    }
+  }
+
+  impl Drop for Breakpoint {
+    /// Disables the breakpoint, including for any inherited threads.
+    fn drop(&mut self) {
+      unsafe { libc::close(self.0) };
+    }
+  }
+}
+
+#[cfg(windows)]
+mod debug {
+  use super::REGISTERS;
+  use crate::detours::ThreadFreeze;
+  use crate::error::Result;
+  use core::ptr;
+  use core::sync::atomic::{AtomicUsize, Ordering};
+  use winapi::shared::minwindef::DWORD;
+  use winapi::um::errhandlingapi::RaiseException;
+  use winapi::um::winnt::{CONTEXT, CONTEXT_DEBUG_REGISTERS, DLL_THREAD_ATTACH, PVOID};
+
+  /// The exception raised by a thread to synchronize its own debug registers.
+  pub const SYNCHRONIZE: DWORD = 0xE064_7230;
+
+  /// The addresses assigned to each debug register (zero if disabled).
+  static ADDRESSES: [AtomicUsize; REGISTERS] = [
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+  ];
+
+  /// Initializes the debug registers of threads created afterwards.
+  #[used]
+  #[link_section = ".CRT$XLD"]
+  static THREAD_CALLBACK: unsafe extern "system" fn(PVOID, DWORD, PVOID) = on_thread;
+
+  /// An enabled breakpoint, assigned to a debug register of every thread.
+  pub struct Breakpoint(usize);
+
+  impl Breakpoint {
+    /// Enables a breakpoint for all threads.
+    pub unsafe fn new(register: usize, target: *const ()) -> Result<Self> {
+      ADDRESSES[register].store(target as usize, Ordering::SeqCst);
+      update_threads();
+      Ok(Breakpoint(register))
+    }
+  }
+
+  impl Drop for Breakpoint {
+    /// Disables the breakpoint for all threads.
+    fn drop(&mut self) {
+      ADDRESSES[self.0].store(0, Ordering::SeqCst);
+      unsafe { update_threads() };
+    }
+  }
+
+  /// Writes the assigned addresses to the debug registers of a context.
+  pub fn synchronize(context: &mut CONTEXT) {
+    let addresses: [usize; REGISTERS] = [0, 1, 2, 3].map(|i| ADDRESSES[i].load(Ordering::SeqCst));
+    let mut control = context.Dr7 as usize;
+
+    for (index, &address) in addresses.iter().enumerate() {
+      // Clear the local enable bit, and the condition and length bits (i.e an
+      // execution breakpoint)
+      control &= !(1 << (index * 2)) & !(0b1111 << (16 + index * 4));
+
+      if address != 0 {
+        control |= 1 << (index * 2);
+      }
+    }
+
+    context.ContextFlags |= CONTEXT_DEBUG_REGISTERS;
+    context.Dr0 = addresses[0] as _;
+    context.Dr1 = addresses[1] as _;
+    context.Dr2 = addresses[2] as _;
+    context.Dr3 = addresses[3] as _;
+    context.Dr7 = control as _;
+  }
+
+  /// Updates the debug registers of every thread within the process.
+  unsafe fn update_threads() {
+    if let Ok(mut freeze) = ThreadFreeze::new() {
+      for thread in freeze.threads_mut() {
+        if let Ok(mut context) = thread.context(CONTEXT_DEBUG_REGISTERS) {
+          synchronize(&mut context);
+          let _ = thread.set_context(&context);
+        }
+      }
+    }
+
+    // A running thread's context cannot be set; the exception handler
+    // updates the context of the current thread instead
+    RaiseException(SYNCHRONIZE, 0, 0, ptr::null());
+  }
+
+  /// Synchronizes the debug registers of a starting thread, if any breakpoint
+  /// is enabled (the exception handler is then installed).
+  unsafe extern "system" fn on_thread(_module: PVOID, reason: DWORD, _reserved: PVOID) {
+    let is_enabled = ADDRESSES
+      .iter()
+      .any(|address| address.load(Ordering::SeqCst) != 0);
+
+    if reason == DLL_THREAD_ATTACH && is_enabled {
+      RaiseException(SYNCHRONIZE, 0, 0, ptr::null());
+    }
+  }
+}
@@ -1,15 +1,61 @@
 use cfg_if::cfg_if;
 
+mod caller;
+mod entry;
 mod generic;
+mod group;
+mod pointer;
 mod raw;
+mod statik;
 
+pub(crate) use self::caller::shims as caller_shims;
+pub use self::caller::{__CallerFrame, caller_address};
+pub use self::entry::*;
 pub use self::generic::*;
+pub use self::group::*;
+pub use self::pointer::*;
 pub use self::raw::*;
+pub use self::statik::*;
+
+cfg_if! {
+    if #[cfg(all(
+      feature = "std",
+      any(target_os = "linux", target_os = "android", target_os = "macos", windows)
+    ))] {
+        mod breakpoint;
+        mod exception;
+        pub use self::breakpoint::*;
+    } else {
+    }
+}
+
+cfg_if! {
+    if #[cfg(all(
+      feature = "std",
+      any(target_os = "linux", target_os = "android", windows)
+    ))] {
+        mod freeze;
+        mod guard;
+        mod hardware;
+        pub use self::freeze::*;
+        pub use self::guard::*;
+        pub use self::hardware::*;
+    } else {
+    }
+}
+
+cfg_if! {
+    if #[cfg(feature = "std")] {
+        mod multi;
+        pub use self::multi::*;
+    } else {
+    }
+}
 
 cfg_if! {
     if #[cfg(feature = "nightly")] {
-        mod statik;
-        pub use self::statik::*;
+        mod variadic;
+        pub use self::variadic::*;
     } else {
     }
 }
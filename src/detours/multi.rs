@@ -0,0 +1,333 @@
+use crate::arch::memory::{self, PageGroup};
+use crate::arch::{self, Detour, Patcher, Trampoline};
+use crate::error::{Error, Result};
+use crate::profiling::CodeKind;
+use crate::{os, pic, pool, Function, HookableWith, RegisterState};
+use core::cell::{Cell, UnsafeCell};
+use core::convert::TryFrom;
+use core::fmt;
+use core::marker::PhantomData;
+use core::ops::Range;
+use core::sync::atomic::{AtomicBool, Ordering};
+use std::vec::Vec;
+
+/// A type-safe detour of multiple targets, sharing a single detour.
+///
+/// Each target is redirected to a small stub, which loads the target's index
+/// and jumps to a relay shared by all targets. The relay records the index,
+/// which the detour retrieves using [target_index](./fn.target_index.html),
+/// before invoking the detour. Every target has its own trampoline.
+///
+/// Compared to hooking each target independently, the stubs are allocated
+/// alongside the relay, only requiring a few bytes per target (besides the
+/// trampolines), and enabling or disabling the detour changes the protection
+/// of targets sharing pages at once. Targets too far apart for a single relay
+/// (e.g in different modules on x64) are assigned another one.
+///
+/// Due to being generated by a macro, the `MultiDetour::call` method is not
+/// exposed in the documentation.
+/// It accepts the index of a target, followed by the same arguments as `T`,
+/// and shares its result type:
+///
+/// ```c
+/// /// Calls an original function regardless of whether it's hooked or not.
+/// fn call(&self, index: usize, T::Arguments) -> T::Output
+/// ```
+///
+/// # Example
+///
+/// ```rust
+/// # use detour::Result;
+/// use detour::{target_index, MultiDetour};
+///
+/// #[inline(never)]
+/// extern "C" fn add5(val: i32) -> i32 {
+///   unsafe { std::ptr::read_volatile(&val) + 5 }
+/// }
+///
+/// #[inline(never)]
+/// extern "C" fn add10(val: i32) -> i32 {
+///   unsafe { std::ptr::read_volatile(&val) + 10 }
+/// }
+///
+/// extern "C" fn detour(val: i32) -> i32 {
+///   val * 100 + target_index().unwrap() as i32
+/// }
+///
+/// type Fn = extern "C" fn(i32) -> i32;
+///
+/// # fn main() -> Result<()> {
+/// let hook = unsafe { MultiDetour::<Fn>::new(&[add5, add10], detour)? };
+///
+/// unsafe { hook.enable()? };
+/// assert_eq!(add5(1), 100);
+/// assert_eq!(add10(1), 101);
+/// assert_eq!(hook.call(1, 1), 11);
+///
+/// unsafe { hook.disable()? };
+/// assert_eq!(add5(1), 6);
+/// # Ok(())
+/// # }
+/// ```
+pub struct MultiDetour<T: Function> {
+  phantom: PhantomData<T>,
+  hooks: Vec<Hook>,
+  groups: Vec<PageGroup>,
+  #[allow(dead_code)]
+  blocks: Vec<pool::ExecutableMemory>,
+  enabled: AtomicBool,
+}
+
+/// The patch and trampoline of a single target.
+struct Hook {
+  patcher: UnsafeCell<Patcher>,
+  trampoline: Trampoline,
+}
+
+impl<T: Function> MultiDetour<T> {
+  /// Create a new hook given a list of target functions and a compatible
+  /// detour function.
+  ///
+  /// The index of each target is its position within the list.
+  pub unsafe fn new<D>(targets: &[T], detour: D) -> Result<Self>
+  where
+    T: HookableWith<D>,
+    D: Function,
+  {
+    let detour = detour.to_ptr();
+    let targets = targets.iter().map(T::to_ptr).collect::<Vec<_>>();
+
+    if detour.is_null() || targets.iter().any(|target| target.is_null()) {
+      Err(Error::NullPointer)?;
+    }
+
+    if targets.contains(&detour) {
+      Err(Error::SameAddress)?;
+    }
+
+    let _guard = memory::LOCK.lock();
+    for &target in &targets {
+      Detour::validate_target(target)?;
+    }
+
+    if !crate::memory::is_executable(detour)? {
+      Err(Error::DetourNotExecutable)?;
+    }
+
+    let trampolines = targets
+      .iter()
+      .map(|&target| {
+        let margin = arch::meta::prolog_margin(target);
+        Trampoline::new_locked(target, margin, pic::CodeEmitter::new())
+      })
+      .collect::<Result<Vec<_>>>()?;
+
+    // Each block contains a relay, followed by the stubs of all targets within
+    // range of it. Another block is only allocated for those out of range.
+    let mut patchers = targets.iter().map(|_| None).collect::<Vec<_>>();
+    let mut pending = (0..targets.len()).collect::<Vec<_>>();
+    let mut blocks = Vec::new();
+
+    while let Some(&first) = pending.first() {
+      let (block, stubs) = Self::allocate_block(&pending, detour, targets[first])?;
+      let mut remaining = Vec::new();
+
+      for (index, stub) in pending.into_iter().zip(stubs) {
+        let size = trampolines[index].prolog_size();
+        match Patcher::new(targets[index], stub, size) {
+          Ok(patcher) => patchers[index] = Some(patcher),
+          Err(Error::OutOfRange) if index != first => remaining.push(index),
+          Err(error) => Err(error)?,
+        }
+      }
+
+      blocks.push(block);
+      pending = remaining;
+    }
+
+    let hooks = patchers
+      .into_iter()
+      .zip(trampolines)
+      .map(|(patcher, trampoline)| Hook {
+        patcher: UnsafeCell::new(patcher.expect("patcher of each target")),
+        trampoline,
+      })
+      .collect::<Vec<_>>();
+
+    Ok(MultiDetour {
+      phantom: PhantomData,
+      groups: PageGroup::partition(
+        &hooks.iter().map(Hook::area).collect::<Vec<_>>(),
+        os::backend()?.page_size(),
+      ),
+      hooks,
+      blocks,
+      enabled: AtomicBool::default(),
+    })
+  }
+
+  /// Allocates a relay and the stubs of targets near an origin, whilst
+  /// holding the lock, returning the address of each stub.
+  unsafe fn allocate_block(
+    indices: &[usize],
+    detour: *const (),
+    origin: *const (),
+  ) -> Result<(pool::ExecutableMemory, Vec<*const ()>)> {
+    let mut emitter = arch::meta::relay_builder(origin, detour, arch::meta::entry_thunk(record))?
+      .expect("relay with a prologue");
+
+    let mut offsets = Vec::with_capacity(indices.len());
+    for &index in indices {
+      let index = u32::try_from(index).expect("target index exceeds 32 bits");
+      offsets.push(emitter.len());
+      emitter.append(arch::meta::index_stub(index, -(emitter.len() as isize)));
+    }
+
+    let block = memory::allocate_pic(&emitter, origin, CodeKind::Relay)?;
+    let stubs = offsets
+      .into_iter()
+      .map(|offset| (block.as_ptr() as usize + offset) as *const ())
+      .collect();
+    Ok((block, stubs))
+  }
+
+  /// Enables the detour for all targets.
+  pub unsafe fn enable(&self) -> Result<()> {
+    self.toggle(true)
+  }
+
+  /// Disables the detour for all targets.
+  pub unsafe fn disable(&self) -> Result<()> {
+    self.toggle(false)
+  }
+
+  /// Returns whether the detour is enabled or not.
+  pub fn is_enabled(&self) -> bool {
+    self.enabled.load(Ordering::SeqCst)
+  }
+
+  /// Returns the number of targets.
+  pub fn len(&self) -> usize {
+    self.hooks.len()
+  }
+
+  /// Returns whether there are no targets.
+  pub fn is_empty(&self) -> bool {
+    self.hooks.is_empty()
+  }
+
+  /// Returns the trampoline of a target, typed as the target function.
+  ///
+  /// # Panics
+  ///
+  /// Panics if the index is out of bounds.
+  pub fn trampoline(&self, index: usize) -> T {
+    unsafe { T::from_ptr(self.hooks[index].trampoline.address()) }
+  }
+
+  /// Patches or unpatches all targets, one group of pages at a time.
+  ///
+  /// If any group fails, those already written are restored.
+  unsafe fn toggle(&self, enabled: bool) -> Result<()> {
+    let _guard = memory::LOCK.lock();
+
+    if self.enabled.load(Ordering::SeqCst) == enabled {
+      return Ok(());
+    }
+
+    PageGroup::write_all(
+      &self.groups,
+      |index| self.hooks[index].patcher.get(),
+      enabled,
+    )?;
+    self.enabled.store(enabled, Ordering::SeqCst);
+    Ok(())
+  }
+}
+
+#[cfg(windows)]
+impl MultiDetour<os::TlsCallback> {
+  /// Creates a detour of every TLS callback of a module, sharing a single
+  /// detour.
+  ///
+  /// The index of each target is its position within the module's callback
+  /// array (see
+  /// [Module::tls_callbacks](./os/struct.Module.html#method.tls_callbacks)),
+  /// excluding repeated callbacks. Callbacks registered after the detour is
+  /// created are not detoured. A module without TLS callbacks yields a detour
+  /// without targets.
+  pub unsafe fn with_tls_callbacks<D>(module: &os::Module, detour: D) -> Result<Self>
+  where
+    os::TlsCallback: HookableWith<D>,
+    D: Function,
+  {
+    // Repeated callbacks are only detoured once
+    let mut targets = Vec::new();
+    for callback in module.tls_callbacks() {
+      if !targets.contains(&callback) {
+        targets.push(callback);
+      }
+    }
+
+    let callbacks = targets
+      .into_iter()
+      .map(|callback| core::mem::transmute::<*const (), os::TlsCallback>(callback))
+      .collect::<Vec<_>>();
+    Self::new(&callbacks, detour)
+  }
+}
+
+impl<T: Function> Drop for MultiDetour<T> {
+  /// Disables the detour, if enabled.
+  ///
+  /// On failure, each target's patcher retries once more when it's dropped,
+  /// and reports the error if it persists.
+  fn drop(&mut self) {
+    let _ = unsafe { self.disable() };
+  }
+}
+
+impl<T: Function> fmt::Debug for MultiDetour<T> {
+  /// Output whether the detour is enabled or not.
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "MultiDetour {{ enabled: {}, targets: {} }}",
+      self.is_enabled(),
+      self.len()
+    )
+  }
+}
+
+unsafe impl<T: Function> Send for MultiDetour<T> {}
+unsafe impl<T: Function> Sync for MultiDetour<T> {}
+
+impl Hook {
+  /// Returns the range of the hook's patch area.
+  fn area(&self) -> Range<usize> {
+    let patcher = unsafe { &*self.patcher.get() };
+    let address = patcher.address() as usize;
+    address..address + patcher.code().len()
+  }
+}
+
+std::thread_local! {
+  /// The index of the target most recently invoked on the thread.
+  static INDEX: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+/// Records the index loaded by a target's stub.
+extern "C" fn record(registers: &RegisterState) {
+  let _ = INDEX.try_with(|index| index.set(Some(arch::meta::stub_index(registers))));
+}
+
+/// Returns the index of the target that invoked the current thread's
+/// [multi detour](./struct.MultiDetour.html).
+///
+/// The index is recorded whenever a target of any multi detour is called, and
+/// remains until the next call on the same thread. It should therefore be
+/// retrieved before the detour calls any other detoured function. Returns
+/// `None` if no target has been called on the thread.
+pub fn target_index() -> Option<usize> {
+  INDEX.try_with(Cell::get).ok().flatten()
+}
@@ -0,0 +1,211 @@
+use crate::arch::{self, memory, DropContext};
+use crate::error::{Error, Result};
+use crate::{os, Function, HookableWith};
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+use core::{fmt, mem};
+
+/// A type-safe detour of a function pointer.
+///
+/// Instead of patching code, the detour replaces a function pointer stored in
+/// data (e.g an entry of a dispatch table, or a callback registered with a
+/// library). The slot is swapped atomically, and restored when disabled,
+/// provided that it still contains the detour.
+///
+/// Due to being generated by a macro, the `PointerDetour::call` method is not
+/// exposed in the documentation.
+/// It accepts the same arguments as `T`, and shares its result type:
+///
+/// ```c
+/// /// Calls the original function pointer regardless of whether it's hooked or not.
+/// fn call(&self, T::Arguments) -> T::Output
+/// ```
+///
+/// # Example
+///
+/// ```rust
+/// # use detour::Result;
+/// use detour::PointerDetour;
+///
+/// fn add5(val: i32) -> i32 {
+///   val + 5
+/// }
+///
+/// fn add10(val: i32) -> i32 {
+///   val + 10
+/// }
+///
+/// # fn main() -> Result<()> {
+/// let mut callback: fn(i32) -> i32 = add5;
+/// let hook = unsafe { PointerDetour::<fn(i32) -> i32>::new(&mut callback, add10)? };
+///
+/// unsafe { hook.enable()? };
+/// assert_eq!(unsafe { std::ptr::read_volatile(&callback) }(5), 15);
+/// assert_eq!(hook.call(5), 10);
+///
+/// unsafe { hook.disable()? };
+/// assert_eq!(unsafe { std::ptr::read_volatile(&callback) }(5), 10);
+/// # Ok(())
+/// # }
+/// ```
+pub struct PointerDetour<T: Function> {
+  phantom: PhantomData<T>,
+  slot: *mut T,
+  original: AtomicPtr<()>,
+  detour: *const (),
+  enabled: AtomicBool,
+  read_only: bool,
+}
+
+impl<T: Function> PointerDetour<T> {
+  /// Create a new hook given the address of a function pointer and a
+  /// compatible detour function.
+  ///
+  /// The slot must be aligned, and remain valid for as long as the hook
+  /// exists. Its current value is called by `call` until the hook is
+  /// enabled.
+  pub unsafe fn new<D>(slot: *mut T, detour: D) -> Result<Self>
+  where
+    T: HookableWith<D>,
+    D: Function,
+  {
+    Self::with_protection(slot, detour, false)
+  }
+
+  /// Create a new hook of a function pointer within read-only memory (e.g a
+  /// relocation table protected by RELRO).
+  ///
+  /// The protection of the slot is changed for the duration of each write.
+  pub unsafe fn new_read_only<D>(slot: *mut T, detour: D) -> Result<Self>
+  where
+    T: HookableWith<D>,
+    D: Function,
+  {
+    Self::with_protection(slot, detour, true)
+  }
+
+  unsafe fn with_protection<D>(slot: *mut T, detour: D, read_only: bool) -> Result<Self>
+  where
+    T: HookableWith<D>,
+    D: Function,
+  {
+    if slot.is_null() || detour.to_ptr().is_null() {
+      Err(Error::NullPointer)?;
+    }
+
+    // Function pointers are always pointer-sized
+    debug_assert_eq!(mem::size_of::<T>(), mem::size_of::<*const ()>());
+
+    Ok(PointerDetour {
+      phantom: PhantomData,
+      original: AtomicPtr::new((*slot).to_ptr() as *mut ()),
+      detour: detour.to_ptr(),
+      enabled: AtomicBool::default(),
+      slot,
+      read_only,
+    })
+  }
+
+  /// Enables the detour.
+  ///
+  /// The slot's current value is saved as the original function.
+  pub unsafe fn enable(&self) -> Result<()> {
+    self.toggle(true)
+  }
+
+  /// Disables the detour.
+  ///
+  /// Returns `SlotChanged` if the slot no longer contains the detour, in
+  /// which case it's left as is.
+  pub unsafe fn disable(&self) -> Result<()> {
+    self.toggle(false)
+  }
+
+  /// Returns whether the detour is enabled or not.
+  pub fn is_enabled(&self) -> bool {
+    self.enabled.load(Ordering::SeqCst)
+  }
+
+  /// Returns the original function pointer.
+  ///
+  /// This is the value the slot contained when the detour was last enabled.
+  pub fn original(&self) -> T {
+    unsafe { T::from_ptr(self.original.load(Ordering::SeqCst)) }
+  }
+
+  /// Returns the address of the slot.
+  pub fn slot(&self) -> *mut T {
+    self.slot
+  }
+
+  unsafe fn toggle(&self, enabled: bool) -> Result<()> {
+    let _guard = memory::LOCK.lock();
+
+    if self.enabled.load(Ordering::SeqCst) == enabled {
+      return Ok(());
+    }
+
+    let _handle = if self.read_only {
+      Some(os::protect_with_guard(
+        self.slot as *const (),
+        mem::size_of::<T>(),
+        os::Protection::READ_WRITE,
+      )?)
+    } else {
+      None
+    };
+
+    let slot = &*(self.slot as *const AtomicPtr<()>);
+    if enabled {
+      let original = slot.swap(self.detour as *mut (), Ordering::SeqCst);
+      self.original.store(original, Ordering::SeqCst);
+    } else {
+      slot
+        .compare_exchange(
+          self.detour as *mut (),
+          self.original.load(Ordering::SeqCst),
+          Ordering::SeqCst,
+          Ordering::SeqCst,
+        )
+        .map_err(|_| Error::SlotChanged)?;
+    }
+
+    self.enabled.store(enabled, Ordering::SeqCst);
+    Ok(())
+  }
+}
+
+impl<T: Function> Drop for PointerDetour<T> {
+  /// Restores the slot, if enabled and it still contains the detour.
+  ///
+  /// Any other failure is reported to the
+  /// [drop error handler](./fn.set_drop_error_handler.html).
+  fn drop(&mut self) {
+    // A slot modified by another party is left as is
+    match unsafe { self.disable() } {
+      Ok(()) | Err(Error::SlotChanged) => (),
+      Err(error) => arch::report_drop_error(
+        &error,
+        DropContext {
+          target: self.slot as *const (),
+          size: mem::size_of::<T>(),
+        },
+      ),
+    }
+  }
+}
+
+impl<T: Function> fmt::Debug for PointerDetour<T> {
+  /// Output whether the detour is enabled or not.
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "PointerDetour {{ enabled: {}, slot: {:?} }}",
+      self.is_enabled(),
+      self.slot
+    )
+  }
+}
+
+unsafe impl<T: Function> Send for PointerDetour<T> {}
+unsafe impl<T: Function> Sync for PointerDetour<T> {}
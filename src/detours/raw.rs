@@ -1,5 +1,8 @@
-use crate::arch::Detour;
-use crate::error::Result;
+use crate::arch::{self, Detour, Options};
+use crate::error::{Error, Result};
+use crate::{pic, pool, PatchStrategy, PreparedPatch, PrologueFill, RelocationRecord};
+use alloc::vec::Vec;
+use core::fmt;
 
 /// A raw detour.
 ///
@@ -37,7 +40,22 @@ use crate::error::Result;
 /// # Ok(())
 /// # }
 /// ```
-#[derive(Debug)]
+///
+/// # Thread safety
+///
+/// The detour is `Send` and `Sync`. All patch operations of the library
+/// (e.g constructing, enabling or disabling any detour) hold a single global
+/// lock, `memory::LOCK`; toggling one detour blocks toggling all others until
+/// it completes, even those of unrelated targets.
+///
+/// Enabling and disabling may therefore be called concurrently from any
+/// thread; each call either changes the state, or returns immediately if the
+/// detour already is in the requested state. `is_enabled` reflects the most
+/// recently completed operation, and once it returns `true` the target is
+/// patched.
+///
+/// Threads executing the target's prolog whilst it's being written are not
+/// suspended (see [ThreadFreeze](./struct.ThreadFreeze.html)).
 pub struct RawDetour(Detour);
 
 // TODO: stop all threads in target during patch?
@@ -53,7 +71,26 @@ impl RawDetour {
     Detour::new(target, detour).map(RawDetour)
   }
 
+  /// Constructs a new inline detour patcher, with custom code executed on
+  /// either side of the detour.
+  ///
+  /// See [Shims](./struct.Shims.html) for the requirements on the code.
+  pub unsafe fn with_shims(target: *const (), detour: *const (), shims: Shims) -> Result<Self> {
+    Detour::with_shims(target, detour, shims.before_detour, shims.before_original).map(RawDetour)
+  }
+
+  /// Returns a builder for a detour from `target` to `detour`.
+  pub fn builder(target: *const (), detour: *const ()) -> DetourBuilder {
+    DetourBuilder::new(target, detour)
+  }
+
   /// Enables the detour.
+  ///
+  /// The target's prolog is analyzed, and the patch and trampoline are
+  /// generated, upon construction (or [swap_target](#method.swap_target));
+  /// enabling and disabling merely write the generated code, changing the
+  /// patch area's protection for the duration of the write, and flush the
+  /// instruction cache.
   pub unsafe fn enable(&self) -> Result<()> {
     self.0.enable()
   }
@@ -63,13 +100,402 @@ impl RawDetour {
     self.0.disable()
   }
 
+  /// Disables the detour if enabled, and drops it, releasing its trampoline
+  /// according to the [reclamation policy](./pool/enum.Reclamation.html).
+  ///
+  /// Unlike dropping the detour, which only reports a failure to restore the
+  /// target to the [drop error handler](./fn.set_drop_error_handler.html),
+  /// the detour is returned along with the error, so the caller may retry.
+  /// Once this succeeds, the target's prolog has been restored.
+  #[allow(clippy::result_large_err)]
+  pub unsafe fn close(self) -> core::result::Result<(), (Self, Error)> {
+    match self.0.disable() {
+      Ok(()) => Ok(()),
+      Err(error) => Err((self, error)),
+    }
+  }
+
   /// Returns whether the detour is enabled or not.
   pub fn is_enabled(&self) -> bool {
     self.0.is_enabled()
   }
 
+  /// Prepares enabling or disabling the detour, so it can be committed from a
+  /// signal handler.
+  ///
+  /// See [PreparedPatch](./struct.PreparedPatch.html), and the
+  /// [signal safety](./pool/index.html#signal-safety) of the library.
+  pub unsafe fn prepare(&self, enabled: bool) -> Result<PreparedPatch<'_>> {
+    self.0.prepare(enabled)
+  }
+
+  /// Binds the detour to another target, e.g after reloading a module.
+  ///
+  /// The detour is disabled at the current target (unless it has been
+  /// unmapped), and re-enabled at the new target if it was enabled. If the
+  /// new target cannot be detoured, the detour is left unbound: it cannot be
+  /// enabled until bound to another target, and the trampoline refers to the
+  /// previous target. Previous trampolines remain valid until the detour is
+  /// dropped.
+  ///
+  /// Returns `Error::NotInitialized` for detours constructed with an entry
+  /// thunk or a breakpoint.
+  pub unsafe fn swap_target(&self, new_target: *const ()) -> Result<()> {
+    self.0.swap_target(new_target)
+  }
+
+  /// Returns whether the detour is bound to a target or not.
+  pub fn is_bound(&self) -> bool {
+    self.0.is_bound()
+  }
+
   /// Returns a reference to the generated trampoline.
+  ///
+  /// Panics if a [lazy](./struct.DetourBuilder.html#method.lazy_trampoline)
+  /// trampoline cannot be emitted.
   pub fn trampoline(&self) -> &() {
     self.0.trampoline()
   }
+
+  /// Returns a reference to the generated trampoline, or the error emitting a
+  /// [lazy](./struct.DetourBuilder.html#method.lazy_trampoline) trampoline.
+  pub fn try_trampoline(&self) -> Result<&()> {
+    self.0.try_trampoline()
+  }
+
+  /// Returns statistics for the pool region containing the trampoline.
+  ///
+  /// Returns `None` if the trampoline was allocated by a custom allocator, or
+  /// resides within a hot-patchable target.
+  pub fn region(&self) -> Option<pool::RegionStats> {
+    self.0.region()
+  }
+
+  /// Returns a record for each instruction relocated to the trampoline.
+  ///
+  /// The records map addresses within the trampoline back to the original
+  /// instructions of the target.
+  pub fn trampoline_map(&self) -> &[RelocationRecord] {
+    self.0.trampoline_map()
+  }
+
+  /// Returns how the target is redirected.
+  ///
+  /// This is only other than `PatchStrategy::Relative` if no memory was
+  /// available within range of the target.
+  pub fn strategy(&self) -> PatchStrategy {
+    self.0.strategy()
+  }
+
+  /// Returns the system call number (SSN) loaded by the target, if it's a
+  /// Windows system call stub (e.g `ntdll!NtClose` on x64).
+  ///
+  /// The stub is relocated as a whole, so calling the trampoline performs the
+  /// system call.
+  pub fn syscall_number(&self) -> Option<u32> {
+    self.0.syscall_number()
+  }
+
+  /// Returns the address of the patch area.
+  ///
+  /// This precedes the target if a hot patch is used.
+  pub fn patch_address(&self) -> *const () {
+    self.0.patch_address()
+  }
+
+  /// Returns the original contents of the patch area, restored when the
+  /// detour is disabled.
+  pub fn original_code(&self) -> Vec<u8> {
+    self.0.original_code()
+  }
+
+  /// Returns the address of the target.
+  pub fn target_address(&self) -> *const () {
+    self.0.target()
+  }
+
+  /// Returns the address of the detour.
+  pub fn detour_address(&self) -> *const () {
+    self.0.destination().expect("destination of a raw detour")
+  }
+
+  /// Returns the address of the trampoline, unless its emission is deferred
+  /// until first use (see
+  /// [lazy_trampoline](./struct.DetourBuilder.html#method.lazy_trampoline)).
+  pub fn trampoline_address(&self) -> Option<*const ()> {
+    self.0.trampoline_address()
+  }
+
+  /// Returns the size of the code written to the patch area when enabled.
+  pub fn patch_size(&self) -> usize {
+    self.0.patch_size()
+  }
+}
+
+impl fmt::Debug for RawDetour {
+  /// Output the addresses of the detour, and whether it's enabled or not.
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    self.0.debug_struct(f, "RawDetour").finish()
+  }
+}
+
+/// Enables detours at once, changing the protection of each group of pages
+/// (and flushing the instruction cache) once, rather than once per detour.
+///
+/// Patch areas sharing pages are grouped, and every group is made writable,
+/// written and restored in turn, in address order. Detours already enabled
+/// are skipped, and stacked detours (i.e sharing a target) are enabled in the
+/// order given. Each group is written in full or not at all; if any group
+/// fails (e.g a [patch callback](./struct.PatchCallbacks.html) rejects one of
+/// its patches), those already written are disabled again, and the error is
+/// returned. Threads may execute a group whilst another is being written.
+///
+/// # Example
+///
+/// ```rust
+/// # use detour::Result;
+/// use detour::RawDetour;
+///
+/// #[inline(never)]
+/// extern "C" fn add5(val: i32) -> i32 {
+///   unsafe { std::ptr::read_volatile(&val) + 5 }
+/// }
+///
+/// #[inline(never)]
+/// extern "C" fn sub5(val: i32) -> i32 {
+///   unsafe { std::ptr::read_volatile(&val) - 5 }
+/// }
+///
+/// extern "C" fn identity(val: i32) -> i32 {
+///   val
+/// }
+///
+/// # fn main() -> Result<()> {
+/// let add = unsafe { RawDetour::new(add5 as *const (), identity as *const ())? };
+/// let sub = unsafe { RawDetour::new(sub5 as *const (), identity as *const ())? };
+///
+/// unsafe { detour::enable_all(&[&add, &sub])? };
+/// assert_eq!((add5(10), sub5(10)), (10, 10));
+///
+/// unsafe { detour::disable_all(&[&add, &sub])? };
+/// assert_eq!((add5(10), sub5(10)), (15, 5));
+/// # Ok(())
+/// # }
+/// ```
+pub unsafe fn enable_all(detours: &[&RawDetour]) -> Result<()> {
+  arch::toggle_all(&inner(detours), true)
+}
+
+/// Disables detours at once, changing the protection of each group of pages
+/// once.
+///
+/// Stacked detours are disabled in the reverse order given; otherwise, the
+/// guarantees of [enable_all](./fn.enable_all.html) apply.
+pub unsafe fn disable_all(detours: &[&RawDetour]) -> Result<()> {
+  arch::toggle_all(&inner(detours), false)
+}
+
+/// Returns the inline detours of raw detours.
+fn inner<'a>(detours: &[&'a RawDetour]) -> Vec<&'a Detour> {
+  detours.iter().map(|detour| &detour.0).collect()
+}
+
+/// Custom code executed on either side of a detour.
+///
+/// The code is emitted into memory allocated from the
+/// [pool](./pool/index.html), and is released along with the detour. It must
+/// be position-independent (static code is never relocated), and must leave
+/// the stack and all registers used for arguments as it found them. Each
+/// sequence falls through to the detour or the original function.
+///
+/// # Example
+///
+/// ```rust
+/// # use detour::Result;
+/// use detour::{RawDetour, Shims};
+///
+/// #[inline(never)]
+/// extern "C" fn add5(val: i32) -> i32 {
+///   unsafe { std::ptr::read_volatile(&val) + 5 }
+/// }
+///
+/// extern "C" fn add10(val: i32) -> i32 {
+///   val + 10
+/// }
+///
+/// # fn main() -> Result<()> {
+/// let mut shims = Shims::default();
+/// // Preserve the flags around code that would otherwise clobber them
+/// shims.before_detour.add_code(&[0x9C, 0x9D]); // pushf; popf
+///
+/// let hook = unsafe { RawDetour::with_shims(add5 as *const (), add10 as *const (), shims)? };
+/// unsafe { hook.enable()? };
+/// assert_eq!(add5(5), 15);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Default)]
+pub struct Shims {
+  /// Code executed before the detour, whenever the target is called.
+  pub before_detour: pic::CodeEmitter,
+  /// Code executed before the original function, whenever the trampoline is
+  /// called.
+  pub before_original: pic::CodeEmitter,
+}
+
+/// A builder for raw detours, with options beyond the defaults.
+///
+/// # Example
+///
+/// ```rust
+/// # use detour::Result;
+/// use detour::DetourBuilder;
+///
+/// #[inline(never)]
+/// extern "C" fn add5(val: i32) -> i32 {
+///   unsafe { std::ptr::read_volatile(&val) + 5 }
+/// }
+///
+/// extern "C" fn add10(val: i32) -> i32 {
+///   val + 10
+/// }
+///
+/// # fn main() -> Result<()> {
+/// let hook = unsafe {
+///   DetourBuilder::new(add5 as *const (), add10 as *const ())
+///     .min_prologue_bytes(8)
+///     .build()?
+/// };
+///
+/// // The patch spans whole instructions, covering at least 8 bytes
+/// assert!(hook.original_code().len() >= 8);
+///
+/// unsafe { hook.enable()? };
+/// assert_eq!(add5(5), 15);
+/// # Ok(())
+/// # }
+/// ```
+pub struct DetourBuilder {
+  target: *const (),
+  detour: *const (),
+  shims: Shims,
+  options: Options,
+}
+
+impl DetourBuilder {
+  /// Creates a builder for a detour from `target` to `detour`.
+  pub fn new(target: *const (), detour: *const ()) -> Self {
+    DetourBuilder {
+      target,
+      detour,
+      shims: Shims::default(),
+      options: Options::default(),
+    }
+  }
+
+  /// Sets custom code executed on either side of the detour.
+  ///
+  /// See [Shims](./struct.Shims.html) for the requirements on the code.
+  pub fn shims(mut self, shims: Shims) -> Self {
+    self.shims = shims;
+    self
+  }
+
+  /// Sets the minimum amount of bytes relocated from the target's prolog.
+  ///
+  /// Whole instructions are relocated until at least `bytes` of the target
+  /// are covered, and all of them are overwritten by the patch (i.e a jump
+  /// followed by NOPs). This is useful if another hooking system requires a
+  /// number of clean bytes at the target, or if a sequence of instructions
+  /// (e.g a stack probe) should reside within the trampoline.
+  ///
+  /// The relocation is still bound by the configured
+  /// [max_prolog_size](./meta/fn.max_prolog_size.html), and fails at any
+  /// instruction that cannot be relocated. If the target returns before the
+  /// minimum is covered, `Error::NoPatchArea` is returned. Hot-patchable
+  /// targets are only patched in place if their entry spans the minimum.
+  pub fn min_prologue_bytes(mut self, bytes: usize) -> Self {
+    self.options.min_prolog_size = bytes;
+    self
+  }
+
+  /// Sets what the remainder of the relocated prolog is filled with, whilst
+  /// the detour is enabled (i.e the bytes after the jump).
+  ///
+  /// By default, the remainder is left as is, unless a minimum amount of
+  /// prologue bytes is set, in which case it's filled with NOPs. Regardless
+  /// of the fill, the original bytes are restored when the detour is
+  /// disabled, and are included in its
+  /// [original_code](./struct.RawDetour.html#method.original_code).
+  pub fn prologue_fill(mut self, fill: PrologueFill) -> Self {
+    self.options.fill = Some(fill);
+    self
+  }
+
+  /// Relocates the target's prolog using the provided code, instead of
+  /// analyzing and relocating it automatically.
+  ///
+  /// The first `original_len` bytes of the target are replaced by the patch,
+  /// and the trampoline consists of `relocated_code` followed by a jump to
+  /// the target at `original_len`. The library still allocates the
+  /// trampoline, and patches, backs up and restores the target. This allows
+  /// detouring targets with instructions that cannot be relocated
+  /// automatically.
+  ///
+  /// # Safety
+  ///
+  /// The code is emitted as is, at an arbitrary address; it must be a
+  /// position-independent equivalent of the replaced instructions, and
+  /// `original_len` must span whole instructions (at least the size of the
+  /// patch, unless followed by padding).
+  pub unsafe fn with_manual_prologue(mut self, relocated_code: &[u8], original_len: usize) -> Self {
+    self.options.manual_prologue = Some((relocated_code.to_vec(), original_len));
+    self
+  }
+
+  /// Sets whether the trampoline is emitted upon first use, instead of upon
+  /// construction.
+  ///
+  /// The target's prolog is still analyzed and validated, and its original
+  /// bytes backed up, upon construction; the detour may be enabled before
+  /// the trampoline exists. The trampoline's code is emitted, and its memory
+  /// allocated from the pool, upon the first call to
+  /// [trampoline](./struct.RawDetour.html#method.trampoline); concurrent
+  /// first calls emit it exactly once. This reduces the cost of installing
+  /// many detours whose original function is rarely called.
+  ///
+  /// If the trampoline cannot be emitted within range of the target, it's
+  /// emitted anywhere. Should that fail too, `trampoline` (and thus the
+  /// `call` method of typed detours) panics, whereas
+  /// [try_trampoline](./struct.RawDetour.html#method.try_trampoline) returns
+  /// the error. Until
+  /// emitted, the detour's [region](./struct.RawDetour.html#method.region) is
+  /// `None`.
+  pub fn lazy_trampoline(mut self, lazy: bool) -> Self {
+    self.options.lazy_trampoline = lazy;
+    self
+  }
+
+  /// Constructs the (disabled) detour.
+  ///
+  /// Returns `Error::InvalidOption` if a manual prologue replaces no bytes, or
+  /// more than [MAX_PROLOG_LIMIT](./meta/constant.MAX_PROLOG_LIMIT.html).
+  pub unsafe fn build(self) -> Result<RawDetour> {
+    if let Some((_, size)) = self.options.manual_prologue {
+      if !(1..=crate::meta::MAX_PROLOG_LIMIT).contains(&size) {
+        Err(Error::InvalidOption {
+          name: "original_len",
+        })?;
+      }
+    }
+
+    Detour::with_options(
+      self.target,
+      self.detour,
+      self.shims.before_detour,
+      self.shims.before_original,
+      self.options,
+    )
+    .map(RawDetour)
+  }
 }
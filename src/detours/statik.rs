@@ -1,8 +1,23 @@
+#[cfg(feature = "std")]
+use crate::arch::Handoff;
 use crate::error::{Error, Result};
-use crate::{Function, GenericDetour};
-use std::sync::atomic::{AtomicPtr, Ordering};
-use std::{mem, ptr};
-use std::marker::Tuple;
+#[cfg(feature = "latency")]
+use crate::latency::{Latency, Metric};
+use crate::{Function, GenericDetour, PreparedPatch, RawDetour, StaticFunction};
+use alloc::boxed::Box;
+#[cfg(feature = "nightly")]
+use core::any::Any;
+#[cfg(feature = "nightly")]
+use core::marker::Tuple;
+use core::ops::Deref;
+use core::sync::atomic::{AtomicPtr, Ordering};
+use core::{fmt, mem, ptr};
+
+/// The type of the closure a static detour stores.
+#[cfg(feature = "nightly")]
+type Closure<T> = dyn Fn<<T as Function>::Arguments, Output = <T as Function>::Output>;
+#[cfg(not(feature = "nightly"))]
+type Closure<T> = <T as StaticFunction>::Closure;
 
 /// A type-safe static detour.
 ///
@@ -12,10 +27,26 @@ use std::marker::Tuple;
 /// ```c
 /// /// Calls the original function regardless of whether it's hooked or not.
 /// ///
-/// /// Panics if called when the static detour has not yet been initialized.
+/// /// Panics if called when the static detour has not yet been initialized,
+/// /// or its lazy trampoline cannot be emitted.
 /// fn call(&self, T::Arguments) -> T::Output
+///
+/// /// Calls the active detour closure, bypassing the target and trampoline.
+/// ///
+/// /// Returns `NotInitialized` or `NoDetourSet` if there's no closure to call.
+/// fn call_detour(&self, T::Arguments) -> Result<T::Output>
+///
+/// /// Changes the detour to a function with the same prototype (without
+/// /// `unsafe`), regardless of whether the hook is enabled or not.
+/// fn set_detour_fn(&self, T)
 /// ```
 ///
+/// A detour that is a function, rather than a closure, is called directly
+/// by the generated dispatch function (i.e without a dynamic dispatch). It's
+/// set using `set_detour_fn`, or by passing a function pointer of the same
+/// type to `initialize` or `set_detour` (e.g `add10 as fn(i32) -> i32`, since
+/// function items are distinct types, and are boxed like closures).
+///
 /// To define a static detour, use the
 /// [static_detour](./macro.static_detour.html) macro.
 ///
@@ -62,20 +93,31 @@ use std::marker::Tuple;
 ///   Ok(())
 /// }
 /// ```
-pub struct StaticDetour<T: Function> {
-  closure: AtomicPtr<Box<dyn Fn<T::Arguments, Output = T::Output>>>,
+///
+/// # Thread safety
+///
+/// The same as [RawDetour](./struct.RawDetour.html#thread-safety).
+pub struct StaticDetour<T: StaticFunction> {
+  closure: AtomicPtr<Box<Closure<T>>>,
+  /// The detour, if it's a function (which takes precedence over `closure`).
+  function: AtomicPtr<()>,
   detour: AtomicPtr<GenericDetour<T>>,
   ffi: T,
+  #[cfg(feature = "latency")]
+  latency: Latency,
 }
 
-impl<T: Function> StaticDetour<T> {
+impl<T: StaticFunction> StaticDetour<T> {
   /// Create a new static detour.
   #[doc(hidden)]
   pub const fn __new(ffi: T) -> Self {
     StaticDetour {
       closure: AtomicPtr::new(ptr::null_mut()),
+      function: AtomicPtr::new(ptr::null_mut()),
       detour: AtomicPtr::new(ptr::null_mut()),
       ffi,
+      #[cfg(feature = "latency")]
+      latency: Latency::new(),
     }
   }
 
@@ -102,11 +144,529 @@ impl<T: Function> StaticDetour<T> {
   /// # Ok(())
   /// # }
   /// ```
+  #[cfg(feature = "nightly")]
+  pub unsafe fn initialize<D>(&self, target: T, closure: D) -> Result<&Self>
+  where
+    D: Fn<T::Arguments, Output = T::Output> + Send + 'static,
+    T::Arguments: Tuple,
+  {
+    let function = function_of::<T, D>(&closure);
+    self.initialize_boxed(target, Box::new(closure), function)
+  }
+
+  /// Create a new hook given a target function and a compatible detour
+  /// closure.
+  ///
+  /// This method can only be called once per static instance. Multiple calls
+  /// will error with `AlreadyExisting`.
+  #[cfg(not(feature = "nightly"))]
   pub unsafe fn initialize<D>(&self, target: T, closure: D) -> Result<&Self>
   where
-    D: Fn<T::Arguments, Output = T::Output> + Send + 'static, <T as Function>::Arguments: Tuple
+    D: crate::StaticClosure<T>,
+  {
+    let function = closure.to_function();
+    self.initialize_boxed(target, closure.into_boxed(), function)
+  }
+
+  unsafe fn initialize_boxed(
+    &self,
+    target: T,
+    closure: Box<Closure<T>>,
+    function: Option<*const ()>,
+  ) -> Result<&Self> {
+    let shims = super::caller_shims();
+    let mut detour = Box::new(GenericDetour::with_shims(target, self.ffi, shims)?);
+    if self
+      .detour
+      .compare_exchange(
+        ptr::null_mut(),
+        &mut *detour,
+        Ordering::SeqCst,
+        Ordering::SeqCst,
+      )
+      .is_err()
+    {
+      Err(Error::AlreadyInitialized)?;
+    }
+
+    self.set_detour_boxed(closure, function);
+    mem::forget(detour);
+    Ok(self)
+  }
+
+  /// Enables the detour.
+  pub unsafe fn enable(&self) -> Result<()> {
+    self.inner()?.enable()
+  }
+
+  /// Disables the detour.
+  pub unsafe fn disable(&self) -> Result<()> {
+    self.inner()?.disable()
+  }
+
+  /// Prepares enabling or disabling the detour, so it can be committed from a
+  /// signal handler (see [PreparedPatch](./struct.PreparedPatch.html)).
+  pub unsafe fn prepare(&self, enabled: bool) -> Result<PreparedPatch<'_>> {
+    self.inner()?.prepare(enabled)
+  }
+
+  /// Returns whether the detour is enabled or not.
+  pub fn is_enabled(&self) -> bool {
+    self
+      .inner()
+      .map(|detour| detour.is_enabled())
+      .unwrap_or(false)
+  }
+
+  /// Returns the underlying detour, or `NotInitialized` before
+  /// [initialize](#method.initialize).
+  ///
+  /// The static detour holds no state of its own besides the closure, hence
+  /// any operation performed through the underlying detour (e.g `disable` or
+  /// `swap_target`) is reflected by the static detour as well. Its detour is
+  /// the function dispatching to the closure, and its `call` method is not
+  /// measured by the static detour's latency recorder.
+  pub fn inner(&self) -> Result<&GenericDetour<T>> {
+    unsafe { self.detour.load(Ordering::SeqCst).as_ref() }.ok_or(Error::NotInitialized)
+  }
+
+  /// Returns whether the detour is initialized or not.
+  pub fn is_initialized(&self) -> bool {
+    !self.detour.load(Ordering::SeqCst).is_null()
+  }
+
+  /// Returns the address of the target, once initialized.
+  pub fn target_address(&self) -> Option<*const ()> {
+    self.inner().ok().map(GenericDetour::target_address)
+  }
+
+  /// Returns the address of the detour, i.e the function dispatching to the
+  /// closure.
+  pub fn detour_address(&self) -> *const () {
+    self.ffi.to_ptr()
+  }
+
+  /// Returns the address of the trampoline, once initialized.
+  pub fn trampoline_address(&self) -> Option<*const ()> {
+    self
+      .inner()
+      .ok()
+      .and_then(GenericDetour::trampoline_address)
+  }
+
+  /// Returns the size of the code written to the patch area when enabled,
+  /// once initialized.
+  pub fn patch_size(&self) -> Option<usize> {
+    self.inner().ok().map(GenericDetour::patch_size)
+  }
+
+  /// Registers the detour under a key, for handing off its state to another
+  /// instance of the library (e.g within a reloaded module).
+  ///
+  /// The detour is handed off by
+  /// [export_state](./handoff/fn.export_state.html) if it's enabled, and it
+  /// adopts the state handed off under the same key by
+  /// [import_state](./handoff/fn.import_state.html), if it's not initialized.
+  /// Registering another detour under the key replaces this one, whilst
+  /// registering this one again replaces its former key.
+  #[cfg(feature = "std")]
+  pub fn set_handoff_key(&'static self, key: &'static str) {
+    crate::handoff::register(key, self);
+  }
+
+  /// Changes the detour, regardless of whether the hook is enabled or not.
+  #[cfg(feature = "nightly")]
+  pub fn set_detour<C>(&self, closure: C)
+  where
+    C: Fn<T::Arguments, Output = T::Output> + Send + 'static,
+    T::Arguments: Tuple,
+  {
+    let function = function_of::<T, C>(&closure);
+    self.set_detour_boxed(Box::new(closure), function);
+  }
+
+  /// Changes the detour, regardless of whether the hook is enabled or not.
+  #[cfg(not(feature = "nightly"))]
+  pub fn set_detour<C>(&self, closure: C)
+  where
+    C: crate::StaticClosure<T>,
+  {
+    let function = closure.to_function();
+    self.set_detour_boxed(closure.into_boxed(), function);
+  }
+
+  /// Changes the detour, along with the function it is (if any).
+  ///
+  /// The function must be a function pointer with the same prototype as `T`,
+  /// and the closure must invoke it. The closure is retained regardless, so
+  /// a concurrent dispatch finds either.
+  pub(crate) fn set_detour_boxed(&self, closure: Box<Closure<T>>, function: Option<*const ()>) {
+    let previous = self
+      .closure
+      .swap(Box::into_raw(Box::new(closure)), Ordering::SeqCst);
+    self.function.store(
+      function.map_or(ptr::null_mut(), |function| function as *mut ()),
+      Ordering::SeqCst,
+    );
+    if !previous.is_null() {
+      mem::drop(unsafe { Box::from_raw(previous) });
+    }
+  }
+
+  /// Returns the generated trampoline, typed as the target function.
+  pub(crate) fn trampoline(&self) -> Result<T> {
+    self.inner().and_then(GenericDetour::try_trampoline)
+  }
+
+  /// Returns a transient reference to the active detour.
+  #[doc(hidden)]
+  pub fn __detour(&self) -> &Closure<T> {
+    self.closure().expect("retrieving detour closure")
+  }
+
+  /// Returns a transient reference to the active detour, if any.
+  fn closure(&self) -> Result<&Closure<T>> {
+    if !self.is_initialized() {
+      return Err(Error::NotInitialized);
+    }
+
+    // TODO: This is not 100% thread-safe in case the thread is stopped
+    unsafe { self.closure.load(Ordering::SeqCst).as_ref() }
+      .map(|closure| &**closure)
+      .ok_or(Error::NoDetourSet)
+  }
+
+  /// Invokes the active detour, on behalf of the generated dispatch function.
+  #[doc(hidden)]
+  #[inline]
+  pub fn __dispatch<S: Function, R>(
+    &self,
+    invoke: impl FnOnce(__Active<'_, S, Closure<T>>) -> R,
+  ) -> R {
+    self
+      .__try_dispatch(invoke)
+      .expect("retrieving detour closure")
+  }
+
+  /// Invokes the active detour, unless there's none.
+  ///
+  /// The detour's function (if any) is typed as `S`, which must share the
+  /// prototype of `T`.
+  #[inline]
+  pub(crate) fn __try_dispatch<S: Function, R>(
+    &self,
+    invoke: impl FnOnce(__Active<'_, S, Closure<T>>) -> R,
+  ) -> Result<R> {
+    #[cfg(feature = "latency")]
+    if let Some(timer) = self.latency.start() {
+      let detour = self.active()?;
+      let timer = timer.lap(Metric::Dispatch);
+      let output = invoke(detour);
+      timer.lap(Metric::Closure);
+      return Ok(output);
+    }
+
+    self.active().map(invoke)
+  }
+
+  /// Returns the active detour, preferring its function over its closure.
+  #[inline]
+  fn active<S: Function>(&self) -> Result<__Active<'_, S, Closure<T>>> {
+    if !self.is_initialized() {
+      return Err(Error::NotInitialized);
+    }
+
+    match self.function.load(Ordering::SeqCst) {
+      function if function.is_null() => self.closure().map(__Active::Closure),
+      // The function shares the prototype of the target
+      function => Ok(__Active::Function(unsafe { S::from_ptr(function) })),
+    }
+  }
+
+  /// Calls the original function through the trampoline.
+  #[inline]
+  pub(crate) fn __call_original<R>(&self, call: impl FnOnce() -> R) -> R {
+    #[cfg(feature = "latency")]
+    return self.latency.measure(Metric::Original, call);
+
+    #[cfg(not(feature = "latency"))]
+    call()
+  }
+
+  /// Returns the latency recorder of the detour.
+  #[cfg(feature = "latency")]
+  pub fn latency(&self) -> &Latency {
+    &self.latency
+  }
+}
+
+#[cfg(feature = "std")]
+impl<T: StaticFunction> crate::handoff::Participant for StaticDetour<T> {
+  unsafe fn hand_off(&self) -> Result<Option<Handoff>> {
+    let detour = match self.inner() {
+      Ok(detour) if detour.is_enabled() => detour,
+      _ => return Ok(None),
+    };
+
+    // Dropping the detour would restore its target, hence it's leaked
+    let handoff = detour.hand_off()?;
+    self.detour.store(ptr::null_mut(), Ordering::SeqCst);
+    Ok(Some(handoff))
+  }
+
+  unsafe fn adopt(&self, handoff: &Handoff) -> Result<()> {
+    if self.is_initialized() {
+      Err(Error::AlreadyInitialized)?;
+    }
+
+    let shims = super::caller_shims();
+    let mut detour = Box::new(GenericDetour::adopt(handoff, self.ffi, shims)?);
+    if self
+      .detour
+      .compare_exchange(
+        ptr::null_mut(),
+        &mut *detour,
+        Ordering::SeqCst,
+        Ordering::SeqCst,
+      )
+      .is_err()
+    {
+      Err(Error::AlreadyInitialized)?;
+    }
+
+    mem::forget(detour);
+    Ok(())
+  }
+}
+
+/// The active detour of a static detour, as passed to its dispatch function.
+#[doc(hidden)]
+pub enum __Active<'a, S, C: ?Sized> {
+  Function(S),
+  Closure(&'a C),
+}
+
+/// Returns the function a closure is, if it's a function pointer of the
+/// target's type.
+#[cfg(feature = "nightly")]
+fn function_of<T: Function, D: 'static>(closure: &D) -> Option<*const ()> {
+  (closure as &dyn Any)
+    .downcast_ref::<T>()
+    .map(Function::to_ptr)
+}
+
+impl<T: StaticFunction> fmt::Debug for StaticDetour<T> {
+  /// Output the addresses of the detour, and whether it's enabled or not,
+  /// omitting the closure.
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct("StaticDetour")
+      .field("target", &self.target_address())
+      .field("detour", &self.detour_address())
+      .field("trampoline", &self.trampoline_address())
+      .field("enabled", &self.is_enabled())
+      .field("initialized", &self.is_initialized())
+      .field("patch_size", &self.patch_size())
+      .finish()
+  }
+}
+
+impl<T: StaticFunction> Drop for StaticDetour<T> {
+  fn drop(&mut self) {
+    let previous = self.closure.swap(ptr::null_mut(), Ordering::Relaxed);
+    if !previous.is_null() {
+      mem::drop(unsafe { Box::from_raw(previous) });
+    }
+
+    let previous = self.detour.swap(ptr::null_mut(), Ordering::Relaxed);
+    if !previous.is_null() {
+      mem::drop(unsafe { Box::from_raw(previous) });
+    }
+  }
+}
+
+/// A type-safe static detour, bound to its target at declaration.
+///
+/// It is defined by the [static_detour](./macro.static_detour.html) macro when
+/// a target is specified (e.g `static Test: fn(i32) -> i32 = add5;`). The
+/// target expression is not evaluated until the detour is initialized.
+///
+/// It dereferences to a [StaticDetour](./struct.StaticDetour.html), providing
+/// the remaining methods (e.g `enable`, `set_detour` and `call`).
+///
+/// # Example
+///
+/// ```rust
+/// # use detour::{Result, static_detour};
+/// static_detour! {
+///   static Test: fn(i32) -> i32 = add5;
+/// }
+///
+/// fn add5(val: i32) -> i32 {
+///   val + 5
+/// }
+///
+/// # fn main() -> Result<()> {
+/// unsafe { Test.install(|val| Test.call(val) * 2)? };
+/// assert_eq!(add5(1), 12);
+///
+/// unsafe { Test.disable()? };
+/// assert_eq!(add5(1), 6);
+/// # Ok(())
+/// # }
+/// ```
+pub struct BoundStaticDetour<T: StaticFunction> {
+  detour: StaticDetour<T>,
+  target: fn() -> T,
+}
+
+impl<T: StaticFunction> BoundStaticDetour<T> {
+  /// Create a new static detour, bound to a target.
+  #[doc(hidden)]
+  pub const fn __new(ffi: T, target: fn() -> T) -> Self {
+    BoundStaticDetour {
+      detour: StaticDetour::__new(ffi),
+      target,
+    }
+  }
+
+  /// Create a new hook given a compatible detour closure.
+  ///
+  /// This method can only be called once per static instance. Multiple calls
+  /// will error with `AlreadyExisting`.
+  #[cfg(feature = "nightly")]
+  pub unsafe fn initialize<D>(&self, closure: D) -> Result<&Self>
+  where
+    D: Fn<T::Arguments, Output = T::Output> + Send + 'static,
+    T::Arguments: Tuple,
   {
-    let mut detour = Box::new(GenericDetour::new(target, self.ffi)?);
+    let function = function_of::<T, D>(&closure);
+    self
+      .detour
+      .initialize_boxed((self.target)(), Box::new(closure), function)?;
+    Ok(self)
+  }
+
+  /// Create a new hook given a compatible detour closure.
+  ///
+  /// This method can only be called once per static instance. Multiple calls
+  /// will error with `AlreadyExisting`.
+  #[cfg(not(feature = "nightly"))]
+  pub unsafe fn initialize<D>(&self, closure: D) -> Result<&Self>
+  where
+    D: crate::StaticClosure<T>,
+  {
+    let function = closure.to_function();
+    self
+      .detour
+      .initialize_boxed((self.target)(), closure.into_boxed(), function)?;
+    Ok(self)
+  }
+
+  /// Create a new hook given a compatible detour closure, and enables it.
+  #[cfg(feature = "nightly")]
+  pub unsafe fn install<D>(&self, closure: D) -> Result<&Self>
+  where
+    D: Fn<T::Arguments, Output = T::Output> + Send + 'static,
+    T::Arguments: Tuple,
+  {
+    self.initialize(closure)?.enable()?;
+    Ok(self)
+  }
+
+  /// Create a new hook given a compatible detour closure, and enables it.
+  #[cfg(not(feature = "nightly"))]
+  pub unsafe fn install<D>(&self, closure: D) -> Result<&Self>
+  where
+    D: crate::StaticClosure<T>,
+  {
+    self.initialize(closure)?.enable()?;
+    Ok(self)
+  }
+}
+
+impl<T: StaticFunction> Deref for BoundStaticDetour<T> {
+  type Target = StaticDetour<T>;
+
+  fn deref(&self) -> &StaticDetour<T> {
+    &self.detour
+  }
+}
+
+/// Converts a resolved address into a function, unless it's null.
+#[doc(hidden)]
+pub unsafe fn __resolve_address<T: Function>(address: *const ()) -> Result<T> {
+  if address.is_null() {
+    Err(Error::NullPointer)
+  } else {
+    Ok(T::from_ptr(address))
+  }
+}
+
+/// Resolves a function exported by any module loaded within the process.
+#[cfg(feature = "std")]
+#[doc(hidden)]
+pub unsafe fn __resolve_loaded<T: Function>(name: &str) -> Result<T> {
+  crate::os::Native::resolve_symbol(name)
+    .map(|address| T::from_ptr(address))
+    .ok_or_else(|| Error::UnknownSymbol { name: name.into() })
+}
+
+/// Resolves a function exported by a library, given a `library!symbol`
+/// specification.
+///
+/// The library is loaded if required, and remains loaded for the remainder of
+/// the process (as a static detour may patch it indefinitely).
+#[cfg(feature = "libloading")]
+#[doc(hidden)]
+pub unsafe fn __resolve_symbol<T: Function>(library: &str, symbol: &str) -> Result<T> {
+  let not_found = |error| Error::SymbolNotFound {
+    name: std::format!("{}!{}", library, symbol),
+    error,
+  };
+
+  let library = libloading::Library::new(library).map_err(not_found)?;
+  let target = library
+    .get::<T>(symbol.as_bytes())
+    .map(|symbol| *symbol)
+    .map_err(not_found)?;
+
+  mem::forget(library);
+  Ok(target)
+}
+
+/// A static detour for a function type with reference arguments.
+///
+/// A function type with elided lifetimes (e.g `fn(&str)`) is generic over
+/// them, and cannot implement [Function](./trait.Function.html). Instead, the
+/// [static_detour](./macro.static_detour.html) macro defines a dedicated type
+/// for each such detour, with the same interface as `StaticDetour`, built
+/// upon this type.
+#[doc(hidden)]
+pub struct StaticRefDetour<F: Copy, C: ?Sized> {
+  closure: AtomicPtr<Box<C>>,
+  detour: AtomicPtr<RawDetour>,
+  ffi: F,
+}
+
+impl<F: Copy, C: ?Sized> StaticRefDetour<F, C> {
+  /// Create a new static detour.
+  pub const fn __new(ffi: F) -> Self {
+    StaticRefDetour {
+      closure: AtomicPtr::new(ptr::null_mut()),
+      detour: AtomicPtr::new(ptr::null_mut()),
+      ffi,
+    }
+  }
+
+  /// Create a new hook given a target function and a boxed detour closure.
+  ///
+  /// `F` must be a function pointer type.
+  pub unsafe fn initialize_boxed(&self, target: F, closure: Box<C>) -> Result<&Self> {
+    let mut detour = Box::new(RawDetour::with_shims(
+      Self::to_ptr(target),
+      Self::to_ptr(self.ffi),
+      super::caller_shims(),
+    )?);
     if self
       .detour
       .compare_exchange(
@@ -120,7 +680,7 @@ impl<T: Function> StaticDetour<T> {
       Err(Error::AlreadyInitialized)?;
     }
 
-    self.set_detour(closure);
+    self.set_detour_boxed(closure);
     mem::forget(detour);
     Ok(self)
   }
@@ -153,40 +713,47 @@ impl<T: Function> StaticDetour<T> {
   }
 
   /// Changes the detour, regardless of whether the hook is enabled or not.
-  pub fn set_detour<C>(&self, closure: C)
-  where
-    C: Fn<T::Arguments, Output = T::Output> + Send + 'static, <T as Function>::Arguments: Tuple
-  {
+  pub fn set_detour_boxed(&self, closure: Box<C>) {
     let previous = self
       .closure
-      .swap(Box::into_raw(Box::new(Box::new(closure))), Ordering::SeqCst);
+      .swap(Box::into_raw(Box::new(closure)), Ordering::SeqCst);
     if !previous.is_null() {
       mem::drop(unsafe { Box::from_raw(previous) });
     }
   }
 
-  /// Returns a reference to the generated trampoline.
-  pub(crate) fn trampoline(&self) -> Result<&()> {
-    Ok(
-      unsafe { self.detour.load(Ordering::SeqCst).as_ref() }
-        .ok_or(Error::NotInitialized)?
-        .trampoline(),
-    )
+  /// Returns the generated trampoline, typed as the target function.
+  pub fn trampoline(&self) -> Result<F> {
+    let detour =
+      unsafe { self.detour.load(Ordering::SeqCst).as_ref() }.ok_or(Error::NotInitialized)?;
+    let trampoline = detour.try_trampoline()? as *const ();
+    Ok(unsafe { mem::transmute_copy(&trampoline) })
   }
 
   /// Returns a transient reference to the active detour.
-  #[doc(hidden)]
-  pub fn __detour(&self) -> &dyn Fn<T::Arguments, Output = T::Output>
-    where <T as Function>::Arguments: Tuple
-  {
-    // TODO: This is not 100% thread-safe in case the thread is stopped
+  pub fn __detour(&self) -> &C {
+    self.__try_detour().expect("retrieving detour closure")
+  }
+
+  /// Returns a transient reference to the active detour, if any.
+  pub fn __try_detour(&self) -> Result<&C> {
+    if self.detour.load(Ordering::SeqCst).is_null() {
+      return Err(Error::NotInitialized);
+    }
+
     unsafe { self.closure.load(Ordering::SeqCst).as_ref() }
-      .ok_or(Error::NotInitialized)
-      .expect("retrieving detour closure")
+      .map(|closure| &**closure)
+      .ok_or(Error::NoDetourSet)
+  }
+
+  /// Converts a function pointer to an untyped pointer.
+  unsafe fn to_ptr(function: F) -> *const () {
+    assert_eq!(mem::size_of::<F>(), mem::size_of::<*const ()>());
+    mem::transmute_copy(&function)
   }
 }
 
-impl<T: Function> Drop for StaticDetour<T> {
+impl<F: Copy, C: ?Sized> Drop for StaticRefDetour<F, C> {
   fn drop(&mut self) {
     let previous = self.closure.swap(ptr::null_mut(), Ordering::Relaxed);
     if !previous.is_null() {
@@ -195,7 +762,7 @@ impl<T: Function> Drop for StaticDetour<T> {
 
     let previous = self.detour.swap(ptr::null_mut(), Ordering::Relaxed);
     if !previous.is_null() {
-      unsafe { Box::from_raw(previous) };
+      mem::drop(unsafe { Box::from_raw(previous) });
     }
   }
 }
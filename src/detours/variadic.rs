@@ -0,0 +1,91 @@
+use crate::arch::Detour;
+use crate::error::Result;
+use crate::{HookableWith, VariadicFunction};
+use core::marker::PhantomData;
+
+/// A type-safe detour for variadic functions.
+///
+/// The variadic arguments cannot be captured by a closure, therefore the detour
+/// must be a function with the same prototype as the target. The original
+/// function can be invoked using the typed trampoline, although the variadic
+/// arguments must be explicitly forwarded (e.g by reading them from the
+/// detour's `VaList`).
+///
+/// # Example
+///
+/// ```rust
+/// # #![feature(c_variadic)]
+/// # use detour::Result;
+/// use detour::VariadicDetour;
+/// use std::sync::atomic::{AtomicPtr, Ordering};
+/// use std::{mem, ptr};
+///
+/// type FnSum = unsafe extern "C" fn(i32, ...) -> i32;
+///
+/// static ORIGINAL: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+///
+/// #[inline(never)]
+/// unsafe extern "C" fn sum(count: i32, mut args: ...) -> i32 {
+///   (0..count).map(|_| args.next_arg::<i32>()).sum()
+/// }
+///
+/// unsafe extern "C" fn sum_twice(count: i32, mut args: ...) -> i32 {
+///   // This assumes that the function is always called with two arguments
+///   let (x, y) = (args.next_arg::<i32>(), args.next_arg::<i32>());
+///   let original: FnSum = mem::transmute(ORIGINAL.load(Ordering::SeqCst));
+///   original(count, x, y) * 2
+/// }
+///
+/// # fn main() -> Result<()> {
+/// let hook = unsafe { VariadicDetour::<FnSum>::new(sum, sum_twice)? };
+/// ORIGINAL.store(hook.trampoline() as *mut (), Ordering::SeqCst);
+///
+/// unsafe { hook.enable()? };
+/// assert_eq!(unsafe { sum(2, 5, 10) }, 30);
+/// assert_eq!(unsafe { hook.trampoline()(2, 5, 10) }, 15);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct VariadicDetour<T: VariadicFunction> {
+  phantom: PhantomData<T>,
+  detour: Detour,
+}
+
+impl<T: VariadicFunction> VariadicDetour<T> {
+  /// Create a new hook given a target function and a compatible detour
+  /// function.
+  pub unsafe fn new<D>(target: T, detour: D) -> Result<Self>
+  where
+    T: HookableWith<D>,
+    D: VariadicFunction,
+  {
+    Detour::new(target.to_ptr(), detour.to_ptr()).map(|detour| VariadicDetour {
+      phantom: PhantomData,
+      detour,
+    })
+  }
+
+  /// Enables the detour.
+  pub unsafe fn enable(&self) -> Result<()> {
+    self.detour.enable()
+  }
+
+  /// Disables the detour.
+  pub unsafe fn disable(&self) -> Result<()> {
+    self.detour.disable()
+  }
+
+  /// Returns whether the detour is enabled or not.
+  pub fn is_enabled(&self) -> bool {
+    self.detour.is_enabled()
+  }
+
+  /// Returns the generated trampoline, typed as the target function.
+  pub fn trampoline(&self) -> T {
+    unsafe { T::from_ptr(self.detour.trampoline() as *const ()) }
+  }
+}
+
+unsafe impl<T: VariadicFunction> Send for VariadicDetour<T> {}
+unsafe impl<T: VariadicFunction> Sync for VariadicDetour<T> {}
@@ -1,22 +1,43 @@
 //! Error types and utilities.
 
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::Range;
+#[cfg(feature = "std")]
 use std::error::Error as StdError;
-use std::fmt;
 
 /// The result of a detour operation.
-pub type Result<T> = ::std::result::Result<T, Error>;
+pub type Result<T> = ::core::result::Result<T, Error>;
 
 /// A representation of all possible errors.
+///
+/// Variants may be added in future releases; prefer [kind](#method.kind) for
+/// deciding how to react to an error.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum Error {
   /// The address for the target and detour are identical
   SameAddress,
   /// The address does not contain valid instructions.
-  InvalidCode,
+  InvalidCode {
+    /// A listing of the target's instructions, with the `disassembly`
+    /// feature.
+    details: Option<String>,
+  },
   /// The address has no available area for patching.
   NoPatchArea,
   /// The address is not executable memory.
   NotExecutable,
+  /// The address of the detour is not executable memory.
+  DetourNotExecutable,
+  /// The target or detour address is null.
+  NullPointer,
+  /// The target is part of a trampoline allocated by the library, or of the
+  /// library's own patching code.
+  SelfHook,
+  /// The detour cannot be reached by a relative jump from the target.
+  OutOfRange,
   /// The detour is not initialized.
   NotInitialized,
   /// The detour is already initialized.
@@ -24,39 +45,608 @@ pub enum Error {
   /// The system is out of executable memory.
   OutOfMemory,
   /// The address contains an instruction that prevents detouring.
-  UnsupportedInstruction,
+  UnsupportedInstruction {
+    /// The absolute address of the instruction.
+    address: usize,
+    /// The offset of the instruction from the start of the function.
+    offset: usize,
+    /// The leading bytes (at most 16) of the instruction.
+    bytes: Vec<u8>,
+    /// A listing of the target's instructions, with the `disassembly`
+    /// feature.
+    details: Option<String>,
+  },
+  /// No operating system backend has been installed.
+  MissingBackend,
+  /// The operation is not permitted in loader-safe mode.
+  LoaderUnsafe,
+  /// No registered region within range has sufficient space.
+  RegionExhausted,
+  /// The operating system denied an operation (e.g mapping executable
+  /// memory).
+  PermissionDenied {
+    /// The operation that was denied (e.g `mmap`).
+    operation: &'static str,
+    /// The underlying error.
+    error: OsError,
+  },
+  /// No free memory could be found close to a target.
+  NoMemoryInRange {
+    /// The address the memory was required to be close to.
+    origin: usize,
+    /// The address range that was searched.
+    range: Range<usize>,
+  },
+  /// The operating system failed to map memory.
+  AllocationFailed {
+    /// The operation that failed (e.g `mmap`).
+    operation: &'static str,
+    /// The underlying error.
+    error: OsError,
+  },
   /// A memory operation failed.
+  #[cfg(all(feature = "std", not(any(target_os = "netbsd", target_os = "openbsd"))))]
   RegionFailure(region::Error),
+  /// All debug registers are occupied by hardware breakpoints.
+  NoDebugRegister,
+  /// A function pointer slot no longer contains the detour.
+  SlotChanged,
+  /// A patch was rejected by a callback.
+  PatchRejected,
+  /// Another patch operation is in progress, and the operation must not block
+  /// (e.g when committing a prepared patch).
+  WouldBlock,
+  /// A configured option has an invalid value.
+  InvalidOption {
+    /// The name of the option.
+    name: &'static str,
+  },
+  /// A static detour has no closure set.
+  NoDetourSet,
+  /// Mapping anonymous executable memory was denied (e.g by the SELinux
+  /// `execmem` policy on Android), and so was each dual mapping fallback.
+  ExecutableMemoryDenied {
+    /// The error mapping anonymous executable memory failed with.
+    error: OsError,
+    /// Each fallback attempted (e.g `memfd`), in order, and its error.
+    fallbacks: Vec<(&'static str, Error)>,
+  },
+  /// A symbol could not be found within the modules loaded by the process.
+  UnknownSymbol {
+    /// The name of the symbol.
+    name: String,
+  },
+  /// A module is not loaded by the process.
+  UnknownModule {
+    /// The name of the module.
+    name: String,
+  },
+  /// An exported symbol is forwarded to another module, and cannot be
+  /// detoured within the exporting module.
+  ForwardedExport {
+    /// The symbol the export is forwarded to (e.g `NTDLL.RtlAllocateHeap`).
+    forwarder: String,
+  },
+  /// The address is ARM64EC code within an emulated process, which cannot be
+  /// patched.
+  ArmCode,
+  /// The address is an ARM64EC fast-forward sequence (i.e an x64 thunk
+  /// exported in place of an ARM64EC function), whose detour would miss calls
+  /// to the real entry.
+  FastForwardThunk {
+    /// The address of the function's ARM64EC entry.
+    entry: usize,
+  },
+  /// Handed off state is malformed, was exported by an incompatible version of
+  /// the library, or its target no longer contains the patch.
+  IncompatibleState,
+  /// No static detour is registered under a handoff key.
+  UnknownKey {
+    /// The key of the handed off detour.
+    key: String,
+  },
+  /// A closure thunk cannot be generated for a function type, due to its
+  /// calling convention or a parameter that is not a scalar.
+  UnsupportedSignature {
+    /// The function type's signature.
+    signature: String,
+  },
+  /// A library symbol could not be found.
+  #[cfg(feature = "libloading")]
+  SymbolNotFound {
+    /// The name of the symbol.
+    name: std::string::String,
+    /// The underlying error.
+    error: libloading::Error,
+  },
 }
 
+#[cfg(feature = "std")]
 impl StdError for Error {
   fn source(&self) -> Option<&(dyn StdError + 'static)> {
-    if let Error::RegionFailure(error) = self {
-      Some(error)
-    } else {
-      None
+    match self {
+      Error::PermissionDenied { error, .. }
+      | Error::AllocationFailed { error, .. }
+      | Error::ExecutableMemoryDenied { error, .. } => Some(error),
+      #[cfg(not(any(target_os = "netbsd", target_os = "openbsd")))]
+      Error::RegionFailure(region::Error::SystemCall(error)) => Some(error),
+      #[cfg(not(any(target_os = "netbsd", target_os = "openbsd")))]
+      Error::RegionFailure(error) => Some(error),
+      #[cfg(feature = "libloading")]
+      Error::SymbolNotFound { error, .. } => Some(error),
+      _ => None,
     }
   }
 }
 
-impl fmt::Display for Error {
-  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+/// A category of errors, describing how they may be handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+  /// The target's code cannot be detoured (e.g an unsupported prolog).
+  Unsupported,
+  /// The operation is denied by a policy of the system or library.
+  PermissionDenied,
+  /// Memory is exhausted, either within range of the target or entirely.
+  ResourceExhausted,
+  /// An argument is invalid (e.g a null or non-executable address).
+  InvalidInput,
+  /// A required item (e.g a library symbol) could not be found.
+  NotFound,
+  /// The operation conflicts with a prior one (e.g a repeated
+  /// initialization).
+  Conflict,
+  /// The operation requires prior initialization or configuration.
+  InvalidState,
+  /// An operating system call failed.
+  Os,
+}
+
+impl Error {
+  /// Returns the category of the error.
+  pub fn kind(&self) -> ErrorKind {
+    match self {
+      Error::InvalidCode { .. }
+      | Error::NoPatchArea
+      | Error::UnsupportedInstruction { .. }
+      | Error::ArmCode
+      | Error::FastForwardThunk { .. }
+      | Error::UnsupportedSignature { .. } => ErrorKind::Unsupported,
+      Error::LoaderUnsafe
+      | Error::PermissionDenied { .. }
+      | Error::PatchRejected
+      | Error::ExecutableMemoryDenied { .. } => ErrorKind::PermissionDenied,
+      Error::OutOfMemory
+      | Error::RegionExhausted
+      | Error::NoMemoryInRange { .. }
+      | Error::NoDebugRegister => ErrorKind::ResourceExhausted,
+      Error::SameAddress
+      | Error::NotExecutable
+      | Error::DetourNotExecutable
+      | Error::NullPointer
+      | Error::SelfHook
+      | Error::OutOfRange
+      | Error::InvalidOption { .. }
+      | Error::ForwardedExport { .. }
+      | Error::IncompatibleState => ErrorKind::InvalidInput,
+      Error::AlreadyInitialized | Error::SlotChanged | Error::WouldBlock => ErrorKind::Conflict,
+      Error::NotInitialized | Error::MissingBackend | Error::NoDetourSet => ErrorKind::InvalidState,
+      Error::AllocationFailed { .. } => ErrorKind::Os,
+      #[cfg(all(feature = "std", not(any(target_os = "netbsd", target_os = "openbsd"))))]
+      Error::RegionFailure(_) => ErrorKind::Os,
+      Error::UnknownSymbol { .. } | Error::UnknownModule { .. } | Error::UnknownKey { .. } => {
+        ErrorKind::NotFound
+      },
+      #[cfg(feature = "libloading")]
+      Error::SymbolNotFound { .. } => ErrorKind::NotFound,
+    }
+  }
+
+  /// Returns a multi-line description of the code causing the error, if
+  /// available.
+  ///
+  /// This is a listing of the target's first instructions, marking the
+  /// offending instruction, which requires the `disassembly` feature.
+  pub fn details(&self) -> Option<&str> {
+    match self {
+      Error::InvalidCode { details } | Error::UnsupportedInstruction { details, .. } => {
+        details.as_deref()
+      },
+      _ => None,
+    }
+  }
+
+  /// Writes the error's description, excluding any details.
+  fn fmt_summary(&self, f: &mut fmt::Formatter) -> fmt::Result {
     match self {
       Error::SameAddress => write!(f, "Target and detour address is the same"),
-      Error::InvalidCode => write!(f, "Address contains invalid assembly"),
+      Error::InvalidCode { .. } => write!(f, "Address contains invalid assembly"),
       Error::NoPatchArea => write!(f, "Cannot find an inline patch area"),
       Error::NotExecutable => write!(f, "Address is not executable"),
+      Error::DetourNotExecutable => write!(f, "Detour address is not executable"),
+      Error::NullPointer => write!(f, "Address is null"),
+      Error::SelfHook => write!(
+        f,
+        "Address is within a trampoline or the library's patching code"
+      ),
+      Error::OutOfRange => write!(f, "Detour is out of range of the target"),
       Error::NotInitialized => write!(f, "Detour is not initialized"),
       Error::AlreadyInitialized => write!(f, "Detour is already initialized"),
       Error::OutOfMemory => write!(f, "Cannot allocate memory"),
-      Error::UnsupportedInstruction => write!(f, "Address contains an unsupported instruction"),
+      Error::UnsupportedInstruction {
+        address,
+        offset,
+        ref bytes,
+        ..
+      } => {
+        write!(
+          f,
+          "Unsupported instruction at {:#x} (offset {:#x}):",
+          address, offset
+        )?;
+        bytes.iter().try_for_each(|byte| write!(f, " {:02x}", byte))
+      },
+      Error::MissingBackend => write!(f, "No operating system backend is installed"),
+      Error::LoaderUnsafe => write!(f, "Operation is not permitted in loader-safe mode"),
+      Error::RegionExhausted => write!(f, "No registered region within range has sufficient space"),
+      Error::PermissionDenied { operation, error } => {
+        write!(f, "Permission denied for `{}` with {:#}", operation, error)
+      },
+      Error::NoMemoryInRange { origin, range } => write!(
+        f,
+        "No free memory within {:#x}..{:#x} of target {:#x}",
+        range.start, range.end, origin
+      ),
+      Error::AllocationFailed { operation, error } => {
+        write!(f, "`{}` failed with {:#}", operation, error)
+      },
+      #[cfg(all(feature = "std", not(any(target_os = "netbsd", target_os = "openbsd"))))]
+      Error::RegionFailure(region::Error::SystemCall(ref error)) => match error.raw_os_error() {
+        Some(code) => write!(f, "Memory operation failed with {:#}", OsError(code)),
+        None => write!(f, "Memory operation failed"),
+      },
+      #[cfg(all(feature = "std", not(any(target_os = "netbsd", target_os = "openbsd"))))]
       Error::RegionFailure(ref error) => write!(f, "{}", error),
+      Error::NoDebugRegister => write!(f, "All debug registers are occupied"),
+      Error::SlotChanged => write!(f, "Pointer slot no longer contains the detour"),
+      Error::PatchRejected => write!(f, "Patch rejected by a callback"),
+      Error::WouldBlock => write!(f, "Another patch operation is in progress"),
+      Error::InvalidOption { name } => write!(f, "Invalid value for option `{}`", name),
+      Error::NoDetourSet => write!(f, "No detour closure is set"),
+      Error::ExecutableMemoryDenied {
+        error,
+        ref fallbacks,
+      } => {
+        write!(
+          f,
+          "Executable memory denied with {:#} (e.g by SELinux `execmem`), fallbacks failed:",
+          error
+        )?;
+        fallbacks
+          .iter()
+          .try_for_each(|(name, error)| write!(f, "\n`{}`: {}", name, error))
+      },
+      Error::UnknownSymbol { ref name } => write!(f, "Cannot find symbol `{}`", name),
+      Error::UnknownModule { ref name } => write!(f, "Module `{}` is not loaded", name),
+      Error::ForwardedExport { ref forwarder } => {
+        write!(f, "Export is forwarded to `{}`", forwarder)
+      },
+      Error::ArmCode => write!(f, "Address is ARM64EC code"),
+      Error::FastForwardThunk { entry } => write!(
+        f,
+        "Address is an ARM64EC fast-forward sequence, with its real entry at {:#x}",
+        entry
+      ),
+      Error::IncompatibleState => write!(f, "Handed off state is incompatible"),
+      Error::UnknownKey { ref key } => write!(f, "No static detour is registered as `{}`", key),
+      Error::UnsupportedSignature { ref signature } => {
+        write!(f, "Closure thunks do not support `{}`", signature)
+      },
+      #[cfg(feature = "libloading")]
+      Error::SymbolNotFound {
+        ref name,
+        ref error,
+      } => {
+        write!(f, "Cannot find symbol `{}`: {}", name, error)
+      },
     }
   }
 }
 
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    self.fmt_summary(f)?;
+    match self.details() {
+      Some(details) => write!(f, "\n{}", details),
+      None => Ok(()),
+    }
+  }
+}
+
+#[cfg(all(feature = "std", not(any(target_os = "netbsd", target_os = "openbsd"))))]
 impl From<region::Error> for Error {
   fn from(error: region::Error) -> Self {
     Error::RegionFailure(error)
   }
 }
+
+/// An error code reported by the operating system (i.e `errno` or
+/// `GetLastError`).
+///
+/// The alternate format (`{:#}`) only displays the code, along with a hint for
+/// common codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OsError(pub i32);
+
+impl OsError {
+  /// Returns the error code.
+  pub const fn code(self) -> i32 {
+    self.0
+  }
+
+  /// Returns a hint describing the likely cause of the error, if known.
+  pub fn hint(self) -> Option<&'static str> {
+    #[cfg(all(windows, feature = "std"))]
+    {
+      use winapi::shared::winerror::{
+        ERROR_DYNAMIC_CODE_BLOCKED, ERROR_INVALID_ADDRESS, ERROR_NOACCESS,
+      };
+
+      match self.0 as u32 {
+        ERROR_INVALID_ADDRESS => Some("the address is reserved or not mapped"),
+        ERROR_NOACCESS => Some("the memory is inaccessible"),
+        ERROR_DYNAMIC_CODE_BLOCKED => {
+          Some("dynamic code is blocked by the process's mitigation policy")
+        },
+        _ => None,
+      }
+    }
+
+    #[cfg(not(all(windows, feature = "std")))]
+    None
+  }
+}
+
+#[cfg(feature = "std")]
+impl StdError for OsError {}
+
+impl fmt::Display for OsError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    if f.alternate() {
+      write!(f, "OS error {}", self.0)?;
+    } else {
+      #[cfg(feature = "std")]
+      write!(f, "{}", std::io::Error::from_raw_os_error(self.0))?;
+
+      #[cfg(not(feature = "std"))]
+      write!(f, "OS error {}", self.0)?;
+    }
+
+    match self.hint() {
+      Some(hint) => write!(f, " ({})", hint),
+      None => Ok(()),
+    }
+  }
+}
+
+/// The errors of an operation upon a set of hooks.
+///
+/// Operations upon a [HookSet](./derive.HookSet.html) are attempted for every
+/// hook, in declaration order, and each failure is recorded along with the
+/// name of its hook.
+#[derive(Debug)]
+pub struct HookSetError {
+  errors: Vec<(&'static str, Error)>,
+}
+
+impl HookSetError {
+  /// Returns the failed hooks' names and errors, in declaration order.
+  pub fn errors(&self) -> &[(&'static str, Error)] {
+    &self.errors
+  }
+
+  /// Consumes the error, returning the failed hooks' names and errors.
+  pub fn into_errors(self) -> Vec<(&'static str, Error)> {
+    self.errors
+  }
+
+  /// Creates an empty set of errors.
+  #[doc(hidden)]
+  pub fn __new() -> Self {
+    HookSetError { errors: Vec::new() }
+  }
+
+  /// Records the result of a hook's operation.
+  #[doc(hidden)]
+  pub fn __record<T>(&mut self, name: &'static str, result: Result<T>) {
+    if let Err(error) = result {
+      self.errors.push((name, error));
+    }
+  }
+
+  /// Returns the recorded errors, if any.
+  #[doc(hidden)]
+  pub fn __finish(self) -> ::core::result::Result<(), Self> {
+    if self.errors.is_empty() {
+      Ok(())
+    } else {
+      Err(self)
+    }
+  }
+}
+
+#[cfg(feature = "std")]
+impl StdError for HookSetError {
+  fn source(&self) -> Option<&(dyn StdError + 'static)> {
+    self
+      .errors
+      .first()
+      .map(|(_, error)| error as &(dyn StdError + 'static))
+  }
+}
+
+impl fmt::Display for HookSetError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{} hook(s) failed", self.errors.len())?;
+    self
+      .errors
+      .iter()
+      .try_for_each(|(name, error)| write!(f, "\n`{}`: {}", name, error))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use alloc::vec;
+
+  #[test]
+  fn error_kinds() {
+    let error = OsError(1);
+    let errors = vec![
+      (Error::SameAddress, ErrorKind::InvalidInput),
+      (Error::InvalidCode { details: None }, ErrorKind::Unsupported),
+      (Error::NoPatchArea, ErrorKind::Unsupported),
+      (Error::NotExecutable, ErrorKind::InvalidInput),
+      (Error::DetourNotExecutable, ErrorKind::InvalidInput),
+      (Error::NullPointer, ErrorKind::InvalidInput),
+      (Error::SelfHook, ErrorKind::InvalidInput),
+      (Error::OutOfRange, ErrorKind::InvalidInput),
+      (Error::NotInitialized, ErrorKind::InvalidState),
+      (Error::AlreadyInitialized, ErrorKind::Conflict),
+      (Error::OutOfMemory, ErrorKind::ResourceExhausted),
+      (
+        Error::UnsupportedInstruction {
+          address: 0x1000,
+          offset: 0,
+          bytes: vec![0xE2, 0x00],
+          details: None,
+        },
+        ErrorKind::Unsupported,
+      ),
+      (Error::MissingBackend, ErrorKind::InvalidState),
+      (Error::LoaderUnsafe, ErrorKind::PermissionDenied),
+      (Error::RegionExhausted, ErrorKind::ResourceExhausted),
+      (Error::NoDebugRegister, ErrorKind::ResourceExhausted),
+      (Error::SlotChanged, ErrorKind::Conflict),
+      (Error::PatchRejected, ErrorKind::PermissionDenied),
+      (Error::WouldBlock, ErrorKind::Conflict),
+      (
+        Error::InvalidOption {
+          name: "detour_range",
+        },
+        ErrorKind::InvalidInput,
+      ),
+      (Error::NoDetourSet, ErrorKind::InvalidState),
+      (
+        Error::ExecutableMemoryDenied {
+          error,
+          fallbacks: vec![(
+            "memfd",
+            Error::PermissionDenied {
+              operation: "mmap(PROT_EXEC) of a shared memory file",
+              error,
+            },
+          )],
+        },
+        ErrorKind::PermissionDenied,
+      ),
+      (
+        Error::PermissionDenied {
+          operation: "mmap",
+          error,
+        },
+        ErrorKind::PermissionDenied,
+      ),
+      (
+        Error::NoMemoryInRange {
+          origin: 0x1000,
+          range: 0..0x2000,
+        },
+        ErrorKind::ResourceExhausted,
+      ),
+      (
+        Error::AllocationFailed {
+          operation: "mmap",
+          error,
+        },
+        ErrorKind::Os,
+      ),
+      (
+        Error::UnknownSymbol {
+          name: "malloc".into(),
+        },
+        ErrorKind::NotFound,
+      ),
+      (
+        Error::UnknownModule {
+          name: "ws2_32.dll".into(),
+        },
+        ErrorKind::NotFound,
+      ),
+      (
+        Error::ForwardedExport {
+          forwarder: "NTDLL.RtlAllocateHeap".into(),
+        },
+        ErrorKind::InvalidInput,
+      ),
+      (Error::ArmCode, ErrorKind::Unsupported),
+      (
+        Error::FastForwardThunk { entry: 0x1000 },
+        ErrorKind::Unsupported,
+      ),
+      (Error::IncompatibleState, ErrorKind::InvalidInput),
+      (
+        Error::UnknownKey { key: "send".into() },
+        ErrorKind::NotFound,
+      ),
+      (
+        Error::UnsupportedSignature {
+          signature: "fn(i32)".into(),
+        },
+        ErrorKind::Unsupported,
+      ),
+      #[cfg(all(feature = "std", not(any(target_os = "netbsd", target_os = "openbsd"))))]
+      (
+        Error::RegionFailure(region::Error::FreeMemory),
+        ErrorKind::Os,
+      ),
+    ];
+
+    for (error, kind) in errors {
+      assert_eq!(error.kind(), kind, "{:?}", error);
+    }
+  }
+
+  #[test]
+  fn executable_memory_denied_lists_fallbacks() {
+    use alloc::string::ToString;
+
+    let error = OsError(13);
+    let denied = Error::ExecutableMemoryDenied {
+      error,
+      fallbacks: vec![
+        (
+          "memfd",
+          Error::PermissionDenied {
+            operation: "mmap(PROT_EXEC) of a shared memory file",
+            error,
+          },
+        ),
+        (
+          "ashmem",
+          Error::AllocationFailed {
+            operation: "open(/dev/ashmem)",
+            error: OsError(2),
+          },
+        ),
+      ],
+    };
+
+    let message = denied.to_string();
+    let lines = message.lines().collect::<Vec<_>>();
+    assert!(lines[0].contains("SELinux `execmem`"));
+    assert!(lines[1].starts_with("`memfd`: Permission denied for `mmap(PROT_EXEC)"));
+    assert!(lines[2].starts_with("`ashmem`: `open(/dev/ashmem)` failed"));
+  }
+}
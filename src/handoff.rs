@@ -0,0 +1,325 @@
+//! Hand off static detours across a reload of the module defining them.
+//!
+//! A module that is hot-reloaded (e.g a plugin rebuilt during development)
+//! would otherwise drop its static detours whilst unloaded, restoring their
+//! targets, and detour them again once reloaded; each target is briefly
+//! unhooked, and written twice. Instead, the outgoing instance
+//! [exports](./fn.export_state.html) its detours, and the incoming instance
+//! [imports](./fn.import_state.html) them, without writing to any target.
+//!
+//! Each participating detour is registered under a key, using
+//! [set_handoff_key](../struct.StaticDetour.html#method.set_handoff_key).
+//! When exported, each enabled detour's patch is redirected (through an
+//! indirect jump owned by the process) to its trampoline, i.e its original
+//! function, and the detour is leaked. When imported, the indirect jump is
+//! redirected to the detour registered under the same key, which becomes
+//! enabled, and owns the patch from then on.
+//!
+//! The state is only meaningful to the same version of this library, within
+//! the same process, and is allocated using the system's allocator, since the
+//! instances may use different global allocators. Each handed off trampoline
+//! and indirect jump is never released, since it may be executing.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use detour::Result;
+//! use detour::{handoff, static_detour};
+//!
+//! static_detour! {
+//!   static Old: fn(i32) -> i32;
+//!   static New: fn(i32) -> i32;
+//! }
+//!
+//! #[inline(never)]
+//! fn add5(val: i32) -> i32 {
+//!   unsafe { std::ptr::read_volatile(&val) + 5 }
+//! }
+//!
+//! # fn main() -> Result<()> {
+//! // Within the outgoing instance
+//! unsafe { Old.initialize(add5, |val| val)?.enable()? };
+//! Old.set_handoff_key("add5");
+//! let state = unsafe { handoff::export_state()? };
+//! assert_eq!(add5(1), 6);
+//!
+//! // Within the incoming instance
+//! New.set_detour(|val| val - 5);
+//! New.set_handoff_key("add5");
+//! let rejected = unsafe { handoff::import_state(state)? };
+//! assert!(rejected.is_empty());
+//! assert_eq!(add5(1), -4);
+//!
+//! unsafe { New.disable()? };
+//! assert_eq!(add5(1), 6);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::arch::Handoff;
+use crate::error::{Error, Result};
+use crate::sync::Mutex;
+use crate::PatchStrategy;
+use core::ffi::c_void;
+use core::mem;
+use core::ptr::NonNull;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::string::String;
+use std::vec;
+use std::vec::Vec;
+
+/// A detour that can be handed off, under a key.
+pub(crate) trait Participant: Sync {
+  /// Hands off the detour, if it's enabled; it's leaked afterwards.
+  unsafe fn hand_off(&self) -> Result<Option<Handoff>>;
+
+  /// Adopts a detour handed off under the same key.
+  unsafe fn adopt(&self, handoff: &Handoff) -> Result<()>;
+}
+
+/// The participants of this instance, along with their keys.
+static PARTICIPANTS: Mutex<Vec<(&'static str, &'static dyn Participant)>> = Mutex::new(Vec::new());
+
+/// Identifies a state exported by this library.
+const MAGIC: usize = 0x6465_746f;
+
+/// The version of the state's encoding.
+const VERSION: usize = 1;
+
+/// Registers a participant, replacing its former key, and any other
+/// participant with the same key.
+pub(crate) fn register(key: &'static str, participant: &'static dyn Participant) {
+  let address = participant as *const dyn Participant as *const ();
+  let mut participants = PARTICIPANTS.lock();
+  participants.retain(|&(other_key, other)| {
+    other_key != key && other as *const dyn Participant as *const () != address
+  });
+  participants.push((key, participant));
+}
+
+/// The state of handed off detours, between two instances of the library.
+///
+/// The state can be passed to another module as a raw pointer. If it's
+/// dropped without being imported, each handed off target is restored.
+#[derive(Debug)]
+pub struct StateHandle(NonNull<usize>);
+
+unsafe impl Send for StateHandle {}
+
+impl StateHandle {
+  /// Consumes the handle, returning a raw pointer to its state.
+  pub fn into_raw(self) -> *mut c_void {
+    let state = self.0.as_ptr() as *mut c_void;
+    mem::forget(self);
+    state
+  }
+
+  /// Constructs a handle from a raw pointer, returned by
+  /// [into_raw](#method.into_raw).
+  ///
+  /// # Safety
+  ///
+  /// The pointer must have been returned by `into_raw`, of either instance,
+  /// and must not be used afterwards.
+  pub unsafe fn from_raw(state: *mut c_void) -> Self {
+    StateHandle(NonNull::new_unchecked(state as *mut usize))
+  }
+
+  /// Returns the keys of the handed off detours, in the order exported.
+  pub fn keys(&self) -> Result<Vec<String>> {
+    Ok(self.decode()?.into_iter().map(|(key, _)| key).collect())
+  }
+
+  /// Encodes handed off detours into a state, allocated by the system.
+  fn encode(records: &[(String, Handoff)]) -> Self {
+    let mut words = vec![MAGIC, VERSION, 0];
+    for (key, handoff) in records {
+      push_bytes(&mut words, key.as_bytes());
+      words.push(handoff.target);
+      words.push(handoff.address);
+      push_bytes(&mut words, &handoff.original);
+      push_bytes(&mut words, &handoff.code);
+      words.push(handoff.trampoline);
+      words.push(handoff.prolog_size);
+      words.push(match handoff.strategy {
+        PatchStrategy::Relative => 0,
+        PatchStrategy::Hop => 1,
+        PatchStrategy::Absolute => 2,
+      });
+      words.push(handoff.cell);
+    }
+    words[2] = words.len();
+
+    unsafe {
+      let state = System.alloc(Self::layout(words.len())) as *mut usize;
+      let state = NonNull::new(state).expect("allocating handed off state");
+      state
+        .as_ptr()
+        .copy_from_nonoverlapping(words.as_ptr(), words.len());
+      StateHandle(state)
+    }
+  }
+
+  /// Decodes the handed off detours of the state.
+  fn decode(&self) -> Result<Vec<(String, Handoff)>> {
+    let header = unsafe { core::slice::from_raw_parts(self.0.as_ptr(), 3) };
+    if header[0] != MAGIC || header[1] != VERSION || header[2] < 3 {
+      Err(Error::IncompatibleState)?;
+    }
+
+    let words = unsafe { core::slice::from_raw_parts(self.0.as_ptr(), header[2]) };
+    let mut reader = Reader { words, offset: 3 };
+    let mut records = Vec::new();
+
+    while reader.offset < words.len() {
+      let key = String::from_utf8(reader.bytes()?).map_err(|_| Error::IncompatibleState)?;
+      let target = reader.word()?;
+      let address = reader.word()?;
+      let original = reader.bytes()?;
+      let code = reader.bytes()?;
+      let trampoline = reader.word()?;
+      let prolog_size = reader.word()?;
+      let strategy = match reader.word()? {
+        0 => PatchStrategy::Relative,
+        1 => PatchStrategy::Hop,
+        2 => PatchStrategy::Absolute,
+        _ => Err(Error::IncompatibleState)?,
+      };
+      let cell = reader.word()?;
+
+      records.push((
+        key,
+        Handoff {
+          target,
+          address,
+          original,
+          code,
+          trampoline,
+          prolog_size,
+          strategy,
+          cell,
+        },
+      ));
+    }
+
+    Ok(records)
+  }
+
+  /// Returns the layout of a state with a number of words.
+  fn layout(words: usize) -> Layout {
+    Layout::array::<usize>(words).expect("handed off state layout")
+  }
+}
+
+impl Drop for StateHandle {
+  /// Restores each handed off target, and releases the state. The state is
+  /// leaked if it cannot be decoded.
+  fn drop(&mut self) {
+    if let Ok(records) = self.decode() {
+      for (_, handoff) in &records {
+        let _ = unsafe { handoff.restore() };
+      }
+
+      let words = unsafe { *self.0.as_ptr().add(2) };
+      unsafe { System.dealloc(self.0.as_ptr() as *mut u8, Self::layout(words)) };
+    }
+  }
+}
+
+/// Reads the words of a state.
+struct Reader<'a> {
+  words: &'a [usize],
+  offset: usize,
+}
+
+impl Reader<'_> {
+  fn word(&mut self) -> Result<usize> {
+    let word = *self
+      .words
+      .get(self.offset)
+      .ok_or(Error::IncompatibleState)?;
+    self.offset += 1;
+    Ok(word)
+  }
+
+  fn bytes(&mut self) -> Result<Vec<u8>> {
+    let size = self.word()?;
+    let count = size.div_ceil(mem::size_of::<usize>());
+    let words = self
+      .words
+      .get(self.offset..self.offset.saturating_add(count))
+      .ok_or(Error::IncompatibleState)?;
+    self.offset += count;
+
+    let bytes = words.iter().flat_map(|word| word.to_ne_bytes());
+    Ok(bytes.take(size).collect())
+  }
+}
+
+/// Appends a byte string to a state, as its length followed by its padded
+/// bytes.
+fn push_bytes(words: &mut Vec<usize>, bytes: &[u8]) {
+  words.push(bytes.len());
+  for chunk in bytes.chunks(mem::size_of::<usize>()) {
+    let mut word = [0; mem::size_of::<usize>()];
+    word[..chunk.len()].copy_from_slice(chunk);
+    words.push(usize::from_ne_bytes(word));
+  }
+}
+
+/// Hands off every enabled detour with a key, for another instance of the
+/// library to import.
+///
+/// Each handed off detour becomes uninitialized, and its target calls the
+/// original function until the state is imported. If a detour cannot be
+/// handed off, those handed off beforehand are restored, and the error is
+/// returned.
+pub unsafe fn export_state() -> Result<StateHandle> {
+  let participants = PARTICIPANTS.lock().clone();
+  let mut records = Vec::new();
+
+  for (key, participant) in participants {
+    match participant.hand_off() {
+      Ok(Some(handoff)) => records.push((String::from(key), handoff)),
+      Ok(None) => (),
+      Err(error) => {
+        for (_, handoff) in &records {
+          let _ = handoff.restore();
+        }
+        return Err(error);
+      },
+    }
+  }
+
+  Ok(StateHandle::encode(&records))
+}
+
+/// Adopts the detours handed off by another instance of the library.
+///
+/// Each handed off detour is adopted by the uninitialized detour registered
+/// under the same key, which becomes enabled. The detours that cannot be
+/// adopted (e.g `Error::UnknownKey` if none is registered) are returned along
+/// with their keys, and their targets are restored. The state is released
+/// either way, unless it's incompatible (`Error::IncompatibleState`), in
+/// which case it's leaked.
+pub unsafe fn import_state(state: StateHandle) -> Result<Vec<(String, Error)>> {
+  let records = state.decode()?;
+  let participants = PARTICIPANTS.lock().clone();
+  let mut rejected = Vec::new();
+
+  for (key, handoff) in &records {
+    let result = match participants.iter().find(|(other, _)| other == key) {
+      Some((_, participant)) => participant.adopt(handoff),
+      None => Err(Error::UnknownKey { key: key.clone() }),
+    };
+
+    if let Err(error) = result {
+      let _ = handoff.restore();
+      rejected.push((key.clone(), error));
+    }
+  }
+
+  let words = *state.0.as_ptr().add(2);
+  System.dealloc(state.into_raw() as *mut u8, StateHandle::layout(words));
+  Ok(rejected)
+}
@@ -0,0 +1,415 @@
+//! Latency measurement of detours, enabled by the `latency` feature.
+//!
+//! Each [StaticDetour](../struct.StaticDetour.html) and
+//! [GenericDetour](../struct.GenericDetour.html) owns a
+//! [Latency](./struct.Latency.html) recorder, which is disabled until
+//! [Latency::set_enabled](./struct.Latency.html#method.set_enabled) is called.
+//! Once enabled, the following durations are recorded for each call:
+//!
+//! - **dispatch**: from entering the generated dispatch function of a static
+//!   detour, until its closure is invoked.
+//! - **closure**: the execution of a static detour's closure, including any
+//!   call of the original function.
+//! - **original**: each call of the original function through `call`.
+//!
+//! Timestamps are read from the time-stamp counter (`rdtsc`), and recorded
+//! into log-linear histograms (similar to HDR histograms, with a relative
+//! error of at most 12.5%). Threads record into separate shards, using relaxed
+//! atomic increments, without locking or allocating. Ticks are converted to
+//! durations when a snapshot is taken.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use detour::Result;
+//! use detour::static_detour;
+//!
+//! static_detour! {
+//!   static Test: fn(i32) -> i32;
+//! }
+//!
+//! #[inline(never)]
+//! fn add5(val: i32) -> i32 {
+//!   unsafe { std::ptr::read_volatile(&val) + 5 }
+//! }
+//!
+//! # fn main() -> Result<()> {
+//! unsafe { Test.initialize(add5, |val| Test.call(val) * 2)?.enable()? };
+//! Test.latency().set_enabled(true);
+//!
+//! for value in 0..100 {
+//!   assert_eq!(add5(value), (value + 5) * 2);
+//! }
+//!
+//! let snapshot = Test.latency().snapshot();
+//! assert_eq!(snapshot.closure.count(), 100);
+//! assert_eq!(snapshot.original.count(), 100);
+//! assert!(snapshot.original.percentile(50.0) <= snapshot.closure.max());
+//! # unsafe { Test.disable()? };
+//! # Ok(())
+//! # }
+//! ```
+
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+use core::{mem, ptr};
+use std::alloc::{self, Layout};
+use std::boxed::Box;
+use std::time::{Duration, Instant};
+use std::vec::Vec;
+
+/// The number of sub-buckets within each power of two.
+const SUB_BUCKET_BITS: u32 = 3;
+
+/// Durations are recorded up to this many ticks (minutes at GHz rates).
+const MAX_TICKS_BITS: u32 = 40;
+
+/// The number of buckets of a histogram.
+const BUCKETS: usize = ((MAX_TICKS_BITS - SUB_BUCKET_BITS + 1) << SUB_BUCKET_BITS) as usize;
+
+/// The number of shards, which threads are assigned to in turn.
+const SHARDS: usize = 8;
+
+/// A recorded duration.
+#[derive(Clone, Copy)]
+pub(crate) enum Metric {
+  Dispatch = 0,
+  Closure = 1,
+  Original = 2,
+}
+
+/// The histograms recorded by a group of threads.
+struct Shard {
+  buckets: [[AtomicU64; BUCKETS]; 3],
+  sums: [AtomicU64; 3],
+}
+
+/// A recorder of the latencies of a detour.
+pub struct Latency {
+  enabled: AtomicBool,
+  shards: AtomicPtr<[Shard; SHARDS]>,
+}
+
+impl Latency {
+  /// Creates a disabled recorder.
+  pub(crate) const fn new() -> Self {
+    Latency {
+      enabled: AtomicBool::new(false),
+      shards: AtomicPtr::new(ptr::null_mut()),
+    }
+  }
+
+  /// Enables or disables recording.
+  ///
+  /// The histograms are allocated once first enabled, and are retained when
+  /// disabled.
+  pub fn set_enabled(&self, enabled: bool) {
+    if enabled && self.shards.load(Ordering::SeqCst).is_null() {
+      // Atomics are valid when zeroed
+      let layout = Layout::new::<[Shard; SHARDS]>();
+      let shards = unsafe { alloc::alloc_zeroed(layout) } as *mut [Shard; SHARDS];
+      if shards.is_null() {
+        alloc::handle_alloc_error(layout);
+      }
+
+      if self
+        .shards
+        .compare_exchange(ptr::null_mut(), shards, Ordering::SeqCst, Ordering::SeqCst)
+        .is_err()
+      {
+        mem::drop(unsafe { Box::from_raw(shards) });
+      }
+    }
+
+    self.enabled.store(enabled, Ordering::SeqCst);
+  }
+
+  /// Returns whether recording is enabled.
+  pub fn is_enabled(&self) -> bool {
+    self.enabled.load(Ordering::Relaxed)
+  }
+
+  /// Returns the latencies recorded by all threads.
+  pub fn snapshot(&self) -> LatencySnapshot {
+    let shards = unsafe { self.shards.load(Ordering::SeqCst).as_ref() };
+    let histogram = |metric: Metric| {
+      let mut histogram = Histogram {
+        counts: std::vec![0; BUCKETS],
+        sum: 0,
+        nanoseconds_per_tick: nanoseconds_per_tick(),
+      };
+
+      for shard in shards.into_iter().flatten() {
+        let buckets = shard.buckets[metric as usize].iter();
+        for (count, bucket) in histogram.counts.iter_mut().zip(buckets) {
+          *count += bucket.load(Ordering::Relaxed);
+        }
+        histogram.sum += shard.sums[metric as usize].load(Ordering::Relaxed);
+      }
+      histogram
+    };
+
+    LatencySnapshot {
+      dispatch: histogram(Metric::Dispatch),
+      closure: histogram(Metric::Closure),
+      original: histogram(Metric::Original),
+    }
+  }
+
+  /// Clears all recorded latencies.
+  ///
+  /// Durations recorded concurrently may be partially cleared.
+  pub fn reset(&self) {
+    let shards = unsafe { self.shards.load(Ordering::SeqCst).as_ref() };
+    for shard in shards.into_iter().flatten() {
+      let buckets = shard.buckets.iter().flatten();
+      for counter in buckets.chain(shard.sums.iter()) {
+        counter.store(0, Ordering::Relaxed);
+      }
+    }
+  }
+
+  /// Starts a measurement, if enabled.
+  #[inline]
+  pub(crate) fn start(&self) -> Option<Timer<'_>> {
+    if self.is_enabled() {
+      Some(Timer {
+        latency: self,
+        start: ticks(),
+      })
+    } else {
+      None
+    }
+  }
+
+  /// Measures the duration of a function, if enabled.
+  #[inline]
+  pub(crate) fn measure<R>(&self, metric: Metric, function: impl FnOnce() -> R) -> R {
+    match self.start() {
+      Some(timer) => {
+        let output = function();
+        timer.lap(metric);
+        output
+      },
+      None => function(),
+    }
+  }
+
+  /// Records a duration within the current thread's shard.
+  #[inline]
+  fn record(&self, metric: Metric, ticks: u64) {
+    let shards = match unsafe { self.shards.load(Ordering::Acquire).as_ref() } {
+      Some(shards) => shards,
+      None => return,
+    };
+
+    let shard = &shards[SHARD.try_with(|shard| *shard).unwrap_or(0)];
+    shard.buckets[metric as usize][bucket(ticks)].fetch_add(1, Ordering::Relaxed);
+    shard.sums[metric as usize].fetch_add(ticks, Ordering::Relaxed);
+  }
+}
+
+impl Default for Latency {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Drop for Latency {
+  fn drop(&mut self) {
+    let shards = self.shards.swap(ptr::null_mut(), Ordering::SeqCst);
+    if !shards.is_null() {
+      mem::drop(unsafe { Box::from_raw(shards) });
+    }
+  }
+}
+
+impl core::fmt::Debug for Latency {
+  fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+    write!(f, "Latency {{ enabled: {} }}", self.is_enabled())
+  }
+}
+
+/// An ongoing measurement.
+pub(crate) struct Timer<'a> {
+  latency: &'a Latency,
+  start: u64,
+}
+
+impl Timer<'_> {
+  /// Records the duration since the measurement (or the previous lap)
+  /// started, and continues the measurement.
+  #[inline]
+  pub fn lap(self, metric: Metric) -> Self {
+    let now = ticks();
+    self.latency.record(metric, now.saturating_sub(self.start));
+    Timer {
+      latency: self.latency,
+      start: now,
+    }
+  }
+}
+
+/// The latencies recorded for a detour.
+#[derive(Debug, Clone)]
+pub struct LatencySnapshot {
+  /// The durations between entering the dispatch function and invoking the
+  /// closure.
+  pub dispatch: Histogram,
+  /// The durations of the closure.
+  pub closure: Histogram,
+  /// The durations of calls to the original function.
+  pub original: Histogram,
+}
+
+/// A histogram of durations.
+#[derive(Debug, Clone)]
+pub struct Histogram {
+  counts: Vec<u64>,
+  sum: u64,
+  nanoseconds_per_tick: f64,
+}
+
+impl Histogram {
+  /// Returns the number of recorded durations.
+  pub fn count(&self) -> u64 {
+    self.counts.iter().sum()
+  }
+
+  /// Returns the mean of the recorded durations.
+  pub fn mean(&self) -> Duration {
+    match self.count() {
+      0 => Duration::ZERO,
+      count => self.duration(self.sum / count),
+    }
+  }
+
+  /// Returns the (approximate) shortest recorded duration.
+  pub fn min(&self) -> Duration {
+    self
+      .counts
+      .iter()
+      .position(|&count| count > 0)
+      .map_or(Duration::ZERO, |index| self.duration(lowest_ticks(index)))
+  }
+
+  /// Returns the (approximate) longest recorded duration.
+  pub fn max(&self) -> Duration {
+    self
+      .counts
+      .iter()
+      .rposition(|&count| count > 0)
+      .map_or(Duration::ZERO, |index| self.duration(highest_ticks(index)))
+  }
+
+  /// Returns the (approximate) duration below which the percentage of
+  /// recorded durations falls (e.g 99.0 for the 99th percentile).
+  pub fn percentile(&self, percentile: f64) -> Duration {
+    let count = self.count();
+    if count == 0 {
+      return Duration::ZERO;
+    }
+
+    let rank = ((percentile.clamp(0.0, 100.0) / 100.0 * count as f64).ceil() as u64).max(1);
+    let mut seen = 0;
+    for (index, &bucket) in self.counts.iter().enumerate() {
+      seen += bucket;
+      if seen >= rank {
+        return self.duration(highest_ticks(index));
+      }
+    }
+    self.max()
+  }
+
+  /// Converts ticks to a duration.
+  fn duration(&self, ticks: u64) -> Duration {
+    Duration::from_nanos((ticks as f64 * self.nanoseconds_per_tick) as u64)
+  }
+}
+
+std::thread_local! {
+  /// The shard the current thread records into.
+  static SHARD: usize = {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    NEXT.fetch_add(1, Ordering::Relaxed) % SHARDS
+  };
+}
+
+/// Returns the index of the bucket containing a duration.
+fn bucket(ticks: u64) -> usize {
+  let ticks = ticks.min((1 << MAX_TICKS_BITS) - 1);
+  if ticks < 1 << SUB_BUCKET_BITS {
+    return ticks as usize;
+  }
+
+  let exponent = 63 - ticks.leading_zeros();
+  let sub_bucket = (ticks >> (exponent - SUB_BUCKET_BITS)) & ((1 << SUB_BUCKET_BITS) - 1);
+  (((exponent - SUB_BUCKET_BITS + 1) << SUB_BUCKET_BITS) as u64 + sub_bucket) as usize
+}
+
+/// Returns the smallest duration within a bucket.
+fn lowest_ticks(index: usize) -> u64 {
+  let sub_buckets = 1 << SUB_BUCKET_BITS;
+  if index < sub_buckets {
+    return index as u64;
+  }
+
+  let exponent = (index >> SUB_BUCKET_BITS) as u32 + SUB_BUCKET_BITS - 1;
+  let sub_bucket = (index & (sub_buckets - 1)) as u64;
+  (sub_buckets as u64 + sub_bucket) << (exponent - SUB_BUCKET_BITS)
+}
+
+/// Returns the largest duration within a bucket.
+fn highest_ticks(index: usize) -> u64 {
+  if index + 1 < BUCKETS {
+    lowest_ticks(index + 1) - 1
+  } else {
+    (1 << MAX_TICKS_BITS) - 1
+  }
+}
+
+/// Reads the time-stamp counter.
+#[inline]
+fn ticks() -> u64 {
+  #[cfg(target_arch = "x86_64")]
+  #[allow(unused_unsafe)]
+  return unsafe { core::arch::x86_64::_rdtsc() };
+
+  #[cfg(target_arch = "x86")]
+  #[allow(unused_unsafe)]
+  return unsafe { core::arch::x86::_rdtsc() };
+}
+
+/// Returns the duration of a tick, calibrated once against the system clock.
+fn nanoseconds_per_tick() -> f64 {
+  static CALIBRATION: AtomicU64 = AtomicU64::new(0);
+
+  match CALIBRATION.load(Ordering::Relaxed) {
+    0 => {
+      let (instant, start) = (Instant::now(), ticks());
+      while instant.elapsed() < Duration::from_millis(10) {
+        core::hint::spin_loop();
+      }
+
+      let elapsed = (instant.elapsed().as_nanos() as f64, ticks() - start);
+      let calibration = elapsed.0 / elapsed.1.max(1) as f64;
+      CALIBRATION.store(calibration.to_bits(), Ordering::Relaxed);
+      calibration
+    },
+    bits => f64::from_bits(bits),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn bucket_bounds() {
+    for ticks in (0..100_000).chain([1 << 20, (1 << 20) + 12_345, (1 << MAX_TICKS_BITS) - 1]) {
+      let index = bucket(ticks);
+      assert!((lowest_ticks(index)..=highest_ticks(index)).contains(&ticks));
+      assert!(highest_ticks(index) - lowest_ticks(index) <= ticks >> SUB_BUCKET_BITS);
+    }
+    assert_eq!(bucket(u64::MAX), BUCKETS - 1);
+  }
+}
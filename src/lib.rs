@@ -1,13 +1,8 @@
-#![feature(tuple_trait)]
 #![recursion_limit = "1024"]
-#![cfg_attr(
-  feature = "nightly",
-  feature(const_fn_trait_bound, unboxed_closures, abi_thiscall)
-)]
-#![cfg_attr(
-  all(feature = "nightly", test),
-  feature(naked_functions, core_intrinsics, asm)
-)]
+#![no_std]
+#![allow(clippy::missing_safety_doc)]
+#![cfg_attr(feature = "nightly", feature(unboxed_closures, tuple_trait))]
+#![cfg_attr(feature = "vectorcall", feature(abi_vectorcall))]
 
 //! A cross-platform detour library written in Rust.
 //!
@@ -25,11 +20,14 @@
 //! - RIP relative operands.
 //! - Detects NOP-padding.
 //! - Relay for large offsets (>2GB).
-//! - Supports hot patching.
+//! - Supports hot patching, and detects
+//!   [hot-patchable](./struct.HotpatchOptions.html) functions.
+//! - Compatible with Control-flow Enforcement (i.e `endbr` landing pads and
+//!   [shadow stacks](./meta/fn.shadow_stack_enabled.html)).
 //!
 //! ## Detours
 //!
-//! Three different types of detours are provided:
+//! Ten different types of detours are provided:
 //!
 //! - [Static](./struct.StaticDetour.html): A static & type-safe interface.
 //!   Thanks to its static nature it can accept a closure as its detour, but is
@@ -39,16 +37,88 @@
 //!   prototype is enforced for both the target and the detour. It is also
 //!   enforced when invoking the original target.
 //!
+//! - [Variadic](./struct.VariadicDetour.html): A type-safe interface for
+//!   variadic C functions. The detour must be a function with an identical
+//!   prototype, and the original is invoked using a typed trampoline.
+//!
+//! - [Pointer](./struct.PointerDetour.html): A type-safe interface for function
+//!   pointers stored in data (e.g callbacks or dispatch tables). The pointer is
+//!   swapped instead of patching code.
+//!
+//! - [Multi](./struct.MultiDetour.html): A type-safe interface redirecting
+//!   multiple targets to a single detour, which retrieves the index of the
+//!   invoked target. Requires `std`.
+//!
+//! - [Entry](./struct.EntryDetour.html): Calls a function with the registers of
+//!   each call, before executing the original function unchanged. The target's
+//!   prototype does not need to be known.
+//!
+//! - [Breakpoint](./struct.BreakpointDetour.html): Redirects the target using a
+//!   single-byte breakpoint and an exception handler, for targets that cannot
+//!   tolerate a jump being written. It's considerably slower, and requires
+//!   `std`.
+//!
+//! - [Hardware breakpoint](./struct.HwBreakpointDetour.html): Redirects the
+//!   target using a debug register, without modifying its code. At most four
+//!   may exist simultaneously.
+//!
+//! - [Page guard](./struct.PageGuardDetour.html): Redirects the target by
+//!   guarding the page containing it, without modifying its code or using a
+//!   debug register. All other code on the page is severely slowed down.
+//!
 //! - [Raw](./struct.RawDetour.html): The underlying building block that the
 //!   others types abstract upon. It has no type-safety and interacts with raw
 //!   pointers. It should be avoided unless any types are references, or not
 //!   known until runtime.
 //!
+//! For custom patch strategies, the inline patch itself is exposed as a
+//! [Patcher](./struct.Patcher.html). It redirects a target without generating
+//! a trampoline, and lets the caller control when and how the patch area is
+//! written. Conversely, a [Trampoline](./struct.Trampoline.html) relocates a
+//! target's prolog without patching it, for targets patched by other means.
+//!
 //! ## Features
 //!
-//! - **nightly**: Enabled by default. Required for static detours, due to usage
-//!   of *const_fn* & *unboxed_closures*.   The feature also enables a more
-//!   extensive test suite.
+//! - **nightly**: Enabled by default. Static detours accept any closure bound
+//!   by `Fn<T::Arguments>`, due to usage of *unboxed_closures*, and variadic
+//!   detours are enabled. The feature also enables a more extensive test suite.
+//!   Without it, static detours use closures implementing `StaticClosure`,
+//!   which works on stable, and their targets must implement
+//!   [StaticFunction](./trait.StaticFunction.html).
+//!
+//! - **std**: Enabled by default. Provides the native operating system
+//!   [backend](./os/index.html) and implements `std::error::Error` for
+//!   [Error](./enum.Error.html). Without it, the library only depends upon
+//!   `core` & `alloc`, and a custom backend must be installed using
+//!   [os::set_backend](./os/fn.set_backend.html).
+//!
+//! - **capi**: Exports a [C interface](./capi/index.html) for creating and
+//!   managing detours, described by the `include/detour.h` header.
+//!
+//! - **disassembly**: Attaches a listing of the target's first instructions to
+//!   errors caused by its code (see
+//!   [Error::details](./enum.Error.html#method.details)), formatted by the
+//!   `udis86` C library.
+//!
+//! - **udis86**: Decodes the instructions relocated into trampolines using the
+//!   `udis86` C library (compiled from source), instead of the default pure
+//!   Rust decoder.
+//!
+//! - **macros**: Provides the [detour](./attr.detour.html) attribute, which
+//!   defines a static detour from its detour function (including its target,
+//!   and functions to install and remove it), and the
+//!   [HookSet](./derive.HookSet.html) derive, which defines a group of static
+//!   detours from a struct of detour functions. The
+//!   [hook_extern](./macro.hook_extern.html) macro defines a static detour for
+//!   each function declared within `extern` blocks.
+//!
+//! - **testing**: Provides [fixture functions](./testing/index.html) with
+//!   distinctive prologs, and a helper asserting a detour's behavior, for
+//!   testing code built upon the library (x86/x64 only).
+//!
+//! - **vectorcall**: Implements [Function](./trait.Function.html) for `extern
+//!   "vectorcall"` functions. Requires a nightly compiler, due to usage of
+//!   *abi_vectorcall*.
 //!
 //! ## Platforms
 //!
@@ -94,22 +164,70 @@
 //! Beyond what is shown here, a trampoline is also generated so the original
 //! function can be called regardless whether the function is hooked or not.
 
+extern crate alloc;
+#[cfg(any(feature = "std", test))]
+extern crate std;
+
 // Re-exports
+pub use arch::{configure_hotpatch, hotpatch_options, HotpatchOptions};
+pub use arch::{patch_callbacks, set_patch_callbacks, AfterPatch, BeforePatch, PatchCallbacks};
+pub use arch::{set_drop_error_handler, DropContext, DropErrorHandler};
+pub use arch::{PatchStrategy, PreparedPatch, PrologueFill};
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+pub use arch::{Patcher, RegisterState, RelocationRecord, Trampoline};
 pub use detours::*;
-pub use error::{Error, Result};
-pub use traits::{Function, HookableWith};
+pub use error::{Error, ErrorKind, HookSetError, OsError, Result};
+#[cfg(feature = "std")]
+pub use handoff::{export_state, import_state, StateHandle};
+#[cfg(not(feature = "nightly"))]
+pub use traits::StaticClosure;
+pub use traits::{
+  AbiCompatible, Function, HookCompatible, HookableWith, Signature, StaticFunction,
+  VariadicFunction,
+};
+
+#[cfg(feature = "macros")]
+pub use detour_macros::{detour, hook_extern, HookSet};
+
+#[doc(hidden)]
+pub use alloc::boxed::Box as __Box;
+#[doc(hidden)]
+pub use traits::__type_names;
 
 #[macro_use]
 mod macros;
 
 // Modules
-mod alloc;
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+pub mod analysis;
 mod arch;
+#[cfg(feature = "capi")]
+pub mod capi;
 mod detours;
 mod error;
-mod pic;
+#[cfg(feature = "std")]
+pub mod handoff;
+#[cfg(feature = "latency")]
+pub mod latency;
+pub mod memory;
+pub mod meta;
+pub mod os;
+pub mod pic;
+pub mod pool;
+pub mod profiling;
+mod sync;
+#[cfg(all(feature = "std", any(unix, windows)))]
+pub mod teardown;
+#[cfg(all(
+  any(feature = "testing", test),
+  any(target_arch = "x86", target_arch = "x86_64")
+))]
+pub mod testing;
+#[cfg(feature = "std")]
+pub mod thunk;
 mod traits;
-mod util;
+#[cfg(all(feature = "std", any(unix, windows)))]
+pub mod unload;
 
 #[cfg(test)]
 mod tests {
@@ -159,4 +277,82 @@ mod tests {
     let err = unsafe { RawDetour::new(add as *const (), add as *const ()).unwrap_err() };
     assert_matches!(err, Error::SameAddress);
   }
+
+  #[test]
+  fn invalid_addresses() -> Result<()> {
+    #[inline(never)]
+    extern "C" fn add(x: i32, y: i32) -> i32 {
+      unsafe { std::ptr::read_volatile(&x as *const i32) + y }
+    }
+
+    extern "C" fn sub(x: i32, y: i32) -> i32 {
+      x - y
+    }
+
+    static DATA: [u8; 16] = [0xC3; 16];
+    let (add, sub, data) = (
+      add as *const (),
+      sub as *const (),
+      DATA.as_ptr() as *const (),
+    );
+    let create = |target, detour| unsafe { RawDetour::new(target, detour) }.unwrap_err();
+
+    assert_matches!(create(std::ptr::null(), sub), Error::NullPointer);
+    assert_matches!(create(add, std::ptr::null()), Error::NullPointer);
+    assert_matches!(create(data, sub), Error::NotExecutable);
+    assert_matches!(create(add, data), Error::DetourNotExecutable);
+
+    // A trampoline cannot be detoured itself
+    let hook = unsafe { RawDetour::new(add, sub)? };
+    let trampoline = hook.trampoline() as *const ();
+    assert_matches!(create(trampoline, sub), Error::SelfHook);
+
+    // Nor can the library's own patching code
+    let enable = crate::arch::Detour::enable as *const ();
+    assert_matches!(create(enable, sub), Error::SelfHook);
+
+    // Including addresses within it, e.g its helpers' loops
+    for function in [
+      crate::arch::memory::write_pool as *const (),
+      <os::Native as os::Backend>::protect as *const (),
+    ] {
+      if let Some(range) = analysis::function_bounds(function) {
+        assert_matches!(create((range.end - 1) as *const (), sub), Error::SelfHook);
+      }
+    }
+    Ok(())
+  }
+
+  #[test]
+  #[cfg(target_arch = "x86")]
+  fn detour_thiscall() -> Result<()> {
+    type FnThiscall = extern "thiscall" fn(*const i32, i32) -> i32;
+
+    /// Reads `this` from ECX, and the (callee cleaned) argument from the stack.
+    #[unsafe(naked)]
+    extern "thiscall" fn read_this(_this: *const i32, _value: i32) -> i32 {
+      std::arch::naked_asm!(
+        "
+            mov eax, [ecx]
+            add eax, [esp+4]
+            ret 4"
+      )
+    }
+
+    extern "thiscall" fn read_this_detour(this: *const i32, value: i32) -> i32 {
+      unsafe { *this * value }
+    }
+
+    let this = 10i32;
+    let hook = unsafe { GenericDetour::<FnThiscall>::new(read_this, read_this_detour)? };
+
+    assert_eq!(read_this(&this, 5), 15);
+    unsafe { hook.enable()? };
+    assert_eq!(read_this(&this, 5), 50);
+    assert_eq!(hook.call(&this, 5), 15);
+
+    unsafe { hook.disable()? };
+    assert_eq!(read_this(&this, 5), 15);
+    Ok(())
+  }
 }
@@ -7,13 +7,45 @@
 ///
 /// ```ignore
 /// static_detour! {
-///   [pub] static NAME_1: [unsafe] [extern "cc"] fn([argument]...) [-> ret];
-///   [pub] static NAME_2: [unsafe] [extern "cc"] fn([argument]...) [-> ret];
+///   [#[attr]...] [pub] static NAME_1: [unsafe] [extern "cc"] fn([[name:] argument]...) [-> ret] [= target];
+///   [#[attr]...] [pub] static NAME_2: [unsafe] [extern "cc"] fn([[name:] argument]...) [-> ret] [= target];
 ///   ...
-///   [pub] static NAME_N: [unsafe] [extern "cc"] fn([argument]...) [-> ret];
+///   [#[attr]...] [pub] static NAME_N: [unsafe] [extern "cc"] fn([[name:] argument]...) [-> ret] [= target];
+/// }
+///
+/// static_detour! {
+///   [#[attr]...] [pub] fn NAME: [unsafe] [extern "cc"] fn([[name:] argument]...) [-> ret] [= target];
 /// }
 /// ```
 ///
+/// Attributes (including documentation and `cfg`) are applied to the static,
+/// and any visibility (e.g `pub(crate)`, `pub(in path)`) is supported. Items
+/// generated alongside the static only inherit its `cfg` and `doc` attributes,
+/// therefore the same name may be declared by several entries with different
+/// signatures, as long as their `cfg` predicates are mutually exclusive. The
+/// function type may be any type implementing
+/// [Function](./trait.Function.html), with or without argument names (e.g a
+/// signature copied from `extern "system"` bindings).
+///
+/// Arguments may also be references with elided lifetimes (e.g `&str`,
+/// `&mut T` or `&[T]`). Since such a function type is generic over its
+/// lifetimes, the macro defines a dedicated type for the detour instead of a
+/// `StaticDetour`, exposing the same methods.
+///
+/// The macro may be used in any item position, including function bodies.
+/// Since associated statics do not exist, an entry declared with `fn` instead
+/// of `static` defines an accessor function, returning a reference to a
+/// `StaticDetour` defined within it. This allows detours to be declared as
+/// associated functions within an `impl` block (the function type may not use
+/// any of its generic parameters). Accessors do not support reference
+/// arguments.
+///
+/// A detour may be bound to its target at declaration (e.g `= path::to::fn`),
+/// in which case it is a [BoundStaticDetour](./struct.BoundStaticDetour.html),
+/// whose `initialize` method only accepts the detour closure, and whose
+/// `install` method also enables it. The target expression is evaluated upon
+/// initialization.
+///
 /// # Example
 ///
 /// ```rust
@@ -27,100 +59,182 @@
 ///
 ///   // A specific visibility modifier
 ///   pub(crate) static PubSelf: unsafe extern "C" fn();
+///
+///   /// A detour only defined on Windows.
+///   #[cfg(windows)]
+///   pub static PubWindows: unsafe extern "system" fn(*const u16) -> i32;
+///
+///   // A detour with reference arguments
+///   static Append: fn(buffer: &mut Vec<u8>, data: &[u8]);
+///
+///   // A detour bound to its target
+///   static Abs: fn(i32) -> i32 = i32::abs;
+/// }
+///
+/// static_detour! {
+///   // A detour whose signature depends on the target
+///   #[cfg(target_pointer_width = "64")]
+///   static Width: fn(u64) -> u64;
+///   #[cfg(not(target_pointer_width = "64"))]
+///   static Width: fn(u32) -> u32;
+/// }
+///
+/// struct Hooks;
+///
+/// impl Hooks {
+///   static_detour! {
+///     // An associated accessor, used as `Hooks::bar()`
+///     pub fn bar: unsafe extern "C" fn(i32) -> i32;
+///   }
 /// }
 /// # fn main() { }
 /// ```
-#[cfg(feature = "nightly")]
 #[macro_export]
 // Inspired by: https://github.com/Jascha-N/minhook-rs
 macro_rules! static_detour {
-  // 1 — meta attributes
-  (@parse_attributes ($($input:tt)*) | #[$attribute:meta] $($rest:tt)*) => {
-    static_detour!(@parse_attributes ($($input)* $attribute) | $($rest)*);
+  // 1 — meta attributes (cfg/doc, shared with companion items/other)
+  (@parse_attributes ($($input:tt)*) ($($shared:tt)*) | #[cfg $($predicate:tt)*] $($rest:tt)*) => {
+    $crate::static_detour!(@parse_attributes
+      ($($input)* cfg $($predicate)*) ($($shared)* cfg $($predicate)*) | $($rest)*);
+  };
+  (@parse_attributes ($($input:tt)*) ($($shared:tt)*) | #[doc $($doc:tt)*] $($rest:tt)*) => {
+    $crate::static_detour!(@parse_attributes
+      ($($input)* doc $($doc)*) ($($shared)* doc $($doc)*) | $($rest)*);
   };
-  (@parse_attributes ($($input:tt)*) | $($rest:tt)+) => {
-    static_detour!(@parse_access_modifier (($($input)*)) | $($rest)*);
+  (@parse_attributes ($($input:tt)*) ($($shared:tt)*) | #[$attribute:meta] $($rest:tt)*) => {
+    $crate::static_detour!(@parse_attributes ($($input)* $attribute) ($($shared)*) | $($rest)*);
+  };
+  (@parse_attributes ($($input:tt)*) ($($shared:tt)*) | $($rest:tt)+) => {
+    $crate::static_detour!(@parse_access_modifier (($($input)*) ($($shared)*)) | $($rest)*);
   };
 
   // 2 — pub modifier (path/scope/yes/no)
-  (@parse_access_modifier ($($input:tt)*) | pub(in $vis:path) static $($rest:tt)*) => {
-    static_detour!(@parse_name ($($input)* (pub(in $vis))) | $($rest)*);
+  (@parse_access_modifier ($($input:tt)*) | pub(in $vis:path) $($rest:tt)*) => {
+    $crate::static_detour!(@parse_item ($($input)* (pub(in $vis))) | $($rest)*);
+  };
+  (@parse_access_modifier ($($input:tt)*) | pub($vis:tt) $($rest:tt)*) => {
+    $crate::static_detour!(@parse_item ($($input)* (pub($vis))) | $($rest)*);
   };
-  (@parse_access_modifier ($($input:tt)*) | pub($vis:tt) static $($rest:tt)*) => {
-    static_detour!(@parse_name ($($input)* (pub($vis))) | $($rest)*);
+  (@parse_access_modifier ($($input:tt)*) | pub $($rest:tt)*) => {
+    $crate::static_detour!(@parse_item ($($input)* (pub)) | $($rest)*);
   };
-  (@parse_access_modifier ($($input:tt)*) | pub static $($rest:tt)*) => {
-    static_detour!(@parse_name ($($input)* (pub)) | $($rest)*);
+  (@parse_access_modifier ($($input:tt)*) | $($rest:tt)*) => {
+    $crate::static_detour!(@parse_item ($($input)* ()) | $($rest)*);
   };
-  (@parse_access_modifier ($($input:tt)*) | static $($rest:tt)*) => {
-    static_detour!(@parse_name ($($input)* ()) | $($rest)*);
+
+  // 3 — item (static/accessor function)
+  (@parse_item ($($input:tt)*) | static $($rest:tt)*) => {
+    $crate::static_detour!(@parse_name ($($input)* (static)) | $($rest)*);
+  };
+  (@parse_item ($($input:tt)*) | fn $($rest:tt)*) => {
+    $crate::static_detour!(@parse_name ($($input)* (fn)) | $($rest)*);
   };
 
-  // 3 — detour name
+  // 4 — detour name
   (@parse_name ($($input:tt)*) | $name:ident : $($rest:tt)*) => {
-    static_detour!(@parse_unsafe ($($input)* ($name)) | $($rest)*);
+    $crate::static_detour!(@parse_unsafe ($($input)* ($name)) | $($rest)*);
   };
 
-  // 4 — unsafe modifier (yes/no)
+  // 5 — unsafe modifier (yes/no)
   (@parse_unsafe ($($input:tt)*) | unsafe $($rest:tt)*) => {
-    static_detour!(@parse_calling_convention ($($input)*) (unsafe) | $($rest)*);
+    $crate::static_detour!(@parse_calling_convention ($($input)* (unsafe)) (unsafe) | $($rest)*);
   };
   (@parse_unsafe ($($input:tt)*) | $($rest:tt)*) => {
-    static_detour!(@parse_calling_convention ($($input)*) () | $($rest)*);
+    $crate::static_detour!(@parse_calling_convention ($($input)* ()) () | $($rest)*);
   };
 
-  // 5 — calling convention (extern "XXX"/extern/-)
+  // 6 — calling convention (extern "XXX"/extern/-)
   (@parse_calling_convention
       ($($input:tt)*) ($($modifier:tt)*) | extern $cc:tt fn $($rest:tt)*) => {
-    static_detour!(@parse_prototype ($($input)* ($($modifier)* extern $cc)) | $($rest)*);
+    $crate::static_detour!(@parse_references ($($input)* ($($modifier)* extern $cc)) | $($rest)*);
   };
   (@parse_calling_convention
       ($($input:tt)*) ($($modifier:tt)*) | extern fn $($rest:tt)*) => {
-    static_detour!(@parse_prototype ($($input)* ($($modifier)* extern)) | $($rest)*);
+    $crate::static_detour!(@parse_references ($($input)* ($($modifier)* extern)) | $($rest)*);
   };
   (@parse_calling_convention ($($input:tt)*) ($($modifier:tt)*) | fn $($rest:tt)*) => {
-    static_detour!(@parse_prototype ($($input)* ($($modifier)*)) | $($rest)*);
+    $crate::static_detour!(@parse_references ($($input)* ($($modifier)*)) | $($rest)*);
   };
 
-  // 6 — argument and return type (return/void)
+  // 7 — argument references (any/none), including those within tuples and arrays
+  (@parse_references ($($input:tt)*) | ($($argument:tt)*) $($rest:tt)*) => {
+    $crate::static_detour!(@find_reference ($($input)*) (($($argument)*) $($rest)*) | $($argument)*);
+  };
+  (@find_reference ($($input:tt)*) ($($rest:tt)*) | & $($token:tt)*) => {
+    $crate::static_detour!(@parse_prototype ($($input)* (reference)) | $($rest)*);
+  };
+  (@find_reference ($($input:tt)*) ($($rest:tt)*) | && $($token:tt)*) => {
+    $crate::static_detour!(@parse_prototype ($($input)* (reference)) | $($rest)*);
+  };
+  (@find_reference ($($input:tt)*) ($($rest:tt)*) | ($($group:tt)*) $($token:tt)*) => {
+    $crate::static_detour!(@find_reference ($($input)*) ($($rest)*) | $($group)* $($token)*);
+  };
+  (@find_reference ($($input:tt)*) ($($rest:tt)*) | [$($group:tt)*] $($token:tt)*) => {
+    $crate::static_detour!(@find_reference ($($input)*) ($($rest)*) | $($group)* $($token)*);
+  };
+  (@find_reference ($($input:tt)*) ($($rest:tt)*) | $head:tt $($token:tt)*) => {
+    $crate::static_detour!(@find_reference ($($input)*) ($($rest)*) | $($token)*);
+  };
+  (@find_reference ($($input:tt)*) ($($rest:tt)*) | ) => {
+    $crate::static_detour!(@parse_prototype ($($input)* (value)) | $($rest)*);
+  };
+
+  // 8 — argument names (named/unnamed), and return type (return/void)
   (@parse_prototype
-      ($($input:tt)*) | ($($argument_type:ty),*) -> $return_type:ty ; $($rest:tt)*) => {
-    static_detour!(
-      @parse_terminator ($($input)* ($($argument_type)*) ($return_type)) | ; $($rest)*);
+      ($($input:tt)*) | ($($argument_name:tt : $argument_type:ty),* $(,)?) $($rest:tt)*) => {
+    $crate::static_detour!(@parse_return ($($input)* ($($argument_type)*)) | $($rest)*);
+  };
+  (@parse_prototype ($($input:tt)*) | ($($argument_type:ty),* $(,)?) $($rest:tt)*) => {
+    $crate::static_detour!(@parse_return ($($input)* ($($argument_type)*)) | $($rest)*);
   };
-  (@parse_prototype ($($input:tt)*) | ($($argument_type:ty),*) $($rest:tt)*) => {
-    static_detour!(@parse_terminator ($($input)* ($($argument_type)*) (())) | $($rest)*);
+  (@parse_return ($($input:tt)*) | -> $return_type:ty = $($rest:tt)*) => {
+    $crate::static_detour!(@parse_target ($($input)* ($return_type)) | = $($rest)*);
+  };
+  (@parse_return ($($input:tt)*) | -> $return_type:ty ; $($rest:tt)*) => {
+    $crate::static_detour!(@parse_target ($($input)* ($return_type)) | ; $($rest)*);
+  };
+  (@parse_return ($($input:tt)*) | $($rest:tt)*) => {
+    $crate::static_detour!(@parse_target ($($input)* (())) | $($rest)*);
+  };
+
+  // 9 — target (bound/unbound)
+  (@parse_target ($($input:tt)*) | = $target:expr ; $($rest:tt)*) => {
+    $crate::static_detour!(@parse_terminator ($($input)* ($target)) | ; $($rest)*);
+  };
+  (@parse_target ($($input:tt)*) | $($rest:tt)*) => {
+    $crate::static_detour!(@parse_terminator ($($input)* ()) | $($rest)*);
   };
 
-  // 7 — semicolon terminator
+  // 10 — semicolon terminator
   (@parse_terminator ($($input:tt)*) | ; $($rest:tt)*) => {
-    static_detour!(@parse_entries ($($input)*) | $($rest)*);
+    $crate::static_detour!(@parse_entries ($($input)*) | $($rest)*);
   };
 
-  // 8 - additional detours (multiple/single)
+  // 11 - additional detours (multiple/single)
   (@parse_entries ($($input:tt)*) | $($rest:tt)+) => {
-    static_detour!(@aggregate $($input)*);
-    static_detour!($($rest)*);
+    $crate::static_detour!(@aggregate $($input)*);
+    $crate::static_detour!($($rest)*);
   };
   (@parse_entries ($($input:tt)*) | ) => {
-    static_detour!(@aggregate $($input)*);
+    $crate::static_detour!(@aggregate $($input)*);
   };
 
-  // 9 - aggregate data for the generate function
-  (@aggregate ($($attribute:meta)*) ($($visibility:tt)*) ($name:ident)
-              ($($modifier:tt)*) ($($argument_type:ty)*) ($return_type:ty)) => {
-    static_detour!(@argument_names (create_detour)(
-      ($($attribute)*) ($($visibility)*) ($name)
-      ($($modifier)*) ($($argument_type)*) ($return_type)
+  // 12 - aggregate data for the generate function
+  (@aggregate ($($attribute:meta)*) ($($shared:meta)*) ($($visibility:tt)*) ($item:tt) ($name:ident)
+              ($($unsafe:tt)*) ($($modifier:tt)*) ($kind:ident) ($($argument_type:ty)*)
+              ($return_type:ty) ($($target:expr)?)) => {
+    $crate::static_detour!(@argument_names (create_detour)(
+      ($item) ($kind) ($($target)?) ($($attribute)*) ($($shared)*) ($($visibility)*) ($name)
+      ($($unsafe)*) ($($modifier)*) ($($argument_type)*) ($return_type)
       ($($modifier)* fn ($($argument_type),*) -> $return_type)
     )($($argument_type)*));
   };
 
-  // 10 - detour type implementation
-  (@create_detour ($($argument_name:ident)*) ($($attribute:meta)*) ($($visibility:tt)*)
-                  ($name:ident) ($($modifier:tt)*) ($($argument_type:ty)*)
-                  ($return_type:ty) ($fn_type:ty)) => {
-    static_detour!(@generate
+  // 13 - detour type implementation (value/reference arguments, unbound/bound, static)
+  (@create_detour ($($argument_name:ident)*) (static) (value) () ($($attribute:meta)*)
+                  ($($shared:meta)*) ($($visibility:tt)*) ($name:ident) ($($unsafe:tt)*)
+                  ($($modifier:tt)*) ($($argument_type:ty)*) ($return_type:ty) ($fn_type:ty)) => {
+    $crate::static_detour!(@generate
       #[allow(non_upper_case_globals)]
       $(#[$attribute])*
       $($visibility)* static $name: $crate::StaticDetour<$fn_type> = {
@@ -128,20 +242,235 @@ macro_rules! static_detour {
         #[allow(unused_unsafe)]
         $($modifier) * fn __ffi_detour(
             $($argument_name: $argument_type),*) -> $return_type {
+          let _caller = $crate::__CallerFrame::enter();
           #[allow(unused_unsafe)]
-          ($name.__detour())($($argument_name),*)
+          $name.__dispatch(|__detour: $crate::__Active<$fn_type, _>| match __detour {
+            $crate::__Active::Function(__function) => unsafe { __function($($argument_name),*) },
+            $crate::__Active::Closure(__closure) => __closure($($argument_name),*),
+          })
         }
 
         $crate::StaticDetour::__new(__ffi_detour)
       };
     );
   };
+  (@create_detour ($($argument_name:ident)*) (static) (value) ($target:expr)
+                  ($($attribute:meta)*) ($($shared:meta)*) ($($visibility:tt)*) ($name:ident)
+                  ($($unsafe:tt)*) ($($modifier:tt)*) ($($argument_type:ty)*) ($return_type:ty)
+                  ($fn_type:ty)) => {
+    $crate::static_detour!(@generate
+      #[allow(non_upper_case_globals)]
+      $(#[$attribute])*
+      $($visibility)* static $name: $crate::BoundStaticDetour<$fn_type> = {
+        #[inline(never)]
+        #[allow(unused_unsafe)]
+        $($modifier) * fn __ffi_detour(
+            $($argument_name: $argument_type),*) -> $return_type {
+          let _caller = $crate::__CallerFrame::enter();
+          #[allow(unused_unsafe)]
+          $name.__dispatch(|__detour: $crate::__Active<$fn_type, _>| match __detour {
+            $crate::__Active::Function(__function) => unsafe { __function($($argument_name),*) },
+            $crate::__Active::Closure(__closure) => __closure($($argument_name),*),
+          })
+        }
+
+        fn __target() -> $fn_type {
+          $target
+        }
+
+        $crate::BoundStaticDetour::__new(__ffi_detour, __target)
+      };
+    );
+  };
+  (@create_detour ($($argument_name:ident)*) (static) (reference) ($($target:expr)?)
+                  ($($attribute:meta)*) ($($shared:meta)*) ($($visibility:tt)*) ($name:ident)
+                  ($($unsafe:tt)*) ($($modifier:tt)*) ($($argument_type:ty)*) ($return_type:ty)
+                  ($fn_type:ty)) => {
+    $crate::static_detour!(@generate
+      #[allow(non_camel_case_types)]
+      $(#[$shared])*
+      $($visibility)* struct $name {
+        inner: $crate::StaticRefDetour<$fn_type, dyn Fn($($argument_type),*) -> $return_type + Send>,
+      }
+    );
+
+    $crate::static_detour!(@generate
+      #[allow(non_upper_case_globals)]
+      $(#[$attribute])*
+      $($visibility)* static $name: $name = {
+        #[inline(never)]
+        #[allow(unused_unsafe)]
+        $($modifier) * fn __ffi_detour(
+            $($argument_name: $argument_type),*) -> $return_type {
+          let _caller = $crate::__CallerFrame::enter();
+          #[allow(unused_unsafe)]
+          ($name.inner.__detour())($($argument_name),*)
+        }
+
+        $name { inner: $crate::StaticRefDetour::__new(__ffi_detour) }
+      };
+    );
+
+    $crate::static_detour!(@generate
+      $(#[$shared])*
+      #[allow(dead_code)]
+      impl $name {
+        $crate::static_detour!(@initialize_reference ($($target)?) ($fn_type)
+                               ($($argument_type)*) ($return_type));
+
+        /// Enables the detour.
+        pub unsafe fn enable(&self) -> $crate::Result<()> {
+          self.inner.enable()
+        }
+
+        /// Disables the detour.
+        pub unsafe fn disable(&self) -> $crate::Result<()> {
+          self.inner.disable()
+        }
+
+        /// Returns whether the detour is enabled or not.
+        pub fn is_enabled(&self) -> bool {
+          self.inner.is_enabled()
+        }
+
+        /// Changes the detour, regardless of whether the hook is enabled or not.
+        pub fn set_detour<D>(&self, closure: D)
+        where
+          D: Fn($($argument_type),*) -> $return_type + Send + 'static,
+        {
+          self.inner.set_detour_boxed($crate::__Box::new(closure));
+        }
+
+        /// Calls the original function regardless of whether it's hooked or not.
+        ///
+        /// Panics if called when the static detour has not yet been initialized.
+        pub $($unsafe)* fn call(&self, $($argument_name: $argument_type),*) -> $return_type {
+          let original: $fn_type = self.inner.trampoline().expect("calling detour trampoline");
+          #[allow(unused_unsafe)]
+          unsafe { original($($argument_name),*) }
+        }
+
+        /// Calls the active detour closure, bypassing the target and trampoline.
+        ///
+        /// Returns `NotInitialized` or `NoDetourSet` if there's no closure to call.
+        pub fn call_detour(&self, $($argument_name: $argument_type),*)
+            -> $crate::Result<$return_type> {
+          Ok((self.inner.__try_detour()?)($($argument_name),*))
+        }
+      }
+    );
+  };
+
+  // 14 - detour accessor implementation (value/reference arguments, unbound/bound, function)
+  (@create_detour ($($argument_name:ident)*) (fn) (value) () ($($attribute:meta)*)
+                  ($($shared:meta)*) ($($visibility:tt)*) ($name:ident) ($($unsafe:tt)*)
+                  ($($modifier:tt)*) ($($argument_type:ty)*) ($return_type:ty) ($fn_type:ty)) => {
+    $crate::static_detour!(@generate
+      $(#[$attribute])*
+      $($visibility)* fn $name() -> &'static $crate::StaticDetour<$fn_type> {
+        static __DETOUR: $crate::StaticDetour<$fn_type> = {
+          #[inline(never)]
+          #[allow(unused_unsafe)]
+          $($modifier) * fn __ffi_detour(
+              $($argument_name: $argument_type),*) -> $return_type {
+            let _caller = $crate::__CallerFrame::enter();
+            #[allow(unused_unsafe)]
+            __DETOUR.__dispatch(|__detour: $crate::__Active<$fn_type, _>| match __detour {
+              $crate::__Active::Function(__function) => unsafe { __function($($argument_name),*) },
+              $crate::__Active::Closure(__closure) => __closure($($argument_name),*),
+            })
+          }
+
+          $crate::StaticDetour::__new(__ffi_detour)
+        };
+
+        &__DETOUR
+      }
+    );
+  };
+  (@create_detour ($($argument_name:ident)*) (fn) (value) ($target:expr)
+                  ($($attribute:meta)*) ($($shared:meta)*) ($($visibility:tt)*) ($name:ident)
+                  ($($unsafe:tt)*) ($($modifier:tt)*) ($($argument_type:ty)*) ($return_type:ty)
+                  ($fn_type:ty)) => {
+    $crate::static_detour!(@generate
+      $(#[$attribute])*
+      $($visibility)* fn $name() -> &'static $crate::BoundStaticDetour<$fn_type> {
+        static __DETOUR: $crate::BoundStaticDetour<$fn_type> = {
+          #[inline(never)]
+          #[allow(unused_unsafe)]
+          $($modifier) * fn __ffi_detour(
+              $($argument_name: $argument_type),*) -> $return_type {
+            let _caller = $crate::__CallerFrame::enter();
+            #[allow(unused_unsafe)]
+            __DETOUR.__dispatch(|__detour: $crate::__Active<$fn_type, _>| match __detour {
+              $crate::__Active::Function(__function) => unsafe { __function($($argument_name),*) },
+              $crate::__Active::Closure(__closure) => __closure($($argument_name),*),
+            })
+          }
+
+          fn __target() -> $fn_type {
+            $target
+          }
+
+          $crate::BoundStaticDetour::__new(__ffi_detour, __target)
+        };
+
+        &__DETOUR
+      }
+    );
+  };
+  (@create_detour ($($argument_name:ident)*) (fn) (reference) ($($target:expr)?)
+                  ($($attribute:meta)*) ($($shared:meta)*) ($($visibility:tt)*) ($name:ident)
+                  $($rest:tt)*) => {
+    compile_error!(concat!(
+      "detour accessor `", stringify!($name), "` cannot have reference arguments; ",
+      "declare it as a `static` instead"
+    ));
+  };
+
+  // Defines the initialization methods of a detour with reference arguments.
+  (@initialize_reference () ($fn_type:ty) ($($argument_type:ty)*) ($return_type:ty)) => {
+    /// Create a new hook given a target function and a compatible detour
+    /// closure.
+    pub unsafe fn initialize<D>(&self, target: $fn_type, closure: D) -> $crate::Result<&Self>
+    where
+      D: Fn($($argument_type),*) -> $return_type + Send + 'static,
+    {
+      self.inner.initialize_boxed(target, $crate::__Box::new(closure))?;
+      Ok(self)
+    }
+  };
+  (@initialize_reference ($target:expr) ($fn_type:ty) ($($argument_type:ty)*) ($return_type:ty)) => {
+    /// Create a new hook given a compatible detour closure.
+    pub unsafe fn initialize<D>(&self, closure: D) -> $crate::Result<&Self>
+    where
+      D: Fn($($argument_type),*) -> $return_type + Send + 'static,
+    {
+      fn __target() -> $fn_type {
+        $target
+      }
+
+      self.inner.initialize_boxed(__target(), $crate::__Box::new(closure))?;
+      Ok(self)
+    }
+
+    /// Create a new hook given a compatible detour closure, and enables it.
+    pub unsafe fn install<D>(&self, closure: D) -> $crate::Result<&Self>
+    where
+      D: Fn($($argument_type),*) -> $return_type + Send + 'static,
+    {
+      self.initialize(closure)?.enable()?;
+      Ok(self)
+    }
+  };
 
   // Associates each argument type with a dummy name.
   (@argument_names ($label:ident) ($($input:tt)*) ($($token:tt)*)) => {
-    static_detour!(@argument_names ($label) ($($input)*)(
+    $crate::static_detour!(@argument_names ($label) ($($input)*)(
       __arg_0  __arg_1  __arg_2  __arg_3  __arg_4  __arg_5  __arg_6
       __arg_7  __arg_8  __arg_9  __arg_10 __arg_11 __arg_12 __arg_13
+      __arg_14 __arg_15 __arg_16 __arg_17 __arg_18 __arg_19 __arg_20
+      __arg_21 __arg_22 __arg_23 __arg_24 __arg_25
     )($($token)*)());
   };
   (@argument_names
@@ -149,21 +478,182 @@ macro_rules! static_detour {
       ($($input:tt)*)
       ($hd_name:tt $($tl_name:tt)*)
       ($hd:tt $($tl:tt)*) ($($acc:tt)*)) => {
-    static_detour!(
+    $crate::static_detour!(
       @argument_names ($label) ($($input)*) ($($tl_name)*) ($($tl)*) ($($acc)* $hd_name));
   };
   (@argument_names ($label:ident) ($($input:tt)*) ($($name:tt)*) () ($($acc:tt)*)) => {
-    static_detour!(@$label ($($acc)*) $($input)*);
+    $crate::static_detour!(@$label ($($acc)*) $($input)*);
   };
 
   (@generate $item:item) => { $item };
 
   // Bootstrapper
   ($($t:tt)+) => {
-    static_detour!(@parse_attributes () | $($t)+);
+    $crate::static_detour!(@parse_attributes () () | $($t)+);
   };
 }
 
+/// A macro for defining function types with calling conventions that the
+/// library does not implement [Function](./trait.Function.html) for.
+///
+/// Due to the orphan rules, a downstream crate cannot implement `Function` for
+/// a function pointer, therefore the macro defines a transparent wrapper around
+/// the function pointer instead. The wrapper implements `Function`, and can be
+/// used with [GenericDetour](./struct.GenericDetour.html) (and
+/// [HookableWith](./trait.HookableWith.html), for additional detour types).
+///
+/// # Syntax
+///
+/// ```ignore
+/// function_type! {
+///   [pub] struct NAME_1([unsafe] [extern "cc"] fn([argument]...) [-> ret]);
+///   ...
+///   [pub] struct NAME_N([unsafe] [extern "cc"] fn([argument]...) [-> ret]);
+/// }
+/// ```
+///
+/// # Invariants
+///
+/// The wrapped type must be a function pointer, and the calling convention
+/// must describe the target and the detour accurately. The wrapper is
+/// `#[repr(transparent)]`, and is constructed from, and converted into, an
+/// untyped pointer without any further verification.
+///
+/// # Example
+///
+/// ```rust
+/// # #[cfg(target_arch = "x86_64")]
+/// # fn main() -> detour::Result<()> {
+/// use detour::{function_type, GenericDetour};
+///
+/// function_type! {
+///   /// A function using the UEFI calling convention.
+///   pub struct EfiAdd(extern "efiapi" fn(u64, u64) -> u64);
+/// }
+///
+/// #[inline(never)]
+/// extern "efiapi" fn add(x: u64, y: u64) -> u64 {
+///   unsafe { std::ptr::read_volatile(&x) + y }
+/// }
+///
+/// extern "efiapi" fn sub(x: u64, y: u64) -> u64 {
+///   x - y
+/// }
+///
+/// let hook = unsafe { GenericDetour::new(EfiAdd(add), EfiAdd(sub))? };
+/// unsafe { hook.enable()? };
+///
+/// assert_eq!(add(10, 5), 5);
+/// assert_eq!((hook.trampoline().0)(10, 5), 15);
+/// # unsafe { hook.disable()? };
+/// # Ok(())
+/// # }
+/// # #[cfg(not(target_arch = "x86_64"))]
+/// # fn main() {}
+/// ```
+#[macro_export]
+macro_rules! function_type {
+  // Normalizes the function type's modifiers
+  (@parse ($($input:tt)*) unsafe extern $cc:literal fn $($rest:tt)*) => {
+    $crate::function_type!(@prototype ($($input)* ($cc)) (unsafe extern $cc fn) $($rest)*);
+  };
+  (@parse ($($input:tt)*) extern $cc:literal fn $($rest:tt)*) => {
+    $crate::function_type!(@prototype ($($input)* ($cc)) (extern $cc fn) $($rest)*);
+  };
+  (@parse ($($input:tt)*) unsafe fn $($rest:tt)*) => {
+    $crate::function_type!(@prototype ($($input)* ("Rust")) (unsafe fn) $($rest)*);
+  };
+  (@parse ($($input:tt)*) fn $($rest:tt)*) => {
+    $crate::function_type!(@prototype ($($input)* ("Rust")) (fn) $($rest)*);
+  };
+
+  // Extracts the argument and return types (return/void)
+  (@prototype ($($input:tt)*) ($($fn_t:tt)*) ($($argument_type:ty),* $(,)?) -> $return_type:ty) => {
+    $crate::function_type!(@name ($($input)*) ($($fn_t)*) ($return_type) ($($argument_type),*)
+      (a b c d e f g h i j k l m n o p q r s t u v w x y z) ());
+  };
+  (@prototype ($($input:tt)*) ($($fn_t:tt)*) ($($argument_type:ty),* $(,)?)) => {
+    $crate::function_type!(@name ($($input)*) ($($fn_t)*) (()) ($($argument_type),*)
+      (a b c d e f g h i j k l m n o p q r s t u v w x y z) ());
+  };
+
+  // Names each argument (to destructure the arguments' tuple)
+  (@name ($($input:tt)*) ($($fn_t:tt)*) ($return_type:ty) () ($($names:ident)*)
+      ($($argument:ident : $argument_type:ty,)*)) => {
+    $crate::function_type!(@generate $($input)* ($($fn_t)*) ($($argument : $argument_type),*) ($return_type));
+  };
+  (@name ($($input:tt)*) ($($fn_t:tt)*) ($return_type:ty) ($head:ty $(, $tail:ty)*)
+      ($name:ident $($names:ident)*) ($($named:tt)*)) => {
+    $crate::function_type!(@name ($($input)*) ($($fn_t)*) ($return_type) ($($tail),*)
+      ($($names)*) ($($named)* $name : $head,));
+  };
+
+  (@generate
+      ($(#[$attribute:meta])*) ($visibility:vis) ($name:ident) ($abi:literal)
+      ($($fn_t:tt)*) ($($argument:ident : $argument_type:ty),*) ($return_type:ty)) => {
+    $(#[$attribute])*
+    #[repr(transparent)]
+    #[derive(Clone, Copy)]
+    $visibility struct $name(pub $($fn_t)* ($($argument_type),*) -> $return_type);
+
+    $crate::__static_function!($name, dyn Fn($($argument_type),*) -> $return_type + Send);
+
+    unsafe impl $crate::Function for $name {
+      type Arguments = ($($argument_type,)*);
+      type Output = $return_type;
+
+      const ARITY: usize = <[&str]>::len(&[$(stringify!($argument_type)),*]);
+      const ABI: &'static str = $abi;
+
+      fn type_names() -> &'static [&'static str] {
+        $crate::__type_names::<Self>(&[
+          $(::core::any::type_name::<$argument_type>(),)*
+          ::core::any::type_name::<$return_type>(),
+        ])
+      }
+
+      unsafe fn from_ptr(ptr: *const ()) -> Self {
+        $name(::core::mem::transmute::<*const (), $($fn_t)* ($($argument_type),*) -> $return_type>(ptr))
+      }
+
+      fn to_ptr(&self) -> *const () {
+        self.0 as *const ()
+      }
+
+      unsafe fn call_tuple(self, arguments: Self::Arguments) -> $return_type {
+        let ($($argument,)*) = arguments;
+        (self.0)($($argument),*)
+      }
+    }
+  };
+
+  ($(#[$attribute:meta])* $visibility:vis struct $name:ident ($($function:tt)*); $($rest:tt)*) => {
+    $crate::function_type!(@parse (($(#[$attribute])*) ($visibility) ($name)) $($function)*);
+    $crate::function_type!($($rest)*);
+  };
+  () => {};
+}
+
+/// Implements `StaticFunction` for a type declared by `function_type!`,
+/// unless it's implemented for every function (with the *nightly* feature).
+#[doc(hidden)]
+#[macro_export]
+#[cfg(not(feature = "nightly"))]
+macro_rules! __static_function {
+  ($name:ident, $closure:ty) => {
+    impl $crate::StaticFunction for $name {
+      type Closure = $closure;
+    }
+  };
+}
+
+#[doc(hidden)]
+#[macro_export]
+#[cfg(feature = "nightly")]
+macro_rules! __static_function {
+  ($name:ident, $closure:ty) => {};
+}
+
 macro_rules! impl_hookable {
   (@recurse () ($($nm:ident : $ty:ident),*)) => {
     impl_hookable!(@impl_all ($($nm : $ty),*));
@@ -176,25 +666,53 @@ macro_rules! impl_hookable {
   };
 
   (@impl_all ($($nm:ident : $ty:ident),*)) => {
-    impl_hookable!(@impl_pair ($($nm : $ty),*) (                  fn($($ty),*) -> Ret));
-    impl_hookable!(@impl_pair ($($nm : $ty),*) (extern "cdecl"    fn($($ty),*) -> Ret));
-    impl_hookable!(@impl_pair ($($nm : $ty),*) (extern "stdcall"  fn($($ty),*) -> Ret));
-    impl_hookable!(@impl_pair ($($nm : $ty),*) (extern "fastcall" fn($($ty),*) -> Ret));
-    impl_hookable!(@impl_pair ($($nm : $ty),*) (extern "win64"    fn($($ty),*) -> Ret));
-    impl_hookable!(@impl_pair ($($nm : $ty),*) (extern "C"        fn($($ty),*) -> Ret));
-    impl_hookable!(@impl_pair ($($nm : $ty),*) (extern "system"   fn($($ty),*) -> Ret));
+    impl_hookable!(@impl_pair ("Rust") ($($nm : $ty),*) (                  fn($($ty),*) -> Ret));
+    impl_hookable!(@impl_pair ("C") ($($nm : $ty),*) (extern "C"        fn($($ty),*) -> Ret));
+    impl_hookable!(@impl_pair ("system") ($($nm : $ty),*) (extern "system"   fn($($ty),*) -> Ret));
+    impl_hookable!(@impl_pair ("C-unwind") ($($nm : $ty),*) (extern "C-unwind" fn($($ty),*) -> Ret));
+    impl_hookable!(@impl_pair ("system-unwind") ($($nm : $ty),*) (extern "system-unwind" fn($($ty),*) -> Ret));
+
+    #[cfg(target_arch = "x86")]
+    impl_hookable!(@impl_pair ("cdecl") ($($nm : $ty),*) (extern "cdecl"    fn($($ty),*) -> Ret));
+    #[cfg(target_arch = "x86")]
+    impl_hookable!(@impl_pair ("stdcall") ($($nm : $ty),*) (extern "stdcall"  fn($($ty),*) -> Ret));
+    #[cfg(target_arch = "x86")]
+    impl_hookable!(@impl_pair ("fastcall") ($($nm : $ty),*) (extern "fastcall" fn($($ty),*) -> Ret));
+    #[cfg(target_arch = "x86")]
+    impl_hookable!(@impl_pair ("thiscall") ($($nm : $ty),*) (extern "thiscall" fn($($ty),*) -> Ret));
 
-    #[cfg(feature = "nightly")]
-    impl_hookable!(@impl_pair ($($nm : $ty),*) (extern "thiscall" fn($($ty),*) -> Ret));
+    #[cfg(target_arch = "x86_64")]
+    impl_hookable!(@impl_pair ("win64") ($($nm : $ty),*) (extern "win64"    fn($($ty),*) -> Ret));
+    #[cfg(target_arch = "x86_64")]
+    impl_hookable!(@impl_pair ("sysv64") ($($nm : $ty),*) (extern "sysv64"   fn($($ty),*) -> Ret));
+
+    #[cfg(feature = "vectorcall")]
+    impl_hookable!(@impl_pair ("vectorcall") ($($nm : $ty),*) (extern "vectorcall" fn($($ty),*) -> Ret));
+
+    impl_hookable!(@impl_variadic ($($nm : $ty),*));
+  };
+
+  // Variadic functions require at least one fixed argument
+  (@impl_variadic ()) => {};
+  (@impl_variadic ($($nm:ident : $ty:ident),+)) => {
+    impl_hookable!(@impl_core ("C") ($($nm : $ty),*) (unsafe extern "C" fn($($ty),*, ...) -> Ret));
+    unsafe impl<Ret: 'static, $($ty: 'static),*> VariadicFunction
+      for unsafe extern "C" fn($($ty),*, ...) -> Ret {}
   };
 
-  (@impl_pair ($($nm:ident : $ty:ident),*) ($($fn_t:tt)*)) => {
-    impl_hookable!(@impl_fun ($($nm : $ty),*) ($($fn_t)*) (unsafe $($fn_t)*));
+  (@impl_pair ($abi:literal) ($($nm:ident : $ty:ident),*) ($($fn_t:tt)*)) => {
+    impl_hookable!(@impl_fun ($abi) ($($nm : $ty),*) ($($fn_t)*) (unsafe $($fn_t)*));
   };
 
-  (@impl_fun ($($nm:ident : $ty:ident),*) ($safe_type:ty) ($unsafe_type:ty)) => {
-    impl_hookable!(@impl_core ($($nm : $ty),*) ($safe_type));
-    impl_hookable!(@impl_core ($($nm : $ty),*) ($unsafe_type));
+  (@impl_fun ($abi:literal) ($($nm:ident : $ty:ident),*) ($safe_type:ty) ($unsafe_type:ty)) => {
+    impl_hookable!(@impl_core ($abi) ($($nm : $ty),*) ($safe_type));
+    impl_hookable!(@impl_core ($abi) ($($nm : $ty),*) ($unsafe_type));
+
+    impl_hookable!(@impl_closure ($($nm : $ty),*) ($safe_type) ($safe_type));
+    impl_hookable!(@impl_closure ($($nm : $ty),*) ($unsafe_type) ($safe_type));
+
+    impl_hookable!(@impl_thunk ($abi) ($($nm : $ty),*) ($safe_type));
+    impl_hookable!(@impl_thunk ($abi) ($($nm : $ty),*) ($unsafe_type));
 
     impl_hookable!(@impl_unsafe ($($nm : $ty),*) ($unsafe_type) ($safe_type));
     impl_hookable!(@impl_safe ($($nm : $ty),*) ($safe_type));
@@ -203,58 +721,179 @@ macro_rules! impl_hookable {
   (@impl_unsafe ($($nm:ident : $ty:ident),*) ($target:ty) ($detour:ty)) => {
     unsafe impl<Ret: 'static, $($ty: 'static),*> HookableWith<$detour> for $target {}
 
-    #[cfg(feature = "nightly")]
     impl<Ret: 'static, $($ty: 'static),*> $crate::StaticDetour<$target> {
       #[doc(hidden)]
       pub unsafe fn call(&self, $($nm : $ty),*) -> Ret {
-        let original: $target = ::std::mem::transmute(self.trampoline().expect("calling detour trampoline"));
-        original($($nm),*)
+        let original: $target = self.trampoline().expect("calling detour trampoline");
+        self.__call_original(|| original($($nm),*))
+      }
+
+      #[doc(hidden)]
+      pub fn call_detour(&self, $($nm : $ty),*) -> $crate::Result<Ret> {
+        self.__try_dispatch(|detour: $crate::__Active<$detour, _>| match detour {
+          $crate::__Active::Function(function) => function($($nm),*),
+          $crate::__Active::Closure(closure) => closure($($nm),*),
+        })
+      }
+
+      #[doc(hidden)]
+      pub fn set_detour_fn(&self, detour: $detour) {
+        let closure = Box::new(move |$($nm : $ty),*| detour($($nm),*));
+        self.set_detour_boxed(closure, Some(detour as *const ()));
       }
     }
 
     impl<Ret: 'static, $($ty: 'static),*> $crate::GenericDetour<$target> {
       #[doc(hidden)]
       pub unsafe fn call(&self, $($nm : $ty),*) -> Ret {
-        let original: $target = ::std::mem::transmute(self.trampoline());
+        let original: $target = self.trampoline();
+        self.__call_original(|| original($($nm),*))
+      }
+    }
+
+    impl<Ret: 'static, $($ty: 'static),*> $crate::PointerDetour<$target> {
+      #[doc(hidden)]
+      pub unsafe fn call(&self, $($nm : $ty),*) -> Ret {
+        let original: $target = self.original();
+        original($($nm),*)
+      }
+    }
+
+    #[cfg(feature = "std")]
+    impl<Ret: 'static, $($ty: 'static),*> $crate::MultiDetour<$target> {
+      #[doc(hidden)]
+      #[allow(clippy::too_many_arguments)]
+      pub unsafe fn call(&self, index: usize, $($nm : $ty),*) -> Ret {
+        let original: $target = self.trampoline(index);
         original($($nm),*)
       }
     }
   };
 
   (@impl_safe ($($nm:ident : $ty:ident),*) ($fn_type:ty)) => {
-    #[cfg(feature = "nightly")]
     impl<Ret: 'static, $($ty: 'static),*> $crate::StaticDetour<$fn_type> {
       #[doc(hidden)]
       pub fn call(&self, $($nm : $ty),*) -> Ret {
-        unsafe {
-          let original: $fn_type = ::std::mem::transmute(self.trampoline().expect("calling detour trampoline"));
-          original($($nm),*)
-        }
+        let original: $fn_type = self.trampoline().expect("calling detour trampoline");
+        self.__call_original(|| original($($nm),*))
+      }
+
+      #[doc(hidden)]
+      pub fn call_detour(&self, $($nm : $ty),*) -> $crate::Result<Ret> {
+        self.__try_dispatch(|detour: $crate::__Active<$fn_type, _>| match detour {
+          $crate::__Active::Function(function) => function($($nm),*),
+          $crate::__Active::Closure(closure) => closure($($nm),*),
+        })
+      }
+
+      #[doc(hidden)]
+      pub fn set_detour_fn(&self, detour: $fn_type) {
+        let closure = Box::new(move |$($nm : $ty),*| detour($($nm),*));
+        self.set_detour_boxed(closure, Some(detour as *const ()));
       }
     }
 
     impl<Ret: 'static, $($ty: 'static),*> $crate::GenericDetour<$fn_type> {
       #[doc(hidden)]
       pub fn call(&self, $($nm : $ty),*) -> Ret {
-        unsafe {
-          let original: $fn_type = ::std::mem::transmute(self.trampoline());
-          original($($nm),*)
+        let original: $fn_type = self.trampoline();
+        self.__call_original(|| original($($nm),*))
+      }
+    }
+
+    impl<Ret: 'static, $($ty: 'static),*> $crate::PointerDetour<$fn_type> {
+      #[doc(hidden)]
+      pub fn call(&self, $($nm : $ty),*) -> Ret {
+        let original: $fn_type = self.original();
+        original($($nm),*)
+      }
+    }
+
+    #[cfg(feature = "std")]
+    impl<Ret: 'static, $($ty: 'static),*> $crate::MultiDetour<$fn_type> {
+      #[doc(hidden)]
+      #[allow(clippy::too_many_arguments)]
+      pub fn call(&self, index: usize, $($nm : $ty),*) -> Ret {
+        let original: $fn_type = self.trampoline(index);
+        original($($nm),*)
+      }
+    }
+  };
+
+  (@impl_closure ($($nm:ident : $ty:ident),*) ($fn_type:ty) ($safe_type:ty)) => {
+    #[cfg(not(feature = "nightly"))]
+    impl<Ret: 'static, $($ty: 'static),*> $crate::StaticFunction for $fn_type {
+      type Closure = dyn Fn($($ty),*) -> Ret + Send;
+    }
+
+    #[cfg(not(feature = "nightly"))]
+    impl<Ret: 'static, $($ty: 'static,)* Func> StaticClosure<$fn_type> for Func
+    where
+      Func: Fn($($ty),*) -> Ret + Send + 'static,
+    {
+      fn into_boxed(self) -> Box<dyn Fn($($ty),*) -> Ret + Send> {
+        Box::new(self)
+      }
+
+      fn to_function(&self) -> Option<*const ()> {
+        (self as &dyn ::core::any::Any)
+          .downcast_ref::<$safe_type>()
+          .map(|function| *function as *const ())
+      }
+    }
+  };
+
+  (@impl_thunk ($abi:literal) ($($nm:ident : $ty:ident),*) ($fn_type:ty)) => {
+    #[cfg(feature = "std")]
+    impl<Ret, $($ty,)* Func> $crate::thunk::ThunkClosure<$fn_type> for Func
+    where
+      Ret: $crate::thunk::Scalar,
+      $($ty: $crate::thunk::Scalar,)*
+      Func: Fn($($ty),*) -> Ret + Send + Sync + 'static,
+    {
+      fn shim() -> *const () {
+        #[allow(clippy::too_many_arguments)]
+        extern $abi fn shim<Ret, $($ty,)* Func: Fn($($ty),*) -> Ret>(
+          $($nm : $ty,)*
+          closure: *const Func,
+        ) -> Ret {
+          unsafe { (*closure)($($nm),*) }
         }
+        shim::<Ret, $($ty,)* Func> as *const ()
+      }
+
+      fn parameters() -> $crate::__Box<[$crate::thunk::Parameter]> {
+        $crate::__Box::new([
+          $($crate::thunk::Parameter::of::<$ty>(),)*
+          $crate::thunk::Parameter::of::<Ret>(),
+        ])
       }
     }
   };
 
-  (@impl_core ($($nm:ident : $ty:ident),*) ($fn_type:ty)) => {
+  (@impl_core ($abi:literal) ($($nm:ident : $ty:ident),*) ($fn_type:ty)) => {
     unsafe impl<Ret: 'static, $($ty: 'static),*> Function for $fn_type {
       type Arguments = ($($ty,)*);
       type Output = Ret;
 
+      const ARITY: usize = <[&str]>::len(&[$(stringify!($ty)),*]);
+      const ABI: &'static str = $abi;
+
+      fn type_names() -> &'static [&'static str] {
+        $crate::__type_names::<Self>(&[$(::core::any::type_name::<$ty>(),)* ::core::any::type_name::<Ret>()])
+      }
+
       unsafe fn from_ptr(ptr: *const ()) -> Self {
-        ::std::mem::transmute(ptr)
+        ::core::mem::transmute(ptr)
       }
 
       fn to_ptr(&self) -> *const () {
-        unsafe { ::std::mem::transmute(*self) }
+        *self as *const ()
+      }
+
+      unsafe fn call_tuple(self, arguments: Self::Arguments) -> Ret {
+        let ($($nm,)*) = arguments;
+        self($($nm),*)
       }
     }
   };
@@ -263,3 +902,63 @@ macro_rules! impl_hookable {
     impl_hookable!(@recurse ($($nm : $ty),*) ());
   };
 }
+
+macro_rules! impl_compatible {
+  (@recurse () ($($target:ident : $detour:ident),*)) => {
+    impl_compatible!(@impl_all ($($target : $detour),*));
+  };
+  (@recurse
+      ($hd_target:ident : $hd_detour:ident $(, $tl_target:ident : $tl_detour:ident)*)
+      ($($target:ident : $detour:ident),*)) => {
+    impl_compatible!(@impl_all ($($target : $detour),*));
+    impl_compatible!(@recurse ($($tl_target : $tl_detour),*) ($($target : $detour,)* $hd_target : $hd_detour));
+  };
+
+  (@impl_all ($($target:ident : $detour:ident),*)) => {
+    impl_compatible!(@impl_abi ($($target : $detour),*) ());
+    impl_compatible!(@impl_abi ($($target : $detour),*) (extern "C"));
+    impl_compatible!(@impl_abi ($($target : $detour),*) (extern "system"));
+    impl_compatible!(@impl_abi ($($target : $detour),*) (extern "C-unwind"));
+    impl_compatible!(@impl_abi ($($target : $detour),*) (extern "system-unwind"));
+
+    #[cfg(target_arch = "x86")]
+    impl_compatible!(@impl_abi ($($target : $detour),*) (extern "cdecl"));
+    #[cfg(target_arch = "x86")]
+    impl_compatible!(@impl_abi ($($target : $detour),*) (extern "stdcall"));
+    #[cfg(target_arch = "x86")]
+    impl_compatible!(@impl_abi ($($target : $detour),*) (extern "fastcall"));
+    #[cfg(target_arch = "x86")]
+    impl_compatible!(@impl_abi ($($target : $detour),*) (extern "thiscall"));
+
+    #[cfg(target_arch = "x86_64")]
+    impl_compatible!(@impl_abi ($($target : $detour),*) (extern "win64"));
+    #[cfg(target_arch = "x86_64")]
+    impl_compatible!(@impl_abi ($($target : $detour),*) (extern "sysv64"));
+
+    #[cfg(feature = "vectorcall")]
+    impl_compatible!(@impl_abi ($($target : $detour),*) (extern "vectorcall"));
+  };
+
+  // Like `HookableWith`, an unsafe target may be detoured by a safe function
+  (@impl_abi ($($target:ident : $detour:ident),*) ($($abi:tt)*)) => {
+    impl_compatible!(@impl ($($target : $detour),*)
+      ($($abi)* fn($($target),*) -> Ret) ($($abi)* fn($($detour),*) -> DetourRet));
+    impl_compatible!(@impl ($($target : $detour),*)
+      (unsafe $($abi)* fn($($target),*) -> Ret) (unsafe $($abi)* fn($($detour),*) -> DetourRet));
+    impl_compatible!(@impl ($($target : $detour),*)
+      (unsafe $($abi)* fn($($target),*) -> Ret) ($($abi)* fn($($detour),*) -> DetourRet));
+  };
+
+  (@impl ($($target:ident : $detour:ident),*) ($target_type:ty) ($detour_type:ty)) => {
+    unsafe impl<Ret: 'static, DetourRet: 'static, $($target: 'static, $detour: 'static),*>
+      HookCompatible<$target_type> for $detour_type
+    where
+      DetourRet: AbiCompatible<Ret>,
+      $($detour: AbiCompatible<$target>),*
+    {}
+  };
+
+  ($($target:ident : $detour:ident),*) => {
+    impl_compatible!(@recurse ($($target : $detour),*) ());
+  };
+}
@@ -0,0 +1,132 @@
+//! Queries of the process's memory.
+//!
+//! These are the queries used by the library to validate targets and
+//! detours, exposed so the same validation can be performed beforehand. They
+//! are answered by the installed [backend](../os/fn.backend.html), and behave
+//! identically across platforms; in particular, adjacent regions sharing a
+//! protection are reported as one, regardless of how the operating system
+//! partitions them (e.g per mapping on Linux, per allocation on Windows, or
+//! per submap on macOS).
+//!
+//! # Example
+//!
+//! ```
+//! use detour::{memory, os};
+//!
+//! fn target() {}
+//!
+//! let address = target as *const ();
+//! assert!(memory::is_executable(address)?);
+//!
+//! let region = memory::region_info(address)?.expect("mapped code");
+//! assert!(region.contains(address));
+//! assert!(region.protection.contains(os::Protection::READ_EXECUTE));
+//! assert!(memory::is_readable_range(address, 1)?);
+//! # Ok::<(), detour::Error>(())
+//! ```
+
+use crate::error::Result;
+use crate::os::{self, Backend, Protection, Region};
+
+/// A region of contiguous pages sharing a protection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegionInfo {
+  /// Base address of the region.
+  pub base: *const (),
+  /// Size of the region.
+  pub len: usize,
+  /// Protection of the region.
+  pub protection: Protection,
+}
+
+impl RegionInfo {
+  /// Returns the address after the region.
+  pub fn end(&self) -> usize {
+    self.base as usize + self.len
+  }
+
+  /// Returns true if the region contains an address.
+  pub fn contains(&self, address: *const ()) -> bool {
+    (self.base as usize..self.end()).contains(&(address as usize))
+  }
+}
+
+/// Returns the region containing an address, or `None` if the address is not
+/// mapped.
+///
+/// The region spans all contiguous pages around the address sharing its
+/// protection, even if the operating system reports them as several regions.
+pub fn region_info(address: *const ()) -> Result<Option<RegionInfo>> {
+  let backend = os::backend()?;
+  let region = match query(backend, address)? {
+    Some(region) => region,
+    None => return Ok(None),
+  };
+
+  let (mut lower, mut upper) = (region.lower(), region.upper());
+  let shares_protection = |other: &Region| other.protection == region.protection;
+
+  while let Some(next) = query(backend, upper as *const ())? {
+    if !shares_protection(&next) || next.lower() != upper || next.upper() <= upper {
+      break;
+    }
+    upper = next.upper();
+  }
+
+  while let Some(previous) = lower.checked_sub(1) {
+    match query(backend, previous as *const ())? {
+      Some(region)
+        if shares_protection(&region) && region.upper() == lower && region.lower() < lower =>
+      {
+        lower = region.lower()
+      },
+      _ => break,
+    }
+  }
+
+  Ok(Some(RegionInfo {
+    base: lower as *const (),
+    len: upper - lower,
+    protection: region.protection,
+  }))
+}
+
+/// Returns true if an address is mapped executable memory.
+pub fn is_executable(address: *const ()) -> Result<bool> {
+  Ok(
+    query(os::backend()?, address)?
+      .is_some_and(|region| region.protection.contains(Protection::EXECUTE)),
+  )
+}
+
+/// Returns true if every byte of a range is mapped readable memory.
+///
+/// Empty ranges are readable, whilst ranges exceeding the address space are
+/// not.
+pub fn is_readable_range(address: *const (), len: usize) -> Result<bool> {
+  let backend = os::backend()?;
+  let upper = match (address as usize).checked_add(len) {
+    Some(upper) => upper,
+    None => return Ok(false),
+  };
+
+  let mut current = address as usize;
+  while current < upper {
+    match query(backend, current as *const ())? {
+      Some(region) if region.protection.contains(Protection::READ) && region.upper() > current => {
+        current = region.upper()
+      },
+      _ => return Ok(false),
+    }
+  }
+  Ok(true)
+}
+
+/// Queries the region containing an address; the null page is never mapped.
+fn query(backend: &dyn Backend, address: *const ()) -> Result<Option<Region>> {
+  if address.is_null() {
+    Ok(None)
+  } else {
+    backend.query(address)
+  }
+}
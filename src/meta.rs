@@ -0,0 +1,126 @@
+//! Limits of the target architecture's detours.
+//!
+//! The constants describe the architecture's defaults, whilst the functions
+//! return the effective (configured) values. The limits can only be made
+//! stricter; a detour range beyond what the architecture's relative jumps can
+//! reach would produce invalid code.
+//!
+//! # Example
+//!
+//! ```rust
+//! use detour::meta;
+//!
+//! // Trampolines and relays are allocated within 1 GiB of their targets
+//! meta::configure(meta::Limits {
+//!   detour_range: 0x4000_0000,
+//!   ..meta::Limits::default()
+//! })?;
+//! assert_eq!(meta::detour_range(), 0x4000_0000);
+//!
+//! // Nonsensical values are rejected
+//! assert!(meta::configure(meta::Limits {
+//!   max_prolog_size: 0,
+//!   ..meta::Limits::default()
+//! })
+//! .is_err());
+//! # meta::configure(meta::Limits::default())?;
+//! # Ok::<(), detour::Error>(())
+//! ```
+
+use crate::error::{Error, Result};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub use crate::arch::meta::{DETOUR_RANGE, MAX_PROLOG_SIZE};
+
+/// The smallest configurable detour range (64 KiB, the allocation
+/// granularity on Windows).
+pub const MIN_DETOUR_RANGE: usize = 0x1_0000;
+
+/// The largest configurable prolog size.
+pub const MAX_PROLOG_LIMIT: usize = 256;
+
+/// Configurable limits of detours.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+  /// The furthest distance between a target and its trampoline or relay.
+  ///
+  /// It must be within `MIN_DETOUR_RANGE..=DETOUR_RANGE`. Detours further
+  /// away than this are reached through a relay.
+  pub detour_range: usize,
+  /// The maximum amount of bytes relocated from a target's prolog.
+  ///
+  /// It must be at least the size of the patch (a long jump), and at most
+  /// `MAX_PROLOG_LIMIT`. Targets requiring more bytes to be relocated fail
+  /// with `Error::NoPatchArea`.
+  pub max_prolog_size: usize,
+}
+
+impl Limits {
+  /// The architecture's limits, used unless they have been configured.
+  pub const DEFAULT: Limits = Limits {
+    detour_range: DETOUR_RANGE,
+    max_prolog_size: MAX_PROLOG_SIZE,
+  };
+}
+
+impl Default for Limits {
+  fn default() -> Self {
+    Self::DEFAULT
+  }
+}
+
+static DETOUR_RANGE_LIMIT: AtomicUsize = AtomicUsize::new(DETOUR_RANGE);
+static PROLOG_SIZE_LIMIT: AtomicUsize = AtomicUsize::new(MAX_PROLOG_SIZE);
+
+/// Configures the limits of detours.
+///
+/// The limits apply to detours created afterwards. Invalid values are
+/// rejected with `Error::InvalidOption`, leaving the limits unchanged.
+pub fn configure(limits: Limits) -> Result<()> {
+  if !(MIN_DETOUR_RANGE..=DETOUR_RANGE).contains(&limits.detour_range) {
+    return Err(Error::InvalidOption {
+      name: "detour_range",
+    });
+  }
+
+  let patch_size = crate::arch::meta::prolog_margin(core::ptr::null());
+  if !(patch_size..=MAX_PROLOG_LIMIT).contains(&limits.max_prolog_size) {
+    return Err(Error::InvalidOption {
+      name: "max_prolog_size",
+    });
+  }
+
+  DETOUR_RANGE_LIMIT.store(limits.detour_range, Ordering::SeqCst);
+  PROLOG_SIZE_LIMIT.store(limits.max_prolog_size, Ordering::SeqCst);
+  Ok(())
+}
+
+/// Returns the configured limits.
+pub fn limits() -> Limits {
+  Limits {
+    detour_range: detour_range(),
+    max_prolog_size: max_prolog_size(),
+  }
+}
+
+/// Returns the furthest distance between a target and its trampoline or
+/// relay.
+pub fn detour_range() -> usize {
+  DETOUR_RANGE_LIMIT.load(Ordering::SeqCst)
+}
+
+/// Returns the maximum amount of bytes relocated from a target's prolog.
+pub fn max_prolog_size() -> usize {
+  PROLOG_SIZE_LIMIT.load(Ordering::SeqCst)
+}
+
+/// Returns whether the current thread has a shadow stack enabled (e.g by
+/// Intel CET).
+///
+/// Detours are compatible with shadow stacks; generated code only returns
+/// from calls it made itself, and branches elsewhere using jumps. Return
+/// addresses are never substituted, so no detour has to adjust the shadow
+/// stack.
+pub fn shadow_stack_enabled() -> bool {
+  crate::arch::meta::shadow_stack_enabled()
+}
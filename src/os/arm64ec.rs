@@ -0,0 +1,120 @@
+//! Classification of code within ARM64EC processes.
+//!
+//! On Windows ARM64, x64 code is emulated within processes that may also
+//! contain ARM64EC code (native code following the x64 calling convention).
+//! A function of an ARM64EC module is exported as a "fast-forward sequence";
+//! a short x64 thunk jumping to the function's ARM64EC entry, which callers
+//! within the module call directly. Only x64 code can be patched, therefore
+//! ARM64EC code is refused, and so is a fast-forward sequence, since its
+//! detour would silently miss those callers.
+//!
+//! Code is classified using `RtlIsEcCode`, which `ntdll` only exports on
+//! ARM64 hosts. Whilst emulated, the instruction cache is flushed after each
+//! write, since the emulator caches translated code. Trampolines are mapped
+//! without `PAGE_TARGETS_INVALID`, therefore they're valid call targets for
+//! Control Flow Guard as is.
+
+use crate::error::{Error, Result};
+use core::mem;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use winapi::um::libloaderapi::{GetModuleHandleA, GetProcAddress};
+
+type FnRtlIsEcCode = unsafe extern "system" fn(u64) -> u8;
+
+/// The address of `RtlIsEcCode`, `ABSENT` if it's not exported, or zero if
+/// it's not resolved yet.
+static IS_EC_CODE: AtomicUsize = AtomicUsize::new(0);
+
+const ABSENT: usize = 1;
+
+/// The instructions of a fast-forward sequence, preceding the displacement of
+/// its final `jmp`:
+///
+/// ```text
+/// mov rax, rsp
+/// mov [rax+20h], rbx
+/// push rbp
+/// pop rbp
+/// jmp <entry>
+/// ```
+const FAST_FORWARD: [u8; 10] = [0x48, 0x8B, 0xC4, 0x48, 0x89, 0x58, 0x20, 0x55, 0x5D, 0xE9];
+
+/// Returns `RtlIsEcCode`, if exported.
+fn is_ec_code() -> Option<FnRtlIsEcCode> {
+  let address = match IS_EC_CODE.load(Ordering::Relaxed) {
+    0 => {
+      let address = unsafe {
+        let ntdll = GetModuleHandleA(b"ntdll.dll\0".as_ptr() as *const _);
+        if ntdll.is_null() {
+          ABSENT
+        } else {
+          match GetProcAddress(ntdll, b"RtlIsEcCode\0".as_ptr() as *const _) as usize {
+            0 => ABSENT,
+            address => address,
+          }
+        }
+      };
+      IS_EC_CODE.store(address, Ordering::Relaxed);
+      address
+    },
+    address => address,
+  };
+
+  (address != ABSENT).then(|| unsafe { mem::transmute::<usize, FnRtlIsEcCode>(address) })
+}
+
+/// Returns whether the process is emulated on an ARM64 host.
+pub fn is_emulated() -> bool {
+  is_ec_code().is_some()
+}
+
+/// Returns the ARM64EC entry of a fast-forward sequence.
+///
+/// The bytes are compared one at a time, so none are read past a mismatch.
+unsafe fn fast_forward_entry(target: *const ()) -> Option<usize> {
+  let code = target as *const u8;
+  if !(0..FAST_FORWARD.len()).all(|index| *code.add(index) == FAST_FORWARD[index]) {
+    return None;
+  }
+
+  let end = target as usize + FAST_FORWARD.len() + mem::size_of::<i32>();
+  let displacement = (code.add(FAST_FORWARD.len()) as *const i32).read_unaligned();
+  Some(end.wrapping_add(displacement as isize as usize))
+}
+
+/// Fails if a target is ARM64EC code or a fast-forward sequence.
+pub unsafe fn validate_target(target: *const ()) -> Result<()> {
+  let is_ec = match is_ec_code() {
+    Some(is_ec) => is_ec,
+    None => return Ok(()),
+  };
+
+  if is_ec(target as u64) != 0 {
+    Err(Error::ArmCode)?;
+  }
+
+  match fast_forward_entry(target) {
+    Some(entry) if is_ec(entry as u64) != 0 => Err(Error::FastForwardThunk { entry }),
+    _ => Ok(()),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn resolves_fast_forward_entry() {
+    let mut code = [0xCCu8; 16];
+    code[..FAST_FORWARD.len()].copy_from_slice(&FAST_FORWARD);
+    code[FAST_FORWARD.len()..][..4].copy_from_slice(&0x20i32.to_le_bytes());
+
+    let target = code.as_ptr() as *const ();
+    let entry = unsafe { fast_forward_entry(target) };
+    assert_eq!(entry, Some(code.as_ptr() as usize + 14 + 0x20));
+
+    // The write is only observed through `target`
+    core::hint::black_box(&mut code)[8] = 0x90;
+    assert_eq!(unsafe { fast_forward_entry(target) }, None);
+  }
+}
@@ -0,0 +1,385 @@
+//! Enumeration of the symbols exported by a loaded module.
+//!
+//! On ELF platforms, the dynamic symbol table of the module is read from its
+//! loaded `PT_DYNAMIC` segment. Indirect functions (`STT_GNU_IFUNC`) are
+//! resolved using `dlsym`, and only the default version of a versioned symbol
+//! is included. On Windows, the export directory of the module is read, and
+//! exports by ordinal only are excluded.
+
+use crate::error::{Error, Result};
+use std::string::String;
+use std::vec::Vec;
+
+/// A symbol exported by a module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Export {
+  /// The name of the symbol.
+  pub name: String,
+  /// The address of the symbol, or null if it's forwarded.
+  pub address: *const (),
+  /// What the symbol refers to.
+  pub kind: ExportKind,
+}
+
+unsafe impl Send for Export {}
+unsafe impl Sync for Export {}
+
+/// What an exported symbol refers to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExportKind {
+  /// A function within the module.
+  Function,
+  /// Data within the module (e.g a variable), or a symbol outside of any
+  /// executable segment.
+  Data,
+  /// A function exported by another module (e.g `NTDLL.RtlAllocateHeap`),
+  /// which is only resolved by the loader.
+  Forwarded(String),
+}
+
+/// Returns the symbols exported by a loaded module, in the order of its
+/// symbol table.
+///
+/// The module is identified by its file name (e.g `libc.so.6` or
+/// `ws2_32.dll`), or by its path on ELF platforms. It's not loaded if
+/// required; `Error::UnknownModule` is returned instead.
+///
+/// # Example
+///
+/// ```
+/// # #[cfg(target_os = "linux")]
+/// # fn main() -> detour::Result<()> {
+/// use detour::os::{self, ExportKind};
+///
+/// let exports = os::exports("libc.so.6")?;
+/// let malloc = exports.iter().find(|export| export.name == "malloc");
+/// assert_eq!(malloc.map(|export| &export.kind), Some(&ExportKind::Function));
+/// # Ok(())
+/// # }
+/// # #[cfg(not(target_os = "linux"))]
+/// # fn main() {}
+/// ```
+pub fn exports(module: &str) -> Result<Vec<Export>> {
+  platform::exports(module).ok_or_else(|| Error::UnknownModule {
+    name: module.into(),
+  })
+}
+
+#[cfg(unix)]
+mod platform {
+  use super::{Export, ExportKind};
+  use core::{mem, slice};
+  use libc::{c_int, c_void, dl_phdr_info, size_t};
+  use std::ffi::CStr;
+  use std::string::String;
+  use std::vec::Vec;
+
+  const DT_NULL: isize = 0;
+  const DT_HASH: isize = 4;
+  const DT_STRTAB: isize = 5;
+  const DT_SYMTAB: isize = 6;
+  const DT_GNU_HASH: isize = 0x6fff_fef5;
+  const DT_VERSYM: isize = 0x6fff_fff0;
+
+  const PT_LOAD: u32 = 1;
+  const PT_DYNAMIC: u32 = 2;
+  const PF_X: u32 = 1;
+
+  const STB_LOCAL: u8 = 0;
+  const STT_NOTYPE: u8 = 0;
+  const STT_FUNC: u8 = 2;
+  const STT_GNU_IFUNC: u8 = 10;
+
+  const SHN_UNDEF: u16 = 0;
+  const SHN_ABS: u16 = 0xfff1;
+
+  /// The version index of a symbol only linked against explicitly.
+  const VERSYM_HIDDEN: u16 = 0x8000;
+
+  #[repr(C)]
+  struct Dyn {
+    tag: isize,
+    value: usize,
+  }
+
+  #[cfg(target_pointer_width = "64")]
+  #[repr(C)]
+  struct Sym {
+    name: u32,
+    info: u8,
+    other: u8,
+    shndx: u16,
+    value: u64,
+    size: u64,
+  }
+
+  #[cfg(target_pointer_width = "32")]
+  #[repr(C)]
+  struct Sym {
+    name: u32,
+    value: u32,
+    size: u32,
+    info: u8,
+    other: u8,
+    shndx: u16,
+  }
+
+  /// The search for a module, passed to the `dl_iterate_phdr` callback.
+  struct Search<'a> {
+    module: &'a str,
+    exports: Option<Vec<Export>>,
+  }
+
+  pub fn exports(module: &str) -> Option<Vec<Export>> {
+    let mut search = Search {
+      module,
+      exports: None,
+    };
+    unsafe { libc::dl_iterate_phdr(Some(visit), &mut search as *mut Search as *mut c_void) };
+    search.exports
+  }
+
+  /// Reads the exports of a module, if it's the one searched for.
+  unsafe extern "C" fn visit(info: *mut dl_phdr_info, _size: size_t, data: *mut c_void) -> c_int {
+    let search = &mut *(data as *mut Search);
+    let info = &*info;
+    if info.dlpi_name.is_null() {
+      return 0;
+    }
+
+    let path = CStr::from_ptr(info.dlpi_name).to_string_lossy();
+    let name = path.rsplit('/').next().unwrap_or_default();
+    if path.is_empty() || (path != search.module && name != search.module) {
+      return 0;
+    }
+
+    search.exports = Some(read(info).unwrap_or_default());
+    1
+  }
+
+  /// Reads the dynamic symbol table of a loaded module.
+  unsafe fn read(info: &dl_phdr_info) -> Option<Vec<Export>> {
+    let headers = slice::from_raw_parts(info.dlpi_phdr, info.dlpi_phnum as usize);
+    let base = info.dlpi_addr as usize;
+    let dynamic = headers.iter().find(|header| header.p_type == PT_DYNAMIC)?;
+    let executable = headers
+      .iter()
+      .filter(|header| header.p_type == PT_LOAD && header.p_flags & PF_X != 0)
+      .map(|header| {
+        let start = base + header.p_vaddr as usize;
+        start..start + header.p_memsz as usize
+      })
+      .collect::<Vec<_>>();
+
+    // Addresses are relocated by the loader, except on some platforms (e.g
+    // with musl)
+    let relocate = |address: usize| {
+      if address < base {
+        address + base
+      } else {
+        address
+      }
+    };
+
+    let (mut symbols, mut strings, mut hash, mut gnu_hash, mut versions) = (0, 0, 0, 0, 0);
+    let mut entry = (base + dynamic.p_vaddr as usize) as *const Dyn;
+    while (*entry).tag != DT_NULL {
+      let value = (*entry).value;
+      match (*entry).tag {
+        DT_SYMTAB => symbols = relocate(value),
+        DT_STRTAB => strings = relocate(value),
+        DT_HASH => hash = relocate(value),
+        DT_GNU_HASH => gnu_hash = relocate(value),
+        DT_VERSYM => versions = relocate(value),
+        _ => (),
+      }
+      entry = entry.add(1);
+    }
+
+    if symbols == 0 || strings == 0 {
+      return None;
+    }
+
+    let count = if hash != 0 {
+      *(hash as *const u32).add(1) as usize
+    } else if gnu_hash != 0 {
+      gnu_hash_count(gnu_hash as *const u32)
+    } else {
+      return None;
+    };
+
+    let symbols = slice::from_raw_parts(symbols as *const Sym, count);
+    let mut handle = None;
+    let mut exports = Vec::new();
+
+    for (index, symbol) in symbols.iter().enumerate() {
+      let binding = symbol.info >> 4;
+      let kind = symbol.info & 0xf;
+      let is_hidden = versions != 0 && *(versions as *const u16).add(index) & VERSYM_HIDDEN != 0;
+
+      if binding == STB_LOCAL
+        || symbol.shndx == SHN_UNDEF
+        || symbol.shndx == SHN_ABS
+        || symbol.value == 0
+        || is_hidden
+      {
+        continue;
+      }
+
+      let name = CStr::from_ptr((strings + symbol.name as usize) as *const _);
+      let mut address = base + symbol.value as usize;
+
+      // The symbol refers to a resolver, returning the implementation
+      if kind == STT_GNU_IFUNC {
+        let handle = *handle
+          .get_or_insert_with(|| libc::dlopen(info.dlpi_name, libc::RTLD_LAZY | libc::RTLD_NOLOAD));
+        address = if handle.is_null() {
+          0
+        } else {
+          libc::dlsym(handle, name.as_ptr()) as usize
+        };
+        if address == 0 {
+          continue;
+        }
+      }
+
+      let is_code = executable.iter().any(|range| range.contains(&address));
+      let kind = match kind {
+        STT_FUNC | STT_GNU_IFUNC | STT_NOTYPE if is_code => ExportKind::Function,
+        _ => ExportKind::Data,
+      };
+
+      exports.push(Export {
+        name: String::from(name.to_string_lossy()),
+        address: address as *const (),
+        kind,
+      });
+    }
+
+    if let Some(handle) = handle.filter(|handle| !handle.is_null()) {
+      libc::dlclose(handle);
+    }
+    Some(exports)
+  }
+
+  /// Returns the number of symbols within a table described by a GNU hash
+  /// table, i.e one past the highest index within any chain.
+  unsafe fn gnu_hash_count(table: *const u32) -> usize {
+    let (count, offset, bloom) = (
+      *table as usize,
+      *table.add(1) as usize,
+      *table.add(2) as usize,
+    );
+    let buckets = slice::from_raw_parts(table.add(4 + bloom * mem::size_of::<usize>() / 4), count);
+    let chains = buckets.as_ptr().add(count);
+
+    let last = buckets.iter().copied().max().unwrap_or(0) as usize;
+    if last < offset {
+      return offset;
+    }
+
+    // The last symbol of a chain has its lowest bit set
+    let mut index = last;
+    while *chains.add(index - offset) & 1 == 0 {
+      index += 1;
+    }
+    index + 1
+  }
+}
+
+#[cfg(windows)]
+mod platform {
+  use super::{Export, ExportKind};
+  use core::slice;
+  use std::ffi::{CStr, CString};
+  use std::string::String;
+  use std::vec::Vec;
+  use winapi::um::libloaderapi::GetModuleHandleA;
+  use winapi::um::winnt::{
+    IMAGE_DIRECTORY_ENTRY_EXPORT, IMAGE_DOS_HEADER, IMAGE_EXPORT_DIRECTORY, IMAGE_FILE_HEADER,
+    IMAGE_NT_HEADERS, IMAGE_SCN_MEM_EXECUTE, IMAGE_SECTION_HEADER,
+  };
+
+  pub fn exports(module: &str) -> Option<Vec<Export>> {
+    let name = CString::new(module).ok()?;
+    let base = unsafe { GetModuleHandleA(name.as_ptr()) } as usize;
+    if base == 0 {
+      return None;
+    }
+    Some(unsafe { read(base) })
+  }
+
+  /// Reads the export directory of a loaded module.
+  unsafe fn read(base: usize) -> Vec<Export> {
+    let dos = &*(base as *const IMAGE_DOS_HEADER);
+    let nt = base + dos.e_lfanew as usize;
+    let headers = &*(nt as *const IMAGE_NT_HEADERS);
+
+    let directory = headers.OptionalHeader.DataDirectory[IMAGE_DIRECTORY_ENTRY_EXPORT as usize];
+    if directory.VirtualAddress == 0 || directory.Size == 0 {
+      return Vec::new();
+    }
+
+    let sections = slice::from_raw_parts(
+      (nt
+        + 4
+        + core::mem::size_of::<IMAGE_FILE_HEADER>()
+        + headers.FileHeader.SizeOfOptionalHeader as usize) as *const IMAGE_SECTION_HEADER,
+      headers.FileHeader.NumberOfSections as usize,
+    );
+    let is_code = |rva: usize| {
+      sections.iter().any(|section| {
+        let start = section.VirtualAddress as usize;
+        let size = *section.Misc.VirtualSize() as usize;
+        (start..start + size).contains(&rva) && section.Characteristics & IMAGE_SCN_MEM_EXECUTE != 0
+      })
+    };
+
+    let forwarders =
+      directory.VirtualAddress as usize..(directory.VirtualAddress + directory.Size) as usize;
+    let table = &*((base + directory.VirtualAddress as usize) as *const IMAGE_EXPORT_DIRECTORY);
+    let functions = slice::from_raw_parts(
+      (base + table.AddressOfFunctions as usize) as *const u32,
+      table.NumberOfFunctions as usize,
+    );
+    let names = slice::from_raw_parts(
+      (base + table.AddressOfNames as usize) as *const u32,
+      table.NumberOfNames as usize,
+    );
+    let ordinals = slice::from_raw_parts(
+      (base + table.AddressOfNameOrdinals as usize) as *const u16,
+      table.NumberOfNames as usize,
+    );
+
+    names
+      .iter()
+      .zip(ordinals)
+      .filter_map(|(&name, &ordinal)| {
+        let rva = *functions.get(ordinal as usize)? as usize;
+        let name = CStr::from_ptr((base + name as usize) as *const _);
+        let name = String::from(name.to_string_lossy());
+
+        // A forwarded export refers to a string (e.g `NTDLL.RtlAllocateHeap`)
+        let export = if forwarders.contains(&rva) {
+          let forwarder = CStr::from_ptr((base + rva) as *const _);
+          Export {
+            name,
+            address: core::ptr::null(),
+            kind: ExportKind::Forwarded(String::from(forwarder.to_string_lossy())),
+          }
+        } else {
+          Export {
+            name,
+            address: (base + rva) as *const (),
+            kind: if is_code(rva) {
+              ExportKind::Function
+            } else {
+              ExportKind::Data
+            },
+          }
+        };
+        Some(export)
+      })
+      .collect()
+  }
+}
@@ -0,0 +1,313 @@
+//! Operating system primitives used by detours.
+//!
+//! Every interaction with the operating system — querying and protecting
+//! memory, mapping executable memory and flushing the instruction cache — is
+//! performed through a [Backend](./trait.Backend.html). With the `std` feature
+//! enabled, a default implementation built upon the host operating system is
+//! used, unless another one is installed beforehand. Without `std`, a backend
+//! must be installed using [set_backend](./fn.set_backend.html) before any
+//! detour is created.
+//!
+//! # Example
+//!
+//! ```
+//! use detour::os;
+//!
+//! let backend = os::backend()?;
+//! assert!(backend.page_size().is_power_of_two());
+//! # Ok::<(), detour::Error>(())
+//! ```
+
+use crate::error::{Error, Result};
+use crate::sync::Global;
+use alloc::vec::Vec;
+use core::ops::{BitOr, Range};
+
+#[cfg(all(
+  feature = "std",
+  any(windows, target_os = "linux", target_os = "android")
+))]
+pub use self::exports::{exports, Export, ExportKind};
+#[cfg(all(feature = "std", windows))]
+pub use self::module::{Module, TlsCallback};
+#[cfg(feature = "std")]
+pub use self::native::Native;
+
+#[cfg(all(feature = "std", windows, target_arch = "x86_64"))]
+pub(crate) mod arm64ec;
+#[cfg(all(
+  feature = "std",
+  any(windows, target_os = "linux", target_os = "android")
+))]
+mod exports;
+#[cfg(all(feature = "std", any(windows, test)))]
+mod module;
+#[cfg(feature = "std")]
+mod native;
+#[cfg(feature = "std")]
+pub(crate) mod primitives;
+
+/// An interface to the operating system primitives required by detours.
+///
+/// # Safety
+///
+/// Implementors must uphold the documented contract of each method; the
+/// library relies upon them to write and execute machine code.
+pub unsafe trait Backend: Sync {
+  /// Returns the size of a memory page.
+  fn page_size(&self) -> usize;
+
+  /// Returns the alignment of addresses passed to `allocate`.
+  ///
+  /// The default implementation returns the page size.
+  fn allocation_granularity(&self) -> usize {
+    self.page_size()
+  }
+
+  /// Returns the memory region containing `address`, or `None` if the
+  /// address is not mapped.
+  fn query(&self, address: *const ()) -> Result<Option<Region>>;
+
+  /// Returns all mapped regions overlapping the range, sorted by address.
+  ///
+  /// This is used to find free memory close to a target, without querying
+  /// each address individually. The default implementation returns `None`,
+  /// denoting that regions cannot be enumerated.
+  fn query_range(&self, _range: Range<usize>) -> Option<Vec<Region>> {
+    None
+  }
+
+  /// Changes the protection of all pages overlapping the range.
+  unsafe fn protect(&self, address: *const (), size: usize, protection: Protection) -> Result<()>;
+
+  /// Maps executable memory at exactly `address`.
+  ///
+  /// The size is always a multiple of the page size. Returns `None` if the
+  /// address is already in use, and an error if the operating system failed
+  /// to map the memory. Any existing mapping must be left intact.
+  ///
+  /// Other addresses are still attempted after an error, unless it's
+  /// `Error::PermissionDenied`; the last error is returned if all fail.
+  ///
+  /// The memory must be writable, unless it has a writable alias (see
+  /// `write_alias`).
+  unsafe fn allocate(&self, address: *const (), size: usize) -> Result<Option<*mut u8>>;
+
+  /// Returns a writable alias of memory mapped by `allocate`, if the memory
+  /// is mapped twice.
+  ///
+  /// Executable memory with an alias is never written to directly; all code
+  /// is written to the alias instead. The default implementation returns
+  /// `None`.
+  fn write_alias(&self, _address: *const ()) -> Option<*mut u8> {
+    None
+  }
+
+  /// Releases memory previously mapped by `allocate`.
+  unsafe fn release(&self, address: *mut u8, size: usize);
+
+  /// Returns true if a grace period has ended, after which no other thread
+  /// is executing code within any of the ranges.
+  ///
+  /// The pool invokes this at most once per operation, with the ranges of all
+  /// memory awaiting reclamation, and only reuses memory once two grace
+  /// periods have ended since its release (see
+  /// [Reclamation](../pool/enum.Reclamation.html)). The default
+  /// implementation is conservative, and always returns false.
+  fn is_quiescent(&self, _ranges: &[Range<usize>]) -> bool {
+    false
+  }
+
+  /// Flushes the instruction cache for a range of modified code.
+  ///
+  /// The default implementation does nothing, which is sufficient for
+  /// architectures with a coherent instruction cache (i.e x86).
+  unsafe fn flush_instruction_cache(&self, _address: *const (), _size: usize) {}
+
+  /// Waits whilst a lock of the library is held by another thread.
+  ///
+  /// Without the `std` feature, the library's locks spin, calling this until
+  /// they're released (with it, they're the operating system's mutexes). A
+  /// backend may yield to its scheduler instead. The default implementation
+  /// hints the processor that it's spinning.
+  fn relax(&self) {
+    core::hint::spin_loop();
+  }
+}
+
+/// A description of a mapped memory region.
+#[derive(Debug, Clone, Copy)]
+pub struct Region {
+  /// Base address of the region.
+  pub base: *const (),
+  /// Size of the region.
+  pub size: usize,
+  /// Protection of the region.
+  pub protection: Protection,
+}
+
+impl Region {
+  /// Returns the region's lower bound.
+  pub fn lower(&self) -> usize {
+    self.base as usize
+  }
+
+  /// Returns the region's upper bound.
+  pub fn upper(&self) -> usize {
+    self.lower() + self.size
+  }
+}
+
+/// Memory page protection flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Protection(u8);
+
+impl Protection {
+  /// No access allowed at all.
+  pub const NONE: Protection = Protection(0);
+  /// Read access.
+  pub const READ: Protection = Protection(1 << 0);
+  /// Write access.
+  pub const WRITE: Protection = Protection(1 << 1);
+  /// Execute access.
+  pub const EXECUTE: Protection = Protection(1 << 2);
+  /// Read and execute access.
+  pub const READ_EXECUTE: Protection = Protection(Self::READ.0 | Self::EXECUTE.0);
+  /// Read and write access.
+  pub const READ_WRITE: Protection = Protection(Self::READ.0 | Self::WRITE.0);
+  /// Read, write and execute access.
+  pub const READ_WRITE_EXECUTE: Protection =
+    Protection(Self::READ.0 | Self::WRITE.0 | Self::EXECUTE.0);
+
+  /// Returns true if all flags in `other` are set.
+  pub const fn contains(self, other: Protection) -> bool {
+    self.0 & other.0 == other.0
+  }
+}
+
+impl BitOr for Protection {
+  type Output = Protection;
+
+  fn bitor(self, other: Protection) -> Protection {
+    Protection(self.0 | other.0)
+  }
+}
+
+/// The installed backend.
+static BACKEND: Global<dyn Backend> = Global::new();
+
+/// Installs the backend used for all operating system interaction.
+///
+/// The backend can only be installed once, and must be installed before it's
+/// used by any detour; `Error::AlreadyInitialized` is returned otherwise.
+pub fn set_backend(backend: &'static dyn Backend) -> Result<()> {
+  BACKEND.set(backend)
+}
+
+/// Returns the installed backend.
+///
+/// With the `std` feature enabled, the native backend is installed if none
+/// has been installed. Otherwise `Error::MissingBackend` is returned.
+pub fn backend() -> Result<&'static dyn Backend> {
+  #[cfg(feature = "std")]
+  {
+    Ok(BACKEND.get_or_set(&Native))
+  }
+
+  #[cfg(not(feature = "std"))]
+  BACKEND.get().ok_or(Error::MissingBackend)
+}
+
+/// Rounds an address down to a multiple of the (power of two) alignment.
+pub(crate) const fn align_down(address: usize, alignment: usize) -> usize {
+  address & !(alignment - 1)
+}
+
+/// Rounds an address up to a multiple of the (power of two) alignment.
+pub(crate) const fn align_up(address: usize, alignment: usize) -> usize {
+  align_down(address.saturating_add(alignment - 1), alignment)
+}
+
+/// Returns the range of all pages overlapping a range.
+pub(crate) const fn page_range(address: usize, size: usize, page_size: usize) -> Range<usize> {
+  align_down(address, page_size)..align_up(address + size, page_size)
+}
+
+/// Changes the protection of a range for the lifetime of the returned guard.
+pub(crate) unsafe fn protect_with_guard(
+  address: *const (),
+  size: usize,
+  protection: Protection,
+) -> Result<ProtectionGuard> {
+  let backend = backend()?;
+  let Range {
+    start: lower,
+    end: upper,
+  } = page_range(address as usize, size, backend.page_size());
+
+  // Determine the current protection of all affected pages
+  let mut regions = Vec::new();
+  let mut current = lower;
+
+  while current < upper {
+    let mut region = backend
+      .query(current as *const ())?
+      .ok_or(Error::NotExecutable)?;
+
+    // Clamp the region to the affected range
+    let region_upper = region.upper().min(upper);
+    region.base = current as *const ();
+    region.size = region_upper - current;
+
+    current = region_upper;
+    regions.push(region);
+  }
+
+  backend.protect(lower as *const (), upper - lower, protection)?;
+  Ok(ProtectionGuard { backend, regions })
+}
+
+/// A guard restoring the previous protection of a range once dropped.
+#[must_use]
+pub(crate) struct ProtectionGuard {
+  backend: &'static dyn Backend,
+  regions: Vec<Region>,
+}
+
+impl Drop for ProtectionGuard {
+  fn drop(&mut self) {
+    for region in &self.regions {
+      let result = unsafe {
+        self
+          .backend
+          .protect(region.base, region.size, region.protection)
+      };
+      debug_assert!(result.is_ok(), "restoring region protection");
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn page_range_with_large_pages() {
+    const PAGE_SIZE: usize = 0x4000;
+
+    // A patch straddling a 4 KiB boundary is within a single 16 KiB page
+    let range = page_range(0x1_2ffe, 5, PAGE_SIZE);
+    assert_eq!((range.start, range.end), (0x1_0000, 0x1_4000));
+
+    // ... whilst a patch straddling a 16 KiB boundary covers two
+    let range = page_range(0x1_3ffe, 5, PAGE_SIZE);
+    assert_eq!((range.start, range.end), (0x1_0000, 0x1_8000));
+
+    assert_eq!(align_up(0x4001, PAGE_SIZE), 0x8000);
+    assert_eq!(align_up(0x4000, PAGE_SIZE), 0x4000);
+    assert_eq!(
+      align_up(usize::MAX, PAGE_SIZE),
+      align_down(usize::MAX, PAGE_SIZE)
+    );
+  }
+}
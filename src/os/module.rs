@@ -0,0 +1,394 @@
+//! Inspection of the PE image of a loaded module.
+//!
+//! Everything is read from the image as mapped by the loader, therefore
+//! addresses within the headers (e.g the TLS directory's callback array) have
+//! already been relocated. Every read is validated against the bounds of the
+//! image (i.e `SizeOfImage`).
+#![cfg_attr(not(windows), allow(dead_code))]
+
+use crate::error::{Error, Result};
+use core::ffi::c_void;
+use core::{mem, ptr};
+use std::format;
+use std::vec::Vec;
+
+/// The signature of a TLS callback (i.e `PIMAGE_TLS_CALLBACK`).
+pub type TlsCallback = unsafe extern "system" fn(*mut c_void, u32, *mut c_void);
+
+const DOS_MAGIC: u16 = 0x5A4D;
+const NT_SIGNATURE: u32 = 0x0000_4550;
+const PE32_MAGIC: u16 = 0x10B;
+const PE32_PLUS_MAGIC: u16 = 0x20B;
+
+/// The offset of `e_lfanew` within the DOS header.
+const DOS_LFANEW: usize = 0x3C;
+/// The offset of the optional header within the NT headers.
+const NT_OPTIONAL_HEADER: usize = 24;
+/// The offset of `AddressOfEntryPoint` within the optional header.
+const OPTIONAL_ENTRY_POINT: usize = 16;
+/// The offset of `SizeOfImage` within the optional header.
+const OPTIONAL_SIZE_OF_IMAGE: usize = 56;
+/// The index of the TLS directory (`IMAGE_DIRECTORY_ENTRY_TLS`).
+const DIRECTORY_TLS: usize = 9;
+
+/// A module loaded by the process, identified by the base of its image.
+///
+/// # Example
+///
+/// ```no_run
+/// # #[cfg(windows)]
+/// # fn main() -> detour::Result<()> {
+/// use detour::os::{Module, TlsCallback};
+/// use detour::MultiDetour;
+/// use std::ffi::c_void;
+///
+/// unsafe extern "system" fn callback(module: *mut c_void, reason: u32, reserved: *mut c_void) {
+///   // ...
+/// }
+///
+/// let module = Module::find("example.dll")?;
+/// println!("entry point: {:?}", module.entry_point());
+///
+/// let callback = callback as TlsCallback;
+/// let hook = unsafe { MultiDetour::<TlsCallback>::with_tls_callbacks(&module, callback)? };
+/// unsafe { hook.enable()? };
+/// # Ok(())
+/// # }
+/// # #[cfg(not(windows))]
+/// # fn main() {}
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Module {
+  base: usize,
+  size: usize,
+  /// Whether the image is a PE32+ image (i.e with 64-bit addresses).
+  is_pe32_plus: bool,
+  /// The offset of the optional header.
+  optional: usize,
+}
+
+unsafe impl Send for Module {}
+unsafe impl Sync for Module {}
+
+impl Module {
+  /// Returns a loaded module, identified by its file name (e.g `ws2_32.dll`).
+  ///
+  /// The module is not loaded if required; `Error::UnknownModule` is
+  /// returned instead.
+  #[cfg(windows)]
+  pub fn find(name: &str) -> Result<Self> {
+    use std::ffi::CString;
+    use winapi::um::libloaderapi::GetModuleHandleA;
+
+    let unknown = || Error::UnknownModule { name: name.into() };
+    let name = CString::new(name).map_err(|_| unknown())?;
+    let base = unsafe { GetModuleHandleA(name.as_ptr()) };
+
+    if base.is_null() {
+      Err(unknown())?;
+    }
+    unsafe { Self::from_base(base as *const ()) }
+  }
+
+  /// Returns the executable of the process.
+  #[cfg(windows)]
+  pub fn executable() -> Result<Self> {
+    use winapi::um::libloaderapi::GetModuleHandleA;
+    unsafe { Self::from_base(GetModuleHandleA(ptr::null()) as *const ()) }
+  }
+
+  /// Returns the module whose image is mapped at `base`.
+  ///
+  /// `Error::UnknownModule` is returned unless `base` contains the headers
+  /// of a PE image.
+  ///
+  /// # Safety
+  ///
+  /// The headers of the image must be readable, and the image must remain
+  /// mapped whilst the module is used.
+  pub unsafe fn from_base(base: *const ()) -> Result<Self> {
+    Self::parse(base as usize).ok_or_else(|| Error::UnknownModule {
+      name: format!("{:p}", base),
+    })
+  }
+
+  /// Parses the headers of an image.
+  unsafe fn parse(base: usize) -> Option<Self> {
+    if base == 0 || read::<u16>(base) != DOS_MAGIC {
+      return None;
+    }
+
+    let nt = read::<u32>(base + DOS_LFANEW) as usize;
+    if read::<u32>(base + nt) != NT_SIGNATURE {
+      return None;
+    }
+
+    let optional = nt + NT_OPTIONAL_HEADER;
+    let is_pe32_plus = match read::<u16>(base + optional) {
+      PE32_MAGIC => false,
+      PE32_PLUS_MAGIC => true,
+      _ => return None,
+    };
+
+    let size = read::<u32>(base + optional + OPTIONAL_SIZE_OF_IMAGE) as usize;
+    let module = Module {
+      base,
+      size,
+      is_pe32_plus,
+      optional,
+    };
+
+    // The data directories must be within the image
+    module.directory(DIRECTORY_TLS)?;
+    Some(module)
+  }
+
+  /// Returns the base address of the image.
+  pub fn base(&self) -> *const () {
+    self.base as *const ()
+  }
+
+  /// Returns the size of the image.
+  pub fn size(&self) -> usize {
+    self.size
+  }
+
+  /// Returns whether an address is within the image.
+  pub fn contains(&self, address: *const ()) -> bool {
+    (self.base..self.base + self.size).contains(&(address as usize))
+  }
+
+  /// Returns the entry point of the module (e.g `DllMain` of a library, or
+  /// `mainCRTStartup` of an executable), if it has one.
+  ///
+  /// Libraries without an entry point, and entry points outside of the
+  /// image, yield `None`.
+  pub fn entry_point(&self) -> Option<*const ()> {
+    let rva = self.read::<u32>(self.optional + OPTIONAL_ENTRY_POINT)? as usize;
+    (rva != 0 && rva < self.size).then(|| (self.base + rva) as *const ())
+  }
+
+  /// Returns the TLS callbacks of the module, in the order they're invoked.
+  ///
+  /// The callback array is read up to its terminating null entry, or the end
+  /// of the image. Since the array may reside within a writable section, it
+  /// reflects any callbacks registered at runtime. Callbacks outside of the
+  /// image are excluded. An image without a TLS directory, or with an empty
+  /// callback array, yields no callbacks.
+  pub fn tls_callbacks(&self) -> Vec<*const ()> {
+    let mut callbacks = Vec::new();
+    let directory = match self.directory(DIRECTORY_TLS) {
+      Some((rva, size)) if rva != 0 && size != 0 => rva,
+      _ => return callbacks,
+    };
+
+    // The array follows the start, end and index addresses
+    let address = self.read_address(directory + 3 * self.address_size());
+    let mut entry = match address.filter(|&address| self.contains(address as *const ())) {
+      Some(address) => address - self.base,
+      None => return callbacks,
+    };
+
+    while let Some(callback) = self.read_address(entry).filter(|&callback| callback != 0) {
+      if self.contains(callback as *const ()) {
+        callbacks.push(callback as *const ());
+      }
+      entry += self.address_size();
+    }
+    callbacks
+  }
+
+  /// Returns the RVA and size of a data directory (zero if the image lacks
+  /// it), unless the headers exceed the image.
+  fn directory(&self, index: usize) -> Option<(usize, usize)> {
+    // The directories follow the amount of directories
+    let count = if self.is_pe32_plus { 108 } else { 92 };
+    if index >= self.read::<u32>(self.optional + count)? as usize {
+      return Some((0, 0));
+    }
+
+    let entry = self.optional + count + 4 + index * 8;
+    let rva = self.read::<u32>(entry)? as usize;
+    let size = self.read::<u32>(entry + 4)? as usize;
+    Some((rva, size))
+  }
+
+  /// Returns the size of an address within the image.
+  fn address_size(&self) -> usize {
+    if self.is_pe32_plus {
+      8
+    } else {
+      4
+    }
+  }
+
+  /// Reads an address at an offset within the image.
+  fn read_address(&self, offset: usize) -> Option<usize> {
+    if self.is_pe32_plus {
+      self.read::<u64>(offset).map(|address| address as usize)
+    } else {
+      self.read::<u32>(offset).map(|address| address as usize)
+    }
+  }
+
+  /// Reads a value at an offset within the image.
+  fn read<T: Copy>(&self, offset: usize) -> Option<T> {
+    let end = offset.checked_add(mem::size_of::<T>())?;
+    (end <= self.size).then(|| unsafe { read(self.base + offset) })
+  }
+}
+
+/// Reads a value at an address, regardless of its alignment.
+unsafe fn read<T: Copy>(address: usize) -> T {
+  ptr::read_unaligned(address as *const T)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// The size of a synthetic image.
+  const SIZE: usize = 0x400;
+  /// The offset of the NT headers.
+  const NT: usize = 0x80;
+  /// The offset of the TLS directory.
+  const TLS: usize = 0x200;
+  /// The offset of the TLS callback array.
+  const CALLBACKS: usize = 0x280;
+
+  /// A synthetic image, with the headers of a PE image.
+  struct Image(Vec<u64>);
+
+  impl Image {
+    /// Constructs an image with an entry point and a TLS directory.
+    fn new() -> Self {
+      let mut image = Image(alloc::vec![0; SIZE / 8]);
+      let optional = NT + NT_OPTIONAL_HEADER;
+
+      image.write(0, &DOS_MAGIC.to_le_bytes());
+      image.write(DOS_LFANEW, &(NT as u32).to_le_bytes());
+      image.write(NT, &NT_SIGNATURE.to_le_bytes());
+      image.write(optional, &PE32_PLUS_MAGIC.to_le_bytes());
+      image.write(optional + OPTIONAL_ENTRY_POINT, &0x300u32.to_le_bytes());
+      image.write(
+        optional + OPTIONAL_SIZE_OF_IMAGE,
+        &(SIZE as u32).to_le_bytes(),
+      );
+      image.write(optional + 108, &16u32.to_le_bytes());
+
+      let directory = optional + 112 + DIRECTORY_TLS * 8;
+      image.write(directory, &(TLS as u32).to_le_bytes());
+      image.write(directory + 4, &40u32.to_le_bytes());
+
+      let callbacks = image.address(CALLBACKS);
+      image.write(TLS + 24, &callbacks.to_le_bytes());
+      image
+    }
+
+    /// Returns the address of an offset within the image.
+    fn address(&self, offset: usize) -> u64 {
+      (self.0.as_ptr() as usize + offset) as u64
+    }
+
+    /// Writes bytes at an offset within the image.
+    fn write(&mut self, offset: usize, bytes: &[u8]) {
+      let image = unsafe { core::slice::from_raw_parts_mut(self.0.as_mut_ptr() as *mut u8, SIZE) };
+      image[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    /// Writes the TLS callback array.
+    fn set_callbacks(&mut self, callbacks: &[u64]) {
+      for (index, callback) in callbacks.iter().enumerate() {
+        self.write(CALLBACKS + index * 8, &callback.to_le_bytes());
+      }
+    }
+
+    fn module(&self) -> Module {
+      unsafe { Module::from_base(self.0.as_ptr() as *const ()) }.unwrap()
+    }
+  }
+
+  #[test]
+  fn entry_point() {
+    let mut image = Image::new();
+    let module = image.module();
+    assert_eq!(module.size(), SIZE);
+    assert_eq!(
+      module.entry_point(),
+      Some(image.address(0x300) as *const ())
+    );
+
+    // Libraries may lack an entry point
+    image.write(
+      NT + NT_OPTIONAL_HEADER + OPTIONAL_ENTRY_POINT,
+      &0u32.to_le_bytes(),
+    );
+    assert_eq!(image.module().entry_point(), None);
+
+    image.write(
+      NT + NT_OPTIONAL_HEADER + OPTIONAL_ENTRY_POINT,
+      &0x1000u32.to_le_bytes(),
+    );
+    assert_eq!(image.module().entry_point(), None);
+  }
+
+  #[test]
+  fn tls_callbacks() {
+    let mut image = Image::new();
+    let (first, second) = (image.address(0x300), image.address(0x310));
+    image.set_callbacks(&[first, second, 0]);
+    assert_eq!(
+      image.module().tls_callbacks(),
+      [first as *const (), second as *const ()]
+    );
+
+    // Callbacks outside of the image are excluded
+    image.set_callbacks(&[first, 0x1234, second, 0]);
+    assert_eq!(
+      image.module().tls_callbacks(),
+      [first as *const (), second as *const ()]
+    );
+
+    // ... and the array ends with the image
+    let image_end = (CALLBACKS..SIZE)
+      .step_by(8)
+      .map(|_| first)
+      .collect::<Vec<_>>();
+    image.set_callbacks(&image_end);
+    assert_eq!(image.module().tls_callbacks().len(), image_end.len());
+  }
+
+  #[test]
+  fn empty_tls_callbacks() {
+    // An empty array
+    let mut image = Image::new();
+    assert!(image.module().tls_callbacks().is_empty());
+
+    // A directory without an array
+    image.write(TLS + 24, &0u64.to_le_bytes());
+    assert!(image.module().tls_callbacks().is_empty());
+
+    // ... or an array outside of the image
+    image.write(TLS + 24, &0x1234u64.to_le_bytes());
+    assert!(image.module().tls_callbacks().is_empty());
+
+    // An image without a TLS directory
+    let directory = NT + NT_OPTIONAL_HEADER + 112 + DIRECTORY_TLS * 8;
+    image.write(directory, &[0; 8]);
+    assert!(image.module().tls_callbacks().is_empty());
+
+    image.write(NT + NT_OPTIONAL_HEADER + 108, &4u32.to_le_bytes());
+    assert!(image.module().tls_callbacks().is_empty());
+  }
+
+  #[test]
+  fn invalid_image() {
+    let mut image = Image::new();
+    image.write(NT, &[0; 4]);
+    let base = image.0.as_ptr() as *const ();
+    assert!(matches!(
+      unsafe { Module::from_base(base) },
+      Err(Error::UnknownModule { .. })
+    ));
+  }
+}
@@ -0,0 +1,1208 @@
+use super::{primitives, Backend, Protection, Region};
+use crate::error::Result;
+use core::ops::Range;
+use std::vec::Vec;
+
+/// The host operating system's backend, used by default with `std`.
+///
+/// On Linux, Android and the BSDs, if mapping anonymous executable memory is
+/// denied (e.g by SELinux), memory is instead mapped twice from a shared memory
+/// file; as read-execute close to each target, and as read-write elsewhere.
+/// On Android, memory is mapped from ashmem, if mapping memfd is denied too.
+/// If all fallbacks fail, `Error::ExecutableMemoryDenied` lists their errors.
+/// OpenBSD enforces W^X, therefore memory is always dual mapped, and targets
+/// are not executable whilst they're patched.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Native;
+
+impl Native {
+  /// Resolves the address of a function exported by any module loaded within
+  /// the process (using `dlsym` or `GetProcAddress`).
+  pub fn resolve_symbol(name: &str) -> Option<*const ()> {
+    let name = std::ffi::CString::new(name).ok()?;
+    symbols::resolve(&name).filter(|address| !address.is_null())
+  }
+}
+
+unsafe impl Backend for Native {
+  #[cfg(not(any(target_os = "netbsd", target_os = "openbsd")))]
+  fn page_size(&self) -> usize {
+    region::page::size()
+  }
+
+  #[cfg(any(target_os = "netbsd", target_os = "openbsd"))]
+  fn page_size(&self) -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+  }
+
+  #[cfg(windows)]
+  fn allocation_granularity(&self) -> usize {
+    regions::allocation_granularity()
+  }
+
+  #[cfg(not(any(target_os = "netbsd", target_os = "openbsd")))]
+  fn query(&self, address: *const ()) -> Result<Option<Region>> {
+    match region::query(address as *const _) {
+      Ok(region) => Ok(Some(Region {
+        base: region.base as *const (),
+        size: region.size,
+        protection: region.protection.into(),
+      })),
+      Err(region::Error::FreeMemory) => Ok(None),
+      Err(error) => Err(error.into()),
+    }
+  }
+
+  #[cfg(any(target_os = "netbsd", target_os = "openbsd"))]
+  fn query(&self, address: *const ()) -> Result<Option<Region>> {
+    let address = address as usize;
+    let regions = regions::vm_map(address..address + 1)?;
+    Ok(regions.into_iter().find(|region| region.lower() <= address))
+  }
+
+  fn query_range(&self, range: Range<usize>) -> Option<Vec<Region>> {
+    regions::query_range(range)
+  }
+
+  #[cfg(not(any(target_os = "netbsd", target_os = "openbsd")))]
+  unsafe fn protect(&self, address: *const (), size: usize, protection: Protection) -> Result<()> {
+    // A detoured primitive is called through its trampoline instead
+    if primitives::PROTECT.is_detoured() {
+      return primitives::protect(address, size, protection)
+        .map_err(|error| region::Error::SystemCall(error).into());
+    }
+
+    region::protect(address as *const _, size, protection.into()).map_err(Into::into)
+  }
+
+  #[cfg(any(target_os = "netbsd", target_os = "openbsd"))]
+  unsafe fn protect(&self, address: *const (), size: usize, protection: Protection) -> Result<()> {
+    let page_size = self.page_size();
+    let lower = address as usize & !(page_size - 1);
+    let upper = (address as usize + size.max(1) + page_size - 1) & !(page_size - 1);
+    mapping::protect(lower as *mut u8, upper - lower, protection)
+  }
+
+  unsafe fn allocate(&self, address: *const (), size: usize) -> Result<Option<*mut u8>> {
+    mapping::allocate(address, size)
+  }
+
+  fn write_alias(&self, address: *const ()) -> Option<*mut u8> {
+    mapping::write_alias(address)
+  }
+
+  fn is_quiescent(&self, ranges: &[Range<usize>]) -> bool {
+    quiescence::is_quiescent(ranges)
+  }
+
+  unsafe fn release(&self, address: *mut u8, size: usize) {
+    mapping::release(address, size)
+  }
+
+  #[cfg(all(windows, target_arch = "x86_64"))]
+  unsafe fn flush_instruction_cache(&self, address: *const (), size: usize) {
+    // The emulator of an ARM64 host caches translated code
+    if super::arm64ec::is_emulated() {
+      primitives::flush_instruction_cache(address, size);
+    }
+  }
+}
+
+#[cfg(not(any(target_os = "netbsd", target_os = "openbsd")))]
+impl From<region::Protection> for Protection {
+  fn from(protection: region::Protection) -> Self {
+    [
+      (region::Protection::READ, Protection::READ),
+      (region::Protection::WRITE, Protection::WRITE),
+      (region::Protection::EXECUTE, Protection::EXECUTE),
+    ]
+    .iter()
+    .filter(|(flag, _)| protection.contains(*flag))
+    .fold(Protection::NONE, |result, (_, flag)| result | *flag)
+  }
+}
+
+#[cfg(not(any(target_os = "netbsd", target_os = "openbsd")))]
+impl From<Protection> for region::Protection {
+  fn from(protection: Protection) -> Self {
+    [
+      (Protection::READ, region::Protection::READ),
+      (Protection::WRITE, region::Protection::WRITE),
+      (Protection::EXECUTE, region::Protection::EXECUTE),
+    ]
+    .iter()
+    .filter(|(flag, _)| protection.contains(*flag))
+    .fold(region::Protection::NONE, |result, (_, flag)| result | *flag)
+  }
+}
+
+#[cfg(unix)]
+mod mapping {
+  use crate::error::{Error, OsError, Result};
+  use crate::os::primitives;
+  #[cfg(any(target_os = "netbsd", target_os = "openbsd"))]
+  use crate::os::Protection;
+
+  /// Replaces no existing mappings (not exposed by libc for all targets).
+  #[cfg(any(target_os = "linux", target_os = "android"))]
+  const MAP_FIXED_NOREPLACE: libc::c_int = 0x100000;
+  /// FreeBSD's equivalent of `MAP_FIXED_NOREPLACE`.
+  #[cfg(target_os = "freebsd")]
+  const MAP_FIXED_NOREPLACE: libc::c_int = libc::MAP_FIXED | libc::MAP_EXCL;
+  /// Prefers the address, unless it's in use (a hint otherwise).
+  #[cfg(target_os = "netbsd")]
+  const MAP_FIXED_NOREPLACE: libc::c_int = libc::MAP_TRYFIXED;
+  #[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "netbsd"
+  )))]
+  const MAP_FIXED_NOREPLACE: libc::c_int = 0;
+
+  /// Maps memory at an address, without replacing any existing mapping.
+  ///
+  /// Kernels that predate `MAP_FIXED_NOREPLACE` (or lack an equivalent, e.g
+  /// OpenBSD) treat the address as a hint, therefore a mapping elsewhere is
+  /// discarded. If anonymous executable memory is denied, memory is dual
+  /// mapped instead (where supported).
+  pub unsafe fn allocate(address: *const (), size: usize) -> Result<Option<*mut u8>> {
+    #[cfg(any(
+      target_os = "linux",
+      target_os = "android",
+      target_os = "freebsd",
+      target_os = "netbsd",
+      target_os = "openbsd"
+    ))]
+    if dual::is_enabled() {
+      return dual::allocate(address, size);
+    }
+
+    let protection = libc::PROT_READ | libc::PROT_WRITE | libc::PROT_EXEC;
+    let flags = libc::MAP_PRIVATE | libc::MAP_ANON | MAP_FIXED_NOREPLACE;
+    let data = primitives::mmap(address as *mut _, size, protection, flags, -1, 0);
+
+    if data == libc::MAP_FAILED {
+      let error = last_error();
+      if !is_denied(error) {
+        return unmapped("mmap", error);
+      }
+
+      #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "openbsd"
+      ))]
+      {
+        dual::enable(error);
+        return dual::allocate(address, size);
+      }
+
+      #[cfg(not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "openbsd"
+      )))]
+      return Err(failure("mmap(PROT_WRITE | PROT_EXEC)", error));
+    }
+
+    Ok(discard_misplaced(data, address, size))
+  }
+
+  /// Unmaps memory previously mapped by `allocate`.
+  pub unsafe fn release(address: *mut u8, size: usize) {
+    #[cfg(any(
+      target_os = "linux",
+      target_os = "android",
+      target_os = "freebsd",
+      target_os = "netbsd",
+      target_os = "openbsd"
+    ))]
+    dual::release(address);
+
+    let result = primitives::munmap(address as *mut _, size);
+    debug_assert_eq!(result, 0);
+  }
+
+  /// Returns the writable alias of dual mapped memory.
+  pub fn write_alias(address: *const ()) -> Option<*mut u8> {
+    #[cfg(any(
+      target_os = "linux",
+      target_os = "android",
+      target_os = "freebsd",
+      target_os = "netbsd",
+      target_os = "openbsd"
+    ))]
+    return dual::write_alias(address);
+
+    #[cfg(not(any(
+      target_os = "linux",
+      target_os = "android",
+      target_os = "freebsd",
+      target_os = "netbsd",
+      target_os = "openbsd"
+    )))]
+    {
+      let _ = address;
+      None
+    }
+  }
+
+  /// Changes the protection of page aligned memory.
+  ///
+  /// W^X is enforced on OpenBSD, therefore writable memory is never
+  /// executable.
+  #[cfg(any(target_os = "netbsd", target_os = "openbsd"))]
+  pub unsafe fn protect(address: *mut u8, size: usize, protection: Protection) -> Result<()> {
+    let mut flags = [
+      (Protection::READ, libc::PROT_READ),
+      (Protection::WRITE, libc::PROT_WRITE),
+      (Protection::EXECUTE, libc::PROT_EXEC),
+    ]
+    .iter()
+    .filter(|(flag, _)| protection.contains(*flag))
+    .fold(libc::PROT_NONE, |result, (_, flag)| result | flag);
+
+    if cfg!(target_os = "openbsd") && flags & libc::PROT_WRITE != 0 {
+      flags &= !libc::PROT_EXEC;
+    }
+
+    if primitives::mprotect(address as *mut _, size, flags) == 0 {
+      Ok(())
+    } else {
+      Err(failure("mprotect", last_error()))
+    }
+  }
+
+  /// Returns the error of the last failed operation.
+  pub(super) fn last_error() -> OsError {
+    OsError(std::io::Error::last_os_error().raw_os_error().unwrap_or(0))
+  }
+
+  /// Returns whether an error denotes a denied operation.
+  fn is_denied(error: OsError) -> bool {
+    matches!(error.code(), libc::EACCES | libc::EPERM)
+  }
+
+  /// Returns the error for a failed operation.
+  pub(super) fn failure(operation: &'static str, error: OsError) -> Error {
+    if is_denied(error) {
+      Error::PermissionDenied { operation, error }
+    } else {
+      Error::AllocationFailed { operation, error }
+    }
+  }
+
+  /// Returns the result of a failed mapping; an address in use is not an
+  /// error.
+  fn unmapped(operation: &'static str, error: OsError) -> Result<Option<*mut u8>> {
+    if is_occupied(error) {
+      Ok(None)
+    } else {
+      Err(failure(operation, error))
+    }
+  }
+
+  /// Returns whether an error denotes an address already in use.
+  ///
+  /// FreeBSD documents `EINVAL` for `MAP_EXCL`, although `ENOMEM` is reported
+  /// by its kernel.
+  fn is_occupied(error: OsError) -> bool {
+    if cfg!(target_os = "freebsd") {
+      matches!(error.code(), libc::EINVAL | libc::ENOMEM)
+    } else {
+      error.code() == libc::EEXIST
+    }
+  }
+
+  /// Unmaps memory mapped at another address than requested.
+  unsafe fn discard_misplaced(
+    data: *mut libc::c_void,
+    address: *const (),
+    size: usize,
+  ) -> Option<*mut u8> {
+    if data as *const () == address {
+      Some(data as *mut u8)
+    } else {
+      primitives::munmap(data, size);
+      None
+    }
+  }
+
+  /// Memory mapped twice; as read-execute at the requested address, and as
+  /// read-write elsewhere. Protections are never changed.
+  #[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd"
+  ))]
+  pub(super) mod dual {
+    use super::{discard_misplaced, failure, last_error, unmapped, MAP_FIXED_NOREPLACE};
+    use crate::error::{Error, OsError, Result};
+    use crate::os::primitives;
+    use crate::sync::Mutex;
+    use core::ptr;
+    use core::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
+    use std::vec::Vec;
+
+    /// Creates a shared memory file of a size.
+    type Source = (&'static str, unsafe fn(usize) -> Result<libc::c_int>);
+
+    /// The sources of shared memory files, in order of preference.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    const SOURCES: &[Source] = &[
+      ("memfd", create_memfd),
+      // Older Android versions only provide ashmem, and SELinux policies may
+      // permit executing ashmem, but not memfd
+      #[cfg(target_os = "android")]
+      ("ashmem", create_ashmem),
+    ];
+    #[cfg(any(target_os = "freebsd", target_os = "netbsd", target_os = "openbsd"))]
+    const SOURCES: &[Source] = &[("shm_open", create_shm)];
+
+    /// Closes the file descriptor once it's no longer needed.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    const MFD_CLOEXEC: libc::c_uint = 1;
+
+    /// The error anonymous executable memory was denied with, once denied.
+    static DENIAL: AtomicI32 = AtomicI32::new(0);
+
+    /// The first source not known to fail.
+    static SOURCE: AtomicUsize = AtomicUsize::new(0);
+
+    /// The writable alias of each dual mapping.
+    static ALIASES: Mutex<Vec<Alias>> = Mutex::new(Vec::new());
+
+    struct Alias {
+      executable: usize,
+      writable: usize,
+      size: usize,
+    }
+
+    /// Returns whether memory is dual mapped (always, with W^X enforced).
+    pub fn is_enabled() -> bool {
+      cfg!(target_os = "openbsd") || DENIAL.load(Ordering::SeqCst) != 0
+    }
+
+    /// Dual maps all subsequent allocations, since anonymous executable
+    /// memory was denied.
+    pub fn enable(denial: OsError) {
+      DENIAL.store(denial.code(), Ordering::SeqCst);
+    }
+
+    /// Maps a shared memory file twice, executable at exactly the address.
+    ///
+    /// Each source of shared memory files is attempted in order, until one is
+    /// successfully mapped.
+    pub unsafe fn allocate(address: *const (), size: usize) -> Result<Option<*mut u8>> {
+      let mut failures = Vec::new();
+
+      for (index, (name, create_file)) in SOURCES
+        .iter()
+        .enumerate()
+        .skip(SOURCE.load(Ordering::Relaxed))
+      {
+        match map_file(*create_file, address, size) {
+          Err(error) => failures.push((*name, error)),
+          result => {
+            SOURCE.store(index, Ordering::Relaxed);
+            return result;
+          },
+        }
+      }
+
+      Err(diagnose(failures))
+    }
+
+    /// Returns the error of failed sources; if anonymous executable memory was
+    /// denied, the denial is reported instead, along with each failure.
+    fn diagnose(mut failures: Vec<(&'static str, Error)>) -> Error {
+      match DENIAL.load(Ordering::SeqCst) {
+        0 => failures
+          .pop()
+          .map(|(_, error)| error)
+          .expect("a failed source"),
+        code => Error::ExecutableMemoryDenied {
+          error: OsError(code),
+          fallbacks: failures,
+        },
+      }
+    }
+
+    /// Maps a shared memory file from a source twice.
+    unsafe fn map_file(
+      create_file: unsafe fn(usize) -> Result<libc::c_int>,
+      address: *const (),
+      size: usize,
+    ) -> Result<Option<*mut u8>> {
+      let fd = create_file(size)?;
+
+      let protection = libc::PROT_READ | libc::PROT_EXEC;
+      let flags = libc::MAP_SHARED | MAP_FIXED_NOREPLACE;
+      let executable = primitives::mmap(address as *mut _, size, protection, flags, fd, 0);
+
+      let result = if executable == libc::MAP_FAILED {
+        unmapped("mmap(PROT_EXEC) of a shared memory file", last_error())
+      } else {
+        match discard_misplaced(executable, address, size) {
+          Some(executable) => map_alias(executable, fd, size).map(Some),
+          None => Ok(None),
+        }
+      };
+
+      libc::close(fd);
+      result
+    }
+
+    /// Maps the writable alias of an executable mapping.
+    unsafe fn map_alias(executable: *mut u8, fd: libc::c_int, size: usize) -> Result<*mut u8> {
+      let protection = libc::PROT_READ | libc::PROT_WRITE;
+      let writable = primitives::mmap(ptr::null_mut(), size, protection, libc::MAP_SHARED, fd, 0);
+
+      if writable == libc::MAP_FAILED {
+        let error = failure("mmap(PROT_WRITE) of a shared memory file", last_error());
+
+        primitives::munmap(executable as *mut _, size);
+        return Err(error);
+      }
+
+      ALIASES.lock().push(Alias {
+        executable: executable as usize,
+        writable: writable as usize,
+        size,
+      });
+      Ok(executable)
+    }
+
+    /// Creates an anonymous memory file.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    unsafe fn create_memfd(size: usize) -> Result<libc::c_int> {
+      let name = b"detour\0".as_ptr();
+      let fd = libc::syscall(libc::SYS_memfd_create, name, MFD_CLOEXEC) as libc::c_int;
+
+      if fd < 0 {
+        return Err(failure("memfd_create", last_error()));
+      }
+
+      resize(fd, size)
+    }
+
+    /// Creates an anonymous shared memory object.
+    ///
+    /// Without `SHM_ANON` (i.e on NetBSD and OpenBSD), a uniquely named object
+    /// is created, and unlinked immediately.
+    #[cfg(any(target_os = "freebsd", target_os = "netbsd", target_os = "openbsd"))]
+    unsafe fn create_shm(size: usize) -> Result<libc::c_int> {
+      #[cfg(target_os = "freebsd")]
+      let fd = libc::shm_open(libc::SHM_ANON, libc::O_RDWR | libc::O_CLOEXEC, 0o600);
+
+      #[cfg(not(target_os = "freebsd"))]
+      let fd = {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let flags = libc::O_RDWR | libc::O_CREAT | libc::O_EXCL | libc::O_CLOEXEC;
+
+        let name = std::format!(
+          "/detour.{}.{}\0",
+          libc::getpid(),
+          COUNTER.fetch_add(1, Ordering::Relaxed)
+        );
+        let fd = libc::shm_open(name.as_ptr() as *const _, flags, 0o600);
+        if fd >= 0 {
+          libc::shm_unlink(name.as_ptr() as *const _);
+        }
+        fd
+      };
+
+      if fd < 0 {
+        return Err(failure("shm_open", last_error()));
+      }
+
+      resize(fd, size)
+    }
+
+    /// Resizes a shared memory file, closing it on failure.
+    unsafe fn resize(fd: libc::c_int, size: usize) -> Result<libc::c_int> {
+      if libc::ftruncate(fd, size as libc::off_t) != 0 {
+        let error = failure("ftruncate", last_error());
+        libc::close(fd);
+        return Err(error);
+      }
+
+      Ok(fd)
+    }
+
+    /// Creates an ashmem region of the specified size.
+    #[cfg(target_os = "android")]
+    unsafe fn create_ashmem(size: usize) -> Result<libc::c_int> {
+      // Equivalent to `_IOW(0x77, 3, size_t)`
+      const ASHMEM_SET_SIZE: libc::c_int =
+        (1 << 30) | ((core::mem::size_of::<usize>() as libc::c_int) << 16) | (0x77 << 8) | 3;
+
+      let fd = libc::open(
+        b"/dev/ashmem\0".as_ptr() as *const _,
+        libc::O_RDWR | libc::O_CLOEXEC,
+      );
+      if fd < 0 {
+        return Err(failure("open(/dev/ashmem)", last_error()));
+      }
+
+      if libc::ioctl(fd, ASHMEM_SET_SIZE as _, size) < 0 {
+        let error = failure("ioctl(ASHMEM_SET_SIZE)", last_error());
+        libc::close(fd);
+        return Err(error);
+      }
+
+      Ok(fd)
+    }
+
+    /// Unmaps the writable alias of an executable mapping, if any.
+    pub unsafe fn release(executable: *mut u8) {
+      let mut aliases = ALIASES.lock();
+
+      if let Some(index) = aliases
+        .iter()
+        .position(|alias| alias.executable == executable as usize)
+      {
+        let alias = aliases.swap_remove(index);
+        primitives::munmap(alias.writable as *mut _, alias.size);
+      }
+    }
+
+    /// Returns the writable alias for an address within an executable mapping.
+    pub fn write_alias(address: *const ()) -> Option<*mut u8> {
+      let address = address as usize;
+
+      ALIASES
+        .lock()
+        .iter()
+        .find(|alias| (alias.executable..alias.executable + alias.size).contains(&address))
+        .map(|alias| (alias.writable + (address - alias.executable)) as *mut u8)
+    }
+
+    #[cfg(test)]
+    mod tests {
+      use super::*;
+      use crate::os::{Backend, Native, Protection};
+
+      #[test]
+      fn dual_mapping_is_written_through_alias() {
+        let size = Native.page_size();
+        let address = unsafe {
+          libc::mmap(
+            ptr::null_mut(),
+            size,
+            libc::PROT_NONE,
+            libc::MAP_PRIVATE | libc::MAP_ANON,
+            -1,
+            0,
+          )
+        };
+        assert_ne!(address, libc::MAP_FAILED);
+
+        // Reuse the address of a reserved mapping, that is known to be free
+        unsafe { libc::munmap(address, size) };
+        let executable = unsafe { allocate(address as *const (), size) }
+          .unwrap()
+          .unwrap();
+        assert_eq!(executable as *mut libc::c_void, address);
+
+        let region = Native.query(executable as *const ()).unwrap().unwrap();
+        assert_eq!(region.protection, Protection::READ_EXECUTE);
+
+        // `mov eax, 42; ret`
+        let code = [0xB8, 0x2A, 0x00, 0x00, 0x00, 0xC3];
+        let alias = write_alias(unsafe { executable.add(8) } as *const ()).unwrap();
+        unsafe { ptr::copy_nonoverlapping(code.as_ptr(), alias, code.len()) };
+
+        let function: extern "C" fn() -> i32 = unsafe { core::mem::transmute(executable.add(8)) };
+        assert_eq!(function(), 42);
+
+        unsafe {
+          release(executable);
+          libc::munmap(executable as *mut _, size);
+        }
+        assert!(write_alias(executable as *const ()).is_none());
+      }
+    }
+  }
+}
+
+#[cfg(windows)]
+mod mapping {
+  use crate::error::{Error, OsError, Result};
+  use crate::sync::Mutex;
+  use std::vec::Vec;
+  use winapi::shared::winerror::{
+    ERROR_ACCESS_DENIED, ERROR_DYNAMIC_CODE_BLOCKED, ERROR_INVALID_ADDRESS,
+  };
+
+  /// Memory maps allocated by the native backend.
+  static MAPS: Mutex<Vec<SendableMemoryMap>> = Mutex::new(Vec::new());
+
+  /// A wrapper for sharing memory maps between threads.
+  struct SendableMemoryMap(mmap::MemoryMap);
+
+  unsafe impl Send for SendableMemoryMap {}
+
+  /// Maps memory at exactly an address.
+  pub unsafe fn allocate(address: *const (), size: usize) -> Result<Option<*mut u8>> {
+    // Try to allocate memory at the specified address
+    let map = mmap::MemoryMap::new(
+      size,
+      &[
+        mmap::MapOption::MapReadable,
+        mmap::MapOption::MapWritable,
+        mmap::MapOption::MapExecutable,
+        mmap::MapOption::MapAddr(address as *const _),
+      ],
+    );
+
+    match map {
+      Ok(map) => {
+        let data = map.data();
+        MAPS.lock().push(SendableMemoryMap(map));
+        Ok(Some(data))
+      },
+      Err(mmap::MapError::ErrVirtualAlloc(code)) => {
+        let (operation, error) = ("VirtualAlloc", OsError(code));
+        match code as u32 {
+          ERROR_INVALID_ADDRESS => Ok(None),
+          ERROR_ACCESS_DENIED | ERROR_DYNAMIC_CODE_BLOCKED => {
+            Err(Error::PermissionDenied { operation, error })
+          },
+          _ => Err(Error::AllocationFailed { operation, error }),
+        }
+      },
+      Err(_) => Ok(None),
+    }
+  }
+
+  /// Memory is never dual mapped.
+  pub fn write_alias(_address: *const ()) -> Option<*mut u8> {
+    None
+  }
+
+  /// Unmaps memory previously mapped by `allocate`.
+  pub unsafe fn release(address: *mut u8, _size: usize) {
+    let mut maps = MAPS.lock();
+
+    // Dropping the memory map unmaps it
+    if let Some(index) = maps.iter().position(|map| map.0.data() == address) {
+      maps.swap_remove(index);
+    }
+  }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod regions {
+  use crate::os::{Protection, Region};
+  use core::ops::Range;
+  use std::vec::Vec;
+
+  /// Returns the regions overlapping the range, by parsing `/proc/self/maps`.
+  pub fn query_range(range: Range<usize>) -> Option<Vec<Region>> {
+    let maps = std::fs::read_to_string("/proc/self/maps").ok()?;
+
+    maps
+      .lines()
+      .map(parse_region)
+      .filter(|region| {
+        region
+          .as_ref()
+          .is_none_or(|region| region.upper() > range.start && region.lower() < range.end)
+      })
+      .collect()
+  }
+
+  /// Parses a region from a line of `/proc/self/maps` (e.g `0-1000 r-xp ...`).
+  fn parse_region(line: &str) -> Option<Region> {
+    let mut parts = line.split_whitespace();
+    let (lower, upper) = parts.next()?.split_once('-')?;
+    let lower = usize::from_str_radix(lower, 16).ok()?;
+    let upper = usize::from_str_radix(upper, 16).ok()?;
+
+    let flags = parts.next()?.as_bytes();
+    let protection = [
+      (b'r', Protection::READ),
+      (b'w', Protection::WRITE),
+      (b'x', Protection::EXECUTE),
+    ]
+    .iter()
+    .filter(|(flag, _)| flags.contains(flag))
+    .fold(Protection::NONE, |result, (_, protection)| {
+      result | *protection
+    });
+
+    Some(Region {
+      base: lower as *const (),
+      size: upper.checked_sub(lower)?,
+      protection,
+    })
+  }
+
+  #[cfg(test)]
+  mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_maps_line() {
+      let region =
+        parse_region("7f00a000-7f00c000 r-xp 00000000 08:01 1234   /lib/libc.so").unwrap();
+      assert_eq!(region.lower(), 0x7f00a000);
+      assert_eq!(region.size, 0x2000);
+      assert_eq!(region.protection, Protection::READ_EXECUTE);
+      assert!(parse_region("invalid").is_none());
+    }
+  }
+}
+
+#[cfg(any(target_os = "freebsd", target_os = "netbsd"))]
+mod regions {
+  use super::mapping::{failure, last_error};
+  use crate::error::Result;
+  use crate::os::{Protection, Region};
+  use core::{ops::Range, slice};
+  use std::vec::Vec;
+
+  /// Returns the regions overlapping the range.
+  pub fn query_range(range: Range<usize>) -> Option<Vec<Region>> {
+    vm_map(range).ok()
+  }
+
+  /// Returns the regions overlapping the range, using `kinfo_getvmmap`.
+  pub fn vm_map(range: Range<usize>) -> Result<Vec<Region>> {
+    let mut count = 0;
+    let entries = unsafe { libc::kinfo_getvmmap(libc::getpid(), &mut count) };
+
+    if entries.is_null() {
+      return Err(failure("kinfo_getvmmap", last_error()));
+    }
+
+    // The entries are sorted by address, and must be freed by the caller
+    let regions = unsafe { slice::from_raw_parts(entries, count as usize) }
+      .iter()
+      .filter(|entry| {
+        entry.kve_end as usize > range.start && (entry.kve_start as usize) < range.end
+      })
+      .map(|entry| Region {
+        base: entry.kve_start as *const (),
+        size: (entry.kve_end - entry.kve_start) as usize,
+        protection: from_native(entry.kve_protection as libc::c_int),
+      })
+      .collect();
+
+    unsafe { libc::free(entries as *mut _) };
+    Ok(regions)
+  }
+
+  /// Converts a native protection to its portable equivalent.
+  fn from_native(protection: libc::c_int) -> Protection {
+    [
+      (libc::KVME_PROT_READ, Protection::READ),
+      (libc::KVME_PROT_WRITE, Protection::WRITE),
+      (libc::KVME_PROT_EXEC, Protection::EXECUTE),
+    ]
+    .iter()
+    .filter(|(flag, _)| protection & flag == *flag)
+    .fold(Protection::NONE, |result, (_, flag)| result | *flag)
+  }
+}
+
+#[cfg(target_os = "openbsd")]
+mod regions {
+  use super::mapping::{failure, last_error};
+  use crate::error::Result;
+  use crate::os::{Protection, Region};
+  use core::{mem, ops::Range, ptr};
+  use std::vec::Vec;
+
+  /// Returns the regions overlapping the range.
+  pub fn query_range(range: Range<usize>) -> Option<Vec<Region>> {
+    vm_map(range).ok()
+  }
+
+  /// Returns the regions overlapping the range, using the `KERN_PROC_VMMAP`
+  /// sysctl, a batch of entries at a time.
+  pub fn vm_map(range: Range<usize>) -> Result<Vec<Region>> {
+    const BATCH_SIZE: usize = 64;
+
+    let mib = [libc::CTL_KERN, libc::KERN_PROC_VMMAP, unsafe {
+      libc::getpid()
+    }];
+    let mut entries = std::vec![unsafe { mem::zeroed::<libc::kinfo_vmentry>() }; BATCH_SIZE];
+    let mut regions = Vec::new();
+    let mut address = range.start;
+
+    while address < range.end {
+      // Entries ending after the start of the first one are returned
+      entries[0].kve_start = address as libc::c_ulong;
+      let mut size = mem::size_of_val(&entries[..]);
+
+      let result = unsafe {
+        libc::sysctl(
+          mib.as_ptr(),
+          mib.len() as libc::c_uint,
+          entries.as_mut_ptr() as *mut _,
+          &mut size,
+          ptr::null_mut(),
+          0,
+        )
+      };
+
+      // Exhausting the buffer is reported as `ENOMEM`, with the entries in it
+      let is_exhausted = result != 0 || size == mem::size_of_val(&entries[..]);
+      if result != 0 {
+        let error = last_error();
+        if error.code() != libc::ENOMEM || size == 0 {
+          return Err(failure("sysctl(KERN_PROC_VMMAP)", error));
+        }
+      }
+
+      let batch = &entries[..size / mem::size_of::<libc::kinfo_vmentry>()];
+      regions.extend(
+        batch
+          .iter()
+          .filter(|entry| {
+            entry.kve_end as usize > address && (entry.kve_start as usize) < range.end
+          })
+          .map(|entry| Region {
+            base: entry.kve_start as *const (),
+            size: (entry.kve_end - entry.kve_start) as usize,
+            protection: from_native(entry.kve_protection),
+          }),
+      );
+
+      match batch.last() {
+        Some(last) if is_exhausted && last.kve_end as usize > address => {
+          address = last.kve_end as usize
+        },
+        _ => break,
+      }
+    }
+
+    Ok(regions)
+  }
+
+  /// Converts a native protection to its portable equivalent.
+  fn from_native(protection: libc::c_int) -> Protection {
+    [
+      (libc::KVE_PROT_READ, Protection::READ),
+      (libc::KVE_PROT_WRITE, Protection::WRITE),
+      (libc::KVE_PROT_EXEC, Protection::EXECUTE),
+    ]
+    .iter()
+    .filter(|(flag, _)| protection & flag == *flag)
+    .fold(Protection::NONE, |result, (_, flag)| result | *flag)
+  }
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+mod regions {
+  use crate::os::{Protection, Region};
+  use core::{mem, ops::Range};
+  use mach::vm_region::{vm_region_basic_info_64, vm_region_info_t, VM_REGION_BASIC_INFO_64};
+  use mach::vm_types::{mach_vm_address_t, mach_vm_size_t};
+  use std::vec::Vec;
+
+  /// Returns the regions overlapping the range, using `mach_vm_region`.
+  pub fn query_range(range: Range<usize>) -> Option<Vec<Region>> {
+    let mut regions = Vec::new();
+    let mut address = range.start as mach_vm_address_t;
+
+    loop {
+      let mut size: mach_vm_size_t = 0;
+      let mut info: vm_region_basic_info_64 = unsafe { mem::zeroed() };
+      let mut count = vm_region_basic_info_64::count();
+      let mut object_name = 0;
+
+      // The first region at or after the address is returned
+      let result = unsafe {
+        mach::vm::mach_vm_region(
+          mach::traps::mach_task_self(),
+          &mut address,
+          &mut size,
+          VM_REGION_BASIC_INFO_64,
+          &mut info as *mut _ as vm_region_info_t,
+          &mut count,
+          &mut object_name,
+        )
+      };
+
+      match result {
+        mach::kern_return::KERN_SUCCESS => (),
+        mach::kern_return::KERN_INVALID_ADDRESS => break,
+        _ => return None,
+      }
+
+      if address as usize >= range.end {
+        break;
+      }
+
+      regions.push(Region {
+        base: address as *const (),
+        size: size as usize,
+        protection: from_native(info.protection),
+      });
+      address = address.checked_add(size)?;
+    }
+
+    Some(regions)
+  }
+
+  /// Converts a native protection to its portable equivalent.
+  fn from_native(protection: mach::vm_prot::vm_prot_t) -> Protection {
+    [
+      (mach::vm_prot::VM_PROT_READ, Protection::READ),
+      (mach::vm_prot::VM_PROT_WRITE, Protection::WRITE),
+      (mach::vm_prot::VM_PROT_EXECUTE, Protection::EXECUTE),
+    ]
+    .iter()
+    .filter(|(flag, _)| protection & flag == *flag)
+    .fold(Protection::NONE, |result, (_, flag)| result | *flag)
+  }
+}
+
+#[cfg(windows)]
+mod regions {
+  use crate::os::{Protection, Region};
+  use core::sync::atomic::{AtomicUsize, Ordering};
+  use core::{mem, ops::Range};
+  use std::vec::Vec;
+  use winapi::um::memoryapi::VirtualQuery;
+  use winapi::um::sysinfoapi::GetSystemInfo;
+  use winapi::um::winnt::{self, MEMORY_BASIC_INFORMATION, MEM_FREE};
+
+  /// The granularity of virtual memory allocations, once queried.
+  static GRANULARITY: AtomicUsize = AtomicUsize::new(0);
+
+  /// Returns the granularity of virtual memory allocations.
+  pub fn allocation_granularity() -> usize {
+    match GRANULARITY.load(Ordering::Relaxed) {
+      0 => {
+        let mut info = unsafe { mem::zeroed() };
+        unsafe { GetSystemInfo(&mut info) };
+
+        let granularity = info.dwAllocationGranularity as usize;
+        GRANULARITY.store(granularity, Ordering::Relaxed);
+        granularity
+      },
+      granularity => granularity,
+    }
+  }
+
+  /// Returns the regions overlapping the range, skipping a region at a time
+  /// using `VirtualQuery`.
+  pub fn query_range(range: Range<usize>) -> Option<Vec<Region>> {
+    let mut regions = Vec::new();
+    let mut address = range.start;
+
+    while address < range.end {
+      let mut info: MEMORY_BASIC_INFORMATION = unsafe { mem::zeroed() };
+      let size = mem::size_of::<MEMORY_BASIC_INFORMATION>();
+
+      // Addresses beyond the user address space cannot be queried
+      if unsafe { VirtualQuery(address as *const _, &mut info, size) } == 0 {
+        break;
+      }
+
+      let base = info.BaseAddress as usize;
+      if info.State != MEM_FREE {
+        regions.push(Region {
+          base: base as *const (),
+          size: info.RegionSize,
+          protection: from_native(info.Protect),
+        });
+      }
+
+      match base.checked_add(info.RegionSize) {
+        Some(upper) if upper > address => address = upper,
+        _ => break,
+      }
+    }
+
+    Some(regions)
+  }
+
+  /// Converts a native protection to its portable equivalent.
+  fn from_native(protection: u32) -> Protection {
+    match protection & 0xFF {
+      winnt::PAGE_READONLY => Protection::READ,
+      winnt::PAGE_READWRITE | winnt::PAGE_WRITECOPY => Protection::READ_WRITE,
+      winnt::PAGE_EXECUTE => Protection::EXECUTE,
+      winnt::PAGE_EXECUTE_READ => Protection::READ_EXECUTE,
+      winnt::PAGE_EXECUTE_READWRITE | winnt::PAGE_EXECUTE_WRITECOPY => {
+        Protection::READ_WRITE_EXECUTE
+      },
+      _ => Protection::NONE,
+    }
+  }
+}
+
+#[cfg(not(any(
+  target_os = "linux",
+  target_os = "android",
+  target_os = "freebsd",
+  target_os = "netbsd",
+  target_os = "openbsd",
+  target_os = "macos",
+  target_os = "ios",
+  windows
+)))]
+mod regions {
+  use crate::os::Region;
+  use core::ops::Range;
+  use std::vec::Vec;
+
+  /// Regions cannot be enumerated on this platform.
+  pub fn query_range(_range: Range<usize>) -> Option<Vec<Region>> {
+    None
+  }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", windows))]
+mod quiescence {
+  use crate::sync::Mutex;
+  use core::ops::Range;
+  use std::time::{Duration, Instant};
+
+  /// The minimum time between the ends of two grace periods.
+  const GRACE_PERIOD: Duration = Duration::from_millis(10);
+
+  /// The end of the most recent grace period.
+  static LAST: Mutex<Option<Instant>> = Mutex::new(None);
+
+  /// Ends a grace period, unless the previous one ended too recently.
+  pub fn is_quiescent(ranges: &[Range<usize>]) -> bool {
+    let mut last = LAST.lock();
+    if last.is_some_and(|last| last.elapsed() < GRACE_PERIOD) || !barrier(ranges) {
+      return false;
+    }
+
+    *last = Some(Instant::now());
+    true
+  }
+
+  /// Issues a memory barrier on all threads of the process.
+  ///
+  /// Each successful barrier interrupts any thread that was executing
+  /// released code. A thread preempted within the code, for longer than the
+  /// grace periods, cannot be detected.
+  #[cfg(any(target_os = "linux", target_os = "android"))]
+  fn barrier(_ranges: &[Range<usize>]) -> bool {
+    use core::sync::atomic::{AtomicBool, Ordering};
+
+    const MEMBARRIER_CMD_GLOBAL: libc::c_int = 1 << 0;
+    const MEMBARRIER_CMD_PRIVATE_EXPEDITED: libc::c_int = 1 << 3;
+    const MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED: libc::c_int = 1 << 4;
+
+    static REGISTERED: AtomicBool = AtomicBool::new(false);
+
+    let membarrier =
+      |command: libc::c_int| unsafe { libc::syscall(libc::SYS_membarrier, command, 0, 0) == 0 };
+
+    if !REGISTERED.load(Ordering::SeqCst) && membarrier(MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED) {
+      REGISTERED.store(true, Ordering::SeqCst);
+    }
+
+    if REGISTERED.load(Ordering::SeqCst) {
+      membarrier(MEMBARRIER_CMD_PRIVATE_EXPEDITED)
+    } else {
+      membarrier(MEMBARRIER_CMD_GLOBAL)
+    }
+  }
+
+  /// Returns true if no other thread's instruction pointer is within any of
+  /// the ranges, by freezing all threads of the process once.
+  ///
+  /// Only instruction pointers are checked; a thread that called out of the
+  /// code (e.g a `call` relocated to a trampoline) may still return to it.
+  #[cfg(windows)]
+  fn barrier(ranges: &[Range<usize>]) -> bool {
+    use crate::detours::ThreadFreeze;
+
+    ThreadFreeze::new()
+      .map(|freeze| {
+        freeze.threads().iter().all(|thread| {
+          let address = thread.instruction_pointer();
+          !ranges.iter().any(|range| range.contains(&address))
+        })
+      })
+      .unwrap_or(false)
+  }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", windows)))]
+mod quiescence {
+  use core::ops::Range;
+
+  /// Quiescence cannot be determined on this platform.
+  pub fn is_quiescent(_ranges: &[Range<usize>]) -> bool {
+    false
+  }
+}
+
+#[cfg(unix)]
+mod symbols {
+  use std::ffi::CStr;
+
+  /// Resolves a symbol within the global scope of the process.
+  pub fn resolve(name: &CStr) -> Option<*const ()> {
+    Some(unsafe { libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr()) } as *const ())
+  }
+}
+
+#[cfg(windows)]
+mod symbols {
+  use core::mem;
+  use std::ffi::CStr;
+  use winapi::shared::minwindef::FALSE;
+  use winapi::um::handleapi::{CloseHandle, INVALID_HANDLE_VALUE};
+  use winapi::um::libloaderapi::GetProcAddress;
+  use winapi::um::tlhelp32::{
+    CreateToolhelp32Snapshot, Module32First, Module32Next, MODULEENTRY32, TH32CS_SNAPMODULE,
+  };
+
+  /// Resolves a symbol exported by any module of the process, in load order.
+  pub fn resolve(name: &CStr) -> Option<*const ()> {
+    unsafe {
+      let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPMODULE, 0);
+      if snapshot == INVALID_HANDLE_VALUE {
+        return None;
+      }
+
+      let mut entry: MODULEENTRY32 = mem::zeroed();
+      entry.dwSize = mem::size_of::<MODULEENTRY32>() as u32;
+
+      let mut address = None;
+      let mut is_valid = Module32First(snapshot, &mut entry) != FALSE;
+
+      while address.is_none() && is_valid {
+        let function = GetProcAddress(entry.hModule, name.as_ptr());
+        if !function.is_null() {
+          address = Some(function as *const ());
+        }
+        is_valid = Module32Next(snapshot, &mut entry) != FALSE;
+      }
+
+      CloseHandle(snapshot);
+      address
+    }
+  }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod symbols {
+  use std::ffi::CStr;
+
+  /// Symbols cannot be resolved on this platform.
+  pub fn resolve(_name: &CStr) -> Option<*const ()> {
+    None
+  }
+}
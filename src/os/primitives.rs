@@ -0,0 +1,274 @@
+//! The system functions called by the native backend to protect and map
+//! memory (e.g `mprotect` or `VirtualProtect`), which may be detoured too.
+//!
+//! If one of them is detoured, patching any target (including the primitive
+//! itself) would invoke the detour on behalf of the library, whilst holding
+//! its lock; a detour creating or toggling detours deadlocks, and one that is
+//! only partially written crashes. Instead, once a detour of a primitive is
+//! created, the backend calls the primitive through the detour's trampoline,
+//! until the detour is dropped (or bound to another target).
+//!
+//! Only detours with a destination are considered, since the entry thunk of
+//! an entry detour is part of its trampoline. On x86, the instruction cache
+//! is only flushed explicitly whilst emulated on Windows ARM64. On Windows,
+//! memory is mapped by `mmap-fixed`, therefore `VirtualAlloc` is called
+//! directly.
+
+use crate::sync::Mutex;
+use core::mem;
+use core::ops::Range;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
+use std::vec::Vec;
+
+/// A system function called by the native backend.
+pub struct Primitive {
+  /// The exported name of the function.
+  name: &'static str,
+  /// The trampoline of the function's detour, or null if it's not detoured.
+  trampoline: AtomicPtr<()>,
+}
+
+impl Primitive {
+  const fn new(name: &'static str) -> Self {
+    Primitive {
+      name,
+      trampoline: AtomicPtr::new(ptr::null_mut()),
+    }
+  }
+
+  /// Returns whether the function is detoured.
+  pub fn is_detoured(&self) -> bool {
+    !self.trampoline.load(Ordering::SeqCst).is_null()
+  }
+
+  /// Returns the function to call, i.e the trampoline of its detour if it's
+  /// detoured. `F` must be a function pointer type.
+  ///
+  /// This neither locks nor allocates (e.g for a signal handler).
+  #[inline]
+  unsafe fn get<F: Copy>(&self, function: F) -> F {
+    let trampoline = self.trampoline.load(Ordering::SeqCst);
+    if trampoline.is_null() {
+      function
+    } else {
+      mem::transmute_copy(&trampoline)
+    }
+  }
+}
+
+/// The addresses of the primitives, resolved upon the first detour.
+static ADDRESSES: Mutex<Option<Vec<(usize, &'static Primitive)>>> = Mutex::new(None);
+
+/// Routes the primitives within a patch area (if any) through the trampoline
+/// of their detour, whilst holding the lock. The trampoline is only resolved
+/// if a primitive is within the area.
+///
+/// A primitive already detoured remains routed through the first detour's
+/// trampoline, since any other detour's trampoline leads to the first one.
+pub fn bind(area: Range<usize>, trampoline: impl FnOnce() -> Option<*const ()>) {
+  let mut addresses = ADDRESSES.lock();
+  let addresses = addresses.get_or_insert_with(platform::resolve);
+
+  let mut primitives = addresses
+    .iter()
+    .filter(|(address, _)| area.contains(address))
+    .peekable();
+
+  if primitives.peek().is_none() {
+    return;
+  }
+
+  let trampoline = match trampoline() {
+    Some(trampoline) => trampoline as *mut (),
+    None => return,
+  };
+
+  for (_, primitive) in primitives {
+    let _ = primitive.trampoline.compare_exchange(
+      ptr::null_mut(),
+      trampoline,
+      Ordering::SeqCst,
+      Ordering::SeqCst,
+    );
+  }
+}
+
+/// Stops routing any primitive through a trampoline, whilst holding the lock.
+pub fn unbind(trampoline: *const ()) {
+  for primitive in platform::PRIMITIVES.iter() {
+    let _ = primitive.trampoline.compare_exchange(
+      trampoline as *mut (),
+      ptr::null_mut(),
+      Ordering::SeqCst,
+      Ordering::SeqCst,
+    );
+  }
+}
+
+#[cfg(unix)]
+pub use self::platform::{mmap, mprotect, munmap, PROTECT};
+
+#[cfg(all(unix, not(any(target_os = "netbsd", target_os = "openbsd"))))]
+pub use self::platform::protect;
+
+#[cfg(windows)]
+pub use self::platform::{protect, PROTECT};
+
+#[cfg(all(windows, target_arch = "x86_64"))]
+pub use self::platform::flush_instruction_cache;
+
+#[cfg(unix)]
+mod platform {
+  use super::Primitive;
+  use crate::os::Native;
+  use libc::{c_int, c_void, off_t, size_t};
+  use std::vec::Vec;
+
+  type FnMprotect = unsafe extern "C" fn(*mut c_void, size_t, c_int) -> c_int;
+  type FnMmap =
+    unsafe extern "C" fn(*mut c_void, size_t, c_int, c_int, c_int, off_t) -> *mut c_void;
+  type FnMunmap = unsafe extern "C" fn(*mut c_void, size_t) -> c_int;
+
+  pub static PROTECT: Primitive = Primitive::new("mprotect");
+  static MMAP: Primitive = Primitive::new("mmap");
+  static MUNMAP: Primitive = Primitive::new("munmap");
+
+  pub static PRIMITIVES: [&Primitive; 3] = [&PROTECT, &MMAP, &MUNMAP];
+
+  /// Resolves the address of each primitive, as exported by the C library.
+  pub fn resolve() -> Vec<(usize, &'static Primitive)> {
+    PRIMITIVES
+      .iter()
+      .filter_map(|&primitive| Some((Native::resolve_symbol(primitive.name)? as usize, primitive)))
+      .collect()
+  }
+
+  pub unsafe fn mprotect(address: *mut c_void, size: size_t, protection: c_int) -> c_int {
+    PROTECT.get::<FnMprotect>(libc::mprotect)(address, size, protection)
+  }
+
+  pub unsafe fn mmap(
+    address: *mut c_void,
+    size: size_t,
+    protection: c_int,
+    flags: c_int,
+    fd: c_int,
+    offset: off_t,
+  ) -> *mut c_void {
+    MMAP.get::<FnMmap>(libc::mmap)(address, size, protection, flags, fd, offset)
+  }
+
+  pub unsafe fn munmap(address: *mut c_void, size: size_t) -> c_int {
+    MUNMAP.get::<FnMunmap>(libc::munmap)(address, size)
+  }
+
+  /// Changes the protection of the pages overlapping a range.
+  #[cfg(not(any(target_os = "netbsd", target_os = "openbsd")))]
+  pub unsafe fn protect(
+    address: *const (),
+    size: usize,
+    protection: crate::os::Protection,
+  ) -> std::io::Result<()> {
+    use crate::os::{self, Backend, Protection};
+
+    let pages = os::page_range(address as usize, size.max(1), Native.page_size());
+    let flags = [
+      (Protection::READ, libc::PROT_READ),
+      (Protection::WRITE, libc::PROT_WRITE),
+      (Protection::EXECUTE, libc::PROT_EXEC),
+    ]
+    .iter()
+    .filter(|(flag, _)| protection.contains(*flag))
+    .fold(libc::PROT_NONE, |result, (_, flag)| result | flag);
+
+    if mprotect(pages.start as *mut _, pages.end - pages.start, flags) == 0 {
+      Ok(())
+    } else {
+      Err(std::io::Error::last_os_error())
+    }
+  }
+}
+
+#[cfg(windows)]
+mod platform {
+  use super::Primitive;
+  use crate::os::Protection;
+  use std::vec::Vec;
+  use winapi::shared::minwindef::{BOOL, DWORD, FALSE, LPCVOID, LPVOID, PDWORD};
+  use winapi::um::libloaderapi::{GetModuleHandleA, GetProcAddress};
+  use winapi::um::memoryapi::VirtualProtect;
+  use winapi::um::processthreadsapi::{FlushInstructionCache, GetCurrentProcess};
+  use winapi::um::winnt::{
+    HANDLE, PAGE_EXECUTE, PAGE_EXECUTE_READ, PAGE_EXECUTE_READWRITE, PAGE_NOACCESS, PAGE_READONLY,
+    PAGE_READWRITE,
+  };
+
+  type FnVirtualProtect = unsafe extern "system" fn(LPVOID, usize, DWORD, PDWORD) -> BOOL;
+  type FnFlushInstructionCache = unsafe extern "system" fn(HANDLE, LPCVOID, usize) -> BOOL;
+
+  pub static PROTECT: Primitive = Primitive::new("VirtualProtect");
+  static FLUSH: Primitive = Primitive::new("FlushInstructionCache");
+
+  pub static PRIMITIVES: [&Primitive; 2] = [&PROTECT, &FLUSH];
+
+  /// The modules exporting the primitives; `kernel32` forwards them to
+  /// `kernelbase`, and either may be detoured.
+  const MODULES: [&[u8]; 2] = [b"kernel32.dll\0", b"kernelbase.dll\0"];
+
+  /// Resolves the address of each primitive, as exported by each module.
+  pub fn resolve() -> Vec<(usize, &'static Primitive)> {
+    let mut addresses = Vec::new();
+
+    for module in MODULES.iter() {
+      let module = unsafe { GetModuleHandleA(module.as_ptr() as *const _) };
+      if module.is_null() {
+        continue;
+      }
+
+      for &primitive in PRIMITIVES.iter() {
+        let name = std::ffi::CString::new(primitive.name).expect("primitive name");
+        let address = unsafe { GetProcAddress(module, name.as_ptr()) };
+        if !address.is_null() {
+          addresses.push((address as usize, primitive));
+        }
+      }
+    }
+    addresses
+  }
+
+  /// Changes the protection of the pages overlapping a range.
+  pub unsafe fn protect(
+    address: *const (),
+    size: usize,
+    protection: Protection,
+  ) -> std::io::Result<()> {
+    let flags = match (
+      protection.contains(Protection::READ) || protection.contains(Protection::WRITE),
+      protection.contains(Protection::WRITE),
+      protection.contains(Protection::EXECUTE),
+    ) {
+      (false, _, false) => PAGE_NOACCESS,
+      (false, _, true) => PAGE_EXECUTE,
+      (true, false, false) => PAGE_READONLY,
+      (true, true, false) => PAGE_READWRITE,
+      (true, false, true) => PAGE_EXECUTE_READ,
+      (true, true, true) => PAGE_EXECUTE_READWRITE,
+    };
+
+    let mut previous = 0;
+    let virtual_protect = PROTECT.get::<FnVirtualProtect>(VirtualProtect);
+    if virtual_protect(address as LPVOID, size.max(1), flags, &mut previous) == FALSE {
+      Err(std::io::Error::last_os_error())
+    } else {
+      Ok(())
+    }
+  }
+
+  /// Flushes the instruction cache for a range of the current process.
+  #[cfg(target_arch = "x86_64")]
+  pub unsafe fn flush_instruction_cache(address: *const (), size: usize) {
+    let flush = FLUSH.get::<FnFlushInstructionCache>(FlushInstructionCache);
+    flush(GetCurrentProcess(), address as LPCVOID, size);
+  }
+}
@@ -1,8 +1,13 @@
 use super::Thunkable;
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
 
 /// An interface for generating PIC.
+///
+/// Cloning an emitter shares its code segments, which allows the same code to
+/// be generated for several addresses.
+#[derive(Clone)]
 pub struct CodeEmitter {
-  thunks: Vec<Box<dyn Thunkable>>,
+  thunks: Vec<Arc<dyn Thunkable>>,
 }
 
 /// Used for combining PIC segments.
@@ -32,11 +37,32 @@ impl CodeEmitter {
 
   /// Adds a position-independant code segment.
   pub fn add_thunk(&mut self, thunk: Box<dyn Thunkable>) {
-    self.thunks.push(thunk);
+    self.thunks.push(Arc::from(thunk));
+  }
+
+  /// Adds position-independant code, copied as is.
+  pub fn add_code(&mut self, code: &[u8]) {
+    self.add_thunk(Box::new(code.to_vec()));
+  }
+
+  /// Adds all code segments of another emitter.
+  pub fn append(&mut self, other: CodeEmitter) {
+    self.thunks.extend(other.thunks);
   }
 
   /// Returns the total size of a all code segments.
   pub fn len(&self) -> usize {
     self.thunks.iter().fold(0, |sum, thunk| sum + thunk.len())
   }
+
+  /// Returns whether the emitter is empty or not.
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+}
+
+impl Default for CodeEmitter {
+  fn default() -> Self {
+    Self::new()
+  }
 }
@@ -1,9 +1,31 @@
+//! Position-independent code (PIC) generation.
+//!
+//! Trampolines and relays are assembled from thunks; segments of code that
+//! are generated once the address they are placed at is known. The types in
+//! this module are exposed for user-provided [shims](../struct.Shims.html),
+//! which are emitted alongside the crate's own code.
+//!
+//! Static byte sequences (i.e a `Vec<u8>`) are copied as is, and are assumed
+//! to be position-independent; they are never relocated. Code that depends on
+//! its address can be generated using a [FixedThunk](./struct.FixedThunk.html)
+//! or an [UnsafeThunk](./struct.UnsafeThunk.html).
+//!
+//! ```rust
+//! use detour::pic::CodeEmitter;
+//!
+//! let mut emitter = CodeEmitter::new();
+//! emitter.add_code(&[0x9C, 0x9D]); // pushf; popf
+//! assert_eq!(emitter.len(), 2);
+//! assert_eq!(emitter.emit(std::ptr::null()), [0x9C, 0x9D]);
+//! ```
 pub use self::emitter::CodeEmitter;
 pub use self::thunk::{FixedThunk, UnsafeThunk};
 
 mod emitter;
 mod thunk;
 
+use alloc::vec::Vec;
+
 /// An interface for generating PIC thunks.
 pub trait Thunkable {
   /// Generates the code at the specified address.
@@ -11,6 +33,11 @@ pub trait Thunkable {
 
   /// Returns the size of a generated thunk.
   fn len(&self) -> usize;
+
+  /// Returns whether the generated thunk is empty or not.
+  fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
 }
 
 /// Thunkable implementation for static data
@@ -1,24 +1,24 @@
 use super::Thunkable;
-use generic_array::{ArrayLength, GenericArray};
+use alloc::{boxed::Box, vec::Vec};
 
 /// A closure that generates a thunk.
-pub struct FixedThunk<N: ArrayLength<u8>>(Box<dyn Fn(usize) -> GenericArray<u8, N>>);
+pub struct FixedThunk<const N: usize>(Box<dyn Fn(usize) -> [u8; N]>);
 
-impl<N: ArrayLength<u8>> FixedThunk<N> {
+impl<const N: usize> FixedThunk<N> {
   /// Constructs a new thunk with a specific closure.
-  pub fn new<T: Fn(usize) -> GenericArray<u8, N> + 'static>(callback: T) -> Self {
+  pub fn new<T: Fn(usize) -> [u8; N] + 'static>(callback: T) -> Self {
     FixedThunk(Box::new(callback))
   }
 }
 
 /// Thunks implement the thunkable interface.
-impl<N: ArrayLength<u8>> Thunkable for FixedThunk<N> {
+impl<const N: usize> Thunkable for FixedThunk<N> {
   fn generate(&self, address: usize) -> Vec<u8> {
     self.0(address).to_vec()
   }
 
   fn len(&self) -> usize {
-    N::to_usize()
+    N
   }
 }
 
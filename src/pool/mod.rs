@@ -0,0 +1,606 @@
+//! The pool of executable memory used for trampolines and relays.
+//!
+//! Memory is allocated on demand, close to each target, and is shared by all
+//! detours. Trampolines and relays are packed within each slab of memory,
+//! aligned to 16 bytes (see [PoolOptions](./struct.PoolOptions.html)) and
+//! padded with NOPs, and all bookkeeping is kept outside of it. The functions
+//! in this module allow the pool to be prepared ahead of time.
+//!
+//! # Custom allocators
+//!
+//! All trampolines and relays are allocated through an
+//! [ExecutableAllocator](./trait.ExecutableAllocator.html). Unless another one
+//! is installed using [set_allocator](./fn.set_allocator.html), the
+//! [DefaultAllocator](./struct.DefaultAllocator.html) is used, which maps
+//! memory using the [backend](../os/trait.Backend.html). Reservations,
+//! loader-safe mode and reclamation only apply to the default allocator.
+//!
+//! # Loader-safe mode
+//!
+//! Hooks are commonly installed from `DllMain` on Windows, where the loader
+//! lock is held and several operations may deadlock the process. Whilst
+//! loader-safe mode is enabled, the pool never grows, and released memory is
+//! not reclaimed, since that may enumerate threads. Any detour that would
+//! require new executable memory fails with `Error::LoaderUnsafe` instead.
+//!
+//! To prepare, reserve memory close to the modules that are to be hooked
+//! before the loader lock is acquired (e.g from the injecting process, or a
+//! previously loaded module). The library itself never loads any libraries;
+//! when using the `libloading` feature, only pass libraries that are already
+//! loaded (e.g with `Library::open_already_loaded` on Windows).
+//!
+//! ```
+//! # use detour::Result;
+//! use detour::{pool, RawDetour};
+//!
+//! # #[inline(never)]
+//! # extern "C" fn add(x: i32, y: i32) -> i32 { unsafe { std::ptr::read_volatile(&x) + y } }
+//! # extern "C" fn sub(x: i32, y: i32) -> i32 { x - y }
+//! # fn main() -> Result<()> {
+//! // Reserve memory before the loader lock is held
+//! pool::reserve(add as *const (), 0x100)?;
+//! pool::set_loader_safe(true);
+//!
+//! // ... this is a loader-safe operation
+//! let hook = unsafe { RawDetour::new(add as *const (), sub as *const ())? };
+//! # pool::set_loader_safe(false);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! # Registered regions
+//!
+//! Instead of mapping new memory, the pool can allocate from existing regions
+//! (e.g padding within a module, or a preallocated section) registered using
+//! [add_region](./fn.add_region.html). Registered regions are preferred over
+//! any other memory within range of a target, and in strict mode, they are
+//! used exclusively; once no region within range has sufficient space,
+//! detours fail with `Error::RegionExhausted`.
+//!
+//! # Statistics
+//!
+//! The state of the default allocator's memory can be inspected using
+//! [stats](./fn.stats.html), e.g to diagnose why memory could not be allocated
+//! close to a target.
+//!
+//! # Reclamation
+//!
+//! Once a detour is dropped, a thread may still be executing its trampoline
+//! (e.g if it entered the trampoline just before the detour was disabled).
+//! Therefore memory is by default only reused once the backend reports that
+//! no thread executes within it. The policy can be changed using
+//! [set_reclamation](./fn.set_reclamation.html).
+//!
+//! Memory awaiting reclamation is checked on subsequent pool operations, or
+//! explicitly using [reclaim](./fn.reclaim.html).
+//!
+//! # Signal safety
+//!
+//! Hooks may be installed from a signal handler (e.g whilst attaching to a
+//! running process), where the interrupted thread may hold any lock. Growing
+//! the pool requires both a lock and the operating system, therefore slots of
+//! memory can be reserved ahead of time using
+//! [reserve_slots](./fn.reserve_slots.html). Whilst slots are available
+//! within range, the default allocator claims them without locking or calling
+//! into the operating system.
+//!
+//! Along with patches prepared ahead of time (see
+//! [RawDetour::prepare](../struct.RawDetour.html#method.prepare)), the
+//! following operations are async-signal-safe:
+//!
+//! - [ExecutableMemory::allocate_near](./struct.ExecutableMemory.html#method.
+//!   allocate_near) and `allocate_within`, served by reserved slots of the
+//!   default allocator. Requests exceeding the size of a slot, or without a
+//!   slot within range, fall back to the locked pool.
+//! - [PreparedPatch::commit](../struct.PreparedPatch.html#method.commit), which
+//!   only changes the protection of the patch area. If another patch operation
+//!   is in progress, it fails with `Error::WouldBlock` instead of waiting for
+//!   it.
+//! - Calling a detour's trampoline, unless it's emitted lazily, and
+//!   `is_enabled`.
+//!
+//! Everything else (e.g constructing or dropping detours and memory, writing
+//! to memory, and enabling or disabling detours) locks, allocates from the
+//! heap, or queries the operating system, and is not. Committing a patch
+//! invokes the patch callbacks installed upon its preparation, which must be
+//! async-signal-safe as well.
+//!
+//! ```
+//! # use detour::Result;
+//! use detour::{pool, RawDetour};
+//!
+//! # #[inline(never)]
+//! # extern "C" fn add(x: i32, y: i32) -> i32 { unsafe { std::ptr::read_volatile(&x) + y } }
+//! # extern "C" fn sub(x: i32, y: i32) -> i32 { x - y }
+//! # fn main() -> Result<()> {
+//! // From normal context, reserve slots and prepare the patch
+//! pool::reserve_slots(4, Some(add as *const ()))?;
+//! let hook = unsafe { RawDetour::new(add as *const (), sub as *const ())? };
+//! let patch = unsafe { hook.prepare(true)? };
+//!
+//! // ... this is async-signal-safe
+//! unsafe { patch.commit()? };
+//! assert_eq!(add(10, 5), 5);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::arch::memory;
+use crate::error::{Error, Result};
+use crate::os::{self, Protection};
+use crate::sync::{Global, Mutex};
+use crate::{meta, profiling};
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+mod proximity;
+mod search;
+mod slots;
+
+/// The memory pool used by the default allocator.
+static POOL: Mutex<proximity::ProximityAllocator> =
+  Mutex::new(proximity::ProximityAllocator::new());
+
+/// The installed allocator.
+static ALLOCATOR: Global<dyn ExecutableAllocator> = Global::new();
+
+/// Whether the pool is prevented from growing.
+static LOADER_SAFE: AtomicBool = AtomicBool::new(false);
+
+/// Whether only registered regions are used.
+static STRICT: AtomicBool = AtomicBool::new(false);
+
+/// The current reclamation policy.
+static RECLAMATION: AtomicU8 = AtomicU8::new(Reclamation::Deferred as u8);
+
+/// The size of each slot reserved using
+/// [reserve_slots](./fn.reserve_slots.html).
+///
+/// It suffices for any relay, and for trampolines of most prologs.
+pub const SLOT_SIZE: usize = 64;
+
+/// A chunk of read-, write- & executable memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExecutableSlice {
+  /// Address of the chunk.
+  pub address: *mut u8,
+  /// Size of the chunk.
+  pub size: usize,
+}
+
+unsafe impl Send for ExecutableSlice {}
+unsafe impl Sync for ExecutableSlice {}
+
+/// An allocator of executable memory, used for trampolines and relays.
+///
+/// # Safety
+///
+/// Allocated memory must be readable, writable and executable, at least
+/// `size` bytes large, and located within `range` bytes of `origin`. It must
+/// remain valid until it's freed.
+pub unsafe trait ExecutableAllocator: Sync {
+  /// Allocates `size` bytes within `range` bytes of `origin`.
+  fn allocate_near(&self, origin: usize, size: usize, range: usize) -> Result<ExecutableSlice>;
+
+  /// Frees memory previously allocated by `allocate_near`.
+  unsafe fn free(&self, slice: ExecutableSlice);
+}
+
+/// The default allocator, sharing pools of memory close to each origin.
+///
+/// Reserved slots are claimed before any pool is locked (see
+/// [reserve_slots](./fn.reserve_slots.html)).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultAllocator;
+
+unsafe impl ExecutableAllocator for DefaultAllocator {
+  fn allocate_near(&self, origin: usize, size: usize, range: usize) -> Result<ExecutableSlice> {
+    match slots::claim(origin, size, range) {
+      Some(slice) => Ok(slice),
+      None => POOL.lock().allocate(origin, size, range),
+    }
+  }
+
+  unsafe fn free(&self, slice: ExecutableSlice) {
+    let mut pool = POOL.lock();
+    slots::vacate(&slice);
+    pool.release(&slice);
+  }
+}
+
+/// Installs the allocator used for all trampolines and relays.
+///
+/// The allocator can only be installed once, and must be installed before
+/// it's used by any detour; `Error::AlreadyInitialized` is returned otherwise.
+pub fn set_allocator(allocator: &'static dyn ExecutableAllocator) -> Result<()> {
+  ALLOCATOR.set(allocator)
+}
+
+/// Returns the installed allocator.
+///
+/// The default allocator is installed if none has been installed.
+pub fn allocator() -> &'static dyn ExecutableAllocator {
+  ALLOCATOR.get_or_set(&DefaultAllocator)
+}
+
+/// The policy for reclaiming memory released by detours.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Reclamation {
+  /// Memory is reused as soon as its detour is dropped.
+  ///
+  /// This is only sound if no thread can be executing the detour's
+  /// trampoline when it's dropped.
+  Immediate,
+  /// Memory is reused once two grace periods have ended since its release.
+  ///
+  /// Pending memory is checked at most once per pool operation, and the
+  /// native backend ends a grace period at most every 10 milliseconds:
+  ///
+  /// - On Linux, by issuing a memory barrier on all threads (`membarrier`).
+  /// - On Windows, by freezing all other threads once (see
+  ///   [ThreadFreeze](../struct.ThreadFreeze.html)), and checking that none is
+  ///   executing within any pending memory.
+  ///
+  /// On other platforms, memory is not reused. Only the instruction pointers
+  /// of threads are considered, not their stacks; a thread that called out of
+  /// a trampoline (e.g through a `call` relocated from the target's prolog)
+  /// may return to it after it has been reused, as may a thread preempted
+  /// within it for longer than the grace periods on Linux. `Never` avoids
+  /// this for such targets.
+  #[default]
+  Deferred,
+  /// Memory is never reused.
+  Never,
+}
+
+/// Sets the policy for reclaiming memory released by detours.
+///
+/// The policy applies to memory released after it has been changed.
+pub fn set_reclamation(policy: Reclamation) {
+  RECLAMATION.store(policy as u8, Ordering::SeqCst);
+}
+
+/// Returns the policy for reclaiming memory released by detours.
+pub fn reclamation() -> Reclamation {
+  match RECLAMATION.load(Ordering::SeqCst) {
+    0 => Reclamation::Immediate,
+    1 => Reclamation::Deferred,
+    _ => Reclamation::Never,
+  }
+}
+
+/// Reclaims any memory awaiting quiescence.
+pub fn reclaim() {
+  let _guard = memory::LOCK.lock();
+  POOL.lock().reclaim();
+}
+
+/// Reserves executable memory close to `origin`.
+///
+/// Unless the pool already has `size` contiguous bytes available within
+/// range of `origin`, new memory is allocated. Subsequent detours with targets
+/// close to `origin` use the reserved memory.
+pub fn reserve(origin: *const (), size: usize) -> Result<()> {
+  let _guard = memory::LOCK.lock();
+  POOL
+    .lock()
+    .reserve(origin as usize, size, meta::detour_range())
+}
+
+/// Reserves `count` slots of [SLOT_SIZE](./constant.SLOT_SIZE.html) bytes
+/// within range of `near`, or anywhere, which are claimed without locking
+/// (e.g from a signal handler).
+///
+/// Unless `count` slots are already available within range, the remainder
+/// is allocated from the pool. At most 64 slots are reserved at once;
+/// `Error::OutOfMemory` is returned if they're exhausted. Reserving slots
+/// installs the default allocator, unless another one has been installed;
+/// slots are only claimed by the default allocator.
+pub fn reserve_slots(count: usize, near: Option<*const ()>) -> Result<()> {
+  let (origin, range) = slot_range(near);
+  allocator();
+
+  let _guard = memory::LOCK.lock();
+  let mut pool = POOL.lock();
+  let required = count.saturating_sub(slots::available(origin, range));
+
+  if !slots::fill(required, || pool.allocate(origin, SLOT_SIZE, range))? {
+    Err(Error::OutOfMemory)?;
+  }
+  Ok(())
+}
+
+/// Returns the amount of reserved slots available within range of `near`,
+/// or anywhere.
+pub fn available_slots(near: Option<*const ()>) -> usize {
+  let (origin, range) = slot_range(near);
+  slots::available(origin, range)
+}
+
+/// Returns the origin and range of reserved slots.
+fn slot_range(near: Option<*const ()>) -> (usize, usize) {
+  match near {
+    Some(origin) => (origin as usize, meta::detour_range()),
+    // Any memory will do, preferably close to the library
+    None => (reserve_slots as *const () as usize, usize::MAX),
+  }
+}
+
+/// Enables or disables loader-safe mode.
+///
+/// Whilst enabled, the pool never allocates new memory, and operations that
+/// would require it fail with `Error::LoaderUnsafe`. Released memory awaiting
+/// reclamation remains pending until it's disabled.
+pub fn set_loader_safe(enabled: bool) {
+  LOADER_SAFE.store(enabled, Ordering::SeqCst);
+}
+
+/// Returns whether loader-safe mode is enabled or not.
+pub fn is_loader_safe() -> bool {
+  LOADER_SAFE.load(Ordering::SeqCst)
+}
+
+/// The protection of a region registered with the pool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+  /// The region is already executable.
+  ///
+  /// If the region is not writable, its protection is temporarily changed
+  /// whilst code is written to it.
+  AlreadyExecutable,
+  /// The region's protection is changed to read, write & execute when it's
+  /// registered.
+  NeedsProtectFlip,
+}
+
+/// Registers a region of memory for allocating trampolines and relays.
+///
+/// Allocations are made within the region's bounds, and only for targets
+/// within range of the allocated memory. The region is never unmapped.
+///
+/// # Safety
+///
+/// The region must remain valid for the remainder of the process, must not be
+/// used for anything else, and must not overlap any other region.
+pub unsafe fn add_region(address: *mut u8, size: usize, kind: RegionKind) -> Result<()> {
+  if size == 0 {
+    return Ok(());
+  }
+
+  let _guard = memory::LOCK.lock();
+  let (lower, upper) = (address as *const (), address.add(size - 1) as *const ());
+
+  match kind {
+    RegionKind::AlreadyExecutable => {
+      if !crate::memory::is_executable(lower)? || !crate::memory::is_executable(upper)? {
+        return Err(Error::NotExecutable);
+      }
+    },
+    RegionKind::NeedsProtectFlip => {
+      os::backend()?.protect(lower, size, Protection::READ_WRITE_EXECUTE)?;
+    },
+  }
+
+  POOL.lock().add_region(address, size);
+  Ok(())
+}
+
+/// Enables or disables strict mode.
+///
+/// Whilst enabled, memory is only allocated from registered regions, and
+/// operations that would require other memory fail with
+/// `Error::RegionExhausted`.
+pub fn set_strict(enabled: bool) {
+  STRICT.store(enabled, Ordering::SeqCst);
+}
+
+/// Returns whether strict mode is enabled or not.
+pub fn is_strict() -> bool {
+  STRICT.load(Ordering::SeqCst)
+}
+
+/// Options for the memory pool of the default allocator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolOptions {
+  /// The minimum size of each mapped region, rounded up to the page size.
+  pub slab_size: usize,
+  /// The maximum distance between a target and its memory.
+  ///
+  /// The distance is always limited to the detour range (see
+  /// [meta::detour_range](../meta/fn.detour_range.html)).
+  pub max_search_distance: usize,
+  /// The distance between each address probed, whilst searching for free
+  /// memory. It's rounded up to the page size.
+  pub search_step: usize,
+  /// The alignment of each allocation, rounded up to a power of two.
+  ///
+  /// Allocations are padded to a multiple of the alignment using NOPs.
+  pub alignment: usize,
+}
+
+impl PoolOptions {
+  /// The options used unless the pool has been configured.
+  pub const DEFAULT: PoolOptions = PoolOptions {
+    slab_size: 0x1000,
+    max_search_distance: usize::MAX,
+    search_step: 0x1000,
+    alignment: 16,
+  };
+}
+
+impl Default for PoolOptions {
+  fn default() -> Self {
+    Self::DEFAULT
+  }
+}
+
+/// Configures the memory pool of the default allocator.
+///
+/// The pool must be configured before any memory has been added to it;
+/// `Error::AlreadyInitialized` is returned otherwise.
+pub fn configure(options: PoolOptions) -> Result<()> {
+  POOL.lock().configure(options)
+}
+
+/// Returns the options of the default allocator's memory pool.
+pub fn options() -> PoolOptions {
+  POOL.lock().options
+}
+
+/// Statistics for a region of memory owned by the default allocator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegionStats {
+  /// Base address of the region.
+  pub base: *const (),
+  /// Size of the region.
+  pub size: usize,
+  /// The number of bytes in use by live allocations.
+  pub used: usize,
+  /// The number of bytes available for allocations.
+  pub free: usize,
+  /// The size of the largest contiguous chunk available for allocations.
+  pub largest_free: usize,
+  /// The number of distinct chunks available for allocations.
+  pub free_chunks: usize,
+  /// The number of released bytes awaiting reclamation.
+  pub pending: usize,
+  /// The number of allocations, including those awaiting reclamation.
+  pub allocations: usize,
+  /// The address the region was allocated close to, unless it was
+  /// registered.
+  pub origin: Option<*const ()>,
+  /// Whether the region is retained once unused.
+  pub reserved: bool,
+  /// Whether the region was registered using `add_region`.
+  pub registered: bool,
+}
+
+unsafe impl Send for RegionStats {}
+unsafe impl Sync for RegionStats {}
+
+/// Returns statistics for each region of memory owned by the default
+/// allocator.
+///
+/// This never waits for detours being enabled or disabled, only for other
+/// allocations.
+pub fn stats() -> Vec<RegionStats> {
+  POOL.lock().stats().collect()
+}
+
+/// Returns statistics for the region of memory containing `address`, if it's
+/// owned by the default allocator.
+pub fn region_of(address: *const ()) -> Option<RegionStats> {
+  let address = address as usize;
+  POOL
+    .lock()
+    .stats()
+    .find(|region| (region.base as usize..region.base as usize + region.size).contains(&address))
+}
+
+/// Owned executable memory, allocated close to an origin.
+///
+/// This is the memory used for trampolines and relays, allocated through the
+/// installed [allocator](./fn.allocator.html), and exposed for code generated
+/// by users (e.g stubs that must be reachable by a relative jump). The memory
+/// is returned to its allocator once dropped.
+///
+/// # Example
+///
+/// ```rust
+/// # fn main() -> detour::Result<()> {
+/// use detour::pool::ExecutableMemory;
+///
+/// #[inline(never)]
+/// extern "C" fn origin() {}
+///
+/// // mov eax, 42; ret
+/// let code = [0xB8, 0x2A, 0x00, 0x00, 0x00, 0xC3];
+///
+/// let mut memory = ExecutableMemory::allocate_near(origin as *const (), code.len())?;
+/// memory.write(0, &code)?;
+///
+/// let stub: extern "C" fn() -> i32 = unsafe { std::mem::transmute(memory.as_ptr()) };
+/// assert_eq!(stub(), 42);
+/// # Ok(())
+/// # }
+/// ```
+pub struct ExecutableMemory {
+  allocator: &'static dyn ExecutableAllocator,
+  slice: ExecutableSlice,
+}
+
+impl ExecutableMemory {
+  /// Allocates at least `size` bytes within the
+  /// [detour range](../meta/fn.detour_range.html) of `origin` (i.e reachable
+  /// by a relative jump on x64).
+  pub fn allocate_near(origin: *const (), size: usize) -> Result<Self> {
+    Self::allocate_within(origin, size, meta::detour_range())
+  }
+
+  /// Allocates at least `size` bytes within `range` bytes of `origin`.
+  pub fn allocate_within(origin: *const (), size: usize, range: usize) -> Result<Self> {
+    let allocator = allocator();
+    let slice = allocator.allocate_near(origin as usize, size, range)?;
+    debug_assert!(slice.size >= size);
+
+    Ok(ExecutableMemory { allocator, slice })
+  }
+
+  /// Returns the address of the memory.
+  pub fn as_ptr(&self) -> *const u8 {
+    self.slice.address
+  }
+
+  /// Returns the size of the memory, which may exceed the requested size.
+  pub fn len(&self) -> usize {
+    self.slice.size
+  }
+
+  /// Returns whether the memory has a size of zero.
+  pub fn is_empty(&self) -> bool {
+    self.slice.size == 0
+  }
+
+  /// Writes code at an offset within the memory, and flushes the instruction
+  /// cache.
+  ///
+  /// The memory is written through its writable alias if it's dual mapped
+  /// (e.g with W^X enforced), or made writable whilst it's written otherwise.
+  ///
+  /// # Panics
+  ///
+  /// Panics if the code does not fit within the memory.
+  pub fn write(&mut self, offset: usize, code: &[u8]) -> Result<()> {
+    let _guard = memory::LOCK.lock();
+    unsafe { self.write_unlocked(offset, code) }
+  }
+
+  /// Writes code at an offset within the memory, whilst holding the lock.
+  pub(crate) unsafe fn write_unlocked(&mut self, offset: usize, code: &[u8]) -> Result<()> {
+    let end = offset.checked_add(code.len());
+    assert!(
+      end.is_some_and(|end| end <= self.len()),
+      "code exceeds executable memory"
+    );
+    memory::write_pool(self.as_ptr().add(offset) as *const (), code)
+  }
+}
+
+impl fmt::Debug for ExecutableMemory {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct("ExecutableMemory")
+      .field("address", &self.slice.address)
+      .field("size", &self.slice.size)
+      .finish()
+  }
+}
+
+impl Drop for ExecutableMemory {
+  fn drop(&mut self) {
+    profiling::unregister(self.slice.address as *const ());
+
+    // Return the chunk to its associated allocator
+    unsafe { self.allocator.free(self.slice) };
+  }
+}
@@ -0,0 +1,550 @@
+use alloc::vec::Vec;
+use core::iter;
+use core::ops::Range;
+
+use super::{search as region_search, ExecutableSlice, PoolOptions, Reclamation, RegionStats};
+use crate::error::{Error, Result};
+use crate::os;
+
+/// Released memory awaiting reclamation.
+struct Pending {
+  memory: Range<usize>,
+  /// The grace period during which it was released.
+  epoch: u64,
+}
+
+/// Shared instance containing all pools
+pub struct ProximityAllocator {
+  pub options: PoolOptions,
+  pub pools: Vec<MemoryPool>,
+  pending: Vec<Pending>,
+  /// The number of grace periods that have ended.
+  epoch: u64,
+  /// The index of the most recently used pool.
+  last: Option<usize>,
+  /// Whether any memory has been added to the allocator.
+  is_initialized: bool,
+}
+
+impl ProximityAllocator {
+  /// Creates an allocator without any pools.
+  pub const fn new() -> Self {
+    ProximityAllocator {
+      options: PoolOptions::DEFAULT,
+      pools: Vec::new(),
+      pending: Vec::new(),
+      epoch: 0,
+      last: None,
+      is_initialized: false,
+    }
+  }
+
+  /// Changes the options, unless any memory has been added.
+  pub fn configure(&mut self, options: PoolOptions) -> Result<()> {
+    if self.is_initialized {
+      return Err(Error::AlreadyInitialized);
+    }
+
+    self.options = options;
+    Ok(())
+  }
+
+  /// Allocates a slice in an eligible memory map.
+  pub fn allocate(&mut self, origin: usize, size: usize, range: usize) -> Result<ExecutableSlice> {
+    let memory_range = self.memory_range(origin, range);
+    let (size, alignment) = self.layout(size);
+
+    self.reclaim();
+
+    // Prefer registered regions, followed by any existing pool
+    if let Some(slice) = self.allocate_memory(&memory_range, size, alignment, true) {
+      return Ok(slice);
+    }
+
+    if super::is_strict() {
+      return Err(Error::RegionExhausted);
+    }
+
+    if let Some(slice) = self.allocate_memory(&memory_range, size, alignment, false) {
+      return Ok(slice);
+    }
+
+    if super::is_loader_safe() {
+      return Err(Error::LoaderUnsafe);
+    }
+
+    // ... otherwise allocate a pool within the memory range
+    let mut pool = self.allocate_pool(&memory_range, origin, size)?;
+    let slice = pool.alloc(size, alignment, &memory_range);
+    self.pools.push(pool);
+    self.last = Some(self.pools.len() - 1);
+    slice.ok_or(Error::OutOfMemory)
+  }
+
+  /// Allocates a new pool close to `origin`, unless `size` bytes are
+  /// already available within range.
+  pub fn reserve(&mut self, origin: usize, size: usize, range: usize) -> Result<()> {
+    let memory_range = self.memory_range(origin, range);
+    let (size, alignment) = self.layout(size);
+
+    let is_available = self.pools.iter().any(|pool| {
+      (pool.registered || !super::is_strict())
+        && pool.find(size, alignment, &memory_range).is_some()
+    });
+
+    if !is_available {
+      if super::is_strict() {
+        return Err(Error::RegionExhausted);
+      }
+
+      if super::is_loader_safe() {
+        return Err(Error::LoaderUnsafe);
+      }
+
+      let mut pool = self.allocate_pool(&memory_range, origin, size)?;
+      pool.reserved = true;
+      self.pools.push(pool);
+    }
+
+    Ok(())
+  }
+
+  /// Registers a region of memory, which is never unmapped.
+  pub fn add_region(&mut self, address: *mut u8, size: usize) {
+    let mut pool = MemoryPool::new(address, size);
+    pool.reserved = true;
+    pool.registered = true;
+    self.pools.push(pool);
+    self.is_initialized = true;
+  }
+
+  /// Returns statistics for each pool.
+  pub fn stats(&self) -> impl Iterator<Item = RegionStats> + '_ {
+    self.pools.iter().map(move |pool| {
+      let range = pool.range();
+      let free = pool.free.iter().map(|chunk| chunk.len()).sum::<usize>();
+      let pending = self
+        .pending
+        .iter()
+        .filter(|pending| range.contains(&pending.memory.start))
+        .map(|pending| pending.memory.len())
+        .sum::<usize>();
+
+      RegionStats {
+        base: pool.data as *const (),
+        size: pool.size,
+        used: pool.size - free - pending,
+        free,
+        largest_free: pool.free.iter().map(|chunk| chunk.len()).max().unwrap_or(0),
+        free_chunks: pool.free.len(),
+        pending,
+        allocations: pool.allocations,
+        origin: pool.origin.map(|origin| origin as *const ()),
+        reserved: pool.reserved,
+        registered: pool.registered,
+      }
+    })
+  }
+
+  /// Releases an allocation, according to the reclamation policy.
+  pub fn release(&mut self, value: &ExecutableSlice) {
+    let memory = (value.address as usize)..(value.address as usize + value.size);
+
+    match super::reclamation() {
+      Reclamation::Immediate => self.free(memory),
+      Reclamation::Deferred => {
+        self.pending.push(Pending {
+          memory,
+          epoch: self.epoch,
+        });
+        self.reclaim();
+      },
+      // The memory is intentionally leaked
+      Reclamation::Never => (),
+    }
+  }
+
+  /// Frees any released memory once two grace periods have ended since its
+  /// release, checking all pending memory at once.
+  ///
+  /// In loader-safe mode, nothing is checked, since the backend may enumerate
+  /// threads (e.g using a toolhelp snapshot on Windows).
+  pub fn reclaim(&mut self) {
+    let backend = match os::backend() {
+      Ok(backend) if !self.pending.is_empty() && !super::is_loader_safe() => backend,
+      _ => return,
+    };
+
+    let ranges = self
+      .pending
+      .iter()
+      .map(|pending| pending.memory.clone())
+      .collect::<Vec<_>>();
+    if backend.is_quiescent(&ranges) {
+      self.epoch += 1;
+    }
+
+    // The grace period during which memory was released may have ended
+    // whilst a thread was still executing it, hence the second one
+    let epoch = self.epoch;
+    while let Some(index) = self
+      .pending
+      .iter()
+      .position(|pending| epoch >= pending.epoch + 2)
+    {
+      let pending = self.pending.swap_remove(index);
+      self.free(pending.memory);
+    }
+  }
+
+  /// Returns memory to its associated pool.
+  fn free(&mut self, memory: Range<usize>) {
+    // Find the associated memory pool
+    let index = self
+      .pools
+      .iter()
+      .position(|pool| pool.range().contains(&memory.start))
+      .expect("retrieving associated memory pool");
+
+    let pool = &mut self.pools[index];
+    pool.free(memory);
+
+    // Unmap the pool once it's unused, unless it has been reserved
+    if pool.is_unused() && !pool.reserved {
+      let pool = self.pools.swap_remove(index);
+      self.last = None;
+      if let Ok(backend) = os::backend() {
+        unsafe { backend.release(pool.data, pool.size) };
+      }
+    }
+  }
+
+  /// Allocates a chunk using any of the existing (registered) pools.
+  fn allocate_memory(
+    &mut self,
+    range: &Range<usize>,
+    size: usize,
+    alignment: usize,
+    registered: bool,
+  ) -> Option<ExecutableSlice> {
+    // Consecutive targets are commonly within the same module, therefore the
+    // most recently used pool is tried first.
+    let last = self
+      .last
+      .filter(|&index| self.pools[index].registered == registered);
+
+    if let Some(slice) = last.and_then(|index| self.pools[index].alloc(size, alignment, range)) {
+      return Some(slice);
+    }
+
+    let (index, slice) = self
+      .pools
+      .iter_mut()
+      .enumerate()
+      .filter(|(_, pool)| pool.registered == registered)
+      .find_map(|(index, pool)| {
+        pool
+          .alloc(size, alignment, range)
+          .map(|slice| (index, slice))
+      })?;
+
+    self.last = Some(index);
+    Some(slice)
+  }
+
+  /// Returns the eligible memory range for an origin.
+  fn memory_range(&self, origin: usize, range: usize) -> Range<usize> {
+    let range = range.min(self.options.max_search_distance);
+    origin.saturating_sub(range)..origin.saturating_add(range)
+  }
+
+  /// Returns the padded size of an allocation, and its alignment.
+  fn layout(&self, size: usize) -> (usize, usize) {
+    let alignment = self.options.alignment.max(1).next_power_of_two();
+    (os::align_up(size.max(1), alignment), alignment)
+  }
+
+  /// Returns the size of a new slab for an allocation, and the search step.
+  fn slab_layout(&self, size: usize, page_size: usize, granularity: usize) -> (usize, usize) {
+    let size = os::align_up(size.max(self.options.slab_size).max(1), page_size);
+    let step = os::align_up(self.options.search_step.max(1), granularity);
+    (size, step)
+  }
+
+  /// Allocates a new pool close to `origin`.
+  fn allocate_pool(
+    &mut self,
+    range: &Range<usize>,
+    origin: usize,
+    size: usize,
+  ) -> Result<MemoryPool> {
+    let backend = os::backend()?;
+    let (size, step) =
+      self.slab_layout(size, backend.page_size(), backend.allocation_granularity());
+    self.is_initialized = true;
+
+    let mut before =
+      region_search::before(origin as *const (), Some(range.clone()), size, step)?.fuse();
+    let mut after =
+      region_search::after(origin as *const (), Some(range.clone()), size, step)?.fuse();
+
+    // Alternate between addresses after and before the origin, trying after
+    // first (mostly because macOS cannot allocate memory before the process's
+    // address).
+    let mut is_after = false;
+    let candidates = iter::from_fn(move || {
+      is_after = !is_after;
+      if is_after {
+        after.next().or_else(|| before.next())
+      } else {
+        before.next().or_else(|| after.next())
+      }
+    });
+
+    // An address may be unavailable for reasons only known once it's mapped
+    // (e.g beyond the address space), therefore errors are only reported once
+    // all candidates are exhausted, unless mapping is denied altogether.
+    let mut failure = None;
+
+    for address in candidates {
+      match unsafe { backend.allocate(address?, size) } {
+        Ok(Some(data)) => {
+          let mut pool = MemoryPool::new(data, size);
+          pool.origin = Some(origin);
+          return Ok(pool);
+        },
+        Ok(None) => (),
+        Err(error @ Error::PermissionDenied { .. }) => return Err(error),
+        Err(error) => failure = Some(error),
+      }
+    }
+
+    Err(failure.unwrap_or(Error::NoMemoryInRange {
+      origin,
+      range: range.clone(),
+    }))
+  }
+}
+
+/// A block of executable memory, divided into allocations.
+pub struct MemoryPool {
+  data: *mut u8,
+  size: usize,
+  /// Whether the pool is retained once unused.
+  reserved: bool,
+  /// Whether the pool is a region registered by the user.
+  registered: bool,
+  /// The address the pool was allocated close to.
+  origin: Option<usize>,
+  /// The number of live allocations.
+  allocations: usize,
+  /// The address ranges of all unused memory, sorted and coalesced.
+  free: Vec<Range<usize>>,
+}
+
+impl MemoryPool {
+  /// Creates a pool of unused memory.
+  fn new(data: *mut u8, size: usize) -> Self {
+    MemoryPool {
+      data,
+      size,
+      reserved: false,
+      registered: false,
+      origin: None,
+      allocations: 0,
+      free: core::iter::once((data as usize)..(data as usize + size)).collect(),
+    }
+  }
+
+  /// Returns the address range of the pool.
+  fn range(&self) -> Range<usize> {
+    (self.data as usize)..(self.data as usize + self.size)
+  }
+
+  /// Returns whether the pool has no live allocations.
+  fn is_unused(&self) -> bool {
+    self.allocations == 0
+  }
+
+  /// Returns the index of the first unused chunk with `size` bytes at an
+  /// aligned address within the range, along with the address of the bytes.
+  fn find(&self, size: usize, alignment: usize, range: &Range<usize>) -> Option<(usize, usize)> {
+    self.free.iter().enumerate().find_map(|(index, chunk)| {
+      let lower = os::align_up(chunk.start.max(range.start), alignment);
+      let upper = chunk.end.min(range.end);
+      (upper.saturating_sub(lower) >= size).then_some((index, lower))
+    })
+  }
+
+  /// Allocates `size` bytes at an aligned address within the range, from the
+  /// first eligible chunk.
+  ///
+  /// Any unaligned bytes preceding the allocation remain unused.
+  fn alloc(
+    &mut self,
+    size: usize,
+    alignment: usize,
+    range: &Range<usize>,
+  ) -> Option<ExecutableSlice> {
+    let (index, lower) = self.find(size, alignment, range)?;
+    let (start, end) = (self.free[index].start, self.free[index].end);
+    let upper = lower + size;
+
+    // Split the chunk if the allocation is in the middle of it
+    match (start == lower, end == upper) {
+      (true, true) => {
+        self.free.remove(index);
+      },
+      (true, false) => self.free[index].start = upper,
+      (false, true) => self.free[index].end = lower,
+      (false, false) => {
+        self.free[index].end = lower;
+        self.free.insert(index + 1, upper..end);
+      },
+    }
+
+    self.allocations += 1;
+    Some(ExecutableSlice {
+      address: lower as *mut u8,
+      size,
+    })
+  }
+
+  /// Returns a chunk of memory to the pool.
+  fn free(&mut self, memory: Range<usize>) {
+    let (lower, upper) = (memory.start, memory.end);
+    debug_assert!(self.range().contains(&lower) && upper <= self.range().end);
+
+    // Keep the chunks sorted, and merge the chunk with any adjacent ones
+    let index = self.free.partition_point(|chunk| chunk.start < lower);
+    let merges_next = self.free.get(index).is_some_and(|next| next.start == upper);
+    let merges_previous = index > 0 && self.free[index - 1].end == lower;
+
+    match (merges_previous, merges_next) {
+      (true, true) => {
+        self.free[index - 1].end = self.free[index].end;
+        self.free.remove(index);
+      },
+      (true, false) => self.free[index - 1].end = upper,
+      (false, true) => self.free[index].start = lower,
+      (false, false) => self.free.insert(index, lower..upper),
+    }
+
+    self.allocations -= 1;
+  }
+}
+
+unsafe impl Send for MemoryPool {}
+unsafe impl Sync for MemoryPool {}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn range(slice: &ExecutableSlice) -> Range<usize> {
+    (slice.address as usize)..(slice.address as usize + slice.size)
+  }
+
+  const ANY: Range<usize> = 0..usize::MAX;
+
+  #[test]
+  fn pool_reuses_and_coalesces_chunks() {
+    let mut memory = [0u8; 64];
+    let mut pool = MemoryPool::new(memory.as_mut_ptr(), memory.len());
+
+    let first = pool.alloc(16, 1, &ANY).unwrap();
+    let second = pool.alloc(16, 1, &ANY).unwrap();
+    let third = pool.alloc(16, 1, &ANY).unwrap();
+    assert!(pool.alloc(32, 1, &ANY).is_none());
+
+    // A released chunk is reused by a subsequent allocation of the same size
+    pool.free(range(&second));
+    let reused = pool.alloc(16, 1, &ANY).unwrap();
+    assert_eq!(reused.address, second.address);
+
+    // Adjacent chunks are merged, allowing larger allocations
+    pool.free(range(&first));
+    pool.free(range(&reused));
+    assert_eq!(pool.free.len(), 2);
+    assert_eq!(pool.alloc(32, 1, &ANY).unwrap().address, first.address);
+
+    pool.free(range(&third));
+    assert_eq!(pool.allocations, 1);
+    assert_eq!(pool.free.len(), 1);
+    assert_eq!(pool.free[0].start, third.address as usize);
+    assert_eq!(pool.free[0].end, memory.as_ptr() as usize + memory.len());
+  }
+
+  #[test]
+  fn pool_aligns_allocations() {
+    let mut memory = [0u8; 128];
+    let mut pool = MemoryPool::new(memory.as_mut_ptr(), memory.len());
+    let base = memory.as_ptr() as usize;
+
+    // The unaligned bytes before an allocation remain available
+    let aligned = pool.alloc(16, 32, &((base + 1)..(base + 128))).unwrap();
+    assert_eq!(aligned.address as usize % 32, 0);
+    assert!(aligned.address as usize > base);
+    assert_eq!(pool.free[0].start, base);
+
+    pool.free(range(&aligned));
+    assert_eq!(pool.free.len(), 1);
+    assert!(pool.is_unused());
+  }
+
+  #[test]
+  fn allocations_are_padded() {
+    let mut allocator = ProximityAllocator::new();
+    assert_eq!(allocator.layout(1), (16, 16));
+    assert_eq!(allocator.layout(17), (32, 16));
+
+    allocator.options.alignment = 48;
+    assert_eq!(allocator.layout(17), (64, 64));
+
+    allocator.options.alignment = 0;
+    assert_eq!(allocator.layout(17), (17, 1));
+  }
+
+  #[test]
+  fn slab_layout_with_large_pages() {
+    let mut allocator = ProximityAllocator::new();
+    assert_eq!(
+      allocator.slab_layout(0x20, 0x1000, 0x10000),
+      (0x1000, 0x10000)
+    );
+    assert_eq!(
+      allocator.slab_layout(0x20, 0x4000, 0x4000),
+      (0x4000, 0x4000)
+    );
+    assert_eq!(
+      allocator.slab_layout(0x4001, 0x4000, 0x4000),
+      (0x8000, 0x4000)
+    );
+
+    allocator.options.slab_size = 0x5000;
+    assert_eq!(
+      allocator.slab_layout(0x20, 0x4000, 0x4000),
+      (0x8000, 0x4000)
+    );
+  }
+
+  #[test]
+  fn pool_allocates_within_range() {
+    let mut memory = [0u8; 64];
+    let mut pool = MemoryPool::new(memory.as_mut_ptr(), memory.len());
+    let base = memory.as_ptr() as usize;
+
+    // The chunk is split around an allocation in the middle of it
+    let middle = pool.alloc(16, 1, &((base + 24)..(base + 48))).unwrap();
+    assert_eq!(middle.address as usize, base + 24);
+    assert_eq!(pool.free.len(), 2);
+
+    // Only 8 bytes are available within this range
+    assert!(pool.alloc(16, 1, &((base + 16)..(base + 48))).is_none());
+    assert!(pool.alloc(16, 1, &((base + 80)..(base + 128))).is_none());
+
+    pool.free(range(&middle));
+    assert_eq!(pool.free.len(), 1);
+    assert!(pool.is_unused());
+  }
+}
@@ -0,0 +1,210 @@
+use crate::error::Result;
+use crate::os::{self, align_down, align_up};
+use alloc::vec::{self, Vec};
+use core::ops::Range;
+
+/// Returns an iterator for free after the specified address.
+pub fn after(
+  origin: *const (),
+  range: Option<Range<usize>>,
+  size: usize,
+  step: usize,
+) -> Result<impl Iterator<Item = Result<*const ()>>> {
+  FreeRegionIter::new(origin, range, size, step, SearchDirection::After)
+}
+
+/// Returns an iterator for free before the specified address.
+pub fn before(
+  origin: *const (),
+  range: Option<Range<usize>>,
+  size: usize,
+  step: usize,
+) -> Result<impl Iterator<Item = Result<*const ()>>> {
+  FreeRegionIter::new(origin, range, size, step, SearchDirection::Before)
+}
+
+/// Direction for the region search.
+#[derive(Clone, Copy)]
+enum SearchDirection {
+  Before,
+  After,
+}
+
+/// An iterator searching for free regions.
+enum FreeRegionIter {
+  /// Candidates within the gaps between all mapped regions.
+  Gaps(vec::IntoIter<usize>),
+  /// Candidates found by querying an address at a time.
+  Probe(ProbeIter),
+}
+
+impl FreeRegionIter {
+  /// Creates a new iterator for free regions.
+  fn new(
+    origin: *const (),
+    range: Option<Range<usize>>,
+    size: usize,
+    step: usize,
+    search: SearchDirection,
+  ) -> Result<Self> {
+    let backend = os::backend()?;
+    let range = range.unwrap_or(0..usize::MAX);
+    let origin = origin as usize;
+
+    // Prefer enumerating all regions at once, if the backend supports it
+    let regions = match backend.query_range(range.clone()) {
+      Some(regions) => regions,
+      None => {
+        return Ok(FreeRegionIter::Probe(ProbeIter {
+          backend,
+          range,
+          current: origin,
+          search,
+          step,
+        }))
+      },
+    };
+
+    let alignment = backend.allocation_granularity();
+    let gaps = gaps(&regions, range, alignment);
+
+    // Select the address closest to the origin within each gap
+    let candidates: Vec<usize> = match search {
+      SearchDirection::After => gaps
+        .into_iter()
+        .filter(|gap| gap.end > origin)
+        .filter_map(|gap| {
+          let address = gap.start.max(align_up(origin, alignment));
+          (address.checked_add(size)? <= gap.end).then_some(address)
+        })
+        .collect(),
+      SearchDirection::Before => gaps
+        .into_iter()
+        .rev()
+        .filter(|gap| gap.start < origin)
+        .filter_map(|gap| {
+          let upper = gap.end.min(align_down(origin, alignment));
+          let address = align_down(upper.checked_sub(size)?, alignment);
+          (address >= gap.start).then_some(address)
+        })
+        .collect(),
+    };
+
+    Ok(FreeRegionIter::Gaps(candidates.into_iter()))
+  }
+}
+
+impl Iterator for FreeRegionIter {
+  type Item = Result<*const ()>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    match self {
+      FreeRegionIter::Gaps(candidates) => candidates.next().map(|address| Ok(address as *const ())),
+      FreeRegionIter::Probe(probe) => probe.next(),
+    }
+  }
+}
+
+/// Returns the aligned gaps between regions, within the range.
+///
+/// The regions must be sorted by address.
+fn gaps(regions: &[os::Region], range: Range<usize>, alignment: usize) -> Vec<Range<usize>> {
+  let mut gaps = Vec::new();
+  let mut lower = align_up(range.start.max(alignment), alignment);
+
+  for region in regions {
+    let upper = align_down(region.lower().min(range.end), alignment);
+    if upper > lower {
+      gaps.push(lower..upper);
+    }
+    lower = lower.max(align_up(region.upper(), alignment));
+  }
+
+  let upper = align_down(range.end, alignment);
+  if upper > lower {
+    gaps.push(lower..upper);
+  }
+
+  gaps
+}
+
+/// An iterator querying each address for free regions.
+struct ProbeIter {
+  backend: &'static dyn os::Backend,
+  range: Range<usize>,
+  search: SearchDirection,
+  current: usize,
+  step: usize,
+}
+
+impl Iterator for ProbeIter {
+  type Item = Result<*const ()>;
+
+  /// Returns the closest free region for the current address.
+  fn next(&mut self) -> Option<Self::Item> {
+    let page_size = self.backend.page_size();
+
+    while self.current > 0 && self.range.contains(&self.current) {
+      match self.backend.query(self.current as *const _) {
+        Ok(Some(region)) => {
+          self.current = match self.search {
+            SearchDirection::Before => region.lower().saturating_sub(page_size),
+            SearchDirection::After => region.upper(),
+          }
+        },
+        result => {
+          // The region is free unless an error occurred
+          let result = Some(result.map(|_| self.current as *const _));
+
+          // Adjust the offset for repeated calls.
+          self.current = match self.search {
+            SearchDirection::Before => self.current.saturating_sub(self.step),
+            SearchDirection::After => self.current.saturating_add(self.step),
+          };
+
+          return result;
+        },
+      }
+    }
+
+    None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn region(lower: usize, upper: usize) -> os::Region {
+    os::Region {
+      base: lower as *const (),
+      size: upper - lower,
+      protection: os::Protection::READ,
+    }
+  }
+
+  #[test]
+  fn gaps_between_regions() {
+    let regions = [
+      region(0x1000, 0x3000),
+      region(0x3000, 0x4000),
+      region(0x8000, 0x9800),
+      region(0xa000, 0x20000),
+    ];
+
+    // Unaligned regions occupy their entire pages
+    let result = gaps(&regions, 0..0x10000, 0x1000);
+    assert_eq!(result.len(), 1);
+    assert_eq!((result[0].start, result[0].end), (0x4000, 0x8000));
+
+    assert_eq!(
+      gaps(&regions, 0x5000..usize::MAX, 0x1000),
+      [0x5000..0x8000, 0x20000..align_down(usize::MAX, 0x1000),]
+    );
+    assert!(gaps(&regions, 0x1000..0x4000, 0x1000).is_empty());
+
+    // With 16 KiB pages, the regions occupy more of the address space
+    let result = gaps(&regions, 0..0x40000, 0x4000);
+    assert_eq!(result, [0x4000..0x8000, 0x20000..0x40000]);
+  }
+}
@@ -0,0 +1,129 @@
+//! Slots of executable memory reserved ahead of time, which are claimed
+//! without locking or calling into the operating system (e.g from a signal
+//! handler).
+//!
+//! Each slot is a chunk allocated from the pool, and is only filled or
+//! vacated whilst the pool is locked; claiming is a single CAS. Once a
+//! claimed chunk is freed, it's released to the pool like any other, and its
+//! slot is vacated.
+
+use super::ExecutableSlice;
+use crate::error::Result;
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+
+/// The maximum amount of slots reserved at once.
+pub const CAPACITY: usize = 64;
+
+/// The slot contains no chunk.
+const VACANT: u8 = 0;
+/// The slot contains a chunk that may be claimed.
+const AVAILABLE: u8 = 1;
+/// The slot contains a claimed chunk.
+const CLAIMED: u8 = 2;
+
+/// A reserved chunk, and its state.
+struct Slot {
+  address: AtomicUsize,
+  size: AtomicUsize,
+  state: AtomicU8,
+}
+
+impl Slot {
+  /// Returns the chunk of the slot.
+  fn chunk(&self) -> ExecutableSlice {
+    ExecutableSlice {
+      address: self.address.load(Ordering::SeqCst) as *mut u8,
+      size: self.size.load(Ordering::SeqCst),
+    }
+  }
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const SLOT: Slot = Slot {
+  address: AtomicUsize::new(0),
+  size: AtomicUsize::new(0),
+  state: AtomicU8::new(VACANT),
+};
+
+static SLOTS: [Slot; CAPACITY] = [SLOT; CAPACITY];
+
+/// Returns whether a chunk lies within `range` bytes of `origin`.
+fn is_within(chunk: &ExecutableSlice, origin: usize, range: usize) -> bool {
+  let address = chunk.address as usize;
+  address.abs_diff(origin) <= range && (address + chunk.size).abs_diff(origin) <= range
+}
+
+/// Claims an available chunk within `range` bytes of `origin`.
+pub fn claim(origin: usize, size: usize, range: usize) -> Option<ExecutableSlice> {
+  SLOTS.iter().find_map(|slot| {
+    // The chunk is stored before it becomes available
+    if slot.state.load(Ordering::SeqCst) != AVAILABLE {
+      return None;
+    }
+
+    let chunk = slot.chunk();
+    if chunk.size < size || !is_within(&chunk, origin, range) {
+      return None;
+    }
+
+    slot
+      .state
+      .compare_exchange(AVAILABLE, CLAIMED, Ordering::SeqCst, Ordering::SeqCst)
+      .ok()?;
+
+    // The slot may have been refilled in between
+    if slot.chunk() != chunk {
+      slot.state.store(AVAILABLE, Ordering::SeqCst);
+      return None;
+    }
+
+    Some(chunk)
+  })
+}
+
+/// Fills vacant slots with `count` chunks, whilst the pool is locked.
+///
+/// Returns `false` if the slots are exhausted.
+pub fn fill(
+  mut count: usize,
+  mut allocate: impl FnMut() -> Result<ExecutableSlice>,
+) -> Result<bool> {
+  for slot in SLOTS.iter() {
+    if count == 0 {
+      break;
+    }
+
+    if slot.state.load(Ordering::SeqCst) == VACANT {
+      let chunk = allocate()?;
+      slot.address.store(chunk.address as usize, Ordering::SeqCst);
+      slot.size.store(chunk.size, Ordering::SeqCst);
+      slot.state.store(AVAILABLE, Ordering::SeqCst);
+      count -= 1;
+    }
+  }
+
+  Ok(count == 0)
+}
+
+/// Vacates the slot of a claimed chunk, whilst the pool is locked.
+pub fn vacate(chunk: &ExecutableSlice) {
+  let slot = SLOTS
+    .iter()
+    .find(|slot| slot.state.load(Ordering::SeqCst) == CLAIMED && slot.chunk() == *chunk);
+
+  if let Some(slot) = slot {
+    slot.address.store(0, Ordering::SeqCst);
+    slot.size.store(0, Ordering::SeqCst);
+    slot.state.store(VACANT, Ordering::SeqCst);
+  }
+}
+
+/// Returns the amount of available chunks within `range` bytes of `origin`.
+pub fn available(origin: usize, range: usize) -> usize {
+  SLOTS
+    .iter()
+    .filter(|slot| {
+      slot.state.load(Ordering::SeqCst) == AVAILABLE && is_within(&slot.chunk(), origin, range)
+    })
+    .count()
+}
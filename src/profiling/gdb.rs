@@ -0,0 +1,373 @@
+//! Registration of generated code with the GDB JIT interface.
+//!
+//! Each piece of code is described by a minimal in-memory ELF object,
+//! containing a `.text` section located at the code and a function symbol
+//! spanning it. Debuggers (GDB and LLDB) set a breakpoint within
+//! `__jit_debug_register_code`, and read the objects linked from
+//! `__jit_debug_descriptor` whenever it's called.
+//!
+//! Debuggers only consult a single definition of the interface per module,
+//! so a process should share one. If another JIT compiler (e.g LLVM's) exports
+//! the symbols, they're resolved upon the first registration and used instead
+//! of the library's own; entries are then only consistent if the other JIT
+//! does not modify the list concurrently. The library's definitions are
+//! unmangled, so linking it statically alongside another definition (including
+//! a second version of this crate) fails with duplicate symbols.
+
+use super::CodeSymbol;
+use crate::sync::Mutex;
+use alloc::boxed::Box;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+use core::{mem, ptr};
+
+// The actions of the interface
+const JIT_NOACTION: u32 = 0;
+const JIT_REGISTER_FN: u32 = 1;
+const JIT_UNREGISTER_FN: u32 = 2;
+
+/// An object registered with the debugger (`jit_code_entry`).
+#[repr(C)]
+struct CodeEntry {
+  next: *mut CodeEntry,
+  prev: *mut CodeEntry,
+  symfile_addr: *const u8,
+  symfile_size: u64,
+}
+
+/// The list of objects read by the debugger (`jit_descriptor`).
+#[repr(C)]
+struct Descriptor {
+  version: u32,
+  action_flag: u32,
+  relevant_entry: *mut CodeEntry,
+  first_entry: *mut CodeEntry,
+}
+
+/// The descriptor, only modified whilst holding the lock of the entries.
+#[repr(transparent)]
+struct Interface(UnsafeCell<Descriptor>);
+
+unsafe impl Sync for Interface {}
+
+#[no_mangle]
+static __jit_debug_descriptor: Interface = Interface(UnsafeCell::new(Descriptor {
+  version: 1,
+  action_flag: JIT_NOACTION,
+  relevant_entry: ptr::null_mut(),
+  first_entry: ptr::null_mut(),
+}));
+
+/// Notifies an attached debugger of the descriptor's action.
+#[no_mangle]
+#[inline(never)]
+extern "C" fn __jit_debug_register_code() {
+  // The debugger's breakpoint requires the function to be retained as is
+  unsafe { ptr::read_volatile(&(*__jit_debug_descriptor.0.get()).action_flag) };
+}
+
+/// The interface used to register objects.
+struct Debugger {
+  descriptor: *mut Descriptor,
+  register_code: unsafe extern "C" fn(),
+}
+
+impl Debugger {
+  /// Resolves the interface exported within the process, or defaults to the
+  /// library's own.
+  fn resolve() -> Self {
+    let own = Debugger {
+      descriptor: __jit_debug_descriptor.0.get(),
+      register_code: __jit_debug_register_code,
+    };
+
+    #[cfg(unix)]
+    unsafe {
+      let resolve = |name: &[u8]| libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr().cast());
+      let descriptor = resolve(b"__jit_debug_descriptor\0");
+      let register_code = resolve(b"__jit_debug_register_code\0");
+
+      let descriptor = descriptor as *mut Descriptor;
+      if !descriptor.is_null() && !register_code.is_null() && (*descriptor).version == 1 {
+        return Debugger {
+          descriptor,
+          register_code: mem::transmute::<*mut libc::c_void, unsafe extern "C" fn()>(register_code),
+        };
+      }
+    }
+    own
+  }
+}
+
+/// The interface and the objects registered with it.
+struct Entries {
+  debugger: Option<Debugger>,
+  registrations: Vec<Registration>,
+}
+
+unsafe impl Send for Entries {}
+
+/// An object registered for generated code.
+struct Registration {
+  address: *const (),
+  entry: Box<CodeEntry>,
+  #[allow(dead_code)]
+  object: Vec<u8>,
+}
+
+unsafe impl Send for Registration {}
+
+/// The registered objects.
+static ENTRIES: Mutex<Entries> = Mutex::new(Entries {
+  debugger: None,
+  registrations: Vec::new(),
+});
+
+/// Registers an object describing generated code.
+pub fn register(symbol: &CodeSymbol) {
+  let object = object(symbol);
+  let mut entries = ENTRIES.lock();
+  let debugger = entries.debugger.get_or_insert_with(Debugger::resolve);
+
+  unsafe {
+    let descriptor = debugger.descriptor;
+    let mut entry = Box::new(CodeEntry {
+      next: (*descriptor).first_entry,
+      prev: ptr::null_mut(),
+      symfile_addr: object.as_ptr(),
+      symfile_size: object.len() as u64,
+    });
+
+    if let Some(first) = (*descriptor).first_entry.as_mut() {
+      first.prev = &mut *entry;
+    }
+
+    (*descriptor).first_entry = &mut *entry;
+    notify(debugger, &mut entry, JIT_REGISTER_FN);
+
+    entries.registrations.push(Registration {
+      address: symbol.address,
+      entry,
+      object,
+    });
+  }
+}
+
+/// Unregisters the object describing released code, if any.
+pub fn unregister(address: *const ()) {
+  let mut entries = ENTRIES.lock();
+  let index = match entries
+    .registrations
+    .iter()
+    .position(|entry| entry.address == address)
+  {
+    Some(index) => index,
+    None => return,
+  };
+
+  let mut registration = entries.registrations.swap_remove(index);
+  let debugger = entries.debugger.as_ref().expect("resolved debugger");
+  unsafe {
+    let descriptor = debugger.descriptor;
+    let entry = &mut *registration.entry;
+
+    match entry.prev.as_mut() {
+      Some(prev) => prev.next = entry.next,
+      None => (*descriptor).first_entry = entry.next,
+    }
+
+    if let Some(next) = entry.next.as_mut() {
+      next.prev = entry.prev;
+    }
+
+    notify(debugger, entry, JIT_UNREGISTER_FN);
+  }
+}
+
+/// Notifies the debugger of an action concerning an entry.
+unsafe fn notify(debugger: &Debugger, entry: &mut CodeEntry, action: u32) {
+  let descriptor = debugger.descriptor;
+  (*descriptor).relevant_entry = entry;
+  (*descriptor).action_flag = action;
+  (debugger.register_code)();
+  (*descriptor).action_flag = JIT_NOACTION;
+}
+
+#[cfg(target_pointer_width = "64")]
+mod layout {
+  pub const CLASS: u8 = 2;
+  pub const HEADER_SIZE: usize = 64;
+  pub const SECTION_SIZE: usize = 64;
+  pub const SYMBOL_SIZE: usize = 24;
+}
+
+#[cfg(target_pointer_width = "32")]
+mod layout {
+  pub const CLASS: u8 = 1;
+  pub const HEADER_SIZE: usize = 52;
+  pub const SECTION_SIZE: usize = 40;
+  pub const SYMBOL_SIZE: usize = 16;
+}
+
+#[cfg(target_arch = "x86_64")]
+const MACHINE: u16 = 62;
+
+#[cfg(target_arch = "x86")]
+const MACHINE: u16 = 3;
+
+/// The names of the sections, at offsets 1 (`.text`), 7 (`.symtab`), 15
+/// (`.strtab`) and 23 (`.shstrtab`).
+const SECTION_NAMES: &[u8] = b"\0.text\0.symtab\0.strtab\0.shstrtab\0";
+
+// Section types and flags
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_NOBITS: u32 = 8;
+const SHF_ALLOC_EXECINSTR: usize = 0x6;
+
+/// Builds a relocatable ELF object, containing a function symbol spanning
+/// the code.
+fn object(symbol: &CodeSymbol) -> Vec<u8> {
+  use self::layout::*;
+
+  let mut names = alloc::vec![0];
+  names.extend_from_slice(symbol.to_string().as_bytes());
+  names.push(0);
+
+  let word = mem::size_of::<usize>();
+  let section_names = HEADER_SIZE;
+  let symbol_names = section_names + SECTION_NAMES.len();
+  let symbols = (symbol_names + names.len()).div_ceil(word) * word;
+  let sections = symbols + 2 * SYMBOL_SIZE;
+
+  let mut elf = Writer(Vec::with_capacity(sections + 5 * SECTION_SIZE));
+  elf.bytes(&[0x7F, b'E', b'L', b'F', CLASS, 1, 1]);
+  elf.bytes(&[0; 9]);
+  elf.u16(1); // ET_REL
+  elf.u16(MACHINE);
+  elf.u32(1);
+  elf.word(0);
+  elf.word(0);
+  elf.word(sections);
+  elf.u32(0);
+  elf.u16(HEADER_SIZE as u16);
+  elf.u16(0);
+  elf.u16(0);
+  elf.u16(SECTION_SIZE as u16);
+  elf.u16(5);
+  elf.u16(4);
+
+  elf.bytes(SECTION_NAMES);
+  elf.bytes(&names);
+  elf.0.resize(symbols + SYMBOL_SIZE, 0);
+  elf.symbol(1, symbol.size);
+
+  elf.0.resize(sections + SECTION_SIZE, 0);
+  elf.section(Section {
+    name: 1,
+    kind: SHT_NOBITS,
+    flags: SHF_ALLOC_EXECINSTR,
+    address: symbol.address as usize,
+    size: symbol.size,
+    alignment: 16,
+    ..Section::default()
+  });
+  elf.section(Section {
+    name: 7,
+    kind: SHT_SYMTAB,
+    offset: symbols,
+    size: 2 * SYMBOL_SIZE,
+    link: 3,
+    info: 1,
+    alignment: word,
+    entry_size: SYMBOL_SIZE,
+    ..Section::default()
+  });
+  elf.section(Section {
+    name: 15,
+    kind: SHT_STRTAB,
+    offset: symbol_names,
+    size: names.len(),
+    ..Section::default()
+  });
+  elf.section(Section {
+    name: 23,
+    kind: SHT_STRTAB,
+    offset: section_names,
+    size: SECTION_NAMES.len(),
+    ..Section::default()
+  });
+  elf.0
+}
+
+/// A writer of little-endian ELF structures, sized for the target.
+struct Writer(Vec<u8>);
+
+impl Writer {
+  fn bytes(&mut self, bytes: &[u8]) {
+    self.0.extend_from_slice(bytes);
+  }
+
+  fn u16(&mut self, value: u16) {
+    self.bytes(&value.to_le_bytes());
+  }
+
+  fn u32(&mut self, value: u32) {
+    self.bytes(&value.to_le_bytes());
+  }
+
+  fn word(&mut self, value: usize) {
+    self.bytes(&value.to_le_bytes());
+  }
+
+  /// Writes a global function symbol, relative to the `.text` section.
+  fn symbol(&mut self, name: u32, size: usize) {
+    const INFO: u8 = 0x12; // STB_GLOBAL, STT_FUNC
+    const TEXT: u16 = 1;
+
+    self.u32(name);
+    #[cfg(target_pointer_width = "64")]
+    {
+      self.bytes(&[INFO, 0]);
+      self.u16(TEXT);
+      self.word(0);
+      self.word(size);
+    }
+    #[cfg(target_pointer_width = "32")]
+    {
+      self.word(0);
+      self.word(size);
+      self.bytes(&[INFO, 0]);
+      self.u16(TEXT);
+    }
+  }
+
+  fn section(&mut self, section: Section) {
+    self.u32(section.name);
+    self.u32(section.kind);
+    self.word(section.flags);
+    self.word(section.address);
+    self.word(section.offset);
+    self.word(section.size);
+    self.u32(section.link);
+    self.u32(section.info);
+    self.word(section.alignment);
+    self.word(section.entry_size);
+  }
+}
+
+/// A section header (`Elf_Shdr`).
+#[derive(Default)]
+struct Section {
+  name: u32,
+  kind: u32,
+  flags: usize,
+  address: usize,
+  offset: usize,
+  size: usize,
+  link: u32,
+  info: u32,
+  alignment: usize,
+  entry_size: usize,
+}
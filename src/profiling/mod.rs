@@ -0,0 +1,259 @@
+//! Symbols for generated code, allowing profilers to attribute samples.
+//!
+//! Samples within trampolines and relays otherwise land in anonymous memory,
+//! which makes the overhead of detours impossible to attribute. With the
+//! `profiling` feature (implied by `gdb-jit`), every piece of code generated
+//! by the library is recorded along with the target it was generated for, and
+//! removed once its memory is released. Without it, nothing is recorded.
+//!
+//! - On Linux, the symbols can be written to a perf map (`/tmp/perf-<pid>.map`)
+//!   using [set_perf_map](./fn.set_perf_map.html), which `perf report`
+//!   consumes. A line is appended whenever code is generated; since perf uses
+//!   the last entry matching an address, memory that is reused is attributed to
+//!   its new code.
+//! - Elsewhere (e.g for an ETW provider emitting method load events on
+//!   Windows), the current symbols can be enumerated using
+//!   [symbols](./fn.symbols.html).
+//! - With the `gdb-jit` feature, each piece of code is registered with the GDB
+//!   JIT interface (`__jit_debug_register_code`), allowing GDB and LLDB to name
+//!   its frames within backtraces.
+//!
+//! Symbols are named after their target (e.g
+//! `detour::trampoline_for_0x7f3a2c001130`), or after its label, if one is
+//! assigned using [set_label](./fn.set_label.html).
+//!
+//! # Example
+//!
+//! ```rust
+//! # use detour::Result;
+//! use detour::{profiling, RawDetour};
+//!
+//! #[inline(never)]
+//! extern "C" fn add5(val: i32) -> i32 {
+//!   unsafe { std::ptr::read_volatile(&val) + 5 }
+//! }
+//!
+//! extern "C" fn add10(val: i32) -> i32 {
+//!   val + 10
+//! }
+//!
+//! # #[cfg(feature = "profiling")]
+//! # fn main() -> Result<()> {
+//! profiling::set_label(add5 as *const (), "add5");
+//! let hook = unsafe { RawDetour::new(add5 as *const (), add10 as *const ())? };
+//!
+//! let symbol = profiling::symbols()
+//!   .into_iter()
+//!   .find(|symbol| symbol.address == hook.trampoline() as *const ())
+//!   .unwrap();
+//! assert_eq!(symbol.to_string(), "detour::trampoline_for_add5");
+//! # Ok(())
+//! # }
+//! # #[cfg(not(feature = "profiling"))]
+//! # fn main() {}
+//! ```
+
+#[cfg(feature = "profiling")]
+use crate::sync::Mutex;
+#[cfg(feature = "profiling")]
+use alloc::vec::Vec;
+#[cfg(feature = "profiling")]
+use core::fmt;
+
+#[cfg(feature = "gdb-jit")]
+mod gdb;
+
+/// The kind of generated code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodeKind {
+  /// A trampoline, executing the relocated prolog of a target.
+  Trampoline,
+  /// A relay, branching from a target to its detour (including the stubs of
+  /// a multi detour).
+  Relay,
+  /// A closure thunk, calling a closure through a function pointer.
+  Thunk,
+}
+
+#[cfg(feature = "profiling")]
+/// A symbol describing generated code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CodeSymbol {
+  /// Address of the code.
+  pub address: *const (),
+  /// Size of the code, including its padding.
+  pub size: usize,
+  /// The kind of code.
+  pub kind: CodeKind,
+  /// The target the code was generated for.
+  pub target: *const (),
+  /// The label assigned to the target, if any.
+  pub label: Option<&'static str>,
+}
+
+#[cfg(feature = "profiling")]
+unsafe impl Send for CodeSymbol {}
+#[cfg(feature = "profiling")]
+unsafe impl Sync for CodeSymbol {}
+
+#[cfg(feature = "profiling")]
+impl fmt::Display for CodeSymbol {
+  /// Outputs the symbol's name (e.g `detour::relay_for_0x7f3a2c001130`).
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let kind = match self.kind {
+      CodeKind::Trampoline => "trampoline",
+      CodeKind::Relay => "relay",
+      CodeKind::Thunk => "thunk",
+    };
+
+    match self.label {
+      Some(label) => write!(f, "detour::{}_for_{}", kind, label),
+      None => write!(f, "detour::{}_for_{:p}", kind, self.target),
+    }
+  }
+}
+
+#[cfg(feature = "profiling")]
+/// The symbols of all live code.
+static SYMBOLS: Mutex<Vec<CodeSymbol>> = Mutex::new(Vec::new());
+
+#[cfg(feature = "profiling")]
+/// The labels assigned to targets.
+static LABELS: Mutex<Vec<(usize, &'static str)>> = Mutex::new(Vec::new());
+
+#[cfg(feature = "profiling")]
+/// Assigns a label to a target, used to name its code.
+///
+/// The label applies to code generated for the target afterwards.
+pub fn set_label(target: *const (), label: &'static str) {
+  let mut labels = LABELS.lock();
+  labels.retain(|&(address, _)| address != target as usize);
+  labels.push((target as usize, label));
+}
+
+#[cfg(feature = "profiling")]
+/// Returns the symbols of all code that is currently allocated.
+pub fn symbols() -> Vec<CodeSymbol> {
+  SYMBOLS.lock().clone()
+}
+
+#[cfg(feature = "profiling")]
+/// Records the symbol of generated code.
+pub(crate) fn register(address: *const (), size: usize, kind: CodeKind, target: *const ()) {
+  let label = LABELS
+    .lock()
+    .iter()
+    .find(|&&(address, _)| address == target as usize)
+    .map(|&(_, label)| label);
+
+  let symbol = CodeSymbol {
+    address,
+    size,
+    kind,
+    target,
+    label,
+  };
+
+  #[cfg(feature = "gdb-jit")]
+  gdb::register(&symbol);
+
+  let mut symbols = SYMBOLS.lock();
+  symbols.push(symbol);
+
+  #[cfg(all(feature = "std", any(target_os = "linux", target_os = "android")))]
+  perf::append(&symbol);
+}
+
+#[cfg(not(feature = "profiling"))]
+pub(crate) fn register(_address: *const (), _size: usize, _kind: CodeKind, _target: *const ()) {}
+
+#[cfg(feature = "profiling")]
+/// Removes the symbol of released code, if any.
+pub(crate) fn unregister(address: *const ()) {
+  #[cfg(feature = "gdb-jit")]
+  gdb::unregister(address);
+
+  let mut symbols = SYMBOLS.lock();
+  if let Some(index) = symbols.iter().position(|symbol| symbol.address == address) {
+    symbols.swap_remove(index);
+  }
+}
+
+#[cfg(not(feature = "profiling"))]
+pub(crate) fn unregister(_address: *const ()) {}
+
+#[cfg(all(
+  feature = "profiling",
+  feature = "std",
+  any(target_os = "linux", target_os = "android")
+))]
+pub use self::perf::set_perf_map;
+
+#[cfg(all(
+  feature = "profiling",
+  feature = "std",
+  any(target_os = "linux", target_os = "android")
+))]
+mod perf {
+  use super::{CodeSymbol, SYMBOLS};
+  use crate::error::{Error, OsError, Result};
+  use crate::sync::Mutex;
+  use core::fmt::Write as _;
+  use std::fs::File;
+  use std::io::{self, Write as _};
+  use std::string::String;
+  use std::{format, process};
+
+  /// The perf map, whilst it's written.
+  static MAP: Mutex<Option<File>> = Mutex::new(None);
+
+  /// Enables or disables writing the perf map of the process.
+  ///
+  /// Once enabled, the map is recreated with the current symbols, and a line
+  /// is appended whenever code is generated. Disabling it leaves the map as
+  /// is, for profilers to consume once the process has exited.
+  pub fn set_perf_map(enabled: bool) -> Result<()> {
+    let symbols = SYMBOLS.lock();
+    let mut map = MAP.lock();
+    *map = None;
+
+    if enabled {
+      let mut lines = String::new();
+      symbols.iter().for_each(|symbol| line(&mut lines, symbol));
+
+      let mut file = File::create(format!("/tmp/perf-{}.map", process::id())).map_err(failure)?;
+      file.write_all(lines.as_bytes()).map_err(failure)?;
+      *map = Some(file);
+    }
+    Ok(())
+  }
+
+  /// Appends a symbol to the perf map, if enabled.
+  pub fn append(symbol: &CodeSymbol) {
+    if let Some(file) = MAP.lock().as_mut() {
+      let mut lines = String::new();
+      line(&mut lines, symbol);
+
+      // A single write keeps the line intact for concurrent readers, and a
+      // failure only affects profiling
+      let _ = file.write_all(lines.as_bytes());
+    }
+  }
+
+  /// Formats the line of a symbol (`START SIZE NAME`).
+  fn line(lines: &mut String, symbol: &CodeSymbol) {
+    let _ = writeln!(
+      lines,
+      "{:x} {:x} {}",
+      symbol.address as usize, symbol.size, symbol
+    );
+  }
+
+  /// Converts a failed write of the map.
+  fn failure(error: io::Error) -> Error {
+    Error::PermissionDenied {
+      operation: "write",
+      error: OsError(error.raw_os_error().unwrap_or(0)),
+    }
+  }
+}
@@ -0,0 +1,143 @@
+//! Synchronization primitives, independent of `std`.
+
+use crate::error::{Error, Result};
+use alloc::boxed::Box;
+use cfg_if::cfg_if;
+use core::sync::atomic::{AtomicPtr, Ordering};
+use core::{mem, ptr};
+
+cfg_if! {
+  if #[cfg(feature = "std")] {
+    pub use std::sync::MutexGuard;
+
+    /// A mutual exclusion primitive, using the operating system's mutex.
+    pub struct Mutex<T>(std::sync::Mutex<T>);
+
+    impl<T> Mutex<T> {
+      /// Creates a new mutex in an unlocked state.
+      pub const fn new(value: T) -> Self {
+        Mutex(std::sync::Mutex::new(value))
+      }
+
+      /// Acquires the mutex, blocking the current thread until it is able to.
+      pub fn lock(&self) -> MutexGuard<'_, T> {
+        self.0.lock().unwrap()
+      }
+
+      /// Acquires the mutex, unless it's already locked.
+      pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        self.0.try_lock().ok()
+      }
+    }
+  } else {
+    use core::cell::UnsafeCell;
+    use core::ops::{Deref, DerefMut};
+    use core::sync::atomic::AtomicBool;
+
+    /// A mutual exclusion primitive, implemented as a spin lock, relaxed by the
+    /// backend whilst contended.
+    pub struct Mutex<T> {
+      locked: AtomicBool,
+      value: UnsafeCell<T>,
+    }
+
+    unsafe impl<T: Send> Send for Mutex<T> {}
+    unsafe impl<T: Send> Sync for Mutex<T> {}
+
+    impl<T> Mutex<T> {
+      /// Creates a new mutex in an unlocked state.
+      pub const fn new(value: T) -> Self {
+        Mutex {
+          locked: AtomicBool::new(false),
+          value: UnsafeCell::new(value),
+        }
+      }
+
+      /// Acquires the mutex, spinning until it is able to.
+      pub fn lock(&self) -> MutexGuard<'_, T> {
+        while self
+          .locked
+          .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+          .is_err()
+        {
+          match crate::os::backend() {
+            Ok(backend) => backend.relax(),
+            Err(_) => core::hint::spin_loop(),
+          }
+        }
+
+        MutexGuard(self)
+      }
+
+      /// Acquires the mutex, unless it's already locked.
+      pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        self
+          .locked
+          .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+          .ok()
+          .map(|_| MutexGuard(self))
+      }
+    }
+
+    /// A scoped lock of a mutex; it's unlocked once dropped.
+    pub struct MutexGuard<'a, T>(&'a Mutex<T>);
+
+    impl<T> Deref for MutexGuard<'_, T> {
+      type Target = T;
+
+      fn deref(&self) -> &T {
+        unsafe { &*self.0.value.get() }
+      }
+    }
+
+    impl<T> DerefMut for MutexGuard<'_, T> {
+      fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.0.value.get() }
+      }
+    }
+
+    impl<T> Drop for MutexGuard<'_, T> {
+      fn drop(&mut self) {
+        self.0.locked.store(false, Ordering::Release);
+      }
+    }
+  }
+}
+
+/// A reference to a global, that can only be assigned once.
+pub struct Global<T: ?Sized + 'static>(AtomicPtr<&'static T>);
+
+impl<T: ?Sized + 'static> Global<T> {
+  /// Creates an unassigned global.
+  pub const fn new() -> Self {
+    Global(AtomicPtr::new(ptr::null_mut()))
+  }
+
+  /// Assigns the global, unless it has already been assigned.
+  pub fn set(&self, value: &'static T) -> Result<()> {
+    // The reference is boxed, since it may be a fat pointer
+    let value = Box::into_raw(Box::new(value));
+
+    self
+      .0
+      .compare_exchange(ptr::null_mut(), value, Ordering::SeqCst, Ordering::SeqCst)
+      .map(|_| ())
+      .map_err(|_| {
+        mem::drop(unsafe { Box::from_raw(value) });
+        Error::AlreadyInitialized
+      })
+  }
+
+  /// Returns the assigned global, if any.
+  pub fn get(&self) -> Option<&'static T> {
+    let value = self.0.load(Ordering::SeqCst);
+    unsafe { value.as_ref().copied() }
+  }
+
+  /// Returns the assigned global, assigning `default` if unassigned.
+  pub fn get_or_set(&self, default: &'static T) -> &'static T {
+    // Any concurrent assignment takes precedence
+    let _ = self.set(default);
+    self.get().unwrap()
+  }
+}
@@ -0,0 +1,272 @@
+//! Restoration of every enabled detour when the process exits or crashes.
+//!
+//! Detours left enabled whilst the process terminates may crash functions
+//! executed during its teardown (e.g `atexit` handlers, or `DllMain` upon
+//! `DLL_PROCESS_DETACH`), masking the actual cause, and leave the code of
+//! their targets modified within crash dumps. Once
+//! [installed](./fn.install.html), the original code of every enabled detour
+//! is restored:
+//!
+//! - When the process exits, using an `atexit` handler.
+//! - When the process crashes, using a handler for fatal signals (`SIGSEGV`,
+//!   `SIGBUS`, `SIGILL`, `SIGFPE` and `SIGABRT`) on Unix, or an unhandled
+//!   exception filter on Windows.
+//!
+//! The original code is restored on a best-effort basis, without locking or
+//! allocating (i.e it's async-signal-safe), most recently created detour
+//! first. The handlers are chained to those installed beforehand, which are
+//! invoked once the code has been restored (e.g a crash reporter writing a
+//! dump). Handlers installed afterwards are invoked before the code is
+//! restored; since breakpoint, hardware breakpoint and page guard detours
+//! handle signals (or exceptions) of their own, the handlers should be
+//! installed before any of them is created.
+//!
+//! The detours are not disabled; their targets are merely restored, and must
+//! not be relied upon afterwards.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use detour::Result;
+//! use detour::{teardown, RawDetour};
+//!
+//! #[inline(never)]
+//! extern "C" fn add5(val: i32) -> i32 {
+//!   unsafe { std::ptr::read_volatile(&val) + 5 }
+//! }
+//!
+//! extern "C" fn add10(val: i32) -> i32 {
+//!   val + 10
+//! }
+//!
+//! # fn main() -> Result<()> {
+//! teardown::install()?;
+//!
+//! let hook = unsafe { RawDetour::new(add5 as *const (), add10 as *const ())? };
+//! unsafe { hook.enable()? };
+//!
+//! // The detour may remain enabled until the process exits
+//! std::mem::forget(hook);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::arch::registry;
+use crate::error::Result;
+use crate::os;
+use crate::sync::Mutex;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Whether the handlers have been installed.
+static INSTALLED: Mutex<bool> = Mutex::new(false);
+
+/// The page size, read before the handlers are installed.
+static PAGE_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+/// Installs the handlers, for the remainder of the process.
+///
+/// Installing them more than once has no effect.
+pub fn install() -> Result<()> {
+  let mut installed = INSTALLED.lock();
+  if !*installed {
+    PAGE_SIZE.store(os::backend()?.page_size(), Ordering::SeqCst);
+    unsafe { platform::install()? };
+    *installed = true;
+  }
+  Ok(())
+}
+
+/// Returns whether the handlers have been installed.
+pub fn is_installed() -> bool {
+  *INSTALLED.lock()
+}
+
+/// Restores the original code of every enabled detour, most recently created
+/// first, without locking or allocating.
+///
+/// This is invoked by the handlers, and may be invoked by any other handler
+/// (e.g a crash reporter's) once the handlers are installed. The detours
+/// remain enabled, and must not be used afterwards.
+pub unsafe fn restore_all() {
+  if PAGE_SIZE.load(Ordering::SeqCst) == 0 {
+    return;
+  }
+
+  // Detours sharing a target restore the code each one replaced in turn
+  let mut bound = usize::MAX;
+  while let Some(entry) = registry::entries()
+    .filter(|entry| entry.sequence < bound)
+    .max_by_key(|entry| entry.sequence)
+  {
+    bound = entry.sequence;
+    if let Some((address, code)) = (*entry.patch).original_raw() {
+      platform::write(address, code);
+    }
+  }
+}
+
+#[cfg(unix)]
+mod platform {
+  use super::{restore_all, PAGE_SIZE};
+  use crate::error::Result;
+  use crate::os::{self, primitives};
+  use core::cell::UnsafeCell;
+  use core::mem::{self, MaybeUninit};
+  use core::ptr;
+  use core::sync::atomic::Ordering;
+  use libc::{c_int, c_void, siginfo_t};
+
+  /// A signal action replaced by the handler.
+  struct Previous(UnsafeCell<MaybeUninit<libc::sigaction>>);
+
+  unsafe impl Sync for Previous {}
+
+  impl Previous {
+    const fn new() -> Self {
+      Previous(UnsafeCell::new(MaybeUninit::uninit()))
+    }
+  }
+
+  /// The fatal signals, along with the actions replaced by the handler (only
+  /// written before it's installed).
+  static SIGNALS: [(c_int, Previous); 5] = [
+    (libc::SIGSEGV, Previous::new()),
+    (libc::SIGBUS, Previous::new()),
+    (libc::SIGILL, Previous::new()),
+    (libc::SIGFPE, Previous::new()),
+    (libc::SIGABRT, Previous::new()),
+  ];
+
+  /// Installs the signal and exit handlers.
+  pub unsafe fn install() -> Result<()> {
+    let mut action: libc::sigaction = mem::zeroed();
+    action.sa_sigaction = handler as *const () as usize;
+    action.sa_flags = libc::SA_SIGINFO | libc::SA_ONSTACK;
+    libc::sigemptyset(&mut action.sa_mask);
+
+    for (signal, previous) in SIGNALS.iter() {
+      // The arguments are valid, so this cannot fail
+      let result = libc::sigaction(*signal, &action, (*previous.0.get()).as_mut_ptr());
+      debug_assert_eq!(result, 0);
+    }
+
+    libc::atexit(on_exit);
+    Ok(())
+  }
+
+  extern "C" fn on_exit() {
+    unsafe { restore_all() };
+  }
+
+  /// Restores the targets, and forwards the signal to the previous action.
+  unsafe extern "C" fn handler(signal: c_int, info: *mut siginfo_t, context: *mut c_void) {
+    restore_all();
+
+    let previous = match SIGNALS.iter().find(|(handled, _)| *handled == signal) {
+      Some((_, previous)) => &*(*previous.0.get()).as_ptr(),
+      None => return,
+    };
+
+    match previous.sa_sigaction {
+      libc::SIG_IGN => (),
+      libc::SIG_DFL => {
+        // Terminate the process as if no handler was installed; the signal is
+        // delivered once the handler returns
+        libc::sigaction(signal, previous, ptr::null_mut());
+        libc::raise(signal);
+      },
+      action if previous.sa_flags & libc::SA_SIGINFO != 0 => {
+        let action: extern "C" fn(c_int, *mut siginfo_t, *mut c_void) = mem::transmute(action);
+        action(signal, info, context);
+      },
+      action => {
+        let action: extern "C" fn(c_int) = mem::transmute(action);
+        action(signal);
+      },
+    }
+  }
+
+  /// Writes code, using system calls only.
+  pub unsafe fn write(address: *const (), code: &[u8]) {
+    let pages = os::page_range(
+      address as usize,
+      code.len(),
+      PAGE_SIZE.load(Ordering::SeqCst),
+    );
+    let (base, size) = (pages.start as *mut c_void, pages.end - pages.start);
+
+    // A detoured `mprotect` is called through its trampoline
+    if primitives::mprotect(
+      base,
+      size,
+      libc::PROT_READ | libc::PROT_WRITE | libc::PROT_EXEC,
+    ) == 0
+    {
+      ptr::copy_nonoverlapping(code.as_ptr(), address as *mut u8, code.len());
+      primitives::mprotect(base, size, libc::PROT_READ | libc::PROT_EXEC);
+    }
+  }
+}
+
+#[cfg(windows)]
+mod platform {
+  use super::restore_all;
+  use crate::error::Result;
+  use core::mem;
+  use core::ptr;
+  use core::sync::atomic::{AtomicUsize, Ordering};
+  use winapi::shared::minwindef::FALSE;
+  use winapi::um::errhandlingapi::SetUnhandledExceptionFilter;
+  use winapi::um::memoryapi::VirtualProtect;
+  use winapi::um::processthreadsapi::{FlushInstructionCache, GetCurrentProcess};
+  use winapi::um::winnt::{EXCEPTION_POINTERS, LONG, PAGE_EXECUTE_READWRITE};
+  use winapi::vc::excpt::EXCEPTION_CONTINUE_SEARCH;
+
+  type FnFilter = unsafe extern "system" fn(*mut EXCEPTION_POINTERS) -> LONG;
+
+  /// The filter replaced by the handler, or zero if there was none.
+  static PREVIOUS: AtomicUsize = AtomicUsize::new(0);
+
+  /// Installs the unhandled exception filter and exit handler.
+  pub unsafe fn install() -> Result<()> {
+    let previous = SetUnhandledExceptionFilter(Some(filter));
+    PREVIOUS.store(
+      previous.map_or(0, |filter| filter as usize),
+      Ordering::SeqCst,
+    );
+    libc::atexit(on_exit);
+    Ok(())
+  }
+
+  extern "C" fn on_exit() {
+    unsafe { restore_all() };
+  }
+
+  /// Restores the targets, and forwards the exception to the previous filter.
+  unsafe extern "system" fn filter(info: *mut EXCEPTION_POINTERS) -> LONG {
+    restore_all();
+
+    match PREVIOUS.load(Ordering::SeqCst) {
+      0 => EXCEPTION_CONTINUE_SEARCH,
+      previous => mem::transmute::<usize, FnFilter>(previous)(info),
+    }
+  }
+
+  /// Writes code, using system calls only.
+  pub unsafe fn write(address: *const (), code: &[u8]) {
+    let mut protection = 0;
+    let size = code.len();
+
+    if VirtualProtect(
+      address as *mut _,
+      size,
+      PAGE_EXECUTE_READWRITE,
+      &mut protection,
+    ) != FALSE
+    {
+      ptr::copy_nonoverlapping(code.as_ptr(), address as *mut u8, size);
+      VirtualProtect(address as *mut _, size, protection, &mut protection);
+      FlushInstructionCache(GetCurrentProcess(), address as *const _, size);
+    }
+  }
+}
@@ -0,0 +1,212 @@
+//! Fixture functions for testing detours.
+//!
+//! Each fixture is a function with a distinctive prolog shape, returning a
+//! distinctive value. The fixtures are written in assembly, so their prologs
+//! are stable regardless of the compiler and its optimizations, and they're
+//! updated alongside the library whenever a new prolog shape is supported.
+//!
+//! [assert_hook_roundtrip](./fn.assert_hook_roundtrip.html) detours a
+//! fixture to [ret10](./fn.ret10.html), and verifies the fixture, the detour
+//! and the trampoline throughout enabling and disabling it.
+//!
+//! # Example
+//!
+//! ```rust
+//! # fn main() -> detour::Result<()> {
+//! use detour::testing;
+//!
+//! unsafe {
+//!   testing::assert_hook_roundtrip(testing::hotpatch_ret0(), 0)?;
+//!   testing::assert_hook_roundtrip(testing::short_branch_ret7, 7)?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+use crate::error::Result;
+use crate::RawDetour;
+use core::arch::naked_asm;
+use core::mem;
+
+/// The type of every fixture.
+pub type Fixture = unsafe extern "C" fn() -> i32;
+
+/// Detours a fixture to [ret10](./fn.ret10.html), and asserts its return
+/// value before, whilst and after it's detoured.
+///
+/// The trampoline must return the original value whilst the detour is
+/// enabled. Panics if any value differs, and returns any error of the detour.
+///
+/// # Safety
+///
+/// The target must be a function with the fixture's signature (e.g one of
+/// this module's fixtures), and `expected` must not be 10.
+#[inline(never)]
+pub unsafe fn assert_hook_roundtrip(target: Fixture, expected: i32) -> Result<()> {
+  let hook = RawDetour::new(target as *const (), ret10 as *const ())?;
+
+  assert_eq!(target(), expected);
+  hook.enable()?;
+  {
+    assert_eq!(target(), 10);
+    let original: Fixture = mem::transmute(hook.trampoline());
+    assert_eq!(original(), expected);
+  }
+  hook.disable()?;
+  assert_eq!(target(), expected);
+  Ok(())
+}
+
+/// Returns 10; the detour used by
+/// [assert_hook_roundtrip](./fn.assert_hook_roundtrip.html).
+pub unsafe extern "C" fn ret10() -> i32 {
+  10
+}
+
+/// Returns 5, with a five byte prolog (`mov eax, 5`).
+#[unsafe(naked)]
+pub unsafe extern "C" fn ret5() -> i32 {
+  naked_asm!(
+    "
+        mov eax, 5
+        ret"
+  )
+}
+
+/// Returns a function returning 0, whose body (`xor eax, eax; ret`) is too
+/// small for a relative jump, preceded by five NOPs (i.e it's hot patched).
+pub fn hotpatch_ret0() -> Fixture {
+  #[unsafe(naked)]
+  unsafe extern "C" fn padded() -> i32 {
+    naked_asm!(
+      "
+          nop
+          nop
+          nop
+          nop
+          nop
+          xor eax, eax
+          ret
+          mov eax, 5"
+    )
+  }
+
+  unsafe { mem::transmute::<usize, Fixture>(padded as *const () as usize + 5) }
+}
+
+/// Returns 7, with a short conditional jump within its prolog, whose
+/// destination is beyond it (i.e the jump is widened when relocated).
+#[unsafe(naked)]
+pub unsafe extern "C" fn short_branch_ret7() -> i32 {
+  naked_asm!(
+    "
+        xor eax, eax
+        jz 2f
+        nop
+        nop
+        nop
+        nop
+        nop
+        nop
+        nop
+        nop
+    2:
+        mov al, 7
+        ret"
+  )
+}
+
+/// Returns 195, with a RIP relative operand within its prolog referring to
+/// code after it (x64 only).
+#[cfg(target_arch = "x86_64")]
+#[unsafe(naked)]
+pub unsafe extern "C" fn rip_relative_ret195() -> i32 {
+  naked_asm!(
+    "
+        xor eax, eax
+        mov al, [rip+0x3]
+        nop
+        nop
+        nop
+        ret"
+  )
+}
+
+/// Returns 7, comparing a RIP relative operand with an immediate, which
+/// follows its displacement (x64 only).
+#[cfg(target_arch = "x86_64")]
+#[unsafe(naked)]
+pub unsafe extern "C" fn rip_relative_immediate_ret7() -> i32 {
+  naked_asm!(
+    "
+        xor eax, eax
+        cmp dword ptr [rip+2f], 5
+        jne 1f
+        mov eax, 7
+    1:
+        ret
+    2:
+        .long 5"
+  )
+}
+
+/// Returns 49, with a RIP relative operand referring to the prolog itself
+/// (x64 only).
+#[cfg(target_arch = "x86_64")]
+#[unsafe(naked)]
+pub unsafe extern "C" fn rip_relative_prolog_ret49() -> i32 {
+  naked_asm!(
+    "
+        xor eax, eax
+        mov al, [rip-0x8]
+        ret"
+  )
+}
+
+/// Returns 3, starting with a five byte NOP (`nop dword ptr [rax+rax]`), such
+/// as a pad emitted by `patchable-function-entry` (i.e it's patched in place).
+#[unsafe(naked)]
+pub unsafe extern "C" fn nop_pad_ret3() -> i32 {
+  naked_asm!(
+    "
+        .byte 0x0f, 0x1f, 0x44, 0x00, 0x00
+        mov eax, 3
+        ret"
+  )
+}
+
+/// Returns 4, starting with a three byte NOP (`nop dword ptr [rax]`) too
+/// small for a patch, which is not relocated.
+#[unsafe(naked)]
+pub unsafe extern "C" fn nop_prefix_ret4() -> i32 {
+  naked_asm!(
+    "
+        .byte 0x0f, 0x1f, 0x00
+        mov eax, 4
+        ret"
+  )
+}
+
+/// Returns 11, starting with a long jump to its hot part located elsewhere,
+/// such as a function split by PGO or BOLT (i.e the jump is relocated).
+#[unsafe(naked)]
+pub unsafe extern "C" fn split_ret11() -> i32 {
+  naked_asm!(
+    "
+        .byte 0xe9
+        .long {hot} - 2f
+    2:
+        int3
+        int3",
+    hot = sym split_ret11_hot,
+  )
+}
+
+/// The hot part of [split_ret11](./fn.split_ret11.html).
+#[unsafe(naked)]
+unsafe extern "C" fn split_ret11_hot() -> i32 {
+  naked_asm!(
+    "
+        mov eax, 11
+        ret"
+  )
+}
@@ -0,0 +1,224 @@
+//! Function pointers calling closures, generated at runtime.
+//!
+//! A callback without a user data parameter (e.g one passed to a hooked
+//! library) cannot be implemented by a closure, since a function pointer
+//! carries no state. A [ClosureThunk](./struct.ClosureThunk.html) is a small
+//! stub embedding the closure's data pointer: it passes the pointer as an
+//! additional, last argument to a function, monomorphized for the closure,
+//! that invokes it.
+//!
+//! If the calling convention passes this argument in a register, the stub
+//! loads it and jumps to the function. Otherwise (e.g with more than six
+//! integer arguments on x64 Linux), the stub copies the stack arguments to a
+//! frame of its own, which has no unwind information: unwinding (or SEH)
+//! through a closure with stack arguments is not supported.
+//!
+//! Only the C-compatible calling conventions are supported, and only with
+//! [scalar](./trait.Scalar.html) parameters (e.g integers, floats and
+//! pointers), since the convention's handling of aggregates is not known.
+//! Closures with other parameters cannot be used, and other calling
+//! conventions return `Error::UnsupportedSignature`.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use detour::Result;
+//! use detour::thunk::ClosureThunk;
+//! use std::sync::atomic::{AtomicI32, Ordering};
+//! use std::sync::Arc;
+//!
+//! type Callback = extern "C" fn(i32) -> i32;
+//!
+//! /// A library API accepting a bare callback.
+//! extern "C" fn apply(callback: Callback, value: i32) -> i32 {
+//!   callback(value)
+//! }
+//!
+//! # fn main() -> Result<()> {
+//! let calls = Arc::new(AtomicI32::new(0));
+//! let counter = calls.clone();
+//! let thunk = ClosureThunk::<Callback>::new(move |value: i32| {
+//!   counter.fetch_add(1, Ordering::SeqCst);
+//!   value * 2
+//! })?;
+//!
+//! assert_eq!(apply(thunk.as_fn(), 21), 42);
+//! assert_eq!(calls.load(Ordering::SeqCst), 1);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::arch::{self, memory};
+use crate::error::Result;
+use crate::profiling::CodeKind;
+use crate::{pool, Function, Signature};
+use alloc::boxed::Box;
+use alloc::string::ToString;
+use core::any::Any;
+use core::ptr::NonNull;
+use core::{fmt, mem};
+
+/// Trait representing a closure that can be called through a
+/// [ClosureThunk](./struct.ClosureThunk.html) with the type `T`.
+///
+/// It is automatically implemented for closures with the same prototype as
+/// `T`, if all of its parameters are [scalars](./trait.Scalar.html). Since a
+/// thunk may be called from any thread, the closure must be both `Send` and
+/// `Sync`.
+pub trait ThunkClosure<T: Function>: Send + Sync + 'static {
+  /// Returns the function invoking the closure, passed as an additional,
+  /// last argument.
+  #[doc(hidden)]
+  fn shim() -> *const ();
+
+  /// Returns the arguments of `T`, followed by its result.
+  #[doc(hidden)]
+  fn parameters() -> Box<[Parameter]>;
+}
+
+/// A type that may be passed to, or returned from, a closure thunk.
+///
+/// It's implemented for integers of up to 64 bits, `bool`, `char`, `f32`,
+/// `f64`, `()`, and pointers to sized types (raw pointers, references and
+/// `NonNull`), whose location is determined by the calling convention alone.
+/// It cannot be implemented outside of this crate, since aggregates (e.g
+/// `#[repr(C)]` structures, or even a `#[repr(transparent)]` float) may be
+/// passed elsewhere.
+///
+/// ```rust,compile_fail
+/// use detour::thunk::ClosureThunk;
+///
+/// #[repr(C)]
+/// struct Point {
+///   x: f32,
+///   y: f32,
+/// }
+///
+/// let thunk = ClosureThunk::<extern "C" fn(Point)>::new(|_: Point| ());
+/// ```
+pub trait Scalar: private::Sealed + 'static {
+  /// Whether the type is a floating-point number.
+  #[doc(hidden)]
+  const FLOAT: bool;
+}
+
+mod private {
+  pub trait Sealed {}
+}
+
+macro_rules! impl_scalar {
+  ($float:literal: $($ty:ty),*) => {
+    $(
+      impl private::Sealed for $ty {}
+      impl Scalar for $ty {
+        const FLOAT: bool = $float;
+      }
+    )*
+  };
+}
+
+impl_scalar!(false: (), bool, char, u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+impl_scalar!(true: f32, f64);
+
+macro_rules! impl_scalar_pointer {
+  ($($ty:ty),*) => {
+    $(
+      impl<T: 'static> private::Sealed for $ty {}
+      impl<T: 'static> Scalar for $ty {
+        const FLOAT: bool = false;
+      }
+    )*
+  };
+}
+
+impl_scalar_pointer!(
+  *const T,
+  *mut T,
+  &'static T,
+  &'static mut T,
+  NonNull<T>,
+  Option<&'static T>,
+  Option<&'static mut T>,
+  Option<NonNull<T>>
+);
+
+/// A parameter of a closure thunk's function type.
+#[doc(hidden)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Parameter {
+  /// The size of the parameter's type.
+  pub size: usize,
+  /// Whether the type is a floating-point number.
+  pub float: bool,
+}
+
+impl Parameter {
+  /// Returns the parameter of a type.
+  pub fn of<T: Scalar>() -> Self {
+    Parameter {
+      size: mem::size_of::<T>(),
+      float: T::FLOAT,
+    }
+  }
+}
+
+/// A function pointer of the type `T`, calling a closure.
+///
+/// The closure is kept alive alongside the thunk's code, and both are released
+/// once the thunk is dropped; the code is returned to the
+/// [pool](../pool/index.html), subject to its reclamation policy. The function
+/// pointer must therefore not be called once the thunk has been dropped (e.g
+/// it should be unregistered from the library it was passed to beforehand).
+pub struct ClosureThunk<T: Function> {
+  function: T,
+  #[allow(dead_code)]
+  memory: pool::ExecutableMemory,
+  #[allow(dead_code)]
+  closure: Box<dyn Any + Send + Sync>,
+}
+
+impl<T: Function> ClosureThunk<T> {
+  /// Allocates a thunk calling a closure.
+  ///
+  /// Returns `Error::UnsupportedSignature` if the calling convention of `T`,
+  /// or any of its parameters, is not supported.
+  pub fn new<F: ThunkClosure<T>>(closure: F) -> Result<Self> {
+    let shim = F::shim();
+    let closure = Box::new(closure);
+    let context = &*closure as *const F as usize;
+
+    let emitter = arch::meta::closure_entry(shim, context, T::ABI, &F::parameters(), || {
+      Signature::of::<T>().to_string()
+    })?;
+
+    let memory = {
+      let _guard = memory::LOCK.lock();
+      memory::allocate_pic(&emitter, shim, CodeKind::Thunk)?
+    };
+
+    Ok(ClosureThunk {
+      function: unsafe { T::from_ptr(memory.as_ptr() as *const ()) },
+      memory,
+      closure,
+    })
+  }
+
+  /// Returns the function pointer calling the closure.
+  pub fn as_fn(&self) -> T {
+    self.function
+  }
+
+  /// Returns the address of the thunk's code.
+  pub fn as_ptr(&self) -> *const () {
+    self.function.to_ptr()
+  }
+}
+
+impl<T: Function> fmt::Debug for ClosureThunk<T> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "ClosureThunk {{ address: {:p} }}", self.as_ptr())
+  }
+}
+
+unsafe impl<T: Function> Send for ClosureThunk<T> {}
+unsafe impl<T: Function> Sync for ClosureThunk<T> {}
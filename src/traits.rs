@@ -3,8 +3,51 @@
 //! Several of the traits in this module are automatically implemented and
 //! should generally not be implemented by users of this library.
 
+use crate::sync::Mutex;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::any::TypeId;
+use core::fmt;
+
 /// Trait representing a function that can be used as a target or detour for
 /// detouring.
+///
+/// It is implemented for safe & unsafe function pointers of the Rust, `C` and
+/// `system` calling conventions. Architecture specific conventions are
+/// implemented where they are available; `cdecl`, `stdcall`, `fastcall` &
+/// `thiscall` on x86, and the explicit `win64` & `sysv64` ABIs on x86-64.
+/// Functions with up to 26 arguments are supported.
+///
+/// Never-returning functions (e.g `extern "C" fn(i32) -> !`) are supported as
+/// well, since their return type is `!`; calling the original function of
+/// such a detour (e.g before terminating the process) never returns either.
+///
+/// # Example
+///
+/// The explicit 64-bit ABIs can be detoured regardless of the host's
+/// default calling convention:
+///
+/// ```rust
+/// # #[cfg(target_arch = "x86_64")]
+/// # fn main() -> detour::Result<()> {
+/// use detour::GenericDetour;
+///
+/// #[inline(never)]
+/// extern "sysv64" fn add(x: u64, y: u64) -> u64 {
+///   x + y
+/// }
+///
+/// extern "sysv64" fn sub(x: u64, y: u64) -> u64 {
+///   x - y
+/// }
+///
+/// let hook = unsafe { GenericDetour::<extern "sysv64" fn(u64, u64) -> u64>::new(add, sub)? };
+/// assert_eq!(hook.call(10, 5), 15);
+/// # Ok(())
+/// # }
+/// # #[cfg(not(target_arch = "x86_64"))]
+/// # fn main() {}
+/// ```
 pub unsafe trait Function: Sized + Copy + Sync + 'static {
   /// The argument types as a tuple.
   type Arguments;
@@ -12,19 +55,338 @@ pub unsafe trait Function: Sized + Copy + Sync + 'static {
   /// The return type.
   type Output;
 
+  /// The number of arguments (excluding those of a variadic function).
+  const ARITY: usize;
+
+  /// The calling convention, as written within `extern` (`Rust` if omitted).
+  const ABI: &'static str;
+
+  /// Returns the names of the argument types, followed by the return type's.
+  ///
+  /// The names are those of `core::any::type_name`, therefore they're only
+  /// meant for diagnostics.
+  fn type_names() -> &'static [&'static str];
+
   /// Constructs a `Function` from an untyped pointer.
   unsafe fn from_ptr(ptr: *const ()) -> Self;
 
   /// Returns an untyped pointer for this function.
   fn to_ptr(&self) -> *const ();
+
+  /// Calls the function, with its arguments as a tuple.
+  #[doc(hidden)]
+  unsafe fn call_tuple(self, arguments: Self::Arguments) -> Self::Output;
+}
+
+/// The signature of a function type, described at runtime for diagnostics.
+///
+/// # Example
+///
+/// ```rust
+/// use detour::{Function, Signature};
+///
+/// type FnRead = extern "C" fn(i32, *const u8, usize) -> isize;
+///
+/// let signature = Signature::of::<FnRead>();
+/// assert_eq!(signature.arity, FnRead::ARITY);
+/// assert_eq!(signature.abi, "C");
+/// assert_eq!(signature.arguments(), ["i32", "*const u8", "usize"]);
+/// assert_eq!(signature.output(), "isize");
+/// assert_eq!(
+///   signature.to_string(),
+///   r#"extern "C" fn(i32, *const u8, usize) -> isize"#
+/// );
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Signature {
+  /// The number of arguments.
+  pub arity: usize,
+  /// The calling convention.
+  pub abi: &'static str,
+  /// The names of the argument types, followed by the return type's.
+  pub type_names: &'static [&'static str],
+}
+
+impl Signature {
+  /// Returns the signature of a function type.
+  pub fn of<T: Function>() -> Self {
+    Signature {
+      arity: T::ARITY,
+      abi: T::ABI,
+      type_names: T::type_names(),
+    }
+  }
+
+  /// Returns the names of the argument types.
+  pub fn arguments(&self) -> &'static [&'static str] {
+    &self.type_names[..self.arity]
+  }
+
+  /// Returns the name of the return type.
+  pub fn output(&self) -> &'static str {
+    self.type_names[self.arity]
+  }
+}
+
+impl fmt::Display for Signature {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    if self.abi != "Rust" {
+      write!(f, "extern {:?} ", self.abi)?;
+    }
+
+    write!(f, "fn(")?;
+    for (index, name) in self.arguments().iter().enumerate() {
+      if index > 0 {
+        write!(f, ", ")?;
+      }
+      write!(f, "{}", name)?;
+    }
+    write!(f, ")")?;
+
+    match self.output() {
+      "()" => Ok(()),
+      output => write!(f, " -> {}", output),
+    }
+  }
+}
+
+/// Returns the type names of a function type, leaked once per type.
+#[doc(hidden)]
+pub fn __type_names<T: 'static>(names: &[&'static str]) -> &'static [&'static str] {
+  static CACHE: Mutex<Vec<(TypeId, &'static [&'static str])>> = Mutex::new(Vec::new());
+
+  let id = TypeId::of::<T>();
+  let mut cache = CACHE.lock();
+  match cache.iter().find(|(other, _)| *other == id) {
+    Some(&(_, cached)) => cached,
+    None => {
+      let leaked: &'static [&'static str] = Box::leak(names.to_vec().into_boxed_slice());
+      cache.push((id, leaked));
+      leaked
+    },
+  }
+}
+
+/// Trait representing a variadic C function (e.g `printf`).
+///
+/// The [Function](./trait.Function.html) implementation of a variadic function
+/// only describes its fixed arguments.
+pub unsafe trait VariadicFunction: Function {}
+
+/// Trait representing a function that can be the target of a
+/// [StaticDetour](./struct.StaticDetour.html).
+///
+/// With the *nightly* feature, it is implemented for every
+/// [Function](./trait.Function.html).
+#[cfg(feature = "nightly")]
+pub trait StaticFunction: Function {}
+
+#[cfg(feature = "nightly")]
+impl<T: Function> StaticFunction for T {}
+
+/// Trait representing a function that can be the target of a
+/// [StaticDetour](./struct.StaticDetour.html).
+///
+/// Without the *nightly* feature, a closure cannot be bound by
+/// `Fn<T::Arguments>`, so the closure type is named by this trait instead. It
+/// is implemented for function pointers and types declared using
+/// [function_type!](./macro.function_type.html); other implementations of
+/// [Function](./trait.Function.html) must implement it (along with
+/// [StaticClosure](./trait.StaticClosure.html)) to be detoured statically.
+#[cfg(not(feature = "nightly"))]
+pub trait StaticFunction: Function {
+  /// A closure type with the same prototype (e.g `dyn Fn(i32) -> i32 + Send`).
+  type Closure: ?Sized + Send + 'static;
+}
+
+/// Trait representing a closure that can be used as the detour of a
+/// [StaticDetour](./struct.StaticDetour.html) with the type `T`.
+///
+/// It is automatically implemented for closures with the same prototype as
+/// `T`, and is required when the *nightly* feature is disabled (since the
+/// closure cannot be bound by `Fn<T::Arguments>` on stable).
+#[cfg(not(feature = "nightly"))]
+pub trait StaticClosure<T: StaticFunction>: Send + 'static {
+  /// Converts the closure into a boxed trait object.
+  #[doc(hidden)]
+  fn into_boxed(self) -> Box<T::Closure>;
+
+  /// Returns the closure as a function pointer, if it is one (dispatched
+  /// without the boxed trait object).
+  #[doc(hidden)]
+  fn to_function(&self) -> Option<*const ()> {
+    None
+  }
 }
 
 /// Trait indicating that `Self` can be detoured by the given function `D`.
+///
+/// It is implemented for every function with itself, and for every unsafe
+/// function with its safe counterpart, since a safe function satisfies the
+/// contract of an unsafe one. Since either may detour an unsafe function, a
+/// function item must be cast to the safe function pointer type. A safe
+/// function cannot be detoured by an unsafe one:
+///
+/// ```rust,compile_fail
+/// # use detour::GenericDetour;
+/// extern "C" fn target(value: i32) -> i32 { value }
+/// unsafe extern "C" fn detour(value: i32) -> i32 { value + 1 }
+///
+/// let detour = detour as unsafe extern "C" fn(i32) -> i32;
+/// let _ = unsafe { GenericDetour::<extern "C" fn(i32) -> i32>::new(target, detour) };
+/// ```
+///
+/// # Example
+///
+/// ```rust
+/// # use detour::Result;
+/// use detour::GenericDetour;
+///
+/// #[inline(never)]
+/// unsafe extern "C" fn target(value: *const i32) -> i32 {
+///   std::ptr::read_volatile(value)
+/// }
+///
+/// extern "C" fn detour(_: *const i32) -> i32 {
+///   0
+/// }
+///
+/// # fn main() -> Result<()> {
+/// type FnTarget = unsafe extern "C" fn(*const i32) -> i32;
+/// type FnDetour = extern "C" fn(*const i32) -> i32;
+/// let hook = unsafe { GenericDetour::<FnTarget>::new(target, detour as FnDetour)? };
+///
+/// unsafe { hook.enable()? };
+/// assert_eq!(unsafe { target(&5) }, 0);
+/// # Ok(())
+/// # }
+/// ```
 pub unsafe trait HookableWith<D: Function>: Function {}
 
 unsafe impl<T: Function> HookableWith<T> for T {}
 
+/// Trait indicating that `Self` can detour the function `T` despite a
+/// different signature, since each of its types is
+/// [AbiCompatible](./trait.AbiCompatible.html) with that of `T`.
+///
+/// See [new_compatible](./struct.GenericDetour.html#method.new_compatible).
+///
+/// It is automatically implemented for functions of the same calling
+/// convention and arity, whose argument and return types are compatible with
+/// those of `T`. [HookableWith](./trait.HookableWith.html) remains strict, so
+/// a detour with a different signature is rejected:
+///
+/// ```rust,compile_fail
+/// # use detour::GenericDetour;
+/// #[repr(transparent)]
+/// struct Handle(u32);
+///
+/// extern "C" fn close(handle: u32) -> u32 { handle }
+/// extern "C" fn close_detour(handle: Handle) -> u32 { handle.0 }
+///
+/// let detour = close_detour as extern "C" fn(Handle) -> u32;
+/// let _ = unsafe { GenericDetour::<extern "C" fn(u32) -> u32>::new(close, detour) };
+/// ```
+///
+/// Unless each of its types is opted in:
+///
+/// ```rust,compile_fail
+/// # use detour::GenericDetour;
+/// #[repr(transparent)]
+/// struct Handle(u32);
+///
+/// extern "C" fn close(handle: u32) -> u32 { handle }
+/// extern "C" fn close_detour(handle: Handle) -> u32 { handle.0 }
+///
+/// let detour = close_detour as extern "C" fn(Handle) -> u32;
+/// let _ = unsafe { GenericDetour::<extern "C" fn(u32) -> u32>::new_compatible(close, detour) };
+/// ```
+pub unsafe trait HookCompatible<T: Function>: Function {}
+
+/// Trait asserting that a value of `Self` is passed and returned identically
+/// to a value of `T`, so a detour may use `Self` where its target uses `T`
+/// (see [HookCompatible](./trait.HookCompatible.html)).
+///
+/// It is implemented for the primitive types with themselves, and between raw
+/// pointers to sized types (e.g `*mut c_void` and `*mut Context`) and
+/// optional function pointers. It must be implemented for any other type
+/// (e.g a `#[repr(transparent)]` wrapper), including for itself if it's used
+/// by both functions.
+///
+/// # Safety
+///
+/// Both types must have the same size, alignment and ABI class, and any value
+/// of `T` must be a valid value of `Self`.
+///
+/// # Example
+///
+/// ```rust
+/// # use detour::Result;
+/// use detour::{AbiCompatible, GenericDetour};
+///
+/// #[repr(transparent)]
+/// struct Handle(u32);
+///
+/// unsafe impl AbiCompatible<u32> for Handle {}
+///
+/// #[inline(never)]
+/// extern "C" fn close(handle: u32) -> u32 {
+///   unsafe { std::ptr::read_volatile(&handle) }
+/// }
+///
+/// extern "C" fn close_detour(handle: Handle) -> u32 {
+///   handle.0 + 1
+/// }
+///
+/// # fn main() -> Result<()> {
+/// let detour = close_detour as extern "C" fn(Handle) -> u32;
+/// let hook = unsafe { GenericDetour::<extern "C" fn(u32) -> u32>::new_compatible(close, detour)? };
+/// unsafe { hook.enable()? };
+/// assert_eq!(close(1), 2);
+/// # Ok(())
+/// # }
+/// ```
+pub unsafe trait AbiCompatible<T> {}
+
+macro_rules! impl_abi_compatible {
+  (@primitive $($ty:ty),*) => {
+    $(unsafe impl AbiCompatible<$ty> for $ty {})*
+  };
+  (@pointer $($source:ty => $($target:ty),*;)*) => {
+    $($(unsafe impl<T, U> AbiCompatible<$target> for $source {})*)*
+  };
+  (@function $($source:ty => $($target:ty),*;)*) => {
+    $($(unsafe impl<F: Function, T> AbiCompatible<$target> for $source {})*)*
+  };
+}
+
+impl_abi_compatible! { @primitive
+  (), bool, char, f32, f64, i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize
+}
+
+impl_abi_compatible! { @pointer
+  *const T => *const U, *mut U;
+  *mut T => *const U, *mut U;
+}
+
+impl_abi_compatible! { @function
+  Option<F> => *const T, *mut T;
+  *const T => Option<F>;
+  *mut T => Option<F>;
+}
+
+unsafe impl<F: Function, G: Function> AbiCompatible<Option<G>> for Option<F> {}
+
 impl_hookable! {
   __arg_0:  A, __arg_1:  B, __arg_2:  C, __arg_3:  D, __arg_4:  E, __arg_5:  F, __arg_6:  G,
-  __arg_7:  H, __arg_8:  I, __arg_9:  J, __arg_10: K, __arg_11: L, __arg_12: M, __arg_13: N
+  __arg_7:  H, __arg_8:  I, __arg_9:  J, __arg_10: K, __arg_11: L, __arg_12: M, __arg_13: N,
+  __arg_14: O, __arg_15: P, __arg_16: Q, __arg_17: R, __arg_18: S, __arg_19: T, __arg_20: U,
+  __arg_21: V, __arg_22: W, __arg_23: X, __arg_24: Y, __arg_25: Z
+}
+
+impl_compatible! {
+  A0:  B0,  A1:  B1,  A2:  B2,  A3:  B3,  A4:  B4,  A5:  B5,  A6:  B6,
+  A7:  B7,  A8:  B8,  A9:  B9,  A10: B10, A11: B11, A12: B12, A13: B13,
+  A14: B14, A15: B15, A16: B16, A17: B17, A18: B18, A19: B19, A20: B20,
+  A21: B21, A22: B22, A23: B23, A24: B24, A25: B25
 }
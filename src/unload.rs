@@ -0,0 +1,343 @@
+//! A safety net for detours into modules that are unloaded at runtime.
+//!
+//! A detour whose destination resides within a dynamically loaded module
+//! (e.g a plugin) redirects its target to unmapped memory once the module is
+//! unloaded, unless it's dropped beforehand. Once
+//! [installed](./fn.install.html), the guard observes each module being
+//! unloaded, and disables every detour whose destination resides within it,
+//! before its pages are unmapped. Each affected detour is reported to the
+//! [observer](./fn.set_observer.html), since it has been leaked by its owner.
+//!
+//! - On Unix, `dlclose` is detoured. Before a handle is closed, the detours
+//!   within its module are disabled; once it's closed, those within modules
+//!   that remain loaded (i.e are still referenced) are enabled again, whilst
+//!   the others are reported.
+//! - On Windows, a DLL notification (`LdrRegisterDllNotification`) is
+//!   registered, which is delivered before a DLL is unmapped. The observer is
+//!   invoked whilst holding the loader lock.
+//!
+//! Every inline detour (e.g [RawDetour](../struct.RawDetour.html),
+//! [GenericDetour](../struct.GenericDetour.html) or
+//! [StaticDetour](../struct.StaticDetour.html)) is recorded along with its
+//! destination, including those created before the guard is installed. A
+//! disabled detour must not be enabled again, although it can be bound to a
+//! reloaded destination's target, or dropped.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use detour::Result;
+//! use detour::unload::{self, UnloadEvent};
+//!
+//! fn on_unload(event: &UnloadEvent) {
+//!   eprintln!("{:p} was leaked by its module", event.detour);
+//! }
+//!
+//! # fn main() -> Result<()> {
+//! unload::set_observer(on_unload);
+//! unload::install()?;
+//! assert!(unload::is_installed());
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::arch::memory;
+use crate::arch::registry::{self, Entry};
+use crate::error::Result;
+use crate::sync::Mutex;
+use std::panic::{self, AssertUnwindSafe};
+use std::vec::Vec;
+
+/// A detour disabled because its destination's module was unloaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnloadEvent {
+  /// The address of the detour's patch area.
+  pub target: *const (),
+  /// The destination of the detour, within the unloaded module.
+  pub detour: *const (),
+  /// Whether the target's code was restored; it remains redirected to the
+  /// unloaded module otherwise (e.g if the patch was rejected by a callback).
+  pub restored: bool,
+}
+
+unsafe impl Send for UnloadEvent {}
+unsafe impl Sync for UnloadEvent {}
+
+/// An observer invoked for each detour disabled by the guard.
+pub type UnloadObserver = fn(&UnloadEvent);
+
+/// Writes the event to the standard error stream.
+fn log_unload(event: &UnloadEvent) {
+  std::eprintln!(
+    "detour: {:p} was disabled, since its detour {:p} was unloaded{}",
+    event.target,
+    event.detour,
+    if event.restored {
+      ""
+    } else {
+      " (failed to restore)"
+    }
+  );
+}
+
+static OBSERVER: Mutex<UnloadObserver> = Mutex::new(log_unload);
+
+/// Whether the guard has been installed.
+static INSTALLED: Mutex<bool> = Mutex::new(false);
+
+/// Sets the observer invoked for each detour disabled by the guard.
+///
+/// The default observer writes each event to the standard error stream. The
+/// observer is invoked without holding any lock of the library, and a panic
+/// within it is caught.
+pub fn set_observer(observer: UnloadObserver) {
+  *OBSERVER.lock() = observer;
+}
+
+/// Installs the guard, for the remainder of the process.
+///
+/// Installing it more than once has no effect.
+pub fn install() -> Result<()> {
+  let mut installed = INSTALLED.lock();
+  if !*installed {
+    unsafe { platform::install()? };
+    *installed = true;
+  }
+  Ok(())
+}
+
+/// Returns whether the guard has been installed.
+pub fn is_installed() -> bool {
+  *INSTALLED.lock()
+}
+
+/// A detour disabled whilst its destination's module is unloaded.
+struct Suspended {
+  entry: Entry,
+  target: *const (),
+  restored: bool,
+}
+
+/// Disables every enabled detour whose destination is within a module being
+/// unloaded.
+///
+/// `is_unloading` is invoked without holding any lock, since resolving the
+/// module of an address may require the loader's lock, which is held by any
+/// thread dropping a detour whilst a module is unloaded.
+fn suspend(mut is_unloading: impl FnMut(usize) -> bool) -> Vec<Suspended> {
+  let affected = registry::entries()
+    .filter(|entry| entry.detour != 0 && is_unloading(entry.detour))
+    .collect::<Vec<_>>();
+
+  if affected.is_empty() {
+    return Vec::new();
+  }
+
+  // Detours may have been dropped in the meantime
+  let _guard = memory::LOCK.lock();
+  affected
+    .into_iter()
+    .filter(registry::contains)
+    .filter_map(|entry| unsafe {
+      let patch = &*entry.patch;
+      patch.is_enabled().then(|| Suspended {
+        entry,
+        target: patch.address_locked(),
+        restored: patch.set_enabled_locked(false).is_ok(),
+      })
+    })
+    .collect()
+}
+
+/// Enables the suspended detours whose module remains loaded, and reports
+/// the others.
+#[cfg(unix)]
+fn resume(suspended: Vec<Suspended>, mut is_loaded: impl FnMut(usize) -> bool) {
+  let (loaded, unloaded): (Vec<_>, Vec<_>) = suspended
+    .into_iter()
+    .partition(|suspended| is_loaded(suspended.entry.detour));
+
+  if !loaded.is_empty() {
+    let _guard = memory::LOCK.lock();
+
+    for suspended in loaded.iter().filter(|suspended| suspended.restored) {
+      let patch = unsafe { &*suspended.entry.patch };
+      if registry::contains(&suspended.entry) && !patch.is_enabled() {
+        let _ = unsafe { patch.set_enabled_locked(true) };
+      }
+    }
+  }
+
+  report(&unloaded);
+}
+
+/// Invokes the observer for each disabled detour, without holding any lock.
+fn report(suspended: &[Suspended]) {
+  let observer = *OBSERVER.lock();
+
+  for suspended in suspended {
+    let event = UnloadEvent {
+      target: suspended.target,
+      detour: suspended.entry.detour as *const (),
+      restored: suspended.restored,
+    };
+    let _ = panic::catch_unwind(AssertUnwindSafe(|| observer(&event)));
+  }
+}
+
+#[cfg(unix)]
+mod platform {
+  use super::{resume, suspend};
+  use crate::error::{Error, Result};
+  use crate::os::Native;
+  use crate::RawDetour;
+  use core::mem::{self, MaybeUninit};
+  use core::ptr;
+  use core::sync::atomic::{AtomicPtr, Ordering};
+  use libc::{c_int, c_void, Dl_info};
+  use std::vec::Vec;
+
+  type FnDlclose = unsafe extern "C" fn(*mut c_void) -> c_int;
+
+  /// The trampoline of `dlclose`.
+  static DLCLOSE: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+  /// Detours `dlclose`, for the remainder of the process.
+  pub unsafe fn install() -> Result<()> {
+    let target = Native::resolve_symbol("dlclose").ok_or_else(|| Error::UnknownSymbol {
+      name: "dlclose".into(),
+    })?;
+
+    let hook = RawDetour::new(target, dlclose_detour as *const ())?;
+    DLCLOSE.store(hook.trampoline() as *const () as *mut (), Ordering::SeqCst);
+    hook.enable()?;
+    mem::forget(hook);
+    Ok(())
+  }
+
+  /// Closes a handle, bypassing the guard.
+  unsafe fn dlclose(handle: *mut c_void) -> c_int {
+    let dlclose: FnDlclose = mem::transmute(DLCLOSE.load(Ordering::SeqCst));
+    dlclose(handle)
+  }
+
+  /// Disables the detours within a handle's module before it's closed, since
+  /// whether the module is unloaded is only known afterwards.
+  unsafe extern "C" fn dlclose_detour(handle: *mut c_void) -> c_int {
+    // Whether each module (by its base) belongs to the handle
+    let mut modules: Vec<(usize, bool)> = Vec::new();
+
+    let suspended = suspend(|detour| {
+      let module = match module_of(detour) {
+        Some(module) => module,
+        None => return false,
+      };
+
+      let base = module.dli_fbase as usize;
+      match modules.iter().find(|&&(other, _)| other == base) {
+        Some(&(_, belongs)) => belongs,
+        None => {
+          let belongs = is_handle_of(handle, &module);
+          modules.push((base, belongs));
+          belongs
+        },
+      }
+    });
+
+    let result = dlclose(handle);
+    resume(suspended, |detour| module_of(detour).is_some());
+    result
+  }
+
+  /// Returns the module containing an address, if any.
+  unsafe fn module_of(address: usize) -> Option<Dl_info> {
+    let mut info = MaybeUninit::<Dl_info>::uninit();
+    (libc::dladdr(address as *const c_void, info.as_mut_ptr()) != 0).then(|| info.assume_init())
+  }
+
+  /// Returns whether a handle refers to a module.
+  unsafe fn is_handle_of(handle: *mut c_void, module: &Dl_info) -> bool {
+    if module.dli_fname.is_null() {
+      return false;
+    }
+
+    // The handle of a loaded module is obtained without loading it
+    let other = libc::dlopen(module.dli_fname, libc::RTLD_LAZY | libc::RTLD_NOLOAD);
+    if other.is_null() {
+      return false;
+    }
+
+    dlclose(other);
+    other == handle
+  }
+}
+
+#[cfg(windows)]
+mod platform {
+  use super::{report, suspend};
+  use crate::error::{Error, OsError, Result};
+  use core::ffi::c_void;
+  use core::{mem, ptr};
+  use winapi::um::libloaderapi::{GetModuleHandleA, GetProcAddress};
+
+  /// The reason of a notification for an unloaded DLL.
+  const LDR_DLL_NOTIFICATION_REASON_UNLOADED: u32 = 2;
+
+  /// The data of a notification (i.e `LDR_DLL_UNLOADED_NOTIFICATION_DATA`).
+  #[repr(C)]
+  struct NotificationData {
+    flags: u32,
+    full_dll_name: *const c_void,
+    base_dll_name: *const c_void,
+    dll_base: *const c_void,
+    size_of_image: u32,
+  }
+
+  type FnNotification = unsafe extern "system" fn(u32, *const NotificationData, *mut c_void);
+  type FnRegister =
+    unsafe extern "system" fn(u32, FnNotification, *mut c_void, *mut *mut c_void) -> i32;
+
+  /// Registers a DLL notification, for the remainder of the process.
+  pub unsafe fn install() -> Result<()> {
+    let ntdll = GetModuleHandleA(b"ntdll.dll\0".as_ptr() as *const _);
+    let register = if ntdll.is_null() {
+      ptr::null_mut()
+    } else {
+      GetProcAddress(ntdll, b"LdrRegisterDllNotification\0".as_ptr() as *const _)
+    };
+
+    if register.is_null() {
+      Err(Error::UnknownSymbol {
+        name: "LdrRegisterDllNotification".into(),
+      })?;
+    }
+
+    let register: FnRegister = mem::transmute(register);
+    let mut cookie = ptr::null_mut();
+    let status = register(0, notification, ptr::null_mut(), &mut cookie);
+
+    if status < 0 {
+      Err(Error::PermissionDenied {
+        operation: "LdrRegisterDllNotification",
+        error: OsError(status),
+      })?;
+    }
+    Ok(())
+  }
+
+  /// Disables the detours within a DLL being unloaded, which remains mapped
+  /// until the notification returns.
+  unsafe extern "system" fn notification(
+    reason: u32,
+    data: *const NotificationData,
+    _context: *mut c_void,
+  ) {
+    if reason != LDR_DLL_NOTIFICATION_REASON_UNLOADED {
+      return;
+    }
+
+    let base = (*data).dll_base as usize;
+    let module = base..base + (*data).size_of_image as usize;
+    report(&suspend(|detour| module.contains(&detour)));
+  }
+}
@@ -0,0 +1,136 @@
+//! Failures to allocate executable memory, injected by a backend.
+#![cfg(feature = "std")]
+mod common;
+
+use common::{add, sub};
+use detour::os::{self, Backend, Native, Protection, Region};
+use detour::{Error, OsError, RawDetour, Result};
+use matches::assert_matches;
+use std::ops::Range;
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+
+/// The operating system's error code for a denied operation.
+const EPERM: i32 = 1;
+
+/// The address space is mapped in its entirety.
+const FULL: u8 = 1;
+/// Every mapping fails with an error.
+const FAILING: u8 = 2;
+/// Every mapping is denied.
+const DENIED: u8 = 3;
+
+/// A backend failing in a configurable manner, delegating to the native
+/// backend.
+struct Faulty {
+  mode: AtomicU8,
+  allocations: AtomicUsize,
+}
+
+unsafe impl Backend for Faulty {
+  fn page_size(&self) -> usize {
+    Native.page_size()
+  }
+
+  fn allocation_granularity(&self) -> usize {
+    Native.allocation_granularity()
+  }
+
+  fn query(&self, address: *const ()) -> Result<Option<Region>> {
+    Native.query(address)
+  }
+
+  fn query_range(&self, range: Range<usize>) -> Option<Vec<Region>> {
+    if self.mode.load(Ordering::SeqCst) != FULL {
+      return Native.query_range(range);
+    }
+
+    Some(vec![Region {
+      base: range.start as *const (),
+      size: range.end - range.start,
+      protection: Protection::READ,
+    }])
+  }
+
+  unsafe fn protect(&self, address: *const (), size: usize, protection: Protection) -> Result<()> {
+    Native.protect(address, size, protection)
+  }
+
+  unsafe fn allocate(&self, address: *const (), size: usize) -> Result<Option<*mut u8>> {
+    self.allocations.fetch_add(1, Ordering::SeqCst);
+    match self.mode.load(Ordering::SeqCst) {
+      FAILING => Err(Error::AllocationFailed {
+        operation: "mmap",
+        error: OsError(EPERM),
+      }),
+      DENIED => Err(Error::PermissionDenied {
+        operation: "mmap",
+        error: OsError(EPERM),
+      }),
+      _ => Native.allocate(address, size),
+    }
+  }
+
+  fn write_alias(&self, address: *const ()) -> Option<*mut u8> {
+    Native.write_alias(address)
+  }
+
+  unsafe fn release(&self, address: *mut u8, size: usize) {
+    Native.release(address, size)
+  }
+}
+
+static FAULTY: Faulty = Faulty {
+  mode: AtomicU8::new(0),
+  allocations: AtomicUsize::new(0),
+};
+
+/// Activates a mode, returning the number of allocations made so far.
+fn set_mode(mode: u8) -> usize {
+  FAULTY.mode.store(mode, Ordering::SeqCst);
+  FAULTY.allocations.load(Ordering::SeqCst)
+}
+
+#[test]
+fn allocation_failures() -> Result<()> {
+  os::set_backend(&FAULTY)?;
+  let target = add as *const () as usize;
+
+  // Without any gap in range, the OS is never asked to map memory
+  let allocations = set_mode(FULL);
+  let error = unsafe { RawDetour::new(add as *const (), sub as *const ()) }.unwrap_err();
+  assert_matches!(error, Error::NoMemoryInRange { origin, ref range }
+    if origin == target && range.contains(&target) && range.start < range.end);
+  assert_eq!(FAULTY.allocations.load(Ordering::SeqCst), allocations);
+
+  // Failures are retried at other addresses, and the last one is reported
+  let allocations = set_mode(FAILING);
+  let error = unsafe { RawDetour::new(add as *const (), sub as *const ()) }.unwrap_err();
+  assert_matches!(
+    error,
+    Error::AllocationFailed {
+      operation: "mmap",
+      error: OsError(EPERM)
+    }
+  );
+  assert!(FAULTY.allocations.load(Ordering::SeqCst) > allocations + 1);
+
+  // ... whilst a denial ends the search immediately
+  let allocations = set_mode(DENIED);
+  let error = unsafe { RawDetour::new(add as *const (), sub as *const ()) }.unwrap_err();
+  assert_matches!(
+    error,
+    Error::PermissionDenied {
+      operation: "mmap",
+      ..
+    }
+  );
+  assert_eq!(FAULTY.allocations.load(Ordering::SeqCst), allocations + 1);
+
+  set_mode(0);
+  let hook = unsafe { RawDetour::new(add as *const (), sub as *const ())? };
+  unsafe { hook.enable()? };
+  assert_eq!(add(10, 5), 5);
+  unsafe { hook.disable()? };
+  assert_eq!(add(10, 5), 15);
+  Ok(())
+}
@@ -0,0 +1,97 @@
+//! Trampolines served by a custom allocator.
+#![cfg(feature = "std")]
+mod common;
+
+use common::{add, sub};
+use detour::pool::{self, DefaultAllocator, ExecutableAllocator, ExecutableSlice};
+use detour::{Error, RawDetour, Result};
+use matches::assert_matches;
+use std::sync::Mutex;
+
+/// An allocator serving requests from a fixed region, recording its usage.
+struct Recorder {
+  state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+  region: Option<ExecutableSlice>,
+  offset: usize,
+  requests: Vec<(usize, usize, usize)>,
+  freed: Vec<ExecutableSlice>,
+}
+
+unsafe impl ExecutableAllocator for Recorder {
+  fn allocate_near(&self, origin: usize, size: usize, range: usize) -> Result<ExecutableSlice> {
+    let mut state = self.state.lock().unwrap();
+    state.requests.push((origin, size, range));
+
+    // The region is a stand-in for memory handed out by a host
+    let region = match state.region {
+      Some(region) => region,
+      None => *state
+        .region
+        .insert(DefaultAllocator.allocate_near(origin, 0x1000, range)?),
+    };
+
+    if state.offset + size > region.size {
+      return Err(Error::OutOfMemory);
+    }
+
+    let address = unsafe { region.address.add(state.offset) };
+    state.offset += size;
+    Ok(ExecutableSlice { address, size })
+  }
+
+  unsafe fn free(&self, slice: ExecutableSlice) {
+    self.state.lock().unwrap().freed.push(slice);
+  }
+}
+
+static RECORDER: Recorder = Recorder {
+  state: Mutex::new(State {
+    region: None,
+    offset: 0,
+    requests: Vec::new(),
+    freed: Vec::new(),
+  }),
+};
+
+#[test]
+fn custom_allocator() -> Result<()> {
+  pool::set_allocator(&RECORDER)?;
+  assert_matches!(
+    pool::set_allocator(&DefaultAllocator),
+    Err(Error::AlreadyInitialized)
+  );
+
+  let hook = unsafe { RawDetour::new(add as *const (), sub as *const ())? };
+  let region = RECORDER.state.lock().unwrap().region.unwrap();
+  let region = (region.address as usize)..(region.address as usize + region.size);
+
+  // The trampoline is served by the custom allocator
+  assert!(region.contains(&(hook.trampoline() as *const () as usize)));
+  {
+    let state = RECORDER.state.lock().unwrap();
+    assert!(!state.requests.is_empty());
+    assert!(state
+      .requests
+      .iter()
+      .all(|&(origin, _, range)| origin == add as *const () as usize && range > 0));
+  }
+
+  unsafe { hook.enable()? };
+  assert_eq!(add(10, 5), 5);
+  unsafe { hook.disable()? };
+  assert_eq!(add(10, 5), 15);
+
+  // All allocations are returned to the allocator
+  std::mem::drop(hook);
+  let state = RECORDER.state.lock().unwrap();
+  assert_eq!(state.freed.len(), state.requests.len());
+  assert!(state
+    .freed
+    .iter()
+    .all(|slice| region.contains(&(slice.address as usize))));
+  Ok(())
+}
@@ -0,0 +1,161 @@
+//! The exact bounds are validated against the symbol table of the test
+//! binary, therefore these tests use a separate binary.
+#![cfg(all(feature = "std", target_os = "linux", target_arch = "x86_64"))]
+use detour::analysis::{self, BoundsSource};
+use std::convert::TryInto;
+use std::ops::Range;
+use std::{fs, ptr};
+
+#[no_mangle]
+#[inline(never)]
+pub extern "C" fn analysis_add(x: i32, y: i32) -> i32 {
+  unsafe { ptr::read_volatile(&x) + y }
+}
+
+#[no_mangle]
+#[inline(never)]
+pub extern "C" fn analysis_sum(count: u32) -> u64 {
+  let count = unsafe { ptr::read_volatile(&count) };
+  (0..count).map(|value| u64::from(value) * 3).sum()
+}
+
+#[no_mangle]
+#[inline(never)]
+pub extern "C" fn analysis_empty() {}
+
+/// Returns the range of a function symbol, as recorded by the ELF symbol table
+/// of the test binary.
+fn symbol_range(name: &str) -> Range<usize> {
+  const SHT_SYMTAB: u32 = 2;
+  const SYMBOL_SIZE: usize = 24;
+
+  let elf = fs::read("/proc/self/exe").unwrap();
+  let u16_at = |offset: usize| u16::from_le_bytes(elf[offset..offset + 2].try_into().unwrap());
+  let u32_at = |offset: usize| u32::from_le_bytes(elf[offset..offset + 4].try_into().unwrap());
+  let u64_at = |offset: usize| u64::from_le_bytes(elf[offset..offset + 8].try_into().unwrap());
+
+  let section_offset = u64_at(0x28) as usize;
+  let section_size = u16_at(0x3A) as usize;
+  let section = |index: usize| section_offset + index * section_size;
+
+  let symtab = (0..u16_at(0x3C) as usize)
+    .map(section)
+    .find(|&header| u32_at(header + 4) == SHT_SYMTAB)
+    .expect("symbol table");
+  let strtab = section(u32_at(symtab + 40) as usize);
+  let strings = u64_at(strtab + 24) as usize;
+
+  let symbols = u64_at(symtab + 24) as usize;
+  (0..u64_at(symtab + 32) as usize / SYMBOL_SIZE)
+    .map(|index| symbols + index * SYMBOL_SIZE)
+    .find(|&symbol| {
+      let start = strings + u32_at(symbol) as usize;
+      let end = start + elf[start..].iter().position(|&byte| byte == 0).unwrap();
+      &elf[start..end] == name.as_bytes()
+    })
+    .map(|symbol| {
+      let value = u64_at(symbol + 8) as usize;
+      value..value + u64_at(symbol + 16) as usize
+    })
+    .expect("symbol")
+}
+
+/// Returns the range of a function, with the size of its symbol.
+fn loaded_range(function: *const (), name: &str) -> Range<usize> {
+  function as usize..function as usize + symbol_range(name).len()
+}
+
+#[test]
+fn bounds_match_symbols() {
+  let functions = [
+    (analysis_add as *const (), "analysis_add"),
+    (analysis_sum as *const (), "analysis_sum"),
+    (analysis_empty as *const (), "analysis_empty"),
+  ];
+
+  for (function, name) in functions {
+    let expected = loaded_range(function, name);
+    let bounds = analysis::bounds(function).expect("bounds");
+    assert_eq!(bounds.source, BoundsSource::UnwindInfo, "{}", name);
+    assert_eq!(bounds.range, expected, "{}", name);
+
+    // Addresses within the function resolve to the same bounds
+    let last = (expected.end - 1) as *const ();
+    assert_eq!(analysis::function_bounds(last), Some(expected), "{}", name);
+  }
+}
+
+/// Copies code to a 16 byte boundary within a buffer.
+fn aligned(buffer: &mut [u8], code: &[u8]) -> *const () {
+  let offset = buffer.as_ptr().align_offset(16);
+  buffer[offset..offset + code.len()].copy_from_slice(code);
+  buffer[offset..].as_ptr() as *const ()
+}
+
+#[test]
+fn padding_estimates_bounds() {
+  let mut buffer = vec![0u8; 256];
+
+  // mov eax, 42; ret; int3...
+  let mut code = vec![0xB8, 0x2A, 0x00, 0x00, 0x00, 0xC3];
+  code.resize(16, 0xCC);
+  let address = aligned(&mut buffer, &code);
+
+  let bounds = analysis::bounds(address).expect("bounds");
+  assert_eq!(bounds.source, BoundsSource::Padding);
+  assert_eq!(bounds.range, address as usize..address as usize + 6);
+
+  // xor eax, eax; ret; nop dword [rax+0]; nop word [rax+rax+0]
+  let code = [
+    0x31, 0xC0, 0xC3, 0x0F, 0x1F, 0x80, 0x00, 0x00, 0x00, 0x00, 0x66, 0x0F, 0x1F, 0x44, 0x00, 0x00,
+  ];
+  let address = aligned(&mut buffer, &code);
+  assert_eq!(
+    analysis::function_bounds(address),
+    Some(address as usize..address as usize + 3)
+  );
+}
+
+#[test]
+fn padding_within_branches_is_skipped() {
+  let mut buffer = vec![0u8; 256];
+
+  // je +14; ret; int3...; mov eax, 1; ret; int3...
+  let mut code = vec![0x74, 0x0E, 0xC3];
+  code.resize(16, 0xCC);
+  code.extend_from_slice(&[0xB8, 0x01, 0x00, 0x00, 0x00, 0xC3]);
+  code.resize(32, 0xCC);
+  let address = aligned(&mut buffer, &code);
+
+  assert_eq!(
+    analysis::function_bounds(address),
+    Some(address as usize..address as usize + 22)
+  );
+}
+
+#[test]
+fn unpadded_code_has_no_bounds() {
+  let page = unsafe {
+    libc::mmap(
+      ptr::null_mut(),
+      0x1000,
+      libc::PROT_READ | libc::PROT_WRITE,
+      libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+      -1,
+      0,
+    )
+  };
+  assert_ne!(page, libc::MAP_FAILED);
+
+  // mov eax, 42; ret; ud2; nop... (i.e no padding up to the end of the page)
+  let code = unsafe { std::slice::from_raw_parts_mut(page as *mut u8, 0x1000) };
+  code.fill(0x90);
+  code[..8].copy_from_slice(&[0xB8, 0x2A, 0x00, 0x00, 0x00, 0xC3, 0x0F, 0x0B]);
+
+  // The page must not be merged with its (writable) neighbours
+  assert_eq!(unsafe { libc::mprotect(page, 0x1000, libc::PROT_READ) }, 0);
+
+  assert_eq!(analysis::bounds(page as *const ()), None);
+  assert_eq!(analysis::bounds(ptr::null()), None);
+  unsafe { libc::munmap(page, 0x1000) };
+}
@@ -0,0 +1,74 @@
+//! Detours through a custom backend.
+#![cfg(feature = "std")]
+mod common;
+
+use common::{add, sub};
+use detour::os::{self, Backend, Native, Protection, Region};
+use detour::{Error, RawDetour, Result};
+use matches::assert_matches;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A backend recording its usage, delegating to the native backend.
+struct Recorder {
+  allocations: AtomicUsize,
+  protections: AtomicUsize,
+  flushes: AtomicUsize,
+}
+
+unsafe impl Backend for Recorder {
+  fn page_size(&self) -> usize {
+    Native.page_size()
+  }
+
+  fn query(&self, address: *const ()) -> Result<Option<Region>> {
+    Native.query(address)
+  }
+
+  unsafe fn protect(&self, address: *const (), size: usize, protection: Protection) -> Result<()> {
+    self.protections.fetch_add(1, Ordering::SeqCst);
+    Native.protect(address, size, protection)
+  }
+
+  unsafe fn allocate(&self, address: *const (), size: usize) -> Result<Option<*mut u8>> {
+    self.allocations.fetch_add(1, Ordering::SeqCst);
+    Native.allocate(address, size)
+  }
+
+  fn write_alias(&self, address: *const ()) -> Option<*mut u8> {
+    Native.write_alias(address)
+  }
+
+  unsafe fn release(&self, address: *mut u8, size: usize) {
+    Native.release(address, size)
+  }
+
+  unsafe fn flush_instruction_cache(&self, address: *const (), size: usize) {
+    self.flushes.fetch_add(1, Ordering::SeqCst);
+    Native.flush_instruction_cache(address, size)
+  }
+}
+
+static RECORDER: Recorder = Recorder {
+  allocations: AtomicUsize::new(0),
+  protections: AtomicUsize::new(0),
+  flushes: AtomicUsize::new(0),
+};
+
+#[test]
+fn custom_backend() -> Result<()> {
+  os::set_backend(&RECORDER)?;
+  assert_matches!(os::set_backend(&Native), Err(Error::AlreadyInitialized));
+
+  let hook = unsafe { RawDetour::new(add as *const (), sub as *const ())? };
+  assert!(RECORDER.allocations.load(Ordering::SeqCst) >= 1);
+
+  unsafe { hook.enable()? };
+  assert_eq!(add(10, 5), 5);
+  unsafe { hook.disable()? };
+  assert_eq!(add(10, 5), 15);
+
+  // Each toggle changes the protection, and restores it afterwards
+  assert!(RECORDER.protections.load(Ordering::SeqCst) >= 4);
+  assert!(RECORDER.flushes.load(Ordering::SeqCst) >= 3);
+  Ok(())
+}
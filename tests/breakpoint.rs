@@ -0,0 +1,104 @@
+//! Breakpoint detours; the tests are serialized.
+#![cfg(target_os = "linux")]
+mod common;
+
+use common::{add, sub};
+use detour::{BreakpointDetour, Result};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::{mem, ptr};
+
+static SERIAL: Mutex<()> = Mutex::new(());
+
+/// A target too small for a jump (`xor eax, eax`, `ret`).
+#[unsafe(naked)]
+extern "C" fn zero() -> i32 {
+  core::arch::naked_asm!("xor eax, eax", "ret")
+}
+
+extern "C" fn one() -> i32 {
+  1
+}
+
+/// Returns the address of the current `SIGTRAP` handler.
+fn current_handler() -> usize {
+  unsafe {
+    let mut action: libc::sigaction = mem::zeroed();
+    libc::sigaction(libc::SIGTRAP, ptr::null(), &mut action);
+    action.sa_sigaction
+  }
+}
+
+#[test]
+fn redirects_breakpoints() -> Result<()> {
+  let _serial = SERIAL.lock().unwrap();
+
+  let add_hook = unsafe { BreakpointDetour::new(add as *const (), sub as *const ())? };
+  let zero_hook = unsafe { BreakpointDetour::new(zero as *const (), one as *const ())? };
+  assert!(!add_hook.is_enabled());
+  assert_eq!(add(10, 5), 15);
+
+  unsafe {
+    add_hook.enable()?;
+    zero_hook.enable()?;
+  }
+
+  assert_eq!(add(10, 5), 5);
+  assert_eq!(zero(), 1);
+  assert_eq!(unsafe { *(zero as *const u8) }, 0xCC);
+
+  let original: extern "C" fn(i32, i32) -> i32 = unsafe { mem::transmute(add_hook.trampoline()) };
+  assert_eq!(original(10, 5), 15);
+  let original: extern "C" fn() -> i32 = unsafe { mem::transmute(zero_hook.trampoline()) };
+  assert_eq!(original(), 0);
+
+  unsafe { zero_hook.disable()? };
+  assert_eq!(zero(), 0);
+  assert_eq!(add(10, 5), 5);
+  Ok(())
+}
+
+#[test]
+fn removes_handler() -> Result<()> {
+  let _serial = SERIAL.lock().unwrap();
+  assert_eq!(current_handler(), libc::SIG_DFL);
+
+  let first = unsafe { BreakpointDetour::new(add as *const (), sub as *const ())? };
+  let second = unsafe { BreakpointDetour::new(zero as *const (), one as *const ())? };
+  assert_ne!(current_handler(), libc::SIG_DFL);
+
+  unsafe { first.enable()? };
+  mem::drop(first);
+  assert_eq!(add(10, 5), 15);
+  assert_ne!(current_handler(), libc::SIG_DFL);
+
+  mem::drop(second);
+  assert_eq!(current_handler(), libc::SIG_DFL);
+  Ok(())
+}
+
+#[test]
+fn forwards_foreign_breakpoints() -> Result<()> {
+  static TRAPS: AtomicUsize = AtomicUsize::new(0);
+
+  extern "C" fn count(_signal: libc::c_int) {
+    TRAPS.fetch_add(1, Ordering::SeqCst);
+  }
+
+  let _serial = SERIAL.lock().unwrap();
+  let previous = unsafe { libc::signal(libc::SIGTRAP, count as *const () as usize) };
+
+  let hook = unsafe { BreakpointDetour::new(add as *const (), sub as *const ())? };
+  unsafe {
+    hook.enable()?;
+    core::arch::asm!("int3");
+  }
+
+  assert_eq!(TRAPS.load(Ordering::SeqCst), 1);
+  assert_eq!(add(10, 5), 5);
+
+  mem::drop(hook);
+  assert_eq!(current_handler(), count as *const () as usize);
+  unsafe { libc::signal(libc::SIGTRAP, previous) };
+  Ok(())
+}
@@ -0,0 +1,45 @@
+//! Installation of many detours at once.
+#![cfg(feature = "std")]
+mod common;
+
+use common::{add, sub};
+use detour::{pool, RawDetour, Result};
+use std::time::Instant;
+
+#[test]
+fn bulk_installation() -> Result<()> {
+  const COUNT: usize = 200;
+  let start = Instant::now();
+
+  let hooks = (0..COUNT)
+    .map(|_| unsafe { RawDetour::new(add as *const (), sub as *const ()) })
+    .collect::<Result<Vec<_>>>()?;
+
+  let elapsed = start.elapsed();
+  eprintln!("created {} detours in {:?}", COUNT, elapsed);
+
+  // The trampolines are packed into slabs, instead of each requiring one
+  let stats = pool::stats();
+  let allocations = stats.iter().map(|region| region.allocations).sum::<usize>();
+  let used = stats.iter().map(|region| region.used).sum::<usize>();
+  let size = stats.iter().map(|region| region.size).sum::<usize>();
+  assert_eq!(allocations, COUNT);
+  assert!(stats.len() < COUNT / 4);
+
+  // Only a fraction of a page is left unused, in no more than a chunk per slab
+  let page_size = detour::os::backend()?.page_size();
+  assert!(size - used < page_size);
+  assert!(stats.iter().all(|region| region.free_chunks <= 1));
+  assert_eq!(
+    stats
+      .iter()
+      .map(|region| region.largest_free)
+      .sum::<usize>(),
+    size - used
+  );
+
+  for hook in &hooks {
+    assert!(hook.region().is_some());
+  }
+  Ok(())
+}
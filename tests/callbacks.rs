@@ -0,0 +1,189 @@
+//! Patch callbacks and the drop error handler; the tests are serialized.
+#![cfg(feature = "std")]
+mod common;
+
+use common::{add, sub};
+use detour::{os, pool, set_drop_error_handler, set_patch_callbacks, DropContext, Error};
+use detour::{GenericDetour, PatchCallbacks, RawDetour, Result};
+use matches::assert_matches;
+use std::sync::Mutex;
+
+static SERIAL: Mutex<()> = Mutex::new(());
+
+/// The patches observed by the callbacks, i.e the address, size and code.
+static PATCHES: Mutex<Vec<(usize, usize, Vec<u8>)>> = Mutex::new(Vec::new());
+
+/// The code written by each patch, i.e its address and contents.
+static CODE: Mutex<Vec<(usize, Vec<u8>)>> = Mutex::new(Vec::new());
+
+/// The addresses of completed patches.
+static COMPLETED: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+
+#[inline(never)]
+extern "C" fn mul(x: i32, y: i32) -> i32 {
+  unsafe { std::ptr::read_volatile(&x as *const i32) * y }
+}
+
+/// The targets reported by the drop error handler.
+static UNRESTORED: Mutex<Vec<(usize, usize)>> = Mutex::new(Vec::new());
+
+fn is_writable(address: *const ()) -> bool {
+  let region = os::backend().unwrap().query(address).unwrap().unwrap();
+  region.protection.contains(os::Protection::WRITE)
+}
+
+fn record(address: *const (), size: usize, code: &[u8]) -> bool {
+  // The code is not writable whilst the callbacks are invoked
+  assert!(!is_writable(address));
+  PATCHES
+    .lock()
+    .unwrap()
+    .push((address as usize, size, code.to_vec()));
+  true
+}
+
+fn complete(address: *const (), size: usize) {
+  assert!(!is_writable(address));
+  assert_eq!(PATCHES.lock().unwrap().last().unwrap().1, size);
+  COMPLETED.lock().unwrap().push(address as usize);
+}
+
+#[test]
+fn invoked_around_patches() -> Result<()> {
+  let _serial = SERIAL.lock().unwrap();
+  let hook = unsafe { RawDetour::new(add as *const (), sub as *const ())? };
+  let prolog = unsafe { *(add as *const [u8; 5]) };
+
+  set_patch_callbacks(PatchCallbacks {
+    on_before_patch: Some(record),
+    on_after_patch: Some(complete),
+  });
+  let result = unsafe { hook.enable().and_then(|_| hook.disable()) };
+  set_patch_callbacks(PatchCallbacks::default());
+  result?;
+
+  let patches = PATCHES.lock().unwrap().drain(..).collect::<Vec<_>>();
+  let completed = COMPLETED.lock().unwrap().drain(..).collect::<Vec<_>>();
+  let target = add as *const () as usize;
+
+  assert_eq!(patches.len(), 2);
+  assert_eq!((patches[0].0, patches[0].1), (target, 5));
+  assert_eq!(patches[0].2[0], 0xE9);
+  assert_eq!(patches[1], (target, 5, prolog.to_vec()));
+  assert_eq!(completed, [target, target]);
+  Ok(())
+}
+
+#[test]
+fn rejected_patches() -> Result<()> {
+  let _serial = SERIAL.lock().unwrap();
+  let hook = unsafe { RawDetour::new(add as *const (), sub as *const ())? };
+  let prolog = unsafe { *(add as *const [u8; 5]) };
+
+  set_patch_callbacks(PatchCallbacks {
+    on_before_patch: Some(|_, _, _| false),
+    on_after_patch: Some(complete),
+  });
+  let result = unsafe { hook.enable() };
+  set_patch_callbacks(PatchCallbacks::default());
+
+  assert_matches!(result, Err(Error::PatchRejected));
+  assert!(!hook.is_enabled());
+  assert!(COMPLETED.lock().unwrap().is_empty());
+  assert_eq!(unsafe { *(add as *const [u8; 5]) }, prolog);
+  assert_eq!(add(10, 5), 15);
+
+  unsafe { hook.enable()? };
+  assert_eq!(add(10, 5), 5);
+  Ok(())
+}
+
+#[test]
+fn reports_drop_errors() -> Result<()> {
+  let _serial = SERIAL.lock().unwrap();
+  let hook = unsafe { RawDetour::new(mul as *const (), sub as *const ())? };
+  unsafe { hook.enable()? };
+
+  fn on_drop_error(error: &Error, context: DropContext) {
+    assert_matches!(error, Error::PatchRejected);
+    UNRESTORED
+      .lock()
+      .unwrap()
+      .push((context.target as usize, context.size));
+    panic!("unwinding out of the handler");
+  }
+
+  // The target remains detoured, since restoring it is rejected
+  set_drop_error_handler(on_drop_error);
+  set_patch_callbacks(PatchCallbacks {
+    on_before_patch: Some(|_, _, _| false),
+    on_after_patch: None,
+  });
+  drop(hook);
+  set_patch_callbacks(PatchCallbacks::default());
+
+  let unrestored = UNRESTORED.lock().unwrap().drain(..).collect::<Vec<_>>();
+  assert_eq!(unrestored, [(mul as *const () as usize, 5)]);
+  assert_eq!(mul(10, 5), 5);
+  Ok(())
+}
+
+#[test]
+fn close_returns_unrestored_detours() -> Result<()> {
+  let _serial = SERIAL.lock().unwrap();
+  let hook = unsafe { GenericDetour::<extern "C" fn(i32, i32) -> i32>::new(add, sub)? };
+  let prolog = unsafe { *(add as *const [u8; 5]) };
+  unsafe { hook.enable()? };
+
+  set_patch_callbacks(PatchCallbacks {
+    on_before_patch: Some(|_, _, _| false),
+    on_after_patch: None,
+  });
+  let result = unsafe { hook.close() };
+  set_patch_callbacks(PatchCallbacks::default());
+
+  // The detour is given back, still enabled
+  let (hook, error) = result.expect_err("rejected close");
+  assert_matches!(error, Error::PatchRejected);
+  assert!(hook.is_enabled());
+  assert_eq!(add(10, 5), 5);
+
+  assert!(unsafe { hook.close() }.is_ok());
+  assert_eq!(unsafe { *(add as *const [u8; 5]) }, prolog);
+  assert_eq!(add(10, 5), 15);
+  Ok(())
+}
+
+#[test]
+fn toggles_reuse_generated_code() -> Result<()> {
+  let _serial = SERIAL.lock().unwrap();
+  let hook = unsafe { RawDetour::new(add as *const (), sub as *const ())? };
+  let trampoline = hook.trampoline() as *const ();
+  let regions = pool::stats();
+  #[cfg(feature = "profiling")]
+  let symbols = detour::profiling::symbols();
+
+  set_patch_callbacks(PatchCallbacks {
+    on_before_patch: Some(|_, _, code| {
+      let code = (code.as_ptr() as usize, code.to_vec());
+      CODE.lock().unwrap().push(code);
+      true
+    }),
+    on_after_patch: None,
+  });
+  let result = (0..3).try_for_each(|_| unsafe { hook.enable().and_then(|_| hook.disable()) });
+  set_patch_callbacks(PatchCallbacks::default());
+  result?;
+
+  // Every toggle writes the same code, generated upon construction
+  let code = CODE.lock().unwrap().drain(..).collect::<Vec<_>>();
+  assert_eq!(code.len(), 6);
+  assert!(code.chunks(2).all(|toggle| toggle == &code[..2]));
+
+  // Nothing is relocated, allocated or registered again
+  assert_eq!(pool::stats(), regions);
+  #[cfg(feature = "profiling")]
+  assert_eq!(detour::profiling::symbols(), symbols);
+  assert_eq!(hook.trampoline() as *const (), trampoline);
+  Ok(())
+}
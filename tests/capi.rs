@@ -0,0 +1,99 @@
+#![cfg(feature = "capi")]
+use detour::capi::*;
+use std::ffi::c_void;
+use std::{mem, ptr};
+
+type FnAdd = extern "C" fn(i32, i32) -> i32;
+
+#[test]
+fn header_is_up_to_date() {
+  let crate_dir = env!("CARGO_MANIFEST_DIR");
+  let config = cbindgen::Config::from_root_or_default(crate_dir);
+  let mut header = Vec::new();
+
+  cbindgen::Builder::new()
+    .with_src(format!("{}/src/capi.rs", crate_dir))
+    .with_config(config)
+    .generate()
+    .expect("generating C header")
+    .write(&mut header);
+
+  // The header is only written when explicitly requested
+  let path = format!("{}/include/detour.h", crate_dir);
+  if std::env::var_os("DETOUR_BLESS").is_some() {
+    std::fs::write(&path, header).unwrap();
+    return;
+  }
+
+  let header = String::from_utf8(header).unwrap();
+  let expected = std::fs::read_to_string(&path).unwrap_or_default();
+  if let Some((line, (actual, expected))) = header
+    .lines()
+    .chain(std::iter::repeat(""))
+    .zip(expected.lines().chain(std::iter::repeat("")))
+    .take(header.lines().count().max(expected.lines().count()))
+    .enumerate()
+    .find(|(_, (actual, expected))| actual != expected)
+  {
+    panic!(
+      "The C header is out of date (run with `DETOUR_BLESS=1` to regenerate it), line \
+       {}:\n-{}\n+{}",
+      line + 1,
+      expected,
+      actual
+    );
+  }
+}
+
+#[test]
+fn lifecycle() {
+  #[inline(never)]
+  extern "C" fn add(x: i32, y: i32) -> i32 {
+    unsafe { ptr::read_volatile(&x as *const i32) + y }
+  }
+
+  extern "C" fn sub(x: i32, y: i32) -> i32 {
+    x - y
+  }
+
+  unsafe {
+    let mut error = DetourError::Panic;
+    let handle = detour_create(add as *const c_void, sub as *const c_void, &mut error);
+    assert_eq!(error, DetourError::Ok);
+    assert!(!detour_is_enabled(handle));
+
+    assert_eq!(detour_enable(handle), DetourError::Ok);
+    assert!(detour_is_enabled(handle));
+    assert_eq!(add(10, 5), 5);
+
+    let trampoline: FnAdd = mem::transmute(detour_trampoline(handle));
+    assert_eq!(trampoline(10, 5), 15);
+
+    // Handles can be destroyed from any thread
+    let handle = handle as usize;
+    std::thread::spawn(move || detour_destroy(handle as *mut DetourHandle))
+      .join()
+      .unwrap();
+    assert_eq!(add(10, 5), 15);
+  }
+}
+
+#[test]
+fn reports_errors() {
+  unsafe {
+    let mut error = DetourError::Ok;
+    let handle = detour_create(ptr::null(), ptr::null(), &mut error);
+    assert!(handle.is_null());
+    assert_eq!(error, DetourError::NullPointer);
+
+    let address = reports_errors as *const c_void;
+    let handle = detour_create(address, address, &mut error);
+    assert!(handle.is_null());
+    assert_eq!(error, DetourError::SameAddress);
+
+    assert_eq!(detour_enable(ptr::null()), DetourError::NullPointer);
+    assert!(detour_trampoline(ptr::null()).is_null());
+    assert!(detour_fast_forward_entry(address).is_null());
+    detour_destroy(ptr::null_mut());
+  }
+}
@@ -0,0 +1,18 @@
+//! Fixtures shared by the test binaries.
+//!
+//! Most library state is process-wide (e.g the backend, the pool or the
+//! signal handlers), therefore tests modifying it use a binary of their own,
+//! rather than interfering with those of `tests/lib.rs`. Tests within a
+//! binary detouring the same fixture are serialized.
+#![allow(dead_code)]
+
+/// A target, whose prolog can be relocated.
+#[inline(never)]
+pub extern "C" fn add(x: i32, y: i32) -> i32 {
+  unsafe { std::ptr::read_volatile(&x as *const i32) + y }
+}
+
+/// A detour of `add`.
+pub extern "C" fn sub(x: i32, y: i32) -> i32 {
+  x - y
+}
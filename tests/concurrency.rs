@@ -0,0 +1,129 @@
+//! Detours toggled, dropped and called from many threads; also run under
+//! ThreadSanitizer and AddressSanitizer in CI.
+#![cfg(feature = "std")]
+use detour::{GenericDetour, Result};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+#[inline(never)]
+fn add5(val: i32) -> i32 {
+  unsafe { std::ptr::read_volatile(&val) + 5 }
+}
+
+#[inline(never)]
+fn add10(val: i32) -> i32 {
+  unsafe { std::ptr::read_volatile(&val) + 10 }
+}
+
+#[inline(never)]
+fn add15(val: i32) -> i32 {
+  unsafe { std::ptr::read_volatile(&val) + 15 }
+}
+
+#[inline(never)]
+fn add20(val: i32) -> i32 {
+  unsafe { std::ptr::read_volatile(&val) + 20 }
+}
+
+#[test]
+fn toggles_concurrently() -> Result<()> {
+  let hook = unsafe { GenericDetour::<fn(i32) -> i32>::new(add5, add10)? };
+  let done = AtomicBool::new(false);
+
+  thread::scope(|scope| {
+    let callers = (0..8)
+      .map(|_| {
+        scope.spawn(|| {
+          while !done.load(Ordering::Relaxed) {
+            let result = add5(1);
+            assert!(result == 6 || result == 11);
+            assert_eq!(hook.call(1), 6);
+          }
+        })
+      })
+      .collect::<Vec<_>>();
+
+    let togglers = (0..8)
+      .map(|index| {
+        let hook = &hook;
+        scope.spawn(move || -> Result<()> {
+          for iteration in 0..200 {
+            if (index + iteration) % 2 == 0 {
+              unsafe { hook.enable()? };
+            } else {
+              unsafe { hook.disable()? };
+            }
+            let _ = hook.is_enabled();
+          }
+          Ok(())
+        })
+      })
+      .collect::<Vec<_>>();
+
+    for toggler in togglers {
+      toggler.join().unwrap()?;
+    }
+    done.store(true, Ordering::Relaxed);
+    callers
+      .into_iter()
+      .for_each(|caller| caller.join().unwrap());
+
+    // The state matches the target's code once all operations completed
+    unsafe { hook.enable()? };
+    assert!(hook.is_enabled());
+    assert_eq!(add5(1), 11);
+
+    unsafe { hook.disable()? };
+    assert!(!hook.is_enabled());
+    assert_eq!(add5(1), 6);
+    Ok(())
+  })
+}
+
+#[test]
+fn reclaims_whilst_called() -> Result<()> {
+  let targets: [fn(i32) -> i32; 3] = [add10, add15, add20];
+  let done = AtomicBool::new(false);
+
+  thread::scope(|scope| {
+    let callers = (0..4)
+      .map(|_| {
+        scope.spawn(|| {
+          while !done.load(Ordering::Relaxed) {
+            for (index, target) in targets.iter().enumerate() {
+              let result = target(1);
+              assert!(result == 6 || result == 11 + 5 * index as i32);
+            }
+          }
+        })
+      })
+      .collect::<Vec<_>>();
+
+    // Each hook's trampoline is released once it's dropped, and its memory
+    // reused by the others once reclaimed
+    let hookers = targets
+      .iter()
+      .enumerate()
+      .map(|(index, &target)| {
+        scope.spawn(move || -> Result<()> {
+          for _ in 0..200 {
+            let hook = unsafe { GenericDetour::<fn(i32) -> i32>::new(target, add5)? };
+            unsafe { hook.enable()? };
+            assert_eq!(hook.call(1), 11 + 5 * index as i32);
+            unsafe { hook.disable()? };
+          }
+          Ok(())
+        })
+      })
+      .collect::<Vec<_>>();
+
+    for hooker in hookers {
+      hooker.join().unwrap()?;
+    }
+    done.store(true, Ordering::Relaxed);
+    callers
+      .into_iter()
+      .for_each(|caller| caller.join().unwrap());
+    Ok(())
+  })
+}
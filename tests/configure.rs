@@ -0,0 +1,39 @@
+//! Configuration of the pool's slabs.
+#![cfg(feature = "std")]
+mod common;
+
+use common::{add, sub};
+use detour::pool::{self, PoolOptions};
+use detour::{os, Error, RawDetour, Result};
+use matches::assert_matches;
+
+#[test]
+fn configured_slab_size() -> Result<()> {
+  assert_eq!(pool::options(), PoolOptions::default());
+
+  let options = PoolOptions {
+    slab_size: 0x1000,
+    max_search_distance: 0x1000_0000,
+    search_step: 0x10000,
+    alignment: 64,
+  };
+  pool::configure(options)?;
+  assert_eq!(pool::options(), options);
+
+  let hook = unsafe { RawDetour::new(add as *const (), sub as *const ())? };
+  let region = hook.region().expect("trampoline region");
+  let distance = (region.base as usize).abs_diff(add as *const () as usize);
+
+  // The trampoline is aligned, and padded to a multiple of the alignment
+  let trampoline = hook.trampoline() as *const () as usize;
+  assert_eq!(trampoline % options.alignment, 0);
+  assert_eq!(region.used % options.alignment, 0);
+
+  // The slab is no larger than requested, and within the search distance
+  assert_eq!(region.size, os::backend()?.page_size().max(0x1000));
+  assert!(distance < options.max_search_distance);
+
+  // The pool cannot be configured once memory has been allocated
+  assert_matches!(pool::configure(options), Err(Error::AlreadyInitialized));
+  Ok(())
+}
@@ -0,0 +1,88 @@
+//! The detoured functions terminate the process, therefore each test executes
+//! this binary as a child process, which calls a detoured never-returning
+//! function, and is identified by its exit code.
+#![cfg(all(feature = "std", target_os = "linux"))]
+use detour::{static_detour, GenericDetour};
+use std::env;
+use std::process::Command;
+use std::sync::OnceLock;
+
+/// The variable selecting the behavior of the child process.
+const CHILD: &str = "DETOUR_DIVERGING_CHILD";
+
+type FnFatal = extern "C" fn(i32) -> !;
+
+static_detour! {
+  static FatalDetour: extern "C" fn(i32) -> !;
+}
+
+static HOOK: OnceLock<GenericDetour<FnFatal>> = OnceLock::new();
+
+/// Terminates the process, like a fatal error handler.
+#[inline(never)]
+extern "C" fn fatal(code: i32) -> ! {
+  unsafe { libc::_exit(std::ptr::read_volatile(&code)) }
+}
+
+/// Logs the error, and terminates the process through the original function
+/// (its exit code offset, to identify the detour).
+extern "C" fn fatal_detour(code: i32) -> ! {
+  eprintln!("fatal error: {}", code);
+  HOOK.get().unwrap().call(code + 1)
+}
+
+/// Executes the child process, returning its exit code.
+fn run(behavior: &str) -> Option<i32> {
+  Command::new(env::current_exe().unwrap())
+    .args(["child", "--exact", "--ignored", "--nocapture"])
+    .env(CHILD, behavior)
+    .status()
+    .unwrap()
+    .code()
+}
+
+#[test]
+#[ignore = "executed as a child process"]
+fn child() {
+  let behavior = match env::var(CHILD) {
+    Ok(behavior) => behavior,
+    Err(_) => return,
+  };
+
+  match behavior.as_str() {
+    "generic" => {
+      let hook = unsafe { GenericDetour::<FnFatal>::new(fatal, fatal_detour).unwrap() };
+      let hook = HOOK.get_or_init(|| hook);
+      unsafe { hook.enable().unwrap() };
+    },
+    "static" => unsafe {
+      FatalDetour
+        .initialize(fatal, |code| {
+          eprintln!("fatal error: {}", code);
+          FatalDetour.call(code + 2)
+        })
+        .unwrap()
+        .enable()
+        .unwrap();
+    },
+    "none" => (),
+    _ => unreachable!(),
+  }
+
+  fatal(10);
+}
+
+#[test]
+fn generic_detour() {
+  assert_eq!(run("generic"), Some(11));
+}
+
+#[test]
+fn static_detour() {
+  assert_eq!(run("static"), Some(12));
+}
+
+#[test]
+fn original() {
+  assert_eq!(run("none"), Some(10));
+}
@@ -0,0 +1,43 @@
+//! Executable memory allocated from the pool.
+#![cfg(feature = "std")]
+use detour::pool::{self, ExecutableMemory, Reclamation};
+use detour::{meta, Result};
+use std::panic::{self, AssertUnwindSafe};
+
+#[inline(never)]
+extern "C" fn origin() {}
+
+#[test]
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn allocates_near_origin() -> Result<()> {
+  pool::set_reclamation(Reclamation::Immediate);
+
+  let origin = origin as *const ();
+  let mut memory = ExecutableMemory::allocate_near(origin, 6)?;
+  assert!(memory.len() >= 6);
+  assert!((memory.as_ptr() as usize).abs_diff(origin as usize) < meta::detour_range());
+
+  // The memory is shared with trampolines
+  let region = pool::region_of(memory.as_ptr() as *const ()).expect("region");
+  assert_eq!(region.origin, Some(origin));
+  assert_eq!(region.allocations, 1);
+
+  // mov eax, 42; ret
+  memory.write(0, &[0xB8, 0x2A, 0x00, 0x00, 0x00, 0xC3])?;
+  let stub: extern "C" fn() -> i32 = unsafe { std::mem::transmute(memory.as_ptr()) };
+  assert_eq!(stub(), 42);
+
+  // mov eax, 7
+  memory.write(1, &[0x07])?;
+  assert_eq!(stub(), 7);
+
+  // Code must fit within the memory
+  let size = memory.len();
+  let result = panic::catch_unwind(AssertUnwindSafe(|| memory.write(size - 1, &[0x90; 2])));
+  assert!(result.is_err());
+
+  // Once dropped, the memory is returned to the pool
+  drop(memory);
+  assert!(pool::stats().is_empty());
+  Ok(())
+}
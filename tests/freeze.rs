@@ -0,0 +1,150 @@
+//! Freezes suspend all threads of the process, therefore these tests use a
+//! separate binary.
+#![cfg(all(feature = "std", any(target_os = "linux", target_os = "android")))]
+use detour::{Result, ThreadFreeze};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Barrier};
+use std::thread;
+
+static STOP: AtomicBool = AtomicBool::new(false);
+
+/// Spawns threads incrementing a counter until stopped.
+fn spawn(count: usize, counter: &Arc<AtomicUsize>) -> Vec<thread::JoinHandle<()>> {
+  let barrier = Arc::new(Barrier::new(count + 1));
+  let threads = (0..count)
+    .map(|_| {
+      let (barrier, counter) = (barrier.clone(), counter.clone());
+      thread::spawn(move || {
+        barrier.wait();
+        while !STOP.load(Ordering::SeqCst) {
+          counter.fetch_add(1, Ordering::SeqCst);
+        }
+      })
+    })
+    .collect();
+
+  barrier.wait();
+  threads
+}
+
+#[test]
+fn freezes_other_threads() -> Result<()> {
+  let counter = Arc::new(AtomicUsize::new(0));
+  let threads = spawn(4, &counter);
+
+  {
+    let freeze = ThreadFreeze::new()?;
+    assert!(freeze.threads().len() >= threads.len());
+    assert!(freeze
+      .threads()
+      .iter()
+      .all(|thread| thread.instruction_pointer() != 0));
+
+    // The counter is not incremented whilst the threads are frozen
+    let frozen = counter.load(Ordering::SeqCst);
+    for _ in 0..1000 {
+      std::hint::spin_loop();
+    }
+    assert_eq!(counter.load(Ordering::SeqCst), frozen);
+  }
+
+  let thawed = counter.load(Ordering::SeqCst);
+  while counter.load(Ordering::SeqCst) == thawed {
+    thread::yield_now();
+  }
+
+  STOP.store(true, Ordering::SeqCst);
+  for thread in threads {
+    thread.join().unwrap();
+  }
+
+  // Freezes can be repeated, once the previous one has been thawed
+  ThreadFreeze::new()?;
+  Ok(())
+}
+
+#[test]
+fn skips_threads_blocking_the_signal() -> Result<()> {
+  use std::time::{Duration, Instant};
+
+  let (sender, receiver) = std::sync::mpsc::channel();
+  let (unblock, done) = (
+    Arc::new(AtomicBool::new(false)),
+    Arc::new(AtomicBool::new(false)),
+  );
+  let blocking = {
+    let (unblock, done) = (unblock.clone(), done.clone());
+    thread::spawn(move || {
+      let mut set = unsafe { std::mem::zeroed() };
+      unsafe {
+        libc::sigemptyset(&mut set);
+        libc::sigaddset(&mut set, libc::SIGPWR);
+        libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut());
+      }
+
+      sender.send(unsafe { libc::gettid() } as usize).unwrap();
+      while !unblock.load(Ordering::SeqCst) {
+        thread::yield_now();
+      }
+
+      // The pending signal is handled once unblocked
+      unsafe { libc::pthread_sigmask(libc::SIG_UNBLOCK, &set, std::ptr::null_mut()) };
+      sender.send(0).unwrap();
+      while !done.load(Ordering::SeqCst) {
+        thread::yield_now();
+      }
+    })
+  };
+  let id = receiver.recv().unwrap();
+
+  // The thread is skipped once, rather than signaled on every pass
+  let start = Instant::now();
+  let freeze = ThreadFreeze::new()?;
+  assert!(start.elapsed() < Duration::from_secs(5));
+  assert!(freeze.threads().iter().all(|thread| thread.id() != id));
+  drop(freeze);
+
+  // ... and subsequent freezes skip it without waiting for it
+  let start = Instant::now();
+  let freeze = ThreadFreeze::new()?;
+  assert!(start.elapsed() < Duration::from_millis(500));
+  assert!(freeze.threads().iter().all(|thread| thread.id() != id));
+  drop(freeze);
+
+  // Once the signal has been handled, the thread is frozen again
+  unblock.store(true, Ordering::SeqCst);
+  receiver.recv().unwrap();
+  let freeze = ThreadFreeze::new()?;
+  assert!(freeze.threads().iter().any(|thread| thread.id() == id));
+  drop(freeze);
+
+  done.store(true, Ordering::SeqCst);
+  blocking.join().unwrap();
+  Ok(())
+}
+
+#[test]
+fn signal_is_configured_before_freezing() -> Result<()> {
+  ThreadFreeze::new()?;
+  assert!(matches!(
+    ThreadFreeze::set_signal(libc::SIGUSR2),
+    Err(detour::Error::AlreadyInitialized)
+  ));
+  Ok(())
+}
+
+#[test]
+fn quiescence_requires_grace_periods() {
+  use detour::os::{Backend, Native};
+  use std::time::Duration;
+
+  let ranges = [0..1, 2..3];
+  while !Native.is_quiescent(&ranges) {
+    thread::sleep(Duration::from_millis(1));
+  }
+
+  // Another grace period cannot end immediately after the previous one
+  assert!(!Native.is_quiescent(&ranges));
+  thread::sleep(Duration::from_millis(20));
+  assert!(Native.is_quiescent(&ranges));
+}
@@ -0,0 +1,63 @@
+//! Trampolines registered with the GDB JIT interface.
+#![cfg(all(feature = "gdb-jit", target_arch = "x86_64"))]
+mod common;
+
+use common::{add, sub};
+use detour::{RawDetour, Result};
+use std::convert::TryInto;
+use std::slice;
+
+#[repr(C)]
+struct CodeEntry {
+  next: *const CodeEntry,
+  prev: *const CodeEntry,
+  symfile_addr: *const u8,
+  symfile_size: u64,
+}
+
+#[repr(C)]
+struct Descriptor {
+  version: u32,
+  action_flag: u32,
+  relevant_entry: *const CodeEntry,
+  first_entry: *const CodeEntry,
+}
+
+extern "C" {
+  static __jit_debug_descriptor: Descriptor;
+}
+
+/// Returns the address of the `.text` section of each registered object.
+fn registered_code() -> Vec<usize> {
+  let read = |object: &[u8], offset: usize| {
+    usize::from_le_bytes(object[offset..offset + 8].try_into().unwrap())
+  };
+
+  let mut addresses = Vec::new();
+  let mut entry = unsafe { std::ptr::read_volatile(&__jit_debug_descriptor.first_entry) };
+
+  while let Some(current) = unsafe { entry.as_ref() } {
+    let object =
+      unsafe { slice::from_raw_parts(current.symfile_addr, current.symfile_size as usize) };
+    assert_eq!(&object[..4], b"\x7FELF");
+
+    // The address of the section following the null section
+    let sections = read(object, 0x28);
+    addresses.push(read(object, sections + 64 + 16));
+    entry = current.next;
+  }
+  addresses
+}
+
+#[test]
+fn registers_trampolines() -> Result<()> {
+  let hook = unsafe { RawDetour::new(add as *const (), sub as *const ())? };
+  let trampoline = hook.trampoline() as *const () as usize;
+
+  assert_eq!(unsafe { __jit_debug_descriptor.version }, 1);
+  assert!(registered_code().contains(&trampoline));
+
+  drop(hook);
+  assert!(!registered_code().contains(&trampoline));
+  Ok(())
+}
@@ -0,0 +1,146 @@
+//! Page guard detours. The targets reside within a dedicated page, so the
+//! harness itself is never guarded; the tests are serialized.
+#![cfg(all(target_os = "linux", target_arch = "x86_64"))]
+use detour::{os, PageGuardDetour, Result};
+use std::sync::Mutex;
+use std::{mem, ptr, thread};
+
+static SERIAL: Mutex<()> = Mutex::new(());
+
+type Function = extern "C" fn(i32, i32) -> i32;
+
+/// A page containing an addition (`lea eax, [rdi + rsi]`) at its start, and a
+/// multiplication (`mov eax, edi; imul eax, esi`) at offset 16.
+struct Page(*mut u8);
+
+impl Page {
+  fn new() -> Page {
+    const ADD: [u8; 4] = [0x8D, 0x04, 0x37, 0xC3];
+    const MUL: [u8; 6] = [0x89, 0xF8, 0x0F, 0xAF, 0xC6, 0xC3];
+
+    unsafe {
+      let page = libc::mmap(
+        ptr::null_mut(),
+        4096,
+        libc::PROT_READ | libc::PROT_WRITE,
+        libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+        -1,
+        0,
+      ) as *mut u8;
+      assert_ne!(page, libc::MAP_FAILED as *mut u8);
+
+      ptr::write_bytes(page, 0xCC, 4096);
+      ptr::copy_nonoverlapping(ADD.as_ptr(), page, ADD.len());
+      ptr::copy_nonoverlapping(MUL.as_ptr(), page.add(16), MUL.len());
+      libc::mprotect(page as *mut _, 4096, libc::PROT_READ | libc::PROT_EXEC);
+      Page(page)
+    }
+  }
+
+  fn add(&self) -> Function {
+    unsafe { mem::transmute(self.0) }
+  }
+
+  fn mul(&self) -> Function {
+    unsafe { mem::transmute(self.0.add(16)) }
+  }
+}
+
+impl Drop for Page {
+  fn drop(&mut self) {
+    unsafe { libc::munmap(self.0 as *mut _, 4096) };
+  }
+}
+
+extern "C" fn sub(x: i32, y: i32) -> i32 {
+  x - y
+}
+
+extern "C" fn div(x: i32, y: i32) -> i32 {
+  x / y
+}
+
+fn protection(address: *const u8) -> Result<os::Protection> {
+  Ok(
+    os::backend()?
+      .query(address as *const ())?
+      .unwrap()
+      .protection,
+  )
+}
+
+#[test]
+fn redirects_target() -> Result<()> {
+  let _serial = SERIAL.lock().unwrap();
+  let page = Page::new();
+  let (add, mul) = (page.add(), page.mul());
+
+  let hook = unsafe { PageGuardDetour::new(add as *const (), sub as *const ())? };
+  assert!(!hook.is_enabled());
+
+  unsafe { hook.enable()? };
+  assert!(hook.is_enabled());
+  assert_eq!(protection(page.0)?, os::Protection::READ);
+
+  // Other code on the page is single-stepped
+  assert_eq!(add(10, 5), 5);
+  assert_eq!(mul(10, 5), 50);
+
+  let original: Function = unsafe { mem::transmute(hook.trampoline()) };
+  assert_eq!(original(10, 5), 15);
+
+  // The page remains readable
+  assert_eq!(unsafe { *page.0 }, 0x8D);
+  assert_eq!(protection(page.0)?, os::Protection::READ);
+
+  unsafe { hook.disable()? };
+  assert_eq!(protection(page.0)?, os::Protection::READ_EXECUTE);
+  assert_eq!(add(10, 5), 15);
+  Ok(())
+}
+
+#[test]
+fn concurrent_faults() -> Result<()> {
+  let _serial = SERIAL.lock().unwrap();
+  let page = Page::new();
+  let (add, mul) = (page.add(), page.mul());
+
+  let add_hook = unsafe { PageGuardDetour::new(add as *const (), sub as *const ())? };
+  let mul_hook = unsafe { PageGuardDetour::new(mul as *const (), div as *const ())? };
+  unsafe {
+    add_hook.enable()?;
+    mul_hook.enable()?;
+  }
+
+  let original: Function = unsafe { mem::transmute(add_hook.trampoline()) };
+  let threads = (0..4)
+    .map(|_| {
+      thread::spawn(move || {
+        for i in 1..200 {
+          assert_eq!(original(i, 2), i + 2);
+
+          // Calls made whilst another thread is stepped are not intercepted
+          let result = mul(i * 2, 2);
+          assert!(result == i || result == i * 4);
+        }
+      })
+    })
+    .collect::<Vec<_>>();
+
+  for thread in threads {
+    thread.join().unwrap();
+  }
+
+  // Once all threads have been stepped, the page is guarded again
+  assert_eq!(protection(page.0)?, os::Protection::READ);
+  assert_eq!(add(10, 5), 5);
+
+  // The page remains guarded whilst any detour is enabled
+  mem::drop(add_hook);
+  assert_eq!(add(10, 5), 15);
+  assert_eq!(mul(10, 5), 2);
+
+  mem::drop(mul_hook);
+  assert_eq!(protection(page.0)?, os::Protection::READ_EXECUTE);
+  Ok(())
+}
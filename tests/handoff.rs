@@ -0,0 +1,88 @@
+//! Detours handed off between instances of the library; the tests are
+//! serialized.
+#![cfg(feature = "std")]
+use detour::{handoff, static_detour, Error, Result, StateHandle};
+use std::sync::Mutex;
+
+static SERIAL: Mutex<()> = Mutex::new(());
+
+static_detour! {
+  static OldMul: fn(i32) -> i32;
+  static NewMul: fn(i32) -> i32;
+  static OldNeg: fn(i32) -> i32;
+}
+
+#[inline(never)]
+fn mul3(x: i32) -> i32 {
+  unsafe { std::ptr::read_volatile(&x) * 3 }
+}
+
+#[inline(never)]
+fn neg(x: i32) -> i32 {
+  unsafe { -std::ptr::read_volatile(&x) }
+}
+
+/// Returns the leading bytes of a function.
+fn prolog(function: fn(i32) -> i32) -> [u8; 16] {
+  unsafe { *(function as *const [u8; 16]) }
+}
+
+#[test]
+fn adopts_detours_without_writing_targets() -> Result<()> {
+  let _serial = SERIAL.lock().unwrap();
+
+  unsafe { OldMul.initialize(mul3, |x| x * 4)?.enable()? };
+  OldMul.set_handoff_key("mul");
+  assert_eq!(mul3(2), 8);
+
+  let patched = prolog(mul3);
+  let state = unsafe { handoff::export_state()? };
+  assert_eq!(state.keys()?, ["mul"]);
+  assert!(!OldMul.is_initialized());
+  assert_eq!(prolog(mul3), patched);
+  assert_eq!(mul3(2), 6);
+
+  // The state may be passed through a foreign interface
+  let state = unsafe { StateHandle::from_raw(state.into_raw()) };
+
+  NewMul.set_detour(|x| x * 5);
+  NewMul.set_handoff_key("mul");
+  let rejected = unsafe { handoff::import_state(state)? };
+  assert!(rejected.is_empty());
+  assert!(NewMul.is_enabled());
+  assert_eq!(prolog(mul3), patched);
+  assert_eq!(mul3(2), 10);
+
+  unsafe { NewMul.disable()? };
+  assert_eq!(mul3(2), 6);
+  unsafe { NewMul.enable()? };
+  assert_eq!(mul3(2), 10);
+  unsafe { NewMul.disable() }
+}
+
+#[test]
+fn restores_detours_not_adopted() -> Result<()> {
+  let _serial = SERIAL.lock().unwrap();
+  let original = prolog(neg);
+
+  unsafe { OldNeg.initialize(neg, |x| x)?.enable()? };
+  OldNeg.set_handoff_key("neg");
+  let state = unsafe { handoff::export_state()? };
+  assert_eq!(neg(2), -2);
+
+  // Once re-registered, no detour remains registered under the exported key
+  OldNeg.set_handoff_key("negate");
+  let rejected = unsafe { handoff::import_state(state)? };
+  assert_eq!(rejected.len(), 1);
+  assert_eq!(rejected[0].0, "neg");
+  assert!(matches!(rejected[0].1, Error::UnknownKey { ref key } if key == "neg"));
+  assert_eq!(prolog(neg), original);
+
+  // A state that is dropped restores its targets as well
+  unsafe { OldNeg.initialize(neg, |x| x)?.enable()? };
+  let state = unsafe { handoff::export_state()? };
+  drop(state);
+  assert_eq!(prolog(neg), original);
+  assert_eq!(neg(2), -2);
+  Ok(())
+}
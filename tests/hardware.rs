@@ -0,0 +1,75 @@
+//! Detours using debug registers; the tests are serialized.
+#![cfg(target_os = "linux")]
+mod common;
+
+use common::{add, sub};
+use detour::{Error, ErrorKind, HwBreakpointDetour, Result};
+use matches::assert_matches;
+use std::sync::Mutex;
+use std::{mem, ptr};
+
+static SERIAL: Mutex<()> = Mutex::new(());
+
+#[inline(never)]
+extern "C" fn mul(x: i32, y: i32) -> i32 {
+  unsafe { ptr::read_volatile(&x as *const i32) * y }
+}
+
+/// Enables a hook, returning false if performance events are unavailable
+/// (e.g within a container).
+unsafe fn try_enable(hook: &HwBreakpointDetour) -> Result<bool> {
+  match hook.enable() {
+    Ok(()) => Ok(true),
+    Err(error) if error.kind() == ErrorKind::PermissionDenied => Ok(false),
+    Err(error) => Err(error),
+  }
+}
+
+#[test]
+fn redirects_without_patching() -> Result<()> {
+  let _serial = SERIAL.lock().unwrap();
+  let prolog = unsafe { *(add as *const [u8; 8]) };
+
+  let hook = unsafe { HwBreakpointDetour::new(add as *const (), sub as *const ())? };
+  assert!(!hook.is_enabled());
+
+  if !unsafe { try_enable(&hook)? } {
+    return Ok(());
+  }
+
+  assert!(hook.is_enabled());
+  assert_eq!(add(10, 5), 5);
+  assert_eq!(unsafe { *(add as *const [u8; 8]) }, prolog);
+
+  let original: extern "C" fn(i32, i32) -> i32 = unsafe { mem::transmute(hook.trampoline()) };
+  assert_eq!(original(10, 5), 15);
+
+  // Threads created afterwards inherit the breakpoint
+  assert_eq!(std::thread::spawn(|| add(10, 5)).join().unwrap(), 5);
+
+  unsafe { hook.disable()? };
+  assert!(!hook.is_enabled());
+  assert_eq!(add(10, 5), 15);
+  Ok(())
+}
+
+#[test]
+fn registers_are_exhausted() -> Result<()> {
+  let _serial = SERIAL.lock().unwrap();
+
+  let hooks = (0..4)
+    .map(|_| unsafe { HwBreakpointDetour::new(mul as *const (), sub as *const ()) })
+    .collect::<Result<Vec<_>>>()?;
+
+  let error = unsafe { HwBreakpointDetour::new(add as *const (), sub as *const ()) }.unwrap_err();
+  assert_matches!(error, Error::NoDebugRegister);
+  assert_eq!(error.kind(), ErrorKind::ResourceExhausted);
+
+  // Dropping a detour releases its register
+  mem::drop(hooks);
+  let hook = unsafe { HwBreakpointDetour::new(add as *const (), sub as *const ())? };
+  if unsafe { try_enable(&hook)? } {
+    assert_eq!(add(10, 5), 5);
+  }
+  Ok(())
+}
@@ -0,0 +1,175 @@
+//! Detours of hot-patchable targets; the tests are serialized.
+#![cfg(all(feature = "nightly", target_arch = "x86_64"))]
+#![cfg_attr(feature = "nightly", feature(patchable_function_entry))]
+use detour::{configure_hotpatch, Error, HotpatchOptions, RawDetour, Result};
+use matches::assert_matches;
+use std::arch::naked_asm;
+use std::sync::Mutex;
+use std::{mem, ptr};
+
+static SERIAL: Mutex<()> = Mutex::new(());
+
+#[patchable_function_entry(prefix_nops = 5, entry_nops = 2)]
+#[inline(never)]
+extern "C" fn add(x: i32, y: i32) -> i32 {
+  unsafe { ptr::read_volatile(&x as *const i32) + y }
+}
+
+/// Starts with five bytes of NOPs, without any padding preceding it.
+#[patchable_function_entry(entry_nops = 5)]
+#[inline(never)]
+extern "C" fn mul(x: i32, y: i32) -> i32 {
+  unsafe { ptr::read_volatile(&x as *const i32) * y }
+}
+
+extern "C" fn sub(x: i32, y: i32) -> i32 {
+  x - y
+}
+
+/// Returns whether the fourth argument (`ecx`) is non-zero, using a `jecxz`
+/// that cannot be relocated, preceded by hot patch padding.
+#[unsafe(naked)]
+unsafe extern "C" fn padded_is_set() -> i32 {
+  naked_asm!(
+    "
+      nop
+      nop
+      nop
+      nop
+      nop
+      xchg ax, ax
+      jecxz 2f
+      mov eax, 1
+      ret
+    2:
+      xor eax, eax
+      ret"
+  )
+}
+
+type IsSet = unsafe extern "C" fn(i32, i32, i32, i32) -> i32;
+
+fn is_set() -> IsSet {
+  unsafe { mem::transmute(padded_is_set as *const () as usize + 5) }
+}
+
+extern "C" fn never_set(_: i32, _: i32, _: i32, _: i32) -> i32 {
+  -1
+}
+
+#[test]
+fn skips_relocation() -> Result<()> {
+  let _serial = SERIAL.lock().unwrap();
+  let entry = add as *const [u8; 2];
+  assert_eq!(unsafe { *entry }, [0x66, 0x90]);
+
+  let hook = unsafe { RawDetour::new(add as *const (), sub as *const ())? };
+  assert_eq!(
+    hook.trampoline() as *const () as usize,
+    add as *const () as usize + 2
+  );
+  assert!(hook.trampoline_map().is_empty());
+  assert!(hook.region().is_none());
+
+  unsafe { hook.enable()? };
+  assert_eq!(add(10, 5), 5);
+  assert_eq!(unsafe { *entry }, [0xEB, 0xF9]);
+
+  let original: extern "C" fn(i32, i32) -> i32 = unsafe { mem::transmute(hook.trampoline()) };
+  assert_eq!(original(10, 5), 15);
+
+  unsafe { hook.disable()? };
+  assert_eq!(unsafe { *entry }, [0x66, 0x90]);
+  assert_eq!(add(10, 5), 15);
+  Ok(())
+}
+
+#[test]
+fn unsupported_instructions() -> Result<()> {
+  let _serial = SERIAL.lock().unwrap();
+  let target = is_set() as *const ();
+
+  let hook = unsafe { RawDetour::new(target, never_set as *const ())? };
+  unsafe { hook.enable()? };
+  assert_eq!(unsafe { is_set()(0, 0, 0, 1) }, -1);
+
+  let original: IsSet = unsafe { mem::transmute(hook.trampoline()) };
+  assert_eq!(unsafe { original(0, 0, 0, 1) }, 1);
+  assert_eq!(unsafe { original(0, 0, 0, 0) }, 0);
+  mem::drop(hook);
+
+  // The prolog must be relocated without the fast path
+  configure_hotpatch(HotpatchOptions {
+    enabled: false,
+    ..HotpatchOptions::default()
+  });
+  let result = unsafe { RawDetour::new(target, never_set as *const ()) };
+  configure_hotpatch(HotpatchOptions::default());
+  assert_matches!(result, Err(Error::UnsupportedInstruction { .. }));
+  Ok(())
+}
+
+#[test]
+fn expected_entry() -> Result<()> {
+  let _serial = SERIAL.lock().unwrap();
+
+  // The target starts with a two byte NOP
+  configure_hotpatch(HotpatchOptions {
+    entry_size: 3,
+    ..HotpatchOptions::default()
+  });
+  let hook = unsafe { RawDetour::new(add as *const (), sub as *const ()) };
+  configure_hotpatch(HotpatchOptions::default());
+
+  let hook = hook?;
+  assert!(hook.region().is_some());
+  assert_ne!(
+    hook.trampoline() as *const () as usize,
+    add as *const () as usize + 2
+  );
+
+  unsafe { hook.enable()? };
+  assert_eq!(add(10, 5), 5);
+  Ok(())
+}
+
+#[test]
+fn entry_pad() -> Result<()> {
+  let _serial = SERIAL.lock().unwrap();
+  let target = mul as *const ();
+
+  // The jump is written over the NOPs
+  let hook = unsafe { RawDetour::new(target, sub as *const ())? };
+  assert_eq!(hook.trampoline() as *const () as usize, target as usize + 5);
+  assert!(hook.region().is_none());
+
+  unsafe { hook.enable()? };
+  assert_eq!(mul(10, 5), 5);
+  let original: extern "C" fn(i32, i32) -> i32 = unsafe { mem::transmute(hook.trampoline()) };
+  assert_eq!(original(10, 5), 50);
+  mem::drop(hook);
+
+  // Otherwise, the NOPs are overwritten, but not relocated
+  configure_hotpatch(HotpatchOptions {
+    entry_pad: false,
+    ..HotpatchOptions::default()
+  });
+  let hook = unsafe { RawDetour::new(target, sub as *const ()) };
+  configure_hotpatch(HotpatchOptions::default());
+
+  let hook = hook?;
+  assert!(hook.region().is_some());
+  let omitted = hook
+    .trampoline_map()
+    .iter()
+    .take_while(|record| record.size == 0)
+    .map(|record| record.original_size)
+    .sum::<usize>();
+  assert_eq!(omitted, 5);
+
+  unsafe { hook.enable()? };
+  assert_eq!(mul(10, 5), 5);
+  let original: extern "C" fn(i32, i32) -> i32 = unsafe { mem::transmute(hook.trampoline()) };
+  assert_eq!(original(10, 5), 50);
+  Ok(())
+}
@@ -1,6 +1,11 @@
+#![cfg_attr(feature = "vectorcall", feature(abi_vectorcall))]
+#![cfg_attr(feature = "nightly", feature(c_variadic))]
+#![allow(clippy::bool_assert_comparison)]
 use detour::Result;
 use std::mem;
 
+mod common;
+
 type FnAdd = extern "C" fn(i32, i32) -> i32;
 
 #[inline(never)]
@@ -47,6 +52,31 @@ mod raw {
     }
     Ok(())
   }
+
+  #[test]
+  fn addresses() -> Result<()> {
+    #[inline(never)]
+    extern "C" fn mul(x: i32, y: i32) -> i32 {
+      unsafe { std::ptr::read_volatile(&x as *const i32) * y }
+    }
+
+    let hook = unsafe { RawDetour::new(mul as *const (), sub_detour as *const ())? };
+    assert_eq!(hook.target_address(), mul as *const ());
+    assert_eq!(hook.detour_address(), sub_detour as *const ());
+    assert_eq!(
+      hook.trampoline_address(),
+      Some(hook.trampoline() as *const ())
+    );
+    assert!(hook.patch_size() >= 5);
+
+    let debug = format!("{:?}", hook);
+    assert!(debug.starts_with("RawDetour { target: 0x"));
+    assert!(debug.ends_with(&format!(
+      "enabled: false, patch_size: {} }}",
+      hook.patch_size()
+    )));
+    Ok(())
+  }
 }
 
 mod generic {
@@ -77,9 +107,213 @@ mod generic {
     }
     Ok(())
   }
+
+  #[test]
+  fn call_tuple() -> Result<()> {
+    use detour::Function;
+
+    type FnMix = extern "C" fn(u8, i64, *const i32) -> i64;
+
+    #[inline(never)]
+    extern "C" fn mix(x: u8, y: i64, z: *const i32) -> i64 {
+      unsafe { std::ptr::read_volatile(&x) as i64 * 100 + y * 10 + *z as i64 }
+    }
+
+    extern "C" fn mix_detour(_: u8, _: i64, _: *const i32) -> i64 {
+      0
+    }
+
+    /// Forwards the arguments, knowing only the function type.
+    fn forward<T: Function>(hook: &GenericDetour<T>, arguments: T::Arguments) -> T::Output {
+      unsafe { hook.call_tuple(arguments) }
+    }
+
+    let hook = unsafe { GenericDetour::<FnMix>::new(mix, mix_detour)? };
+    unsafe { hook.enable()? };
+    assert_eq!(mix(1, 2, &3), 0);
+    assert_eq!(forward(&hook, (1, 2, &3)), 123);
+    unsafe { hook.disable()? };
+    assert_eq!(forward(&hook, (4, 5, &6)), 456);
+    Ok(())
+  }
+
+  #[test]
+  fn signature() -> Result<()> {
+    #[inline(never)]
+    extern "C" fn add(x: i32, y: i32) -> i32 {
+      unsafe { std::ptr::read_volatile(&x as *const i32) + y }
+    }
+
+    let hook = unsafe { GenericDetour::<FnAdd>::new(add, sub_detour)? };
+    let signature = hook.signature();
+    assert_eq!(signature.arity, 2);
+    assert_eq!(signature.abi, "C");
+    assert_eq!(signature.type_names, ["i32", "i32", "i32"]);
+    assert_eq!(signature.to_string(), r#"extern "C" fn(i32, i32) -> i32"#);
+
+    let signature = detour::Signature::of::<unsafe fn(*const u8)>();
+    assert_eq!(signature.abi, "Rust");
+    assert_eq!(signature.arguments(), ["*const u8"]);
+    assert_eq!(signature.to_string(), "fn(*const u8)");
+    Ok(())
+  }
+
+  #[test]
+  fn safe_detour_of_unsafe_target() -> Result<()> {
+    type FnUnsafeAdd = unsafe extern "C" fn(i32, i32) -> i32;
+
+    #[inline(never)]
+    unsafe extern "C" fn add(x: i32, y: i32) -> i32 {
+      std::ptr::read_volatile(&x as *const i32) + y
+    }
+
+    unsafe {
+      let hook = GenericDetour::<FnUnsafeAdd>::new(add, sub_detour as FnAdd)?;
+      hook.enable()?;
+      assert_eq!(add(10, 5), 5);
+      assert_eq!(hook.call(10, 5), 15);
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn compatible() -> Result<()> {
+    #[repr(transparent)]
+    struct Meters(i32);
+
+    type FnScale = extern "C" fn(Meters, i32) -> i32;
+
+    #[inline(never)]
+    extern "C" fn mul(x: i32, y: i32) -> i32 {
+      unsafe { std::ptr::read_volatile(&x as *const i32) * y }
+    }
+
+    extern "C" fn scale(distance: Meters, factor: i32) -> i32 {
+      distance.0 * factor * 100
+    }
+
+    unsafe impl detour::AbiCompatible<i32> for Meters {}
+
+    unsafe {
+      let hook = GenericDetour::<FnAdd>::new_compatible(mul, scale as FnScale)?;
+      hook.enable()?;
+      assert_eq!(mul(2, 3), 600);
+      assert_eq!(hook.call(2, 3), 6);
+      hook.disable()?;
+    }
+    assert_eq!(mul(2, 3), 6);
+    Ok(())
+  }
+
+  #[test]
+  fn compatible_pointers() -> Result<()> {
+    use std::ffi::c_void;
+
+    type FnVisit = unsafe extern "C" fn(*mut c_void, Option<extern "C" fn(i32)>) -> i32;
+    type FnVisitDetour = extern "C" fn(*mut i32, *const ()) -> i32;
+
+    #[inline(never)]
+    unsafe extern "C" fn visit(context: *mut c_void, _: Option<extern "C" fn(i32)>) -> i32 {
+      std::ptr::read_volatile(context as *const i32)
+    }
+
+    extern "C" fn visit_detour(context: *mut i32, callback: *const ()) -> i32 {
+      unsafe { *context + callback.is_null() as i32 }
+    }
+
+    let mut value = 5;
+    let context = &mut value as *mut i32 as *mut c_void;
+    unsafe {
+      let hook = GenericDetour::<FnVisit>::new_compatible(visit, visit_detour as FnVisitDetour)?;
+      hook.enable()?;
+      assert_eq!(visit(context, None), 6);
+      assert_eq!(hook.call(context, None), 5);
+    }
+    Ok(())
+  }
+}
+
+mod pointer {
+  use super::*;
+  use detour::{os, Error, PointerDetour};
+  use matches::assert_matches;
+  use std::ptr;
+
+  extern "C" fn add(x: i32, y: i32) -> i32 {
+    x + y
+  }
+
+  extern "C" fn mul(x: i32, y: i32) -> i32 {
+    x * y
+  }
+
+  #[test]
+  fn test() -> Result<()> {
+    let mut callback: FnAdd = add;
+
+    unsafe {
+      let hook = PointerDetour::<FnAdd>::new(&mut callback, sub_detour)?;
+      assert_eq!(hook.call(10, 5), 15);
+
+      hook.enable()?;
+      assert_eq!(ptr::read_volatile(&callback)(10, 5), 5);
+      assert_eq!(hook.call(10, 5), 15);
+
+      hook.disable()?;
+      assert_eq!(ptr::read_volatile(&callback)(10, 5), 15);
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn modified_slot() -> Result<()> {
+    let mut callback: FnAdd = add;
+
+    unsafe {
+      let hook = PointerDetour::<FnAdd>::new(&mut callback, sub_detour)?;
+      hook.enable()?;
+
+      // Another party replaces the detour, which must not be overwritten
+      ptr::write_volatile(&mut callback, mul);
+      assert_matches!(hook.disable(), Err(Error::SlotChanged));
+      assert!(hook.is_enabled());
+      assert_eq!(ptr::read_volatile(&callback)(10, 5), 50);
+
+      mem::drop(hook);
+      assert_eq!(ptr::read_volatile(&callback)(10, 5), 50);
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn read_only_slot() -> Result<()> {
+    /// A table occupying a page of its own.
+    #[repr(C, align(4096))]
+    struct Table([FnAdd; 1]);
+
+    static mut TABLE: Table = Table([add]);
+
+    unsafe {
+      let slot = ptr::addr_of_mut!(TABLE.0[0]);
+      let backend = os::backend()?;
+      backend.protect(slot as *const (), 4096, os::Protection::READ)?;
+
+      let hook = PointerDetour::<FnAdd>::new_read_only(slot, sub_detour)?;
+      hook.enable()?;
+      assert_eq!(ptr::read_volatile(slot)(10, 5), 5);
+
+      // The slot's protection is restored after each write
+      let region = backend.query(slot as *const ())?.unwrap();
+      assert_eq!(region.protection, os::Protection::READ);
+
+      hook.disable()?;
+      assert_eq!(ptr::read_volatile(slot)(10, 5), 15);
+      backend.protect(slot as *const (), 4096, os::Protection::READ_WRITE)?;
+    }
+    Ok(())
+  }
 }
 
-#[cfg(feature = "nightly")]
 mod statik {
   use super::*;
   use detour::static_detour;
@@ -116,4 +350,1472 @@ mod statik {
     }
     Ok(())
   }
+
+  #[test]
+  fn call_detour() -> Result<()> {
+    #[inline(never)]
+    extern "C" fn mul(x: i32, y: i32) -> i32 {
+      unsafe { std::ptr::read_volatile(&x as *const i32) * y }
+    }
+
+    static_detour! {
+      static DetourMul: extern "C" fn(i32, i32) -> i32;
+    }
+
+    let result = DetourMul.call_detour(2, 3);
+    assert!(matches!(result, Err(detour::Error::NotInitialized)));
+
+    // The closure is invoked without the target being patched
+    unsafe { DetourMul.initialize(mul, |x, y| x + y)? };
+    assert_eq!(DetourMul.call_detour(2, 3)?, 5);
+    assert_eq!(mul(2, 3), 6);
+
+    DetourMul.set_detour(|x, y| x - y);
+    assert_eq!(DetourMul.call_detour(2, 3)?, -1);
+    Ok(())
+  }
+
+  #[test]
+  fn function_detour() -> Result<()> {
+    #[inline(never)]
+    extern "C" fn div(x: i32, y: i32) -> i32 {
+      unsafe { std::ptr::read_volatile(&x as *const i32) / y }
+    }
+
+    extern "C" fn rem(x: i32, y: i32) -> i32 {
+      x % y
+    }
+
+    extern "C" fn max(x: i32, y: i32) -> i32 {
+      x.max(y)
+    }
+
+    static_detour! {
+      static DetourDiv: extern "C" fn(i32, i32) -> i32;
+    }
+
+    unsafe { DetourDiv.initialize(div, |x, y| x + y)?.enable()? };
+    assert_eq!(div(7, 2), 9);
+
+    // The function is dispatched directly
+    DetourDiv.set_detour_fn(rem);
+    assert_eq!(div(7, 2), 1);
+    assert_eq!(DetourDiv.call_detour(7, 2)?, 1);
+
+    DetourDiv.set_detour_fn(max);
+    assert_eq!(div(7, 2), 7);
+
+    // A closure replaces the function
+    DetourDiv.set_detour(|x, y| x * y);
+    assert_eq!(div(7, 2), 14);
+    assert_eq!(DetourDiv.call_detour(7, 2)?, 14);
+
+    // The detour of an unsafe target is a safe function
+    static_detour! {
+      static DetourUnsafeDiv: unsafe extern "C" fn(i32, i32) -> i32;
+    }
+
+    let target: unsafe extern "C" fn(i32, i32) -> i32 = div;
+    unsafe { DetourUnsafeDiv.initialize(target, |x, y| x - y)? };
+    DetourUnsafeDiv.set_detour_fn(max);
+    assert_eq!(DetourUnsafeDiv.call_detour(7, 2)?, 7);
+    Ok(())
+  }
+
+  #[test]
+  fn function_dispatch() -> Result<()> {
+    const CALLS: i32 = 1_000_000;
+
+    #[inline(never)]
+    fn neg(x: i32) -> i32 {
+      unsafe { -std::ptr::read_volatile(&x as *const i32) }
+    }
+
+    fn identity(x: i32) -> i32 {
+      x
+    }
+
+    static_detour! {
+      static DetourNeg: fn(i32) -> i32;
+    }
+
+    let measure = || {
+      let start = std::time::Instant::now();
+      let sum = (0..CALLS).fold(0i32, |sum, x| sum.wrapping_add(neg(x)));
+      (start.elapsed(), sum)
+    };
+
+    unsafe { DetourNeg.initialize(neg, |x| x)?.enable()? };
+    let (closure, expected) = measure();
+
+    // A function pointer of the target's type is detected
+    DetourNeg.set_detour(identity as fn(i32) -> i32);
+    let (function, sum) = measure();
+    assert_eq!(sum, expected);
+
+    eprintln!(
+      "{} calls: {:?} through a closure, {:?} through a function",
+      CALLS, closure, function
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn inner() -> Result<()> {
+    #[inline(never)]
+    extern "C" fn sub(x: i32, y: i32) -> i32 {
+      unsafe { std::ptr::read_volatile(&x as *const i32) - y }
+    }
+
+    static_detour! {
+      static DetourSub: extern "C" fn(i32, i32) -> i32;
+    }
+
+    assert!(matches!(
+      DetourSub.inner(),
+      Err(detour::Error::NotInitialized)
+    ));
+    unsafe { DetourSub.initialize(sub, |x, y| x * y)?.enable()? };
+
+    // The state of the hook is shared with the underlying detour
+    let inner = DetourSub.inner()?;
+    assert!(inner.is_enabled());
+    assert_eq!(inner.call(6, 2), 4);
+    assert_eq!(sub(6, 2), 12);
+
+    unsafe { inner.disable()? };
+    assert!(!DetourSub.is_enabled());
+    assert_eq!(sub(6, 2), 4);
+    Ok(())
+  }
+
+  #[test]
+  fn debug() -> Result<()> {
+    #[inline(never)]
+    extern "C" fn div(x: i32, y: i32) -> i32 {
+      unsafe { std::ptr::read_volatile(&x as *const i32) / y }
+    }
+
+    static_detour! {
+      static DetourDiv: extern "C" fn(i32, i32) -> i32;
+    }
+
+    let debug = format!("{:?}", DetourDiv);
+    assert!(debug.contains("target: None"));
+    assert!(debug.contains("initialized: false"));
+
+    // Formatting the detour within itself does not deadlock
+    unsafe {
+      DetourDiv
+        .initialize(div, |x, y| format!("{:?}", DetourDiv).len() as i32 + x * y)?
+        .enable()?
+    };
+    assert!(div(6, 2) > 12);
+
+    let debug = format!("{:?}", DetourDiv);
+    assert!(debug.contains(&format!("target: Some({:?})", div as *const ())));
+    assert!(debug.contains("enabled: true, initialized: true"));
+    assert_eq!(DetourDiv.target_address(), Some(div as *const ()));
+    unsafe { DetourDiv.disable() }
+  }
+}
+
+#[cfg(feature = "std")]
+mod caller {
+  use super::*;
+  use detour::{caller_address, static_detour};
+  use std::sync::Mutex;
+
+  #[inline(never)]
+  extern "C" fn outer(x: i32) -> i32 {
+    unsafe { std::ptr::read_volatile(&x as *const i32) }
+  }
+
+  #[inline(never)]
+  extern "C" fn inner(x: i32) -> i32 {
+    unsafe { std::ptr::read_volatile(&x as *const i32) }
+  }
+
+  /// Calls the outer function from a known location.
+  #[inline(never)]
+  extern "C" fn call_outer(x: i32) -> i32 {
+    outer(x) + 1
+  }
+
+  static_detour! {
+    static OuterHook: extern "C" fn(i32) -> i32;
+    static InnerHook: extern "C" fn(i32) -> i32;
+  }
+
+  static CALLERS: Mutex<Vec<Option<usize>>> = Mutex::new(Vec::new());
+
+  fn record() {
+    let caller = caller_address().map(|address| address as usize);
+    CALLERS.lock().unwrap().push(caller);
+  }
+
+  #[test]
+  fn nested_callers() -> Result<()> {
+    unsafe {
+      OuterHook.initialize(outer, |x| {
+        record();
+        let result = inner(x);
+        record();
+        result
+      })?;
+      InnerHook.initialize(inner, |x| {
+        record();
+        x * 2
+      })?;
+
+      OuterHook.enable()?;
+      InnerHook.enable()?;
+      assert_eq!(call_outer(5), 11);
+      OuterHook.disable()?;
+      InnerHook.disable()?;
+    }
+
+    let callers = CALLERS.lock().unwrap().clone();
+    let callers = callers.into_iter().map(Option::unwrap).collect::<Vec<_>>();
+
+    // The outer detour was called from within `call_outer`
+    let offset = callers[0] - call_outer as *const () as usize;
+    assert!(offset < 0x100, "unexpected caller offset: {:#x}", offset);
+
+    // The inner detour has its own caller, restoring the outer one on return
+    assert_ne!(callers[1], callers[0]);
+    assert_eq!(callers[2], callers[0]);
+
+    // There is no caller outside of a detour
+    assert_eq!(caller_address(), None);
+    Ok(())
+  }
+}
+
+#[cfg(target_arch = "x86")]
+mod fastcall {
+  use super::*;
+  use detour::GenericDetour;
+
+  type FnFastcall = extern "fastcall" fn(i32, i32, i32) -> i32;
+
+  // The first two arguments are passed in ECX & EDX, the third on the stack
+  #[inline(never)]
+  extern "fastcall" fn mul_add(x: i32, y: i32, z: i32) -> i32 {
+    unsafe { std::ptr::read_volatile(&x as *const i32) * y + z }
+  }
+
+  extern "fastcall" fn mul_sub(x: i32, y: i32, z: i32) -> i32 {
+    x * y - z
+  }
+
+  #[test]
+  fn generic() -> Result<()> {
+    unsafe {
+      let hook = GenericDetour::<FnFastcall>::new(mul_add, mul_sub)?;
+
+      assert_eq!(mul_add(2, 3, 4), 10);
+      hook.enable()?;
+      {
+        assert_eq!(hook.call(2, 3, 4), 10);
+        assert_eq!(mul_add(2, 3, 4), 2);
+      }
+      hook.disable()?;
+      assert_eq!(mul_add(2, 3, 4), 10);
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn statik() -> Result<()> {
+    use detour::static_detour;
+
+    #[inline(never)]
+    extern "fastcall" fn add3(x: i32, y: i32, z: i32) -> i32 {
+      unsafe { std::ptr::read_volatile(&x as *const i32) + y + z }
+    }
+
+    static_detour! {
+      static DetourFastcall: extern "fastcall" fn(i32, i32, i32) -> i32;
+    }
+
+    unsafe {
+      DetourFastcall
+        .initialize(add3, |x, y, z| DetourFastcall.call(x, y, z) * 2)?
+        .enable()?;
+      assert_eq!(add3(1, 2, 3), 12);
+      DetourFastcall.disable()?;
+      assert_eq!(add3(1, 2, 3), 6);
+    }
+    Ok(())
+  }
+}
+
+#[cfg(feature = "vectorcall")]
+mod vectorcall {
+  use super::*;
+  use detour::GenericDetour;
+
+  type FnVectorcall = extern "vectorcall" fn(f32, f32, f32, f32) -> f32;
+
+  // The arguments are passed in XMM0-XMM3, and the result returned in XMM0
+  #[inline(never)]
+  extern "vectorcall" fn sum(a: f32, b: f32, c: f32, d: f32) -> f32 {
+    unsafe { std::ptr::read_volatile(&a as *const f32) + b + c + d }
+  }
+
+  extern "vectorcall" fn product(a: f32, b: f32, c: f32, d: f32) -> f32 {
+    a * b * c * d
+  }
+
+  #[test]
+  fn test() -> Result<()> {
+    unsafe {
+      let hook = GenericDetour::<FnVectorcall>::new(sum, product)?;
+
+      assert_eq!(sum(1.5, 2.0, 3.0, 4.0), 10.5);
+      hook.enable()?;
+      {
+        assert_eq!(hook.call(1.5, 2.0, 3.0, 4.0), 10.5);
+        assert_eq!(sum(1.5, 2.0, 3.0, 4.0), 36.0);
+      }
+      hook.disable()?;
+      assert_eq!(sum(1.5, 2.0, 3.0, 4.0), 10.5);
+    }
+    Ok(())
+  }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod explicit_abi {
+  use super::*;
+  use detour::GenericDetour;
+
+  #[test]
+  fn sysv64() -> Result<()> {
+    type FnSysV = extern "sysv64" fn(u64, u64, u64, u64, u64, u64, u64) -> u64;
+
+    // The 7th argument is the first one to be passed on the stack
+    #[inline(never)]
+    extern "sysv64" fn sum(a: u64, b: u64, c: u64, d: u64, e: u64, f: u64, g: u64) -> u64 {
+      unsafe { std::ptr::read_volatile(&a as *const u64) + b + c + d + e + f + g }
+    }
+
+    extern "sysv64" fn last(_: u64, _: u64, _: u64, _: u64, _: u64, _: u64, g: u64) -> u64 {
+      g
+    }
+
+    unsafe {
+      let hook = GenericDetour::<FnSysV>::new(sum, last)?;
+      hook.enable()?;
+      assert_eq!(sum(1, 2, 3, 4, 5, 6, 7), 7);
+      assert_eq!(hook.call(1, 2, 3, 4, 5, 6, 7), 28);
+      hook.disable()?;
+      assert_eq!(sum(1, 2, 3, 4, 5, 6, 7), 28);
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn win64() -> Result<()> {
+    type FnWin64 = unsafe extern "win64" fn(u64, u64, u64, u64, u64) -> u64;
+
+    // The 5th argument is the first one to be passed on the stack
+    #[inline(never)]
+    unsafe extern "win64" fn sum(a: u64, b: u64, c: u64, d: u64, e: u64) -> u64 {
+      std::ptr::read_volatile(&a as *const u64) + b + c + d + e
+    }
+
+    unsafe extern "win64" fn last(_: u64, _: u64, _: u64, _: u64, e: u64) -> u64 {
+      e
+    }
+
+    unsafe {
+      let hook = GenericDetour::<FnWin64>::new(sum, last as FnWin64)?;
+      hook.enable()?;
+      assert_eq!(sum(1, 2, 3, 4, 5), 5);
+      assert_eq!(hook.call(1, 2, 3, 4, 5), 15);
+      hook.disable()?;
+      assert_eq!(sum(1, 2, 3, 4, 5), 15);
+    }
+    Ok(())
+  }
+}
+
+#[cfg(feature = "nightly")]
+mod variadic {
+  use super::*;
+  use detour::VariadicDetour;
+  use std::sync::atomic::{AtomicPtr, Ordering};
+
+  type FnSum = unsafe extern "C" fn(i32, ...) -> i32;
+
+  static TRAMPOLINE: AtomicPtr<()> = AtomicPtr::new(std::ptr::null_mut());
+
+  #[inline(never)]
+  unsafe extern "C" fn sum(count: i32, mut args: ...) -> i32 {
+    let mut total = std::ptr::read_volatile(&0);
+    for _ in 0..count {
+      total += args.next_arg::<i32>();
+    }
+    total
+  }
+
+  unsafe extern "C" fn sum_squares(count: i32, mut args: ...) -> i32 {
+    assert_eq!(count, 3);
+    let (x, y, z) = (
+      args.next_arg::<i32>(),
+      args.next_arg::<i32>(),
+      args.next_arg::<i32>(),
+    );
+
+    let original: FnSum = mem::transmute(TRAMPOLINE.load(Ordering::SeqCst));
+    original(count, x * x, y * y, z * z)
+  }
+
+  #[test]
+  fn test() -> Result<()> {
+    unsafe {
+      let hook = VariadicDetour::<FnSum>::new(sum, sum_squares)?;
+      TRAMPOLINE.store(hook.trampoline() as *mut (), Ordering::SeqCst);
+
+      assert_eq!(sum(3, 1, 2, 3), 6);
+      hook.enable()?;
+      {
+        assert_eq!(sum(3, 1, 2, 3), 14);
+        assert_eq!(hook.trampoline()(3, 1, 2, 3), 6);
+      }
+      hook.disable()?;
+      assert_eq!(sum(3, 1, 2, 3), 6);
+    }
+    Ok(())
+  }
+}
+
+mod wide_types {
+  use super::*;
+  use detour::GenericDetour;
+
+  #[inline(never)]
+  extern "C" fn mul_i128(x: i128, y: i128) -> i128 {
+    unsafe { std::ptr::read_volatile(&x as *const i128) * y }
+  }
+
+  extern "C" fn div_i128(x: i128, y: i128) -> i128 {
+    x / y
+  }
+
+  #[test]
+  fn i128() -> Result<()> {
+    type FnI128 = extern "C" fn(i128, i128) -> i128;
+    let (x, y) = (-(1i128 << 100), 1i128 << 20);
+
+    unsafe {
+      let hook = GenericDetour::<FnI128>::new(mul_i128, div_i128)?;
+      hook.enable()?;
+      assert_eq!(mul_i128(x, y), -(1i128 << 80));
+      assert_eq!(hook.call(x, 1 << 5), -(1i128 << 105));
+      hook.disable()?;
+      assert_eq!(mul_i128(x, y), x * y);
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn u128_statik() -> Result<()> {
+    use detour::static_detour;
+
+    static_detour! {
+      static DetourU128: extern "C" fn(u64, u128) -> u128;
+    }
+
+    #[inline(never)]
+    extern "C" fn shift(count: u64, value: u128) -> u128 {
+      unsafe { std::ptr::read_volatile(&value as *const u128) << count }
+    }
+
+    let value = u128::MAX / 3;
+    unsafe {
+      // The result of the trampoline is post-processed by the detour
+      DetourU128
+        .initialize(shift, |count, value| !DetourU128.call(count, value))?
+        .enable()?;
+      assert_eq!(shift(7, value), !(value << 7));
+      assert_eq!(DetourU128.call(7, value), value << 7);
+      DetourU128.disable()?;
+    }
+    Ok(())
+  }
+
+  // The vector types are not considered FFI-safe, although both ABIs define
+  // how they are passed (in XMM registers with SysV, by reference with Win64).
+  #[cfg(target_arch = "x86_64")]
+  #[allow(improper_ctypes_definitions)]
+  mod simd {
+    use super::*;
+    use std::arch::x86_64::*;
+
+    unsafe fn to_array(value: __m128) -> [f32; 4] {
+      mem::transmute(value)
+    }
+
+    macro_rules! impl_simd_test {
+      ($name:ident, $abi:tt) => {
+        #[test]
+        fn $name() -> Result<()> {
+          type FnSimd = extern $abi fn(__m128, __m128, u128) -> __m128;
+
+          #[inline(never)]
+          extern $abi fn add(x: __m128, y: __m128, _: u128) -> __m128 {
+            unsafe { _mm_add_ps(std::ptr::read_volatile(&x), y) }
+          }
+
+          extern $abi fn mul(x: __m128, y: __m128, z: u128) -> __m128 {
+            assert_eq!(z, u128::MAX);
+            unsafe { _mm_mul_ps(x, y) }
+          }
+
+          unsafe {
+            let x = _mm_set_ps(4.0, 3.0, 2.0, 1.0);
+            let y = _mm_set1_ps(2.0);
+
+            let hook = GenericDetour::<FnSimd>::new(add, mul)?;
+            hook.enable()?;
+            assert_eq!(to_array(add(x, y, u128::MAX)), [2.0, 4.0, 6.0, 8.0]);
+            assert_eq!(to_array(hook.call(x, y, u128::MAX)), [3.0, 4.0, 5.0, 6.0]);
+            hook.disable()?;
+            assert_eq!(to_array(add(x, y, u128::MAX)), [3.0, 4.0, 5.0, 6.0]);
+          }
+          Ok(())
+        }
+      };
+    }
+
+    impl_simd_test!(sysv64, "sysv64");
+    impl_simd_test!(win64, "win64");
+  }
+}
+
+mod function_type {
+  use super::*;
+  use detour::{function_type, GenericDetour};
+  use std::sync::atomic::{AtomicI32, Ordering};
+
+  static VALUE: AtomicI32 = AtomicI32::new(0);
+
+  function_type! {
+    struct FnStore(unsafe extern "C" fn(i32));
+    struct FnAdd(fn(i32, i32) -> i32);
+  }
+
+  #[inline(never)]
+  unsafe extern "C" fn store(value: i32) {
+    VALUE.store(value, Ordering::SeqCst);
+  }
+
+  unsafe extern "C" fn store_negated(value: i32) {
+    VALUE.store(-value, Ordering::SeqCst);
+  }
+
+  #[test]
+  fn test() -> Result<()> {
+    unsafe {
+      let hook = GenericDetour::new(FnStore(store), FnStore(store_negated))?;
+      hook.enable()?;
+
+      store(5);
+      assert_eq!(VALUE.load(Ordering::SeqCst), -5);
+      (hook.trampoline().0)(5);
+      assert_eq!(VALUE.load(Ordering::SeqCst), 5);
+      hook.call_tuple((7,));
+      assert_eq!(VALUE.load(Ordering::SeqCst), 7);
+    }
+
+    let _: Option<GenericDetour<FnAdd>> = None;
+    assert_eq!(
+      detour::Signature::of::<FnStore>().to_string(),
+      r#"extern "C" fn(i32)"#
+    );
+    assert_eq!(
+      <FnAdd as detour::Function>::type_names(),
+      ["i32", "i32", "i32"]
+    );
+    Ok(())
+  }
+}
+
+mod arity {
+  use detour::{Function, GenericDetour, HookableWith};
+
+  type U = u8;
+
+  macro_rules! assert_max_arity {
+    (@assert $fn_type:ty) => {
+      assert_hookable::<$fn_type>();
+      assert_eq!(<$fn_type as Function>::ARITY, 26);
+      let _ = GenericDetour::<$fn_type>::call;
+    };
+    ($($abi:tt)*) => {
+      assert_max_arity!(@assert $($abi)* fn(U, U, U, U, U, U, U, U, U, U, U, U, U, U, U, U, U, U, U, U, U, U, U, U, U, U) -> U);
+      assert_max_arity!(@assert unsafe $($abi)* fn(U, U, U, U, U, U, U, U, U, U, U, U, U, U, U, U, U, U, U, U, U, U, U, U, U, U) -> U);
+    };
+  }
+
+  fn assert_hookable<T: Function + HookableWith<T>>() {}
+
+  #[test]
+  fn maximum_arity() {
+    assert_max_arity!();
+    assert_max_arity!(extern "C");
+    assert_max_arity!(extern "system");
+
+    #[cfg(target_arch = "x86")]
+    {
+      assert_max_arity!(extern "cdecl");
+      assert_max_arity!(extern "stdcall");
+      assert_max_arity!(extern "fastcall");
+      assert_max_arity!(extern "thiscall");
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    {
+      assert_max_arity!(extern "win64");
+      assert_max_arity!(extern "sysv64");
+    }
+  }
+}
+
+#[cfg(all(feature = "libloading", target_os = "linux"))]
+mod library_symbol {
+  use super::*;
+  use detour::{Error, GenericDetour};
+  use libloading::Library;
+  use matches::assert_matches;
+  use std::sync::Arc;
+
+  type FnCbrt = extern "C" fn(f64) -> f64;
+
+  extern "C" fn cbrt_detour(x: f64) -> f64 {
+    x * 2.0
+  }
+
+  #[test]
+  fn retains_library() -> Result<()> {
+    let library = Arc::new(unsafe { Library::new("libm.so.6") }.unwrap());
+    let cbrt: FnCbrt = unsafe { *library.get::<FnCbrt>(b"cbrt\0").unwrap() };
+    let hook =
+      unsafe { GenericDetour::<FnCbrt>::for_library_symbol(&library, b"cbrt\0", cbrt_detour)? };
+
+    let original = cbrt(27.0);
+
+    // The hook keeps the library loaded
+    mem::drop(library);
+
+    unsafe { hook.enable()? };
+    assert_eq!(cbrt(27.0), 54.0);
+    assert_eq!(hook.call(27.0), original);
+    Ok(())
+  }
+
+  #[test]
+  fn missing_symbol() {
+    let library = Arc::new(unsafe { Library::new("libm.so.6") }.unwrap());
+    let error = unsafe {
+      GenericDetour::<FnCbrt>::for_library_symbol(&library, b"not_a_symbol\0", cbrt_detour)
+    }
+    .unwrap_err();
+
+    assert_matches!(error, Error::SymbolNotFound { ref name, .. } if name == "not_a_symbol");
+    assert!(error.to_string().contains("not_a_symbol"));
+    assert_eq!(error.kind(), detour::ErrorKind::NotFound);
+  }
+}
+
+#[cfg(target_os = "linux")]
+mod exports {
+  use super::*;
+  use detour::os::{self, ExportKind};
+  use detour::{DetourGroup, Error};
+  use matches::assert_matches;
+  use std::collections::HashSet;
+
+  type FnCbrt = extern "C" fn(f64) -> f64;
+
+  extern "C" fn cbrt_detour(x: f64) -> f64 {
+    x * 2.0
+  }
+
+  /// Loads `libm` for the remainder of the process, returning its handle.
+  fn load_libm() -> *mut libc::c_void {
+    let handle = unsafe { libc::dlopen(b"libm.so.6\0".as_ptr() as *const _, libc::RTLD_NOW) };
+    assert!(!handle.is_null());
+    handle
+  }
+
+  #[test]
+  fn enumerate() -> Result<()> {
+    let libm = load_libm();
+    let exports = os::exports("libm.so.6")?;
+    let find = |name: &str| exports.iter().find(|export| export.name == name).unwrap();
+
+    let cbrt = unsafe { libc::dlsym(libm, b"cbrt\0".as_ptr() as *const _) };
+    assert_eq!(find("cbrt").kind, ExportKind::Function);
+    assert_eq!(find("cbrt").address, cbrt as *const ());
+    assert_eq!(find("signgam").kind, ExportKind::Data);
+
+    // Indirect functions are resolved to their implementation
+    let libc = os::exports("libc.so.6")?;
+    let memcpy = libc.iter().find(|export| export.name == "memcpy").unwrap();
+    let resolved = unsafe { libc::dlsym(libc::RTLD_DEFAULT, b"memcpy\0".as_ptr() as *const _) };
+    assert_eq!(memcpy.address, resolved as *const ());
+
+    // Only the default version of each symbol is included
+    let names = exports
+      .iter()
+      .map(|export| &export.name)
+      .collect::<HashSet<_>>();
+    assert_eq!(names.len(), exports.len());
+
+    assert_matches!(
+      os::exports("not_a_module.so"),
+      Err(Error::UnknownModule { ref name }) if name == "not_a_module.so"
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn hook_exports() -> Result<()> {
+    load_libm();
+    let mut factory_calls = Vec::new();
+    let (group, errors) = unsafe {
+      DetourGroup::hook_exports(
+        "libm.so.6",
+        |name| ["cbrt", "cbrtf", "signgam"].contains(&name),
+        |name, _address| {
+          factory_calls.push(name.to_string());
+          (name == "cbrt").then_some(cbrt_detour as *const ())
+        },
+      )?
+    };
+
+    // Data exports are reported without invoking the factory
+    factory_calls.sort();
+    assert_eq!(factory_calls, ["cbrt", "cbrtf"]);
+    assert_eq!(errors.len(), 1);
+    assert_matches!(errors[0], (ref name, Error::NotExecutable) if name == "signgam");
+
+    assert_eq!(group.len(), 1);
+    let cbrt: FnCbrt = unsafe { mem::transmute(group.get("cbrt").unwrap().patch_address()) };
+    let original = cbrt(27.0);
+
+    unsafe { group.enable()? };
+    assert!(group.is_enabled());
+    assert_eq!(cbrt(27.0), 54.0);
+
+    unsafe { group.disable()? };
+    assert_eq!(cbrt(27.0), original);
+    Ok(())
+  }
+}
+
+mod reclamation {
+  use super::*;
+  use detour::RawDetour;
+  use std::sync::atomic::{AtomicBool, Ordering};
+  use std::sync::Arc;
+  use std::thread;
+
+  #[test]
+  fn concurrent_toggle_and_drop() -> Result<()> {
+    #[inline(never)]
+    extern "C" fn add(x: i32, y: i32) -> i32 {
+      unsafe { std::ptr::read_volatile(&x as *const i32) + y }
+    }
+
+    let running = Arc::new(AtomicBool::new(true));
+    let caller = {
+      let running = running.clone();
+      thread::spawn(move || {
+        while running.load(Ordering::SeqCst) {
+          let result = add(10, 5);
+          assert!(result == 15 || result == 5);
+        }
+      })
+    };
+
+    for _ in 0..50 {
+      let hook = Arc::new(unsafe { RawDetour::new(add as *const (), sub_detour as *const ())? });
+
+      // Toggle the same detour from several threads simultaneously
+      let togglers = (0..4)
+        .map(|_| {
+          let hook = hook.clone();
+          thread::spawn(move || -> Result<()> {
+            for _ in 0..10 {
+              unsafe {
+                hook.enable()?;
+                hook.disable()?;
+              }
+            }
+            Ok(())
+          })
+        })
+        .collect::<Vec<_>>();
+
+      for toggler in togglers {
+        toggler.join().unwrap()?;
+      }
+      unsafe { hook.enable()? };
+    }
+
+    running.store(false, Ordering::SeqCst);
+    caller.join().unwrap();
+    assert_eq!(add(10, 5), 15);
+    Ok(())
+  }
+}
+
+mod rebinding {
+  use super::*;
+  use detour::{Error, RawDetour};
+  use matches::assert_matches;
+
+  #[test]
+  fn swap_target() -> Result<()> {
+    #[inline(never)]
+    extern "C" fn add(x: i32, y: i32) -> i32 {
+      unsafe { std::ptr::read_volatile(&x as *const i32) + y }
+    }
+
+    #[inline(never)]
+    extern "C" fn mul(x: i32, y: i32) -> i32 {
+      unsafe { std::ptr::read_volatile(&x as *const i32) * y }
+    }
+
+    static DATA: [u8; 16] = [0; 16];
+
+    unsafe {
+      let hook = RawDetour::new(add as *const (), sub_detour as *const ())?;
+      hook.enable()?;
+      assert_eq!(add(10, 5), 5);
+
+      // The detour is moved whilst remaining enabled
+      hook.swap_target(mul as *const ())?;
+      let trampoline: FnAdd = mem::transmute(hook.trampoline());
+      assert!(hook.is_enabled() && hook.is_bound());
+      assert_eq!(add(10, 5), 15);
+      assert_eq!(mul(10, 5), 5);
+      assert_eq!(trampoline(10, 5), 50);
+
+      // Failing to bind leaves the detour disabled and unbound
+      let result = hook.swap_target(DATA.as_ptr() as *const ());
+      assert_matches!(result, Err(Error::NotExecutable));
+      assert!(!hook.is_enabled() && !hook.is_bound());
+      assert_matches!(hook.enable(), Err(Error::NotInitialized));
+      assert_eq!(mul(10, 5), 50);
+
+      hook.swap_target(add as *const ())?;
+      assert!(!hook.is_enabled() && hook.is_bound());
+      hook.enable()?;
+      assert_eq!(add(10, 5), 5);
+      assert_eq!(trampoline(10, 5), 50);
+    }
+    Ok(())
+  }
+}
+
+#[cfg(feature = "std")]
+mod thunk {
+  use super::*;
+  use detour::thunk::ClosureThunk;
+  use detour::GenericDetour;
+  use std::sync::Arc;
+  use std::thread;
+
+  #[test]
+  fn calls_closure() -> Result<()> {
+    let offset = 7;
+    let add = ClosureThunk::<FnAdd>::new(move |x: i32, y: i32| x + y + offset)?;
+    let scale = ClosureThunk::<extern "C" fn(f64, u8) -> f64>::new(|x: f64, y: u8| x * y as f64)?;
+
+    assert_eq!(add.as_fn()(10, 5), 22);
+    assert_eq!(scale.as_fn()(1.5, 4), 6.0);
+    assert_eq!(add.as_ptr(), add.as_fn() as *const ());
+    Ok(())
+  }
+
+  #[test]
+  fn nested_thunks() -> Result<()> {
+    let inner = Arc::new(ClosureThunk::<FnAdd>::new(|x: i32, y: i32| x * y)?);
+    let outer = {
+      let inner = inner.clone();
+      ClosureThunk::<FnAdd>::new(move |x: i32, y: i32| inner.as_fn()(x, y) + x)?
+    };
+
+    // The closure of each thunk is resolved independently
+    assert_eq!(outer.as_fn()(10, 5), 60);
+    assert_eq!(inner.as_fn()(10, 5), 50);
+    Ok(())
+  }
+
+  #[test]
+  fn concurrent_calls() -> Result<()> {
+    let thunks = (0..4)
+      .map(|index| ClosureThunk::<FnAdd>::new(move |x: i32, y: i32| x + y * index))
+      .collect::<Result<Vec<_>>>()?;
+
+    let callers = thunks
+      .iter()
+      .enumerate()
+      .map(|(index, thunk)| {
+        let function = thunk.as_fn();
+        thread::spawn(move || (0..1000).all(|x| function(x, 2) == x + 2 * index as i32))
+      })
+      .collect::<Vec<_>>();
+
+    for caller in callers {
+      assert!(caller.join().unwrap());
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn stack_arguments() -> Result<()> {
+    type FnInts = extern "C" fn(u8, i16, i32, i64, u32, usize, i32, i64) -> i64;
+    type FnMixed = extern "C" fn(f64, i32, f32, f64, f64, f64, f64, f64, f64, f64, i64) -> f64;
+    type FnWin64 = extern "win64" fn(i32, f64, i32, i32, i64, f32) -> f64;
+
+    let offset = 100;
+    let ints = ClosureThunk::<FnInts>::new(
+      move |a: u8, b: i16, c: i32, d: i64, e: u32, f: usize, g: i32, h: i64| {
+        a as i64
+          + b as i64
+          + c as i64
+          + d
+          + e as i64
+          + f as i64
+          + g as i64 * 1000
+          + h * 10000
+          + offset
+      },
+    )?;
+    let mixed = ClosureThunk::<FnMixed>::new(
+      move |a: f64,
+            b: i32,
+            c: f32,
+            d: f64,
+            e: f64,
+            f: f64,
+            g: f64,
+            h: f64,
+            i: f64,
+            j: f64,
+            k: i64| {
+        a + b as f64
+          + c as f64
+          + d
+          + e
+          + f
+          + g
+          + h
+          + i * 100.0
+          + j * 1000.0
+          + k as f64
+          + offset as f64
+      },
+    )?;
+    let win64 =
+      ClosureThunk::<FnWin64>::new(move |a: i32, b: f64, c: i32, d: i32, e: i64, f: f32| {
+        a as f64 + b + c as f64 + d as f64 + e as f64 * 10.0 + f as f64 * 100.0 + offset as f64
+      })?;
+
+    assert_eq!(ints.as_fn()(1, 2, 3, 4, 5, 6, 7, 8), 87_121);
+    assert_eq!(
+      mixed.as_fn()(0.5, 1, 1.5, 1.0, 1.0, 1.0, 1.0, 1.0, 2.0, 3.0, 4),
+      3312.0
+    );
+    assert_eq!(win64.as_fn()(1, 0.5, 2, 3, 4, 5.0), 646.5);
+    Ok(())
+  }
+
+  #[test]
+  fn unsupported_signatures() {
+    let error = ClosureThunk::<fn(i32) -> i32>::new(|x: i32| x).unwrap_err();
+    assert_eq!(
+      error.to_string(),
+      "Closure thunks do not support `fn(i32) -> i32`"
+    );
+  }
+
+  #[test]
+  fn releases_closure() -> Result<()> {
+    let state = Arc::new(5);
+    let thunk = {
+      let state = state.clone();
+      ClosureThunk::<extern "C" fn() -> i32>::new(move || *state)?
+    };
+
+    assert_eq!(thunk.as_fn()(), 5);
+    assert_eq!(Arc::strong_count(&state), 2);
+    drop(thunk);
+    assert_eq!(Arc::strong_count(&state), 1);
+    Ok(())
+  }
+
+  #[test]
+  fn detour_with_state() -> Result<()> {
+    #[inline(never)]
+    extern "C" fn add(x: i32, y: i32) -> i32 {
+      unsafe { std::ptr::read_volatile(&x as *const i32) + y }
+    }
+
+    let factor = 3;
+    let thunk = ClosureThunk::<FnAdd>::new(move |x: i32, y: i32| (x + y) * factor)?;
+    let hook = unsafe { GenericDetour::<FnAdd>::new(add, thunk.as_fn())? };
+
+    unsafe { hook.enable()? };
+    assert_eq!(add(10, 5), 45);
+    assert_eq!(hook.call(10, 5), 15);
+    Ok(())
+  }
+}
+
+mod errors {
+  use detour::os::{Backend, Native, Protection};
+  use std::error::Error as _;
+  use std::io;
+
+  #[test]
+  fn protection_failure_source() {
+    // The first page is never mapped
+    let page = Native.page_size() as *const ();
+    let error = unsafe { Native.protect(page, 1, Protection::READ) }.unwrap_err();
+
+    // The underlying error is preserved, and its code is displayed
+    let source = error
+      .source()
+      .and_then(|source| source.downcast_ref::<io::Error>())
+      .expect("retrieving the underlying error");
+    let code = source.raw_os_error().unwrap();
+    assert!(error.to_string().ends_with(&format!("OS error {}", code)));
+  }
+}
+
+mod macros {
+  use crate::common::add;
+  use detour::Result;
+  use std::sync::Mutex;
+
+  static SERIAL: Mutex<()> = Mutex::new(());
+
+  mod hooks {
+    pub mod inner {
+      detour::static_detour! {
+        /// A detour with documentation.
+        #[allow(dead_code)]
+        pub(in crate::macros::hooks) static InPath: fn(i32) -> i32;
+
+        // The entry is removed entirely, including its (invalid) type
+        #[cfg(any())]
+        pub static Removed: unsafe extern "C" fn(NotAType) -> NotAType;
+
+        #[cfg(all())]
+        #[allow(non_upper_case_globals)]
+        pub(crate) static InCrate: extern "C" fn(i32, i32) -> i32;
+
+        #[allow(dead_code)]
+        pub(super) static InSuper: fn();
+
+        #[allow(dead_code)]
+        pub(self) static InSelf: fn();
+
+        #[allow(dead_code)]
+        static Private: fn();
+
+        pub static Public: unsafe extern "C" fn(i32) -> i32;
+      }
+
+      #[allow(dead_code)]
+      fn private() -> [&'static dyn std::any::Any; 2] {
+        [&InSelf, &Private]
+      }
+    }
+
+    #[allow(dead_code)]
+    fn within_parent() -> [&'static dyn std::any::Any; 2] {
+      [&inner::InPath, &inner::InSuper]
+    }
+  }
+
+  #[test]
+  fn visibility_and_attributes() -> Result<()> {
+    let _serial = SERIAL.lock().unwrap();
+    use hooks::inner::{InCrate, Public};
+    assert!(!Public.is_enabled());
+
+    unsafe { InCrate.initialize(add, |x, y| InCrate.call(x, y) * 2)? };
+    unsafe { InCrate.enable()? };
+    assert_eq!(add(1, 2), 6);
+    unsafe { InCrate.disable()? };
+    assert_eq!(add(1, 2), 3);
+    Ok(())
+  }
+
+  #[test]
+  fn system_function_types() -> Result<()> {
+    use std::ffi::c_void;
+
+    detour::static_detour! {
+      // As declared by Windows API bindings
+      static MessageBox: unsafe extern "system" fn(
+        hwnd: *mut c_void,
+        text: *const u16,
+        caption: *const u16,
+        kind: u32,
+      ) -> i32;
+
+      static Unwinding: extern "C-unwind" fn(Option<&'static u8>,) -> usize;
+    }
+
+    #[inline(never)]
+    unsafe extern "system" fn message_box(
+      _: *mut c_void,
+      text: *const u16,
+      _: *const u16,
+      kind: u32,
+    ) -> i32 {
+      std::ptr::read_volatile(text) as i32 + kind as i32
+    }
+
+    let text = [5u16];
+    let show = || unsafe { message_box(std::ptr::null_mut(), text.as_ptr(), text.as_ptr(), 1) };
+
+    unsafe {
+      MessageBox
+        .initialize(message_box, |hwnd, text, caption, kind| {
+          MessageBox.call(hwnd, text, caption, kind) * 10
+        })?
+        .enable()?
+    };
+    assert_eq!(show(), 60);
+    unsafe { MessageBox.disable()? };
+    assert_eq!(show(), 6);
+    assert!(!Unwinding.is_enabled());
+    Ok(())
+  }
+
+  #[test]
+  fn reference_arguments() -> Result<()> {
+    detour::static_detour! {
+      static IsEmpty: fn(&str) -> bool;
+      static Extend: unsafe extern "C" fn(buffer: &mut Vec<u8>, value: u32);
+      #[allow(dead_code)]
+      static Nested: fn((&u8, [&[u16]; 2]), Option<&&str>);
+    }
+
+    #[inline(never)]
+    fn is_empty(text: &str) -> bool {
+      unsafe { std::ptr::read_volatile(&text.len()) == 0 }
+    }
+
+    #[inline(never)]
+    unsafe extern "C" fn extend(buffer: &mut Vec<u8>, value: u32) {
+      buffer.push(std::ptr::read_volatile(&value) as u8);
+    }
+
+    unsafe {
+      IsEmpty
+        .initialize(is_empty, |text| IsEmpty.call(text.trim()))?
+        .enable()?
+    };
+    let text = String::from("  ");
+    assert!(is_empty(&text));
+    assert!(!IsEmpty.call(&text));
+
+    IsEmpty.set_detour(|text| text.starts_with('#'));
+    assert!(is_empty("# comment"));
+    assert!(IsEmpty.call_detour("# comment")?);
+    unsafe { IsEmpty.disable()? };
+    assert!(!is_empty("# comment"));
+
+    unsafe {
+      Extend
+        .initialize(extend, |buffer, value| {
+          buffer.extend_from_slice(b"<");
+          Extend.call(buffer, value * 2);
+          buffer.extend_from_slice(b">");
+        })?
+        .enable()?
+    };
+
+    let mut buffer = Vec::new();
+    unsafe { extend(&mut buffer, 1) };
+    unsafe { Extend.call(&mut buffer, 3) };
+    assert_eq!(buffer, [b'<', 2, b'>', 3]);
+    unsafe { Extend.disable()? };
+    assert!(!Nested.is_enabled());
+    Ok(())
+  }
+
+  mod positions {
+    pub struct Hooks;
+
+    impl Hooks {
+      detour::static_detour! {
+        /// An associated accessor.
+        pub fn add: extern "C" fn(i32, i32) -> i32;
+
+        #[allow(dead_code)]
+        fn unused: unsafe extern "C" fn();
+      }
+
+      detour::static_detour! {
+        #[allow(dead_code)]
+        pub(crate) fn add_named: extern "C" fn(x: i32, y: i32) -> i32;
+      }
+    }
+
+    pub struct Wrapper<T>(#[allow(dead_code)] T);
+
+    impl<T> Wrapper<T> {
+      detour::static_detour! {
+        #[allow(dead_code)]
+        pub fn generic: fn(usize) -> usize;
+      }
+    }
+
+    pub mod nested {
+      pub mod deeper {
+        detour::static_detour! {
+          pub static Nested: fn() -> i32;
+          pub fn nested: fn() -> i32;
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn item_positions() -> Result<()> {
+    let _serial = SERIAL.lock().unwrap();
+    use positions::{nested::deeper, Hooks, Wrapper};
+
+    // Multiple invocations in the same scope do not collide
+    detour::static_detour! {
+      static Local: fn() -> i32;
+      fn local: fn() -> i32;
+    }
+
+    detour::static_detour! {
+      #[allow(dead_code)]
+      static Other: fn() -> i32;
+    }
+
+    assert!(!Local.is_enabled());
+    assert!(!local().is_enabled());
+    assert!(!deeper::Nested.is_enabled());
+    assert!(!deeper::nested().is_enabled());
+    assert!(!Wrapper::<u8>::generic().is_enabled());
+    assert!(!std::ptr::eq(Hooks::add(), Hooks::add_named()));
+
+    unsafe {
+      Hooks::add()
+        .initialize(add, |x, y| Hooks::add().call(x, y) + 10)?
+        .enable()?
+    };
+    assert_eq!(add(1, 2), 13);
+    assert!(std::ptr::eq(Hooks::add(), Hooks::add()));
+    unsafe { Hooks::add().disable()? };
+    assert_eq!(add(1, 2), 3);
+    Ok(())
+  }
+
+  #[test]
+  fn bound_targets() -> Result<()> {
+    mod targets {
+      #[inline(never)]
+      pub fn add(x: i32, y: i32) -> i32 {
+        unsafe { std::ptr::read_volatile(&x as *const i32) + y }
+      }
+
+      #[inline(never)]
+      pub fn len(text: &str) -> usize {
+        unsafe { std::ptr::read_volatile(&text.len()) }
+      }
+    }
+
+    fn evaluated<T>(target: T) -> T {
+      EVALUATED.store(true, std::sync::atomic::Ordering::SeqCst);
+      target
+    }
+
+    static EVALUATED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+    detour::static_detour! {
+      static Add: fn(i32, i32) -> i32 = evaluated(targets::add);
+      static Len: fn(text: &str) -> usize = targets::len;
+      #[allow(dead_code)]
+      fn unused: unsafe extern "C" fn() = { extern "C" fn noop() {} noop };
+    }
+
+    assert!(!EVALUATED.load(std::sync::atomic::Ordering::SeqCst));
+    unsafe { Add.initialize(|x, y| Add.call(x, y) + 1)? };
+    assert!(EVALUATED.load(std::sync::atomic::Ordering::SeqCst));
+    assert_eq!(targets::add(1, 2), 3);
+    unsafe { Add.enable()? };
+    assert_eq!(targets::add(1, 2), 4);
+    unsafe { Add.disable()? };
+    assert!(unsafe { Add.install(|x, y| x * y) }.is_err());
+
+    unsafe { Len.install(|text| Len.call(text.trim()))? };
+    assert!(Len.is_enabled());
+    assert_eq!(targets::len(" a "), 1);
+    unsafe { Len.disable()? };
+    assert_eq!(targets::len(" a "), 3);
+    Ok(())
+  }
+
+  mod configurations {
+    detour::static_detour! {
+      /// A signature depending on the pointer width.
+      #[cfg(target_pointer_width = "64")]
+      pub static Width: fn(u64) -> u64;
+
+      /// A signature depending on the pointer width.
+      #[cfg(not(target_pointer_width = "64"))]
+      pub static Width: fn(u32) -> u32;
+
+      // A reference detour (i.e with companion items) and a value detour
+      #[cfg(unix)]
+      #[used]
+      pub static Length: fn(text: &str) -> usize;
+
+      #[cfg(not(unix))]
+      #[used]
+      pub static Length: fn(text: *const u8) -> usize;
+    }
+
+    detour::static_detour! {
+      // Every predicate must hold
+      #[cfg(all())]
+      #[cfg(any())]
+      pub static Stacked: fn(NotAType);
+
+      #[cfg(all())]
+      #[cfg(not(any()))]
+      pub static Stacked: fn() -> bool;
+
+      #[cfg(any())]
+      pub fn accessor: fn(NotAType);
+
+      #[cfg(not(any()))]
+      pub fn accessor: fn() -> u8;
+    }
+  }
+
+  #[test]
+  fn configured_signatures() -> Result<()> {
+    use configurations::{accessor, Length, Stacked, Width};
+
+    #[inline(never)]
+    fn double(x: usize) -> usize {
+      unsafe { std::ptr::read_volatile(&x) * 2 }
+    }
+
+    #[inline(never)]
+    fn length(text: &str) -> usize {
+      unsafe { std::ptr::read_volatile(&text.len()) }
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    let target: fn(u64) -> u64 = unsafe { std::mem::transmute(double as fn(usize) -> usize) };
+    #[cfg(not(target_pointer_width = "64"))]
+    let target: fn(u32) -> u32 = unsafe { std::mem::transmute(double as fn(usize) -> usize) };
+
+    unsafe { Width.initialize(target, |x| Width.call(x) + 1)?.enable()? };
+    assert_eq!(double(2), 5);
+    unsafe { Width.disable()? };
+
+    #[cfg(unix)]
+    {
+      unsafe { Length.initialize(length, |text| Length.call(text) + 1)? };
+      unsafe { Length.enable()? };
+      assert_eq!(length("abc"), 4);
+      unsafe { Length.disable()? };
+    }
+    #[cfg(not(unix))]
+    let _ = (length, &Length);
+
+    let _: &detour::StaticDetour<fn() -> bool> = &Stacked;
+    let _: &detour::StaticDetour<fn() -> u8> = accessor();
+    Ok(())
+  }
+}
+
+#[cfg(feature = "latency")]
+mod latency {
+  use detour::{static_detour, GenericDetour, Result};
+
+  static_detour! {
+    static Twice: fn(i32) -> i32;
+  }
+
+  #[inline(never)]
+  fn add5(val: i32) -> i32 {
+    unsafe { std::ptr::read_volatile(&val) + 5 }
+  }
+
+  #[inline(never)]
+  fn sub5(val: i32) -> i32 {
+    unsafe { std::ptr::read_volatile(&val) - 5 }
+  }
+
+  #[test]
+  fn records_static_detour() -> Result<()> {
+    unsafe {
+      Twice
+        .initialize(add5, |val| Twice.call(val) * 2)?
+        .enable()?
+    };
+
+    // Calls are not recorded until enabled
+    assert_eq!(add5(1), 12);
+    assert_eq!(Twice.latency().snapshot().closure.count(), 0);
+
+    Twice.latency().set_enabled(true);
+    for value in 0..1000 {
+      assert_eq!(add5(value), (value + 5) * 2);
+    }
+
+    let snapshot = Twice.latency().snapshot();
+    assert_eq!(snapshot.dispatch.count(), 1000);
+    assert_eq!(snapshot.closure.count(), 1000);
+    assert_eq!(snapshot.original.count(), 1000);
+    assert!(snapshot.closure.min() <= snapshot.closure.percentile(50.0));
+    assert!(snapshot.closure.percentile(50.0) <= snapshot.closure.percentile(99.0));
+    assert!(snapshot.closure.percentile(99.0) <= snapshot.closure.max());
+
+    Twice.latency().reset();
+    let snapshot = Twice.latency().snapshot();
+    assert_eq!(snapshot.closure.count(), 0);
+    assert_eq!(snapshot.closure.mean(), std::time::Duration::ZERO);
+
+    unsafe { Twice.disable() }
+  }
+
+  #[test]
+  fn records_generic_detour() -> Result<()> {
+    let hook = unsafe { GenericDetour::<fn(i32) -> i32>::new(sub5, add5)? };
+    hook.latency().set_enabled(true);
+
+    std::thread::scope(|scope| {
+      for _ in 0..4 {
+        scope.spawn(|| (0..100).for_each(|value| assert_eq!(hook.call(value), value - 5)));
+      }
+    });
+
+    let snapshot = hook.latency().snapshot();
+    assert_eq!(snapshot.original.count(), 400);
+    assert_eq!(snapshot.closure.count(), 0);
+    Ok(())
+  }
 }
@@ -0,0 +1,87 @@
+//! The pool in loader-safe mode.
+mod common;
+
+use common::{add, sub};
+use detour::{pool, Error, RawDetour, Result};
+use matches::assert_matches;
+
+#[test]
+fn pool_does_not_grow() -> Result<()> {
+  pool::set_loader_safe(true);
+  assert!(pool::is_loader_safe());
+
+  // The trampoline of a lazy detour is allocated upon first use
+  let lazy = unsafe {
+    RawDetour::builder(add as *const (), sub as *const ())
+      .lazy_trampoline(true)
+      .build()?
+  };
+  assert_matches!(lazy.try_trampoline(), Err(Error::LoaderUnsafe));
+  drop(lazy);
+
+  // The pool is empty, so any detour requires new memory
+  let error = unsafe { RawDetour::new(add as *const (), sub as *const ()) }.unwrap_err();
+  assert_matches!(error, Error::LoaderUnsafe);
+  assert_matches!(
+    pool::reserve(add as *const (), 0x100),
+    Err(Error::LoaderUnsafe)
+  );
+
+  pool::set_loader_safe(false);
+  pool::reserve(add as *const (), 0x100)?;
+  pool::set_loader_safe(true);
+
+  // Memory is available, so reserving again is a no-op
+  pool::reserve(add as *const (), 0x100)?;
+
+  let hook = unsafe { RawDetour::new(add as *const (), sub as *const ())? };
+  unsafe { hook.enable()? };
+  assert_eq!(add(10, 5), 5);
+  unsafe { hook.disable()? };
+  assert_eq!(add(10, 5), 15);
+
+  // Released memory is not reclaimed, since that may enumerate threads
+  drop(hook);
+  for _ in 0..3 {
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    pool::reclaim();
+  }
+  assert!(pool::stats().iter().any(|region| region.pending > 0));
+  Ok(())
+}
+
+/// Loads a library installing a detour from `DllMain`, whilst the loader
+/// lock is held (see `examples/loader_safe_dll.rs`).
+#[test]
+#[cfg(windows)]
+fn hooks_from_dllmain() {
+  use std::ffi::OsStr;
+  use std::os::windows::ffi::OsStrExt;
+  use winapi::um::libloaderapi::{GetProcAddress, LoadLibraryW};
+
+  // Examples are built alongside the tests, within the same profile
+  let path = std::env::current_exe().unwrap();
+  let path = path
+    .parent()
+    .and_then(|deps| deps.parent())
+    .unwrap()
+    .join("examples")
+    .join("loader_safe_dll.dll");
+  let path = OsStr::new(&path)
+    .encode_wide()
+    .chain(Some(0))
+    .collect::<Vec<u16>>();
+
+  unsafe {
+    let module = LoadLibraryW(path.as_ptr());
+    assert!(!module.is_null());
+
+    let symbol = |name: &[u8]| GetProcAddress(module, name.as_ptr() as *const _) as *const ();
+    let status: extern "C" fn() -> i32 = std::mem::transmute(symbol(b"loader_safe_status\0"));
+    let target: extern "C" fn(i32, i32) -> i32 =
+      std::mem::transmute(symbol(b"loader_safe_target\0"));
+
+    assert_eq!(status(), 0);
+    assert_eq!(target(10, 5), 5);
+  }
+}
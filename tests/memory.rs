@@ -0,0 +1,81 @@
+//! Memory queries, normalized by the library.
+#![cfg(feature = "std")]
+mod common;
+
+use common::{add, sub};
+use detour::memory;
+use detour::os::{self, Backend, Native, Protection, Region};
+use detour::{RawDetour, Result};
+
+/// A backend reporting each page as a separate region (e.g like the submaps
+/// of macOS), delegating to the native backend.
+struct Paged;
+
+unsafe impl Backend for Paged {
+  fn page_size(&self) -> usize {
+    Native.page_size()
+  }
+
+  fn query(&self, address: *const ()) -> Result<Option<Region>> {
+    let page_size = self.page_size();
+    let base = address as usize & !(page_size - 1);
+    Ok(Native.query(address)?.map(|region| Region {
+      base: base as *const (),
+      size: page_size,
+      protection: region.protection,
+    }))
+  }
+
+  unsafe fn protect(&self, address: *const (), size: usize, protection: Protection) -> Result<()> {
+    Native.protect(address, size, protection)
+  }
+
+  unsafe fn allocate(&self, address: *const (), size: usize) -> Result<Option<*mut u8>> {
+    Native.allocate(address, size)
+  }
+
+  fn write_alias(&self, address: *const ()) -> Option<*mut u8> {
+    Native.write_alias(address)
+  }
+
+  unsafe fn release(&self, address: *mut u8, size: usize) {
+    Native.release(address, size)
+  }
+}
+
+static DATA: [u8; 16] = [0; 16];
+
+#[test]
+fn normalized_queries() -> Result<()> {
+  os::set_backend(&Paged)?;
+  let target = add as *const ();
+
+  // Pages sharing a protection are reported as one region
+  let native = Native.query(target)?.expect("mapped code");
+  let region = memory::region_info(target)?.expect("mapped code");
+  assert!(region.contains(target));
+  assert_eq!(region.protection, native.protection);
+  assert!(region.base as usize <= native.lower() && region.end() >= native.upper());
+  assert!(region.len > Paged.page_size());
+  assert!(memory::region_info(std::ptr::null())?.is_none());
+
+  assert!(memory::is_executable(target)?);
+  assert!(!memory::is_executable(DATA.as_ptr() as *const ())?);
+  assert!(!memory::is_executable(std::ptr::null())?);
+
+  // Readable ranges may span several regions
+  let buffer = vec![0u8; Paged.page_size() * 3];
+  let address = buffer.as_ptr() as *const ();
+  assert!(memory::is_readable_range(address, buffer.len())?);
+  assert!(memory::is_readable_range(target, 0)?);
+  assert!(!memory::is_readable_range(std::ptr::null(), 1)?);
+  assert!(!memory::is_readable_range(address, usize::MAX)?);
+
+  // The library validates targets using the same queries
+  let hook = unsafe { RawDetour::new(target, sub as *const ())? };
+  unsafe { hook.enable()? };
+  assert_eq!(add(10, 5), 5);
+  unsafe { hook.disable()? };
+  assert_eq!(add(10, 5), 15);
+  Ok(())
+}
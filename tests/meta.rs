@@ -0,0 +1,112 @@
+//! Limits of relocated prologs and detour ranges.
+#![cfg(all(feature = "std", any(target_arch = "x86", target_arch = "x86_64")))]
+mod common;
+
+use common::{add, sub};
+use detour::{meta, Error, RawDetour, Result, Trampoline};
+use std::sync::Mutex;
+
+static SERIAL: Mutex<()> = Mutex::new(());
+
+/// A target with 64 bytes of instructions, followed by `ret`.
+#[unsafe(naked)]
+extern "C" fn padded() -> i32 {
+  core::arch::naked_asm!(".rept 8", "mov eax, 1", "nop", "nop", "nop", ".endr", "ret")
+}
+
+/// A target with 27 bytes of NOPs, followed by `mov eax, 1` and `ret`.
+#[unsafe(naked)]
+extern "C" fn nop_sled() -> i32 {
+  core::arch::naked_asm!(
+    ".rept 3",
+    ".byte 0x66, 0x0f, 0x1f, 0x84, 0x00, 0x00, 0x00, 0x00, 0x00",
+    ".endr",
+    "mov eax, 1",
+    "ret"
+  )
+}
+
+#[test]
+fn rejects_invalid_limits() {
+  let _serial = SERIAL.lock().unwrap();
+  let invalid = [
+    meta::Limits {
+      detour_range: 0,
+      ..meta::Limits::default()
+    },
+    meta::Limits {
+      detour_range: meta::DETOUR_RANGE + 1,
+      ..meta::Limits::default()
+    },
+    meta::Limits {
+      max_prolog_size: 4,
+      ..meta::Limits::default()
+    },
+    meta::Limits {
+      max_prolog_size: meta::MAX_PROLOG_LIMIT + 1,
+      ..meta::Limits::default()
+    },
+  ];
+
+  for limits in &invalid {
+    assert!(matches!(
+      meta::configure(*limits),
+      Err(Error::InvalidOption { .. })
+    ));
+  }
+  assert_eq!(meta::limits(), meta::Limits::DEFAULT);
+}
+
+#[test]
+fn limits_prolog_size() -> Result<()> {
+  let _serial = SERIAL.lock().unwrap();
+
+  // The default limit prevents deliberately large prologs
+  let result = unsafe { Trampoline::new(padded as *const (), 32) };
+  assert!(matches!(result, Err(Error::NoPatchArea)));
+
+  meta::configure(meta::Limits {
+    max_prolog_size: 40,
+    ..meta::Limits::default()
+  })?;
+  let trampoline = unsafe { Trampoline::new(padded as *const (), 32)? };
+  assert_eq!(trampoline.prolog_size(), 32);
+
+  let original: extern "C" fn() -> i32 = unsafe { std::mem::transmute(trampoline.address()) };
+  assert_eq!(original(), 1);
+
+  meta::configure(meta::Limits::DEFAULT)
+}
+
+#[test]
+fn exempts_leading_nops() -> Result<()> {
+  let _serial = SERIAL.lock().unwrap();
+
+  // Only the instruction after the NOPs counts against the limit
+  let trampoline = unsafe { Trampoline::new(nop_sled as *const (), 32)? };
+  assert_eq!(trampoline.prolog_size(), 32);
+  assert!(trampoline.relocations()[..3]
+    .iter()
+    .all(|record| record.size == 0));
+
+  let original: extern "C" fn() -> i32 = unsafe { std::mem::transmute(trampoline.address()) };
+  assert_eq!(original(), 1);
+  Ok(())
+}
+
+#[test]
+fn limits_detour_range() -> Result<()> {
+  let _serial = SERIAL.lock().unwrap();
+  let range = meta::MIN_DETOUR_RANGE * 0x100;
+  meta::configure(meta::Limits {
+    detour_range: range,
+    ..meta::Limits::default()
+  })?;
+
+  let hook = unsafe { RawDetour::new(add as *const (), sub as *const ())? };
+  let distance = (hook.trampoline() as *const () as usize).abs_diff(add as *const () as usize);
+  assert!(distance < range);
+
+  drop(hook);
+  meta::configure(meta::Limits::DEFAULT)
+}
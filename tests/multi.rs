@@ -0,0 +1,123 @@
+//! Multi-target and entry detours; the tests are serialized. The targets
+//! reside within a dedicated page.
+#![cfg(all(target_os = "linux", target_arch = "x86_64"))]
+use detour::{pool, target_index, EntryDetour, MultiDetour, RegisterState, Result};
+use std::sync::Mutex;
+use std::time::Instant;
+use std::{mem, ptr};
+
+static SERIAL: Mutex<()> = Mutex::new(());
+
+type Function = extern "C" fn(i32, i32) -> i32;
+
+/// A page containing an addition (`mov eax, edi; add eax, esi; nop`) every 16
+/// bytes.
+struct Page(*mut u8);
+
+impl Page {
+  const COUNT: usize = 4096 / 16;
+
+  fn new() -> Page {
+    const ADD: [u8; 6] = [0x89, 0xF8, 0x01, 0xF0, 0x90, 0xC3];
+
+    unsafe {
+      let page = libc::mmap(
+        ptr::null_mut(),
+        4096,
+        libc::PROT_READ | libc::PROT_WRITE,
+        libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+        -1,
+        0,
+      ) as *mut u8;
+      assert_ne!(page, libc::MAP_FAILED as *mut u8);
+
+      ptr::write_bytes(page, 0xCC, 4096);
+      for index in 0..Self::COUNT {
+        ptr::copy_nonoverlapping(ADD.as_ptr(), page.add(index * 16), ADD.len());
+      }
+      libc::mprotect(page as *mut _, 4096, libc::PROT_READ | libc::PROT_EXEC);
+      Page(page)
+    }
+  }
+
+  fn targets(&self, count: usize) -> Vec<Function> {
+    (0..count)
+      .map(|index| unsafe { mem::transmute(self.0.add(index * 16)) })
+      .collect()
+  }
+}
+
+impl Drop for Page {
+  fn drop(&mut self) {
+    unsafe { libc::munmap(self.0 as *mut _, 4096) };
+  }
+}
+
+extern "C" fn detour(x: i32, y: i32) -> i32 {
+  target_index().unwrap() as i32 * 1000 + x - y
+}
+
+extern "C" fn log(_registers: &RegisterState) {}
+
+fn pool_usage() -> usize {
+  pool::stats().iter().map(|region| region.used).sum()
+}
+
+#[test]
+fn redirects_all_targets() -> Result<()> {
+  let _serial = SERIAL.lock().unwrap();
+  let page = Page::new();
+  let targets = page.targets(8);
+
+  let hook = unsafe { MultiDetour::new(&targets, detour as Function)? };
+  assert_eq!(hook.len(), 8);
+  assert!(!hook.is_enabled());
+
+  unsafe { hook.enable()? };
+  for (index, target) in targets.iter().enumerate() {
+    assert_eq!(target(10, 5), index as i32 * 1000 + 5);
+    assert_eq!(target_index(), Some(index));
+    assert_eq!(hook.call(index, 10, 5), 15);
+  }
+
+  unsafe { hook.disable()? };
+  assert!(targets.iter().all(|target| target(10, 5) == 15));
+  Ok(())
+}
+
+#[test]
+fn shares_the_relay() -> Result<()> {
+  let _serial = SERIAL.lock().unwrap();
+  let page = Page::new();
+  let targets = page.targets(Page::COUNT);
+
+  let (usage, start) = (pool_usage(), Instant::now());
+  let hooks = targets
+    .iter()
+    .map(|&target| unsafe { EntryDetour::new(target as *const (), log) })
+    .collect::<Result<Vec<_>>>()?;
+  for hook in &hooks {
+    unsafe { hook.enable()? };
+  }
+  let (independent, independent_time) = (pool_usage() - usage, start.elapsed());
+  mem::drop(hooks);
+
+  let (usage, start) = (pool_usage(), Instant::now());
+  let hook = unsafe { MultiDetour::new(&targets, detour as Function)? };
+  unsafe { hook.enable()? };
+  let (shared, shared_time) = (pool_usage() - usage, start.elapsed());
+
+  eprintln!(
+    "{} independent hooks: {} bytes in {:?}, multi detour: {} bytes in {:?}",
+    Page::COUNT,
+    independent,
+    independent_time,
+    shared,
+    shared_time
+  );
+  assert!(shared < independent);
+  for (index, target) in targets.iter().enumerate() {
+    assert_eq!(target(10, 5), index as i32 * 1000 + 5);
+  }
+  Ok(())
+}
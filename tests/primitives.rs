@@ -0,0 +1,69 @@
+//! Detours of the system functions used by the library itself.
+#![cfg(all(feature = "std", target_os = "linux"))]
+mod common;
+
+use common::{add, sub};
+use detour::{RawDetour, Result};
+use libc::{c_int, c_void, size_t};
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+type FnMprotect = unsafe extern "C" fn(*mut c_void, size_t, c_int) -> c_int;
+
+/// The trampoline of the `mprotect` detour.
+static TRAMPOLINE: AtomicPtr<()> = AtomicPtr::new(std::ptr::null_mut());
+
+/// The number of calls making memory executable.
+static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+/// Counts calls making memory executable, since the allocator may call
+/// `mprotect` as well.
+unsafe extern "C" fn counting(address: *mut c_void, size: size_t, protection: c_int) -> c_int {
+  if protection & libc::PROT_EXEC != 0 {
+    CALLS.fetch_add(1, Ordering::SeqCst);
+  }
+
+  let trampoline: FnMprotect = std::mem::transmute(TRAMPOLINE.load(Ordering::SeqCst));
+  trampoline(address, size, protection)
+}
+
+/// Returns the number of detoured calls performed by an operation.
+fn calls(operation: impl FnOnce() -> Result<()>) -> Result<usize> {
+  let start = CALLS.load(Ordering::SeqCst);
+  operation()?;
+  Ok(CALLS.load(Ordering::SeqCst) - start)
+}
+
+#[test]
+fn bypasses_detoured_primitives() -> Result<()> {
+  let mprotect = unsafe { libc::dlsym(libc::RTLD_DEFAULT, b"mprotect\0".as_ptr() as *const _) };
+  assert!(!mprotect.is_null());
+
+  let primitive = unsafe { RawDetour::new(mprotect as *const (), counting as *const ())? };
+  TRAMPOLINE.store(
+    primitive.trampoline() as *const () as *mut (),
+    Ordering::SeqCst,
+  );
+
+  // Neither toggling the detour itself, nor any other detour, invokes it
+  assert_eq!(calls(|| unsafe { primitive.enable() })?, 0);
+
+  let hook = unsafe { RawDetour::new(add as *const (), sub as *const ())? };
+  assert_eq!(calls(|| unsafe { hook.enable() })?, 0);
+  assert_eq!(add(10, 5), 5);
+  assert_eq!(calls(|| unsafe { hook.disable() })?, 0);
+  assert_eq!(add(10, 5), 15);
+
+  // Whilst any other caller does
+  let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize };
+  let page = vec![0u8; page_size * 2];
+  let aligned = ((page.as_ptr() as usize + page_size - 1) & !(page_size - 1)) as *mut c_void;
+  let direct = calls(|| {
+    unsafe { libc::mprotect(aligned, page_size, libc::PROT_READ | libc::PROT_EXEC) };
+    unsafe { libc::mprotect(aligned, page_size, libc::PROT_READ | libc::PROT_WRITE) };
+    Ok(())
+  })?;
+  assert_eq!(direct, 1);
+
+  assert_eq!(calls(|| unsafe { primitive.disable() })?, 0);
+  Ok(())
+}
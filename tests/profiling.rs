@@ -0,0 +1,42 @@
+//! Trampolines recorded by the perf map.
+#![cfg(all(
+  feature = "profiling",
+  feature = "std",
+  any(target_os = "linux", target_os = "android")
+))]
+mod common;
+
+use common::{add, sub};
+use detour::{profiling, RawDetour, Result};
+use std::{fs, process};
+
+fn perf_map() -> String {
+  fs::read_to_string(format!("/tmp/perf-{}.map", process::id())).unwrap()
+}
+
+#[test]
+fn writes_perf_map() -> Result<()> {
+  profiling::set_label(add as *const (), "add");
+  profiling::set_perf_map(true)?;
+
+  let hook = unsafe { RawDetour::new(add as *const (), sub as *const ())? };
+  let trampoline = hook.trampoline() as *const () as usize;
+
+  let map = perf_map();
+  let line = map
+    .lines()
+    .find(|line| line.ends_with(" detour::trampoline_for_add"))
+    .unwrap();
+  assert!(line.starts_with(&format!("{:x} ", trampoline)));
+
+  // Released code is removed from the symbols, whilst its line remains
+  drop(hook);
+  assert!(perf_map().contains("trampoline_for_add"));
+  assert!(profiling::symbols()
+    .iter()
+    .all(|symbol| symbol.address as usize != trampoline));
+
+  profiling::set_perf_map(false)?;
+  let _ = fs::remove_file(format!("/tmp/perf-{}.map", process::id()));
+  Ok(())
+}
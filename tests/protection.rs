@@ -0,0 +1,126 @@
+//! Protection changes coalesced across detours, counted by a custom backend.
+#![cfg(all(feature = "std", unix, target_arch = "x86_64"))]
+use detour::os::{self, Backend, Native, Protection, Region};
+use detour::{RawDetour, Result};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+/// The number of stubs detoured at once.
+const STUBS: usize = 256;
+
+/// The size of each stub.
+const STRIDE: usize = 16;
+
+/// A backend counting protection changes, delegating to the native backend.
+struct Counting;
+
+static PROTECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl Backend for Counting {
+  fn page_size(&self) -> usize {
+    Native.page_size()
+  }
+
+  fn query(&self, address: *const ()) -> Result<Option<Region>> {
+    Native.query(address)
+  }
+
+  unsafe fn protect(&self, address: *const (), size: usize, protection: Protection) -> Result<()> {
+    PROTECTIONS.fetch_add(1, Ordering::SeqCst);
+    Native.protect(address, size, protection)
+  }
+
+  unsafe fn allocate(&self, address: *const (), size: usize) -> Result<Option<*mut u8>> {
+    Native.allocate(address, size)
+  }
+
+  fn write_alias(&self, address: *const ()) -> Option<*mut u8> {
+    Native.write_alias(address)
+  }
+
+  unsafe fn release(&self, address: *mut u8, size: usize) {
+    Native.release(address, size)
+  }
+}
+
+extern "C" fn answer() -> u32 {
+  42
+}
+
+/// Maps a page of stubs, each returning its index (i.e `mov eax, imm32; ret`).
+unsafe fn map_stubs() -> *const u8 {
+  let size = Native.page_size();
+  let page = libc::mmap(
+    std::ptr::null_mut(),
+    size,
+    libc::PROT_READ | libc::PROT_WRITE,
+    libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+    -1,
+    0,
+  ) as *mut u8;
+  assert_ne!(page as *mut libc::c_void, libc::MAP_FAILED);
+
+  for index in 0..STUBS {
+    let stub = std::slice::from_raw_parts_mut(page.add(index * STRIDE), STRIDE);
+    stub.fill(0x90);
+    stub[0] = 0xB8;
+    stub[1..5].copy_from_slice(&(index as u32).to_le_bytes());
+    stub[5] = 0xC3;
+  }
+
+  assert_eq!(
+    libc::mprotect(page as *mut _, size, libc::PROT_READ | libc::PROT_EXEC),
+    0
+  );
+  page
+}
+
+/// Returns the number of protection changes performed by an operation.
+fn protections(operation: impl FnOnce() -> Result<()>) -> Result<usize> {
+  let start = PROTECTIONS.load(Ordering::SeqCst);
+  let time = Instant::now();
+  operation()?;
+  let count = PROTECTIONS.load(Ordering::SeqCst) - start;
+  eprintln!("{} protection changes in {:?}", count, time.elapsed());
+  Ok(count)
+}
+
+#[test]
+fn coalesces_protection_changes() -> Result<()> {
+  os::set_backend(&Counting)?;
+
+  let page = unsafe { map_stubs() };
+  let stub = |index: usize| -> extern "C" fn() -> u32 {
+    unsafe { std::mem::transmute(page.add(index * STRIDE)) }
+  };
+
+  let hooks = (0..STUBS)
+    .map(|index| unsafe { RawDetour::new(stub(index) as *const (), answer as *const ()) })
+    .collect::<Result<Vec<_>>>()?;
+  let hooks = hooks.iter().collect::<Vec<_>>();
+
+  let individual = protections(|| hooks.iter().try_for_each(|hook| unsafe { hook.enable() }))?;
+  assert!((0..STUBS).all(|index| stub(index)() == 42));
+
+  let individual =
+    individual + protections(|| hooks.iter().try_for_each(|hook| unsafe { hook.disable() }))?;
+  assert!((0..STUBS).all(|index| stub(index)() == index as u32));
+
+  // All stubs share a page, which is made writable (and restored) once
+  let bulk = protections(|| unsafe { detour::enable_all(&hooks) })?;
+  assert!(hooks.iter().all(|hook| hook.is_enabled()));
+  assert!((0..STUBS).all(|index| stub(index)() == 42));
+
+  let bulk = bulk + protections(|| unsafe { detour::disable_all(&hooks) })?;
+  assert!(hooks.iter().all(|hook| !hook.is_enabled()));
+  assert!((0..STUBS).all(|index| stub(index)() == index as u32));
+
+  assert!(bulk > 0 && bulk * 10 < individual);
+
+  // Detours already in the requested state are skipped
+  unsafe { hooks[0].enable()? };
+  unsafe { detour::enable_all(&[hooks[0], hooks[1], hooks[1]])? };
+  assert!(hooks[0].is_enabled() && hooks[1].is_enabled());
+  assert_eq!(protections(|| unsafe { detour::disable_all(&[]) })?, 0);
+  Ok(())
+}
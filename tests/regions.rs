@@ -0,0 +1,69 @@
+//! Trampolines served by registered regions.
+#![cfg(feature = "std")]
+mod common;
+
+use common::{add, sub};
+use detour::os::{self, Protection};
+use detour::pool::{self, DefaultAllocator, ExecutableAllocator, RegionKind};
+use detour::{Error, RawDetour, Result};
+use matches::assert_matches;
+
+/// Returns a page of memory close to `add`, with the specified protection.
+fn page(protection: Protection) -> Result<*mut u8> {
+  let backend = os::backend()?;
+  let size = backend.page_size();
+  let slice = DefaultAllocator.allocate_near(add as *const () as usize, size, 0x4000_0000)?;
+
+  assert_eq!(slice.address as usize % size, 0);
+  unsafe { backend.protect(slice.address as *const (), size, protection)? };
+  Ok(slice.address)
+}
+
+fn protection(address: *const u8) -> Result<Protection> {
+  Ok(
+    os::backend()?
+      .query(address as *const ())?
+      .unwrap()
+      .protection,
+  )
+}
+
+#[test]
+fn registered_regions() -> Result<()> {
+  let page_size = os::backend()?.page_size();
+  let executable = page(Protection::READ_EXECUTE)?;
+  let writable = page(Protection::READ_WRITE)?;
+
+  pool::set_strict(true);
+  assert!(pool::is_strict());
+
+  // No region has been registered
+  let error = unsafe { RawDetour::new(add as *const (), sub as *const ()) }.unwrap_err();
+  assert_matches!(error, Error::RegionExhausted);
+
+  // A non-executable region must have its protection changed
+  assert_matches!(
+    unsafe { pool::add_region(writable, page_size, RegionKind::AlreadyExecutable) },
+    Err(Error::NotExecutable)
+  );
+  unsafe { pool::add_region(writable, 8, RegionKind::NeedsProtectFlip)? };
+  assert!(protection(writable)?.contains(Protection::EXECUTE));
+
+  // The region is too small for any trampoline
+  let error = unsafe { RawDetour::new(add as *const (), sub as *const ()) }.unwrap_err();
+  assert_matches!(error, Error::RegionExhausted);
+
+  unsafe { pool::add_region(executable, page_size, RegionKind::AlreadyExecutable)? };
+  let hook = unsafe { RawDetour::new(add as *const (), sub as *const ())? };
+
+  // The trampoline is written within the region, without leaving it writable
+  let trampoline = hook.trampoline() as *const () as usize;
+  assert!((executable as usize..executable as usize + page_size).contains(&trampoline));
+  assert_eq!(protection(executable)?, Protection::READ_EXECUTE);
+
+  unsafe { hook.enable()? };
+  assert_eq!(add(10, 5), 5);
+  unsafe { hook.disable()? };
+  assert_eq!(add(10, 5), 15);
+  Ok(())
+}
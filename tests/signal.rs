@@ -0,0 +1,109 @@
+//! Patches prepared ahead of, and committed within, signal handlers.
+#![cfg(all(feature = "std", unix))]
+use detour::pool::{self, ExecutableMemory};
+use detour::{meta, set_patch_callbacks, Error, PatchCallbacks, PreparedPatch, RawDetour, Result};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::OnceLock;
+use std::{mem, ptr};
+
+type FnAdd = extern "C" fn(i32, i32) -> i32;
+
+#[inline(never)]
+extern "C" fn add(x: i32, y: i32) -> i32 {
+  unsafe { ptr::read_volatile(&x) + y }
+}
+
+#[inline(never)]
+extern "C" fn mul(x: i32, y: i32) -> i32 {
+  unsafe { ptr::read_volatile(&x) * y }
+}
+
+extern "C" fn sub(x: i32, y: i32) -> i32 {
+  x - y
+}
+
+static PATCH: OnceLock<PreparedPatch<'static>> = OnceLock::new();
+static MEMORY: OnceLock<ExecutableMemory> = OnceLock::new();
+
+/// The result of the most recent commit within the signal handler.
+static RESULT: AtomicU8 = AtomicU8::new(NONE);
+const NONE: u8 = 0;
+const COMMITTED: u8 = 1;
+const WOULD_BLOCK: u8 = 2;
+const FAILED: u8 = 3;
+
+/// Commits the prepared patch, and claims memory close to the target.
+extern "C" fn handler(_signal: libc::c_int) {
+  let result = match unsafe { PATCH.get().unwrap().commit() } {
+    Ok(()) => COMMITTED,
+    Err(Error::WouldBlock) => WOULD_BLOCK,
+    Err(_) => FAILED,
+  };
+  RESULT.store(result, Ordering::SeqCst);
+
+  if MEMORY.get().is_none() {
+    if let Ok(memory) = ExecutableMemory::allocate_near(add as *const (), 16) {
+      let _ = MEMORY.set(memory);
+    }
+  }
+}
+
+/// Raises the signal, returning the result of the handler's commit.
+fn raise() -> u8 {
+  RESULT.store(NONE, Ordering::SeqCst);
+  assert_eq!(unsafe { libc::raise(libc::SIGUSR1) }, 0);
+  RESULT.load(Ordering::SeqCst)
+}
+
+#[test]
+fn commit_from_signal_handler() -> Result<()> {
+  let hook: &'static RawDetour = Box::leak(Box::new(unsafe {
+    RawDetour::new(add as *const (), sub as *const ())?
+  }));
+  let other = unsafe { RawDetour::new(mul as *const (), sub as *const ())? };
+  PATCH.set(unsafe { hook.prepare(true)? }).unwrap();
+
+  // Slots are reserved after the detours have been allocated
+  let near = Some(add as *const ());
+  pool::reserve_slots(2, near)?;
+  assert!(pool::available_slots(near) >= 2);
+  let (available, regions) = (pool::available_slots(near), pool::stats().len());
+
+  unsafe {
+    let mut action: libc::sigaction = mem::zeroed();
+    action.sa_sigaction = handler as extern "C" fn(libc::c_int) as usize;
+    libc::sigemptyset(&mut action.sa_mask);
+    assert_eq!(libc::sigaction(libc::SIGUSR1, &action, ptr::null_mut()), 0);
+  }
+
+  assert_eq!(raise(), COMMITTED);
+  assert!(hook.is_enabled());
+  assert_eq!(add(10, 5), 5);
+
+  let original: FnAdd = unsafe { mem::transmute(hook.trampoline()) };
+  assert_eq!(original(10, 5), 15);
+
+  // The memory was claimed from a slot, without growing the pool
+  let memory = MEMORY.get().expect("claimed memory");
+  assert!((memory.as_ptr() as usize).abs_diff(add as *const () as usize) < meta::detour_range());
+  assert_eq!(pool::available_slots(near), available - 1);
+  assert_eq!(pool::stats().len(), regions);
+
+  // Whilst another patch operation is in progress, the commit must not block
+  unsafe { hook.disable()? };
+  set_patch_callbacks(PatchCallbacks {
+    on_before_patch: Some(|_address, _size, _code| raise() == WOULD_BLOCK),
+    ..PatchCallbacks::default()
+  });
+  let result = unsafe { other.enable() };
+  set_patch_callbacks(PatchCallbacks::default());
+
+  result?;
+  assert!(!hook.is_enabled());
+  assert_eq!(add(10, 5), 15);
+
+  // ... but succeeds once it has completed
+  assert_eq!(raise(), COMMITTED);
+  assert_eq!(add(10, 5), 5);
+  Ok(())
+}
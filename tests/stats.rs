@@ -0,0 +1,37 @@
+//! Statistics of the pool's regions.
+#![cfg(feature = "std")]
+mod common;
+
+use common::{add, sub};
+use detour::pool::{self, Reclamation};
+use detour::{RawDetour, Result};
+
+#[test]
+fn stats_track_allocations() -> Result<()> {
+  pool::set_reclamation(Reclamation::Immediate);
+  assert!(pool::stats().is_empty());
+
+  for _ in 0..10 {
+    let hook = unsafe { RawDetour::new(add as *const (), sub as *const ())? };
+    let region = hook.region().expect("trampoline region");
+
+    assert_eq!(pool::stats(), vec![region]);
+    assert_eq!(region.origin, Some(add as *const ()));
+    assert!(region.allocations >= 1);
+    assert!(region.used > 0);
+    assert_eq!(region.used + region.free + region.pending, region.size);
+    assert!(!region.reserved && !region.registered);
+
+    // Once dropped, the unused region is unmapped
+    drop(hook);
+    assert!(pool::stats().is_empty());
+  }
+
+  // Reserved regions are retained
+  pool::reserve(add as *const (), 0x100)?;
+  let stats = pool::stats();
+  assert_eq!(stats.len(), 1);
+  assert!(stats[0].reserved);
+  assert_eq!(stats[0].used, 0);
+  Ok(())
+}
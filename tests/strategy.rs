@@ -0,0 +1,104 @@
+//! Patch strategies chosen when no memory is within range of the target.
+#![cfg(all(feature = "std", target_arch = "x86_64"))]
+use detour::pool::{self, DefaultAllocator, ExecutableAllocator, ExecutableSlice};
+use detour::{meta, Error, PatchStrategy, RawDetour, Result};
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether allocations close to a target are permitted for hops.
+static ALLOW_HOPS: AtomicBool = AtomicBool::new(false);
+
+/// An allocator without any memory within range of a relative jump, except
+/// for hops (if permitted).
+struct Constrained;
+
+unsafe impl ExecutableAllocator for Constrained {
+  fn allocate_near(&self, origin: usize, size: usize, range: usize) -> Result<ExecutableSlice> {
+    let is_hop = size <= 16 && ALLOW_HOPS.load(Ordering::SeqCst);
+    if range <= meta::DETOUR_RANGE && !is_hop {
+      return Err(Error::NoMemoryInRange {
+        origin,
+        range: origin.saturating_sub(range)..origin.saturating_add(range),
+      });
+    }
+    DefaultAllocator.allocate_near(origin, size, range)
+  }
+
+  unsafe fn free(&self, slice: ExecutableSlice) {
+    DefaultAllocator.free(slice)
+  }
+}
+
+/// A target with a 15 byte prolog, sufficient for an absolute jump.
+#[unsafe(naked)]
+extern "C" fn large_prolog() -> i32 {
+  core::arch::naked_asm!(".rept 3", "mov eax, 1", ".endr", "ret")
+}
+
+/// A target with a five byte prolog, followed by a branch that cannot be
+/// relocated.
+#[unsafe(naked)]
+extern "C" fn small_prolog() -> i32 {
+  core::arch::naked_asm!(
+    "mov eax, 2",
+    "jecxz 2f",
+    ".rept 16",
+    "nop",
+    ".endr",
+    "2:",
+    "ret"
+  )
+}
+
+extern "C" fn detour() -> i32 {
+  10
+}
+
+/// Returns the destination of the jump at a target.
+unsafe fn jump_destination(target: *const ()) -> *const () {
+  let code = target as *const u8;
+  let destination = match *code {
+    0xE9 => {
+      let displacement = (code.add(1) as *const i32).read_unaligned();
+      (target as usize + 5).wrapping_add(displacement as usize)
+    },
+    0xFF => (code.add(6) as *const usize).read_unaligned(),
+    opcode => panic!("unexpected opcode {:#X}", opcode),
+  };
+  destination as *const ()
+}
+
+#[test]
+fn falls_back_without_memory_in_range() -> Result<()> {
+  pool::set_allocator(&Constrained)?;
+
+  // Without any memory close to the target, an absolute jump is used
+  let hook = unsafe { RawDetour::new(large_prolog as *const (), detour as *const ())? };
+  let original: extern "C" fn() -> i32 = unsafe { mem::transmute(hook.trampoline()) };
+  assert_eq!(hook.strategy(), PatchStrategy::Absolute);
+
+  unsafe { hook.enable()? };
+  let destination = unsafe { jump_destination(large_prolog as *const ()) };
+  assert_eq!(destination, detour as *const ());
+  assert_eq!((large_prolog(), original()), (10, 1));
+  unsafe { hook.disable()? };
+  assert_eq!(large_prolog(), 1);
+
+  // The prolog is too small for an absolute jump
+  let result = unsafe { RawDetour::new(small_prolog as *const (), detour as *const ()) };
+  assert!(matches!(result, Err(Error::NoMemoryInRange { .. })));
+
+  // Only a hop is allocated close to the target
+  ALLOW_HOPS.store(true, Ordering::SeqCst);
+  let hook = unsafe { RawDetour::new(small_prolog as *const (), detour as *const ())? };
+  let original: extern "C" fn() -> i32 = unsafe { mem::transmute(hook.trampoline()) };
+  assert_eq!(hook.strategy(), PatchStrategy::Hop);
+
+  unsafe { hook.enable()? };
+  let hop = unsafe { jump_destination(small_prolog as *const ()) };
+  assert_eq!(unsafe { jump_destination(hop) }, detour as *const ());
+  assert_eq!((small_prolog(), original()), (10, 2));
+  unsafe { hook.disable()? };
+  assert_eq!(small_prolog(), 2);
+  Ok(())
+}
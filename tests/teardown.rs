@@ -0,0 +1,87 @@
+//! Restoration of detoured targets upon teardown. Each test executes this
+//! binary as a child process, which crashes (or exits) with a detour enabled,
+//! and reports whether its target has been restored using a handler installed
+//! beforehand.
+#![cfg(all(feature = "std", target_os = "linux"))]
+mod common;
+
+use common::{add, sub};
+use detour::{teardown, RawDetour};
+use std::env;
+use std::process::Command;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// The variable selecting the behavior of the child process.
+const CHILD: &str = "DETOUR_TEARDOWN_CHILD";
+
+/// The exit codes of the child process.
+const PRISTINE: i32 = 42;
+const PATCHED: i32 = 43;
+
+/// The leading bytes of the target, before it's detoured.
+static ORIGINAL: AtomicU64 = AtomicU64::new(0);
+
+fn leading_bytes() -> u64 {
+  unsafe { std::ptr::read_unaligned(add as *const u64) }
+}
+
+/// Exits with whether the target has been restored, like a crash reporter
+/// writing a dump.
+extern "C" fn report() {
+  let pristine = leading_bytes() == ORIGINAL.load(Ordering::SeqCst);
+  unsafe { libc::_exit(if pristine { PRISTINE } else { PATCHED }) };
+}
+
+extern "C" fn report_signal(_signal: libc::c_int) {
+  report();
+}
+
+/// Executes the child process, returning its exit code.
+fn run(behavior: &str) -> Option<i32> {
+  Command::new(env::current_exe().unwrap())
+    .args(["child", "--exact", "--ignored", "--nocapture"])
+    .env(CHILD, behavior)
+    .status()
+    .unwrap()
+    .code()
+}
+
+#[test]
+#[ignore = "executed as a child process"]
+fn child() {
+  let behavior = match env::var(CHILD) {
+    Ok(behavior) => behavior,
+    Err(_) => return,
+  };
+
+  ORIGINAL.store(leading_bytes(), Ordering::SeqCst);
+  unsafe {
+    libc::signal(
+      libc::SIGSEGV,
+      report_signal as *const () as libc::sighandler_t,
+    );
+    libc::atexit(report);
+  }
+  teardown::install().unwrap();
+
+  let hook = unsafe { RawDetour::new(add as *const (), sub as *const ()).unwrap() };
+  unsafe { hook.enable().unwrap() };
+  assert_eq!(add(10, 5), 5);
+  assert_ne!(leading_bytes(), ORIGINAL.load(Ordering::SeqCst));
+
+  match behavior.as_str() {
+    "crash" => unsafe { std::ptr::write_volatile(std::ptr::null_mut::<u8>(), 0) },
+    "exit" => std::process::exit(0),
+    _ => unreachable!(),
+  }
+}
+
+#[test]
+fn restores_upon_crash() {
+  assert_eq!(run("crash"), Some(PRISTINE));
+}
+
+#[test]
+fn restores_upon_exit() {
+  assert_eq!(run("exit"), Some(PRISTINE));
+}
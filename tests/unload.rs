@@ -0,0 +1,53 @@
+//! The guard detouring the unloading of modules.
+#![cfg(all(feature = "std", target_os = "linux"))]
+use detour::unload::{self, UnloadEvent};
+use detour::{RawDetour, Result};
+use std::sync::Mutex;
+
+static EVENTS: Mutex<Vec<UnloadEvent>> = Mutex::new(Vec::new());
+
+#[inline(never)]
+extern "C" fn halve(x: f64) -> f64 {
+  unsafe { std::ptr::read_volatile(&x) / 2.0 }
+}
+
+#[test]
+fn disables_detours_into_unloaded_modules() -> Result<()> {
+  unload::set_observer(|event| EVENTS.lock().unwrap().push(*event));
+  unload::install()?;
+  unload::install()?;
+
+  let open = || unsafe {
+    libc::dlopen(
+      b"libm.so.6\0".as_ptr() as *const _,
+      libc::RTLD_NOW | libc::RTLD_LOCAL,
+    )
+  };
+  let (library, extra) = (open(), open());
+  assert!(!library.is_null() && !extra.is_null());
+
+  let fabs = unsafe { libc::dlsym(library, b"fabs\0".as_ptr() as *const _) } as *const ();
+  let hook = unsafe { RawDetour::new(halve as *const (), fabs)? };
+  unsafe { hook.enable()? };
+  assert_eq!(halve(-4.0), 4.0);
+
+  // The module remains loaded, so the detour is enabled again
+  assert_eq!(unsafe { libc::dlclose(extra) }, 0);
+  assert!(hook.is_enabled());
+  assert_eq!(halve(-4.0), 4.0);
+  assert!(EVENTS.lock().unwrap().is_empty());
+
+  // The target is restored before the module is unmapped
+  assert_eq!(unsafe { libc::dlclose(library) }, 0);
+  assert!(!hook.is_enabled());
+  assert_eq!(halve(-4.0), -2.0);
+  assert_eq!(
+    *EVENTS.lock().unwrap(),
+    [UnloadEvent {
+      target: hook.patch_address(),
+      detour: fabs,
+      restored: true,
+    }]
+  );
+  Ok(())
+}